@@ -0,0 +1,325 @@
+//! Browser demo of statime, see the README for how to run it.
+//!
+//! The JavaScript side owns the WebSocket and the timers. It feeds everything
+//! that happens into a [`Demo`], which returns what should be sent and which
+//! timers should be (re)started.
+
+use std::{cell::Cell, collections::VecDeque, rc::Rc};
+
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use rand::{rngs::SmallRng, SeedableRng};
+use statime::{
+    BasicFilter, Clock, ClockIdentity, CommunicationMode, DelayMechanism, Duration, InstanceConfig,
+    Interval, LogMessageIntervals, Port, PortAction, PortActionIterator, PortConfig, PtpInstance,
+    Running, SdoId, Time, TimePropertiesDS, TimeSource, TimestampContext, TimestampSource,
+    TimestampingQuality, TransmitEnable,
+};
+use wasm_bindgen::prelude::*;
+
+/// Channel byte for tunneled event messages
+const EVENT: u8 = 0;
+/// Channel byte for tunneled general messages
+const GENERAL: u8 = 1;
+
+#[derive(Debug, Clone, Copy)]
+struct ClockState {
+    // Time of the host clock at which the state was last updated, in nanoseconds
+    base: f64,
+    // Virtual time at base, in nanoseconds
+    offset: f64,
+    frequency: f64,
+}
+
+/// A virtual clock on top of the browser's wall clock
+///
+/// Adjustments only change the virtual clock, so the demo can run without any
+/// special permissions. Clones share the same virtual clock.
+#[derive(Debug, Clone)]
+struct BrowserClock {
+    state: Rc<Cell<ClockState>>,
+}
+
+impl BrowserClock {
+    fn new() -> Self {
+        let now = host_nanos();
+        Self {
+            state: Rc::new(Cell::new(ClockState {
+                base: now,
+                offset: now,
+                frequency: 1.0,
+            })),
+        }
+    }
+}
+
+impl ClockState {
+    fn virtual_nanos(&self, host: f64) -> f64 {
+        self.offset + (host - self.base) * self.frequency
+    }
+}
+
+fn host_nanos() -> f64 {
+    js_sys::Date::now() * 1e6
+}
+
+impl Clock for BrowserClock {
+    type Error = core::convert::Infallible;
+
+    fn now(&self) -> Time {
+        Time::from_nanos(self.state.get().virtual_nanos(host_nanos()) as u64)
+    }
+
+    fn adjust(
+        &mut self,
+        time_offset: Duration,
+        frequency_multiplier: f64,
+        _time_properties_ds: &TimePropertiesDS,
+    ) -> Result<(), Self::Error> {
+        let host = host_nanos();
+        let state = self.state.get();
+        self.state.set(ClockState {
+            base: host,
+            offset: state.virtual_nanos(host) + time_offset.nanos_lossy(),
+            frequency: state.frequency * frequency_multiplier,
+        });
+        Ok(())
+    }
+
+    fn frequency_multiplier(&self) -> Option<f64> {
+        Some(self.state.get().frequency)
+    }
+}
+
+type DemoInstance = PtpInstance<BrowserClock, BasicFilter>;
+type RunningPort = Port<Running<'static, BrowserClock, BasicFilter>, SmallRng>;
+
+enum Output {
+    Event(Vec<u8>, TimestampContext),
+    General(Vec<u8>),
+    Timer(&'static str, core::time::Duration),
+}
+
+// Copy the actions out of the port, so the port can be used again
+fn collect(actions: PortActionIterator<'_>) -> Vec<Output> {
+    actions
+        .filter_map(|action| match action {
+            PortAction::SendTimeCritical { context, data, .. } => {
+                Some(Output::Event(data.to_vec(), context))
+            }
+            PortAction::SendGeneral { data } => Some(Output::General(data.to_vec())),
+            // Only used by ports granting or requesting unicast transmission,
+            // and the relay only tunnels multicast
+            PortAction::SendUnicastGeneral { .. }
+            | PortAction::SendUnicastTimeCritical { .. }
+            | PortAction::SendToUnicastMaster { .. }
+            | PortAction::ResetUnicastNegotiationTimer { .. } => None,
+            // Only started for statistics windows, which the demo doesn't enable
+            PortAction::ResetStatisticsTimer { .. } => None,
+            PortAction::ResetAnnounceTimer { duration } => {
+                Some(Output::Timer("announce", duration))
+            }
+            PortAction::ResetSyncTimer { duration } => Some(Output::Timer("sync", duration)),
+            PortAction::ResetDelayRequestTimer { duration } => {
+                Some(Output::Timer("delay_request", duration))
+            }
+            PortAction::ResetAnnounceReceiptTimer { duration } => {
+                Some(Output::Timer("announce_receipt", duration))
+            }
+        })
+        .collect()
+}
+
+fn frame(channel: u8, data: &[u8]) -> Object {
+    let mut bytes = Vec::with_capacity(data.len() + 1);
+    bytes.push(channel);
+    bytes.extend_from_slice(data);
+
+    let object = Object::new();
+    Reflect::set(&object, &"frame".into(), &Uint8Array::from(&bytes[..])).unwrap();
+    object
+}
+
+fn timer(name: &str, duration: core::time::Duration) -> Object {
+    let object = Object::new();
+    Reflect::set(&object, &"timer".into(), &name.into()).unwrap();
+    Reflect::set(
+        &object,
+        &"delay".into(),
+        &(duration.as_secs_f64() * 1000.0).into(),
+    )
+    .unwrap();
+    object
+}
+
+/// A single PTP node with one port
+///
+/// Every method returns an array of things to do, containing objects of the
+/// form `{ frame: Uint8Array }` for frames to send to the relay and
+/// `{ timer: string, delay: number }` for timers to (re)start. When a timer
+/// expires, call [`Demo::timer`] with its name.
+#[wasm_bindgen]
+pub struct Demo {
+    instance: &'static DemoInstance,
+    clock: BrowserClock,
+    // Only None while the BMCA is running
+    port: Option<RunningPort>,
+    pending: Vec<Output>,
+}
+
+#[wasm_bindgen]
+impl Demo {
+    /// Create a node. The priority is used as priority 1 in the BMCA, so the
+    /// node with the lowest priority becomes master.
+    #[wasm_bindgen(constructor)]
+    pub fn new(priority: u8) -> Demo {
+        let mut clock_identity = [0; 8];
+        for byte in clock_identity.iter_mut() {
+            *byte = (js_sys::Math::random() * 256.0) as u8;
+        }
+
+        let config = InstanceConfig {
+            clock_identity: ClockIdentity(clock_identity),
+            priority_1: priority,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        };
+        let time_properties_ds =
+            TimePropertiesDS::new_arbitrary_time(false, false, TimeSource::InternalOscillator);
+
+        let clock = BrowserClock::new();
+
+        // The port borrows the instance for as long as the page lives
+        let instance: &'static DemoInstance = Box::leak(Box::new(PtpInstance::new(
+            config,
+            time_properties_ds,
+            clock.clone(),
+            BasicFilter::for_quality(TimestampingQuality::Software),
+        )));
+
+        let port_config = PortConfig {
+            delay_mechanism: DelayMechanism::E2E {
+                interval: Interval::ONE_SECOND,
+            },
+            announce_interval: Interval::ONE_SECOND,
+            announce_receipt_timeout: 3,
+            sync_interval: Interval::ONE_SECOND,
+            master_only: false,
+            delay_asymmetry: Duration::ZERO,
+            ingress_latency: Duration::ZERO,
+            egress_latency: Duration::ZERO,
+            management: Default::default(),
+            communication_mode: CommunicationMode::Multicast,
+            transmit: TransmitEnable::ALL,
+            unicast_client: None,
+            log_message_intervals: LogMessageIntervals::STANDARD,
+        };
+        let rng = SmallRng::seed_from_u64((js_sys::Math::random() * u64::MAX as f64) as u64);
+        let (mut port, actions) = instance.add_port(port_config, rng).end_bmca();
+        port.set_measurement_queue(true);
+        let pending = collect(actions);
+
+        Demo {
+            instance,
+            clock,
+            port: Some(port),
+            pending,
+        }
+    }
+
+    /// The actions resulting from creating the node
+    pub fn start(&mut self) -> Array {
+        let pending = std::mem::take(&mut self.pending);
+        self.run(pending)
+    }
+
+    /// Handle a frame received from the relay
+    pub fn receive(&mut self, frame: &[u8]) -> Array {
+        let timestamp = self.clock.now();
+        let port = self.port();
+
+        let outputs = match frame.split_first() {
+            Some((&EVENT, data)) => collect(port.handle_timecritical_receive_from(
+                data,
+                timestamp,
+                TimestampSource::Software,
+            )),
+            Some((&GENERAL, data)) => collect(port.handle_general_receive(data)),
+            _ => vec![],
+        };
+        self.run(outputs)
+    }
+
+    /// Handle the expiry of a timer
+    pub fn timer(&mut self, name: &str) -> Array {
+        let port = self.port();
+
+        let outputs = match name {
+            "announce" => collect(port.handle_announce_timer()),
+            "sync" => collect(port.handle_sync_timer()),
+            "delay_request" => collect(port.handle_delay_request_timer()),
+            "announce_receipt" => collect(port.handle_announce_receipt_timer()),
+            _ => vec![],
+        };
+        self.run(outputs)
+    }
+
+    /// Run the best master clock algorithm. This should be called every
+    /// [`Demo::bmca_interval`] milliseconds.
+    pub fn bmca(&mut self) -> Array {
+        let mut port = self.port.take().expect("port missing").start_bmca();
+        self.instance.bmca(&mut [&mut port]);
+
+        let (port, actions) = port.end_bmca();
+        let outputs = collect(actions);
+        self.port = Some(port);
+        self.run(outputs)
+    }
+
+    /// Interval between runs of the BMCA, in milliseconds
+    pub fn bmca_interval(&self) -> f64 {
+        self.instance.bmca_interval().as_secs_f64() * 1000.0
+    }
+
+    /// The offsets to the master measured since the last call, in nanoseconds
+    pub fn take_offsets(&mut self) -> Vec<f64> {
+        let port = self.port();
+        std::iter::from_fn(|| port.take_measurement())
+            .map(|measurement| measurement.master_offset.nanos_lossy())
+            .collect()
+    }
+
+    fn port(&mut self) -> &mut RunningPort {
+        self.port.as_mut().expect("port missing")
+    }
+
+    // Convert the outputs for javascript. Frames are considered sent right
+    // away, which is the best timestamp available in a browser.
+    fn run(&mut self, outputs: Vec<Output>) -> Array {
+        let result = Array::new();
+        let mut queue = VecDeque::from(outputs);
+
+        while let Some(output) = queue.pop_front() {
+            match output {
+                Output::Event(data, context) => {
+                    result.push(&frame(EVENT, &data));
+                    let timestamp = self.clock.now();
+                    queue.extend(collect(self.port().handle_send_timestamp_from(
+                        context,
+                        timestamp,
+                        TimestampSource::Software,
+                    )));
+                }
+                Output::General(data) => {
+                    result.push(&frame(GENERAL, &data));
+                }
+                Output::Timer(name, duration) => {
+                    result.push(&timer(name, duration));
+                }
+            }
+        }
+
+        result
+    }
+}
@@ -0,0 +1,446 @@
+/*
+ * C interface to the statime PTP state machines.
+ *
+ * Statime doesn't do any IO itself. The application passes everything that
+ * happens to a port into it: received packets, expired timers and the times
+ * at which packets were sent. In response, the port queues actions, which
+ * the application takes one at a time with statime_port_next_action and
+ * carries out. The only callbacks are those of the clock that is being
+ * synchronized.
+ *
+ * None of these functions are thread safe: an instance and its ports must
+ * only be used from one thread at a time.
+ */
+
+#ifndef STATIME_H
+#define STATIME_H
+
+/* Generated with cbindgen from statime-ffi, do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * What a port asks to be done
+ */
+typedef enum StatimeActionKind {
+  /**
+   * Send `data` to the event port (319), and report the time it was sent
+   * with `statime_port_handle_send_timestamp`, passing `context`
+   */
+  STATIME_ACTION_KIND_SEND_EVENT,
+  /**
+   * Send `data` to the general port (320)
+   */
+  STATIME_ACTION_KIND_SEND_GENERAL,
+  /**
+   * Send `data` to the event port (319) of the port identified by
+   * `clock_identity` and `port_number`, and report the time it was sent
+   * like for a send event action
+   */
+  STATIME_ACTION_KIND_SEND_UNICAST_EVENT,
+  /**
+   * Send `data` to the general port (320) of the port identified by
+   * `clock_identity` and `port_number`
+   */
+  STATIME_ACTION_KIND_SEND_UNICAST_GENERAL,
+  /**
+   * Send `data` to the general port (320) of entry `master` of the unicast
+   * master table of the application
+   */
+  STATIME_ACTION_KIND_SEND_TO_UNICAST_MASTER,
+  /**
+   * (Re)start `timer` to expire after `duration_ns`
+   */
+  STATIME_ACTION_KIND_RESET_TIMER,
+} StatimeActionKind;
+
+/**
+ * The delay mechanism of a port
+ */
+typedef enum StatimeDelayMechanism {
+  /**
+   * End to end delay measurement
+   */
+  STATIME_DELAY_MECHANISM_E2E,
+  /**
+   * No delay measurement, assuming a fixed path delay
+   */
+  STATIME_DELAY_MECHANISM_ONE_WAY,
+  /**
+   * Peer to peer delay measurement
+   */
+  STATIME_DELAY_MECHANISM_P2P,
+} StatimeDelayMechanism;
+
+/**
+ * Which PTP management messages a port acts on, see [`ManagementPolicy`]
+ */
+typedef enum StatimeManagementPolicy {
+  /**
+   * Only answer GET requests
+   */
+  STATIME_MANAGEMENT_POLICY_READ_ONLY,
+  /**
+   * Also accept SET and COMMAND messages that did not pass a boundary
+   * clock
+   */
+  STATIME_MANAGEMENT_POLICY_LOCAL_ONLY,
+  /**
+   * Accept SET and COMMAND messages from any management node
+   */
+  STATIME_MANAGEMENT_POLICY_FULL,
+} StatimeManagementPolicy;
+
+/**
+ * The timers of a port
+ */
+typedef enum StatimeTimer {
+  STATIME_TIMER_ANNOUNCE,
+  STATIME_TIMER_SYNC,
+  STATIME_TIMER_DELAY_REQUEST,
+  STATIME_TIMER_ANNOUNCE_RECEIPT,
+  /**
+   * Only used by unicast clients, which start it by handling its expiry
+   * once
+   */
+  STATIME_TIMER_UNICAST_NEGOTIATION,
+} StatimeTimer;
+
+/**
+ * A PTP instance, created with [`statime_instance_new`]
+ */
+typedef struct StatimeInstance StatimeInstance;
+
+/**
+ * A port of an instance, created with [`statime_instance_add_port`]
+ */
+typedef struct StatimePort StatimePort;
+
+/**
+ * Configuration of an instance
+ */
+typedef struct StatimeInstanceConfig {
+  uint8_t clock_identity[8];
+  uint8_t priority_1;
+  uint8_t priority_2;
+  uint8_t domain_number;
+  bool slave_only;
+  uint16_t sdo_id;
+  /**
+   * Use the PTP timescale with the given UTC offset, instead of an
+   * arbitrary timescale
+   */
+  bool ptp_timescale;
+  int16_t current_utc_offset;
+  /**
+   * Whether packets are timestamped in hardware
+   */
+  bool hardware_timestamping;
+} StatimeInstanceConfig;
+
+/**
+ * A point in time, as seconds and nanoseconds since the PTP epoch
+ */
+typedef struct StatimeTime {
+  uint64_t seconds;
+  uint32_t nanoseconds;
+} StatimeTime;
+
+/**
+ * Callbacks to read and steer the clock that is synchronized
+ */
+typedef struct StatimeClock {
+  /**
+   * Passed to every callback
+   */
+  void *context;
+  /**
+   * The current time of the clock
+   */
+  struct StatimeTime (*now)(void *context);
+  /**
+   * Step the clock by `time_offset_ns`, and multiply its frequency by
+   * `frequency_multiplier`. Returns false if the clock couldn't be adjusted.
+   */
+  bool (*adjust)(void *context, double time_offset_ns, double frequency_multiplier);
+} StatimeClock;
+
+/**
+ * Configuration of a port
+ */
+typedef struct StatimePortConfig {
+  enum StatimeDelayMechanism delay_mechanism;
+  /**
+   * Only used with [`StatimeDelayMechanism::E2E`] and
+   * [`StatimeDelayMechanism::P2P`]
+   */
+  int8_t log_min_delay_request_interval;
+  /**
+   * Only used with [`StatimeDelayMechanism::OneWay`]
+   */
+  int64_t path_delay_ns;
+  /**
+   * Only used with [`StatimeDelayMechanism::P2P`], for hardware that adds
+   * the turnaround time to peer delay responses
+   */
+  bool one_step_peer_delay_responder;
+  int8_t log_announce_interval;
+  uint8_t announce_receipt_timeout;
+  int8_t log_sync_interval;
+  bool master_only;
+  /**
+   * Positive when the path from the master is slower than the path to it
+   */
+  int64_t delay_asymmetry_ns;
+  /**
+   * How much later than the packet passed the network interface receive
+   * timestamps are taken
+   */
+  int64_t ingress_latency_ns;
+  /**
+   * How much earlier than the packet passed the network interface send
+   * timestamps are taken
+   */
+  int64_t egress_latency_ns;
+  bool unicast;
+  enum StatimeManagementPolicy management_policy;
+  /**
+   * Seed for the randomization of timeouts, which should differ between
+   * ports and nodes
+   */
+  uint64_t random_seed;
+} StatimePortConfig;
+
+/**
+ * Configuration of a unicast master, see [`statime_port_set_unicast_master`]
+ */
+typedef struct StatimeUnicastMasterConfig {
+  /**
+   * The number of clients granted each type of message at the same time
+   */
+  size_t max_clients;
+  /**
+   * The longest grant given, in seconds
+   */
+  uint32_t max_lease_duration;
+  /**
+   * The shortest interval between announce messages to a client that is
+   * granted
+   */
+  int8_t log_min_announce_interval;
+  /**
+   * The shortest interval between sync messages to a client that is
+   * granted
+   */
+  int8_t log_min_sync_interval;
+  /**
+   * The shortest interval between the delay requests of a client that are
+   * answered
+   */
+  int8_t log_min_delay_resp_interval;
+} StatimeUnicastMasterConfig;
+
+/**
+ * Configuration of a unicast client, see [`statime_port_set_unicast_client`]
+ */
+typedef struct StatimeUnicastClientConfig {
+  /**
+   * The number of entries in the unicast master table of the application,
+   * at most 8
+   */
+  size_t masters;
+  /**
+   * The duration of the grants requested, in seconds
+   */
+  uint32_t lease_duration;
+} StatimeUnicastClientConfig;
+
+/**
+ * A single action, see [`StatimeActionKind`] for which fields are used
+ */
+typedef struct StatimeAction {
+  enum StatimeActionKind kind;
+  const uint8_t *data;
+  size_t data_len;
+  uint32_t context;
+  uint8_t clock_identity[8];
+  uint16_t port_number;
+  size_t master;
+  enum StatimeTimer timer;
+  uint64_t duration_ns;
+} StatimeAction;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create an instance, returning NULL if the configuration or clock is
+ * invalid.
+ *
+ * # Safety
+ *
+ * `config` must point to a valid configuration, and the callbacks of `clock`
+ * must be safe to call with its context for as long as the instance exists.
+ */
+struct StatimeInstance *statime_instance_new(const struct StatimeInstanceConfig *config,
+                                             struct StatimeClock clock);
+
+/**
+ * Free an instance. All of its ports must have been freed before.
+ *
+ * # Safety
+ *
+ * `instance` must be NULL or returned by [`statime_instance_new`], and must
+ * not be used afterwards.
+ */
+void statime_instance_free(struct StatimeInstance *instance);
+
+/**
+ * Add a port to the instance, returning NULL if the configuration is
+ * invalid. Ports have to be added before the BMCA is first run.
+ *
+ * # Safety
+ *
+ * `instance` must be returned by [`statime_instance_new`] and outlive the
+ * port, and `config` must point to a valid configuration.
+ */
+struct StatimePort *statime_instance_add_port(const struct StatimeInstance *instance,
+                                              const struct StatimePortConfig *config);
+
+/**
+ * Free a port.
+ *
+ * # Safety
+ *
+ * `port` must be NULL or returned by [`statime_instance_add_port`], and must
+ * not be used afterwards.
+ */
+void statime_port_free(struct StatimePort *port);
+
+/**
+ * Run the best master clock algorithm over all ports of the instance.
+ *
+ * # Safety
+ *
+ * `instance` must be returned by [`statime_instance_new`], and `ports` must
+ * point to `count` distinct ports of that instance.
+ */
+bool statime_instance_bmca(const struct StatimeInstance *instance,
+                           struct StatimePort *const *ports,
+                           size_t count);
+
+/**
+ * Interval at which [`statime_instance_bmca`] should be called, in
+ * nanoseconds. This depends on the ports, so it is only known once a port
+ * has been added.
+ *
+ * # Safety
+ *
+ * `instance` must be returned by [`statime_instance_new`].
+ */
+uint64_t statime_instance_bmca_interval_ns(const struct StatimeInstance *instance);
+
+/**
+ * Handle a packet received on the event port (319), timestamped on
+ * reception.
+ *
+ * # Safety
+ *
+ * `port` must be returned by [`statime_instance_add_port`], and `data` must
+ * point to `len` readable bytes.
+ */
+void statime_port_handle_event_receive(struct StatimePort *port,
+                                       const uint8_t *data,
+                                       size_t len,
+                                       struct StatimeTime timestamp);
+
+/**
+ * Handle a packet received on the general port (320).
+ *
+ * # Safety
+ *
+ * `port` must be returned by [`statime_instance_add_port`], and `data` must
+ * point to `len` readable bytes.
+ */
+void statime_port_handle_general_receive(struct StatimePort *port, const uint8_t *data, size_t len);
+
+/**
+ * Report the time a packet of a send event action was sent. Returns false
+ * if the context is unknown or was already used.
+ *
+ * # Safety
+ *
+ * `port` must be returned by [`statime_instance_add_port`].
+ */
+bool statime_port_handle_send_timestamp(struct StatimePort *port,
+                                        uint32_t context,
+                                        struct StatimeTime timestamp);
+
+/**
+ * Handle the expiry of a timer.
+ *
+ * # Safety
+ *
+ * `port` must be returned by [`statime_instance_add_port`].
+ */
+void statime_port_handle_timer(struct StatimePort *port, enum StatimeTimer timer);
+
+/**
+ * Grant unicast transmission to clients that request it, to up to
+ * `max_clients` clients at the same time. Passing NULL stops granting, but
+ * doesn't cancel the current grants. Returns false if the configuration is
+ * invalid, keeping the previous one.
+ *
+ * Only used by ports configured for unicast. Messages to a client are sent
+ * with the unicast send actions, so the application has to remember the
+ * address each port identity last sent from.
+ *
+ * # Safety
+ *
+ * `port` must be returned by [`statime_instance_add_port`], and `config`
+ * must be NULL or point to a valid configuration.
+ */
+bool statime_port_set_unicast_master(struct StatimePort *port,
+                                     const struct StatimeUnicastMasterConfig *config);
+
+/**
+ * Request unicast transmission from the entries of the unicast master table
+ * of the application, which has `masters` entries. Passing NULL stops
+ * requesting. Returns false if the configuration is invalid, keeping the
+ * previous one.
+ *
+ * Only used by ports configured for unicast. Requests are sent with
+ * `STATIME_ACTION_KIND_SEND_TO_UNICAST_MASTER` actions, starting once the
+ * port handles an expiry of `STATIME_TIMER_UNICAST_NEGOTIATION`, so that
+ * should be passed to the port once after configuring it.
+ *
+ * # Safety
+ *
+ * `port` must be returned by [`statime_instance_add_port`], and `config`
+ * must be NULL or point to a valid configuration.
+ */
+bool statime_port_set_unicast_client(struct StatimePort *port,
+                                     const struct StatimeUnicastClientConfig *config);
+
+/**
+ * Take the next action of the port, returning false when there are none
+ * left. The data of the action stays valid until the port handles its next
+ * input or BMCA run.
+ *
+ * # Safety
+ *
+ * `port` must be returned by [`statime_instance_add_port`], and `action`
+ * must point to writable memory for an action.
+ */
+bool statime_port_next_action(struct StatimePort *port, struct StatimeAction *action);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* STATIME_H */
@@ -0,0 +1,222 @@
+//! Driving a port from code that can't borrow from it, shared by the C
+//! interface and the Python bindings.
+//!
+//! The actions of a port borrow the port, so they are copied out before it
+//! handles anything else. The timestamp contexts of event messages stay with
+//! the driver, and are replaced by numbers that are passed back with the send
+//! timestamp.
+
+use rand::Rng;
+use statime::{
+    Clock, ClockIdentity, Filter, InBmca, Port, PortAction, PortActionIterator, Running, Time,
+    TimestampContext, TimestampSource,
+};
+
+/// The timers of a port
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatimeTimer {
+    Announce,
+    Sync,
+    DelayRequest,
+    AnnounceReceipt,
+    /// Only used by unicast clients, which start it by handling its expiry
+    /// once
+    UnicastNegotiation,
+}
+
+/// An action of a port, copied out of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Send an event message, and report the time it was sent with
+    /// [`PortDriver::send_timestamp`], passing the context
+    SendEvent { data: Vec<u8>, context: u32 },
+    /// Send a general message
+    SendGeneral { data: Vec<u8> },
+    /// Send an event message to a single port, and report the time it was
+    /// sent like for [`Action::SendEvent`]
+    SendUnicastEvent {
+        data: Vec<u8>,
+        context: u32,
+        clock_identity: ClockIdentity,
+        port_number: u16,
+    },
+    /// Send a general message to a single port
+    SendUnicastGeneral {
+        data: Vec<u8>,
+        clock_identity: ClockIdentity,
+        port_number: u16,
+    },
+    /// Send a general message to an entry of the unicast master table of the
+    /// runtime
+    SendToUnicastMaster { data: Vec<u8>, master: usize },
+    /// (Re)start a timer
+    ResetTimer {
+        timer: StatimeTimer,
+        duration: core::time::Duration,
+    },
+}
+
+/// A running port, with the timestamp contexts of the event messages it sent
+#[derive(Debug)]
+pub struct PortDriver<'a, C, F, R> {
+    // Only None while the BMCA is running
+    port: Option<Port<Running<'a, C, F>, R>>,
+    timestamp_source: TimestampSource,
+    contexts: Vec<(u32, TimestampContext)>,
+    next_context: u32,
+}
+
+impl<'a, C: Clock, F: Filter, R: Rng> PortDriver<'a, C, F, R> {
+    /// Drive a port that was just added to its instance, returning the
+    /// actions of starting it. Packets are timestamped by `timestamp_source`.
+    pub fn new(
+        port: Port<InBmca<'a, C, F>, R>,
+        timestamp_source: TimestampSource,
+    ) -> (Self, Vec<Action>) {
+        let mut driver = Self {
+            port: None,
+            timestamp_source,
+            contexts: Vec::new(),
+            next_context: 0,
+        };
+        let actions = driver.end_bmca(port);
+        (driver, actions)
+    }
+
+    /// The port, unless it was taken for the BMCA
+    pub fn port_mut(&mut self) -> Option<&mut Port<Running<'a, C, F>, R>> {
+        self.port.as_mut()
+    }
+
+    /// Handle an event message received at `timestamp`
+    pub fn receive_event(&mut self, data: &[u8], timestamp: Time) -> Vec<Action> {
+        let source = self.timestamp_source;
+        self.handle(|port| port.handle_timecritical_receive_from(data, timestamp, source))
+    }
+
+    /// Handle a general message
+    pub fn receive_general(&mut self, data: &[u8]) -> Vec<Action> {
+        self.handle(|port| port.handle_general_receive(data))
+    }
+
+    /// Report the time an event message was sent. Returns `None` if the
+    /// context is unknown or was already used.
+    pub fn send_timestamp(&mut self, context: u32, timestamp: Time) -> Option<Vec<Action>> {
+        let index = self.contexts.iter().position(|(id, _)| *id == context)?;
+        let (_, context) = self.contexts.swap_remove(index);
+        let source = self.timestamp_source;
+        Some(self.handle(|port| port.handle_send_timestamp_from(context, timestamp, source)))
+    }
+
+    /// Handle the expiry of a timer
+    pub fn timer(&mut self, timer: StatimeTimer) -> Vec<Action> {
+        self.handle(|port| match timer {
+            StatimeTimer::Announce => port.handle_announce_timer(),
+            StatimeTimer::Sync => port.handle_sync_timer(),
+            StatimeTimer::DelayRequest => port.handle_delay_request_timer(),
+            StatimeTimer::AnnounceReceipt => port.handle_announce_receipt_timer(),
+            StatimeTimer::UnicastNegotiation => port.handle_unicast_negotiation_timer(),
+        })
+    }
+
+    /// Run a handler on the port and copy out the resulting actions. Does
+    /// nothing while the port is taken for the BMCA.
+    pub fn handle(
+        &mut self,
+        handler: impl for<'p> FnOnce(&'p mut Port<Running<'a, C, F>, R>) -> PortActionIterator<'p>,
+    ) -> Vec<Action> {
+        let Some(mut port) = self.port.take() else {
+            return Vec::new();
+        };
+        let actions = self.collect(handler(&mut port));
+        self.port = Some(port);
+        actions
+    }
+
+    /// Take the port to run the BMCA over, to be returned with
+    /// [`PortDriver::end_bmca`]
+    pub fn start_bmca(&mut self) -> Option<Port<InBmca<'a, C, F>, R>> {
+        Some(self.port.take()?.start_bmca())
+    }
+
+    /// Return the port after the BMCA, and copy out the resulting actions
+    pub fn end_bmca(&mut self, port: Port<InBmca<'a, C, F>, R>) -> Vec<Action> {
+        let (port, actions) = port.end_bmca();
+        let actions = self.collect(actions);
+        self.port = Some(port);
+        actions
+    }
+
+    fn context_id(&mut self, context: TimestampContext) -> u32 {
+        let id = self.next_context;
+        self.next_context = self.next_context.wrapping_add(1);
+        self.contexts.push((id, context));
+        id
+    }
+
+    fn collect(&mut self, actions: PortActionIterator<'_>) -> Vec<Action> {
+        actions
+            .filter_map(|action| {
+                Some(match action {
+                    PortAction::SendTimeCritical { context, data, .. } => Action::SendEvent {
+                        data: data.to_vec(),
+                        context: self.context_id(context),
+                    },
+                    PortAction::SendGeneral { data } => Action::SendGeneral {
+                        data: data.to_vec(),
+                    },
+                    PortAction::SendUnicastTimeCritical {
+                        context,
+                        data,
+                        clock_identity,
+                        port_number,
+                    } => Action::SendUnicastEvent {
+                        data: data.to_vec(),
+                        context: self.context_id(context),
+                        clock_identity,
+                        port_number,
+                    },
+                    PortAction::SendUnicastGeneral {
+                        data,
+                        clock_identity,
+                        port_number,
+                    } => Action::SendUnicastGeneral {
+                        data: data.to_vec(),
+                        clock_identity,
+                        port_number,
+                    },
+                    PortAction::SendToUnicastMaster { data, master } => {
+                        Action::SendToUnicastMaster {
+                            data: data.to_vec(),
+                            master,
+                        }
+                    }
+                    // Only started for statistics windows, which the bindings
+                    // don't enable
+                    PortAction::ResetStatisticsTimer { .. } => return None,
+                    PortAction::ResetAnnounceTimer { duration } => Action::ResetTimer {
+                        timer: StatimeTimer::Announce,
+                        duration,
+                    },
+                    PortAction::ResetSyncTimer { duration } => Action::ResetTimer {
+                        timer: StatimeTimer::Sync,
+                        duration,
+                    },
+                    PortAction::ResetDelayRequestTimer { duration } => Action::ResetTimer {
+                        timer: StatimeTimer::DelayRequest,
+                        duration,
+                    },
+                    PortAction::ResetAnnounceReceiptTimer { duration } => Action::ResetTimer {
+                        timer: StatimeTimer::AnnounceReceipt,
+                        duration,
+                    },
+                    PortAction::ResetUnicastNegotiationTimer { duration } => Action::ResetTimer {
+                        timer: StatimeTimer::UnicastNegotiation,
+                        duration,
+                    },
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,951 @@
+//! C interface to statime. The header `include/statime.h` is generated from
+//! this crate with cbindgen, see the README.
+//!
+//! Statime doesn't do any IO itself, so embedding it only requires passing
+//! received packets, timer expiries and send timestamps into a port, and
+//! carrying out the actions it returns. These are queued inside each port and
+//! retrieved one at a time with [`statime_port_next_action`], which keeps the
+//! interface free of callbacks except for the clock.
+//!
+//! Instances and ports are handed out as pointers to heap allocations. A port
+//! borrows its instance, so all ports have to be freed before the instance.
+
+use std::{collections::VecDeque, ffi::c_void};
+
+pub use driver::StatimeTimer;
+use driver::{Action, PortDriver};
+use rand::{rngs::SmallRng, SeedableRng};
+use statime::{
+    BasicFilter, Clock, ClockIdentity, CommunicationMode, DelayMechanism, Duration, InBmca,
+    InstanceConfig, Interval, LogMessageIntervals, ManagementPolicy, Port, PortConfig, PtpInstance,
+    SdoId, Time, TimePropertiesDS, TimeSource, TimestampSource, TimestampingQuality,
+    TransmitEnable, UnicastClientConfig, UnicastGrantSlot, UnicastMasterConfig,
+};
+
+pub mod driver;
+
+/// A point in time, as seconds and nanoseconds since the PTP epoch
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatimeTime {
+    pub seconds: u64,
+    pub nanoseconds: u32,
+}
+
+impl From<StatimeTime> for Time {
+    fn from(time: StatimeTime) -> Self {
+        Time::from_secs(time.seconds) + Duration::from_nanos(time.nanoseconds.into())
+    }
+}
+
+/// Callbacks to read and steer the clock that is synchronized
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct StatimeClock {
+    /// Passed to every callback
+    pub context: *mut c_void,
+    /// The current time of the clock
+    pub now: Option<extern "C" fn(context: *mut c_void) -> StatimeTime>,
+    /// Step the clock by `time_offset_ns`, and multiply its frequency by
+    /// `frequency_multiplier`. Returns false if the clock couldn't be adjusted.
+    pub adjust: Option<
+        extern "C" fn(context: *mut c_void, time_offset_ns: f64, frequency_multiplier: f64) -> bool,
+    >,
+}
+
+#[derive(Debug)]
+struct AdjustError;
+
+#[derive(Debug)]
+struct FfiClock {
+    context: *mut c_void,
+    now: extern "C" fn(context: *mut c_void) -> StatimeTime,
+    adjust: extern "C" fn(context: *mut c_void, f64, f64) -> bool,
+}
+
+impl Clock for FfiClock {
+    type Error = AdjustError;
+
+    fn now(&self) -> Time {
+        (self.now)(self.context).into()
+    }
+
+    fn adjust(
+        &mut self,
+        time_offset: Duration,
+        frequency_multiplier: f64,
+        _time_properties_ds: &TimePropertiesDS,
+    ) -> Result<(), Self::Error> {
+        match (self.adjust)(
+            self.context,
+            time_offset.nanos_lossy(),
+            frequency_multiplier,
+        ) {
+            true => Ok(()),
+            false => Err(AdjustError),
+        }
+    }
+}
+
+/// Configuration of an instance
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct StatimeInstanceConfig {
+    pub clock_identity: [u8; 8],
+    pub priority_1: u8,
+    pub priority_2: u8,
+    pub domain_number: u8,
+    pub slave_only: bool,
+    pub sdo_id: u16,
+    /// Use the PTP timescale with the given UTC offset, instead of an
+    /// arbitrary timescale
+    pub ptp_timescale: bool,
+    pub current_utc_offset: i16,
+    /// Whether packets are timestamped in hardware
+    pub hardware_timestamping: bool,
+}
+
+/// The delay mechanism of a port
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatimeDelayMechanism {
+    /// End to end delay measurement
+    E2E,
+    /// No delay measurement, assuming a fixed path delay
+    OneWay,
+    /// Peer to peer delay measurement
+    P2P,
+}
+
+/// Which PTP management messages a port acts on, see [`ManagementPolicy`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatimeManagementPolicy {
+    /// Only answer GET requests
+    ReadOnly,
+    /// Also accept SET and COMMAND messages that did not pass a boundary
+    /// clock
+    LocalOnly,
+    /// Accept SET and COMMAND messages from any management node
+    Full,
+}
+
+/// Configuration of a port
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct StatimePortConfig {
+    pub delay_mechanism: StatimeDelayMechanism,
+    /// Only used with [`StatimeDelayMechanism::E2E`] and
+    /// [`StatimeDelayMechanism::P2P`]
+    pub log_min_delay_request_interval: i8,
+    /// Only used with [`StatimeDelayMechanism::OneWay`]
+    pub path_delay_ns: i64,
+    /// Only used with [`StatimeDelayMechanism::P2P`], for hardware that adds
+    /// the turnaround time to peer delay responses
+    pub one_step_peer_delay_responder: bool,
+    pub log_announce_interval: i8,
+    pub announce_receipt_timeout: u8,
+    pub log_sync_interval: i8,
+    pub master_only: bool,
+    /// Positive when the path from the master is slower than the path to it
+    pub delay_asymmetry_ns: i64,
+    /// How much later than the packet passed the network interface receive
+    /// timestamps are taken
+    pub ingress_latency_ns: i64,
+    /// How much earlier than the packet passed the network interface send
+    /// timestamps are taken
+    pub egress_latency_ns: i64,
+    pub unicast: bool,
+    pub management_policy: StatimeManagementPolicy,
+    /// Seed for the randomization of timeouts, which should differ between
+    /// ports and nodes
+    pub random_seed: u64,
+}
+
+/// Configuration of a unicast master, see [`statime_port_set_unicast_master`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct StatimeUnicastMasterConfig {
+    /// The number of clients granted each type of message at the same time
+    pub max_clients: usize,
+    /// The longest grant given, in seconds
+    pub max_lease_duration: u32,
+    /// The shortest interval between announce messages to a client that is
+    /// granted
+    pub log_min_announce_interval: i8,
+    /// The shortest interval between sync messages to a client that is
+    /// granted
+    pub log_min_sync_interval: i8,
+    /// The shortest interval between the delay requests of a client that are
+    /// answered
+    pub log_min_delay_resp_interval: i8,
+}
+
+/// Configuration of a unicast client, see [`statime_port_set_unicast_client`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct StatimeUnicastClientConfig {
+    /// The number of entries in the unicast master table of the application,
+    /// at most 8
+    pub masters: usize,
+    /// The duration of the grants requested, in seconds
+    pub lease_duration: u32,
+}
+
+/// What a port asks to be done
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatimeActionKind {
+    /// Send `data` to the event port (319), and report the time it was sent
+    /// with `statime_port_handle_send_timestamp`, passing `context`
+    SendEvent,
+    /// Send `data` to the general port (320)
+    SendGeneral,
+    /// Send `data` to the event port (319) of the port identified by
+    /// `clock_identity` and `port_number`, and report the time it was sent
+    /// like for a send event action
+    SendUnicastEvent,
+    /// Send `data` to the general port (320) of the port identified by
+    /// `clock_identity` and `port_number`
+    SendUnicastGeneral,
+    /// Send `data` to the general port (320) of entry `master` of the unicast
+    /// master table of the application
+    SendToUnicastMaster,
+    /// (Re)start `timer` to expire after `duration_ns`
+    ResetTimer,
+}
+
+/// A single action, see [`StatimeActionKind`] for which fields are used
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct StatimeAction {
+    pub kind: StatimeActionKind,
+    pub data: *const u8,
+    pub data_len: usize,
+    pub context: u32,
+    pub clock_identity: [u8; 8],
+    pub port_number: u16,
+    pub master: usize,
+    pub timer: StatimeTimer,
+    pub duration_ns: u64,
+}
+
+impl StatimeAction {
+    fn new(kind: StatimeActionKind) -> Self {
+        Self {
+            kind,
+            data: std::ptr::null(),
+            data_len: 0,
+            context: 0,
+            clock_identity: [0; 8],
+            port_number: 0,
+            master: 0,
+            timer: StatimeTimer::Announce,
+            duration_ns: 0,
+        }
+    }
+
+    fn send(kind: StatimeActionKind, data: &[u8]) -> Self {
+        Self {
+            data: data.as_ptr(),
+            data_len: data.len(),
+            ..Self::new(kind)
+        }
+    }
+}
+
+type FfiInstance = PtpInstance<FfiClock, BasicFilter>;
+type FfiDriver = PortDriver<'static, FfiClock, BasicFilter, SmallRng>;
+type BmcaPort = Port<InBmca<'static, FfiClock, BasicFilter>, SmallRng>;
+
+/// A PTP instance, created with [`statime_instance_new`]
+pub struct StatimeInstance {
+    instance: FfiInstance,
+    config: InstanceConfig,
+    timestamp_source: TimestampSource,
+}
+
+// The grant table of a unicast master, which the port borrows for as long as
+// it is configured
+struct GrantTable(*mut [UnicastGrantSlot]);
+
+impl GrantTable {
+    fn new(slots: usize) -> Self {
+        let table: Box<[UnicastGrantSlot]> = (0..slots).map(|_| UnicastGrantSlot::EMPTY).collect();
+        Self(Box::into_raw(table))
+    }
+}
+
+impl Drop for GrantTable {
+    fn drop(&mut self) {
+        // SAFETY: created in new from a box, and no longer borrowed by the
+        // port
+        drop(unsafe { Box::from_raw(self.0) });
+    }
+}
+
+/// A port of an instance, created with [`statime_instance_add_port`]
+pub struct StatimePort {
+    driver: FfiDriver,
+    // Declared after the driver, so the port is dropped before the table it
+    // borrows
+    grants: GrantTable,
+    actions: VecDeque<Action>,
+    // Actions already returned, whose data the caller may still be reading
+    taken: Vec<Action>,
+}
+
+impl StatimePort {
+    fn push_actions(&mut self, actions: Vec<Action>) {
+        self.taken.clear();
+        self.actions.extend(actions);
+    }
+}
+
+/// Create an instance, returning NULL if the configuration or clock is
+/// invalid.
+///
+/// # Safety
+///
+/// `config` must point to a valid configuration, and the callbacks of `clock`
+/// must be safe to call with its context for as long as the instance exists.
+#[no_mangle]
+pub unsafe extern "C" fn statime_instance_new(
+    config: *const StatimeInstanceConfig,
+    clock: StatimeClock,
+) -> *mut StatimeInstance {
+    let Some(config) = config.as_ref() else {
+        return std::ptr::null_mut();
+    };
+    let (Some(now), Some(adjust)) = (clock.now, clock.adjust) else {
+        return std::ptr::null_mut();
+    };
+    let Some(sdo_id) = SdoId::new(config.sdo_id) else {
+        return std::ptr::null_mut();
+    };
+
+    let instance_config = InstanceConfig {
+        clock_identity: ClockIdentity(config.clock_identity),
+        priority_1: config.priority_1,
+        priority_2: config.priority_2,
+        domain_number: config.domain_number,
+        slave_only: config.slave_only,
+        sdo_id,
+    };
+    if instance_config.validate().is_err() {
+        return std::ptr::null_mut();
+    }
+
+    let time_properties_ds = if config.ptp_timescale {
+        TimePropertiesDS::new_ptp_time(
+            Some(config.current_utc_offset),
+            Default::default(),
+            false,
+            false,
+            TimeSource::InternalOscillator,
+        )
+    } else {
+        TimePropertiesDS::new_arbitrary_time(false, false, TimeSource::InternalOscillator)
+    };
+
+    let (quality, timestamp_source) = if config.hardware_timestamping {
+        (TimestampingQuality::Hardware, TimestampSource::Hardware)
+    } else {
+        (TimestampingQuality::Software, TimestampSource::Software)
+    };
+
+    let instance = PtpInstance::new(
+        instance_config,
+        time_properties_ds,
+        FfiClock {
+            context: clock.context,
+            now,
+            adjust,
+        },
+        BasicFilter::for_quality(quality),
+    );
+
+    Box::into_raw(Box::new(StatimeInstance {
+        instance,
+        config: instance_config,
+        timestamp_source,
+    }))
+}
+
+/// Free an instance. All of its ports must have been freed before.
+///
+/// # Safety
+///
+/// `instance` must be NULL or returned by [`statime_instance_new`], and must
+/// not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn statime_instance_free(instance: *mut StatimeInstance) {
+    if !instance.is_null() {
+        drop(Box::from_raw(instance));
+    }
+}
+
+/// Add a port to the instance, returning NULL if the configuration is
+/// invalid. Ports have to be added before the BMCA is first run.
+///
+/// # Safety
+///
+/// `instance` must be returned by [`statime_instance_new`] and outlive the
+/// port, and `config` must point to a valid configuration.
+#[no_mangle]
+pub unsafe extern "C" fn statime_instance_add_port(
+    instance: *const StatimeInstance,
+    config: *const StatimePortConfig,
+) -> *mut StatimePort {
+    let (Some(instance), Some(config)) = (instance.as_ref(), config.as_ref()) else {
+        return std::ptr::null_mut();
+    };
+
+    let delay_mechanism = match config.delay_mechanism {
+        StatimeDelayMechanism::E2E => DelayMechanism::E2E {
+            interval: Interval::from_log_2(config.log_min_delay_request_interval),
+        },
+        StatimeDelayMechanism::OneWay => DelayMechanism::OneWay {
+            path_delay: Duration::from_nanos(config.path_delay_ns),
+        },
+        StatimeDelayMechanism::P2P => DelayMechanism::P2P {
+            interval: Interval::from_log_2(config.log_min_delay_request_interval),
+            one_step_responder: config.one_step_peer_delay_responder,
+        },
+    };
+
+    let port_config = PortConfig {
+        delay_mechanism,
+        announce_interval: Interval::from_log_2(config.log_announce_interval),
+        announce_receipt_timeout: config.announce_receipt_timeout,
+        sync_interval: Interval::from_log_2(config.log_sync_interval),
+        master_only: config.master_only,
+        delay_asymmetry: Duration::from_nanos(config.delay_asymmetry_ns),
+        ingress_latency: Duration::from_nanos(config.ingress_latency_ns),
+        egress_latency: Duration::from_nanos(config.egress_latency_ns),
+        communication_mode: if config.unicast {
+            CommunicationMode::Unicast
+        } else {
+            CommunicationMode::Multicast
+        },
+        transmit: TransmitEnable::ALL,
+        unicast_client: None,
+        log_message_intervals: LogMessageIntervals::STANDARD,
+        management: match config.management_policy {
+            StatimeManagementPolicy::ReadOnly => ManagementPolicy::ReadOnly,
+            StatimeManagementPolicy::LocalOnly => ManagementPolicy::LocalOnly,
+            StatimeManagementPolicy::Full => ManagementPolicy::Full,
+        },
+    };
+    if port_config.validate(&instance.config).is_err() {
+        return std::ptr::null_mut();
+    }
+
+    let rng = SmallRng::seed_from_u64(config.random_seed);
+    let port = instance.instance.add_port(port_config, rng);
+    let (driver, actions) = PortDriver::new(port, instance.timestamp_source);
+
+    let mut port = StatimePort {
+        driver,
+        grants: GrantTable::new(0),
+        actions: VecDeque::new(),
+        taken: Vec::new(),
+    };
+    port.push_actions(actions);
+    Box::into_raw(Box::new(port))
+}
+
+/// Free a port.
+///
+/// # Safety
+///
+/// `port` must be NULL or returned by [`statime_instance_add_port`], and must
+/// not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn statime_port_free(port: *mut StatimePort) {
+    if !port.is_null() {
+        drop(Box::from_raw(port));
+    }
+}
+
+/// Run the best master clock algorithm over all ports of the instance.
+///
+/// # Safety
+///
+/// `instance` must be returned by [`statime_instance_new`], and `ports` must
+/// point to `count` distinct ports of that instance.
+#[no_mangle]
+pub unsafe extern "C" fn statime_instance_bmca(
+    instance: *const StatimeInstance,
+    ports: *const *mut StatimePort,
+    count: usize,
+) -> bool {
+    let Some(instance) = instance.as_ref() else {
+        return false;
+    };
+    if ports.is_null() && count > 0 {
+        return false;
+    }
+
+    let ports: Vec<&mut StatimePort> = (0..count)
+        .filter_map(|index| (*ports.add(index)).as_mut())
+        .collect();
+    if ports.len() != count {
+        return false;
+    }
+
+    let mut bmca_ports: Vec<(&mut StatimePort, BmcaPort)> = ports
+        .into_iter()
+        .filter_map(|port| {
+            let bmca_port = port.driver.start_bmca()?;
+            Some((port, bmca_port))
+        })
+        .collect();
+
+    let mut refs: Vec<&mut BmcaPort> = bmca_ports.iter_mut().map(|(_, port)| port).collect();
+    instance.instance.bmca(&mut refs);
+    drop(refs);
+
+    for (wrapper, port) in bmca_ports {
+        let actions = wrapper.driver.end_bmca(port);
+        wrapper.push_actions(actions);
+    }
+
+    true
+}
+
+/// Interval at which [`statime_instance_bmca`] should be called, in
+/// nanoseconds. This depends on the ports, so it is only known once a port
+/// has been added.
+///
+/// # Safety
+///
+/// `instance` must be returned by [`statime_instance_new`].
+#[no_mangle]
+pub unsafe extern "C" fn statime_instance_bmca_interval_ns(
+    instance: *const StatimeInstance,
+) -> u64 {
+    match instance.as_ref() {
+        Some(instance) => instance.instance.bmca_interval().as_nanos() as u64,
+        None => 0,
+    }
+}
+
+/// Handle a packet received on the event port (319), timestamped on
+/// reception.
+///
+/// # Safety
+///
+/// `port` must be returned by [`statime_instance_add_port`], and `data` must
+/// point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn statime_port_handle_event_receive(
+    port: *mut StatimePort,
+    data: *const u8,
+    len: usize,
+    timestamp: StatimeTime,
+) {
+    let (Some(port), false) = (port.as_mut(), data.is_null()) else {
+        return;
+    };
+    let data = std::slice::from_raw_parts(data, len);
+    let actions = port.driver.receive_event(data, timestamp.into());
+    port.push_actions(actions);
+}
+
+/// Handle a packet received on the general port (320).
+///
+/// # Safety
+///
+/// `port` must be returned by [`statime_instance_add_port`], and `data` must
+/// point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn statime_port_handle_general_receive(
+    port: *mut StatimePort,
+    data: *const u8,
+    len: usize,
+) {
+    let (Some(port), false) = (port.as_mut(), data.is_null()) else {
+        return;
+    };
+    let data = std::slice::from_raw_parts(data, len);
+    let actions = port.driver.receive_general(data);
+    port.push_actions(actions);
+}
+
+/// Report the time a packet of a send event action was sent. Returns false
+/// if the context is unknown or was already used.
+///
+/// # Safety
+///
+/// `port` must be returned by [`statime_instance_add_port`].
+#[no_mangle]
+pub unsafe extern "C" fn statime_port_handle_send_timestamp(
+    port: *mut StatimePort,
+    context: u32,
+    timestamp: StatimeTime,
+) -> bool {
+    let Some(port) = port.as_mut() else {
+        return false;
+    };
+    let Some(actions) = port.driver.send_timestamp(context, timestamp.into()) else {
+        return false;
+    };
+
+    port.push_actions(actions);
+    true
+}
+
+/// Handle the expiry of a timer.
+///
+/// # Safety
+///
+/// `port` must be returned by [`statime_instance_add_port`].
+#[no_mangle]
+pub unsafe extern "C" fn statime_port_handle_timer(port: *mut StatimePort, timer: StatimeTimer) {
+    let Some(port) = port.as_mut() else {
+        return;
+    };
+    let actions = port.driver.timer(timer);
+    port.push_actions(actions);
+}
+
+/// Grant unicast transmission to clients that request it, to up to
+/// `max_clients` clients at the same time. Passing NULL stops granting, but
+/// doesn't cancel the current grants. Returns false if the configuration is
+/// invalid, keeping the previous one.
+///
+/// Only used by ports configured for unicast. Messages to a client are sent
+/// with the unicast send actions, so the application has to remember the
+/// address each port identity last sent from.
+///
+/// # Safety
+///
+/// `port` must be returned by [`statime_instance_add_port`], and `config`
+/// must be NULL or point to a valid configuration.
+#[no_mangle]
+pub unsafe extern "C" fn statime_port_set_unicast_master(
+    port: *mut StatimePort,
+    config: *const StatimeUnicastMasterConfig,
+) -> bool {
+    let Some(port) = port.as_mut() else {
+        return false;
+    };
+    let config = config.as_ref().map(|config| UnicastMasterConfig {
+        max_clients: config.max_clients,
+        max_lease_duration: config.max_lease_duration,
+        min_announce_interval: Interval::from_log_2(config.log_min_announce_interval),
+        min_sync_interval: Interval::from_log_2(config.log_min_sync_interval),
+        min_delay_resp_interval: Interval::from_log_2(config.log_min_delay_resp_interval),
+    });
+    let Some(running) = port.driver.port_mut() else {
+        return false;
+    };
+
+    let grants = GrantTable::new(config.map_or(0, |config| config.max_clients));
+    // SAFETY: the table lives until the port is dropped or borrows another
+    // table, see GrantTable
+    let table = &mut *grants.0;
+    if running.set_unicast_master(config, table).is_err() {
+        return false;
+    }
+    // The port no longer borrows the previous table
+    port.grants = grants;
+    true
+}
+
+/// Request unicast transmission from the entries of the unicast master table
+/// of the application, which has `masters` entries. Passing NULL stops
+/// requesting. Returns false if the configuration is invalid, keeping the
+/// previous one.
+///
+/// Only used by ports configured for unicast. Requests are sent with
+/// `STATIME_ACTION_KIND_SEND_TO_UNICAST_MASTER` actions, starting once the
+/// port handles an expiry of `STATIME_TIMER_UNICAST_NEGOTIATION`, so that
+/// should be passed to the port once after configuring it.
+///
+/// # Safety
+///
+/// `port` must be returned by [`statime_instance_add_port`], and `config`
+/// must be NULL or point to a valid configuration.
+#[no_mangle]
+pub unsafe extern "C" fn statime_port_set_unicast_client(
+    port: *mut StatimePort,
+    config: *const StatimeUnicastClientConfig,
+) -> bool {
+    let Some(running) = port.as_mut().and_then(|port| port.driver.port_mut()) else {
+        return false;
+    };
+    let config = config.as_ref().map(|config| UnicastClientConfig {
+        masters: config.masters,
+        lease_duration: config.lease_duration,
+    });
+    running.set_unicast_client(config).is_ok()
+}
+
+/// Take the next action of the port, returning false when there are none
+/// left. The data of the action stays valid until the port handles its next
+/// input or BMCA run.
+///
+/// # Safety
+///
+/// `port` must be returned by [`statime_instance_add_port`], and `action`
+/// must point to writable memory for an action.
+#[no_mangle]
+pub unsafe extern "C" fn statime_port_next_action(
+    port: *mut StatimePort,
+    action: *mut StatimeAction,
+) -> bool {
+    let (Some(port), Some(action)) = (port.as_mut(), action.as_mut()) else {
+        return false;
+    };
+
+    let Some(current) = port.actions.pop_front() else {
+        return false;
+    };
+
+    *action = match &current {
+        Action::SendEvent { data, context } => StatimeAction {
+            context: *context,
+            ..StatimeAction::send(StatimeActionKind::SendEvent, data)
+        },
+        Action::SendGeneral { data } => StatimeAction::send(StatimeActionKind::SendGeneral, data),
+        Action::SendUnicastEvent {
+            data,
+            context,
+            clock_identity,
+            port_number,
+        } => StatimeAction {
+            context: *context,
+            clock_identity: clock_identity.0,
+            port_number: *port_number,
+            ..StatimeAction::send(StatimeActionKind::SendUnicastEvent, data)
+        },
+        Action::SendUnicastGeneral {
+            data,
+            clock_identity,
+            port_number,
+        } => StatimeAction {
+            clock_identity: clock_identity.0,
+            port_number: *port_number,
+            ..StatimeAction::send(StatimeActionKind::SendUnicastGeneral, data)
+        },
+        Action::SendToUnicastMaster { data, master } => StatimeAction {
+            master: *master,
+            ..StatimeAction::send(StatimeActionKind::SendToUnicastMaster, data)
+        },
+        Action::ResetTimer { timer, duration } => StatimeAction {
+            timer: *timer,
+            duration_ns: duration.as_nanos() as u64,
+            ..StatimeAction::new(StatimeActionKind::ResetTimer)
+        },
+    };
+    // Moving the action doesn't move the data it points to
+    port.taken.push(current);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn now(_context: *mut c_void) -> StatimeTime {
+        StatimeTime {
+            seconds: 1_000,
+            nanoseconds: 500,
+        }
+    }
+
+    extern "C" fn adjust(_context: *mut c_void, _offset: f64, _frequency: f64) -> bool {
+        true
+    }
+
+    fn actions(port: *mut StatimePort) -> Vec<StatimeAction> {
+        let mut actions = vec![];
+        let mut action = std::mem::MaybeUninit::uninit();
+        while unsafe { statime_port_next_action(port, action.as_mut_ptr()) } {
+            actions.push(unsafe { action.assume_init() });
+        }
+        actions
+    }
+
+    fn instance_config() -> StatimeInstanceConfig {
+        StatimeInstanceConfig {
+            clock_identity: [1; 8],
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: 0,
+            ptp_timescale: true,
+            current_utc_offset: 37,
+            hardware_timestamping: false,
+        }
+    }
+
+    fn clock() -> StatimeClock {
+        StatimeClock {
+            context: std::ptr::null_mut(),
+            now: Some(now),
+            adjust: Some(adjust),
+        }
+    }
+
+    fn port_config() -> StatimePortConfig {
+        StatimePortConfig {
+            delay_mechanism: StatimeDelayMechanism::E2E,
+            log_min_delay_request_interval: 0,
+            path_delay_ns: 0,
+            one_step_peer_delay_responder: false,
+            log_announce_interval: 1,
+            announce_receipt_timeout: 3,
+            log_sync_interval: 0,
+            master_only: false,
+            delay_asymmetry_ns: 0,
+            ingress_latency_ns: 0,
+            egress_latency_ns: 0,
+            unicast: false,
+            management_policy: StatimeManagementPolicy::ReadOnly,
+            random_seed: 1,
+        }
+    }
+
+    #[test]
+    fn master_sends_announce() {
+        let instance_config = instance_config();
+        let clock = clock();
+        let port_config = port_config();
+
+        unsafe {
+            let missing_clock = StatimeClock { now: None, ..clock };
+            assert!(statime_instance_new(&instance_config, missing_clock).is_null());
+
+            let instance = statime_instance_new(&instance_config, clock);
+            assert!(!instance.is_null());
+
+            let port = statime_instance_add_port(instance, &port_config);
+            assert!(!port.is_null());
+            assert_eq!(statime_instance_bmca_interval_ns(instance), 2_000_000_000);
+
+            // A new port listens for announce messages first
+            let started = actions(port);
+            assert!(started
+                .iter()
+                .any(|action| action.kind == StatimeActionKind::ResetTimer
+                    && action.timer == StatimeTimer::AnnounceReceipt));
+
+            // Without any other node, the port becomes master and starts
+            // announcing itself
+            statime_port_handle_timer(port, StatimeTimer::AnnounceReceipt);
+            assert!(statime_instance_bmca(instance, &port, 1));
+            let started = actions(port);
+            assert!(started
+                .iter()
+                .any(|action| action.kind == StatimeActionKind::ResetTimer
+                    && action.timer == StatimeTimer::Announce));
+
+            statime_port_handle_timer(port, StatimeTimer::Announce);
+            let announced = actions(port);
+            let announce = announced
+                .iter()
+                .find(|action| action.kind == StatimeActionKind::SendGeneral)
+                .unwrap();
+            // Announce message type, and the length in the header, with a
+            // path trace TLV of just the grandmaster
+            assert_eq!(*announce.data & 0x0f, 0xb);
+            assert_eq!(announce.data_len, 76);
+
+            statime_port_handle_timer(port, StatimeTimer::Sync);
+            let sync = actions(port)
+                .into_iter()
+                .find(|action| action.kind == StatimeActionKind::SendEvent)
+                .unwrap();
+            assert!(statime_port_handle_send_timestamp(
+                port,
+                sync.context,
+                now(std::ptr::null_mut())
+            ));
+            assert!(!statime_port_handle_send_timestamp(
+                port,
+                sync.context,
+                now(std::ptr::null_mut())
+            ));
+
+            statime_port_free(port);
+            statime_instance_free(instance);
+        }
+    }
+
+    #[test]
+    fn unicast_negotiation() {
+        let unicast = StatimePortConfig {
+            unicast: true,
+            ..port_config()
+        };
+        let master_config = StatimeUnicastMasterConfig {
+            max_clients: 2,
+            max_lease_duration: 300,
+            log_min_announce_interval: 0,
+            log_min_sync_interval: -2,
+            log_min_delay_resp_interval: -2,
+        };
+        let client_config = StatimeUnicastClientConfig {
+            masters: 1,
+            lease_duration: 60,
+        };
+
+        unsafe {
+            let master_instance = statime_instance_new(&instance_config(), clock());
+            let master = statime_instance_add_port(master_instance, &unicast);
+            let client_instance = statime_instance_new(
+                &StatimeInstanceConfig {
+                    clock_identity: [2; 8],
+                    ..instance_config()
+                },
+                clock(),
+            );
+            let client = statime_instance_add_port(client_instance, &unicast);
+            actions(master);
+            actions(client);
+
+            assert!(statime_port_set_unicast_master(master, &master_config));
+            assert!(!statime_port_set_unicast_client(
+                client,
+                &StatimeUnicastClientConfig {
+                    masters: 9,
+                    ..client_config
+                }
+            ));
+            assert!(statime_port_set_unicast_client(client, &client_config));
+
+            // The client starts requesting from its master on the first
+            // expiry of the negotiation timer
+            statime_port_handle_timer(client, StatimeTimer::UnicastNegotiation);
+            let requested = actions(client);
+            let request = requested
+                .iter()
+                .find(|action| action.kind == StatimeActionKind::SendToUnicastMaster)
+                .unwrap();
+            assert_eq!(request.master, 0);
+            assert!(requested
+                .iter()
+                .any(|action| action.kind == StatimeActionKind::ResetTimer
+                    && action.timer == StatimeTimer::UnicastNegotiation));
+
+            // The master answers to the identity of the client
+            let request = std::slice::from_raw_parts(request.data, request.data_len).to_vec();
+            statime_port_handle_general_receive(master, request.as_ptr(), request.len());
+            let grant = actions(master)
+                .into_iter()
+                .find(|action| action.kind == StatimeActionKind::SendUnicastGeneral)
+                .unwrap();
+            assert_eq!(grant.clock_identity, [2; 8]);
+            assert_eq!(grant.port_number, 0);
+
+            statime_port_free(client);
+            statime_port_free(master);
+            statime_instance_free(client_instance);
+            statime_instance_free(master_instance);
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! Batched socket IO using `recvmmsg` and `sendmmsg`
+//!
+//! At high message rates, handling one datagram per system call spends a
+//! significant amount of time in syscall overhead. These functions move up to
+//! [`BATCH_SIZE`] datagrams per call instead. Both are non-blocking, and report
+//! [`WouldBlock`](std::io::ErrorKind::WouldBlock) when nothing could be
+//! transferred, so they can be used from `AsyncFd::async_io`.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    os::fd::AsRawFd,
+};
+
+/// Maximum number of datagrams transferred per system call
+pub const BATCH_SIZE: usize = 16;
+
+/// Size of the receive buffers, large enough for any PTP message
+pub const RECV_BUFFER_SIZE: usize = 2048;
+
+// Room for the control message with the packet info of a datagram, in words
+// to align it
+const CONTROL_WORDS: usize = 8;
+
+/// A datagram received by [`recv_batch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceivedDatagram {
+    /// Number of bytes of the datagram in its buffer
+    pub length: usize,
+    /// The address the datagram was sent to. Only known for sockets with
+    /// [`set_packet_info`](crate::socket_options::set_packet_info).
+    pub destination: Option<IpAddr>,
+    /// The address the datagram was sent from
+    pub source: Option<SocketAddr>,
+}
+
+/// Receive up to [`BATCH_SIZE`] datagrams.
+///
+/// Datagram `i` is placed in `buffers[i]`. Datagrams larger than the buffer
+/// are truncated by the kernel, for which `None` is returned, as what is left
+/// of them is not a valid message.
+pub fn recv_batch(
+    socket: &UdpSocket,
+    buffers: &mut [[u8; RECV_BUFFER_SIZE]; BATCH_SIZE],
+) -> io::Result<Vec<Option<ReceivedDatagram>>> {
+    let mut iovecs: Vec<libc::iovec> = buffers
+        .iter_mut()
+        .map(|buffer| libc::iovec {
+            iov_base: buffer.as_mut_ptr().cast(),
+            iov_len: buffer.len(),
+        })
+        .collect();
+
+    let mut controls = [[0u64; CONTROL_WORDS]; BATCH_SIZE];
+    // SAFETY: sockaddr_storage is a plain C struct for which all zeroes is valid
+    let mut sources: [libc::sockaddr_storage; BATCH_SIZE] = unsafe { std::mem::zeroed() };
+    let mut headers: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .zip(&mut controls)
+        .zip(&mut sources)
+        .map(|((iovec, control), source)| {
+            // SAFETY: mmsghdr is a plain C struct for which all zeroes is valid
+            let mut header: libc::mmsghdr = unsafe { std::mem::zeroed() };
+            header.msg_hdr.msg_name = (source as *mut libc::sockaddr_storage).cast();
+            header.msg_hdr.msg_namelen = std::mem::size_of_val(source) as libc::socklen_t;
+            header.msg_hdr.msg_iov = iovec;
+            header.msg_hdr.msg_iovlen = 1;
+            header.msg_hdr.msg_control = control.as_mut_ptr().cast();
+            header.msg_hdr.msg_controllen = std::mem::size_of_val(control);
+            header
+        })
+        .collect();
+
+    // SAFETY: every header points to a single iovec, which points to a buffer
+    // of the given length, and to an address and a control buffer of the
+    // given lengths. All of them outlive the call.
+    let received = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            headers.as_mut_ptr(),
+            headers.len() as libc::c_uint,
+            libc::MSG_DONTWAIT,
+            std::ptr::null_mut(),
+        )
+    };
+
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(headers[..received as usize]
+        .iter()
+        .zip(&sources)
+        .map(|(header, source)| {
+            if header.msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+                return None;
+            }
+            Some(ReceivedDatagram {
+                length: header.msg_len as usize,
+                destination: packet_destination(&header.msg_hdr),
+                source: stored_address(source),
+            })
+        })
+        .collect())
+}
+
+// The socket address the kernel stored, if it is an IP address
+fn stored_address(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            // SAFETY: the kernel stored a sockaddr_in, and sockaddr_storage
+            // is suitably aligned for it
+            let address =
+                unsafe { &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>() };
+            Some(SocketAddr::new(
+                Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr)).into(),
+                u16::from_be(address.sin_port),
+            ))
+        }
+        libc::AF_INET6 => {
+            // SAFETY: the kernel stored a sockaddr_in6, and sockaddr_storage
+            // is suitably aligned for it
+            let address = unsafe {
+                &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>()
+            };
+            Some(SocketAddr::new(
+                Ipv6Addr::from(address.sin6_addr.s6_addr).into(),
+                u16::from_be(address.sin6_port),
+            ))
+        }
+        _ => None,
+    }
+}
+
+// The destination address in the packet info control message of a received
+// datagram, if any
+fn packet_destination(header: &libc::msghdr) -> Option<IpAddr> {
+    // SAFETY: recvmmsg filled in the control messages of the header, and
+    // CMSG_NXTHDR returns null after the last one that fits
+    let mut control = unsafe { libc::CMSG_FIRSTHDR(header) };
+    while !control.is_null() {
+        // SAFETY: control points to a complete control message header
+        let (level, kind) = unsafe { ((*control).cmsg_level, (*control).cmsg_type) };
+        match (level, kind) {
+            (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                // SAFETY: the kernel places an in_pktinfo after this header,
+                // which need not be aligned
+                let info: libc::in_pktinfo =
+                    unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(control).cast()) };
+                return Some(Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr)).into());
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                // SAFETY: the kernel places an in6_pktinfo after this header,
+                // which need not be aligned
+                let info: libc::in6_pktinfo =
+                    unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(control).cast()) };
+                return Some(Ipv6Addr::from(info.ipi6_addr.s6_addr).into());
+            }
+            _ => {}
+        }
+        // SAFETY: see CMSG_FIRSTHDR
+        control = unsafe { libc::CMSG_NXTHDR(header, control) };
+    }
+
+    None
+}
+
+/// Send datagrams to `address`, up to [`BATCH_SIZE`] per call, returning how
+/// many were sent. This can be less than the number of datagrams given.
+pub fn send_batch(
+    socket: &UdpSocket,
+    datagrams: &[&[u8]],
+    address: SocketAddr,
+) -> io::Result<usize> {
+    let (mut storage, address_len) = socket_address(address);
+
+    let mut iovecs: Vec<libc::iovec> = datagrams
+        .iter()
+        .take(BATCH_SIZE)
+        .map(|datagram| libc::iovec {
+            // sendmmsg doesn't write through the pointer
+            iov_base: datagram.as_ptr() as *mut libc::c_void,
+            iov_len: datagram.len(),
+        })
+        .collect();
+
+    let mut headers: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .map(|iovec| {
+            // SAFETY: mmsghdr is a plain C struct for which all zeroes is valid
+            let mut header: libc::mmsghdr = unsafe { std::mem::zeroed() };
+            header.msg_hdr.msg_name = (&mut storage as *mut libc::sockaddr_storage).cast();
+            header.msg_hdr.msg_namelen = address_len;
+            header.msg_hdr.msg_iov = iovec;
+            header.msg_hdr.msg_iovlen = 1;
+            header
+        })
+        .collect();
+
+    // SAFETY: every header points to the address and a single iovec, which
+    // points to a datagram of the given length. All of them outlive the call.
+    let sent = unsafe {
+        libc::sendmmsg(
+            socket.as_raw_fd(),
+            headers.as_mut_ptr(),
+            headers.len() as libc::c_uint,
+            libc::MSG_DONTWAIT,
+        )
+    };
+
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(sent as usize)
+}
+
+fn socket_address(address: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    // SAFETY: sockaddr_storage is a plain C struct for which all zeroes is valid
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+
+    let len = match address {
+        SocketAddr::V4(address) => {
+            let sockaddr = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: address.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(address.ip().octets()),
+                },
+                sin_zero: [0; 8],
+            };
+            // SAFETY: sockaddr_storage is large enough and suitably aligned
+            // for any socket address
+            unsafe {
+                std::ptr::write(
+                    (&mut storage as *mut libc::sockaddr_storage).cast(),
+                    sockaddr,
+                )
+            };
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(address) => {
+            let sockaddr = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: address.port().to_be(),
+                sin6_flowinfo: address.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: address.ip().octets(),
+                },
+                sin6_scope_id: address.scope_id(),
+            };
+            // SAFETY: sockaddr_storage is large enough and suitably aligned
+            // for any socket address
+            unsafe {
+                std::ptr::write(
+                    (&mut storage as *mut libc::sockaddr_storage).cast(),
+                    sockaddr,
+                )
+            };
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    (storage, len as libc::socklen_t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_and_receive_batch() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut buffers = Box::new([[0; RECV_BUFFER_SIZE]; BATCH_SIZE]);
+        let error = recv_batch(&receiver, &mut buffers).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+
+        let datagrams: [&[u8]; 3] = [b"one", b"two", b"three"];
+        let sent = send_batch(&sender, &datagrams, receiver.local_addr().unwrap()).unwrap();
+        assert_eq!(sent, 3);
+
+        let lengths: Vec<_> = recv_batch(&receiver, &mut buffers)
+            .unwrap()
+            .into_iter()
+            .map(|datagram| datagram.unwrap().length)
+            .collect();
+        assert_eq!(lengths, [3, 3, 5]);
+        assert_eq!(&buffers[2][..5], b"three");
+    }
+
+    #[test]
+    fn truncated_datagrams_have_no_length() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let too_long = [0; RECV_BUFFER_SIZE + 1];
+        let datagrams: [&[u8]; 3] = [b"one", &too_long, b"three"];
+        let sent = send_batch(&sender, &datagrams, receiver.local_addr().unwrap()).unwrap();
+        assert_eq!(sent, 3);
+
+        let mut buffers = Box::new([[0; RECV_BUFFER_SIZE]; BATCH_SIZE]);
+        let lengths: Vec<_> = recv_batch(&receiver, &mut buffers)
+            .unwrap()
+            .into_iter()
+            .map(|datagram| datagram.map(|datagram| datagram.length))
+            .collect();
+        assert_eq!(lengths, [Some(3), None, Some(5)]);
+    }
+
+    #[test]
+    fn receive_destination() {
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut buffers = Box::new([[0; RECV_BUFFER_SIZE]; BATCH_SIZE]);
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender
+            .send_to(b"one", receiver.local_addr().unwrap())
+            .unwrap();
+        let datagrams = recv_batch(&receiver, &mut buffers).unwrap();
+        assert_eq!(datagrams[0].unwrap().destination, None);
+
+        crate::socket_options::set_packet_info(&receiver).unwrap();
+        sender
+            .send_to(b"two", receiver.local_addr().unwrap())
+            .unwrap();
+        let datagrams = recv_batch(&receiver, &mut buffers).unwrap();
+        assert_eq!(
+            datagrams[0].unwrap().destination,
+            Some([127, 0, 0, 1].into())
+        );
+    }
+
+    #[test]
+    fn receive_source() {
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut buffers = Box::new([[0; RECV_BUFFER_SIZE]; BATCH_SIZE]);
+
+        let datagrams: [&[u8]; 2] = [b"one", b"two"];
+        send_batch(&sender, &datagrams, receiver.local_addr().unwrap()).unwrap();
+        let sources: Vec<_> = recv_batch(&receiver, &mut buffers)
+            .unwrap()
+            .into_iter()
+            .map(|datagram| datagram.unwrap().source)
+            .collect();
+        assert_eq!(sources, [Some(sender.local_addr().unwrap()); 2]);
+    }
+}
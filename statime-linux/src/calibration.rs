@@ -0,0 +1,108 @@
+//! Keeping the calibration of each port in a directory, so it survives
+//! restarts.
+//!
+//! Each port has a file named after its port number, holding its
+//! [`Calibration`] in the binary format of [`Calibration::to_bytes`].
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use statime::{Calibration, CalibrationStore, CalibrationStoreError};
+
+use crate::drift::replace_file;
+
+/// A [`CalibrationStore`] with a file per port in a directory
+#[derive(Debug, Clone)]
+pub struct CalibrationFiles {
+    directory: PathBuf,
+}
+
+impl CalibrationFiles {
+    /// Keep the calibrations in `directory`, which must exist
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    fn path(&self, port_number: u16) -> PathBuf {
+        self.directory.join(format!("port-{port_number}"))
+    }
+}
+
+impl CalibrationStore for CalibrationFiles {
+    fn load(&self, port_number: u16) -> Option<Calibration> {
+        let path = self.path(port_number);
+        match read_calibration_file(&path) {
+            Ok(calibration) => calibration,
+            Err(error) => {
+                log::warn!(
+                    "Could not read calibration file {}: {error}",
+                    path.display()
+                );
+                None
+            }
+        }
+    }
+
+    fn store(
+        &self,
+        port_number: u16,
+        calibration: &Calibration,
+    ) -> Result<(), CalibrationStoreError> {
+        let path = self.path(port_number);
+        replace_file(&path, &calibration.to_bytes()).map_err(|error| {
+            log::error!(
+                "Could not write calibration file {}: {error}",
+                path.display()
+            );
+            CalibrationStoreError::WriteFailed
+        })
+    }
+}
+
+fn read_calibration_file(path: &Path) -> io::Result<Option<Calibration>> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    let bytes = contents
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "not a calibration of a port"))?;
+    Ok(Some(Calibration::from_bytes(&bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use statime::Duration;
+
+    use super::*;
+
+    #[test]
+    fn calibration_files_round_trip() {
+        let directory =
+            std::env::temp_dir().join(format!("statime-calibration-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let files = CalibrationFiles::new(&directory);
+
+        assert_eq!(files.load(1), None);
+
+        let calibration = Calibration {
+            delay_asymmetry: Duration::from_nanos(-150),
+            ingress_latency: Duration::from_fixed_nanos(210.5),
+            egress_latency: Duration::from_nanos(180),
+        };
+        files.store(1, &calibration).unwrap();
+        assert_eq!(files.load(1), Some(calibration));
+        assert_eq!(files.load(2), None);
+
+        std::fs::write(directory.join("port-1"), b"garbage").unwrap();
+        assert_eq!(files.load(1), None);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,6 +1,12 @@
 //! Implementation of the abstract clock for the linux platform
 
-use std::path::Path;
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use clock_steering::unix::UnixClock;
 use statime::{Clock, Duration, Time, TimePropertiesDS};
@@ -8,17 +14,91 @@ use statime::{Clock, Duration, Time, TimePropertiesDS};
 #[derive(Debug, Clone)]
 pub struct LinuxClock {
     clock: clock_steering::unix::UnixClock,
+    frequency_multiplier: FrequencyMultiplier,
+}
+
+// The frequency correction applied through a clock and its clones: the
+// product of all multipliers since it was last set. Only this process adjusts
+// the clock, so that is the correction of the clock itself. There is only one
+// realtime clock, so every handle to it shares the same correction.
+#[derive(Debug, Clone)]
+enum FrequencyMultiplier {
+    Realtime,
+    Shared(Arc<AtomicU64>),
+}
+
+static REALTIME_FREQUENCY_MULTIPLIER: AtomicU64 = AtomicU64::new(1.0f64.to_bits());
+
+impl FrequencyMultiplier {
+    fn new() -> Self {
+        Self::Shared(Arc::new(AtomicU64::new(1.0f64.to_bits())))
+    }
+
+    fn bits(&self) -> &AtomicU64 {
+        match self {
+            Self::Realtime => &REALTIME_FREQUENCY_MULTIPLIER,
+            Self::Shared(bits) => bits,
+        }
+    }
+
+    fn get(&self) -> f64 {
+        f64::from_bits(self.bits().load(Ordering::Relaxed))
+    }
+
+    fn set(&self, frequency_multiplier: f64) {
+        self.bits()
+            .store(frequency_multiplier.to_bits(), Ordering::Relaxed);
+    }
+
+    // Set from a frequency offset in parts per million, as the kernel takes it
+    fn set_ppm(&self, frequency: f64) {
+        self.set(1.0 + frequency * 1e-6);
+    }
+
+    fn apply(&self, frequency_multiplier: f64) {
+        self.set(self.get() * frequency_multiplier);
+    }
 }
 
 impl LinuxClock {
-    pub const CLOCK_REALTIME: Self = Self {
-        clock: UnixClock::CLOCK_REALTIME,
-    };
+    #[deprecated(note = "use `LinuxClock::realtime` instead")]
+    pub const CLOCK_REALTIME: Self = Self::realtime();
+
+    pub const fn realtime() -> Self {
+        Self {
+            clock: UnixClock::CLOCK_REALTIME,
+            frequency_multiplier: FrequencyMultiplier::Realtime,
+        }
+    }
 
     pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
         let clock = UnixClock::open(path)?;
 
-        Ok(Self { clock })
+        Ok(Self::new(clock))
+    }
+
+    fn new(clock: UnixClock) -> Self {
+        Self {
+            clock,
+            frequency_multiplier: FrequencyMultiplier::new(),
+        }
+    }
+
+    /// Set the frequency correction of the clock relative to its unadjusted
+    /// oscillator, replacing any correction applied before. This is used to
+    /// restore the correction learned before a restart.
+    pub fn set_frequency_multiplier(
+        &self,
+        frequency_multiplier: f64,
+    ) -> Result<(), clock_steering::unix::Error> {
+        use clock_steering::Clock;
+
+        // The kernel takes the frequency offset in parts per million
+        self.clock
+            .set_frequency((frequency_multiplier - 1.0) * 1e6)?;
+        self.frequency_multiplier.set(frequency_multiplier);
+
+        Ok(())
     }
 
     pub fn timespec(&self) -> std::io::Result<libc::timespec> {
@@ -44,7 +124,9 @@ impl clock_steering::Clock for LinuxClock {
     }
 
     fn set_frequency(&self, frequency: f64) -> Result<clock_steering::Timestamp, Self::Error> {
-        self.clock.set_frequency(frequency)
+        let timestamp = self.clock.set_frequency(frequency)?;
+        self.frequency_multiplier.set_ppm(frequency);
+        Ok(timestamp)
     }
 
     fn step_clock(
@@ -116,10 +198,83 @@ impl Clock for LinuxClock {
         self.clock.adjust_frequency(frequency_multiplier)?;
         self.clock.step_clock(offset)?;
 
+        self.frequency_multiplier.apply(frequency_multiplier);
+
         Ok(())
     }
+
+    /// The frequency correction applied since the clock was opened. Any
+    /// correction that was active before then is not included, unless it was
+    /// replaced with [`LinuxClock::set_frequency_multiplier`] or
+    /// [`clock_steering::Clock::set_frequency`].
+    fn frequency_multiplier(&self) -> Option<f64> {
+        Some(self.frequency_multiplier.get())
+    }
+
+    fn monotonic_now(&self) -> Option<Time> {
+        let mut timespec = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: timespec is valid for writes for the duration of the call
+        let result = unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut timespec) };
+        (result == 0).then(|| libc_timespec_into_instant(timespec))
+    }
+}
+
+/// How many seconds the local timezone is currently ahead of UTC, or 0 when
+/// that can't be determined
+pub fn local_utc_offset() -> i32 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs() as libc::time_t);
+    // SAFETY: tm is a plain C struct for which all zeroes is valid
+    let mut local: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the duration of the call
+    if unsafe { libc::localtime_r(&now, &mut local) }.is_null() {
+        return 0;
+    }
+
+    local.tm_gmtoff as i32
 }
 
 pub fn libc_timespec_into_instant(spec: libc::timespec) -> Time {
     Time::from_fixed_nanos(spec.tv_sec as i128 * 1_000_000_000i128 + spec.tv_nsec as i128)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequency_multiplier() {
+        let multiplier = FrequencyMultiplier::new();
+        let clone = multiplier.clone();
+        assert_eq!(multiplier.get(), 1.0);
+
+        // Adjustments multiply, through every clone
+        multiplier.apply(1.00002);
+        clone.apply(0.99999);
+        assert!((multiplier.get() - 1.00002 * 0.99999).abs() < 1e-15);
+
+        // Setting the frequency replaces them
+        clone.set_ppm(-5.0);
+        assert!((multiplier.get() - 0.999995).abs() < 1e-15);
+        multiplier.apply(1.00001);
+        assert!((clone.get() - 0.999995 * 1.00001).abs() < 1e-15);
+        multiplier.set(1.0);
+        assert_eq!(clone.get(), 1.0);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn realtime_shares_frequency_multiplier() {
+        let clock = LinuxClock::realtime();
+        clock.frequency_multiplier.set(1.00001);
+        assert_eq!(
+            LinuxClock::CLOCK_REALTIME.frequency_multiplier.get(),
+            1.00001
+        );
+        clock.frequency_multiplier.set(1.0);
+    }
+}
@@ -0,0 +1,76 @@
+//! Persisting the frequency correction of the clock across restarts.
+//!
+//! Like the drift file of chrony, the file holds the frequency offset of the
+//! clock in parts per million. Restoring it at startup means the filter
+//! starts from the right frequency, instead of learning the error of the
+//! oscillator from scratch.
+
+use std::{io, path::Path};
+
+/// Largest frequency offset that is restored, in parts per million. Linux
+/// doesn't steer clocks any further than this.
+const MAX_PPM: f64 = 500.0;
+
+/// Read the frequency multiplier stored in `path`, or `None` if the file
+/// doesn't exist yet.
+pub fn read_drift_file(path: &Path) -> io::Result<Option<f64>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    match contents.split_whitespace().next().map(str::parse::<f64>) {
+        Some(Ok(ppm)) if ppm.abs() <= MAX_PPM => Ok(Some(1.0 + ppm * 1e-6)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "expected a frequency offset in ppm, got {:?}",
+                contents.trim()
+            ),
+        )),
+    }
+}
+
+/// Store the frequency multiplier in `path`.
+///
+/// The file is replaced atomically, so it can't be left half written when the
+/// daemon is stopped at the wrong moment.
+pub fn write_drift_file(path: &Path, frequency_multiplier: f64) -> io::Result<()> {
+    let ppm = (frequency_multiplier - 1.0) * 1e6;
+    replace_file(path, format!("{ppm:.6}\n").as_bytes())
+}
+
+// Write the contents to a temporary file next to `path` and move it in place
+pub(crate) fn replace_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    std::fs::write(&temporary, contents)?;
+    std::fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drift_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("statime-drift-{}", std::process::id()));
+
+        assert!(read_drift_file(&path).unwrap().is_none());
+
+        write_drift_file(&path, 1.0 + 12.5e-6).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "12.500000\n");
+        let multiplier = read_drift_file(&path).unwrap().unwrap();
+        assert!((multiplier - (1.0 + 12.5e-6)).abs() < 1e-12);
+
+        for invalid in ["", "fast", "1000.0"] {
+            std::fs::write(&path, invalid).unwrap();
+            let error = read_drift_file(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,22 @@
 extern crate core;
 
+pub mod batch;
+pub mod calibration;
 pub mod clock;
+pub mod drift;
+pub mod logging;
+pub mod management;
 pub mod network;
+pub mod phc;
+pub mod quirks;
+pub mod scan;
+pub mod scheduling;
+pub mod self_test;
+mod shared_memory;
+pub mod socket_options;
+#[cfg(feature = "snapshot")]
+pub mod state_file;
+pub mod statistics_segment;
+#[cfg(feature = "time-transfer")]
+pub mod time_transfer;
+pub mod tx_timestamp;
@@ -0,0 +1,210 @@
+//! Log output in either a human readable or a structured format.
+//!
+//! The structured format writes one JSON object per line, so it can be
+//! appended to a file that is rotated by copying and truncating it, and
+//! parsed by log pipelines without matching on the message text. Every line
+//! has these fields:
+//!
+//! - `time`: seconds since the unix epoch, with nanosecond precision
+//! - `level`: one of `ERROR`, `WARN`, `INFO`, `DEBUG` and `TRACE`
+//! - `target`: the module the message was logged from, or `statime::port<N>`
+//!   for the messages of statime about the port with port number `N`
+//! - `message`: the human readable message
+//!
+//! Messages about the synchronization carry additional fields:
+//!
+//! - `port`: number of the port the message is about, counting from 1
+//! - `port_number` and `clock_identity`: the identity of the port a message of
+//!   statime itself is about. Port numbers in the identity count from 0.
+//! - `offset_ns` and `timestamp_source`: a measured offset from the master, and
+//!   the least precise source of the timestamps it is based on
+//! - `raw_receive_time_ns` and `receive_time_ns`: the receive timestamp of the
+//!   sync a measured offset is based on, before and after the correction for
+//!   the ingress latency, as strings with the exact fractional nanoseconds
+//! - `previous_state` and `state`: a change of the state of a port
+//! - `ptp_time`: the time a port takes from its new master, as an RFC3339
+//!   timestamp in UTC
+//! - `event`: the name of some other port event
+
+use std::{
+    fmt::Write,
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use fern::colors::{Color, ColoredLevelConfig};
+use log::kv::{Key, Value, VisitSource};
+
+/// How log messages are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("Invalid log format {0:?}, expected `text` or `json`")]
+pub struct LogFormatError(String);
+
+impl FromStr for LogFormat {
+    type Err = LogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(LogFormatError(s.to_string())),
+        }
+    }
+}
+
+/// Install the global logger, writing to `file` if given and to stdout
+/// otherwise. Files are opened for appending.
+pub fn setup_logger(
+    level: log::LevelFilter,
+    format: LogFormat,
+    file: Option<&Path>,
+) -> Result<(), fern::InitError> {
+    let dispatch = match format {
+        LogFormat::Text => {
+            // Escape codes only make sense on a terminal
+            let colored = file.is_none();
+            let colors = ColoredLevelConfig::new()
+                .error(Color::Red)
+                .warn(Color::Yellow)
+                .info(Color::BrightGreen)
+                .debug(Color::BrightBlue)
+                .trace(Color::BrightBlack);
+
+            fern::Dispatch::new().format(move |out, message, record| {
+                let delta = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+                let h = delta.as_secs() % (24 * 60 * 60) / (60 * 60);
+                let m = delta.as_secs() % (60 * 60) / 60;
+                let s = delta.as_secs() % 60;
+                let f = delta.as_secs_f64().fract() * 1e7;
+
+                let level = if colored {
+                    colors.color(record.level()).to_string()
+                } else {
+                    record.level().to_string()
+                };
+
+                out.finish(format_args!(
+                    "{}[{}][{}] {}",
+                    format_args!("[{h:02}:{m:02}:{s:02}.{f:07}]"),
+                    record.target(),
+                    level,
+                    message
+                ))
+            })
+        }
+        LogFormat::Json => fern::Dispatch::new().format(|out, message, record| {
+            out.finish(format_args!(
+                "{}",
+                json_line(SystemTime::now(), record, &message.to_string())
+            ))
+        }),
+    };
+
+    let dispatch = dispatch.level(level);
+    match file {
+        Some(path) => dispatch.chain(fern::log_file(path)?),
+        None => dispatch.chain(std::io::stdout()),
+    }
+    .apply()?;
+
+    Ok(())
+}
+
+fn json_line(time: SystemTime, record: &log::Record, message: &str) -> String {
+    let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    let mut line = format!(
+        "{{\"time\":{}.{:09},\"level\":{},\"target\":{},\"message\":{}",
+        time.as_secs(),
+        time.subsec_nanos(),
+        json_string(record.level().as_str()),
+        json_string(record.target()),
+        json_string(message),
+    );
+
+    // Writing to a string can't fail
+    let _ = record.key_values().visit(&mut JsonFields(&mut line));
+
+    line.push('}');
+    line
+}
+
+// Appends the key-value pairs of a record as JSON fields
+struct JsonFields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = if let Some(value) = value.to_u64() {
+            value.to_string()
+        } else if let Some(value) = value.to_i64() {
+            value.to_string()
+        } else if let Some(value) = value.to_f64() {
+            if value.is_finite() {
+                value.to_string()
+            } else {
+                "null".to_string()
+            }
+        } else if let Some(value) = value.to_bool() {
+            value.to_string()
+        } else {
+            json_string(&value.to_string())
+        };
+
+        let _ = write!(self.0, ",{}:{}", json_string(key.as_str()), value);
+        Ok(())
+    }
+}
+
+fn json_string(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn parse_log_format() {
+        assert_eq!("text".parse(), Ok(LogFormat::Text));
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert_eq!(
+            "yaml".parse::<LogFormat>(),
+            Err(LogFormatError("yaml".into()))
+        );
+    }
+
+    #[test]
+    fn json_line_fields() {
+        let time = UNIX_EPOCH + Duration::new(1_700_000_000, 1_500);
+        let fields: &[(&str, Value)] = &[
+            ("port", Value::from(1u16)),
+            ("offset_ns", Value::from(-12.5f64)),
+            ("state", Value::from("Slave")),
+        ];
+        let record = log::Record::builder()
+            .level(log::Level::Info)
+            .target("statime_linux")
+            .key_values(&fields)
+            .build();
+
+        assert_eq!(
+            json_line(time, &record, "Port 1 \"offset\""),
+            "{\"time\":1700000000.000001500,\"level\":\"INFO\",\"target\":\"statime_linux\",\"\
+             message\":\"Port 1 \
+             \\\"offset\\\"\",\"port\":1,\"offset_ns\":-12.5,\"state\":\"Slave\"}"
+        );
+    }
+}
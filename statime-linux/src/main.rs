@@ -1,23 +1,49 @@
 use std::{
     future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
     pin::{pin, Pin},
-    sync::{Arc, OnceLock},
+    sync::{Arc, Mutex, OnceLock},
 };
 
-use clap::Parser;
-use fern::colors::Color;
+use clap::{Parser, Subcommand};
 use rand::{rngs::StdRng, SeedableRng};
+#[cfg(feature = "time-transfer")]
+use statime::time_transfer::TransferredTime;
 use statime::{
-    BasicFilter, Clock, ClockIdentity, DelayMechanism, Duration, InBmca, InstanceConfig, Interval,
-    Port, PortAction, PortActionIterator, PortConfig, PtpInstance, SdoId, Time, TimePropertiesDS,
-    TimeSource, TimestampContext,
+    BasicFilter, Calibration, Clock, ClockIdentity, CommunicationMode, CrossCheckDomain,
+    CrossCheckEvent, DelayMechanism, DomainCrossCheck, Duration, FailedSend,
+    IdentityCollisionResponse, InBmca, InstanceConfig, InstanceConfigError, Interval,
+    LogMessageIntervals, ManagementPolicy, PathDirection, Port, PortAction, PortActionIterator,
+    PortConfig, PortConfigError, PortEvent, PortStateKind, PriorityBounds, Profile, PtpInstance,
+    QuirkRule, Role, RolePreset, Running, SdoId, SendError, SimulatedPath, StartupBurst, Time,
+    TimePropertiesDS, TimeSource, TimestampContext, TimestampSource, TimestampingQuality,
+    TransmitEnable,
 };
+#[cfg(feature = "snapshot")]
+use statime_linux::state_file::{read_state_file, write_state_file};
+#[cfg(feature = "time-transfer")]
+use statime_linux::time_transfer::{MonotonicRawClock, SharedTimeFile};
 use statime_linux::{
+    calibration::CalibrationFiles,
     clock::LinuxClock,
-    network::{get_clock_id, LinuxNetworkPort, LinuxRuntime},
+    drift::{read_drift_file, write_drift_file},
+    logging::{setup_logger, LogFormat},
+    management::{Management, PortStatus},
+    network::{
+        get_clock_id, receive_packet, send_error, LinuxNetworkPort, LinuxRuntime, NetworkPacket,
+    },
+    phc::{clock_phc_index, interface_phc_index, phc_path},
+    quirks::parse_quirk_rule,
+    scan::{scan, ScanReport},
+    scheduling::{CpuList, ThreadScheduling},
+    self_test::self_test,
+    statistics_segment::{PortRecord, StatisticsSegment},
+    tx_timestamp::TxTimestampPolicy,
 };
 use timestamped_socket::{interface::InterfaceDescriptor, raw_udp_socket::TimestampingMode};
 use tokio::{
+    signal::unix::{signal, SignalKind},
     sync::{
         mpsc::{Receiver, Sender},
         Notify,
@@ -62,13 +88,109 @@ impl clap::builder::TypedValueParser for SdoIdParser {
     }
 }
 
+fn parse_delay_asymmetry(s: &str) -> Result<Duration, String> {
+    let (direction, excess) = s
+        .split_once(':')
+        .ok_or_else(|| format!("Expected <direction>:<nanoseconds>, got {s:?}"))?;
+    let slower = match direction {
+        "master-to-slave" => PathDirection::MasterToSlave,
+        "slave-to-master" => PathDirection::SlaveToMaster,
+        _ => {
+            return Err(format!(
+                "Invalid direction {direction:?}, expected master-to-slave or slave-to-master"
+            ))
+        }
+    };
+    let excess = excess
+        .parse()
+        .map_err(|_| format!("Invalid number of nanoseconds {excess:?}"))?;
+
+    Ok(PortConfig::delay_asymmetry_from(
+        slower,
+        Duration::from_nanos(excess),
+    ))
+}
+
+// The management endpoint speaks plain HTTP, so it must not be reachable
+// from other hosts
+fn parse_loopback_address(s: &str) -> Result<SocketAddr, String> {
+    let address: SocketAddr = s.parse().map_err(|error| format!("{error}"))?;
+    if !address.ip().is_loopback() {
+        return Err(format!(
+            "{} is not a loopback address, and management requests are not encrypted",
+            address.ip()
+        ));
+    }
+    Ok(address)
+}
+
+fn parse_management_policy(s: &str) -> Result<ManagementPolicy, String> {
+    match s {
+        "read-only" => Ok(ManagementPolicy::ReadOnly),
+        "local-only" => Ok(ManagementPolicy::LocalOnly),
+        "full" => Ok(ManagementPolicy::Full),
+        _ => Err(format!(
+            "Invalid policy {s:?}, expected read-only, local-only or full"
+        )),
+    }
+}
+
+fn parse_role(s: &str) -> Result<Role, String> {
+    match s {
+        "grandmaster" => Ok(Role::Grandmaster),
+        "boundary" => Ok(Role::Boundary),
+        "ordinary-slave" => Ok(Role::OrdinarySlave),
+        "media-follower" => Ok(Role::MediaFollower),
+        _ => Err(format!(
+            "Invalid role {s:?}, expected grandmaster, boundary, ordinary-slave or media-follower"
+        )),
+    }
+}
+
+fn parse_profile(s: &str) -> Result<Profile, String> {
+    match s {
+        "default" => Ok(Profile::Default),
+        "smpte-2059" => Ok(Profile::Smpte2059),
+        "g8275.1" => Ok(Profile::G8275_1),
+        _ => Err(format!(
+            "Invalid profile {s:?}, expected default, smpte-2059 or g8275.1"
+        )),
+    }
+}
+
+/// The defaults of the instance when no --role is given
+const NO_ROLE: RolePreset = RolePreset {
+    priority_1: 255,
+    priority_2: 255,
+    domain_number: 0,
+    slave_only: false,
+    announce_interval: Interval::TWO_SECONDS,
+    announce_receipt_timeout: 3,
+    sync_interval: Interval::ONE_SECOND,
+    min_delay_req_interval: Interval::TWO_SECONDS,
+    startup_burst: None,
+    master_only: false,
+};
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Set desired logging level
     #[clap(short, long, default_value_t = log::LevelFilter::Info)]
     loglevel: log::LevelFilter,
 
+    /// Format of the log messages, either `text` or `json`. The latter writes
+    /// one JSON object per line, with stable field names.
+    #[clap(long, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Append log messages to this file instead of writing them to stdout
+    #[clap(long)]
+    log_file: Option<PathBuf>,
+
     /// Set interface on which to listen to PTP messages
     #[clap(short, long)]
     interface: InterfaceDescriptor,
@@ -77,70 +199,351 @@ struct Args {
     #[clap(long, default_value_t = SdoId::default(), value_parser = SdoIdParser)]
     sdo: SdoId,
 
-    /// The domain number of the desired ptp domain
-    #[clap(long, default_value_t = 0)]
-    domain: u8,
+    /// What this instance is for, either `grandmaster`, `boundary`,
+    /// `ordinary-slave` or `media-follower`. Picks consistent defaults for
+    /// the priorities, the domain, the message intervals and the startup
+    /// burst within --profile. Options given explicitly still override them.
+    #[clap(long, value_parser = parse_role)]
+    role: Option<Role>,
+
+    /// The profile the defaults of --role are taken from, either `default`,
+    /// `smpte-2059` or `g8275.1`. The message intervals must lie within the
+    /// ranges of the profile, also without --role, which uses `default`. The
+    /// profile is advertised in announce messages, and a master advertising
+    /// another one is reported. With `g8275.1` the master is selected with the
+    /// alternate BMCA of the telecom profile.
+    #[clap(long, default_value = "default", value_parser = parse_profile, requires = "role")]
+    profile: Profile,
+
+    /// The domain number of the desired ptp domain. Defaults to 0 without
+    /// --role.
+    #[clap(long)]
+    domain: Option<u8>,
+
+    /// Also follow this domain, without ever steering the clock from it, and
+    /// warn when its time diverges from that of --domain. The ports of this
+    /// domain are numbered after those of --domain in the log.
+    #[clap(long)]
+    cross_check_domain: Option<u8>,
+
+    /// Nanoseconds the time of --cross-check-domain may differ from that of
+    /// --domain before the alarm is raised
+    #[clap(long, default_value_t = 1000)]
+    cross_check_bound: i64,
+
+    /// Measurements in a row beyond --cross-check-bound before the alarm is
+    /// raised, and back within it before the alarm is cleared
+    #[clap(long, default_value_t = 3)]
+    cross_check_persistence: u8,
+
+    /// Local clock priority (part 1) used in master clock selection. Defaults
+    /// to 255 without --role.
+    #[clap(long)]
+    priority_1: Option<u8>,
 
-    /// Local clock priority (part 1) used in master clock selection
-    /// Default init value is 128, see: A.9.4.2
-    #[clap(long, default_value_t = 255)]
-    priority_1: u8,
+    /// Local clock priority (part 2) used in master clock selection. Defaults
+    /// to 255 without --role.
+    #[clap(long)]
+    priority_2: Option<u8>,
 
-    /// Local clock priority (part 2) used in master clock selection
-    /// Default init value is 128, see: A.9.4.2
-    #[clap(long, default_value_t = 255)]
-    priority_2: u8,
+    /// The localPriority of the ports, 1 to 255 with the lowest the most
+    /// preferred. Only used by the alternate BMCA of --profile g8275.1.
+    #[clap(long, default_value_t = 128)]
+    local_priority: u8,
 
     /// Log value of interval expected between announce messages, see: 7.7.2.2
-    /// Default init value is 1, see: A.9.4.2
-    #[clap(long, default_value_t = 1)]
-    log_announce_interval: i8,
+    /// Defaults to 1 without --role.
+    #[clap(long)]
+    log_announce_interval: Option<i8>,
 
     /// Time interval between Sync messages, see: 7.7.2.3
-    /// Default init value is 0, see: A.9.4.2
-    #[clap(long, default_value_t = 0)]
-    log_sync_interval: i8,
-
-    /// Default init value is 3, see: A.9.4.2
-    #[clap(long, default_value_t = 3)]
-    announce_receipt_timeout: u8,
-
-    /// Use hardware clock
+    /// Defaults to 0 without --role.
+    #[clap(long)]
+    log_sync_interval: Option<i8>,
+
+    /// Defaults to 3, see: A.9.4.2
+    #[clap(long)]
+    announce_receipt_timeout: Option<u8>,
+
+    /// Use hardware clock, the path of a PTP hardware clock device such as
+    /// `/dev/ptp0`, or `auto` to use the clock of the network card of
+    /// --interface. The clock must be the one that timestamps the packets of
+    /// the interface.
     #[clap(long, short = 'c')]
     hardware_clock: Option<String>,
+
+    /// Quality of the timestamps, either `hardware` or `software`. Selects
+    /// suitable filter settings. Defaults to `hardware` when a hardware clock
+    /// is used and to `software` otherwise.
+    #[clap(long)]
+    timestamping_quality: Option<TimestampingQuality>,
+
+    /// Run the protocol and log measurements, but never adjust the clock
+    #[clap(long)]
+    free_run: bool,
+
+    /// Run the threads handling sockets and timers with this SCHED_FIFO
+    /// priority (1-99), to reduce timestamp processing latency
+    #[clap(long, value_parser = clap::value_parser!(i32).range(1..=99))]
+    rt_priority: Option<i32>,
+
+    /// Pin the threads handling sockets and timers to these CPUs, for example
+    /// `2,3` or `2-3`
+    #[clap(long)]
+    cpus: Option<CpuList>,
+
+    /// Busy poll the time critical socket for up to this many microseconds
+    /// when receiving, trading CPU time for lower receive latency
+    #[clap(long)]
+    busy_poll: Option<u32>,
+
+    /// Look this many more times, waiting longer each time, for send
+    /// timestamps the network driver delivers late. 0 uses the time a message
+    /// was sent instead right away
+    #[clap(long, default_value_t = 5)]
+    tx_timestamp_retries: u32,
+
+    /// Periodically log MTIE and TDEV over these observation intervals, given
+    /// as a number of sync intervals, for example `1,8,64`
+    #[clap(long, value_delimiter = ',')]
+    time_error_intervals: Vec<usize>,
+
+    /// Warn when the mean frequency correction over a minute moves by at least
+    /// this many parts per billion from that of the minute before, as it does
+    /// when the temperature of the oscillator changes
+    #[clap(long)]
+    frequency_change_threshold: Option<f64>,
+
+    /// Keep offsets from the master of at least this many nanoseconds in the
+    /// timeline of each port, among the events around them, to explain them
+    /// afterwards through the management endpoint
+    #[clap(long)]
+    offset_spike_threshold: Option<i64>,
+
+    /// Keep the minimum, maximum, mean and percentiles of the offsets from the
+    /// master over windows of this many seconds, logging each window as it
+    /// ends and serving the recent ones through the management endpoint
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    statistics_window: Option<u64>,
+
+    /// Work around masters that don't follow the standard, given as
+    /// `<selector>=<quirks>`. The selector is `any`, `oui:<hex>` to match the
+    /// vendor of the grandmaster, or `sdo:<id>` to match a profile. Quirks are
+    /// `delay-resp-sequence-id`, `follow-up-before-sync` (enabled when no rule
+    /// matches) or `none`. Can be repeated, the first matching rule is used.
+    #[clap(long = "quirk", value_parser = parse_quirk_rule)]
+    quirks: Vec<QuirkRule>,
+
+    /// Measure the path delay more often during this many seconds after
+    /// selecting a master, to converge faster. The media-follower role does
+    /// this for 30 seconds unless given.
+    #[clap(long)]
+    startup_burst: Option<u32>,
+
+    /// Log value of the delay request interval during the startup burst. The
+    /// master can still ask for a longer interval.
+    #[clap(long, default_value_t = -4)]
+    startup_log_delay_req_interval: i8,
+
+    /// Correct for a path that is slower in one direction, given as
+    /// `<direction>:<nanoseconds>`. The direction is `master-to-slave` or
+    /// `slave-to-master`, the nanoseconds how much longer messages in that
+    /// direction take than in the other one.
+    #[clap(long, value_parser = parse_delay_asymmetry)]
+    delay_asymmetry: Option<Duration>,
+
+    /// Nanoseconds the receive timestamps are taken after the packet passed
+    /// the network interface, subtracted from every receive timestamp. The
+    /// JSON log format reports each sync receive timestamp before and after
+    /// this correction.
+    #[clap(long)]
+    ingress_latency: Option<i64>,
+
+    /// Nanoseconds the send timestamps are taken before the packet passed the
+    /// network interface, added to every send timestamp.
+    #[clap(long)]
+    egress_latency: Option<i64>,
+
+    /// Keep the calibration of each port in this directory, to restore it
+    /// when starting. Values given with --delay-asymmetry, --ingress-latency
+    /// or --egress-latency replace the stored ones, and are stored instead.
+    #[clap(long)]
+    calibration_dir: Option<PathBuf>,
+
+    /// Pretend the path to the master is this many nanoseconds longer, for
+    /// testing the filter and servo on loopback or virtual networks. Never
+    /// use this in production, the clock is steered by a delay that doesn't
+    /// exist.
+    #[clap(long)]
+    simulated_path_delay: Option<i64>,
+
+    /// Pretend the path to the master is asymmetric, given like
+    /// --delay-asymmetry. Only for testing, like --simulated-path-delay.
+    #[clap(long, value_parser = parse_delay_asymmetry)]
+    simulated_delay_asymmetry: Option<Duration>,
+
+    /// Stop taking part in the domain when another clock uses our clock
+    /// identity, until its announce messages time out. By default the
+    /// collision is only logged.
+    #[clap(long)]
+    fault_on_identity_collision: bool,
+
+    /// Which PTP management messages to act on, either `read-only`,
+    /// `local-only` or `full`. Every policy answers GET requests. With
+    /// `local-only`, SET and COMMAND messages like DISABLE_PORT are accepted
+    /// when they did not pass a boundary clock, with `full` from anywhere.
+    #[clap(long, default_value = "read-only", value_parser = parse_management_policy)]
+    ptp_management: ManagementPolicy,
+
+    /// Serve the HTTP management endpoint on this loopback address, for
+    /// example `127.0.0.1:9319`
+    #[clap(long, requires = "management_token_file", value_parser = parse_loopback_address)]
+    management_listen: Option<SocketAddr>,
+
+    /// File containing the bearer token required by the management endpoint
+    #[clap(long)]
+    management_token_file: Option<PathBuf>,
+
+    /// Keep the frequency correction of the clock in this file, to restore it
+    /// when starting. The file holds the frequency offset in ppm, and is
+    /// updated once the clock first locks to its master, every hour after
+    /// that, and when stopping on SIGTERM or SIGINT.
+    #[clap(long)]
+    drift_file: Option<PathBuf>,
+
+    /// Keep the state of the instance in this file, to restore it when
+    /// starting. This carries over the current grandmaster, time properties,
+    /// scheduled leap second and priority 1, so upgrading the daemon doesn't
+    /// disrupt the network.
+    #[cfg(feature = "snapshot")]
+    #[clap(long)]
+    state_file: Option<PathBuf>,
+
+    /// Publish the time of the instance in this file, for containers and
+    /// virtual machine guests that map it, for example on `/dev/shm`. The
+    /// time is given relative to the raw monotonic clock, and published every
+    /// 100ms.
+    #[cfg(feature = "time-transfer")]
+    #[clap(long)]
+    time_transfer_file: Option<PathBuf>,
+
+    /// Keep the last offset and the counters of each port in this file, for
+    /// exporters that map it, for example on `/dev/shm`, to read them without
+    /// system calls. See the statistics_segment module for the layout.
+    #[clap(long)]
+    statistics_segment: Option<PathBuf>,
 }
 
-fn setup_logger(level: log::LevelFilter) -> Result<(), fern::InitError> {
-    let colors = fern::colors::ColoredLevelConfig::new()
-        .error(Color::Red)
-        .warn(Color::Yellow)
-        .info(Color::BrightGreen)
-        .debug(Color::BrightBlue)
-        .trace(Color::BrightBlack);
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Listen to the PTP traffic on --interface without taking part in it,
+    /// and report the domains, masters, profiles and transports in use. Any
+    /// options other than --interface and the logging options are ignored.
+    Scan {
+        /// Seconds to listen for
+        #[clap(long, default_value_t = 10)]
+        duration: u64,
+    },
+    /// Check that --interface and its hardware clock work for PTP before
+    /// joining a live domain: that the clock can be read and adjusted, and
+    /// that send and receive timestamps are delivered and plausible. Sends a
+    /// single message in a domain no PTP instance uses. Exits with status 1
+    /// when a check fails.
+    SelfTest,
+}
 
-    fern::Dispatch::new()
-        .format(move |out, message, record| {
-            use std::time::{SystemTime, UNIX_EPOCH};
+/// A problem with the configuration given on the command line
+#[derive(thiserror::Error, Debug)]
+enum ConfigError {
+    #[error("--hardware-clock: could not open {path}: {error}")]
+    HardwareClock { path: String, error: std::io::Error },
+    #[error(
+        "--hardware-clock: hardware timestamping needs --interface to name a network interface, \
+         not an address"
+    )]
+    HardwareClockWithoutInterface,
+    #[error("--hardware-clock: could not find the hardware clock of {interface}: {error}")]
+    InterfaceClock {
+        interface: String,
+        error: std::io::Error,
+    },
+    #[error("--hardware-clock: {interface} has no hardware clock to take timestamps with")]
+    NoInterfaceClock { interface: String },
+    #[error(
+        "--hardware-clock: {path} is not the clock that timestamps the packets of {interface}, \
+         which is {}",
+        phc_path(*index).display()
+    )]
+    HardwareClockMismatch {
+        path: String,
+        interface: String,
+        index: u32,
+    },
+    #[error("instance: {0}")]
+    Instance(#[from] InstanceConfigError),
+    #[error("--cross-check-domain: the instance already follows domain {0}")]
+    CrossCheckSameDomain(u8),
+    #[error("port: {0}")]
+    Port(#[from] PortConfigError),
+    #[error("--drift-file: could not read {path}: {error}")]
+    DriftFile {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[cfg(feature = "time-transfer")]
+    #[error("--time-transfer-file: could not map {path}: {error}")]
+    TimeTransferFile {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("--statistics-segment: could not map {path}: {error}")]
+    StatisticsSegment {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+}
 
-            let delta = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+/// The path of the hardware clock to use for `interface`. Either the clock of
+/// the interface when `clock` is `auto`, or `clock` after checking that it
+/// is the one that timestamps the packets of the interface.
+fn interface_hardware_clock(clock: &str, interface: &str) -> Result<String, ConfigError> {
+    let index = match interface_phc_index(interface) {
+        Ok(Some(index)) => index,
+        Ok(None) => {
+            return Err(ConfigError::NoInterfaceClock {
+                interface: interface.to_owned(),
+            })
+        }
+        Err(error) if clock == "auto" => {
+            return Err(ConfigError::InterfaceClock {
+                interface: interface.to_owned(),
+                error,
+            })
+        }
+        Err(error) => {
+            log::warn!(
+                "Could not check that {} is the hardware clock of {}: {}",
+                clock,
+                interface,
+                error
+            );
+            return Ok(clock.to_owned());
+        }
+    };
 
-            let h = delta.as_secs() % (24 * 60 * 60) / (60 * 60);
-            let m = delta.as_secs() % (60 * 60) / 60;
-            let s = delta.as_secs() % 60;
-            let f = delta.as_secs_f64().fract() * 1e7;
+    if clock == "auto" {
+        return Ok(phc_path(index).display().to_string());
+    }
 
-            out.finish(format_args!(
-                "{}[{}][{}] {}",
-                format_args!("[{h:02}:{m:02}:{s:02}.{f:07}]"),
-                record.target(),
-                colors.color(record.level()),
-                message
-            ))
-        })
-        .level(level)
-        .chain(std::io::stdout())
-        .apply()?;
-    Ok(())
+    // A path that cannot be resolved is reported when opening the clock
+    match clock_phc_index(Path::new(clock)) {
+        Ok(Some(clock_index)) if clock_index != index => Err(ConfigError::HardwareClockMismatch {
+            path: clock.to_owned(),
+            interface: interface.to_owned(),
+            index,
+        }),
+        _ => Ok(clock.to_owned()),
+    }
 }
 
 pin_project_lite::pin_project! {
@@ -186,112 +589,571 @@ impl Future for Timer {
     }
 }
 
+/// How often the frequency correction is written to the drift file
+const DRIFT_FILE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// How often the time is published to the time transfer file
+#[cfg(feature = "time-transfer")]
+const TIME_TRANSFER_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Offsets kept for the time error metrics of a port, when its longest
+/// observation interval needs fewer
+const MIN_TIME_ERROR_SAMPLES: usize = 256;
+
+/// Offsets kept at most, so that a mistyped interval doesn't exhaust memory
+const MAX_TIME_ERROR_SAMPLES: usize = 1 << 20;
+
 // used to borrow the instance with a static lifetime
-static INSTANCE: OnceLock<PtpInstance<LinuxClock, BasicFilter>> = OnceLock::new();
+static INSTANCE: OnceLock<PtpInstance<LinuxClock, BasicFilter, Profile>> = OnceLock::new();
+// the instance following --cross-check-domain, if any
+static CROSS_CHECK_INSTANCE: OnceLock<PtpInstance<LinuxClock, BasicFilter>> = OnceLock::new();
+// the segment given with --statistics-segment, if any, for all port tasks to
+// publish in
+static STATISTICS_SEGMENT: OnceLock<StatisticsSegment> = OnceLock::new();
+
+fn main() {
+    let args = Args::parse();
 
-#[tokio::main]
-async fn main() {
-    actual_main().await;
+    setup_logger(args.loglevel, args.log_format, args.log_file.as_deref())
+        .expect("Could not setup logging");
+
+    let scheduling = ThreadScheduling {
+        fifo_priority: args.rt_priority,
+        cpus: args.cpus.clone(),
+    };
+
+    // The main thread drives the runtime as well, and doing this here first
+    // reports missing permissions before any worker thread is started
+    if let Err(error) = scheduling.apply_to_current_thread() {
+        eprintln!("Could not apply thread scheduling settings: {error}");
+        std::process::exit(1);
+    }
+    if !scheduling.is_default() {
+        log::info!("Using thread scheduling settings {:?}", scheduling);
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .on_thread_start(move || {
+            if let Err(error) = scheduling.apply_to_current_thread() {
+                log::error!("Could not apply thread scheduling settings: {}", error);
+            }
+        })
+        .build()
+        .expect("Could not start the runtime");
+
+    runtime.block_on(actual_main(args));
 }
 
-async fn actual_main() {
-    let args = Args::parse();
+async fn actual_main(args: Args) {
+    if let Some(Command::Scan { duration }) = args.command {
+        match scan(args.interface, std::time::Duration::from_secs(duration)).await {
+            Ok(scanner) => print!("{}", ScanReport(&scanner)),
+            Err(error) => {
+                eprintln!("Could not scan: {error}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(Command::SelfTest) = args.command {
+        let report = self_test(args.interface).await;
+        print!("{report}");
+        if !report.passed() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Check the complete configuration up front, and report every problem at
+    // once instead of failing halfway through starting up
+    let mut errors = Vec::new();
+
+    let hardware_clock = match (&args.hardware_clock, args.interface.interface_name) {
+        (Some(clock), Some(interface_name)) => {
+            match interface_hardware_clock(clock, &interface_name.to_string()) {
+                Ok(path) => Some(path),
+                Err(error) => {
+                    errors.push(error);
+                    None
+                }
+            }
+        }
+        // Reported with the timestamping mode below
+        (Some(clock), None) => Some(clock.clone()),
+        (None, _) => None,
+    };
+
+    let local_clock = match &hardware_clock {
+        Some(path) => match LinuxClock::open(path) {
+            Ok(clock) => clock,
+            Err(error) => {
+                errors.push(ConfigError::HardwareClock {
+                    path: path.clone(),
+                    error,
+                });
+                // Only used until we exit below
+                LinuxClock::realtime()
+            }
+        },
+        None => LinuxClock::realtime(),
+    };
 
-    setup_logger(args.loglevel).expect("Could not setup logging");
+    let frequency_multiplier = match &args.drift_file {
+        Some(path) => match read_drift_file(path) {
+            Ok(frequency_multiplier) => frequency_multiplier,
+            Err(error) => {
+                errors.push(ConfigError::DriftFile {
+                    path: path.clone(),
+                    error,
+                });
+                None
+            }
+        },
+        None => None,
+    };
 
-    let local_clock = if let Some(hardware_clock) = &args.hardware_clock {
-        LinuxClock::open(hardware_clock).expect("Could not open hardware clock")
-    } else {
-        LinuxClock::CLOCK_REALTIME
+    #[cfg(feature = "time-transfer")]
+    let time_transfer_file = match &args.time_transfer_file {
+        Some(path) => match SharedTimeFile::create(path) {
+            Ok(shared) => Some(shared),
+            Err(error) => {
+                errors.push(ConfigError::TimeTransferFile {
+                    path: path.clone(),
+                    error,
+                });
+                None
+            }
+        },
+        None => None,
     };
 
-    let timestamping_mode = if args.hardware_clock.is_some() {
-        match args.interface.interface_name {
-            Some(interface_name) => TimestampingMode::Hardware(interface_name),
-            None => panic!("an interface name is required when using hardware timestamping"),
+    if let Some(path) = &args.statistics_segment {
+        match StatisticsSegment::create(path) {
+            Ok(segment) => {
+                let _ = STATISTICS_SEGMENT.set(segment);
+            }
+            Err(error) => errors.push(ConfigError::StatisticsSegment {
+                path: path.clone(),
+                error,
+            }),
+        }
+    }
+
+    let timestamping_mode = match (&args.hardware_clock, args.interface.interface_name) {
+        (None, _) => TimestampingMode::Software,
+        (Some(_), Some(interface_name)) => TimestampingMode::Hardware(interface_name),
+        (Some(_), None) => {
+            errors.push(ConfigError::HardwareClockWithoutInterface);
+            TimestampingMode::Software
         }
-    } else {
-        TimestampingMode::Software
     };
 
-    let mut network_runtime = LinuxRuntime::new(timestamping_mode, local_clock.clone());
+    let timestamping_quality = match args.timestamping_quality {
+        Some(quality) => quality,
+        None if args.hardware_clock.is_some() => TimestampingQuality::Hardware,
+        None => TimestampingQuality::Software,
+    };
+
     let clock_identity = ClockIdentity(get_clock_id().expect("Could not get clock identity"));
 
+    let preset = match args.role {
+        Some(role) => role.preset(args.profile),
+        None => NO_ROLE,
+    };
+
     let config = InstanceConfig {
         clock_identity,
-        priority_1: args.priority_1,
-        priority_2: args.priority_2,
-        domain_number: args.domain,
-        slave_only: false,
+        priority_1: args.priority_1.unwrap_or(preset.priority_1),
+        priority_2: args.priority_2.unwrap_or(preset.priority_2),
+        domain_number: args.domain.unwrap_or(preset.domain_number),
+        slave_only: preset.slave_only,
         sdo_id: args.sdo,
     };
 
+    errors.extend(
+        config
+            .errors(&PriorityBounds::default())
+            .map(ConfigError::from),
+    );
+
+    // Slave only and in free run, so only the main instance steers the clock
+    let cross_check_config = args.cross_check_domain.map(|domain_number| InstanceConfig {
+        domain_number,
+        slave_only: true,
+        priority_1: 255,
+        priority_2: 255,
+        ..config
+    });
+    if args.cross_check_domain == Some(config.domain_number) {
+        errors.push(ConfigError::CrossCheckSameDomain(config.domain_number));
+    }
+
     let time_properties_ds =
         TimePropertiesDS::new_arbitrary_time(false, false, TimeSource::InternalOscillator);
     let port_config = PortConfig {
         delay_mechanism: DelayMechanism::E2E {
-            interval: Interval::TWO_SECONDS,
+            interval: preset.min_delay_req_interval,
         },
-        announce_interval: Interval::from_log_2(args.log_announce_interval),
-        announce_receipt_timeout: args.announce_receipt_timeout,
-        sync_interval: Interval::from_log_2(args.log_sync_interval),
-        master_only: false,
-        delay_asymmetry: Duration::ZERO,
+        announce_interval: args
+            .log_announce_interval
+            .map_or(preset.announce_interval, Interval::from_log_2),
+        announce_receipt_timeout: args
+            .announce_receipt_timeout
+            .unwrap_or(preset.announce_receipt_timeout),
+        sync_interval: args
+            .log_sync_interval
+            .map_or(preset.sync_interval, Interval::from_log_2),
+        master_only: preset.master_only,
+        delay_asymmetry: args.delay_asymmetry.unwrap_or(Duration::ZERO),
+        ingress_latency: Duration::from_nanos(args.ingress_latency.unwrap_or(0)),
+        egress_latency: Duration::from_nanos(args.egress_latency.unwrap_or(0)),
+        communication_mode: CommunicationMode::Multicast,
+        transmit: TransmitEnable::ALL,
+        unicast_client: None,
+        log_message_intervals: LogMessageIntervals::STANDARD,
+        management: args.ptp_management,
     };
 
-    let instance = PtpInstance::new(
+    // Without --role, the intervals still have to fit the default profile
+    errors.extend(
+        port_config
+            .errors(&config, &args.profile.interval_bounds())
+            .map(ConfigError::from),
+    );
+
+    if !errors.is_empty() {
+        eprintln!("Invalid configuration:");
+        for error in errors {
+            eprintln!("  {error}");
+        }
+        std::process::exit(1);
+    }
+
+    if let Some(path) = args.drift_file.clone() {
+        // Without a stored correction, start from the unadjusted oscillator so
+        // the correction that is stored later is complete
+        if let Some(frequency_multiplier) = frequency_multiplier {
+            log::info!(
+                "Restoring frequency correction of {:.3}ppm",
+                (frequency_multiplier - 1.0) * 1e6
+            );
+        }
+        if let Err(error) =
+            local_clock.set_frequency_multiplier(frequency_multiplier.unwrap_or(1.0))
+        {
+            eprintln!("Could not set the frequency correction: {error}");
+            std::process::exit(1);
+        }
+
+        let clock = local_clock.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(DRIFT_FILE_INTERVAL);
+            // The first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                store_drift(&path, &clock);
+            }
+        });
+    }
+
+    // Registered before anything runs, so stopping the daemon always takes
+    // the path below that stores the drift
+    let (mut terminate, mut interrupt) = match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) {
+        (Ok(terminate), Ok(interrupt)) => (terminate, interrupt),
+        (Err(error), _) | (_, Err(error)) => {
+            eprintln!("Could not handle signals: {error}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut network_runtime = LinuxRuntime::new(timestamping_mode, local_clock.clone());
+    network_runtime.set_busy_poll(args.busy_poll);
+    network_runtime.set_tx_timestamp_policy(TxTimestampPolicy {
+        attempts: args.tx_timestamp_retries,
+        ..Default::default()
+    });
+
+    // The profile also orders the masters, as IEEE1588 does without --role
+    let instance = PtpInstance::with_master_selection(
         config,
         time_properties_ds,
         local_clock.clone(),
-        BasicFilter::new(0.25),
+        BasicFilter::for_quality(timestamping_quality),
+        args.profile,
     );
 
+    #[cfg(feature = "snapshot")]
+    if let Some(path) = &args.state_file {
+        match read_state_file(path) {
+            Ok(Some(snapshot)) => {
+                if let Err(error) = instance.restore(&snapshot) {
+                    log::warn!("Not restoring state from {}: {error}", path.display());
+                }
+            }
+            Ok(None) => {}
+            Err(error) => {
+                log::warn!("Could not read state file {}: {error}", path.display());
+            }
+        }
+    }
+
+    instance
+        .set_free_run(args.free_run)
+        .expect("the ports that could keep the instance busy don't run yet");
+    if args.role.is_some() {
+        instance.set_profile_identifier(Some(args.profile.identifier()));
+    }
+    if let Some(directory) = &args.calibration_dir {
+        let store: &'static CalibrationFiles =
+            Box::leak(Box::new(CalibrationFiles::new(directory)));
+        instance.set_calibration_store(Some(store));
+    }
+
     // borrow instance with the static lifetime
     let instance = INSTANCE.get_or_init(|| instance);
 
+    let cross_check = cross_check_config.map(|cross_check_config| {
+        let cross_check_instance = PtpInstance::new(
+            cross_check_config,
+            time_properties_ds,
+            local_clock.clone(),
+            BasicFilter::for_quality(timestamping_quality),
+        );
+        cross_check_instance
+            .set_free_run(true)
+            .expect("the ports that could keep the instance busy don't run yet");
+        let cross_check_instance = CROSS_CHECK_INSTANCE.get_or_init(|| cross_check_instance);
+
+        log::info!(
+            "Checking domain {} against domain {}",
+            cross_check_config.domain_number,
+            config.domain_number
+        );
+        let check = DomainCrossCheck::new(
+            Duration::from_nanos(args.cross_check_bound),
+            args.cross_check_persistence,
+        );
+        (cross_check_instance, Arc::new(Mutex::new(check)))
+    });
+
+    #[cfg(feature = "time-transfer")]
+    if let Some(shared) = time_transfer_file {
+        // The frequency correction of the system clock is relative to the
+        // oscillator the raw monotonic clock runs off, but that of a hardware
+        // clock isn't, so its rate is measured between publications instead
+        let measure_rate = hardware_clock.is_some();
+        let clock = local_clock.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TIME_TRANSFER_INTERVAL);
+            let mut last: Option<TransferredTime> = None;
+            loop {
+                interval.tick().await;
+                let mut transferred = TransferredTime::measure(
+                    &clock,
+                    &MonotonicRawClock,
+                    instance.status().time_properties_ds,
+                );
+                if let (true, Some(last)) = (measure_rate, last) {
+                    let rate = (transferred.time - last.time).nanos_lossy()
+                        / (transferred.reference - last.reference).nanos_lossy();
+                    // A step of the clock is not a change of its rate
+                    if (rate - 1.0).abs() < 1e-3 {
+                        transferred.rate = rate;
+                    }
+                }
+                shared.publish(&transferred);
+                last = Some(transferred);
+            }
+        });
+    }
+
     let rng1 = StdRng::from_entropy();
     let port_in_bmca1 = instance.add_port(port_config, rng1);
 
     let rng2 = StdRng::from_entropy();
     let port_in_bmca2 = instance.add_port(port_config, rng2);
 
-    let ports = vec![port_in_bmca1, port_in_bmca2];
+    let mut ports = vec![port_in_bmca1, port_in_bmca2];
 
-    let bmca_notify = Arc::new(Notify::new());
+    for port in ports.iter_mut() {
+        let samples = time_error_samples(&args.time_error_intervals);
+        if let Err(error) = port.set_time_error_intervals(&args.time_error_intervals, samples) {
+            eprintln!("Invalid time error intervals: {error}");
+            std::process::exit(1);
+        }
+        port.set_frequency_change_threshold(args.frequency_change_threshold);
+        port.set_local_priority(args.local_priority);
+        port.set_offset_spike_threshold(args.offset_spike_threshold.map(Duration::from_nanos));
+        port.set_statistics_window(args.statistics_window);
+        if let Err(error) = port.set_quirks(&args.quirks) {
+            eprintln!("Invalid quirks: {error}");
+            std::process::exit(1);
+        }
+        port.set_startup_burst(
+            args.startup_burst
+                .map(|seconds| StartupBurst {
+                    duration: Duration::from_secs(seconds.into()),
+                    delay_req_interval: Interval::from_log_2(args.startup_log_delay_req_interval),
+                })
+                .or(preset.startup_burst),
+        );
+        port.set_identity_collision_response(if args.fault_on_identity_collision {
+            IdentityCollisionResponse::Faulty
+        } else {
+            IdentityCollisionResponse::Report
+        });
+        port.set_measurement_queue(true);
+        if args.simulated_path_delay.is_some() || args.simulated_delay_asymmetry.is_some() {
+            port.set_simulated_path(Some(SimulatedPath {
+                delay: Duration::from_nanos(args.simulated_path_delay.unwrap_or(0)),
+                asymmetry: args.simulated_delay_asymmetry.unwrap_or(Duration::ZERO),
+            }));
+        }
 
-    let mut main_task_senders = Vec::with_capacity(ports.len());
-    let mut main_task_receivers = Vec::with_capacity(ports.len());
+        // Calibration constants given on the command line replace those
+        // restored from the calibration directory
+        if args.delay_asymmetry.is_some()
+            || args.ingress_latency.is_some()
+            || args.egress_latency.is_some()
+        {
+            let restored = port.calibration();
+            let calibration = Calibration {
+                delay_asymmetry: args.delay_asymmetry.unwrap_or(restored.delay_asymmetry),
+                ingress_latency: args
+                    .ingress_latency
+                    .map_or(restored.ingress_latency, Duration::from_nanos),
+                egress_latency: args
+                    .egress_latency
+                    .map_or(restored.egress_latency, Duration::from_nanos),
+            };
+            if let Err(error) = port.set_calibration(calibration) {
+                log::error!("Could not store the calibration: {error}");
+            }
+        }
+    }
 
-    for port in ports.into_iter() {
-        let network_port = network_runtime.open(args.interface.clone()).await.unwrap();
+    let management = match (args.management_listen, &args.management_token_file) {
+        (Some(address), Some(token_file)) => {
+            let token = match std::fs::read_to_string(token_file) {
+                Ok(token) if !token.trim().is_empty() => token.trim().to_string(),
+                Ok(_) => {
+                    eprintln!("The management token file is empty");
+                    std::process::exit(1);
+                }
+                Err(error) => {
+                    eprintln!("Could not read the management token file: {error}");
+                    std::process::exit(1);
+                }
+            };
+            let listener = match tokio::net::TcpListener::bind(address).await {
+                Ok(listener) => listener,
+                Err(error) => {
+                    eprintln!("Could not listen for management requests on {address}: {error}");
+                    std::process::exit(1);
+                }
+            };
 
-        let (main_task_sender, port_task_receiver) = tokio::sync::mpsc::channel(1);
-        let (port_task_sender, main_task_receiver) = tokio::sync::mpsc::channel(1);
+            log::info!("Serving management requests on {address}");
+            let (management, instance_tasks) = Management::new(local_clock.clone(), token);
+            let management = Arc::new(management);
+            let server = management.clone();
+            tokio::spawn(async move {
+                if let Err(error) = server.serve(listener).await {
+                    log::error!("Management endpoint stopped: {error}");
+                }
+            });
+            Some((management, instance_tasks))
+        }
+        _ => None,
+    };
+    let (management, mut instance_tasks) = management.unzip();
 
-        tokio::spawn(port_task(
-            port_task_receiver,
-            port_task_sender,
-            network_port,
-            local_clock.clone(),
-            bmca_notify.clone(),
+    let port_count = ports.len();
+    let bmca_notify = Arc::new(Notify::new());
+    let feed = cross_check.as_ref().map(|(_, check)| CrossCheckFeed {
+        check: check.clone(),
+        domain: CrossCheckDomain::Steering,
+    });
+    let (main_task_senders, mut main_task_receivers) = spawn_port_tasks(
+        ports,
+        0,
+        &mut network_runtime,
+        &args.interface,
+        &local_clock,
+        &bmca_notify,
+        feed,
+    )
+    .await;
+
+    if let Some((cross_check_instance, check)) = cross_check {
+        let ports = (0..port_count)
+            .map(|_| {
+                let mut port = cross_check_instance.add_port(port_config, StdRng::from_entropy());
+                port.set_measurement_queue(true);
+                port
+            })
+            .collect();
+        let bmca_notify = Arc::new(Notify::new());
+        let feed = CrossCheckFeed {
+            check,
+            domain: CrossCheckDomain::Verifying,
+        };
+        let (senders, receivers) = spawn_port_tasks(
+            ports,
+            port_count,
+            &mut network_runtime,
+            &args.interface,
+            &local_clock,
+            &bmca_notify,
+            Some(feed),
+        )
+        .await;
+        tokio::spawn(run_bmca(
+            cross_check_instance,
+            bmca_notify,
+            senders,
+            receivers,
         ));
-
-        main_task_sender.send(port).await.unwrap();
-
-        main_task_senders.push(main_task_sender);
-        main_task_receivers.push(main_task_receiver);
     }
 
     // run bmca over all of the ports at the same time. The ports don't perform
     // their normal actions at this time: bmca is stop-the-world!
     let mut bmca_timer = pin!(Timer::new());
 
-    loop {
+    // The state last written to the state file
+    #[cfg(feature = "snapshot")]
+    let mut last_snapshot = None;
+
+    // The drift is stored as soon as the filter first locks, rather than
+    // after the first interval
+    let mut drift_stored = false;
+
+    'running: loop {
         // reset bmca timer
         bmca_timer.as_mut().reset(instance.bmca_interval());
 
-        // wait until the next BMCA
-        bmca_timer.as_mut().await;
+        // wait until the next BMCA, running what management requests need done
+        // on the instance in the mean time, as the BMCA can't run concurrently
+        loop {
+            let next_task = async {
+                match &mut instance_tasks {
+                    Some(tasks) => tasks.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                () = bmca_timer.as_mut() => break,
+                Some(task) = next_task => task(instance),
+                _ = terminate.recv() => break 'running,
+                _ = interrupt.recv() => break 'running,
+            }
+        }
 
         // notify all the ports that they need to stop what they're doing
         bmca_notify.notify_waiters();
@@ -311,13 +1173,183 @@ async fn actual_main() {
 
         drop(mut_bmca_ports);
 
+        if let (Some(path), false) = (&args.drift_file, drift_stored) {
+            // No port handles measurements while the BMCA runs
+            if instance.is_locked() == Ok(true) {
+                store_drift(path, &local_clock);
+                drift_stored = true;
+            }
+        }
+
+        #[cfg(feature = "snapshot")]
+        if let Some(path) = &args.state_file {
+            let snapshot = instance.snapshot();
+            if last_snapshot.as_ref() != Some(&snapshot) {
+                match write_state_file(path, &snapshot) {
+                    Ok(()) => last_snapshot = Some(snapshot),
+                    Err(error) => {
+                        log::error!("Could not write state file {}: {error}", path.display())
+                    }
+                }
+            }
+        }
+
+        for (index, port) in bmca_ports.iter().enumerate() {
+            log_time_error(index, port);
+        }
+
+        if let Some(management) = &management {
+            management.update_ports(
+                bmca_ports
+                    .iter()
+                    .map(|port| PortStatus {
+                        statistics: *port.statistics(),
+                        time_error: port.time_error().metrics().collect(),
+                        frequency: port.frequency().clone(),
+                        timeline: port.timeline().entries().to_vec(),
+                        statistics_windows: port.statistics_windows().history().to_vec(),
+                    })
+                    .collect(),
+            );
+        }
+
         for (port, sender) in bmca_ports.into_iter().zip(main_task_senders.iter()) {
             sender.send(port).await.unwrap();
         }
     }
+
+    log::info!("Shutting down");
+    if let Some(path) = &args.drift_file {
+        store_drift(path, &local_clock);
+    }
+}
+
+// Write the current frequency correction of the clock to the drift file. A
+// clock that can't report its correction leaves the file as it is, rather
+// than storing that there is none.
+fn store_drift(path: &Path, clock: &LinuxClock) {
+    let Some(frequency_multiplier) = clock.frequency_multiplier() else {
+        log::error!(
+            "Could not write drift file {}: the clock doesn't report its frequency correction",
+            path.display()
+        );
+        return;
+    };
+    if let Err(error) = write_drift_file(path, frequency_multiplier) {
+        log::error!("Could not write drift file {}: {error}", path.display());
+    }
+}
+
+// A buffer for the offsets of a port computing time error metrics over
+// `intervals`, or none when there are no intervals
+fn time_error_samples(intervals: &[usize]) -> &'static mut [f64] {
+    match intervals.iter().max() {
+        Some(&longest) => {
+            // Too long intervals are rejected by the port
+            let len = longest.saturating_mul(3).min(MAX_TIME_ERROR_SAMPLES);
+            vec![0.0; len.max(MIN_TIME_ERROR_SAMPLES)].leak()
+        }
+        None => &mut [],
+    }
+}
+
+fn log_time_error(index: usize, port: &BmcaPort) {
+    let metrics: Vec<String> = port
+        .time_error()
+        .metrics()
+        .filter_map(|metrics| {
+            Some(format!(
+                "n={} MTIE {} TDEV {}",
+                metrics.observation_interval, metrics.mtie?, metrics.tdev?
+            ))
+        })
+        .collect();
+
+    if !metrics.is_empty() {
+        log::info!("Port {} time error: {}", index + 1, metrics.join(", "));
+    }
 }
 
 type BmcaPort = Port<InBmca<'static, LinuxClock, BasicFilter>, StdRng>;
+type RunningPort = Port<Running<'static, LinuxClock, BasicFilter>, StdRng>;
+
+// The cross-check a port hands its measurements to, and the domain they are
+// made in
+#[derive(Clone)]
+struct CrossCheckFeed {
+    check: Arc<Mutex<DomainCrossCheck>>,
+    domain: CrossCheckDomain,
+}
+
+// Open a network port for each port and spawn its task, numbering them after
+// `numbered_after`. Returns the channels to hand the ports to their tasks and
+// to get them back for the BMCA.
+async fn spawn_port_tasks(
+    ports: Vec<BmcaPort>,
+    numbered_after: usize,
+    network_runtime: &mut LinuxRuntime,
+    interface: &InterfaceDescriptor,
+    local_clock: &LinuxClock,
+    bmca_notify: &Arc<Notify>,
+    cross_check: Option<CrossCheckFeed>,
+) -> (Vec<Sender<BmcaPort>>, Vec<Receiver<BmcaPort>>) {
+    let mut main_task_senders = Vec::with_capacity(ports.len());
+    let mut main_task_receivers = Vec::with_capacity(ports.len());
+
+    for (index, port) in ports.into_iter().enumerate() {
+        let network_port = network_runtime.open(interface.clone()).await.unwrap();
+
+        let (main_task_sender, port_task_receiver) = tokio::sync::mpsc::channel(1);
+        let (port_task_sender, main_task_receiver) = tokio::sync::mpsc::channel(1);
+
+        tokio::spawn(port_task(
+            numbered_after + index + 1,
+            port_task_receiver,
+            port_task_sender,
+            network_port,
+            local_clock.clone(),
+            bmca_notify.clone(),
+            cross_check.clone(),
+        ));
+
+        main_task_sender.send(port).await.unwrap();
+
+        main_task_senders.push(main_task_sender);
+        main_task_receivers.push(main_task_receiver);
+    }
+
+    (main_task_senders, main_task_receivers)
+}
+
+// Run the BMCA of an instance that needs nothing else done in between, like
+// the one following --cross-check-domain
+async fn run_bmca(
+    instance: &'static PtpInstance<LinuxClock, BasicFilter>,
+    bmca_notify: Arc<Notify>,
+    senders: Vec<Sender<BmcaPort>>,
+    mut receivers: Vec<Receiver<BmcaPort>>,
+) {
+    let mut bmca_timer = pin!(Timer::new());
+
+    loop {
+        bmca_timer.as_mut().reset(instance.bmca_interval());
+        bmca_timer.as_mut().await;
+        bmca_notify.notify_waiters();
+
+        let mut bmca_ports = Vec::with_capacity(receivers.len());
+        for receiver in receivers.iter_mut() {
+            bmca_ports.push(receiver.recv().await.unwrap());
+        }
+
+        let mut mut_bmca_ports: Vec<_> = bmca_ports.iter_mut().collect();
+        instance.bmca(&mut mut_bmca_ports);
+        drop(mut_bmca_ports);
+
+        for (port, sender) in bmca_ports.into_iter().zip(senders.iter()) {
+            sender.send(port).await.unwrap();
+        }
+    }
+}
 
 // the Port task
 //
@@ -326,18 +1358,25 @@ type BmcaPort = Port<InBmca<'static, LinuxClock, BasicFilter>, StdRng>;
 // the task is notified of a BMCA, it will stop running, move the port into the
 // bmca state, and send it on its Sender
 async fn port_task(
+    port_number: usize,
     mut port_task_receiver: Receiver<BmcaPort>,
     port_task_sender: Sender<BmcaPort>,
     mut network_port: LinuxNetworkPort,
     mut local_clock: LinuxClock,
     bmca_notify: Arc<Notify>,
+    cross_check: Option<CrossCheckFeed>,
 ) {
     let mut timers = Timers {
         port_sync_timer: pin!(Timer::new()),
         port_announce_timer: pin!(Timer::new()),
         port_announce_timeout_timer: pin!(Timer::new()),
         delay_request_timer: pin!(Timer::new()),
+        statistics_timer: pin!(Timer::new()),
     };
+    // End of the last statistics window logged
+    let mut logged_window = None;
+    // What the port publishes in the statistics segment
+    let mut record = PortRecord::default();
 
     loop {
         let port_in_bmca = port_task_receiver.recv().await.unwrap();
@@ -345,12 +1384,22 @@ async fn port_task(
         // handle post-bmca actions
         let (mut port, actions) = port_in_bmca.end_bmca();
 
-        let mut pending_timestamp =
+        let mut pending_send =
             handle_actions(actions, &mut network_port, &mut timers, &mut local_clock).await;
-
-        while let Some((context, timestamp)) = pending_timestamp {
-            pending_timestamp = handle_actions(
-                port.handle_send_timestamp(context, timestamp),
+        // Windows roll on their own boundaries, whatever the BMCA does
+        let statistics_actions = port.handle_statistics_timer();
+        handle_actions(
+            statistics_actions,
+            &mut network_port,
+            &mut timers,
+            &mut local_clock,
+        )
+        .await;
+
+        while let Some(outcome) = pending_send {
+            let source = network_port.timestamp_source();
+            pending_send = handle_actions(
+                handle_send_outcome(&mut port, outcome, source),
                 &mut network_port,
                 &mut timers,
                 &mut local_clock,
@@ -358,46 +1407,81 @@ async fn port_task(
             .await;
         }
 
+        flush(&mut port, &mut network_port, &mut timers, &mut local_clock).await;
+        log_port_output(
+            port_number,
+            &mut port,
+            &local_clock,
+            cross_check.as_ref(),
+            &mut record,
+        );
+        log_statistics_window(port_number, &port, &network_port, &mut logged_window);
+
+        let mut packets = Vec::new();
+
         loop {
-            let mut actions = tokio::select! {
-                result = network_port.recv() => {
-                    match result {
-                        Ok(packet) => {
-                            match packet.timestamp {
-                                Some(timestamp) => port.handle_timecritical_receive(&packet.data, timestamp),
-                                None => port.handle_general_receive(&packet.data),
-                            }
-                        },
-                        Err(error) => panic!("Error receiving: {error:?}"),
+            let input = tokio::select! {
+                result = network_port.recv_batch(&mut packets) => {
+                    if let Err(error) = result {
+                        log::error!("Error receiving on port {port_number}: {error}");
                     }
+                    None
                 },
                 () = &mut timers.port_announce_timer => {
-                    port.handle_announce_timer()
+                    Some(Input::AnnounceTimer)
                 },
                 () = &mut timers.port_sync_timer => {
-                    port.handle_sync_timer()
+                    Some(Input::SyncTimer)
                 },
                 () = &mut timers.port_announce_timeout_timer => {
-                    port.handle_announce_receipt_timer()
+                    Some(Input::AnnounceReceiptTimer)
                 },
                 () = &mut timers.delay_request_timer => {
-                    port.handle_delay_request_timer()
+                    Some(Input::DelayRequestTimer)
+                },
+                () = &mut timers.statistics_timer => {
+                    Some(Input::StatisticsTimer)
                 },
                 () = bmca_notify.notified() => {
                     break;
                 }
             };
 
-            loop {
-                let pending_timestamp =
-                    handle_actions(actions, &mut network_port, &mut timers, &mut local_clock).await;
-
-                // there might be more actions to handle based on the current action
-                actions = match pending_timestamp {
-                    Some((context, timestamp)) => port.handle_send_timestamp(context, timestamp),
-                    None => break,
-                };
+            match input {
+                Some(input) => {
+                    handle_input(
+                        &mut port,
+                        input,
+                        &mut network_port,
+                        &mut timers,
+                        &mut local_clock,
+                    )
+                    .await
+                }
+                // Feed the whole batch through the port before sending anything
+                None => {
+                    for packet in packets.drain(..) {
+                        handle_input(
+                            &mut port,
+                            Input::Packet(&packet),
+                            &mut network_port,
+                            &mut timers,
+                            &mut local_clock,
+                        )
+                        .await
+                    }
+                }
             }
+
+            flush(&mut port, &mut network_port, &mut timers, &mut local_clock).await;
+            log_port_output(
+                port_number,
+                &mut port,
+                &local_clock,
+                cross_check.as_ref(),
+                &mut record,
+            );
+            log_statistics_window(port_number, &port, &network_port, &mut logged_window);
         }
 
         let port_in_bmca = port.start_bmca();
@@ -405,11 +1489,290 @@ async fn port_task(
     }
 }
 
+// Log the offsets measured by and the events reported by the port, with
+// structured fields for the JSON log format, and publish them in the
+// statistics segment
+fn log_port_output(
+    port_number: usize,
+    port: &mut RunningPort,
+    clock: &LinuxClock,
+    cross_check: Option<&CrossCheckFeed>,
+    record: &mut PortRecord,
+) {
+    while let Some(measurement) = port.take_measurement() {
+        if let Some(feed) = cross_check {
+            let event = feed.check.lock().unwrap().record(feed.domain, &measurement);
+            log_cross_check_event(event);
+        }
+
+        record.measurements += 1;
+        record.offset_ns = measurement.master_offset.nanos().saturating_to_num();
+        record.measurement_time_ns = measurement.event_time.nanos().saturating_to_num();
+
+        let offset_ns = measurement.master_offset.nanos_lossy();
+        let timestamp_source = match measurement.timestamp_source {
+            TimestampSource::Hardware => "hardware",
+            TimestampSource::Software => "software",
+            TimestampSource::Legacy => "legacy",
+        };
+        // Exact fixed point values, to check the ingress latency against
+        let raw_receive_time = measurement.raw_receive_time.to_string();
+        let receive_time = measurement.receive_time.to_string();
+        // How much of the path transparent clocks account for
+        let correction = measurement.correction;
+        let residence_time_ns = correction.residence_time.nanos_lossy();
+        let sub_nanosecond_ns = correction.sub_nanosecond.nanos_lossy();
+        let asymmetry_ns = correction.asymmetry.nanos_lossy();
+        let path_delay_ns = correction.path_delay.nanos_lossy();
+        let ptp_aware_fraction = correction.ptp_aware_fraction().unwrap_or(0.0);
+        log::info!(
+            port = port_number, offset_ns = offset_ns, timestamp_source = timestamp_source,
+            raw_receive_time_ns = raw_receive_time.as_str(),
+            receive_time_ns = receive_time.as_str(), residence_time_ns = residence_time_ns,
+            sub_nanosecond_ns = sub_nanosecond_ns, asymmetry_ns = asymmetry_ns,
+            path_delay_ns = path_delay_ns, ptp_aware_fraction = ptp_aware_fraction;
+            "Port {port_number} offset {offset_ns}ns ({timestamp_source} timestamps)"
+        );
+    }
+
+    while let Some(event) = port.take_event() {
+        match event {
+            PortEvent::StateChanged { previous, current } => {
+                record.state = Some(current);
+                let is_slave = current == PortStateKind::Slave;
+                let (previous, current) = (previous.to_string(), current.to_string());
+                log::info!(
+                    port = port_number, previous_state = previous.as_str(), state = current.as_str();
+                    "Port {port_number} state {previous} -> {current}"
+                );
+
+                // The time we now take from the master, for people to check
+                let time_properties = INSTANCE.get().map(|instance| instance.time_properties());
+                if let Some(time) = time_properties
+                    .filter(|_| is_slave)
+                    .and_then(|time_properties| clock.now().to_rfc3339(&time_properties, 0))
+                {
+                    log::info!(
+                        port = port_number, ptp_time = time.as_str();
+                        "Port {port_number} follows its master, the time is {time}"
+                    );
+                }
+            }
+            PortEvent::FrequencyCorrectionChanged {
+                previous_ppb,
+                current_ppb,
+            } => {
+                log::warn!(
+                    port = port_number, event = "frequency_correction_changed",
+                    previous_ppb = previous_ppb, ppb = current_ppb;
+                    "Port {port_number} frequency correction moved from {previous_ppb}ppb to \
+                     {current_ppb}ppb, check the temperature of the oscillator"
+                );
+            }
+            PortEvent::ClockIdentityCollision {
+                port_number: claimed_port,
+                message_type,
+            } => {
+                log::error!(
+                    port = port_number, event = "clock_identity_collision";
+                    "Port {port_number} received a {message_type:?} message from another clock \
+                     using our clock identity (as port {claimed_port}), check for duplicated MAC \
+                     addresses or cloned configurations"
+                );
+            }
+            PortEvent::ParentProfileMismatch { local, advertised } => {
+                let (local, advertised) = (local.to_string(), advertised.to_string());
+                log::warn!(
+                    port = port_number, event = "parent_profile_mismatch",
+                    profile = local.as_str(), advertised_profile = advertised.as_str();
+                    "Port {port_number} follows a master running profile {advertised}, not \
+                     {local}, check --profile"
+                );
+            }
+            event => {
+                let name = match event {
+                    PortEvent::AnnouncedLeapIndicator { .. } => "announced_leap_indicator",
+                    PortEvent::AnnouncedUtcOffset { .. } => "announced_utc_offset",
+                    PortEvent::ClockIdentityCollision { .. } => "clock_identity_collision",
+                    PortEvent::Diagnostic(_) => "diagnostic",
+                    PortEvent::StateChanged { .. } => "state_changed",
+                    PortEvent::FrequencyCorrectionChanged { .. } => "frequency_correction_changed",
+                    PortEvent::ParentProfileMismatch { .. } => "parent_profile_mismatch",
+                    PortEvent::AsCapableChanged { .. } => "as_capable_changed",
+                };
+                log::info!(port = port_number, event = name; "Port {port_number} {event:?}");
+            }
+        }
+    }
+
+    if let Some(segment) = STATISTICS_SEGMENT.get() {
+        record.update_counters(port.statistics());
+        segment.publish(port_number, record);
+    }
+}
+
+// Log the statistics window that ended last, unless it already was
+fn log_statistics_window(
+    port_number: usize,
+    port: &RunningPort,
+    network_port: &LinuxNetworkPort,
+    logged: &mut Option<Time>,
+) {
+    let Some(window) = port.statistics_windows().latest() else {
+        return;
+    };
+    if *logged == Some(window.end) {
+        return;
+    }
+    *logged = Some(window.end);
+
+    let (start_s, end_s) = (window.start.secs(), window.end.secs());
+    let offsets = window.offsets;
+    let count = offsets.count;
+    let (min_ns, max_ns) = (offsets.min.nanos_lossy(), offsets.max.nanos_lossy());
+    let mean_ns = offsets.mean().map_or(0.0, |mean| mean.nanos_lossy());
+    let percentile = |value: Option<Duration>| value.map_or(0.0, |value| value.nanos_lossy());
+    let (median_ns, p90_ns, p99_ns) = (
+        percentile(window.median),
+        percentile(window.p90),
+        percentile(window.p99),
+    );
+    log::info!(
+        port = port_number, event = "statistics_window", start_s = start_s, end_s = end_s,
+        count = count, min_ns = min_ns, max_ns = max_ns, mean_ns = mean_ns,
+        median_ns = median_ns, p90_ns = p90_ns, p99_ns = p99_ns;
+        "Port {port_number} offsets from {start_s}s to {end_s}s: {count} measured, min {min_ns}ns, \
+         max {max_ns}ns, |offset| p50 {median_ns}ns p90 {p90_ns}ns p99 {p99_ns}ns"
+    );
+
+    let interface = network_port.interface_name();
+    let tx_timestamps = network_port.tx_timestamp_stats();
+    let (on_time, late, missed) = (
+        tx_timestamps.on_time,
+        tx_timestamps.late,
+        tx_timestamps.missed,
+    );
+    let mean_us = tx_timestamps
+        .mean_latency()
+        .map_or(0.0, |mean| mean.as_secs_f64() * 1e6);
+    let max_us = tx_timestamps.max_latency.as_secs_f64() * 1e6;
+    log::info!(
+        port = port_number, event = "tx_timestamps", interface = interface, on_time = on_time,
+        late = late, missed = missed, mean_us = mean_us, max_us = max_us;
+        "Port {port_number} send timestamps on {interface}: {on_time} on time, {late} late, \
+         {missed} missed, latency mean {mean_us:.1}us max {max_us:.1}us"
+    );
+}
+
+fn log_cross_check_event(event: Option<CrossCheckEvent>) {
+    match event {
+        Some(CrossCheckEvent::Diverged { divergence }) => {
+            let divergence_ns = divergence.nanos_lossy();
+            log::error!(
+                event = "cross_check_diverged", divergence_ns = divergence_ns;
+                "The time of --cross-check-domain diverged {divergence_ns}ns from that of the \
+                 domain the clock follows"
+            );
+        }
+        Some(CrossCheckEvent::Converged { divergence }) => {
+            let divergence_ns = divergence.nanos_lossy();
+            log::info!(
+                event = "cross_check_converged", divergence_ns = divergence_ns;
+                "The time of --cross-check-domain is back within the bound, {divergence_ns}ns \
+                 from that of the domain the clock follows"
+            );
+        }
+        None => {}
+    }
+}
+
+// Something the port task needs to hand to the port
+enum Input<'a> {
+    Packet(&'a NetworkPacket),
+    AnnounceTimer,
+    SyncTimer,
+    AnnounceReceiptTimer,
+    DelayRequestTimer,
+    StatisticsTimer,
+    SendFailure(&'a [u8], SendError),
+}
+
+async fn handle_input(
+    port: &mut RunningPort,
+    input: Input<'_>,
+    network_port: &mut LinuxNetworkPort,
+    timers: &mut Timers<'_>,
+    local_clock: &mut LinuxClock,
+) {
+    let source = network_port.timestamp_source();
+    let mut actions = match input {
+        Input::Packet(packet) => match receive_packet(port, packet, source) {
+            Some(actions) => actions,
+            None => return,
+        },
+        Input::AnnounceTimer => port.handle_announce_timer(),
+        Input::SyncTimer => port.handle_sync_timer(),
+        Input::AnnounceReceiptTimer => port.handle_announce_receipt_timer(),
+        Input::DelayRequestTimer => port.handle_delay_request_timer(),
+        Input::StatisticsTimer => port.handle_statistics_timer(),
+        Input::SendFailure(data, error) => {
+            port.handle_send_failure(FailedSend::General { data }, error)
+        }
+    };
+
+    loop {
+        let pending_send = handle_actions(actions, network_port, timers, local_clock).await;
+
+        // there might be more actions to handle based on the current action
+        actions = match pending_send {
+            Some(outcome) => handle_send_outcome(port, outcome, source),
+            None => break,
+        };
+    }
+}
+
+// Send the queued general messages, telling the port about those that could
+// not be sent
+async fn flush(
+    port: &mut RunningPort,
+    network_port: &mut LinuxNetworkPort,
+    timers: &mut Timers<'_>,
+    local_clock: &mut LinuxClock,
+) {
+    while let Err(failure) = network_port.flush().await {
+        log::debug!("Could not send general message: {}", failure.error);
+        let input = Input::SendFailure(&failure.data, send_error(&failure.error));
+        handle_input(port, input, network_port, timers, local_clock).await;
+    }
+}
+
+// What became of the last time critical message sent for a set of actions
+enum SendOutcome {
+    Sent(TimestampContext, Time),
+    Failed(TimestampContext, SendError),
+}
+
+fn handle_send_outcome(
+    port: &mut RunningPort,
+    outcome: SendOutcome,
+    source: TimestampSource,
+) -> PortActionIterator<'_> {
+    match outcome {
+        SendOutcome::Sent(context, timestamp) => {
+            port.handle_send_timestamp_from(context, timestamp, source)
+        }
+        SendOutcome::Failed(context, error) => {
+            port.handle_send_failure(FailedSend::TimeCritical { context }, error)
+        }
+    }
+}
+
 struct Timers<'a> {
     port_sync_timer: Pin<&'a mut Timer>,
     port_announce_timer: Pin<&'a mut Timer>,
     port_announce_timeout_timer: Pin<&'a mut Timer>,
     delay_request_timer: Pin<&'a mut Timer>,
+    statistics_timer: Pin<&'a mut Timer>,
 }
 
 async fn handle_actions(
@@ -417,24 +1780,35 @@ async fn handle_actions(
     network_port: &mut statime_linux::network::LinuxNetworkPort,
     timers: &mut Timers<'_>,
     local_clock: &mut LinuxClock,
-) -> Option<(TimestampContext, Time)> {
-    let mut pending_timestamp = None;
+) -> Option<SendOutcome> {
+    let mut pending_send = None;
 
     for action in actions {
         match action {
-            PortAction::SendTimeCritical { context, data } => {
-                // send timestamp of the send
-                let time = network_port
-                    .send_time_critical(data)
-                    .await
-                    .unwrap()
-                    .unwrap_or(local_clock.now());
+            PortAction::SendTimeCritical { context, data, .. } => {
+                let outcome = match network_port.send_time_critical(data).await {
+                    // send timestamp of the send
+                    Ok(time) => SendOutcome::Sent(context, time.unwrap_or(local_clock.now())),
+                    Err(error) => {
+                        log::debug!("Could not send time critical message: {error}");
+                        SendOutcome::Failed(context, send_error(&error))
+                    }
+                };
 
                 // anything we send later will have a later pending (send) timestamp
-                pending_timestamp = Some((context, time));
+                pending_send = Some(outcome);
             }
             PortAction::SendGeneral { data } => {
-                network_port.send(data).await.unwrap();
+                // sent in a batch when the port task flushes
+                network_port.queue_general(data);
+            }
+            PortAction::SendUnicastGeneral { .. }
+            | PortAction::SendUnicastTimeCritical { .. }
+            | PortAction::SendToUnicastMaster { .. }
+            | PortAction::ResetUnicastNegotiationTimer { .. } => {
+                // Only used by ports granting or requesting unicast
+                // transmission, which this daemon doesn't configure
+                log::error!("Statime bug: unexpected unicast action");
             }
             PortAction::ResetAnnounceTimer { duration } => {
                 timers.port_announce_timer.as_mut().reset(duration);
@@ -448,8 +1822,11 @@ async fn handle_actions(
             PortAction::ResetAnnounceReceiptTimer { duration } => {
                 timers.port_announce_timeout_timer.as_mut().reset(duration);
             }
+            PortAction::ResetStatisticsTimer { duration } => {
+                timers.statistics_timer.as_mut().reset(duration);
+            }
         }
     }
 
-    pending_timestamp
+    pending_send
 }
@@ -0,0 +1,721 @@
+//! Authenticated HTTP management endpoint
+//!
+//! Serves a JSON view of the instance and its ports, and allows changing the
+//! settings that are safe to change at runtime, so configuration systems can
+//! manage a fleet of nodes. Every request must carry an
+//! `Authorization: Bearer <token>` header with the configured token.
+//!
+//! | Request              | Effect                                       |
+//! |----------------------|----------------------------------------------|
+//! | `GET /v1/instance`   | Datasets of the instance                     |
+//! | `GET /v1/ports`      | Statistics, time error and timeline per port |
+//! | `PUT /v1/priority-1` | Set priority 1 to the number in the body     |
+//! | `PUT /v1/demote`     | Hand over to another master, see below       |
+//! | `PUT /v1/free-run`   | Set free-run mode to `true` or `false`       |
+//!
+//! Demoting sets priority 1 to the number in the body, but keeps the ports
+//! that are master sending sync messages for [`DEMOTION_HANDOVER`] announce
+//! intervals after a new master appeared, so the slaves don't lose sync. The
+//! instance reports `demoting` until the new master took over.
+//!
+//! The instance includes what time the clock shows, as RFC3339 timestamps in
+//! UTC and in the local timezone, or `null` while the time isn't related to
+//! UTC. Port information is a snapshot taken by the runtime after each BMCA
+//! run, as the ports themselves are owned by their tasks. Requests on the
+//! instance are run by the runtime as well, see [`InstanceTask`].
+//!
+//! The endpoint speaks plain HTTP, so the token and the requests are not
+//! encrypted. It therefore only listens on loopback addresses. Configuration
+//! systems that manage remote nodes reach it through an SSH tunnel, or through
+//! a TLS terminating proxy on the node. There is no gRPC variant of the
+//! endpoint.
+
+use std::{
+    fmt::Write,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+use statime::{
+    AnnounceContent, BasicFilter, Clock, ClockIdentity, ClockQuality, DelayRespRejections,
+    DurationStatistics, FrequencyCorrection, FrequencyStatistics, MessageRate, MessageRates,
+    MessageTypeRates, PortStatistics, Profile, PtpInstance, QuirkCounts, StatisticsWindow, Time,
+    TimeErrorMetrics, TimePropertiesDS, TimelineEntry, TimelineEvent, TimestampSourceCounts,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+};
+
+use crate::clock::{local_utc_offset, LinuxClock};
+
+/// Largest request accepted, including the headers
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Time a client gets to send its complete request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests waiting for the runtime to run them on the instance
+const MAX_PENDING_INSTANCE_TASKS: usize = 16;
+
+/// Announce intervals the ports stay master after a new master appeared, when
+/// demoting through `PUT /v1/demote`
+pub const DEMOTION_HANDOVER: u8 = 3;
+
+type Instance = PtpInstance<LinuxClock, BasicFilter, Profile>;
+
+/// Work on the instance that a management request needs done.
+///
+/// The runtime runs these from the task that also runs the BMCA, between
+/// runs of it. The BMCA is the only one that holds the instance state
+/// mutably, so the instance never has to wait for its state there.
+pub type InstanceTask = Box<dyn FnOnce(&Instance) + Send>;
+
+/// Snapshot of a port, taken by the runtime
+#[derive(Debug, Clone)]
+pub struct PortStatus {
+    pub statistics: PortStatistics,
+    pub time_error: Vec<TimeErrorMetrics>,
+    pub frequency: FrequencyStatistics,
+    pub timeline: Vec<TimelineEntry>,
+    pub statistics_windows: Vec<StatisticsWindow>,
+}
+
+pub struct Management {
+    clock: LinuxClock,
+    token: String,
+    ports: Mutex<Vec<PortStatus>>,
+    instance_tasks: mpsc::Sender<InstanceTask>,
+}
+
+impl Management {
+    /// The endpoint, and the receiver of the [`InstanceTask`]s the runtime
+    /// must run for it
+    pub fn new(clock: LinuxClock, token: String) -> (Self, mpsc::Receiver<InstanceTask>) {
+        let (instance_tasks, receiver) = mpsc::channel(MAX_PENDING_INSTANCE_TASKS);
+        let management = Management {
+            clock,
+            token,
+            ports: Mutex::new(Vec::new()),
+            instance_tasks,
+        };
+        (management, receiver)
+    }
+
+    /// Replace the port snapshot served by `GET /v1/ports`
+    pub fn update_ports(&self, ports: Vec<PortStatus>) {
+        *self.ports.lock().unwrap() = ports;
+    }
+
+    /// Accept and answer management requests until accepting fails
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let management = self.clone();
+            tokio::spawn(async move {
+                if let Err(error) = management.handle_connection(stream).await {
+                    log::debug!("Management connection from {peer} failed: {error}");
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let response = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&stream)).await {
+            Ok(Ok(request)) => self.respond(&request).await,
+            Ok(Err(error)) if error.kind() == io::ErrorKind::InvalidData => {
+                Response::error(400, &error.to_string())
+            }
+            Ok(Err(error)) => return Err(error),
+            Err(_) => Response::error(408, "request timed out"),
+        };
+
+        write_all(&stream, &response.to_bytes()).await
+    }
+
+    async fn respond(&self, request: &Request) -> Response {
+        let authorized = request
+            .authorization
+            .as_deref()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), self.token.as_bytes()));
+        if !authorized {
+            return Response::error(401, "missing or invalid token");
+        }
+
+        let result = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/v1/instance") => {
+                let now = self.clock.now();
+                self.with_instance(move |instance| {
+                    Response::json(200, instance_json(instance, now))
+                })
+                .await
+            }
+            ("GET", "/v1/ports") => Ok(Response::json(200, self.ports_json())),
+            ("PUT", "/v1/priority-1") => match request.body_str().trim().parse::<u8>() {
+                Ok(priority_1) => {
+                    self.with_instance(move |instance| match instance.set_priority_1(priority_1) {
+                        Ok(()) => {
+                            log::info!("Priority 1 set to {priority_1} through management");
+                            Response::empty()
+                        }
+                        Err(error) => Response::error(409, &error.to_string()),
+                    })
+                    .await
+                }
+                Err(_) => Ok(Response::error(
+                    400,
+                    "expected a priority between 0 and 255",
+                )),
+            },
+            ("PUT", "/v1/demote") => match request.body_str().trim().parse::<u8>() {
+                Ok(priority_1) => {
+                    self.with_instance(move |instance| {
+                        match instance.demote(priority_1, DEMOTION_HANDOVER) {
+                            Ok(()) => {
+                                log::info!(
+                                    "Demoting to priority 1 {priority_1} through management"
+                                );
+                                Response::empty()
+                            }
+                            Err(error) => Response::error(409, &error.to_string()),
+                        }
+                    })
+                    .await
+                }
+                Err(_) => Ok(Response::error(
+                    400,
+                    "expected a priority between 0 and 255",
+                )),
+            },
+            ("PUT", "/v1/free-run") => match request.body_str().trim().parse::<bool>() {
+                Ok(enabled) => {
+                    self.with_instance(move |instance| match instance.set_free_run(enabled) {
+                        Ok(()) => Response::empty(),
+                        Err(error) => Response::error(503, &error.to_string()),
+                    })
+                    .await
+                }
+                Err(_) => Ok(Response::error(400, "expected true or false")),
+            },
+            (
+                _,
+                "/v1/instance" | "/v1/ports" | "/v1/priority-1" | "/v1/demote" | "/v1/free-run",
+            ) => Ok(Response::error(405, "method not allowed")),
+            _ => Ok(Response::error(404, "not found")),
+        };
+
+        result.unwrap_or_else(|_| Response::error(503, "the runtime stopped"))
+    }
+
+    /// Have the runtime run `task` on the instance, and wait for its result
+    async fn with_instance<T: Send + 'static>(
+        &self,
+        task: impl FnOnce(&Instance) -> T + Send + 'static,
+    ) -> Result<T, RuntimeStopped> {
+        let (sender, receiver) = oneshot::channel();
+        let task: InstanceTask = Box::new(move |instance| {
+            // The request may have timed out in the mean time
+            let _ = sender.send(task(instance));
+        });
+
+        self.instance_tasks
+            .send(task)
+            .await
+            .map_err(|_| RuntimeStopped)?;
+        receiver.await.map_err(|_| RuntimeStopped)
+    }
+
+    fn ports_json(&self) -> Value {
+        let ports = self.ports.lock().unwrap();
+        ports
+            .iter()
+            .enumerate()
+            .map(|(index, port)| port_json(index + 1, port))
+            .collect()
+    }
+}
+
+/// The runtime dropped an [`InstanceTask`] without running it
+struct RuntimeStopped;
+
+fn instance_json(instance: &Instance, now: Time) -> Value {
+    let status = instance.status();
+    json!({
+        "clock_identity": identity_json(&status.clock_identity),
+        "priority_1": instance.priority_1(),
+        "priority_2": status.priority_2,
+        "clock_quality": quality_json(&status.clock_quality),
+        "domain_number": status.domain_number,
+        "steps_removed": status.steps_removed,
+        "grandmaster": {
+            "identity": identity_json(&status.grandmaster_identity),
+            "priority_1": status.grandmaster_priority_1,
+            "priority_2": status.grandmaster_priority_2,
+            "clock_quality": quality_json(&status.grandmaster_clock_quality),
+        },
+        "ptp_timescale": status.time_properties_ds.is_ptp(),
+        "leap_indicator": format!("{:?}", status.time_properties_ds.leap_indicator()),
+        "free_run": status.free_run,
+        "demoting": instance.demotion_pending(),
+        "time": rfc3339_json(now, &status.time_properties_ds, 0),
+        "local_time": rfc3339_json(now, &status.time_properties_ds, local_utc_offset()),
+    })
+}
+
+fn port_json(number: usize, port: &PortStatus) -> Value {
+    let statistics = &port.statistics;
+    let time_error: Vec<Value> = port
+        .time_error
+        .iter()
+        .map(|metrics| {
+            json!({
+                "observation_interval": metrics.observation_interval,
+                "mtie_ns": optional_nanos_json(metrics.mtie),
+                "tdev_ns": optional_nanos_json(metrics.tdev),
+            })
+        })
+        .collect();
+
+    json!({
+        "port": number,
+        "clock_source_changes": statistics.clock_source_changes,
+        "delay_resp_turnaround": duration_statistics_json(&statistics.delay_resp_turnaround),
+        "measurements_dropped": statistics.measurements_dropped,
+        "unexpected_unicast_messages": statistics.unexpected_unicast_messages,
+        "unexpected_multicast_messages": statistics.unexpected_multicast_messages,
+        "events_dropped": statistics.events_dropped,
+        "timestamp_sources": source_counts_json(&statistics.timestamp_sources),
+        "measurement_sources": source_counts_json(&statistics.measurement_sources),
+        "quirks": quirk_counts_json(&statistics.quirks),
+        "delay_resp_rejections": delay_resp_rejections_json(&statistics.delay_resp_rejections),
+        "non_parent_sync_messages": statistics.non_parent_sync_messages,
+        "send_failures": statistics.send_failures,
+        "identity_collisions": statistics.identity_collisions,
+        "profile_mismatches": statistics.profile_mismatches,
+        "serialization_failures": statistics.serialization_failures,
+        "internal_errors": statistics.internal_errors,
+        "last_announce": statistics.last_announce.as_ref().map(announce_json),
+        "message_rates": message_rates_json(&statistics.message_rates),
+        "time_error": time_error,
+        "frequency": frequency_json(&port.frequency),
+        "timeline": port.timeline.iter().map(timeline_entry_json).collect::<Vec<_>>(),
+        "statistics_windows": port
+            .statistics_windows
+            .iter()
+            .map(statistics_window_json)
+            .collect::<Vec<_>>(),
+    })
+}
+
+// The offsets of a closed window, with percentiles of their absolute values
+fn statistics_window_json(window: &StatisticsWindow) -> Value {
+    json!({
+        "start_s": window.start.secs(),
+        "end_s": window.end.secs(),
+        "offsets": duration_statistics_json(&window.offsets),
+        "median_ns": optional_nanos_json(window.median),
+        "p90_ns": optional_nanos_json(window.p90),
+        "p99_ns": optional_nanos_json(window.p99),
+    })
+}
+
+// The kind of event, and what else is known about it
+fn timeline_entry_json(entry: &TimelineEntry) -> Value {
+    let identity = |identity: Option<ClockIdentity>| identity.as_ref().map(identity_json);
+    let mut value = match entry.event {
+        TimelineEvent::OffsetSpike { offset } => {
+            json!({ "event": "offset_spike", "offset_ns": offset.nanos_lossy() })
+        }
+        TimelineEvent::ClockStepped { correction } => {
+            json!({ "event": "clock_stepped", "correction_ns": correction.nanos_lossy() })
+        }
+        TimelineEvent::StateChanged { previous, current } => json!({
+            "event": "state_changed",
+            "previous": previous.to_string(),
+            "current": current.to_string(),
+        }),
+        TimelineEvent::ParentChanged { previous, current } => json!({
+            "event": "parent_changed",
+            "previous": identity(previous),
+            "current": identity(current),
+        }),
+        TimelineEvent::AnnounceReceiptTimeout => json!({ "event": "announce_receipt_timeout" }),
+        TimelineEvent::SyncReceiptTimeout => json!({ "event": "sync_receipt_timeout" }),
+        TimelineEvent::DelayReceiptTimeout => json!({ "event": "delay_receipt_timeout" }),
+        TimelineEvent::ClockSourceChanged => json!({ "event": "clock_source_changed" }),
+        TimelineEvent::LinkChanged { up } => json!({ "event": "link_changed", "up": up }),
+    };
+    value["time_s"] = json!(entry.time.secs());
+    value
+}
+
+// The latest correction, and the mean correction of each recent minute
+fn frequency_json(frequency: &FrequencyStatistics) -> Value {
+    let correction = |correction: &FrequencyCorrection| json!({ "time_s": correction.time.secs(), "ppb": correction.ppb });
+    json!({
+        "current": frequency.current().as_ref().map(correction),
+        "history": frequency.history().iter().map(correction).collect::<Vec<_>>(),
+    })
+}
+
+fn source_counts_json(counts: &TimestampSourceCounts) -> Value {
+    json!({
+        "hardware": counts.hardware,
+        "software": counts.software,
+        "legacy": counts.legacy,
+    })
+}
+
+fn quirk_counts_json(counts: &QuirkCounts) -> Value {
+    json!({
+        "delay_resp_sequence_id": counts.delay_resp_sequence_id,
+        "follow_up_before_sync": counts.follow_up_before_sync,
+    })
+}
+
+fn delay_resp_rejections_json(rejections: &DelayRespRejections) -> Value {
+    json!({
+        "requesting_port": rejections.requesting_port,
+        "source": rejections.source,
+        "sequence_id": rejections.sequence_id,
+    })
+}
+
+fn message_rates_json(rates: &MessageRates) -> Value {
+    json!({
+        "received": message_type_rates_json(&rates.received),
+        "sent": message_type_rates_json(&rates.sent),
+    })
+}
+
+// Messages per second of each type, or null before the second message
+fn message_type_rates_json(rates: &MessageTypeRates) -> Value {
+    let rate = |rate: &MessageRate| rate.per_second();
+    json!({
+        "sync": rate(&rates.sync),
+        "announce": rate(&rates.announce),
+        "delay_req": rate(&rates.delay_req),
+        "pdelay_req": rate(&rates.pdelay_req),
+    })
+}
+
+fn announce_json(announce: &AnnounceContent) -> Value {
+    json!({
+        "sequence_id": announce.sequence_id,
+        "unicast": announce.unicast,
+        "leap_indicator": format!("{:?}", announce.leap_indicator),
+        "current_utc_offset": announce.current_utc_offset,
+        "ptp_timescale": announce.ptp_timescale,
+        "time_traceable": announce.time_traceable,
+        "frequency_traceable": announce.frequency_traceable,
+        "time_source": format!("{:?}", announce.time_source),
+        "grandmaster": {
+            "identity": identity_json(&announce.grandmaster_identity),
+            "priority_1": announce.grandmaster_priority_1,
+            "priority_2": announce.grandmaster_priority_2,
+            "clock_quality": quality_json(&announce.grandmaster_clock_quality),
+        },
+        "steps_removed": announce.steps_removed,
+    })
+}
+
+fn duration_statistics_json(statistics: &DurationStatistics) -> Value {
+    json!({
+        "count": statistics.count,
+        "min_ns": statistics.min.nanos_lossy(),
+        "max_ns": statistics.max.nanos_lossy(),
+        "mean_ns": optional_nanos_json(statistics.mean()),
+    })
+}
+
+fn rfc3339_json(time: Time, time_properties: &TimePropertiesDS, local_offset: i32) -> Value {
+    time.to_rfc3339(time_properties, local_offset).into()
+}
+
+fn optional_nanos_json(duration: Option<statime::Duration>) -> Value {
+    duration.map(|duration| duration.nanos_lossy()).into()
+}
+
+fn identity_json(identity: &ClockIdentity) -> Value {
+    identity.to_string().into()
+}
+
+fn quality_json(quality: &ClockQuality) -> Value {
+    json!({
+        "clock_class": u8::from(quality.clock_class),
+        "clock_accuracy": format!("{:?}", quality.clock_accuracy),
+        "offset_scaled_log_variance": quality.offset_scaled_log_variance,
+    })
+}
+
+// Compare the token without leaking how much of it matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn body_str(&self) -> &str {
+        std::str::from_utf8(&self.body).unwrap_or_default()
+    }
+}
+
+async fn write_all(stream: &TcpStream, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        stream.writable().await?;
+        match stream.try_write(data) {
+            Ok(written) => data = &data[written..],
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+async fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 1024];
+
+    loop {
+        if let Some(request) = parse_request(&buffer)? {
+            return Ok(request);
+        }
+
+        if buffer.len() >= MAX_REQUEST_SIZE {
+            return Err(invalid_data("request too large"));
+        }
+
+        stream.readable().await?;
+        let read = match stream.try_read(&mut chunk) {
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(error) => return Err(error),
+        };
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Parse an HTTP/1.1 request, returning `None` while it is incomplete
+fn parse_request(buffer: &[u8]) -> io::Result<Option<Request>> {
+    let Some(header_end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return Ok(None);
+    };
+
+    let head = std::str::from_utf8(&buffer[..header_end])
+        .map_err(|_| invalid_data("request headers are not valid UTF-8"))?;
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(path), Some(version), None) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return Err(invalid_data("malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(invalid_data("unsupported HTTP version"));
+    }
+
+    let mut authorization = None;
+    let mut content_length = 0;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(invalid_data("malformed header"));
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .parse()
+                .map_err(|_| invalid_data("invalid content length"))?;
+        }
+    }
+
+    let body_start = header_end + 4;
+    if body_start + content_length > MAX_REQUEST_SIZE {
+        return Err(invalid_data("request too large"));
+    }
+    if buffer.len() < body_start + content_length {
+        return Ok(None);
+    }
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        authorization,
+        body: buffer[body_start..body_start + content_length].to_vec(),
+    }))
+}
+
+struct Response {
+    status: u16,
+    body: Option<String>,
+}
+
+impl Response {
+    fn json(status: u16, body: Value) -> Self {
+        Response {
+            status,
+            body: Some(body.to_string()),
+        }
+    }
+
+    fn empty() -> Self {
+        Response {
+            status: 204,
+            body: None,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, json!({ "error": message }))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            204 => "No Content",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            503 => "Service Unavailable",
+            _ => "Error",
+        };
+
+        let mut response = format!("HTTP/1.1 {} {reason}\r\nConnection: close\r\n", self.status);
+        if self.status == 401 {
+            response.push_str("WWW-Authenticate: Bearer\r\n");
+        }
+        match &self.body {
+            Some(body) => {
+                let _ = write!(
+                    response,
+                    "Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+            None => response.push_str("\r\n"),
+        }
+        response.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_complete_request() {
+        let request = b"PUT /v1/priority-1 HTTP/1.1\r\nHost: ptp\r\nauthorization: Bearer \
+                        secret\r\nContent-Length: 3\r\n\r\n100";
+
+        assert_eq!(parse_request(&request[..20]).unwrap(), None);
+        assert_eq!(parse_request(&request[..request.len() - 1]).unwrap(), None);
+        assert_eq!(
+            parse_request(request).unwrap(),
+            Some(Request {
+                method: "PUT".into(),
+                path: "/v1/priority-1".into(),
+                authorization: Some("Bearer secret".into()),
+                body: b"100".to_vec(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_invalid_request() {
+        assert!(parse_request(b"GET /v1/ports\r\n\r\n").is_err());
+        assert!(parse_request(b"GET /v1/ports HTTP/2\r\n\r\n").is_err());
+        assert!(parse_request(b"GET /v1/ports HTTP/1.1\r\nno colon\r\n\r\n").is_err());
+        assert!(parse_request(b"GET / HTTP/1.1\r\nContent-Length: 100000\r\n\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn instance_requests_run_by_runtime() {
+        use statime::{InstanceConfig, SdoId, TimeSource, TimestampingQuality};
+
+        let instance: &'static Instance = Box::leak(Box::new(PtpInstance::with_master_selection(
+            InstanceConfig {
+                clock_identity: ClockIdentity([1; 8]),
+                priority_1: 128,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            },
+            TimePropertiesDS::new_arbitrary_time(false, false, TimeSource::InternalOscillator),
+            LinuxClock::realtime(),
+            BasicFilter::for_quality(TimestampingQuality::Software),
+            Profile::Default,
+        )));
+        let (management, mut instance_tasks) =
+            Management::new(LinuxClock::realtime(), "secret".into());
+        let runtime = tokio::spawn(async move {
+            while let Some(task) = instance_tasks.recv().await {
+                task(instance);
+            }
+        });
+
+        let request = |method: &str, path: &str, body: &str| Request {
+            method: method.into(),
+            path: path.into(),
+            authorization: Some("Bearer secret".into()),
+            body: body.as_bytes().to_vec(),
+        };
+
+        let response = management
+            .respond(&request("PUT", "/v1/free-run", "true"))
+            .await;
+        assert_eq!(response.status, 204);
+
+        let response = management
+            .respond(&request("GET", "/v1/instance", ""))
+            .await;
+        assert_eq!(response.status, 200);
+        let body: Value = serde_json::from_str(&response.body.unwrap()).unwrap();
+        assert_eq!(body["free_run"], json!(true));
+        assert_eq!(body["priority_1"], json!(128));
+        assert_eq!(
+            body["clock_identity"],
+            json!(ClockIdentity([1; 8]).to_string())
+        );
+
+        // Without a runtime to run them, requests on the instance fail
+        runtime.abort();
+        let _ = runtime.await;
+        let response = management
+            .respond(&request("GET", "/v1/instance", ""))
+            .await;
+        assert_eq!(response.status, 503);
+    }
+
+    #[test]
+    fn token_comparison() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
@@ -4,12 +4,16 @@
 
 use std::{
     io,
-    io::ErrorKind,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Instant,
 };
 
 use arrayvec::ArrayVec;
-use statime::{Time, MAX_DATA_LEN};
+use rand::Rng;
+use statime::{
+    route_packet, Clock, Destination, Filter, PacketArrival, PacketMatch, Port, PortActionIterator,
+    Running, SendError, SourceAddress, Time, TimestampSource, MAX_DATA_LEN,
+};
 use timestamped_socket::{
     interface::{InterfaceDescriptor, InterfaceIterator},
     raw_udp_socket::{RawUdpSocket, TimestampingMode},
@@ -17,7 +21,12 @@ use timestamped_socket::{
 };
 use tokio::io::{unix::AsyncFd, Interest};
 
-use crate::clock::LinuxClock;
+use crate::{
+    batch::{recv_batch, send_batch, BATCH_SIZE, RECV_BUFFER_SIZE},
+    clock::LinuxClock,
+    socket_options::{set_busy_poll, set_packet_info},
+    tx_timestamp::{poll_error_queue, read_error_queue, TxTimestampPolicy, TxTimestampStats},
+};
 
 /// The time-critical port
 const TC_PORT: u16 = 319;
@@ -28,6 +37,8 @@ const NTC_PORT: u16 = 320;
 pub struct LinuxRuntime {
     timestamping_mode: TimestampingMode,
     clock: LinuxClock,
+    busy_poll: Option<u32>,
+    tx_timestamp_policy: TxTimestampPolicy,
 }
 
 impl LinuxRuntime {
@@ -35,9 +46,24 @@ impl LinuxRuntime {
         LinuxRuntime {
             timestamping_mode,
             clock,
+            busy_poll: None,
+            tx_timestamp_policy: TxTimestampPolicy::default(),
         }
     }
 
+    /// Busy poll the time critical socket of ports opened from now on for up
+    /// to the given number of microseconds, see
+    /// [`set_busy_poll`](crate::socket_options::set_busy_poll).
+    pub fn set_busy_poll(&mut self, micros: Option<u32>) {
+        self.busy_poll = micros;
+    }
+
+    /// How ports opened from now on look for send timestamps that the
+    /// network driver delivers late
+    pub fn set_tx_timestamp_policy(&mut self, policy: TxTimestampPolicy) {
+        self.tx_timestamp_policy = policy;
+    }
+
     const IPV6_PRIMARY_MULTICAST: Ipv6Addr = Ipv6Addr::new(0xff, 0x0e, 0, 0, 0, 0, 0x01, 0x81);
     const IPV6_PDELAY_MULTICAST: Ipv6Addr = Ipv6Addr::new(0xff, 0x02, 0, 0, 0, 0, 0, 0x6b);
 
@@ -100,14 +126,13 @@ impl LinuxRuntime {
         &mut self,
         interface: InterfaceDescriptor,
     ) -> Result<LinuxNetworkPort, NetworkError> {
-        log::info!(
-            "Opening network port on '{}'",
-            interface
-                .interface_name
-                .as_ref()
-                .map(|if_name| if_name.as_str())
-                .unwrap_or("Unknown")
-        );
+        let interface_name = interface
+            .interface_name
+            .as_ref()
+            .map(|if_name| if_name.as_str())
+            .unwrap_or("Unknown")
+            .to_owned();
+        log::info!("Opening network port on '{interface_name}'");
 
         let bind_ip = interface.mode.unspecified_ip_addr();
         let tc_addr = SocketAddr::new(bind_ip, TC_PORT);
@@ -119,28 +144,75 @@ impl LinuxRuntime {
         let tc_socket = RawUdpSocket::new_into_std(tc_addr, interface.interface_name)?;
         let ntc_socket = RawUdpSocket::new_into_std(ntc_addr, interface.interface_name)?;
 
+        let own_address = interface.get_address()?;
         let tc_address = Self::join_multicast(&interface, &tc_socket)?;
         let ntc_address = Self::join_multicast(&interface, &ntc_socket)?;
+        set_packet_info(&ntc_socket)?;
 
+        if let Some(micros) = self.busy_poll {
+            log::info!("Busy polling time critical socket for {micros}us");
+            set_busy_poll(&tc_socket, micros)?;
+        }
+
+        let timestamp_source = match self.timestamping_mode {
+            TimestampingMode::Hardware(_) => TimestampSource::Hardware,
+            TimestampingMode::Software => TimestampSource::Software,
+        };
+        // Shares the error queue the send timestamps arrive on
+        let tc_error_queue = tc_socket.try_clone()?;
         let tc_socket = TimestampedUdpSocket::from_udp_socket(tc_socket, self.timestamping_mode)?;
         let ntc_socket = AsyncFd::new(ntc_socket)?;
 
         Ok(LinuxNetworkPort {
             tc_socket,
+            tc_error_queue,
             ntc_socket,
             tc_address,
             ntc_address,
+            own_address,
+            timestamp_source,
+            interface_name,
+            tx_timestamp_policy: self.tx_timestamp_policy,
+            tx_timestamps: TxTimestampStats::default(),
             clock: self.clock.clone(),
+            recv_buffers: Box::new([[0; RECV_BUFFER_SIZE]; BATCH_SIZE]),
+            outbox: Vec::new(),
         })
     }
 }
 
 pub struct LinuxNetworkPort {
     tc_socket: TimestampedUdpSocket,
+    tc_error_queue: std::net::UdpSocket,
     ntc_socket: AsyncFd<std::net::UdpSocket>,
     tc_address: SocketAddr,
     ntc_address: SocketAddr,
+    // The address of the interface, which our own packets come from
+    own_address: IpAddr,
+    timestamp_source: TimestampSource,
+    interface_name: String,
+    tx_timestamp_policy: TxTimestampPolicy,
+    tx_timestamps: TxTimestampStats,
     clock: LinuxClock,
+    recv_buffers: Box<[[u8; RECV_BUFFER_SIZE]; BATCH_SIZE]>,
+    // General messages waiting for the next flush
+    outbox: Vec<ArrayVec<u8, MAX_DATA_LEN>>,
+}
+
+/// A general message that could not be sent, see [`LinuxNetworkPort::flush`]
+#[derive(Debug)]
+pub struct FailedGeneralSend {
+    pub data: ArrayVec<u8, MAX_DATA_LEN>,
+    pub error: io::Error,
+}
+
+/// Whether the port can't send at all after this error, or sending may work
+/// when tried again
+pub fn send_error(error: &io::Error) -> SendError {
+    match error.raw_os_error() {
+        Some(libc::ENETDOWN | libc::ENODEV | libc::ENXIO | libc::EADDRNOTAVAIL) => SendError::Fatal,
+        _ => SendError::Transient,
+    }
 }
 
 fn libc_timestamp_to_instant(ts: LibcTimestamp) -> Time {
@@ -164,9 +236,29 @@ pub struct NetworkPacket {
     /// Required for packets from the event socket. Should not be present
     /// for packets from general socket.
     pub timestamp: Option<Time>,
+    /// How the packet reached the port. Where it was sent to is only known
+    /// for packets from the general socket, as the event socket doesn't
+    /// report it. Where it was sent from is known for both.
+    pub arrival: PacketArrival,
 }
 
 impl LinuxNetworkPort {
+    /// Where the timestamps of time critical packets are taken
+    pub fn timestamp_source(&self) -> TimestampSource {
+        self.timestamp_source
+    }
+
+    /// The name of the interface the port was opened on
+    pub fn interface_name(&self) -> &str {
+        &self.interface_name
+    }
+
+    /// How quickly the send timestamps of time critical packets became
+    /// available
+    pub fn tx_timestamp_stats(&self) -> &TxTimestampStats {
+        &self.tx_timestamps
+    }
+
     pub async fn send(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
         log::trace!("Send NTC");
 
@@ -182,12 +274,90 @@ impl LinuxNetworkPort {
     ) -> Result<Option<statime::Time>, std::io::Error> {
         log::trace!("Send TC");
 
+        let policy = self.tx_timestamp_policy;
+        let hardware = self.timestamp_source == TimestampSource::Hardware;
+        if policy.attempts > 0 {
+            // Timestamps of earlier packets that showed up after we stopped
+            // looking would otherwise be taken for the one of this packet
+            while let Ok(Some(_)) = read_error_queue(&self.tc_error_queue, hardware) {
+                log::debug!("Dropped stale send timestamp on {}", self.interface_name);
+            }
+        }
+
+        let sent = Instant::now();
         let opt_libc_ts = self.tc_socket.send(data, self.tc_address).await?;
+        if let Some(libc_ts) = opt_libc_ts {
+            self.tx_timestamps.record_on_time(sent.elapsed());
+            return Ok(Some(libc_timestamp_to_instant(libc_ts)));
+        }
 
-        Ok(opt_libc_ts.map(libc_timestamp_to_instant))
+        match poll_error_queue(&self.tc_error_queue, hardware, &policy).await {
+            Some(timestamp) => {
+                let latency = sent.elapsed();
+                self.tx_timestamps.record_late(latency);
+                log::debug!(
+                    "Send timestamp on {} arrived late, after {latency:?}",
+                    self.interface_name
+                );
+                Ok(Some(timestamp))
+            }
+            None => {
+                self.tx_timestamps.record_missed();
+                Ok(None)
+            }
+        }
     }
 
-    pub async fn recv(&mut self) -> Result<NetworkPacket, std::io::Error> {
+    /// Queue a general message, to be sent with the next [`flush`](Self::flush)
+    pub fn queue_general(&mut self, data: &[u8]) {
+        match ArrayVec::try_from(data) {
+            Ok(data) => self.outbox.push(data),
+            Err(_) => log::error!("Statime bug: general message too long"),
+        }
+    }
+
+    /// Send all queued general messages, batching them into as few system
+    /// calls as possible.
+    ///
+    /// Stops at the first message that could not be sent, which is taken out
+    /// of the queue and returned. The messages after it are sent by the next
+    /// call.
+    pub async fn flush(&mut self) -> Result<(), FailedGeneralSend> {
+        while !self.outbox.is_empty() {
+            let datagrams: Vec<&[u8]> = self.outbox.iter().map(|data| data.as_slice()).collect();
+            let address = self.ntc_address;
+            let result = self
+                .ntc_socket
+                .async_io(Interest::WRITABLE, |inner| {
+                    send_batch(inner, &datagrams, address)
+                })
+                .await;
+            drop(datagrams);
+
+            // sendmmsg only fails when not even the first datagram was sent
+            let sent = match result {
+                Ok(sent) => sent,
+                Err(error) => {
+                    let data = self.outbox.remove(0);
+                    return Err(FailedGeneralSend { data, error });
+                }
+            };
+            log::trace!("Send NTC batch of {sent}");
+
+            self.outbox.drain(..sent);
+        }
+
+        Ok(())
+    }
+
+    /// Wait for packets and append them to `packets`.
+    ///
+    /// Time critical packets are received one at a time, as they need to be
+    /// timestamped. General packets are received in batches.
+    pub async fn recv_batch(
+        &mut self,
+        packets: &mut Vec<NetworkPacket>,
+    ) -> Result<(), std::io::Error> {
         let time_critical_future = async {
             let mut buf = [0; MAX_DATA_LEN];
 
@@ -196,34 +366,73 @@ impl LinuxNetworkPort {
             let packet = NetworkPacket {
                 data: buf.into(),
                 timestamp: Some(libc_timestamp_to_instant(recv_result.timestamp)),
+                arrival: PacketArrival {
+                    source: Some(source_address(
+                        self.own_address,
+                        recv_result.peer_address.ip(),
+                    )),
+                    ..Default::default()
+                },
             };
 
             log::trace!("Recv TC");
 
-            Ok(packet)
+            Ok::<_, io::Error>(vec![packet])
         };
 
+        let own_address = self.own_address;
+        let recv_buffers = &mut self.recv_buffers;
         let non_time_critical_future = async {
-            let mut buffer = [0; 2048];
-            let (received_len, _) = self
+            let datagrams = self
                 .ntc_socket
-                .async_io(Interest::READABLE, |inner| inner.recv_from(&mut buffer))
+                .async_io(Interest::READABLE, |inner| recv_batch(inner, recv_buffers))
                 .await?;
-            log::trace!("Recv NTC");
-
-            let data_too_long = |_| io::Error::new(ErrorKind::InvalidData, "too long");
-            let data = buffer[..received_len].try_into().map_err(data_too_long)?;
+            log::trace!("Recv NTC batch of {}", datagrams.len());
+
+            let mut packets = Vec::with_capacity(datagrams.len());
+            for (buffer, datagram) in recv_buffers.iter().zip(datagrams) {
+                let Some(data) = datagram.and_then(|d| buffer[..d.length].try_into().ok()) else {
+                    log::warn!("Ignoring general packet that is too long");
+                    continue;
+                };
+                let destination = datagram.and_then(|datagram| datagram.destination);
+                let source = datagram.and_then(|datagram| datagram.source);
+                packets.push(NetworkPacket {
+                    data,
+                    timestamp: None,
+                    arrival: PacketArrival {
+                        destination: destination.map(|address| {
+                            if address.is_multicast() {
+                                Destination::Multicast
+                            } else {
+                                Destination::Unicast
+                            }
+                        }),
+                        source: source.map(|address| source_address(own_address, address.ip())),
+                    },
+                });
+            }
 
-            Ok(NetworkPacket {
-                data,
-                timestamp: None,
-            })
+            Ok(packets)
         };
 
-        tokio::select! {
-            packet = time_critical_future => { packet }
-            packet = non_time_critical_future => { packet }
-        }
+        let received = tokio::select! {
+            received = time_critical_future => { received }
+            received = non_time_critical_future => { received }
+        }?;
+        packets.extend(received);
+
+        Ok(())
+    }
+}
+
+// Whether a packet from `address` was sent by this host over the interface
+// with `own_address`, as our own multicast messages are
+fn source_address(own_address: IpAddr, address: IpAddr) -> SourceAddress {
+    if address == own_address {
+        SourceAddress::Own
+    } else {
+        SourceAddress::Other
     }
 }
 
@@ -243,12 +452,157 @@ pub fn get_clock_id() -> Option<[u8; 8]> {
     None
 }
 
+/// Hand a packet received on the network port of `port` to it, going by the
+/// message rather than the socket it came in on.
+///
+/// Returns `None` for packets the port doesn't handle, like our own multicast
+/// messages coming back and responses to other ports, and for event messages
+/// without a receive timestamp. The port reports invalid packets itself.
+pub fn receive_packet<'p, C: Clock, F: Filter, R: Rng>(
+    port: &'p mut Port<Running<'_, C, F>, R>,
+    packet: &NetworkPacket,
+    source: TimestampSource,
+) -> Option<PortActionIterator<'p>> {
+    let matched =
+        route_packet(&packet.data).map(|route| port.match_packet_via(&route, packet.arrival));
+    let event = match matched {
+        Ok(PacketMatch::Handle { event }) => event,
+        Ok(_) => return None,
+        Err(_) => packet.timestamp.is_some(),
+    };
+    match (event, packet.timestamp) {
+        (true, Some(timestamp)) => Some(port.handle_timecritical_receive_via(
+            &packet.data,
+            timestamp,
+            source,
+            packet.arrival,
+        )),
+        (true, None) => {
+            log::warn!("Ignoring event message without a receive timestamp");
+            None
+        }
+        (false, _) => Some(port.handle_general_receive_via(&packet.data, packet.arrival)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use rand::rngs::mock::StepRng;
+    use statime::{
+        BasicFilter, ClockIdentity, CommunicationMode, DelayMechanism, Duration, InstanceConfig,
+        Interval, LogMessageIntervals, PortAction, PortConfig, PtpInstance, SdoId,
+        TimePropertiesDS, TransmitEnable,
+    };
     use timestamped_socket::interface::LinuxNetworkMode;
 
     use super::*;
 
+    struct TestClock;
+
+    impl Clock for TestClock {
+        type Error = std::convert::Infallible;
+
+        fn now(&self) -> Time {
+            Time::from_secs(10)
+        }
+
+        fn adjust(
+            &mut self,
+            _time_offset: Duration,
+            _frequency_multiplier: f64,
+            _time_properties_ds: &TimePropertiesDS,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn receive_cloned_announce() {
+        let instance = PtpInstance::new(
+            InstanceConfig {
+                clock_identity: ClockIdentity([1; 8]),
+                priority_1: 128,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            },
+            TimePropertiesDS::default(),
+            TestClock,
+            BasicFilter::new(0.25),
+        );
+        let config = PortConfig {
+            delay_mechanism: DelayMechanism::E2E {
+                interval: Interval::ONE_SECOND,
+            },
+            announce_interval: Interval::ONE_SECOND,
+            announce_receipt_timeout: 3,
+            sync_interval: Interval::ONE_SECOND,
+            master_only: false,
+            delay_asymmetry: Duration::ZERO,
+            ingress_latency: Duration::ZERO,
+            egress_latency: Duration::ZERO,
+            management: Default::default(),
+            communication_mode: CommunicationMode::Multicast,
+            transmit: TransmitEnable::ALL,
+            unicast_client: None,
+            log_message_intervals: LogMessageIntervals::STANDARD,
+        };
+        let (mut port, _) = instance.add_port(config, StepRng::new(2, 1)).end_bmca();
+        drop(port.handle_announce_receipt_timer());
+
+        let mut announce = None;
+        for action in port.handle_announce_timer() {
+            if let PortAction::SendGeneral { data } = action {
+                announce = Some(ArrayVec::try_from(data).unwrap());
+            }
+        }
+        let mut packet = NetworkPacket {
+            data: announce.expect("No announce message sent"),
+            timestamp: None,
+            arrival: PacketArrival::default(),
+        };
+
+        // Our own announce message coming back is no collision
+        assert!(receive_packet(&mut port, &packet, TimestampSource::Software).is_some());
+        assert_eq!(port.statistics().identity_collisions, 0);
+
+        // But one from another clock with our identity and port number is
+        packet.data[30..32].copy_from_slice(&100u16.to_be_bytes());
+        assert!(receive_packet(&mut port, &packet, TimestampSource::Software).is_some());
+        assert_eq!(port.statistics().identity_collisions, 1);
+
+        // Other messages of that clock are told apart from ours by where they
+        // come from
+        let mut sync = NetworkPacket {
+            timestamp: Some(Time::from_secs(1)),
+            ..packet
+        };
+        sync.data[0] = 0;
+        sync.arrival.source = Some(source_address(
+            [192, 168, 0, 1].into(),
+            [192, 168, 0, 1].into(),
+        ));
+        assert!(receive_packet(&mut port, &sync, TimestampSource::Software).is_none());
+        assert_eq!(port.statistics().identity_collisions, 1);
+
+        sync.arrival.source = Some(source_address(
+            [192, 168, 0, 1].into(),
+            [192, 168, 0, 2].into(),
+        ));
+        assert!(receive_packet(&mut port, &sync, TimestampSource::Software).is_some());
+        assert_eq!(port.statistics().identity_collisions, 2);
+    }
+
+    #[test]
+    fn classify_send_errors() {
+        let fatal = io::Error::from_raw_os_error(libc::ENETDOWN);
+        assert_eq!(send_error(&fatal), SendError::Fatal);
+
+        let transient = io::Error::from_raw_os_error(libc::ENOBUFS);
+        assert_eq!(send_error(&transient), SendError::Transient);
+    }
+
     #[tokio::test]
     async fn port_setup_ipv4() -> Result<(), Box<dyn std::error::Error>> {
         let port = 9000;
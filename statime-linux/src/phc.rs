@@ -0,0 +1,155 @@
+//! Discovery of the PTP hardware clock (PHC) of a network interface
+//!
+//! Hardware timestamps are taken by the clock of the network card that
+//! receives or sends a packet. Steering any other clock based on them makes
+//! the measurements meaningless, so the configured clock must be the PHC of
+//! the interface.
+
+use std::{
+    io,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+const ETHTOOL_GET_TS_INFO: u32 = 0x41;
+const SIOCETHTOOL: libc::c_ulong = 0x8946;
+
+/// `struct ethtool_ts_info` from `linux/ethtool.h`
+#[repr(C)]
+#[derive(Default)]
+struct EthtoolTsInfo {
+    cmd: u32,
+    so_timestamping: u32,
+    phc_index: i32,
+    tx_types: u32,
+    tx_reserved: [u32; 3],
+    rx_filters: u32,
+    rx_reserved: [u32; 3],
+}
+
+/// `struct ifreq` from `linux/if.h`, with only the data pointer of the union
+#[repr(C)]
+struct IfreqData {
+    name: [u8; libc::IFNAMSIZ],
+    data: *mut libc::c_void,
+    // The union is as large as a struct ifmap
+    _padding: [u8; 24 - std::mem::size_of::<*mut libc::c_void>()],
+}
+
+/// The index of the PHC of `interface`, the `N` in `/dev/ptpN`, or `None` when
+/// the interface has no hardware clock.
+///
+/// Asks the driver with `ETHTOOL_GET_TS_INFO`, and falls back to
+/// `/sys/class/net/<interface>/device/ptp` for drivers that do not support it.
+pub fn interface_phc_index(interface: &str) -> io::Result<Option<u32>> {
+    match ethtool_phc_index(interface) {
+        Ok(index) => Ok(index),
+        Err(error) => match sysfs_phc_index(interface) {
+            Ok(Some(index)) => Ok(Some(index)),
+            // The ioctl is the better explanation of why nothing was found
+            _ => Err(error),
+        },
+    }
+}
+
+fn ethtool_phc_index(interface: &str) -> io::Result<Option<u32>> {
+    let mut name = [0; libc::IFNAMSIZ];
+    // The name must be nul terminated
+    if interface.len() >= name.len() {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    name[..interface.len()].copy_from_slice(interface.as_bytes());
+
+    let mut info = EthtoolTsInfo {
+        cmd: ETHTOOL_GET_TS_INFO,
+        ..Default::default()
+    };
+    let mut request = IfreqData {
+        name,
+        data: &mut info as *mut EthtoolTsInfo as *mut libc::c_void,
+        _padding: Default::default(),
+    };
+
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+    // SAFETY: the request and the info it points to outlive the call, and
+    // have the layout the kernel expects for this ioctl.
+    let result = unsafe {
+        libc::ioctl(
+            socket.as_raw_fd(),
+            SIOCETHTOOL as _,
+            &mut request as *mut IfreqData,
+        )
+    };
+
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(u32::try_from(info.phc_index).ok())
+}
+
+fn sysfs_phc_index(interface: &str) -> io::Result<Option<u32>> {
+    let directory = Path::new("/sys/class/net")
+        .join(interface)
+        .join("device/ptp");
+
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    for entry in entries {
+        if let Some(index) = entry?.file_name().to_str().and_then(parse_ptp_name) {
+            return Ok(Some(index));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The index of the PHC at `path`, or `None` when it is not a `/dev/ptpN`
+/// device. Symbolic links, such as those udev creates, are followed.
+pub fn clock_phc_index(path: &Path) -> io::Result<Option<u32>> {
+    let path = std::fs::canonicalize(path)?;
+    Ok(path
+        .file_name()
+        .and_then(|name| parse_ptp_name(name.to_str()?)))
+}
+
+/// The device path of the PHC with index `index`
+pub fn phc_path(index: u32) -> PathBuf {
+    PathBuf::from(format!("/dev/ptp{index}"))
+}
+
+fn parse_ptp_name(name: &str) -> Option<u32> {
+    let index = name.strip_prefix("ptp")?;
+    // Reject what u32::from_str accepts but the kernel never names a clock
+    if index.starts_with('+') {
+        return None;
+    }
+    index.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ptp_names() {
+        assert_eq!(parse_ptp_name("ptp0"), Some(0));
+        assert_eq!(parse_ptp_name("ptp12"), Some(12));
+        assert_eq!(parse_ptp_name("ptp"), None);
+        assert_eq!(parse_ptp_name("ptp+1"), None);
+        assert_eq!(parse_ptp_name("ptp_hyperv"), None);
+        assert_eq!(parse_ptp_name("rtc0"), None);
+
+        assert_eq!(phc_path(3), Path::new("/dev/ptp3"));
+    }
+
+    #[test]
+    fn loopback_has_no_phc() {
+        assert_eq!(sysfs_phc_index("lo").unwrap(), None);
+        assert!(ethtool_phc_index("an-interface-name-that-is-too-long").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+//! Parsing of the interoperability workarounds given on the command line.
+//!
+//! A rule is written as `<selector>=<quirks>`, where the selector is `any`,
+//! `oui:<6 hex digits>` for the vendor of the grandmaster, or `sdo:<id>` for
+//! a profile. The quirks are a comma separated list of workaround names, or
+//! `none` to disable all of them, for example
+//! `oui:001b19=delay-resp-sequence-id,follow-up-before-sync`.
+
+use statime::{QuirkMatch, QuirkRule, Quirks, SdoId};
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum QuirkRuleError {
+    #[error("Expected <selector>=<quirks>, got {0:?}")]
+    MissingQuirks(String),
+    #[error("Invalid selector {0:?}, expected any, oui:<6 hex digits> or sdo:<id>")]
+    InvalidSelector(String),
+    #[error("Unknown quirk {0:?}, expected delay-resp-sequence-id, follow-up-before-sync or none")]
+    UnknownQuirk(String),
+}
+
+/// Parse a single rule, see the module documentation for the syntax
+pub fn parse_quirk_rule(s: &str) -> Result<QuirkRule, QuirkRuleError> {
+    let (selector, names) = s
+        .split_once('=')
+        .ok_or_else(|| QuirkRuleError::MissingQuirks(s.to_string()))?;
+
+    let invalid_selector = || QuirkRuleError::InvalidSelector(selector.to_string());
+    let matches = match selector.split_once(':') {
+        None if selector == "any" => QuirkMatch::Any,
+        Some(("oui", oui)) if oui.len() == 6 && oui.chars().all(|c| c.is_ascii_hexdigit()) => {
+            let oui = u32::from_str_radix(oui, 16).map_err(|_| invalid_selector())?;
+            let [_, a, b, c] = oui.to_be_bytes();
+            QuirkMatch::Oui([a, b, c])
+        }
+        Some(("sdo", id)) => {
+            let id = id.parse().ok().and_then(SdoId::new);
+            QuirkMatch::SdoId(id.ok_or_else(invalid_selector)?)
+        }
+        _ => return Err(invalid_selector()),
+    };
+
+    let mut quirks = Quirks::NONE;
+    for name in names.split(',').map(str::trim) {
+        match name {
+            "none" => {}
+            "delay-resp-sequence-id" => quirks.delay_resp_sequence_id = true,
+            "follow-up-before-sync" => quirks.follow_up_before_sync = true,
+            _ => return Err(QuirkRuleError::UnknownQuirk(name.to_string())),
+        }
+    }
+
+    Ok(QuirkRule { matches, quirks })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!(
+            parse_quirk_rule("oui:001B19=delay-resp-sequence-id,follow-up-before-sync"),
+            Ok(QuirkRule {
+                matches: QuirkMatch::Oui([0x00, 0x1b, 0x19]),
+                quirks: Quirks {
+                    delay_resp_sequence_id: true,
+                    follow_up_before_sync: true,
+                },
+            })
+        );
+        assert_eq!(
+            parse_quirk_rule("sdo:256=none"),
+            Ok(QuirkRule {
+                matches: QuirkMatch::SdoId(SdoId::new(256).unwrap()),
+                quirks: Quirks::NONE,
+            })
+        );
+        assert_eq!(
+            parse_quirk_rule("any=follow-up-before-sync"),
+            Ok(QuirkRule {
+                matches: QuirkMatch::Any,
+                quirks: Quirks::DEFAULT,
+            })
+        );
+
+        assert!(matches!(
+            parse_quirk_rule("any"),
+            Err(QuirkRuleError::MissingQuirks(_))
+        ));
+        assert!(matches!(
+            parse_quirk_rule("oui:1b19=none"),
+            Err(QuirkRuleError::InvalidSelector(_))
+        ));
+        assert!(matches!(
+            parse_quirk_rule("sdo:5000=none"),
+            Err(QuirkRuleError::InvalidSelector(_))
+        ));
+        assert!(matches!(
+            parse_quirk_rule("any=sync-before-follow-up"),
+            Err(QuirkRuleError::UnknownQuirk(_))
+        ));
+    }
+}
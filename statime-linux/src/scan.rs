@@ -0,0 +1,187 @@
+//! Listening to the PTP traffic on an interface without taking part in it, to
+//! find out how to configure a new node, see [`NetworkScanner`].
+
+use std::{fmt, net::IpAddr};
+
+use statime::{Clock, InferredProfile, MessageRate, NetworkScanner, Transport};
+use timestamped_socket::{interface::InterfaceDescriptor, raw_udp_socket::TimestampingMode};
+
+use crate::{
+    clock::LinuxClock,
+    network::{LinuxRuntime, NetworkError},
+};
+
+/// Listen on the PTP ports of `interface` for `duration`, and collect what
+/// was received. Nothing is sent.
+pub async fn scan(
+    interface: InterfaceDescriptor,
+    duration: std::time::Duration,
+) -> Result<NetworkScanner, NetworkError> {
+    let transport = match interface.get_address()? {
+        IpAddr::V4(_) => Transport::UdpIpv4,
+        IpAddr::V6(_) => Transport::UdpIpv6,
+    };
+
+    let clock = LinuxClock::realtime();
+    let mut runtime = LinuxRuntime::new(TimestampingMode::Software, clock.clone());
+    let mut port = runtime.open(interface).await?;
+
+    let mut scanner = NetworkScanner::new();
+    let mut packets = Vec::new();
+    let deadline = tokio::time::Instant::now() + duration;
+    while let Ok(received) = tokio::time::timeout_at(deadline, port.recv_batch(&mut packets)).await
+    {
+        received?;
+        for packet in packets.drain(..) {
+            if let Err(error) = scanner.observe(&packet.data, transport, clock.now()) {
+                log::debug!("Ignoring packet: {error}");
+            }
+        }
+    }
+
+    Ok(scanner)
+}
+
+/// A human readable summary of a scan
+pub struct ScanReport<'a>(pub &'a NetworkScanner);
+
+impl fmt::Display for ScanReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scanner = self.0;
+        if scanner.domains().next().is_none() {
+            return writeln!(f, "No PTP traffic received");
+        }
+
+        for domain in scanner.domains() {
+            let transports: Vec<_> = domain
+                .transports()
+                .iter()
+                .map(|transport| match transport {
+                    Transport::UdpIpv4 => "UDP/IPv4",
+                    Transport::UdpIpv6 => "UDP/IPv6",
+                    Transport::Ethernet => "ethernet",
+                })
+                .collect();
+            let profile = match domain.profile() {
+                Some(InferredProfile::Default) => "the default profile (--profile default)",
+                Some(InferredProfile::Smpte2059) => "SMPTE ST 2059-2 (--profile smpte-2059)",
+                Some(InferredProfile::Ieee8021As) => "IEEE 802.1AS, which is not supported",
+                None => "an unknown profile",
+            };
+            writeln!(
+                f,
+                "Domain {} (sdo id {:#05x}) over {}: {} messages, likely {}",
+                domain.domain_number,
+                domain.sdo_id,
+                transports.join(", "),
+                domain.messages,
+                profile
+            )?;
+
+            let interval = |interval: Option<i8>| match interval {
+                Some(interval) => format!("2^{interval}s"),
+                None => "unknown".to_owned(),
+            };
+            let mut modes = Vec::new();
+            if domain.multicast {
+                modes.push("multicast");
+            }
+            if domain.unicast {
+                modes.push("unicast");
+            }
+            modes.push(if domain.two_step {
+                "two-step"
+            } else {
+                "one-step"
+            });
+            if domain.delay_req {
+                modes.push("delay request-response");
+            }
+            if domain.peer_delay {
+                modes.push("peer delay");
+            }
+            writeln!(
+                f,
+                "  announce interval {}, sync interval {}, delay request interval {}, {}",
+                interval(domain.announce_interval),
+                interval(domain.sync_interval),
+                interval(domain.min_delay_req_interval),
+                modes.join(", ")
+            )?;
+            if !domain.tlv_types().is_empty() {
+                writeln!(f, "  TLVs: {:?}", domain.tlv_types())?;
+            }
+
+            for master in scanner.masters().filter(|master| {
+                master.domain_number == domain.domain_number && master.sdo_id == domain.sdo_id
+            }) {
+                let rate = |rate: MessageRate| match rate.per_second() {
+                    Some(per_second) => format!("{per_second:.2}/s"),
+                    None => "unknown".to_owned(),
+                };
+                let announce = master.announce;
+                writeln!(
+                    f,
+                    "  Master {} port {}: grandmaster {}, priority {}/{}, class {}, {:?}, {} \
+                     steps removed, announce rate {}, sync rate {}",
+                    master.clock_identity,
+                    master.port_number,
+                    announce.grandmaster_identity,
+                    announce.grandmaster_priority_1,
+                    announce.grandmaster_priority_2,
+                    u8::from(announce.grandmaster_clock_quality.clock_class),
+                    announce.time_source,
+                    announce.steps_removed,
+                    rate(master.announce_rate),
+                    rate(master.sync_rate)
+                )?;
+            }
+        }
+
+        if scanner.untracked() > 0 {
+            writeln!(
+                f,
+                "{} messages of further domains or masters were left out",
+                scanner.untracked()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use statime::Time;
+
+    use super::*;
+
+    #[test]
+    fn scan_report() {
+        let mut scanner = NetworkScanner::new();
+        assert_eq!(
+            ScanReport(&scanner).to_string(),
+            "No PTP traffic received\n"
+        );
+
+        // A two-step sync message in domain 24
+        let mut sync = [0u8; 44];
+        sync[1] = 0x02;
+        sync[2..4].copy_from_slice(&44u16.to_be_bytes());
+        sync[4] = 24;
+        sync[6] = 0x02;
+        sync[20..28].copy_from_slice(&[1; 8]);
+        sync[29] = 1;
+        sync[33] = 0xfd;
+        scanner
+            .observe(&sync, Transport::UdpIpv6, Time::from_secs(0))
+            .unwrap();
+
+        assert_eq!(
+            ScanReport(&scanner).to_string(),
+            "Domain 24 (sdo id 0x000) over UDP/IPv6: 1 messages, likely SMPTE ST 2059-2 \
+             (--profile smpte-2059)\n  announce interval unknown, sync interval 2^-3s, delay \
+             request interval unknown, multicast, two-step\n"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Real-time scheduling and CPU pinning for the threads handling the event
+//! path.
+//!
+//! With software timestamping, the time between a packet being timestamped
+//! and it being processed directly adds to the jitter of the measurements.
+//! Running the runtime threads with a real-time priority on dedicated CPUs
+//! keeps that latency low on a loaded host.
+
+use std::str::FromStr;
+
+/// Highest CPU number that can be pinned to
+const MAX_CPU: usize = libc::CPU_SETSIZE as usize - 1;
+
+/// A set of CPUs, parsed from a list like `0,2-3`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuList(Vec<usize>);
+
+impl CpuList {
+    pub fn cpus(&self) -> &[usize] {
+        &self.0
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum CpuListError {
+    #[error("Invalid CPU number {0:?}")]
+    InvalidNumber(String),
+    #[error("CPU {0} is larger than the maximum of {MAX_CPU}")]
+    TooLarge(usize),
+    #[error("Invalid CPU range {0}-{1}")]
+    InvalidRange(usize, usize),
+    #[error("The CPU list is empty")]
+    Empty,
+}
+
+impl FromStr for CpuList {
+    type Err = CpuListError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |cpu: &str| {
+            let cpu: usize = cpu
+                .trim()
+                .parse()
+                .map_err(|_| CpuListError::InvalidNumber(cpu.to_string()))?;
+            if cpu > MAX_CPU {
+                return Err(CpuListError::TooLarge(cpu));
+            }
+            Ok(cpu)
+        };
+
+        let mut cpus = Vec::new();
+        for part in s.split(',').filter(|part| !part.trim().is_empty()) {
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (parse(first)?, parse(last)?);
+                    if first > last {
+                        return Err(CpuListError::InvalidRange(first, last));
+                    }
+                    cpus.extend(first..=last);
+                }
+                None => cpus.push(parse(part)?),
+            }
+        }
+
+        if cpus.is_empty() {
+            return Err(CpuListError::Empty);
+        }
+
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(CpuList(cpus))
+    }
+}
+
+/// Scheduling settings for a thread
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreadScheduling {
+    /// `SCHED_FIFO` priority, from 1 to 99. The default scheduler is kept when
+    /// this is `None`.
+    pub fifo_priority: Option<i32>,
+    /// CPUs the thread may run on. All CPUs are allowed when this is `None`.
+    pub cpus: Option<CpuList>,
+}
+
+impl ThreadScheduling {
+    /// Whether applying these settings changes anything
+    pub fn is_default(&self) -> bool {
+        self.fifo_priority.is_none() && self.cpus.is_none()
+    }
+
+    /// Apply the settings to the calling thread
+    pub fn apply_to_current_thread(&self) -> std::io::Result<()> {
+        if let Some(cpus) = &self.cpus {
+            // SAFETY: cpu_set_t is a plain bitmask, for which all zeroes is a
+            // valid (empty) value. All CPU numbers were checked to be below
+            // CPU_SETSIZE when parsing.
+            let result = unsafe {
+                let mut set: libc::cpu_set_t = std::mem::zeroed();
+                for &cpu in cpus.cpus() {
+                    libc::CPU_SET(cpu, &mut set);
+                }
+                // pid 0 is the calling thread
+                libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+            };
+            if result != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        if let Some(priority) = self.fifo_priority {
+            let param = libc::sched_param {
+                sched_priority: priority,
+            };
+            // SAFETY: pthread_self is always a valid thread, and param
+            // outlives the call.
+            let result = unsafe {
+                libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param)
+            };
+            if result != 0 {
+                return Err(std::io::Error::from_raw_os_error(result));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cpu_list() {
+        assert_eq!("3".parse(), Ok(CpuList(vec![3])));
+        assert_eq!("0,2-4".parse(), Ok(CpuList(vec![0, 2, 3, 4])));
+        assert_eq!("5, 1,1-2".parse(), Ok(CpuList(vec![1, 2, 5])));
+
+        assert_eq!(
+            "a".parse::<CpuList>(),
+            Err(CpuListError::InvalidNumber("a".into()))
+        );
+        assert_eq!(
+            "4-2".parse::<CpuList>(),
+            Err(CpuListError::InvalidRange(4, 2))
+        );
+        assert_eq!(
+            "100000".parse::<CpuList>(),
+            Err(CpuListError::TooLarge(100000))
+        );
+        assert_eq!("".parse::<CpuList>(), Err(CpuListError::Empty));
+    }
+
+    #[test]
+    fn default_scheduling_is_noop() {
+        let scheduling = ThreadScheduling::default();
+        assert!(scheduling.is_default());
+        scheduling.apply_to_current_thread().unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+//! Checking that an interface and its clock work for PTP before joining a
+//! live domain, see [`self_test`].
+
+use std::fmt;
+
+use statime::{Clock, Duration};
+use timestamped_socket::{interface::InterfaceDescriptor, raw_udp_socket::TimestampingMode};
+
+use crate::{
+    clock::LinuxClock,
+    network::{LinuxRuntime, NetworkPacket},
+    phc::{interface_phc_index, phc_path},
+};
+
+/// How far in seconds a timestamp may be from the clock it was taken with
+const PLAUSIBLE_OFFSET: i64 = 1;
+/// How long to wait for the looped message
+const LOOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How a single check of a [`SelfTestReport`] turned out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    /// Something that limits the accuracy, or that could not be checked
+    Warning,
+    /// Something that keeps statime from working
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+    pub detail: String,
+}
+
+/// The outcome of [`self_test`], in the order the checks ran
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    pub checks: Vec<Check>,
+}
+
+impl SelfTestReport {
+    /// Whether no check failed
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.outcome != Outcome::Fail)
+    }
+
+    fn push(&mut self, name: &'static str, outcome: Outcome, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            outcome,
+            detail: detail.into(),
+        });
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let outcome = match check.outcome {
+                Outcome::Pass => "ok",
+                Outcome::Warning => "warning",
+                Outcome::Fail => "FAILED",
+            };
+            writeln!(f, "{:<16} {:<8} {}", check.name, outcome, check.detail)?;
+        }
+
+        let failed = self
+            .checks
+            .iter()
+            .filter(|check| check.outcome == Outcome::Fail)
+            .count();
+        match failed {
+            0 => writeln!(f, "All checks passed"),
+            1 => writeln!(f, "1 check failed"),
+            failed => writeln!(f, "{failed} checks failed"),
+        }
+    }
+}
+
+/// Check the clock statime would use with `interface` and the timestamps of
+/// the messages it sends and receives on it.
+///
+/// The hardware clock of the interface is read and adjusted by zero, which
+/// needs the same permissions as steering it. Then a single event message is
+/// sent, which the kernel loops back, to check the send and receive
+/// timestamps. It is a sync message in domain 255, which is reserved, so PTP
+/// instances on the network ignore it.
+pub async fn self_test(interface: InterfaceDescriptor) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+
+    let (clock, timestamping_mode) = check_clock(&interface, &mut report);
+    check_adjustment(&clock, &mut report);
+    check_timestamping(interface, timestamping_mode, clock, &mut report).await;
+
+    report
+}
+
+// The hardware clock of the interface, or the system clock when it has none
+fn check_clock(
+    interface: &InterfaceDescriptor,
+    report: &mut SelfTestReport,
+) -> (LinuxClock, TimestampingMode) {
+    let software = (LinuxClock::realtime(), TimestampingMode::Software);
+    let Some(interface_name) = interface.interface_name else {
+        report.push(
+            "hardware clock",
+            Outcome::Warning,
+            "--interface is an address, so only software timestamping is checked",
+        );
+        return software;
+    };
+
+    let name = interface_name.to_string();
+    let path = match interface_phc_index(&name) {
+        Ok(Some(index)) => phc_path(index),
+        Ok(None) => {
+            report.push(
+                "hardware clock",
+                Outcome::Warning,
+                format!("{name} has no hardware clock, only software timestamping is available"),
+            );
+            return software;
+        }
+        Err(error) => {
+            report.push(
+                "hardware clock",
+                Outcome::Fail,
+                format!("could not find the hardware clock of {name}: {error}"),
+            );
+            return software;
+        }
+    };
+
+    match LinuxClock::open(&path).and_then(|clock| Ok((clock.timespec()?, clock))) {
+        Ok((now, clock)) => {
+            report.push(
+                "hardware clock",
+                Outcome::Pass,
+                format!("{} reads {}.{:09}", path.display(), now.tv_sec, now.tv_nsec),
+            );
+            (clock, TimestampingMode::Hardware(interface_name))
+        }
+        Err(error) => {
+            report.push(
+                "hardware clock",
+                Outcome::Fail,
+                format!("could not read {}: {}", path.display(), error),
+            );
+            software
+        }
+    }
+}
+
+// Adjusting by zero is allowed exactly when steering the clock is
+fn check_adjustment(clock: &LinuxClock, report: &mut SelfTestReport) {
+    match clock_steering::Clock::step_clock(clock, std::time::Duration::ZERO) {
+        Ok(_) => report.push("clock adjustment", Outcome::Pass, "allowed"),
+        Err(error) => report.push(
+            "clock adjustment",
+            Outcome::Fail,
+            format!("not allowed to adjust the clock: {error}"),
+        ),
+    }
+}
+
+async fn check_timestamping(
+    interface: InterfaceDescriptor,
+    timestamping_mode: TimestampingMode,
+    clock: LinuxClock,
+    report: &mut SelfTestReport,
+) {
+    let hardware = matches!(timestamping_mode, TimestampingMode::Hardware(_));
+    let mut runtime = LinuxRuntime::new(timestamping_mode, clock.clone());
+    let mut port = match runtime.open(interface).await {
+        Ok(port) => port,
+        Err(error) => {
+            report.push(
+                "sockets",
+                Outcome::Fail,
+                format!("could not open the PTP ports: {error}"),
+            );
+            return;
+        }
+    };
+    let mode = if hardware { "hardware" } else { "software" };
+    report.push(
+        "sockets",
+        Outcome::Pass,
+        format!("opened the PTP ports with {mode} timestamping"),
+    );
+
+    let probe = probe_message(std::process::id() as u16);
+    let send_time = match port.send_time_critical(&probe).await {
+        Ok(Some(send_time)) => send_time,
+        Ok(None) => {
+            report.push(
+                "send timestamp",
+                Outcome::Fail,
+                "no timestamp was delivered for the sent message",
+            );
+            return;
+        }
+        Err(error) => {
+            report.push(
+                "send timestamp",
+                Outcome::Fail,
+                format!("could not send: {error}"),
+            );
+            return;
+        }
+    };
+    let offset = clock.now() - send_time;
+    if offset.abs() > Duration::from_secs(PLAUSIBLE_OFFSET) {
+        report.push(
+            "send timestamp",
+            Outcome::Fail,
+            format!("{offset} before the time of the clock, which is not plausible"),
+        );
+    } else {
+        report.push("send timestamp", Outcome::Pass, "delivered");
+    }
+
+    let looped = tokio::time::timeout(LOOP_TIMEOUT, async {
+        let mut packets = Vec::new();
+        loop {
+            port.recv_batch(&mut packets).await?;
+            if let Some(packet) = packets
+                .drain(..)
+                .find(|packet: &NetworkPacket| packet.data.starts_with(&probe))
+            {
+                return Ok::<_, std::io::Error>(packet);
+            }
+        }
+    })
+    .await;
+
+    let packet = match looped {
+        Ok(Ok(packet)) => packet,
+        Ok(Err(error)) => {
+            report.push(
+                "receive timestamp",
+                Outcome::Fail,
+                format!("could not receive: {error}"),
+            );
+            return;
+        }
+        Err(_) => {
+            report.push(
+                "receive timestamp",
+                Outcome::Fail,
+                "the sent message was not looped back, is multicast loopback disabled?",
+            );
+            return;
+        }
+    };
+
+    match packet.timestamp {
+        Some(receive_time) => {
+            let delay = receive_time - send_time;
+            if delay.abs() > Duration::from_secs(PLAUSIBLE_OFFSET) {
+                report.push(
+                    "receive timestamp",
+                    Outcome::Fail,
+                    format!("{delay} after the send timestamp, which is not plausible"),
+                );
+            } else {
+                report.push(
+                    "receive timestamp",
+                    Outcome::Pass,
+                    format!("delivered, {delay} after the send timestamp"),
+                );
+            }
+        }
+        // Looped messages don't pass the network interface
+        None if hardware => report.push(
+            "receive timestamp",
+            Outcome::Warning,
+            "the looped message has no hardware timestamp, check with live traffic",
+        ),
+        None => report.push(
+            "receive timestamp",
+            Outcome::Fail,
+            "no timestamp was delivered for the looped message",
+        ),
+    }
+}
+
+// A sync message in the reserved domain 255
+fn probe_message(sequence_id: u16) -> [u8; 44] {
+    let mut message = [0; 44];
+    // Version 2.1
+    message[1] = 0x12;
+    message[2..4].copy_from_slice(&44u16.to_be_bytes());
+    message[4] = 255;
+    message[20..28].copy_from_slice(b"selftest");
+    message[30..32].copy_from_slice(&sequence_id.to_be_bytes());
+    // Not sent periodically
+    message[33] = 0x7f;
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use statime::MessageType;
+
+    use super::*;
+
+    #[test]
+    fn self_test_report() {
+        let mut report = SelfTestReport::default();
+        report.push("hardware clock", Outcome::Warning, "no hardware clock");
+        report.push("clock adjustment", Outcome::Pass, "allowed");
+        assert!(report.passed());
+        assert_eq!(
+            report.to_string(),
+            "hardware clock   warning  no hardware clock\nclock adjustment ok       allowed\nAll \
+             checks passed\n"
+        );
+
+        report.push("sockets", Outcome::Fail, "no permission");
+        assert!(!report.passed());
+        assert!(report.to_string().ends_with("1 check failed\n"));
+    }
+
+    #[test]
+    fn probe_is_ignored_sync() {
+        let probe = probe_message(7);
+        let message = statime::decode_message(&probe).unwrap();
+        assert_eq!(message.message_type, MessageType::Sync);
+        assert_eq!(message.domain_number, 255);
+        assert_eq!(message.sequence_id, 7);
+    }
+}
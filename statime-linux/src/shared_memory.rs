@@ -0,0 +1,173 @@
+//! Files mapped into memory, to share data with other processes without
+//! system calls, see the `statistics_segment` and `time_transfer` modules.
+
+use std::{
+    fs::OpenOptions,
+    io,
+    os::fd::AsRawFd,
+    path::Path,
+    ptr::NonNull,
+    sync::atomic::{fence, AtomicU64, Ordering},
+};
+
+/// A file mapped into memory, unmapped when dropped
+#[derive(Debug)]
+pub(crate) struct MappedFile {
+    memory: NonNull<u8>,
+    size: usize,
+}
+
+// SAFETY: MappedFile only hands out its memory as a pointer, and the users
+// only access it through atomics
+unsafe impl Send for MappedFile {}
+// SAFETY: see Send
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Map the first `size` bytes of `path` writable, creating the file when
+    /// it doesn't exist and growing it with zeroes when it is smaller
+    pub(crate) fn create(path: &Path, size: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() < size as u64 {
+            file.set_len(size as u64)?;
+        }
+
+        Self::map(&file, size, libc::PROT_READ | libc::PROT_WRITE)
+    }
+
+    /// Map the first `size` bytes of the existing file `path`, which fails
+    /// when the file is smaller. The mapping is only writable when `writable`
+    /// is set.
+    pub(crate) fn open(path: &Path, size: usize, writable: bool) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(writable).open(path)?;
+        if file.metadata()?.len() < size as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("file smaller than the {size} bytes expected"),
+            ));
+        }
+
+        let protection = if writable {
+            libc::PROT_READ | libc::PROT_WRITE
+        } else {
+            libc::PROT_READ
+        };
+        Self::map(&file, size, protection)
+    }
+
+    fn map(file: &std::fs::File, size: usize, protection: libc::c_int) -> io::Result<Self> {
+        // SAFETY: the file is at least size bytes long, and the mapping stays
+        // valid after the file is closed
+        let memory = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                protection,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if memory == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            memory: NonNull::new(memory.cast()).ok_or(io::ErrorKind::InvalidData)?,
+            size,
+        })
+    }
+
+    /// The start of the mapping. It is page aligned, and valid for as long
+    /// as self lives.
+    pub(crate) fn as_ptr<T>(&self) -> NonNull<T> {
+        self.memory.cast()
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created in map with this size, and no
+        // references to it outlive self
+        unsafe { libc::munmap(self.memory.as_ptr().cast(), self.size) };
+    }
+}
+
+/// Why words guarded by a sequence number couldn't be read, see
+/// [`seqlock_read`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SeqLockReadError {
+    /// The sequence number is zero, so nothing was written yet
+    NotPublished,
+    /// The words kept changing while reading them
+    Busy,
+}
+
+/// Attempts at reading consistent words before giving up
+const MAX_READ_ATTEMPTS: usize = 16;
+
+/// Replace `words` by `values`. The sequence number is odd while writing, and
+/// even but never zero afterwards. Only a single writer may write the words.
+pub(crate) fn seqlock_write(
+    sequence: &AtomicU64,
+    words: &[AtomicU64],
+    values: impl IntoIterator<Item = u64>,
+) {
+    let current = sequence.load(Ordering::Relaxed);
+    sequence.store(current.wrapping_add(1) | 1, Ordering::Relaxed);
+    fence(Ordering::Release);
+
+    for (word, value) in words.iter().zip(values) {
+        word.store(value, Ordering::Relaxed);
+    }
+
+    // Skip zero on wrapping around, as that means nothing was published
+    let next = current.wrapping_add(2) & !1;
+    sequence.store(if next == 0 { 2 } else { next }, Ordering::Release);
+}
+
+/// Zero `words` and the sequence number, so that readers see nothing was
+/// written
+pub(crate) fn seqlock_clear(sequence: &AtomicU64, words: &[AtomicU64]) {
+    sequence.store(1, Ordering::Relaxed);
+    fence(Ordering::Release);
+    for word in words {
+        word.store(0, Ordering::Relaxed);
+    }
+    sequence.store(0, Ordering::Release);
+}
+
+/// Copy `words` as last written by [`seqlock_write`], trying again while
+/// they are being written
+pub(crate) fn seqlock_read<const N: usize>(
+    sequence: &AtomicU64,
+    words: &[AtomicU64],
+) -> Result<[u64; N], SeqLockReadError> {
+    for _ in 0..MAX_READ_ATTEMPTS {
+        let current = sequence.load(Ordering::Acquire);
+        if current == 0 {
+            return Err(SeqLockReadError::NotPublished);
+        }
+        if current & 1 != 0 {
+            std::hint::spin_loop();
+            continue;
+        }
+
+        let mut values = [0; N];
+        for (value, word) in values.iter_mut().zip(words) {
+            *value = word.load(Ordering::Relaxed);
+        }
+
+        fence(Ordering::Acquire);
+        if sequence.load(Ordering::Relaxed) == current {
+            return Ok(values);
+        }
+    }
+
+    Err(SeqLockReadError::Busy)
+}
@@ -0,0 +1,73 @@
+//! Socket options that are not exposed by the standard library
+
+use std::{net::SocketAddr, os::fd::AsRawFd};
+
+/// Enable busy polling on a socket.
+///
+/// When the socket is read, the kernel polls the device queue for up to
+/// `micros` microseconds instead of waiting for an interrupt. This lowers and
+/// stabilizes the receive latency at the cost of CPU time, which helps a busy
+/// grandmaster serving many clients. Values above the `net.core.busy_read`
+/// sysctl require `CAP_NET_ADMIN`.
+pub fn set_busy_poll(socket: &impl AsRawFd, micros: u32) -> std::io::Result<()> {
+    let value = libc::c_int::try_from(micros)
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+
+    set_int_option(socket, libc::SOL_SOCKET, libc::SO_BUSY_POLL, value)
+}
+
+/// Have the kernel report the address datagrams received on a socket were
+/// sent to, which [`recv_batch`](crate::batch::recv_batch) passes on. This
+/// tells multicast datagrams apart from those sent to the host itself.
+pub fn set_packet_info(socket: &std::net::UdpSocket) -> std::io::Result<()> {
+    match socket.local_addr()? {
+        SocketAddr::V4(_) => set_int_option(socket, libc::IPPROTO_IP, libc::IP_PKTINFO, 1),
+        SocketAddr::V6(_) => set_int_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, 1),
+    }
+}
+
+fn set_int_option(
+    socket: &impl AsRawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> std::io::Result<()> {
+    // SAFETY: the file descriptor is valid for the lifetime of the socket, and
+    // value outlives the call with the size that is passed along.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn busy_poll_on_udp_socket() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        // Disabling busy polling is always allowed
+        set_busy_poll(&socket, 0).unwrap();
+        assert!(set_busy_poll(&socket, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn packet_info_on_udp_sockets() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        set_packet_info(&socket).unwrap();
+        let socket = std::net::UdpSocket::bind("[::1]:0").unwrap();
+        set_packet_info(&socket).unwrap();
+    }
+}
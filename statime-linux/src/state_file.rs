@@ -0,0 +1,35 @@
+//! Persisting the state of the PTP instance across restarts.
+//!
+//! The file holds an [`InstanceSnapshot`] in its binary format. It is written
+//! whenever the state changes, so a restarted daemon, for example after an
+//! upgrade, continues advertising the same state instead of its configured
+//! defaults.
+
+use std::{io, path::Path};
+
+use statime::InstanceSnapshot;
+
+use crate::drift::replace_file;
+
+/// Read the snapshot stored in `path`, or `None` if the file doesn't exist
+/// yet.
+pub fn read_state_file(path: &Path) -> io::Result<Option<InstanceSnapshot>> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    InstanceSnapshot::deserialize(&contents)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Store the snapshot in `path`, replacing the file atomically.
+pub fn write_state_file(path: &Path, snapshot: &InstanceSnapshot) -> io::Result<()> {
+    let mut buffer = [0; InstanceSnapshot::SIZE];
+    let len = snapshot
+        .serialize(&mut buffer)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    replace_file(path, &buffer[..len])
+}
@@ -0,0 +1,341 @@
+//! Port statistics in shared memory, for exporters that scrape them often.
+//!
+//! With `--statistics-segment` the daemon maps a file, preferably on a tmpfs
+//! like `/dev/shm`, and keeps the last offset and the counters of every port
+//! up to date in it. Exporters map the same file read only, so reading it
+//! takes no system calls and never waits for or wakes the port tasks.
+//!
+//! The file is a sequence of 64 bit words in native byte order:
+//!
+//! | Words | Content                                            |
+//! |-------|----------------------------------------------------|
+//! | 0     | [`MAGIC`]                                          |
+//! | 1     | Layout version, [`LAYOUT_VERSION`]                 |
+//! | 2     | Number of port slots, [`MAX_PORTS`]                |
+//! | 3     | Words per slot, [`SLOT_WORDS`]                     |
+//! | 4..   | A slot per port, port 1 first                      |
+//!
+//! Each slot starts with a sequence number that is odd while the daemon
+//! updates the slot, and zero while the port hasn't published anything. The
+//! rest of the slot holds the fields of [`PortRecord`] in order, signed
+//! values as their two's complement. A reader copies the slot, and tries
+//! again when the sequence number was odd or changed while copying, see
+//! [`StatisticsReader::read_port`]. Later versions only add fields at the
+//! end of a slot, so readers can use the words per slot to skip what they
+//! don't know.
+
+use std::{
+    io,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use statime::{PortStateKind, PortStatistics};
+
+use crate::shared_memory::{
+    seqlock_clear, seqlock_read, seqlock_write, MappedFile, SeqLockReadError,
+};
+
+/// First word of the segment, "STATIMES" in ASCII
+pub const MAGIC: u64 = u64::from_be_bytes(*b"STATIMES");
+
+/// Version of the layout of the segment
+pub const LAYOUT_VERSION: u64 = 1;
+
+/// Ports that get a slot in the segment. Ports numbered higher aren't
+/// published.
+pub const MAX_PORTS: usize = 32;
+
+/// Words of a [`PortRecord`]
+const RECORD_WORDS: usize = 15;
+
+/// Words of a port slot, the sequence number and a [`PortRecord`]
+pub const SLOT_WORDS: usize = 1 + RECORD_WORDS;
+
+/// Words before the first slot
+const HEADER_WORDS: usize = 4;
+
+/// Total words of the segment
+const SEGMENT_WORDS: usize = HEADER_WORDS + MAX_PORTS * SLOT_WORDS;
+
+/// Reasons a slot couldn't be read
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentReadError {
+    #[error("port {0} has no slot in the segment")]
+    NoSuchPort(usize),
+    #[error("the port hasn't published its statistics yet")]
+    NotPublished,
+    #[error("the port kept updating its statistics while reading")]
+    Busy,
+}
+
+/// What the segment holds for a port
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PortRecord {
+    /// State of the port since its last state change, if it changed since
+    /// the daemon started
+    pub state: Option<PortStateKind>,
+    /// Number of measurements the port made
+    pub measurements: u64,
+    /// Offset of the last measurement, in nanoseconds
+    pub offset_ns: i64,
+    /// Local time of the last measurement, in nanoseconds
+    pub measurement_time_ns: u64,
+    /// See [`PortStatistics::measurements_dropped`]
+    pub measurements_dropped: u64,
+    /// See [`PortStatistics::events_dropped`]
+    pub events_dropped: u64,
+    /// See [`PortStatistics::clock_source_changes`]
+    pub clock_source_changes: u64,
+    /// See [`PortStatistics::unexpected_unicast_messages`]
+    pub unexpected_unicast_messages: u64,
+    /// See [`PortStatistics::unexpected_multicast_messages`]
+    pub unexpected_multicast_messages: u64,
+    /// See [`PortStatistics::non_parent_sync_messages`]
+    pub non_parent_sync_messages: u64,
+    /// See [`PortStatistics::send_failures`]
+    pub send_failures: u64,
+    /// See [`PortStatistics::identity_collisions`]
+    pub identity_collisions: u64,
+    /// See [`PortStatistics::profile_mismatches`]
+    pub profile_mismatches: u64,
+    /// See [`PortStatistics::serialization_failures`]
+    pub serialization_failures: u64,
+    /// See [`PortStatistics::internal_errors`]
+    pub internal_errors: u64,
+}
+
+impl PortRecord {
+    /// Take over the counters of `statistics`
+    pub fn update_counters(&mut self, statistics: &PortStatistics) {
+        self.measurements_dropped = statistics.measurements_dropped.into();
+        self.events_dropped = statistics.events_dropped.into();
+        self.clock_source_changes = statistics.clock_source_changes.into();
+        self.unexpected_unicast_messages = statistics.unexpected_unicast_messages.into();
+        self.unexpected_multicast_messages = statistics.unexpected_multicast_messages.into();
+        self.non_parent_sync_messages = statistics.non_parent_sync_messages.into();
+        self.send_failures = statistics.send_failures.into();
+        self.identity_collisions = statistics.identity_collisions.into();
+        self.profile_mismatches = statistics.profile_mismatches.into();
+        self.serialization_failures = statistics.serialization_failures.into();
+        self.internal_errors = statistics.internal_errors.into();
+    }
+
+    fn encode(&self) -> [u64; RECORD_WORDS] {
+        [
+            encode_state(self.state),
+            self.measurements,
+            self.offset_ns as u64,
+            self.measurement_time_ns,
+            self.measurements_dropped,
+            self.events_dropped,
+            self.clock_source_changes,
+            self.unexpected_unicast_messages,
+            self.unexpected_multicast_messages,
+            self.non_parent_sync_messages,
+            self.send_failures,
+            self.identity_collisions,
+            self.profile_mismatches,
+            self.serialization_failures,
+            self.internal_errors,
+        ]
+    }
+
+    fn decode(words: &[u64; RECORD_WORDS]) -> Self {
+        Self {
+            state: decode_state(words[0]),
+            measurements: words[1],
+            offset_ns: words[2] as i64,
+            measurement_time_ns: words[3],
+            measurements_dropped: words[4],
+            events_dropped: words[5],
+            clock_source_changes: words[6],
+            unexpected_unicast_messages: words[7],
+            unexpected_multicast_messages: words[8],
+            non_parent_sync_messages: words[9],
+            send_failures: words[10],
+            identity_collisions: words[11],
+            profile_mismatches: words[12],
+            serialization_failures: words[13],
+            internal_errors: words[14],
+        }
+    }
+}
+
+// Zero while the state is unknown
+fn encode_state(state: Option<PortStateKind>) -> u64 {
+    match state {
+        None => 0,
+        Some(PortStateKind::Listening) => 1,
+        Some(PortStateKind::Passive) => 2,
+        Some(PortStateKind::Master) => 3,
+        Some(PortStateKind::Slave) => 4,
+        Some(PortStateKind::Faulty) => 5,
+        Some(PortStateKind::Disabled) => 6,
+    }
+}
+
+fn decode_state(word: u64) -> Option<PortStateKind> {
+    match word {
+        1 => Some(PortStateKind::Listening),
+        2 => Some(PortStateKind::Passive),
+        3 => Some(PortStateKind::Master),
+        4 => Some(PortStateKind::Slave),
+        5 => Some(PortStateKind::Faulty),
+        6 => Some(PortStateKind::Disabled),
+        _ => None,
+    }
+}
+
+/// The statistics segment, mapped from a file to publish statistics in
+#[derive(Debug)]
+pub struct StatisticsSegment {
+    slots: Slots,
+}
+
+impl StatisticsSegment {
+    /// Size of the segment in bytes
+    pub const SIZE: usize = SEGMENT_WORDS * core::mem::size_of::<u64>();
+
+    /// Map `path` to publish statistics in, creating it when it doesn't
+    /// exist. Whatever a previous run published is cleared, but readers that
+    /// mapped the file before can keep reading it.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let slots = Slots(MappedFile::create(path, Self::SIZE)?);
+
+        let words = slots.words();
+        for slot in words[HEADER_WORDS..].chunks(SLOT_WORDS) {
+            seqlock_clear(&slot[0], &slot[1..]);
+        }
+        words[1].store(LAYOUT_VERSION, Ordering::Relaxed);
+        words[2].store(MAX_PORTS as u64, Ordering::Relaxed);
+        words[3].store(SLOT_WORDS as u64, Ordering::Relaxed);
+        words[0].store(MAGIC, Ordering::Release);
+
+        Ok(Self { slots })
+    }
+
+    /// Replace what the segment holds for the port numbered `port_number`,
+    /// the first being port 1. Only a single task may publish for a port.
+    /// Returns false when the port has no slot.
+    pub fn publish(&self, port_number: usize, record: &PortRecord) -> bool {
+        let Some(slot) = self.slots.slot(port_number) else {
+            return false;
+        };
+
+        seqlock_write(&slot[0], &slot[1..], record.encode());
+        true
+    }
+}
+
+/// The statistics segment of a daemon, mapped read only from a file
+#[derive(Debug)]
+pub struct StatisticsReader {
+    slots: Slots,
+}
+
+impl StatisticsReader {
+    /// Map `path` to read the statistics a daemon publishes in it
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let slots = Slots(MappedFile::open(path, StatisticsSegment::SIZE, false)?);
+
+        let words = slots.words();
+        if words[0].load(Ordering::Acquire) != MAGIC
+            || words[1].load(Ordering::Relaxed) != LAYOUT_VERSION
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a statistics segment of a supported version",
+            ));
+        }
+
+        Ok(Self { slots })
+    }
+
+    /// What port `port_number`, counting from 1, last published
+    pub fn read_port(&self, port_number: usize) -> Result<PortRecord, SegmentReadError> {
+        let slot = self
+            .slots
+            .slot(port_number)
+            .ok_or(SegmentReadError::NoSuchPort(port_number))?;
+
+        match seqlock_read(&slot[0], &slot[1..]) {
+            Ok(words) => Ok(PortRecord::decode(&words)),
+            Err(SeqLockReadError::NotPublished) => Err(SegmentReadError::NotPublished),
+            Err(SeqLockReadError::Busy) => Err(SegmentReadError::Busy),
+        }
+    }
+}
+
+/// The words of a mapped segment, which is only accessed through atomics
+#[derive(Debug)]
+struct Slots(MappedFile);
+
+impl Slots {
+    fn words(&self) -> &[AtomicU64] {
+        // SAFETY: the mapping holds SEGMENT_WORDS words, is page aligned and
+        // lives as long as self, and any value is a valid AtomicU64
+        unsafe { std::slice::from_raw_parts(self.0.as_ptr().as_ptr(), SEGMENT_WORDS) }
+    }
+
+    fn slot(&self, port_number: usize) -> Option<&[AtomicU64]> {
+        let index = port_number
+            .checked_sub(1)
+            .filter(|&index| index < MAX_PORTS)?;
+        let start = HEADER_WORDS + index * SLOT_WORDS;
+        Some(&self.words()[start..start + SLOT_WORDS])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statistics_segment_file() {
+        let path = std::env::temp_dir().join(format!("statime-statistics-{}", std::process::id()));
+
+        assert_eq!(
+            StatisticsReader::open(&path).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let daemon = StatisticsSegment::create(&path).unwrap();
+        let exporter = StatisticsReader::open(&path).unwrap();
+        assert_eq!(exporter.read_port(1), Err(SegmentReadError::NotPublished));
+        assert_eq!(exporter.read_port(0), Err(SegmentReadError::NoSuchPort(0)));
+        assert_eq!(
+            exporter.read_port(MAX_PORTS + 1),
+            Err(SegmentReadError::NoSuchPort(MAX_PORTS + 1))
+        );
+
+        let mut record = PortRecord {
+            state: Some(PortStateKind::Slave),
+            measurements: 12,
+            offset_ns: -250,
+            measurement_time_ns: 1_700_000_000_000_000_000,
+            ..Default::default()
+        };
+        record.update_counters(&PortStatistics {
+            send_failures: 3,
+            internal_errors: 1,
+            ..Default::default()
+        });
+        assert!(daemon.publish(2, &record));
+        assert!(!daemon.publish(MAX_PORTS + 1, &record));
+        assert_eq!(exporter.read_port(2), Ok(record));
+        assert_eq!(exporter.read_port(1), Err(SegmentReadError::NotPublished));
+
+        record.offset_ns = 100;
+        daemon.publish(2, &record);
+        assert_eq!(exporter.read_port(2).unwrap().offset_ns, 100);
+
+        // A restarted daemon clears the previous run for mapped readers
+        drop(daemon);
+        let daemon = StatisticsSegment::create(&path).unwrap();
+        assert_eq!(exporter.read_port(2), Err(SegmentReadError::NotPublished));
+        drop(daemon);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
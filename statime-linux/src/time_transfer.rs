@@ -0,0 +1,120 @@
+//! Passing the time of the instance to containers and virtual machine guests,
+//! see [`statime::time_transfer`].
+//!
+//! The time is published in a file that is mapped into memory. On a tmpfs,
+//! like `/dev/shm`, containers on the same host can map the same file, and
+//! hypervisors like QEMU can hand it to their virtual machines as a shared
+//! memory device (ivshmem). The reference is the raw monotonic clock of the
+//! host. Containers share it, but virtual machines need to be given a
+//! reference that reads the same by their hypervisor.
+
+use std::{io, ops::Deref, path::Path};
+
+use statime::{
+    time_transfer::{ReferenceClock, SharedTime},
+    Time,
+};
+
+use crate::{clock::libc_timespec_into_instant, shared_memory::MappedFile};
+
+/// A [`SharedTime`] in a memory mapped file
+#[derive(Debug)]
+pub struct SharedTimeFile {
+    file: MappedFile,
+}
+
+impl SharedTimeFile {
+    /// Map `path` to publish the time in, creating it when it doesn't exist.
+    /// Guests keep the time last published until it is published again.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        // Zeroes, which is a shared time with nothing published
+        let file = MappedFile::create(path, SharedTime::SIZE)?;
+        Ok(Self { file })
+    }
+
+    /// Map `path` to read the time a host publishes in it.
+    ///
+    /// The file is mapped writable like the one of the host, as
+    /// [`SharedTime`] can be published to through any reference. So a guest
+    /// needs write access to the file, even though it should only read the
+    /// time.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = MappedFile::open(path, SharedTime::SIZE, true)?;
+        Ok(Self { file })
+    }
+}
+
+impl Deref for SharedTimeFile {
+    type Target = SharedTime;
+
+    fn deref(&self) -> &SharedTime {
+        // SAFETY: the mapping is writable, page aligned and lives as long as
+        // self, and all zeroes or anything a host published is a valid
+        // SharedTime
+        unsafe { self.file.as_ptr::<SharedTime>().as_ref() }
+    }
+}
+
+/// The raw monotonic clock, which is not adjusted by NTP or PTP
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MonotonicRawClock;
+
+impl ReferenceClock for MonotonicRawClock {
+    fn now(&self) -> Time {
+        let mut timespec = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: timespec is valid for writes for the duration of the call
+        let result = unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC_RAW, &mut timespec) };
+        // Only fails for invalid arguments
+        debug_assert_eq!(result, 0);
+
+        libc_timespec_into_instant(timespec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use statime::{time_transfer::TransferredTime, TimePropertiesDS, TimeSource};
+
+    use super::*;
+
+    #[test]
+    fn time_transfer_file() {
+        let path = std::env::temp_dir().join(format!("statime-time-{}", std::process::id()));
+
+        assert_eq!(
+            SharedTimeFile::open(&path).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let host = SharedTimeFile::create(&path).unwrap();
+        let guest = SharedTimeFile::open(&path).unwrap();
+        assert!(guest.read().is_err());
+
+        let transferred = TransferredTime {
+            reference: MonotonicRawClock.now(),
+            time: Time::from_secs(1_700_000_000),
+            rate: 1.0,
+            time_properties: TimePropertiesDS::new_arbitrary_time(
+                false,
+                false,
+                TimeSource::InternalOscillator,
+            ),
+        };
+        host.publish(&transferred);
+        assert_eq!(guest.read(), Ok(transferred));
+        drop(host);
+
+        // The time stays published for guests that map the file later
+        let later = SharedTimeFile::open(&path).unwrap();
+        assert_eq!(later.read(), Ok(transferred));
+
+        // Publishing through a guest mapping is pointless, but can't fault
+        later.publish(&transferred);
+        assert_eq!(guest.read(), Ok(transferred));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//! Send timestamps that the network driver delivers late
+//!
+//! The kernel hands the timestamp of a sent packet back on the error queue of
+//! the socket it was sent from. The socket library waits a few milliseconds
+//! for it, which is plenty for most drivers, but some deliver hardware
+//! timestamps later than that. Rather than falling back to the time the
+//! message was handed to the kernel, the port can keep looking on the error
+//! queue for a while, with increasing waits in between.
+
+use std::{io, net::UdpSocket, os::fd::AsRawFd, time::Duration};
+
+use statime::Time;
+
+use crate::{batch::RECV_BUFFER_SIZE, clock::libc_timespec_into_instant};
+
+/// How long to keep looking for a send timestamp that the socket library
+/// didn't receive in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxTimestampPolicy {
+    /// Number of times to look on the error queue, 0 to not look at all
+    pub attempts: u32,
+    /// The wait before the first attempt, doubled before every next one
+    pub initial_backoff: Duration,
+    /// The longest wait between two attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for TxTimestampPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(8),
+        }
+    }
+}
+
+impl TxTimestampPolicy {
+    /// The wait before each attempt
+    pub fn backoffs(&self) -> impl Iterator<Item = Duration> + '_ {
+        let mut backoff = self.initial_backoff;
+        (0..self.attempts).map(move |_| {
+            let wait = backoff.min(self.max_backoff);
+            backoff = backoff.saturating_mul(2);
+            wait
+        })
+    }
+}
+
+/// How quickly the send timestamps of a network interface became available
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxTimestampStats {
+    /// Timestamps the socket library received while sending
+    pub on_time: u64,
+    /// Timestamps found on the error queue after the socket library gave up
+    pub late: u64,
+    /// Messages without a send timestamp, for which the time they were sent
+    /// was used instead
+    pub missed: u64,
+    /// The longest time from sending a message until its timestamp was
+    /// available
+    pub max_latency: Duration,
+    total_latency: Duration,
+}
+
+impl TxTimestampStats {
+    pub fn record_on_time(&mut self, latency: Duration) {
+        self.on_time += 1;
+        self.record_latency(latency);
+    }
+
+    pub fn record_late(&mut self, latency: Duration) {
+        self.late += 1;
+        self.record_latency(latency);
+    }
+
+    pub fn record_missed(&mut self) {
+        self.missed += 1;
+    }
+
+    /// The mean time from sending a message until its timestamp was
+    /// available, if any timestamp was
+    pub fn mean_latency(&self) -> Option<Duration> {
+        let count = u32::try_from(self.on_time + self.late).ok()?;
+        self.total_latency.checked_div(count)
+    }
+
+    fn record_latency(&mut self, latency: Duration) {
+        self.max_latency = self.max_latency.max(latency);
+        self.total_latency = self.total_latency.saturating_add(latency);
+    }
+}
+
+/// Take the next send timestamp off the error queue of `socket`, or `None`
+/// when there is none. Entries without a timestamp are skipped.
+///
+/// `hardware` selects the timestamp taken by the network interface rather
+/// than the one taken by the kernel. Doesn't block.
+pub fn read_error_queue(socket: &UdpSocket, hardware: bool) -> io::Result<Option<Time>> {
+    loop {
+        let mut data = [0u8; RECV_BUFFER_SIZE];
+        // u64 for the alignment of the control message headers
+        let mut control = [0u64; 64];
+        let mut iovec = libc::iovec {
+            iov_base: data.as_mut_ptr().cast(),
+            iov_len: data.len(),
+        };
+
+        // SAFETY: msghdr is a plain C struct for which all zeroes is valid
+        let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+        header.msg_iov = &mut iovec;
+        header.msg_iovlen = 1;
+        header.msg_control = control.as_mut_ptr().cast();
+        header.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        // SAFETY: the header points to a single iovec, which points to a
+        // buffer of the given length, and to a control buffer of the given
+        // length. All of them outlive the call.
+        let received = unsafe {
+            libc::recvmsg(
+                socket.as_raw_fd(),
+                &mut header,
+                libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT,
+            )
+        };
+        if received < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::WouldBlock => Ok(None),
+                _ => Err(error),
+            };
+        }
+
+        if let Some(timestamp) = find_timestamp(&header, hardware) {
+            return Ok(Some(timestamp));
+        }
+    }
+}
+
+fn find_timestamp(header: &libc::msghdr, hardware: bool) -> Option<Time> {
+    // SAFETY: recvmsg filled in the header, which points to the control
+    // buffer, so this returns null or a control message within it
+    let mut message = unsafe { libc::CMSG_FIRSTHDR(header) };
+    while !message.is_null() {
+        // SAFETY: message points to a control message header within the
+        // control buffer
+        let (level, kind) = unsafe { ((*message).cmsg_level, (*message).cmsg_type) };
+        if level == libc::SOL_SOCKET && kind == libc::SCM_TIMESTAMPING {
+            // SAFETY: the data of SCM_TIMESTAMPING is three timespecs, of
+            // which the first is the software timestamp and the last the
+            // hardware one. It need not be aligned for them.
+            let timestamps: [libc::timespec; 3] =
+                unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(message).cast()) };
+            let timestamp = if hardware {
+                timestamps[2]
+            } else {
+                timestamps[0]
+            };
+            if timestamp.tv_sec != 0 || timestamp.tv_nsec != 0 {
+                return Some(libc_timespec_into_instant(timestamp));
+            }
+        }
+        // SAFETY: as above, for the message after this one
+        message = unsafe { libc::CMSG_NXTHDR(header, message) };
+    }
+
+    None
+}
+
+/// Look for a send timestamp on the error queue of `socket` as `policy`
+/// says, waiting in between
+pub async fn poll_error_queue(
+    socket: &UdpSocket,
+    hardware: bool,
+    policy: &TxTimestampPolicy,
+) -> Option<Time> {
+    for backoff in policy.backoffs() {
+        tokio::time::sleep(backoff).await;
+        match read_error_queue(socket, hardware) {
+            Ok(Some(timestamp)) => return Some(timestamp),
+            Ok(None) => {}
+            Err(error) => {
+                log::debug!("Could not read the error queue: {error}");
+                return None;
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let policy = TxTimestampPolicy::default();
+        let backoffs: Vec<_> = policy.backoffs().map(|wait| wait.as_millis()).collect();
+        assert_eq!(backoffs, [1, 2, 4, 8, 8]);
+
+        let disabled = TxTimestampPolicy {
+            attempts: 0,
+            ..policy
+        };
+        assert_eq!(disabled.backoffs().count(), 0);
+    }
+
+    #[test]
+    fn latency_statistics() {
+        let mut stats = TxTimestampStats::default();
+        assert_eq!(stats.mean_latency(), None);
+
+        stats.record_on_time(Duration::from_micros(100));
+        stats.record_late(Duration::from_millis(5));
+        stats.record_missed();
+        assert_eq!((stats.on_time, stats.late, stats.missed), (1, 1, 1));
+        assert_eq!(stats.max_latency, Duration::from_millis(5));
+        assert_eq!(stats.mean_latency(), Some(Duration::from_micros(2550)));
+    }
+
+    #[test]
+    fn empty_error_queue() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert_eq!(read_error_queue(&socket, false).unwrap(), None);
+    }
+}
@@ -0,0 +1,451 @@
+//! End to end test of two instances in one process, one master and one slave,
+//! connected by a loopback transport. Packets are handed to the ports the way
+//! statime-linux hands over those from its sockets, with software timestamps
+//! from the clock of each node, and the slave steers its clock with the
+//! measurements. The test checks that the clocks of both nodes settle within
+//! a bound of each other.
+//!
+//! The nodes run on simulated time, so the test is deterministic and takes
+//! far less time than the protocol time it covers.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use arrayvec::ArrayVec;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use statime::{
+    BasicFilter, Clock, ClockIdentity, CommunicationMode, DelayMechanism, Destination, Duration,
+    InstanceConfig, Interval, LogMessageIntervals, PacketArrival, Port, PortAction,
+    PortActionIterator, PortConfig, PtpInstance, Running, SdoId, SourceAddress, Time,
+    TimePropertiesDS, TimestampContext, TimestampSource, TransmitEnable,
+};
+use statime_linux::network::{receive_packet, NetworkPacket};
+
+const MASTER: ClockIdentity = ClockIdentity([1; 8]);
+const SLAVE: ClockIdentity = ClockIdentity([2; 8]);
+
+/// Where the simulated time starts, in nanoseconds
+const START_NS: f64 = 1_000_000_000_000.0;
+/// How far the clock of the slave is off at the start
+const SLAVE_OFFSET_NS: f64 = 500_000.0;
+/// How fast the clock of the slave runs before it is steered
+const SLAVE_RATE: f64 = 1.0 + 50e-6;
+
+/// Time a packet takes through the loopback transport, in nanoseconds
+const LATENCY_NS: f64 = 20_000.0;
+/// Largest random variation on top of [`LATENCY_NS`], in nanoseconds
+const JITTER_NS: f64 = 2_000.0;
+
+/// Largest offset between the clocks once the slave has converged
+const OFFSET_BOUND_NS: f64 = 1_000.0;
+/// Consecutive samples within the bound needed to pass, one per second
+const CONVERGED_SAMPLES: usize = 20;
+/// Seconds of simulated time to converge in
+const TIMEOUT_SECS: u32 = 60;
+
+/// A clock running at a fixed rate on the simulated time, until the instance
+/// adjusts it. Clones share the same clock.
+#[derive(Clone)]
+struct SimulatedClock {
+    now: Rc<Cell<f64>>,
+    state: Rc<RefCell<ClockState>>,
+}
+
+struct ClockState {
+    // Simulated time at which the clock read `local`
+    base: f64,
+    local: f64,
+    rate: f64,
+}
+
+impl SimulatedClock {
+    fn new(now: &Rc<Cell<f64>>, offset: f64, rate: f64) -> Self {
+        let state = ClockState {
+            base: now.get(),
+            local: now.get() + offset,
+            rate,
+        };
+        SimulatedClock {
+            now: now.clone(),
+            state: Rc::new(RefCell::new(state)),
+        }
+    }
+
+    fn nanos(&self) -> f64 {
+        let state = self.state.borrow();
+        state.local + (self.now.get() - state.base) * state.rate
+    }
+}
+
+impl Clock for SimulatedClock {
+    type Error = std::convert::Infallible;
+
+    fn now(&self) -> Time {
+        Time::from_fixed_nanos(self.nanos())
+    }
+
+    fn adjust(
+        &mut self,
+        time_offset: Duration,
+        frequency_multiplier: f64,
+        _time_properties_ds: &TimePropertiesDS,
+    ) -> Result<(), Self::Error> {
+        let local = self.nanos() + time_offset.nanos_lossy();
+        let mut state = self.state.borrow_mut();
+        state.base = self.now.get();
+        state.local = local;
+        state.rate *= frequency_multiplier;
+
+        Ok(())
+    }
+}
+
+/// A packet on its way through the loopback transport
+struct InFlight {
+    deliver_at: f64,
+    from: usize,
+    data: ArrayVec<u8, { statime::MAX_DATA_LEN }>,
+    event: bool,
+}
+
+/// Delivers every packet to all nodes, including the one that sent it, as
+/// multicast on a real network does
+struct Loopback {
+    in_flight: Vec<InFlight>,
+    jitter: StdRng,
+}
+
+impl Loopback {
+    fn send(&mut self, from: usize, data: &[u8], event: bool, now: f64) {
+        self.in_flight.push(InFlight {
+            deliver_at: now + LATENCY_NS + self.jitter.gen_range(0.0..JITTER_NS),
+            from,
+            data: data.try_into().unwrap(),
+            event,
+        });
+    }
+
+    // Take the first packet due at or before `until`
+    fn next(&mut self, until: f64) -> Option<InFlight> {
+        let (index, _) = self
+            .in_flight
+            .iter()
+            .enumerate()
+            .filter(|(_, packet)| packet.deliver_at <= until)
+            .min_by(|(_, a), (_, b)| a.deliver_at.total_cmp(&b.deliver_at))?;
+        Some(self.in_flight.remove(index))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Timer {
+    Announce,
+    Sync,
+    DelayRequest,
+    AnnounceReceipt,
+    Statistics,
+    Bmca,
+}
+
+const TIMERS: [Timer; 6] = [
+    Timer::Announce,
+    Timer::Sync,
+    Timer::DelayRequest,
+    Timer::AnnounceReceipt,
+    Timer::Statistics,
+    Timer::Bmca,
+];
+
+enum Input<'a> {
+    Timer(Timer),
+    Packet(&'a NetworkPacket),
+}
+
+type SimulatedPort<'a> = Port<Running<'a, SimulatedClock, BasicFilter>, StdRng>;
+
+/// When each timer of a node expires, in simulated time
+#[derive(Default)]
+struct Timers([Option<f64>; TIMERS.len()]);
+
+impl Timers {
+    fn next(&self) -> Option<(f64, Timer)> {
+        TIMERS
+            .iter()
+            .filter_map(|&timer| Some((self.0[timer as usize]?, timer)))
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+    }
+
+    fn reset(&mut self, timer: Timer, duration: core::time::Duration, now: f64) {
+        self.0[timer as usize] = Some(now + duration.as_nanos() as f64);
+    }
+}
+
+struct Node<'a> {
+    instance: &'a PtpInstance<SimulatedClock, BasicFilter>,
+    // Only taken out while the BMCA runs
+    port: Option<SimulatedPort<'a>>,
+    clock: SimulatedClock,
+    timers: Timers,
+}
+
+impl<'a> Node<'a> {
+    fn new(
+        instance: &'a PtpInstance<SimulatedClock, BasicFilter>,
+        clock: SimulatedClock,
+        index: usize,
+        now: f64,
+        loopback: &mut Loopback,
+    ) -> Self {
+        let (port, actions) = instance
+            .add_port(port_config(), StdRng::seed_from_u64(index as u64))
+            .end_bmca();
+        let mut timers = Timers::default();
+        // A port that is still listening sends nothing
+        let sent = handle_actions(actions, index, now, &mut timers, &clock, loopback);
+        assert!(sent.is_none());
+        timers.reset(Timer::Bmca, instance.bmca_interval(), now);
+
+        Node {
+            instance,
+            port: Some(port),
+            clock,
+            timers,
+        }
+    }
+
+    fn handle(&mut self, index: usize, input: Input, now: f64, loopback: &mut Loopback) {
+        let port = self.port.as_mut().unwrap();
+        let mut actions = match input {
+            Input::Timer(Timer::Announce) => port.handle_announce_timer(),
+            Input::Timer(Timer::Sync) => port.handle_sync_timer(),
+            Input::Timer(Timer::DelayRequest) => port.handle_delay_request_timer(),
+            Input::Timer(Timer::AnnounceReceipt) => port.handle_announce_receipt_timer(),
+            Input::Timer(Timer::Statistics) => port.handle_statistics_timer(),
+            Input::Timer(Timer::Bmca) => {
+                let mut bmca_port = self.port.take().unwrap().start_bmca();
+                self.instance.bmca(&mut [&mut bmca_port]);
+                let (port, actions) = bmca_port.end_bmca();
+                self.port = Some(port);
+                self.timers
+                    .reset(Timer::Bmca, self.instance.bmca_interval(), now);
+                actions
+            }
+            Input::Packet(packet) => {
+                match receive_packet(port, packet, TimestampSource::Software) {
+                    Some(actions) => actions,
+                    None => return,
+                }
+            }
+        };
+
+        // Carry on with the actions that follow from the send timestamps
+        while let Some((context, timestamp)) =
+            handle_actions(actions, index, now, &mut self.timers, &self.clock, loopback)
+        {
+            let port = self.port.as_mut().unwrap();
+            actions = port.handle_send_timestamp(context, timestamp);
+        }
+    }
+}
+
+// Carry out the actions of the port of node `index`, returning the context
+// and send timestamp of the time critical message sent last
+fn handle_actions(
+    actions: PortActionIterator<'_>,
+    index: usize,
+    now: f64,
+    timers: &mut Timers,
+    clock: &SimulatedClock,
+    loopback: &mut Loopback,
+) -> Option<(TimestampContext, Time)> {
+    let mut sent = None;
+
+    for action in actions {
+        match action {
+            PortAction::SendTimeCritical { context, data, .. } => {
+                loopback.send(index, data, true, now);
+                sent = Some((context, clock.now()));
+            }
+            PortAction::SendGeneral { data } => loopback.send(index, data, false, now),
+            PortAction::ResetAnnounceTimer { duration } => {
+                timers.reset(Timer::Announce, duration, now)
+            }
+            PortAction::ResetSyncTimer { duration } => timers.reset(Timer::Sync, duration, now),
+            PortAction::ResetDelayRequestTimer { duration } => {
+                timers.reset(Timer::DelayRequest, duration, now)
+            }
+            PortAction::ResetAnnounceReceiptTimer { duration } => {
+                timers.reset(Timer::AnnounceReceipt, duration, now)
+            }
+            PortAction::ResetStatisticsTimer { duration } => {
+                timers.reset(Timer::Statistics, duration, now)
+            }
+            PortAction::SendUnicastGeneral { .. }
+            | PortAction::SendUnicastTimeCritical { .. }
+            | PortAction::SendToUnicastMaster { .. }
+            | PortAction::ResetUnicastNegotiationTimer { .. } => {
+                panic!("Unexpected unicast action")
+            }
+        }
+    }
+
+    sent
+}
+
+fn instance_config(clock_identity: ClockIdentity, priority_1: u8) -> InstanceConfig {
+    InstanceConfig {
+        clock_identity,
+        priority_1,
+        priority_2: 128,
+        domain_number: 0,
+        slave_only: false,
+        sdo_id: SdoId::default(),
+    }
+}
+
+fn port_config() -> PortConfig {
+    PortConfig {
+        delay_mechanism: DelayMechanism::E2E {
+            interval: Interval::from_log_2(-3),
+        },
+        announce_interval: Interval::from_log_2(-2),
+        announce_receipt_timeout: 3,
+        sync_interval: Interval::from_log_2(-3),
+        master_only: false,
+        delay_asymmetry: Duration::ZERO,
+        ingress_latency: Duration::ZERO,
+        egress_latency: Duration::ZERO,
+        management: Default::default(),
+        communication_mode: CommunicationMode::Multicast,
+        transmit: TransmitEnable::ALL,
+        unicast_client: None,
+        log_message_intervals: LogMessageIntervals::STANDARD,
+    }
+}
+
+/// The nodes and the transport between them, on a shared simulated time
+struct Simulation<'a> {
+    now: Rc<Cell<f64>>,
+    nodes: [Node<'a>; 2],
+    loopback: Loopback,
+}
+
+impl Simulation<'_> {
+    // Handle all packets and timers up to `until`, in the order they are due
+    fn run_until(&mut self, until: f64) {
+        loop {
+            let timer = (0..self.nodes.len())
+                .filter_map(|index| Some((self.nodes[index].timers.next()?, index)))
+                .filter(|((at, _), _)| *at <= until)
+                .min_by(|((a, _), _), ((b, _), _)| a.total_cmp(b));
+            let packet_due = self
+                .loopback
+                .in_flight
+                .iter()
+                .map(|packet| packet.deliver_at)
+                .min_by(f64::total_cmp);
+
+            match (timer, packet_due) {
+                (Some(((at, _), _)), Some(due)) if due <= at => self.deliver(due),
+                (None, Some(due)) if due <= until => self.deliver(due),
+                (Some(((at, timer), index)), _) => {
+                    self.now.set(at);
+                    self.nodes[index].timers.0[timer as usize] = None;
+                    self.nodes[index].handle(index, Input::Timer(timer), at, &mut self.loopback);
+                }
+                _ => break,
+            }
+        }
+
+        self.now.set(until);
+    }
+
+    fn deliver(&mut self, due: f64) {
+        self.now.set(due);
+        let packet = self.loopback.next(due).unwrap();
+
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            let source = if index == packet.from {
+                SourceAddress::Own
+            } else {
+                SourceAddress::Other
+            };
+            let network_packet = NetworkPacket {
+                data: packet.data.clone(),
+                timestamp: packet.event.then(|| node.clock.now()),
+                arrival: PacketArrival {
+                    source: Some(source),
+                    destination: Some(Destination::Multicast),
+                },
+            };
+            node.handle(
+                index,
+                Input::Packet(&network_packet),
+                due,
+                &mut self.loopback,
+            );
+        }
+    }
+}
+
+#[test]
+fn two_node_convergence() {
+    let now = Rc::new(Cell::new(START_NS));
+    let master_clock = SimulatedClock::new(&now, 0.0, 1.0);
+    let slave_clock = SimulatedClock::new(&now, SLAVE_OFFSET_NS, SLAVE_RATE);
+
+    let master = PtpInstance::new(
+        instance_config(MASTER, 64),
+        TimePropertiesDS::default(),
+        master_clock.clone(),
+        BasicFilter::new(0.25),
+    );
+    let slave = PtpInstance::new(
+        instance_config(SLAVE, 128),
+        TimePropertiesDS::default(),
+        slave_clock.clone(),
+        BasicFilter::new(0.25),
+    );
+
+    let mut loopback = Loopback {
+        in_flight: Vec::new(),
+        jitter: StdRng::seed_from_u64(1588),
+    };
+    let nodes = [
+        Node::new(&master, master_clock, 0, now.get(), &mut loopback),
+        Node::new(&slave, slave_clock, 1, now.get(), &mut loopback),
+    ];
+    let mut simulation = Simulation {
+        now,
+        nodes,
+        loopback,
+    };
+
+    let mut offsets = Vec::new();
+    let mut converged = 0;
+    for second in 1..=TIMEOUT_SECS {
+        simulation.run_until(START_NS + second as f64 * 1e9);
+
+        let [master, slave] = &simulation.nodes;
+        let offset = slave.clock.nanos() - master.clock.nanos();
+        offsets.push(offset);
+
+        let synchronized = slave.instance.status().grandmaster_identity == MASTER;
+        if synchronized && offset.abs() < OFFSET_BOUND_NS {
+            converged += 1;
+        } else {
+            converged = 0;
+        }
+
+        if converged == CONVERGED_SAMPLES {
+            return;
+        }
+    }
+
+    panic!(
+        "No convergence within {TIMEOUT_SECS}s, last offsets (ns): {:?}",
+        &offsets[offsets.len() - CONVERGED_SAMPLES..]
+    );
+}
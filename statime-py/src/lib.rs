@@ -0,0 +1,448 @@
+//! Python bindings to statime, see the README for how to build and use them.
+//!
+//! The bindings are thin wrappers around [`node`] and [`simulation`], which
+//! contain the logic and can be tested without a Python interpreter.
+
+// Triggered by the code the pyo3 macros generate
+#![allow(clippy::useless_conversion)]
+
+use pyo3::{
+    exceptions::{PyKeyError, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyDict, PyList, PyTuple},
+};
+use statime::{Clock, ClockIdentity, ClockQuality, DecodedMessage, Time, UnicastNegotiation};
+
+pub mod node;
+pub mod simulation;
+
+use node::{timer_from_name, timer_name, Node, NodeConfig, Output, TimeBase, VirtualClock};
+use simulation::Simulation;
+
+fn identity_hex(identity: ClockIdentity) -> String {
+    identity
+        .0
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn time_nanos(time: Time) -> u64 {
+    time.secs() * 1_000_000_000 + u64::from(time.subsec_nanos())
+}
+
+fn clock_quality_dict<'py>(py: Python<'py>, quality: ClockQuality) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new_bound(py);
+    dict.set_item("clock_class", u8::from(quality.clock_class))?;
+    dict.set_item("clock_accuracy", format!("{:?}", quality.clock_accuracy))?;
+    dict.set_item(
+        "offset_scaled_log_variance",
+        quality.offset_scaled_log_variance,
+    )?;
+    Ok(dict)
+}
+
+fn message_dict<'py>(py: Python<'py>, message: &DecodedMessage) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new_bound(py);
+    dict.set_item("message_type", format!("{:?}", message.message_type))?;
+    dict.set_item("domain_number", message.domain_number)?;
+    dict.set_item("sdo_id", message.sdo_id)?;
+    dict.set_item("sequence_id", message.sequence_id)?;
+    dict.set_item(
+        "source_clock_identity",
+        identity_hex(message.source_clock_identity),
+    )?;
+    dict.set_item("source_port_number", message.source_port_number)?;
+    dict.set_item("correction_ns", message.correction.nanos_lossy())?;
+    dict.set_item("log_message_interval", message.log_message_interval)?;
+    dict.set_item("two_step", message.two_step)?;
+    dict.set_item("unicast", message.unicast)?;
+    dict.set_item("timestamp_ns", message.timestamp.map(time_nanos))?;
+    dict.set_item(
+        "requesting_port",
+        message
+            .requesting_port
+            .map(|(identity, port)| (identity_hex(identity), port)),
+    )?;
+
+    match &message.announce {
+        Some(announce) => {
+            let body = PyDict::new_bound(py);
+            body.set_item("current_utc_offset", announce.current_utc_offset)?;
+            body.set_item("grandmaster_priority_1", announce.grandmaster_priority_1)?;
+            body.set_item(
+                "grandmaster_clock_quality",
+                clock_quality_dict(py, announce.grandmaster_clock_quality)?,
+            )?;
+            body.set_item("grandmaster_priority_2", announce.grandmaster_priority_2)?;
+            body.set_item(
+                "grandmaster_identity",
+                identity_hex(announce.grandmaster_identity),
+            )?;
+            body.set_item("steps_removed", announce.steps_removed)?;
+            body.set_item("time_source", format!("{:?}", announce.time_source))?;
+            body.set_item(
+                "profile_identifier",
+                announce
+                    .profile_identifier
+                    .map(|identifier| identifier.to_string()),
+            )?;
+            dict.set_item("announce", body)?;
+        }
+        None => dict.set_item("announce", py.None())?,
+    }
+
+    match &message.signaling {
+        Some(signaling) => {
+            let body = PyDict::new_bound(py);
+            let (identity, port) = signaling.target_port;
+            body.set_item("target_port", (identity_hex(identity), port))?;
+            let negotiations = PyList::empty_bound(py);
+            for negotiation in signaling.unicast_negotiations() {
+                negotiations.append(negotiation_dict(py, negotiation)?)?;
+            }
+            body.set_item("unicast_negotiations", negotiations)?;
+            dict.set_item("signaling", body)?;
+        }
+        None => dict.set_item("signaling", py.None())?,
+    }
+
+    Ok(dict)
+}
+
+fn negotiation_dict<'py>(
+    py: Python<'py>,
+    negotiation: &UnicastNegotiation,
+) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new_bound(py);
+    let (tlv, message_type) = match *negotiation {
+        UnicastNegotiation::Request {
+            message_type,
+            log_interval,
+            duration,
+        } => {
+            dict.set_item("log_interval", log_interval)?;
+            dict.set_item("duration", duration)?;
+            ("request", message_type)
+        }
+        UnicastNegotiation::Grant {
+            message_type,
+            log_interval,
+            duration,
+            renewal_invited,
+        } => {
+            dict.set_item("log_interval", log_interval)?;
+            dict.set_item("duration", duration)?;
+            dict.set_item("renewal_invited", renewal_invited)?;
+            ("grant", message_type)
+        }
+        UnicastNegotiation::Cancel { message_type } => ("cancel", message_type),
+        UnicastNegotiation::AcknowledgeCancel { message_type } => {
+            ("acknowledge_cancel", message_type)
+        }
+    };
+    dict.set_item("tlv", tlv)?;
+    dict.set_item("message_type", format!("{message_type:?}"))?;
+    Ok(dict)
+}
+
+/// Decode a PTP message with the parser statime uses, returning its fields as
+/// a dict. Raises ValueError if the message is invalid.
+#[pyfunction]
+fn parse_message<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyDict>> {
+    let message =
+        statime::decode_message(data).map_err(|error| PyValueError::new_err(error.to_string()))?;
+    message_dict(py, &message)
+}
+
+fn parse_identity(identity: &[u8]) -> PyResult<ClockIdentity> {
+    identity
+        .try_into()
+        .map(ClockIdentity)
+        .map_err(|_| PyValueError::new_err("a clock identity is 8 bytes"))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn node_config(
+    clock_identity: &[u8],
+    priority_1: u8,
+    priority_2: u8,
+    domain_number: u8,
+    slave_only: bool,
+    log_announce_interval: i8,
+    log_sync_interval: i8,
+    log_min_delay_request_interval: i8,
+    seed: u64,
+) -> PyResult<NodeConfig> {
+    Ok(NodeConfig {
+        clock_identity: parse_identity(clock_identity)?,
+        priority_1,
+        priority_2,
+        domain_number,
+        slave_only,
+        log_announce_interval,
+        log_sync_interval,
+        log_min_delay_request_interval,
+        seed,
+    })
+}
+
+fn outputs_list<'py>(py: Python<'py>, outputs: Vec<Output>) -> Bound<'py, PyList> {
+    let outputs = outputs.into_iter().map(|output| -> Bound<'py, PyTuple> {
+        match output {
+            Output::Event { data, context } => PyTuple::new_bound(
+                py,
+                [
+                    "event".into_py(py),
+                    PyBytes::new_bound(py, &data).into_py(py),
+                    context.into_py(py),
+                ],
+            ),
+            Output::General { data } => PyTuple::new_bound(
+                py,
+                [
+                    "general".into_py(py),
+                    PyBytes::new_bound(py, &data).into_py(py),
+                ],
+            ),
+            Output::Timer { timer, duration } => PyTuple::new_bound(
+                py,
+                [
+                    "timer".into_py(py),
+                    timer_name(timer).into_py(py),
+                    duration.as_secs_f64().into_py(py),
+                ],
+            ),
+        }
+    });
+    PyList::new_bound(py, outputs)
+}
+
+/// An ordinary clock with a single port, for running against real devices.
+///
+/// The clock doesn't do any IO. Every method returns a list of things to do:
+/// `("event", data, context)` to send an event message and report the time it
+/// was sent with `send_timestamp`, `("general", data)` to send a general
+/// message, and `("timer", name, seconds)` to (re)start a timer. When a timer
+/// expires, call `timer` with its name.
+///
+/// The clock that is synchronized is a virtual clock on top of the system
+/// clock, so the system clock is never changed. Timestamps are in
+/// nanoseconds of this virtual clock, see `now_ns`.
+#[pyclass(unsendable)]
+struct OrdinaryClock {
+    node: Node,
+}
+
+#[pymethods]
+impl OrdinaryClock {
+    #[new]
+    #[pyo3(signature = (
+        clock_identity,
+        priority_1 = 128,
+        priority_2 = 128,
+        domain_number = 0,
+        slave_only = false,
+        log_announce_interval = 1,
+        log_sync_interval = 0,
+        log_min_delay_request_interval = 0,
+        seed = 0,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        clock_identity: &[u8],
+        priority_1: u8,
+        priority_2: u8,
+        domain_number: u8,
+        slave_only: bool,
+        log_announce_interval: i8,
+        log_sync_interval: i8,
+        log_min_delay_request_interval: i8,
+        seed: u64,
+    ) -> PyResult<Self> {
+        let config = node_config(
+            clock_identity,
+            priority_1,
+            priority_2,
+            domain_number,
+            slave_only,
+            log_announce_interval,
+            log_sync_interval,
+            log_min_delay_request_interval,
+            seed,
+        )?;
+        let clock = VirtualClock::new(TimeBase::System, 0.0, 0.0);
+        let node =
+            Node::new(config, clock).map_err(|error| PyValueError::new_err(error.to_string()))?;
+        Ok(Self { node })
+    }
+
+    /// The current time of the virtual clock in nanoseconds
+    fn now_ns(&self) -> u64 {
+        time_nanos(self.node.clock().now())
+    }
+
+    /// The outputs resulting from creating the clock
+    fn start<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyList> {
+        outputs_list(py, self.node.start())
+    }
+
+    /// Handle an event message, received at `timestamp_ns` or now
+    #[pyo3(signature = (data, timestamp_ns = None))]
+    fn receive_event<'py>(
+        &mut self,
+        py: Python<'py>,
+        data: &[u8],
+        timestamp_ns: Option<u64>,
+    ) -> Bound<'py, PyList> {
+        let timestamp = match timestamp_ns {
+            Some(nanos) => Time::from_nanos(nanos),
+            None => self.node.clock().now(),
+        };
+        outputs_list(py, self.node.receive_event(data, timestamp))
+    }
+
+    /// Handle a general message
+    fn receive_general<'py>(&mut self, py: Python<'py>, data: &[u8]) -> Bound<'py, PyList> {
+        outputs_list(py, self.node.receive_general(data))
+    }
+
+    /// Report that the event message with `context` was sent at
+    /// `timestamp_ns` or now
+    #[pyo3(signature = (context, timestamp_ns = None))]
+    fn send_timestamp<'py>(
+        &mut self,
+        py: Python<'py>,
+        context: u32,
+        timestamp_ns: Option<u64>,
+    ) -> PyResult<Bound<'py, PyList>> {
+        let timestamp = match timestamp_ns {
+            Some(nanos) => Time::from_nanos(nanos),
+            None => self.node.clock().now(),
+        };
+        match self.node.send_timestamp(context, timestamp) {
+            Some(outputs) => Ok(outputs_list(py, outputs)),
+            None => Err(PyKeyError::new_err(context)),
+        }
+    }
+
+    /// Handle the expiry of the timer with the given name
+    fn timer<'py>(&mut self, py: Python<'py>, name: &str) -> PyResult<Bound<'py, PyList>> {
+        let timer = timer_from_name(name)
+            .ok_or_else(|| PyValueError::new_err(format!("unknown timer {name:?}")))?;
+        Ok(outputs_list(py, self.node.timer(timer)))
+    }
+
+    /// Run the best master clock algorithm. This should be done every
+    /// `bmca_interval` seconds.
+    fn bmca<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyList> {
+        outputs_list(py, self.node.bmca())
+    }
+
+    /// Interval between runs of the BMCA, in seconds
+    fn bmca_interval(&self) -> f64 {
+        self.node.bmca_interval().as_secs_f64()
+    }
+
+    /// The offsets to the master measured since the last call, in nanoseconds
+    fn take_offsets(&mut self) -> Vec<f64> {
+        self.node.take_offsets()
+    }
+}
+
+/// A simulated network of ordinary clocks, connected to a single segment
+/// where every message takes `delay_ns` to arrive.
+#[pyclass(name = "Simulation", unsendable)]
+struct PySimulation {
+    simulation: Simulation,
+}
+
+#[pymethods]
+impl PySimulation {
+    #[new]
+    #[pyo3(signature = (delay_ns = 10_000))]
+    fn new(delay_ns: u64) -> Self {
+        Self {
+            simulation: Simulation::new(delay_ns),
+        }
+    }
+
+    /// Add a node, returning its index. Its clock starts `offset_ns` away
+    /// from the true time, and runs `frequency_ppm` parts per million fast.
+    #[pyo3(signature = (
+        clock_identity,
+        priority_1 = 128,
+        priority_2 = 128,
+        domain_number = 0,
+        slave_only = false,
+        log_announce_interval = 1,
+        log_sync_interval = 0,
+        log_min_delay_request_interval = 0,
+        offset_ns = 0.0,
+        frequency_ppm = 0.0,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn add_node(
+        &mut self,
+        clock_identity: &[u8],
+        priority_1: u8,
+        priority_2: u8,
+        domain_number: u8,
+        slave_only: bool,
+        log_announce_interval: i8,
+        log_sync_interval: i8,
+        log_min_delay_request_interval: i8,
+        offset_ns: f64,
+        frequency_ppm: f64,
+    ) -> PyResult<usize> {
+        let config = node_config(
+            clock_identity,
+            priority_1,
+            priority_2,
+            domain_number,
+            slave_only,
+            log_announce_interval,
+            log_sync_interval,
+            log_min_delay_request_interval,
+            u64::from_be_bytes(parse_identity(clock_identity)?.0),
+        )?;
+        self.simulation
+            .add_node(config, offset_ns, frequency_ppm)
+            .map_err(|error| PyValueError::new_err(error.to_string()))
+    }
+
+    /// Run the simulation for the given number of simulated seconds
+    fn run(&mut self, seconds: f64) {
+        self.simulation.run((seconds * 1e9) as u64);
+    }
+
+    /// The simulated true time in nanoseconds
+    fn now_ns(&self) -> u64 {
+        self.simulation.now_ns()
+    }
+
+    /// The difference between the clock of a node and the true time, in
+    /// nanoseconds
+    fn clock_offset_ns(&self, node: usize) -> PyResult<f64> {
+        self.simulation
+            .clock_offset_ns(node)
+            .ok_or_else(|| PyKeyError::new_err(node))
+    }
+
+    /// The offsets to its master a node measured since the last call, in
+    /// nanoseconds
+    fn take_offsets(&mut self, node: usize) -> PyResult<Vec<f64>> {
+        self.simulation
+            .take_offsets(node)
+            .ok_or_else(|| PyKeyError::new_err(node))
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "statime")]
+fn statime_module(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(parse_message, module)?)?;
+    module.add_class::<OrdinaryClock>()?;
+    module.add_class::<PySimulation>()?;
+    Ok(())
+}
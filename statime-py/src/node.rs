@@ -0,0 +1,336 @@
+//! A single ordinary clock with one port, driven from the outside.
+//!
+//! The node doesn't do any IO. Everything that happens is passed in, and each
+//! call returns what should be sent and which timers should be (re)started,
+//! as in the statime examples.
+
+use std::{cell::Cell, rc::Rc};
+
+use rand::{rngs::SmallRng, SeedableRng};
+use statime::{
+    BasicFilter, Clock, ClockIdentity, CommunicationMode, DelayMechanism, Duration, InstanceConfig,
+    Interval, LogMessageIntervals, PortConfig, PtpInstance, SdoId, Time, TimePropertiesDS,
+    TimeSource, TimestampSource, TimestampingQuality, TransmitEnable,
+};
+pub use statime_ffi::driver::StatimeTimer as Timer;
+use statime_ffi::driver::{Action, PortDriver};
+
+/// Where a virtual clock takes the passing of time from
+#[derive(Debug, Clone)]
+pub enum TimeBase {
+    /// The system clock of the host
+    System,
+    /// A simulated time in nanoseconds, shared between the clocks of a
+    /// simulation
+    Simulated(Rc<Cell<f64>>),
+}
+
+impl TimeBase {
+    fn nanos(&self) -> f64 {
+        match self {
+            TimeBase::System => {
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64()
+                    * 1e9
+            }
+            TimeBase::Simulated(now) => now.get(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClockState {
+    // Time of the base at which the state was last updated, in nanoseconds
+    base: f64,
+    // Virtual time at base, in nanoseconds
+    offset: f64,
+    // Rate of the unadjusted oscillator relative to the base
+    oscillator: f64,
+    // Frequency correction applied through adjust
+    correction: f64,
+}
+
+/// A clock on top of a [`TimeBase`]
+///
+/// Adjustments only change the virtual clock, so nodes can run without any
+/// special permissions. Clones share the same virtual clock.
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    time_base: TimeBase,
+    state: Rc<Cell<ClockState>>,
+}
+
+impl VirtualClock {
+    /// A clock that starts `offset_ns` away from its time base, and runs
+    /// `frequency_ppm` parts per million fast
+    pub fn new(time_base: TimeBase, offset_ns: f64, frequency_ppm: f64) -> Self {
+        let base = time_base.nanos();
+        Self {
+            time_base,
+            state: Rc::new(Cell::new(ClockState {
+                base,
+                offset: base + offset_ns,
+                oscillator: 1.0 + frequency_ppm * 1e-6,
+                correction: 1.0,
+            })),
+        }
+    }
+
+    /// The current time of the clock in nanoseconds
+    pub fn nanos(&self) -> f64 {
+        let state = self.state.get();
+        state.offset + (self.time_base.nanos() - state.base) * state.oscillator * state.correction
+    }
+
+    /// The difference between the clock and its time base in nanoseconds
+    pub fn offset_nanos(&self) -> f64 {
+        self.nanos() - self.time_base.nanos()
+    }
+}
+
+impl Clock for VirtualClock {
+    type Error = core::convert::Infallible;
+
+    fn now(&self) -> Time {
+        Time::from_fixed_nanos(self.nanos())
+    }
+
+    fn adjust(
+        &mut self,
+        time_offset: Duration,
+        frequency_multiplier: f64,
+        _time_properties_ds: &TimePropertiesDS,
+    ) -> Result<(), Self::Error> {
+        let base = self.time_base.nanos();
+        let state = self.state.get();
+        // The filter asks for a multiplier above one when the clock runs too
+        // fast compared to the master
+        self.state.set(ClockState {
+            base,
+            offset: self.nanos() + time_offset.nanos_lossy(),
+            correction: state.correction / frequency_multiplier,
+            ..state
+        });
+        Ok(())
+    }
+
+    fn frequency_multiplier(&self) -> Option<f64> {
+        Some(self.state.get().correction)
+    }
+}
+
+/// The name of a timer in Python
+pub fn timer_name(timer: Timer) -> &'static str {
+    match timer {
+        Timer::Announce => "announce",
+        Timer::Sync => "sync",
+        Timer::DelayRequest => "delay_request",
+        Timer::AnnounceReceipt => "announce_receipt",
+        Timer::UnicastNegotiation => "unicast_negotiation",
+    }
+}
+
+/// The timer with the given name in Python
+pub fn timer_from_name(name: &str) -> Option<Timer> {
+    [
+        Timer::Announce,
+        Timer::Sync,
+        Timer::DelayRequest,
+        Timer::AnnounceReceipt,
+        Timer::UnicastNegotiation,
+    ]
+    .into_iter()
+    .find(|timer| timer_name(*timer) == name)
+}
+
+/// Something a node asks to be done
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// Send an event message, and report the time it was sent with
+    /// [`Node::send_timestamp`], passing the context
+    Event { data: Vec<u8>, context: u32 },
+    /// Send a general message
+    General { data: Vec<u8> },
+    /// (Re)start a timer
+    Timer {
+        timer: Timer,
+        duration: core::time::Duration,
+    },
+}
+
+/// Configuration of a node
+#[derive(Debug, Clone, Copy)]
+pub struct NodeConfig {
+    pub clock_identity: ClockIdentity,
+    pub priority_1: u8,
+    pub priority_2: u8,
+    pub domain_number: u8,
+    pub slave_only: bool,
+    pub log_announce_interval: i8,
+    pub log_sync_interval: i8,
+    pub log_min_delay_request_interval: i8,
+    pub seed: u64,
+}
+
+type NodeInstance = PtpInstance<VirtualClock, BasicFilter>;
+type NodeDriver = PortDriver<'static, VirtualClock, BasicFilter, SmallRng>;
+
+/// An ordinary clock with a single port
+pub struct Node {
+    // Declared before the instance its port borrows, see the Drop
+    // implementation
+    driver: Option<NodeDriver>,
+    instance: *mut NodeInstance,
+    clock: VirtualClock,
+    started: Vec<Output>,
+}
+
+impl Node {
+    pub fn new(
+        config: NodeConfig,
+        clock: VirtualClock,
+    ) -> Result<Self, statime::InstanceConfigError> {
+        let instance_config = InstanceConfig {
+            clock_identity: config.clock_identity,
+            priority_1: config.priority_1,
+            priority_2: config.priority_2,
+            domain_number: config.domain_number,
+            slave_only: config.slave_only,
+            sdo_id: SdoId::default(),
+        };
+        instance_config.validate()?;
+
+        let instance = Box::into_raw(Box::new(PtpInstance::new(
+            instance_config,
+            TimePropertiesDS::new_arbitrary_time(false, false, TimeSource::InternalOscillator),
+            clock.clone(),
+            BasicFilter::for_quality(TimestampingQuality::Software),
+        )));
+        // SAFETY: the instance is only freed when the node is dropped, after
+        // the port borrowing it
+        let instance_ref: &'static NodeInstance = unsafe { &*instance };
+
+        let port_config = PortConfig {
+            delay_mechanism: DelayMechanism::E2E {
+                interval: Interval::from_log_2(config.log_min_delay_request_interval),
+            },
+            announce_interval: Interval::from_log_2(config.log_announce_interval),
+            announce_receipt_timeout: 3,
+            sync_interval: Interval::from_log_2(config.log_sync_interval),
+            master_only: false,
+            delay_asymmetry: Duration::ZERO,
+            ingress_latency: Duration::ZERO,
+            egress_latency: Duration::ZERO,
+            management: Default::default(),
+            communication_mode: CommunicationMode::Multicast,
+            transmit: TransmitEnable::ALL,
+            unicast_client: None,
+            log_message_intervals: LogMessageIntervals::STANDARD,
+        };
+        let rng = SmallRng::seed_from_u64(config.seed);
+        let port = instance_ref.add_port(port_config, rng);
+        let (mut driver, actions) = PortDriver::new(port, TimestampSource::Software);
+        if let Some(port) = driver.port_mut() {
+            port.set_measurement_queue(true);
+        }
+
+        Ok(Node {
+            driver: Some(driver),
+            instance,
+            clock,
+            started: outputs(actions),
+        })
+    }
+
+    /// The clock the node steers
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// The outputs resulting from creating the node
+    pub fn start(&mut self) -> Vec<Output> {
+        std::mem::take(&mut self.started)
+    }
+
+    /// Handle an event message received at `timestamp`
+    pub fn receive_event(&mut self, data: &[u8], timestamp: Time) -> Vec<Output> {
+        outputs(self.driver().receive_event(data, timestamp))
+    }
+
+    /// Handle a general message
+    pub fn receive_general(&mut self, data: &[u8]) -> Vec<Output> {
+        outputs(self.driver().receive_general(data))
+    }
+
+    /// Report the time an event message was sent. Returns `None` if the
+    /// context is unknown or was already used.
+    pub fn send_timestamp(&mut self, context: u32, timestamp: Time) -> Option<Vec<Output>> {
+        self.driver()
+            .send_timestamp(context, timestamp)
+            .map(outputs)
+    }
+
+    /// Handle the expiry of a timer
+    pub fn timer(&mut self, timer: Timer) -> Vec<Output> {
+        outputs(self.driver().timer(timer))
+    }
+
+    /// Run the best master clock algorithm
+    pub fn bmca(&mut self) -> Vec<Output> {
+        let instance = self.instance();
+        let driver = self.driver();
+        let mut port = driver.start_bmca().expect("port missing");
+        instance.bmca(&mut [&mut port]);
+        outputs(driver.end_bmca(port))
+    }
+
+    /// Interval between runs of the BMCA
+    pub fn bmca_interval(&self) -> core::time::Duration {
+        self.instance().bmca_interval()
+    }
+
+    /// The offsets to the master measured since the last call, in
+    /// nanoseconds
+    pub fn take_offsets(&mut self) -> Vec<f64> {
+        let port = self.driver().port_mut().expect("port missing");
+        std::iter::from_fn(|| port.take_measurement())
+            .map(|measurement| measurement.master_offset.nanos_lossy())
+            .collect()
+    }
+
+    fn instance(&self) -> &'static NodeInstance {
+        // SAFETY: see new
+        unsafe { &*self.instance }
+    }
+
+    fn driver(&mut self) -> &mut NodeDriver {
+        self.driver.as_mut().expect("driver missing")
+    }
+}
+
+// Nodes always use multicast and don't request unicast transmission, so the
+// unicast actions don't occur
+fn outputs(actions: Vec<Action>) -> Vec<Output> {
+    actions
+        .into_iter()
+        .filter_map(|action| match action {
+            Action::SendEvent { data, context } => Some(Output::Event { data, context }),
+            Action::SendGeneral { data } => Some(Output::General { data }),
+            Action::ResetTimer { timer, duration } => Some(Output::Timer { timer, duration }),
+            Action::SendUnicastEvent { .. }
+            | Action::SendUnicastGeneral { .. }
+            | Action::SendToUnicastMaster { .. } => None,
+        })
+        .collect()
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        // The port borrows the instance, so it has to go first
+        self.driver = None;
+        // SAFETY: created in new from a box, and no longer borrowed
+        drop(unsafe { Box::from_raw(self.instance) });
+    }
+}
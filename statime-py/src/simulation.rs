@@ -0,0 +1,286 @@
+//! A simulated network of ordinary clocks.
+//!
+//! All nodes are connected to a single multicast segment with a fixed delay.
+//! Time only passes between events, so a simulation of hours takes well under
+//! a second. Each node has a virtual clock with its own initial offset and
+//! frequency error relative to the simulated true time.
+
+use std::{
+    cell::Cell,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    rc::Rc,
+};
+
+use statime::{Clock, Time};
+
+use crate::node::{Node, NodeConfig, Output, TimeBase, Timer, VirtualClock};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Event {
+    Deliver {
+        node: usize,
+        event: bool,
+        data: Vec<u8>,
+    },
+    Timer {
+        node: usize,
+        timer: Timer,
+        generation: u64,
+    },
+    Bmca {
+        node: usize,
+    },
+}
+
+// Events at the same time are handled in the order they were scheduled
+#[derive(Debug, PartialEq, Eq)]
+struct Scheduled {
+    at: u64,
+    sequence: u64,
+    event: Event,
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.at, self.sequence).cmp(&(other.at, other.sequence))
+    }
+}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+struct SimulatedNode {
+    node: Node,
+    // Incremented when a timer is reset, to ignore earlier expiries
+    timers: HashMap<Timer, u64>,
+    offsets: Vec<f64>,
+}
+
+/// A network of simulated ordinary clocks
+pub struct Simulation {
+    now: Rc<Cell<f64>>,
+    delay_ns: u64,
+    nodes: Vec<SimulatedNode>,
+    queue: BinaryHeap<Reverse<Scheduled>>,
+    sequence: u64,
+}
+
+impl Simulation {
+    /// A network in which every message takes `delay_ns` to arrive
+    pub fn new(delay_ns: u64) -> Self {
+        Self {
+            now: Rc::new(Cell::new(0.0)),
+            delay_ns,
+            nodes: vec![],
+            queue: BinaryHeap::new(),
+            sequence: 0,
+        }
+    }
+
+    /// The simulated true time in nanoseconds
+    pub fn now_ns(&self) -> u64 {
+        self.now.get() as u64
+    }
+
+    /// Add a node whose clock starts `offset_ns` away from the true time and
+    /// runs `frequency_ppm` parts per million fast, returning its index
+    pub fn add_node(
+        &mut self,
+        config: NodeConfig,
+        offset_ns: f64,
+        frequency_ppm: f64,
+    ) -> Result<usize, statime::InstanceConfigError> {
+        let clock = VirtualClock::new(
+            TimeBase::Simulated(self.now.clone()),
+            offset_ns,
+            frequency_ppm,
+        );
+        let mut node = Node::new(config, clock)?;
+        let index = self.nodes.len();
+
+        let outputs = node.start();
+        let bmca_interval = node.bmca_interval();
+        self.nodes.push(SimulatedNode {
+            node,
+            timers: HashMap::new(),
+            offsets: vec![],
+        });
+        self.handle_outputs(index, outputs);
+        self.schedule(bmca_interval.as_nanos() as u64, Event::Bmca { node: index });
+
+        Ok(index)
+    }
+
+    /// Run the simulation for `duration_ns` of simulated time
+    pub fn run(&mut self, duration_ns: u64) {
+        let end = self.now_ns() + duration_ns;
+
+        while let Some(Reverse(scheduled)) = self.queue.peek() {
+            if scheduled.at > end {
+                break;
+            }
+            let Some(Reverse(scheduled)) = self.queue.pop() else {
+                break;
+            };
+            self.now.set(scheduled.at as f64);
+            self.handle_event(scheduled.event);
+        }
+
+        self.now.set(end as f64);
+    }
+
+    /// The difference between the clock of a node and the true time, in
+    /// nanoseconds
+    pub fn clock_offset_ns(&self, node: usize) -> Option<f64> {
+        Some(self.nodes.get(node)?.node.clock().offset_nanos())
+    }
+
+    /// The offsets to its master a node measured since the last call, in
+    /// nanoseconds
+    pub fn take_offsets(&mut self, node: usize) -> Option<Vec<f64>> {
+        Some(std::mem::take(&mut self.nodes.get_mut(node)?.offsets))
+    }
+
+    fn schedule(&mut self, after_ns: u64, event: Event) {
+        self.sequence += 1;
+        self.queue.push(Reverse(Scheduled {
+            at: self.now_ns() + after_ns,
+            sequence: self.sequence,
+            event,
+        }));
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        let (node, outputs) = match event {
+            Event::Deliver { node, event, data } => {
+                let simulated = &mut self.nodes[node].node;
+                let outputs = if event {
+                    let timestamp = simulated.clock().now();
+                    simulated.receive_event(&data, timestamp)
+                } else {
+                    simulated.receive_general(&data)
+                };
+                (node, outputs)
+            }
+            Event::Timer {
+                node,
+                timer,
+                generation,
+            } => {
+                let simulated = &mut self.nodes[node];
+                if simulated.timers.get(&timer) != Some(&generation) {
+                    return;
+                }
+                (node, simulated.node.timer(timer))
+            }
+            Event::Bmca { node } => {
+                let simulated = &mut self.nodes[node].node;
+                let outputs = simulated.bmca();
+                let interval = simulated.bmca_interval();
+                self.schedule(interval.as_nanos() as u64, Event::Bmca { node });
+                (node, outputs)
+            }
+        };
+
+        self.handle_outputs(node, outputs);
+
+        let simulated = &mut self.nodes[node];
+        let offsets = simulated.node.take_offsets();
+        simulated.offsets.extend(offsets);
+    }
+
+    fn handle_outputs(&mut self, node: usize, outputs: Vec<Output>) {
+        let mut queue = std::collections::VecDeque::from(outputs);
+
+        while let Some(output) = queue.pop_front() {
+            match output {
+                Output::Event { data, context } => {
+                    self.broadcast(node, true, data);
+                    // Packets leave right away
+                    let simulated = &mut self.nodes[node];
+                    let timestamp: Time = simulated.node.clock().now();
+                    if let Some(outputs) = simulated.node.send_timestamp(context, timestamp) {
+                        queue.extend(outputs);
+                    }
+                }
+                Output::General { data } => self.broadcast(node, false, data),
+                Output::Timer { timer, duration } => {
+                    let generation = self.nodes[node].timers.entry(timer).or_default();
+                    *generation += 1;
+                    let generation = *generation;
+                    self.schedule(
+                        duration.as_nanos() as u64,
+                        Event::Timer {
+                            node,
+                            timer,
+                            generation,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    fn broadcast(&mut self, from: usize, event: bool, data: Vec<u8>) {
+        for node in 0..self.nodes.len() {
+            if node != from {
+                self.schedule(
+                    self.delay_ns,
+                    Event::Deliver {
+                        node,
+                        event,
+                        data: data.clone(),
+                    },
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use statime::ClockIdentity;
+
+    use super::*;
+
+    fn config(identity: u8, priority_1: u8) -> NodeConfig {
+        NodeConfig {
+            clock_identity: ClockIdentity([identity; 8]),
+            priority_1,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            log_announce_interval: 0,
+            log_sync_interval: 0,
+            log_min_delay_request_interval: 0,
+            seed: identity.into(),
+        }
+    }
+
+    #[test]
+    fn slave_follows_master() {
+        let mut simulation = Simulation::new(50_000);
+        let master = simulation.add_node(config(1, 64), 0.0, 0.0).unwrap();
+        let slave = simulation
+            .add_node(config(2, 128), 2_000_000.0, 20.0)
+            .unwrap();
+
+        simulation.run(600_000_000_000);
+
+        let master_offset = simulation.clock_offset_ns(master).unwrap();
+        let slave_offset = simulation.clock_offset_ns(slave).unwrap();
+        assert_eq!(master_offset, 0.0);
+        assert!(
+            (slave_offset - master_offset).abs() < 1_000.0,
+            "slave is {slave_offset}ns off"
+        );
+
+        let offsets = simulation.take_offsets(slave).unwrap();
+        assert!(!offsets.is_empty());
+        assert!(simulation.take_offsets(master).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,28 @@
+//! The cost of handling a packet on a slave port, the path that runs for
+//! every Sync and FollowUp. Compare against an earlier run with
+//! `cargo bench -p statime --features testing -- --save-baseline <name>` and
+//! `--baseline <name>`.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn sync_exchange(c: &mut Criterion) {
+    let instance = common::instance();
+    let mut port = common::slave_port(&instance);
+
+    // With logging disabled, like in a release build on a small target
+    log::set_max_level(log::LevelFilter::Off);
+
+    let mut sequence_id = 0u16;
+    c.bench_function("sync and follow up", |b| {
+        b.iter(|| {
+            common::exchange(&mut port, sequence_id);
+            sequence_id = sequence_id.wrapping_add(1);
+        })
+    });
+}
+
+criterion_group!(benches, sync_exchange);
+criterion_main!(benches);
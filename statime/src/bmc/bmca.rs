@@ -5,6 +5,7 @@ use core::cmp::Ordering;
 use super::{
     dataset_comparison::{ComparisonDataset, DatasetOrdering},
     foreign_master::ForeignMasterList,
+    MasterSelection,
 };
 use crate::{
     datastructures::{
@@ -28,6 +29,10 @@ use crate::{
 ///   be done using [Bmca::find_best_announce_message]
 /// - Then to get the recommended state for each port,
 ///   [Bmca::calculate_recommended_state] needs to be called
+///
+/// Every comparison between two candidate masters is delegated to a
+/// [MasterSelection], so profiles can replace the ordering while keeping the
+/// rest of the algorithm.
 #[derive(Debug)]
 pub(crate) struct Bmca {
     foreign_master_list: ForeignMasterList,
@@ -61,10 +66,12 @@ impl Bmca {
         }
     }
 
-    /// Takes the Erbest from this port
+    /// Takes the Erbest from this port, which has the given localPriority
     pub(crate) fn take_best_port_announce_message(
         &mut self,
         current_time: WireTimestamp,
+        selection: &impl MasterSelection,
+        local_priority: u8,
     ) -> Option<BestAnnounceMessage> {
         // Find the announce message we want to use from each foreign master that has
         // qualified messages
@@ -73,14 +80,15 @@ impl Bmca {
             .take_qualified_announce_messages(current_time);
 
         // The best of the foreign master messages is our erbest
-        let erbest =
-            Self::find_best_announce_message(announce_messages.map(|(message, timestamp)| {
-                BestAnnounceMessage {
-                    message,
-                    timestamp,
-                    identity: self.own_port_identity,
-                }
-            }));
+        let erbest = Self::find_best_announce_message(
+            selection,
+            announce_messages.map(|(message, timestamp)| BestAnnounceMessage {
+                message,
+                timestamp,
+                identity: self.own_port_identity,
+                local_priority,
+            }),
+        );
 
         if let Some(best) = &erbest {
             // All messages that were considered have been removed from the
@@ -96,24 +104,25 @@ impl Bmca {
     /// The port identity in the tuple is the identity of the port that received
     /// the announce message.
     pub(crate) fn find_best_announce_message(
+        selection: &impl MasterSelection,
         announce_messages: impl IntoIterator<Item = BestAnnounceMessage>,
     ) -> Option<BestAnnounceMessage> {
         announce_messages
             .into_iter()
-            .max_by(BestAnnounceMessage::compare)
+            .max_by(|a, b| a.compare(b, selection))
     }
 
     fn compare_d0_best(
+        selection: &impl MasterSelection,
         d0: &ComparisonDataset,
         opt_best: Option<BestAnnounceMessage>,
     ) -> MessageComparison {
         match opt_best {
             None => MessageComparison::Better,
             Some(best) => {
-                let dataset =
-                    ComparisonDataset::from_announce_message(&best.message, &best.identity);
+                let dataset = best.dataset();
 
-                match d0.compare(&dataset).as_ordering() {
+                match selection.compare(d0, &dataset).as_ordering() {
                     Ordering::Less => MessageComparison::Worse(best),
                     Ordering::Equal => MessageComparison::Same,
                     Ordering::Greater => MessageComparison::Better,
@@ -141,6 +150,7 @@ impl Bmca {
     /// If None is returned, then the port should remain in the same state as it
     /// is now.
     pub(crate) fn calculate_recommended_state(
+        selection: &impl MasterSelection,
         own_data: &DefaultDS,
         best_global_announce_message: Option<BestAnnounceMessage>,
         best_port_announce_message: Option<BestAnnounceMessage>,
@@ -148,15 +158,17 @@ impl Bmca {
     ) -> Option<RecommendedState> {
         if best_global_announce_message.is_none() && matches!(port_state, PortState::Listening) {
             None
-        } else if (1..=127).contains(&own_data.clock_quality.clock_class) {
+        } else if own_data.clock_quality.clock_class.is_master_only() {
             // only consider the best message of the port
             Some(Self::calculate_recommended_state_low_class(
+                selection,
                 own_data,
                 best_port_announce_message,
             ))
         } else {
             // see if the best of this port is better than the global best
             Some(Self::calculate_recommended_state_high_class(
+                selection,
                 own_data,
                 best_global_announce_message,
                 best_port_announce_message,
@@ -165,12 +177,13 @@ impl Bmca {
     }
 
     fn calculate_recommended_state_low_class(
+        selection: &impl MasterSelection,
         own_data: &DefaultDS,
         best_port_announce_message: Option<BestAnnounceMessage>,
     ) -> RecommendedState {
         let d0 = ComparisonDataset::from_own_data(own_data);
 
-        match Self::compare_d0_best(&d0, best_port_announce_message) {
+        match Self::compare_d0_best(selection, &d0, best_port_announce_message) {
             MessageComparison::Better => RecommendedState::M1(*own_data),
             MessageComparison::Same => RecommendedState::M1(*own_data),
             MessageComparison::Worse(port) => RecommendedState::P1(port.message),
@@ -178,23 +191,27 @@ impl Bmca {
     }
 
     fn calculate_recommended_state_high_class(
+        selection: &impl MasterSelection,
         own_data: &DefaultDS,
         best_global_announce_message: Option<BestAnnounceMessage>,
         best_port_announce_message: Option<BestAnnounceMessage>,
     ) -> RecommendedState {
         let d0 = ComparisonDataset::from_own_data(own_data);
 
-        match Self::compare_d0_best(&d0, best_global_announce_message) {
+        match Self::compare_d0_best(selection, &d0, best_global_announce_message) {
             MessageComparison::Better => RecommendedState::M2(*own_data),
             MessageComparison::Same => RecommendedState::M2(*own_data),
             MessageComparison::Worse(global_message) => match best_port_announce_message {
                 None => RecommendedState::M3(global_message.message),
-                Some(port_message) => Self::compare_global_and_port(global_message, port_message),
+                Some(port_message) => {
+                    Self::compare_global_and_port(selection, global_message, port_message)
+                }
             },
         }
     }
 
     fn compare_global_and_port(
+        selection: &impl MasterSelection,
         global_message: BestAnnounceMessage,
         port_message: BestAnnounceMessage,
     ) -> RecommendedState {
@@ -202,18 +219,14 @@ impl Bmca {
             // effectively, E_best == E_rbest
             RecommendedState::S1(global_message.message)
         } else {
-            let ebest = ComparisonDataset::from_announce_message(
-                &global_message.message,
-                &global_message.identity,
-            );
-
-            let erbest = ComparisonDataset::from_announce_message(
-                &port_message.message,
-                &port_message.identity,
-            );
+            let ebest = global_message.dataset();
+            let erbest = port_message.dataset();
 
             // E_best better by topology than E_rbest
-            if matches!(ebest.compare(&erbest), DatasetOrdering::BetterByTopology) {
+            if matches!(
+                selection.compare(&ebest, &erbest),
+                DatasetOrdering::BetterByTopology
+            ) {
                 RecommendedState::P2(port_message.message)
             } else {
                 RecommendedState::M3(global_message.message)
@@ -234,20 +247,25 @@ pub(crate) struct BestAnnounceMessage {
     message: AnnounceMessage,
     timestamp: WireTimestamp,
     identity: PortIdentity,
+    // The localPriority of the receiving port
+    local_priority: u8,
 }
 
 impl BestAnnounceMessage {
-    fn compare(&self, other: &Self) -> Ordering {
+    fn compare(&self, other: &Self, selection: &impl MasterSelection) -> Ordering {
         // use the timestamp as a tie-break if needed (prefer newer messages)
         let tie_break = self.timestamp.cmp(&other.timestamp);
-        self.compare_dataset(other).as_ordering().then(tie_break)
+        self.compare_dataset(other, selection)
+            .as_ordering()
+            .then(tie_break)
     }
 
-    fn compare_dataset(&self, other: &Self) -> DatasetOrdering {
-        let data1 = ComparisonDataset::from_announce_message(&self.message, &self.identity);
-        let data2 = ComparisonDataset::from_announce_message(&other.message, &other.identity);
+    fn compare_dataset(&self, other: &Self, selection: &impl MasterSelection) -> DatasetOrdering {
+        selection.compare(&self.dataset(), &other.dataset())
+    }
 
-        data1.compare(&data2)
+    fn dataset(&self) -> ComparisonDataset {
+        ComparisonDataset::from_announce_message(&self.message, &self.identity, self.local_priority)
     }
 }
 
@@ -266,9 +284,10 @@ pub(crate) enum RecommendedState {
 mod tests {
     use super::*;
     use crate::{
+        bmc::DefaultMasterSelection,
         config::InstanceConfig,
         datastructures::messages::{Header, PtpVersion},
-        ClockIdentity,
+        ClockClass, ClockIdentity,
     };
 
     fn default_announce_message() -> AnnounceMessage {
@@ -304,6 +323,7 @@ mod tests {
             grandmaster_identity: Default::default(),
             steps_removed: Default::default(),
             time_source: Default::default(),
+            path_trace: Default::default(),
         }
     }
 
@@ -324,6 +344,7 @@ mod tests {
             message,
             timestamp,
             identity,
+            local_priority: 128,
         }
     }
 
@@ -332,7 +353,9 @@ mod tests {
         let message1 = default_best_announce_message();
         let message2 = default_best_announce_message();
 
-        let ordering = message1.compare_dataset(&message2).as_ordering();
+        let ordering = message1
+            .compare_dataset(&message2, &DefaultMasterSelection)
+            .as_ordering();
         assert_eq!(ordering, Ordering::Equal);
     }
 
@@ -350,11 +373,23 @@ mod tests {
         message2.message.grandmaster_priority_1 = 1;
 
         // hence we expect message1 to be better than message2
-        assert_eq!(message1.compare_dataset(&message2), DatasetOrdering::Better);
-        assert_eq!(message2.compare_dataset(&message1), DatasetOrdering::Worse);
+        assert_eq!(
+            message1.compare_dataset(&message2, &DefaultMasterSelection),
+            DatasetOrdering::Better
+        );
+        assert_eq!(
+            message2.compare_dataset(&message1, &DefaultMasterSelection),
+            DatasetOrdering::Worse
+        );
 
-        assert_eq!(message1.compare(&message2), Ordering::Greater);
-        assert_eq!(message2.compare(&message1), Ordering::Less);
+        assert_eq!(
+            message1.compare(&message2, &DefaultMasterSelection),
+            Ordering::Greater
+        );
+        assert_eq!(
+            message2.compare(&message1, &DefaultMasterSelection),
+            Ordering::Less
+        );
     }
 
     #[test]
@@ -374,11 +409,71 @@ mod tests {
         // the newest message should be preferred
         assert!(message2.timestamp > message1.timestamp);
 
-        let ordering = message1.compare_dataset(&message2).as_ordering();
+        let ordering = message1
+            .compare_dataset(&message2, &DefaultMasterSelection)
+            .as_ordering();
         assert_eq!(ordering, Ordering::Equal);
 
         // so message1 is lower in the ordering than message2
-        assert_eq!(message1.compare(&message2), Ordering::Less)
+        assert_eq!(
+            message1.compare(&message2, &DefaultMasterSelection),
+            Ordering::Less
+        )
+    }
+
+    // Prefers a fixed grandmaster, as in a statically configured network
+    struct PreferredGrandmaster(ClockIdentity);
+
+    impl MasterSelection for PreferredGrandmaster {
+        fn compare(&self, a: &ComparisonDataset, b: &ComparisonDataset) -> DatasetOrdering {
+            match (a.gm_identity() == self.0, b.gm_identity() == self.0) {
+                (true, false) => DatasetOrdering::Better,
+                (false, true) => DatasetOrdering::Worse,
+                _ => a.compare(b),
+            }
+        }
+    }
+
+    #[test]
+    fn custom_master_selection() {
+        let mut message1 = default_best_announce_message();
+        let mut message2 = default_best_announce_message();
+
+        message1.message.grandmaster_identity = ClockIdentity([1; 8]);
+        message1.message.grandmaster_priority_1 = 0;
+        message2.message.grandmaster_identity = ClockIdentity([2; 8]);
+        message2.message.grandmaster_priority_1 = 255;
+
+        let best = Bmca::find_best_announce_message(&DefaultMasterSelection, [message1, message2]);
+        assert_eq!(best.unwrap().message, message1.message);
+
+        let selection = PreferredGrandmaster(ClockIdentity([2; 8]));
+        let best = Bmca::find_best_announce_message(&selection, [message1, message2]);
+        assert_eq!(best.unwrap().message, message2.message);
+
+        // The state decision algorithm uses the same ordering
+        let mut own_data = default_own_data();
+        own_data.clock_quality.clock_class = ClockClass::Default;
+        assert!(matches!(
+            Bmca::calculate_recommended_state(
+                &selection,
+                &own_data,
+                best,
+                best,
+                &PortState::Listening
+            ),
+            Some(RecommendedState::S1(_))
+        ));
+        assert!(matches!(
+            Bmca::calculate_recommended_state(
+                &DefaultMasterSelection,
+                &own_data,
+                best,
+                best,
+                &PortState::Listening
+            ),
+            Some(RecommendedState::M2(_))
+        ));
     }
 
     fn default_own_data() -> DefaultDS {
@@ -404,10 +499,17 @@ mod tests {
         let mut own_data = default_own_data();
 
         // zero is reserved
-        own_data.clock_quality.clock_class = 1;
+        own_data.clock_quality.clock_class = ClockClass::PrimaryReference;
 
-        let call =
-            |port_state| Bmca::calculate_recommended_state(&own_data, None, None, port_state);
+        let call = |port_state| {
+            Bmca::calculate_recommended_state(
+                &DefaultMasterSelection,
+                &own_data,
+                None,
+                None,
+                port_state,
+            )
+        };
 
         // when E_best is empty and the port state is listening, it should remain
         // listening
@@ -438,8 +540,8 @@ mod tests {
             sdo_id,
         });
 
-        own_data.clock_quality.clock_class = 1;
-        assert!((1..=127).contains(&own_data.clock_quality.clock_class));
+        own_data.clock_quality.clock_class = ClockClass::PrimaryReference;
+        assert!(own_data.clock_quality.clock_class.is_master_only());
 
         // D0 is the same as E_rbest; this is unreachable in practice, but we return M1
         // in this case
@@ -447,13 +549,14 @@ mod tests {
         let port_message = default_best_announce_message();
 
         assert!(matches!(
-            Bmca::compare_d0_best(&d0, Some(port_message)),
+            Bmca::compare_d0_best(&DefaultMasterSelection, &d0, Some(port_message)),
             MessageComparison::Same
         ));
 
         assert_eq!(
             Some(RecommendedState::M1(own_data)),
             Bmca::calculate_recommended_state(
+                &DefaultMasterSelection,
                 &own_data,
                 None,
                 Some(port_message),
@@ -468,13 +571,14 @@ mod tests {
         port_message.identity.port_number = 1;
 
         assert!(matches!(
-            Bmca::compare_d0_best(&d0, Some(port_message)),
+            Bmca::compare_d0_best(&DefaultMasterSelection, &d0, Some(port_message)),
             MessageComparison::Better
         ));
 
         assert_eq!(
             Some(RecommendedState::M1(own_data)),
             Bmca::calculate_recommended_state(
+                &DefaultMasterSelection,
                 &own_data,
                 None,
                 Some(port_message),
@@ -496,13 +600,14 @@ mod tests {
         let d0 = ComparisonDataset::from_own_data(&own_data);
 
         assert!(matches!(
-            Bmca::compare_d0_best(&d0, Some(port_message)),
+            Bmca::compare_d0_best(&DefaultMasterSelection, &d0, Some(port_message)),
             MessageComparison::Worse(_)
         ));
 
         assert_eq!(
             Some(RecommendedState::P1(port_message.message)),
             Bmca::calculate_recommended_state(
+                &DefaultMasterSelection,
                 &own_data,
                 None,
                 Some(port_message),
@@ -515,8 +620,8 @@ mod tests {
     fn recommend_state_high() {
         let mut own_data = default_own_data();
 
-        own_data.clock_quality.clock_class = 128;
-        assert!(!(1..=127).contains(&own_data.clock_quality.clock_class));
+        own_data.clock_quality.clock_class = ClockClass::Reserved(128);
+        assert!(!own_data.clock_quality.clock_class.is_master_only());
 
         // D0 is the same as E_best; this is unreachable in practice, but we return M2
         // in this case
@@ -524,13 +629,14 @@ mod tests {
         let global_message = default_best_announce_message();
 
         assert!(matches!(
-            Bmca::compare_d0_best(&d0, Some(global_message)),
+            Bmca::compare_d0_best(&DefaultMasterSelection, &d0, Some(global_message)),
             MessageComparison::Same
         ));
 
         assert_eq!(
             Some(RecommendedState::M2(own_data)),
             Bmca::calculate_recommended_state(
+                &DefaultMasterSelection,
                 &own_data,
                 Some(global_message),
                 None,
@@ -545,13 +651,14 @@ mod tests {
         global_message.identity.port_number = 1;
 
         assert!(matches!(
-            Bmca::compare_d0_best(&d0, Some(global_message)),
+            Bmca::compare_d0_best(&DefaultMasterSelection, &d0, Some(global_message)),
             MessageComparison::Better
         ));
 
         assert_eq!(
             Some(RecommendedState::M2(own_data)),
             Bmca::calculate_recommended_state(
+                &DefaultMasterSelection,
                 &own_data,
                 Some(global_message),
                 None,
@@ -573,13 +680,14 @@ mod tests {
         let d0 = ComparisonDataset::from_own_data(&own_data);
 
         assert!(matches!(
-            Bmca::compare_d0_best(&d0, Some(global_message)),
+            Bmca::compare_d0_best(&DefaultMasterSelection, &d0, Some(global_message)),
             MessageComparison::Worse(_)
         ));
 
         assert_eq!(
             Some(RecommendedState::S1(global_message.message)),
             Bmca::calculate_recommended_state(
+                &DefaultMasterSelection,
                 &own_data,
                 Some(global_message),
                 Some(global_message),
@@ -594,7 +702,7 @@ mod tests {
         let mut global_message = default_best_announce_message();
 
         // take the erest branch
-        own_data.clock_quality.clock_class = 128;
+        own_data.clock_quality.clock_class = ClockClass::Reserved(128);
 
         own_data.clock_identity = ClockIdentity([0; 8]);
         global_message.message.grandmaster_identity = ClockIdentity([1; 8]);
@@ -613,13 +721,8 @@ mod tests {
             nanos: 4,
         };
 
-        let ebest = ComparisonDataset::from_announce_message(
-            &global_message.message,
-            &global_message.identity,
-        );
-
-        let erbest =
-            ComparisonDataset::from_announce_message(&port_message.message, &port_message.identity);
+        let ebest = global_message.dataset();
+        let erbest = port_message.dataset();
 
         assert!(!matches!(
             ebest.compare(&erbest),
@@ -629,6 +732,7 @@ mod tests {
         assert_eq!(
             Some(RecommendedState::M3(global_message.message)),
             Bmca::calculate_recommended_state(
+                &DefaultMasterSelection,
                 &own_data,
                 Some(global_message),
                 Some(port_message),
@@ -643,7 +747,7 @@ mod tests {
         let mut global_message = default_best_announce_message();
 
         // take the erest branch
-        own_data.clock_quality.clock_class = 128;
+        own_data.clock_quality.clock_class = ClockClass::Reserved(128);
 
         own_data.clock_identity = ClockIdentity([0; 8]);
         global_message.message.grandmaster_identity = ClockIdentity([1; 8]);
@@ -662,13 +766,8 @@ mod tests {
             nanos: 4,
         };
 
-        let ebest = ComparisonDataset::from_announce_message(
-            &global_message.message,
-            &global_message.identity,
-        );
-
-        let erbest =
-            ComparisonDataset::from_announce_message(&port_message.message, &port_message.identity);
+        let ebest = global_message.dataset();
+        let erbest = port_message.dataset();
 
         assert!(!matches!(
             ebest.compare(&erbest),
@@ -678,6 +777,7 @@ mod tests {
         assert_eq!(
             Some(RecommendedState::M3(global_message.message)),
             Bmca::calculate_recommended_state(
+                &DefaultMasterSelection,
                 &own_data,
                 Some(global_message),
                 Some(port_message),
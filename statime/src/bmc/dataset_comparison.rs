@@ -2,6 +2,7 @@
 
 use core::cmp::Ordering;
 
+use super::MasterSelection;
 use crate::datastructures::{
     common::{ClockIdentity, ClockQuality, PortIdentity},
     datasets::DefaultDS,
@@ -10,11 +11,10 @@ use crate::datastructures::{
 
 /// A collection of data that is gathered from other sources (mainly announce
 /// messages and the DefaultDS). When gathered from two different sources, the
-/// [compare](crate::bmc::dataset_comparison::ComparisonDataset) method can be
-/// used to find out which source is better according to the dataset comparison
-/// algorithm.
+/// [compare](ComparisonDataset::compare) method can be used to find out which
+/// source is better according to the dataset comparison algorithm.
 #[derive(Eq, PartialEq, Default, Debug)]
-pub(crate) struct ComparisonDataset {
+pub struct ComparisonDataset {
     gm_priority_1: u8,
     gm_identity: ClockIdentity,
     gm_clock_quality: ClockQuality,
@@ -22,14 +22,17 @@ pub(crate) struct ComparisonDataset {
     steps_removed: u16,
     identity_of_senders: ClockIdentity,
     identity_of_receiver: PortIdentity,
+    local_priority: u8,
 }
 
 impl ComparisonDataset {
     /// Create a ComparisonDataset from the data in an announce message and the
-    /// port identity of the port that received the announce message
+    /// port identity and localPriority of the port that received the announce
+    /// message
     pub(crate) fn from_announce_message(
         message: &AnnounceMessage,
         port_receiver_identity: &PortIdentity,
+        local_priority: u8,
     ) -> Self {
         Self {
             gm_priority_1: message.grandmaster_priority_1,
@@ -39,6 +42,7 @@ impl ComparisonDataset {
             steps_removed: message.steps_removed,
             identity_of_senders: message.header.source_port_identity.clock_identity,
             identity_of_receiver: *port_receiver_identity,
+            local_priority,
         }
     }
 
@@ -54,11 +58,61 @@ impl ComparisonDataset {
                 clock_identity: data.clock_identity,
                 port_number: 0,
             },
+            local_priority: data.local_priority,
         }
     }
 
-    /// Returns the ordering of `self` in comparison to other.
-    pub(crate) fn compare(&self, other: &Self) -> DatasetOrdering {
+    /// Priority 1 of the grandmaster
+    pub fn gm_priority_1(&self) -> u8 {
+        self.gm_priority_1
+    }
+
+    /// Identity of the grandmaster
+    pub fn gm_identity(&self) -> ClockIdentity {
+        self.gm_identity
+    }
+
+    /// Clock quality of the grandmaster
+    pub fn gm_clock_quality(&self) -> ClockQuality {
+        self.gm_clock_quality
+    }
+
+    /// Priority 2 of the grandmaster
+    pub fn gm_priority_2(&self) -> u8 {
+        self.gm_priority_2
+    }
+
+    /// Number of boundary clocks between the grandmaster and the receiver
+    pub fn steps_removed(&self) -> u16 {
+        self.steps_removed
+    }
+
+    /// Identity of the clock that sent the announce message
+    pub fn identity_of_senders(&self) -> ClockIdentity {
+        self.identity_of_senders
+    }
+
+    /// Clock identity of the port that received the announce message
+    pub fn receiver_clock_identity(&self) -> ClockIdentity {
+        self.identity_of_receiver.clock_identity
+    }
+
+    /// Port number of the port that received the announce message
+    pub fn receiver_port_number(&self) -> u16 {
+        self.identity_of_receiver.port_number
+    }
+
+    /// The localPriority of the port that received the announce message, or
+    /// that of the local clock for the local clock itself. Only alternate
+    /// BMCAs such as [`TelecomMasterSelection`](super::TelecomMasterSelection)
+    /// use it.
+    pub fn local_priority(&self) -> u8 {
+        self.local_priority
+    }
+
+    /// Returns the ordering of `self` in comparison to other according to the
+    /// dataset comparison algorithm of IEEE1588-2019 section 9.3.4.
+    pub fn compare(&self, other: &Self) -> DatasetOrdering {
         if self.gm_identity == other.gm_identity {
             Self::compare_same_identity(self, other)
         } else {
@@ -74,9 +128,16 @@ impl ComparisonDataset {
         // Figure 34
         let ordering = (self.gm_priority_1.cmp(&other.gm_priority_1))
             .then_with(|| self_quality.clock_class.cmp(&other_quality.clock_class))
-            // The spec assumes numerical ordering (which is the reverse of the semantic ordering)
-            .then_with(|| self_quality.clock_accuracy.cmp_numeric(&other_quality.clock_accuracy))
-            .then_with(|| self_quality.offset_scaled_log_variance.cmp(&other_quality.offset_scaled_log_variance))
+            .then_with(|| {
+                self_quality
+                    .clock_accuracy
+                    .cmp(&other_quality.clock_accuracy)
+            })
+            .then_with(|| {
+                self_quality
+                    .offset_scaled_log_variance
+                    .cmp(&other_quality.offset_scaled_log_variance)
+            })
             .then_with(|| self.gm_priority_2.cmp(&other.gm_priority_2))
             .then_with(|| self.gm_identity.cmp(&other.gm_identity));
 
@@ -88,7 +149,7 @@ impl ComparisonDataset {
     }
 
     /// Potentially the same PTP grandmaster instance
-    fn compare_same_identity(&self, other: &Self) -> DatasetOrdering {
+    pub(super) fn compare_same_identity(&self, other: &Self) -> DatasetOrdering {
         let steps_removed_difference = self.steps_removed as i32 - other.steps_removed as i32;
 
         // Figure 35
@@ -147,6 +208,17 @@ pub enum DatasetOrdering {
     Worse,
 }
 
+/// The master selection of IEEE1588-2019, ordering candidates with
+/// [`ComparisonDataset::compare`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DefaultMasterSelection;
+
+impl MasterSelection for DefaultMasterSelection {
+    fn compare(&self, a: &ComparisonDataset, b: &ComparisonDataset) -> DatasetOrdering {
+        a.compare(b)
+    }
+}
+
 impl DatasetOrdering {
     pub const fn as_ordering(self) -> Ordering {
         // We get errors if two announce messages are (functionally) the same
@@ -162,7 +234,7 @@ impl DatasetOrdering {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::datastructures::common::ClockAccuracy;
+    use crate::datastructures::common::{ClockAccuracy, ClockClass};
 
     const IDENTITY_A: ClockIdentity = ClockIdentity([1, 1, 1, 1, 1, 1, 1, 1]);
     const IDENTITY_B: ClockIdentity = ClockIdentity([2, 2, 2, 2, 2, 2, 2, 2]);
@@ -205,8 +277,8 @@ mod tests {
         assert_eq!(a.compare(&b), DatasetOrdering::Worse);
         assert_eq!(b.compare(&a), DatasetOrdering::Better);
 
-        a.gm_clock_quality.clock_class = 0;
-        b.gm_clock_quality.clock_class = 1;
+        a.gm_clock_quality.clock_class = ClockClass::PrimaryReference;
+        b.gm_clock_quality.clock_class = ClockClass::PrimaryReferenceHoldover;
 
         assert_eq!(a.compare(&b), DatasetOrdering::Better);
         assert_eq!(b.compare(&a), DatasetOrdering::Worse);
@@ -218,6 +290,85 @@ mod tests {
         assert_eq!(b.compare(&a), DatasetOrdering::Better);
     }
 
+    /// The fields of figure 34, from most to least significant
+    const TIE_BREAKING_ORDER: [&str; 6] = [
+        "priority_1",
+        "clock_class",
+        "clock_accuracy",
+        "offset_scaled_log_variance",
+        "priority_2",
+        "identity",
+    ];
+
+    // Give the dataset the better (rank 0) or worse (rank 1) value of a field
+    fn set_rank(dataset: &mut ComparisonDataset, field: &str, rank: u8) {
+        let quality = &mut dataset.gm_clock_quality;
+        match field {
+            "priority_1" => dataset.gm_priority_1 = 100 + rank,
+            "clock_class" => quality.clock_class = ClockClass::from(100 + rank),
+            "clock_accuracy" => {
+                quality.clock_accuracy = [ClockAccuracy::NS1, ClockAccuracy::US1][rank as usize]
+            }
+            "offset_scaled_log_variance" => quality.offset_scaled_log_variance = 100 + rank as u16,
+            "priority_2" => dataset.gm_priority_2 = 100 + rank,
+            "identity" => dataset.gm_identity = [IDENTITY_A, IDENTITY_B][rank as usize],
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn tie_breaking_order() {
+        // For every field, a dataset that is better in that field must win from
+        // one that is better in every less significant field
+        for (index, field) in TIE_BREAKING_ORDER.iter().enumerate() {
+            let (mut a, mut b) = get_default_test_pair();
+
+            set_rank(&mut a, field, 0);
+            set_rank(&mut b, field, 1);
+
+            for less_significant in &TIE_BREAKING_ORDER[index + 1..] {
+                set_rank(&mut a, less_significant, 1);
+                set_rank(&mut b, less_significant, 0);
+            }
+
+            // The identity must always differ to end up in figure 34
+            if *field != "identity" {
+                assert_ne!(a.gm_identity, b.gm_identity);
+            }
+
+            assert_eq!(a.compare(&b), DatasetOrdering::Better, "{field}");
+            assert_eq!(b.compare(&a), DatasetOrdering::Worse, "{field}");
+        }
+    }
+
+    #[test]
+    fn tie_breaking_pairs() {
+        // Every pair of fields in isolation, with all other fields equal
+        for (index, field) in TIE_BREAKING_ORDER.iter().enumerate() {
+            for less_significant in &TIE_BREAKING_ORDER[index + 1..] {
+                let (mut a, mut b) = get_default_test_pair();
+                a.gm_identity = IDENTITY_B;
+                b.gm_identity = IDENTITY_C;
+
+                set_rank(&mut a, field, 0);
+                set_rank(&mut b, field, 1);
+                set_rank(&mut a, less_significant, 1);
+                set_rank(&mut b, less_significant, 0);
+
+                assert_eq!(
+                    a.compare(&b),
+                    DatasetOrdering::Better,
+                    "{field} over {less_significant}"
+                );
+                assert_eq!(
+                    b.compare(&a),
+                    DatasetOrdering::Worse,
+                    "{field} over {less_significant}"
+                );
+            }
+        }
+    }
+
     #[test]
     fn figure_35() {
         let (mut a, mut b) = get_default_test_pair();
@@ -219,7 +219,16 @@ impl ForeignMasterList {
             return false;
         }
 
-        // 4. The announce message may not be from a foreign master with fewer messages
+        // 4. The announce message must not have passed us before. Otherwise it went
+        // around a loop, and we would be synchronizing to ourselves.
+        if announce_message
+            .path_trace
+            .contains(self.own_port_identity.clock_identity)
+        {
+            return false;
+        }
+
+        // 5. The announce message may not be from a foreign master with fewer messages
         // than FOREIGN_MASTER_THRESHOLD, but that is handled in the
         // `take_qualified_announce_messages` method.
 
@@ -3,3 +3,33 @@
 pub mod bmca;
 pub mod dataset_comparison;
 pub mod foreign_master;
+pub mod telecom;
+
+pub use dataset_comparison::{ComparisonDataset, DatasetOrdering, DefaultMasterSelection};
+pub use telecom::TelecomMasterSelection;
+
+/// The ordering of candidate masters used by the best master clock algorithm.
+///
+/// The foreign master bookkeeping and the state decision algorithm are the
+/// same for every profile, but profiles such as the telecom alternate BMCA or
+/// statically configured networks order the candidates differently. They can
+/// implement this trait and pass it to
+/// [`PtpInstance::with_master_selection`](crate::PtpInstance::with_master_selection).
+///
+/// This crate provides [`DefaultMasterSelection`], which implements the
+/// dataset comparison algorithm of IEEE1588-2019 section 9.3.4, and
+/// [`TelecomMasterSelection`] for the ITU-T G.8275.1 telecom profile. A
+/// [`Profile`](crate::Profile) orders candidates with the selection it
+/// specifies.
+pub trait MasterSelection {
+    /// Determine how candidate `a` ranks compared to candidate `b`.
+    ///
+    /// Either candidate can be the local clock itself, which has
+    /// [`ComparisonDataset::steps_removed`] zero and is its own sender. The
+    /// topology orderings are used by the state decision algorithm to decide
+    /// between the passive and master state, so implementations that only
+    /// care about the grandmaster usually fall back to
+    /// [`ComparisonDataset::compare`] when both candidates have the same
+    /// grandmaster.
+    fn compare(&self, a: &ComparisonDataset, b: &ComparisonDataset) -> DatasetOrdering;
+}
@@ -0,0 +1,201 @@
+//! The alternate BMCA of the ITU-T G.8275.1 telecom profile
+
+use core::cmp::Ordering;
+
+use super::{ComparisonDataset, DatasetOrdering, MasterSelection};
+
+/// The master selection of the ITU-T G.8275.1 telecom profile (section 6.3),
+/// see [`Profile::G8275_1`](crate::Profile::G8275_1).
+///
+/// Candidates are compared by the clock class, clock accuracy, offset scaled
+/// log variance and priority 2 of their grandmaster, and then by the
+/// localPriority of the port that received them, or that of the clock itself
+/// for the local clock. Priority 1 is fixed by the profile and not compared.
+///
+/// Grandmasters with a clock class of 127 or less are traceable to a primary
+/// reference, and the profile treats those that are equal up to here as
+/// equally good: rather than by their clock identity, they are ordered by
+/// topology as if they were the same grandmaster, so each clock follows the
+/// nearest one. Other grandmasters are ordered by their clock identity.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TelecomMasterSelection;
+
+impl MasterSelection for TelecomMasterSelection {
+    fn compare(&self, a: &ComparisonDataset, b: &ComparisonDataset) -> DatasetOrdering {
+        let a_quality = a.gm_clock_quality();
+        let b_quality = b.gm_clock_quality();
+
+        // Figure 2 of G.8275.1
+        let ordering = (a_quality.clock_class.cmp(&b_quality.clock_class))
+            .then_with(|| a_quality.clock_accuracy.cmp(&b_quality.clock_accuracy))
+            .then_with(|| {
+                a_quality
+                    .offset_scaled_log_variance
+                    .cmp(&b_quality.offset_scaled_log_variance)
+            })
+            .then_with(|| a.gm_priority_2().cmp(&b.gm_priority_2()))
+            .then_with(|| a.local_priority().cmp(&b.local_priority()));
+
+        match ordering {
+            Ordering::Less => DatasetOrdering::Better,
+            Ordering::Greater => DatasetOrdering::Worse,
+            Ordering::Equal
+                if a.gm_identity() == b.gm_identity() || a_quality.clock_class.is_master_only() =>
+            {
+                // Figure 3 of G.8275.1, which is figure 35 of IEEE1588-2019
+                a.compare_same_identity(b)
+            }
+            Ordering::Equal => match a.gm_identity().cmp(&b.gm_identity()) {
+                Ordering::Greater => DatasetOrdering::Worse,
+                _ => DatasetOrdering::Better,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::InstanceConfig,
+        datastructures::{
+            common::{ClockClass, ClockIdentity, PortIdentity},
+            datasets::DefaultDS,
+            messages::{AnnounceMessage, Header},
+        },
+    };
+
+    const GM_A: ClockIdentity = ClockIdentity([1; 8]);
+    const GM_B: ClockIdentity = ClockIdentity([2; 8]);
+    const LOCAL: ClockIdentity = ClockIdentity([3; 8]);
+
+    // An announce message of `grandmaster`, sent by the grandmaster itself
+    // when `steps_removed` is 0 and by `sender` otherwise
+    fn announce(
+        grandmaster: ClockIdentity,
+        clock_class: ClockClass,
+        steps_removed: u16,
+        sender: ClockIdentity,
+    ) -> AnnounceMessage {
+        let mut message = AnnounceMessage {
+            header: Header::default(),
+            origin_timestamp: Default::default(),
+            current_utc_offset: 37,
+            grandmaster_priority_1: 128,
+            grandmaster_clock_quality: Default::default(),
+            grandmaster_priority_2: 128,
+            grandmaster_identity: grandmaster,
+            steps_removed,
+            time_source: Default::default(),
+            path_trace: Default::default(),
+        };
+        message.grandmaster_clock_quality.clock_class = clock_class;
+        message.header.source_port_identity.clock_identity = sender;
+        message
+    }
+
+    fn received(
+        message: &AnnounceMessage,
+        port_number: u16,
+        local_priority: u8,
+    ) -> ComparisonDataset {
+        let receiver = PortIdentity {
+            clock_identity: LOCAL,
+            port_number,
+        };
+        ComparisonDataset::from_announce_message(message, &receiver, local_priority)
+    }
+
+    #[test]
+    fn priority_1_is_ignored() {
+        let mut a = announce(GM_A, ClockClass::Default, 0, GM_A);
+        let b = announce(GM_B, ClockClass::Default, 0, GM_B);
+        a.grandmaster_priority_1 = 255;
+
+        let (a, b) = (received(&a, 1, 128), received(&b, 2, 128));
+        assert_eq!(
+            TelecomMasterSelection.compare(&a, &b),
+            DatasetOrdering::Better
+        );
+        assert_eq!(a.compare(&b), DatasetOrdering::Worse);
+    }
+
+    #[test]
+    fn local_priority() {
+        let a = announce(GM_A, ClockClass::Default, 0, GM_A);
+        let b = announce(GM_B, ClockClass::Default, 0, GM_B);
+
+        // The localPriority of the receiving port comes before the identity
+        // of the grandmaster
+        let (a_data, b_data) = (received(&a, 1, 200), received(&b, 2, 100));
+        assert_eq!(
+            TelecomMasterSelection.compare(&a_data, &b_data),
+            DatasetOrdering::Worse
+        );
+
+        // But after its quality
+        let mut b = b;
+        b.grandmaster_priority_2 = 129;
+        let b_data = received(&b, 2, 100);
+        assert_eq!(
+            TelecomMasterSelection.compare(&a_data, &b_data),
+            DatasetOrdering::Better
+        );
+
+        // It also picks between the ports that receive the same grandmaster
+        let (a1, a2) = (received(&a, 1, 128), received(&a, 2, 1));
+        assert_eq!(
+            TelecomMasterSelection.compare(&a1, &a2),
+            DatasetOrdering::Worse
+        );
+
+        // And decides between the local clock and the others
+        let mut own = DefaultDS::new(InstanceConfig {
+            clock_identity: LOCAL,
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 24,
+            slave_only: false,
+            sdo_id: Default::default(),
+        });
+        own.local_priority = 1;
+        let own = ComparisonDataset::from_own_data(&own);
+        assert_eq!(
+            TelecomMasterSelection.compare(&own, &a1),
+            DatasetOrdering::Better
+        );
+    }
+
+    #[test]
+    fn primary_reference_grandmasters_by_topology() {
+        // Two grandmasters traceable to a primary reference, the better
+        // identity further away
+        let near = announce(GM_B, ClockClass::PrimaryReference, 0, GM_B);
+        let far = announce(GM_A, ClockClass::PrimaryReference, 2, ClockIdentity([4; 8]));
+        let (near, far) = (received(&near, 1, 128), received(&far, 2, 128));
+
+        assert_eq!(
+            TelecomMasterSelection.compare(&near, &far),
+            DatasetOrdering::Better
+        );
+        assert_eq!(
+            TelecomMasterSelection.compare(&far, &near),
+            DatasetOrdering::Worse
+        );
+        assert_eq!(near.compare(&far), DatasetOrdering::Worse);
+
+        // Other grandmasters are still ordered by identity
+        let near = announce(GM_B, ClockClass::Default, 0, GM_B);
+        let far = announce(GM_A, ClockClass::Default, 2, ClockIdentity([4; 8]));
+        let (near, far) = (received(&near, 1, 128), received(&far, 2, 128));
+
+        assert_eq!(
+            TelecomMasterSelection.compare(&near, &far),
+            DatasetOrdering::Worse
+        );
+        assert_eq!(
+            TelecomMasterSelection.compare(&far, &near),
+            DatasetOrdering::Better
+        );
+    }
+}
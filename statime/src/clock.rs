@@ -36,4 +36,25 @@ pub trait Clock {
         frequency_multiplier: f64,
         time_properties_ds: &TimePropertiesDS,
     ) -> Result<(), Self::Error>;
+
+    /// The frequency correction currently applied to the clock, as the rate of
+    /// the clock relative to its unadjusted oscillator.
+    ///
+    /// Intervals measured with a steered clock are scaled by this factor, so
+    /// calculations that need unadjusted intervals can divide by it. Clocks
+    /// that can't report their correction return `None`, which is the default.
+    fn frequency_multiplier(&self) -> Option<f64> {
+        None
+    }
+
+    /// The time of a clock that is never stepped, like `CLOCK_MONOTONIC`, to
+    /// measure timeouts with. Only the differences between its times are
+    /// used.
+    ///
+    /// Clocks that can't report one return `None`, which is the default. The
+    /// steps made with [`Clock::adjust`] are then taken out of [`Clock::now`]
+    /// instead, which misses steps made by anything else.
+    fn monotonic_now(&self) -> Option<Time> {
+        None
+    }
 }
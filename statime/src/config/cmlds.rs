@@ -0,0 +1,33 @@
+use crate::time::{Duration, Interval};
+
+/// How a [`CmldsLinkPort`](crate::CmldsLinkPort) measures its link, the
+/// cmldsLinkPortDS of IEEE1588-2019 section 16.6.3.
+///
+/// The ports that take their link delay from the Common Mean Link Delay
+/// Service use
+/// [`DelayMechanism::CommonP2P`](crate::DelayMechanism::CommonP2P), and their
+/// own latencies no longer apply to the peer delay exchanges, so the link
+/// port has its own.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct CmldsConfig {
+    /// The logMinPdelayReqInterval of the link port
+    pub interval: Interval,
+    /// Answer peer delay requests as a one-step responder, see
+    /// [`DelayMechanism::P2P`](crate::DelayMechanism::P2P)
+    pub one_step_responder: bool,
+    /// See [`PortConfig::ingress_latency`](crate::PortConfig::ingress_latency)
+    pub ingress_latency: Duration,
+    /// See [`PortConfig::egress_latency`](crate::PortConfig::egress_latency)
+    pub egress_latency: Duration,
+}
+
+impl Default for CmldsConfig {
+    fn default() -> Self {
+        Self {
+            interval: Interval::ONE_SECOND,
+            one_step_responder: false,
+            ingress_latency: Duration::ZERO,
+            egress_latency: Duration::ZERO,
+        }
+    }
+}
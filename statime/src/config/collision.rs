@@ -0,0 +1,20 @@
+/// What a port does when it receives messages from another clock that uses
+/// its clock identity.
+///
+/// Two clocks with the same identity, for example because of a duplicated MAC
+/// address or a cloned configuration, break the BMCA for the whole domain, as
+/// every clock takes their announce messages to come from a single clock.
+/// The port always reports the collision with
+/// [`PortEvent::ClockIdentityCollision`](crate::PortEvent::ClockIdentityCollision)
+/// and ignores the offending message. Set with
+/// [`Port::set_identity_collision_response`](crate::Port::set_identity_collision_response).
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum IdentityCollisionResponse {
+    /// Keep running as before
+    #[default]
+    Report,
+    /// Stop taking part in the domain by becoming faulty, so the collision
+    /// can't disturb the other clocks. The port tries again when its announce
+    /// receipt timer expires.
+    Faulty,
+}
@@ -0,0 +1,80 @@
+use crate::datastructures::common::{ClockIdentity, PortIdentity};
+
+/// The master of a point-to-point link that is known in advance, such as a
+/// backplane Ethernet link in an embedded system.
+///
+/// A port with a fixed master neither needs nor sends announce messages, and
+/// stays out of the BMCA. It becomes slave of the port with the given clock
+/// identity and port number right away, and counts only Sync messages from
+/// that port towards its announce receipt timer. Once
+/// `sync_receipt_timeout` sync intervals pass without one, or
+/// `delay_receipt_timeout` delay requests in a row go unanswered, it gives
+/// up on the master and listens, to try again when the timer expires.
+/// Set with [`Port::set_fixed_master`](crate::Port::set_fixed_master).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct FixedMasterConfig {
+    /// The clock identity of the master
+    pub clock_identity: ClockIdentity,
+    /// The number of the port of the master on the link
+    pub port_number: u16,
+    /// How many sync intervals may pass without a Sync from the master, at
+    /// least one
+    pub sync_receipt_timeout: u8,
+    /// How many delay requests in a row the master may leave unanswered, at
+    /// least one. With peer to peer delay measurement these are the
+    /// PdelayReq messages, which the master answers as the neighbor on the
+    /// link.
+    pub delay_receipt_timeout: u8,
+}
+
+impl FixedMasterConfig {
+    pub(crate) fn master(&self) -> PortIdentity {
+        PortIdentity {
+            clock_identity: self.clock_identity,
+            port_number: self.port_number,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), FixedMasterConfigError> {
+        if self.sync_receipt_timeout == 0 {
+            return Err(FixedMasterConfigError::ZeroSyncReceiptTimeout);
+        }
+        if self.delay_receipt_timeout == 0 {
+            return Err(FixedMasterConfigError::ZeroDelayReceiptTimeout);
+        }
+        Ok(())
+    }
+}
+
+/// Reasons a [`FixedMasterConfig`] can be rejected
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FixedMasterConfigError {
+    /// A sync receipt timeout of zero sync intervals was configured, which
+    /// would expire right away
+    ZeroSyncReceiptTimeout,
+    /// A delay receipt timeout of zero delay requests was configured, which
+    /// would expire before the first request is answered
+    ZeroDelayReceiptTimeout,
+}
+
+impl core::fmt::Display for FixedMasterConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FixedMasterConfigError::ZeroSyncReceiptTimeout => {
+                write!(
+                    f,
+                    "the sync receipt timeout must be at least one sync interval"
+                )
+            }
+            FixedMasterConfigError::ZeroDelayReceiptTimeout => {
+                write!(
+                    f,
+                    "the delay receipt timeout must be at least one delay request"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FixedMasterConfigError {}
@@ -0,0 +1,34 @@
+use crate::time::Duration;
+
+/// When a port in gPTP mode considers its link fit for time transport, the
+/// asCapable of IEEE 802.1AS-2020 section 10.2.5.1.
+///
+/// A port in gPTP mode measures the delay to its neighbor with the peer delay
+/// mechanism in every state, not just as slave, so its
+/// [`PortConfig::delay_mechanism`](crate::PortConfig::delay_mechanism)
+/// should be [`DelayMechanism::P2P`](crate::DelayMechanism::P2P), or
+/// [`DelayMechanism::CommonP2P`](crate::DelayMechanism::CommonP2P) to leave
+/// the measurement to the Common Mean Link Delay Service. The link is
+/// asCapable once a peer delay exchange completed with a mean link delay of
+/// at most `neighbor_prop_delay_thresh`. It stops being so when an exchange
+/// measures more than that, or when more than `allowed_lost_responses`
+/// requests in a row went unanswered. Set with
+/// [`Port::set_gptp`](crate::Port::set_gptp).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct GptpConfig {
+    /// The largest mean link delay of an asCapable link
+    pub neighbor_prop_delay_thresh: Duration,
+    /// How many peer delay requests in a row may go unanswered before the
+    /// link stops being asCapable
+    pub allowed_lost_responses: u8,
+}
+
+impl Default for GptpConfig {
+    /// The defaults of IEEE 802.1AS-2020 for full-duplex Ethernet links
+    fn default() -> Self {
+        Self {
+            neighbor_prop_delay_thresh: Duration::from_nanos(800),
+            allowed_lost_responses: 9,
+        }
+    }
+}
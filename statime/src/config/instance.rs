@@ -9,3 +9,167 @@ pub struct InstanceConfig {
     pub slave_only: bool,
     pub sdo_id: SdoId,
 }
+
+impl InstanceConfig {
+    /// Check that the configuration is allowed by the standard. Returns the
+    /// first problem found, see [`InstanceConfig::errors`] for all of them.
+    ///
+    /// The only restriction on the priorities is that a slave-only instance
+    /// must use a priority 1 of 255.
+    pub fn validate(&self) -> Result<(), InstanceConfigError> {
+        self.validate_with_bounds(&PriorityBounds::default())
+    }
+
+    /// Check that the configuration is allowed by the standard, and that the
+    /// priorities lie within the given bounds. This can be used to enforce the
+    /// restrictions of a profile. Returns the first problem found, see
+    /// [`InstanceConfig::errors`] for all of them.
+    pub fn validate_with_bounds(&self, bounds: &PriorityBounds) -> Result<(), InstanceConfigError> {
+        match self.errors(bounds).next() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Every problem [`InstanceConfig::validate_with_bounds`] finds with the
+    /// configuration, so they can all be reported at once.
+    pub fn errors(&self, bounds: &PriorityBounds) -> impl Iterator<Item = InstanceConfigError> {
+        let slave_only = (self.slave_only && self.priority_1 != 255)
+            .then_some(InstanceConfigError::SlaveOnlyPriority1(self.priority_1));
+        let priority_1 = (!(bounds.priority_1_min..=bounds.priority_1_max)
+            .contains(&self.priority_1))
+        .then_some(InstanceConfigError::Priority1OutOfBounds(self.priority_1));
+        let priority_2 = (!(bounds.priority_2_min..=bounds.priority_2_max)
+            .contains(&self.priority_2))
+        .then_some(InstanceConfigError::Priority2OutOfBounds(self.priority_2));
+
+        [slave_only, priority_1, priority_2].into_iter().flatten()
+    }
+}
+
+/// Inclusive bounds on the priorities an instance may be configured with
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PriorityBounds {
+    pub priority_1_min: u8,
+    pub priority_1_max: u8,
+    pub priority_2_min: u8,
+    pub priority_2_max: u8,
+}
+
+impl Default for PriorityBounds {
+    fn default() -> Self {
+        Self {
+            priority_1_min: 0,
+            priority_1_max: 255,
+            priority_2_min: 0,
+            priority_2_max: 255,
+        }
+    }
+}
+
+/// Reasons an [`InstanceConfig`] can be rejected
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InstanceConfigError {
+    /// The instance is slave-only, but priority 1 isn't 255
+    SlaveOnlyPriority1(u8),
+    /// Priority 1 lies outside the configured bounds
+    Priority1OutOfBounds(u8),
+    /// Priority 2 lies outside the configured bounds
+    Priority2OutOfBounds(u8),
+}
+
+impl core::fmt::Display for InstanceConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InstanceConfigError::SlaveOnlyPriority1(value) => write!(
+                f,
+                "priority 1 must be 255 for a slave-only instance, but is {value}"
+            ),
+            InstanceConfigError::Priority1OutOfBounds(value) => {
+                write!(f, "priority 1 ({value}) is outside the allowed bounds")
+            }
+            InstanceConfigError::Priority2OutOfBounds(value) => {
+                write!(f, "priority 2 ({value}) is outside the allowed bounds")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InstanceConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(priority_1: u8, priority_2: u8, slave_only: bool) -> InstanceConfig {
+        InstanceConfig {
+            clock_identity: ClockIdentity::default(),
+            priority_1,
+            priority_2,
+            domain_number: 0,
+            slave_only,
+            sdo_id: SdoId::default(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(config(0, 0, false).validate(), Ok(()));
+        assert_eq!(config(255, 255, false).validate(), Ok(()));
+        assert_eq!(config(255, 0, true).validate(), Ok(()));
+        assert_eq!(
+            config(128, 128, true).validate(),
+            Err(InstanceConfigError::SlaveOnlyPriority1(128))
+        );
+    }
+
+    #[test]
+    fn test_validate_with_bounds() {
+        let bounds = PriorityBounds {
+            priority_1_min: 128,
+            priority_1_max: 128,
+            priority_2_min: 0,
+            priority_2_max: 254,
+        };
+
+        assert_eq!(
+            config(128, 254, false).validate_with_bounds(&bounds),
+            Ok(())
+        );
+        assert_eq!(
+            config(127, 0, false).validate_with_bounds(&bounds),
+            Err(InstanceConfigError::Priority1OutOfBounds(127))
+        );
+        assert_eq!(
+            config(129, 0, false).validate_with_bounds(&bounds),
+            Err(InstanceConfigError::Priority1OutOfBounds(129))
+        );
+        assert_eq!(
+            config(128, 255, false).validate_with_bounds(&bounds),
+            Err(InstanceConfigError::Priority2OutOfBounds(255))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let bounds = PriorityBounds {
+            priority_1_min: 128,
+            priority_1_max: 128,
+            priority_2_min: 0,
+            priority_2_max: 254,
+        };
+
+        assert_eq!(config(128, 0, false).errors(&bounds).next(), None);
+        assert_eq!(
+            config(127, 255, true)
+                .errors(&bounds)
+                .collect::<std::vec::Vec<_>>(),
+            [
+                InstanceConfigError::SlaveOnlyPriority1(127),
+                InstanceConfigError::Priority1OutOfBounds(127),
+                InstanceConfigError::Priority2OutOfBounds(255),
+            ]
+        );
+    }
+}
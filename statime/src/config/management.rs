@@ -0,0 +1,39 @@
+use crate::datastructures::messages::{ManagementAction, ManagementMessage};
+
+/// Which management messages a port acts on, see
+/// [`PortConfig::management`](crate::config::PortConfig::management).
+///
+/// Every policy answers GET requests for the datasets. The policy decides
+/// who may change the instance and the port with SET and COMMAND messages,
+/// like setting priority 1 or disabling the port. Requests that are not
+/// allowed are answered with an error, and change nothing.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum ManagementPolicy {
+    /// Only answer GET requests
+    #[default]
+    ReadOnly,
+    /// Also accept SET and COMMAND messages from management nodes on the
+    /// network of the port, whose messages did not pass a boundary clock on
+    /// their way. This says nothing about routers, so it only keeps out
+    /// management nodes that can't reach the port directly.
+    LocalOnly,
+    /// Accept SET and COMMAND messages from any management node
+    Full,
+}
+
+impl ManagementPolicy {
+    /// Whether the policy allows acting on `request`
+    pub(crate) fn allows(self, request: &ManagementMessage) -> bool {
+        match request.action() {
+            ManagementAction::GET => true,
+            ManagementAction::SET | ManagementAction::COMMAND => match self {
+                ManagementPolicy::ReadOnly => false,
+                ManagementPolicy::LocalOnly => !request.was_forwarded(),
+                ManagementPolicy::Full => true,
+            },
+            ManagementAction::RESPONSE
+            | ManagementAction::ACKNOWLEDGE
+            | ManagementAction::Reserved => false,
+        }
+    }
+}
@@ -1,5 +1,31 @@
+mod cmlds;
+mod collision;
+mod fixed_master;
+mod gptp;
 mod instance;
+mod management;
 mod port;
+mod quirks;
+mod role;
+mod simulation;
+mod startup;
+mod unicast;
 
-pub use instance::InstanceConfig;
-pub use port::{DelayMechanism, PortConfig};
+pub use cmlds::CmldsConfig;
+pub use collision::IdentityCollisionResponse;
+pub use fixed_master::{FixedMasterConfig, FixedMasterConfigError};
+pub use gptp::GptpConfig;
+pub use instance::{InstanceConfig, InstanceConfigError, PriorityBounds};
+pub use management::ManagementPolicy;
+pub use port::{
+    CommunicationMode, DelayMechanism, IntervalBounds, IntervalField, LogMessageIntervals,
+    PathDirection, PortConfig, PortConfigError, TransmitEnable,
+};
+pub(crate) use quirks::resolve_quirks;
+pub use quirks::{QuirkConfigError, QuirkMatch, QuirkRule, Quirks, MAX_QUIRK_RULES};
+pub use role::{Profile, Role, RolePreset};
+pub use simulation::SimulatedPath;
+pub use startup::StartupBurst;
+pub use unicast::{
+    UnicastClientConfig, UnicastConfigError, UnicastMasterConfig, MAX_UNICAST_MASTERS,
+};
@@ -1,10 +1,14 @@
+use arrayvec::ArrayVec;
 use rand::Rng;
 
-use crate::{time::Interval, Duration};
+use super::{InstanceConfig, ManagementPolicy, UnicastClientConfig, UnicastConfigError};
+use crate::{datastructures::messages::MessageType, time::Interval, Duration};
 
 /// Which delay mechanism a port is using.
 ///
-/// Currently, statime only supports the end to end (E2E) delay mechanism.
+/// Currently, statime supports the end to end (E2E) and peer to peer (P2P)
+/// delay mechanisms, peer to peer through the Common Mean Link Delay Service,
+/// and one-way operation without any delay measurement.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum DelayMechanism {
     /// End to end delay mechanism. Delay measurement is done directly to the
@@ -12,7 +16,185 @@ pub enum DelayMechanism {
     ///
     /// the interval corresponds to the PortDS logMinDelayReqInterval
     E2E { interval: Interval },
-    // No support for other delay mechanisms
+    /// Peer to peer delay mechanism. A slave port measures the delay of the
+    /// link to its neighbour with PdelayReq messages, and relies on the
+    /// transparent clocks in between to correct Sync messages for the delay
+    /// of the links further upstream. Ports answer the PdelayReq messages of
+    /// their neighbour in every state but faulty.
+    ///
+    /// The interval corresponds to the PortDS logMinPdelayReqInterval. Peer
+    /// delay messages must be sent to the peer delay multicast address
+    /// (224.0.0.107, FF02::6B or 01-80-C2-00-00-0E), which runtimes can
+    /// recognize with [`route_packet`](crate::route_packet).
+    ///
+    /// As a one-step responder, the port leaves it to the hardware to add the
+    /// turnaround time to the correction field of the PdelayResp while
+    /// sending it, and sends no PdelayRespFollowUp. Only enable this when the
+    /// network card supports one-step peer delay responses.
+    P2P {
+        interval: Interval,
+        one_step_responder: bool,
+    },
+    /// No delay measurement (NO_MECHANISM in the standard). The offset to the
+    /// master is computed from Sync and FollowUp messages only, assuming the
+    /// given path delay (which may be zero).
+    ///
+    /// This is intended for broadcast-only networks, such as satellite
+    /// downlinks or data diodes, where DelayReq messages can't be sent. A port
+    /// using this mechanism never sends DelayReq messages and doesn't respond
+    /// to them while master.
+    OneWay { path_delay: Duration },
+    /// Peer to peer delay mechanism through the Common Mean Link Delay
+    /// Service (COMMON_P2P, IEEE1588-2019 section 16.6). Instead of measuring
+    /// the link itself, the port uses the mean link delay measured by the
+    /// [`CmldsLinkPort`](crate::CmldsLinkPort) of its link, which the
+    /// runtime hands it with
+    /// [`Port::handle_common_link_delay`](crate::Port::handle_common_link_delay).
+    /// This lets the instances of several domains on the same link share a
+    /// single measurement.
+    ///
+    /// Like with [`DelayMechanism::P2P`], the port relies on transparent
+    /// clocks to correct Sync messages for the delay of the links further
+    /// upstream. It neither sends nor answers peer delay requests itself, and
+    /// ignores delay requests.
+    CommonP2P,
+}
+
+/// How PTP messages are delivered to and from a port.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum CommunicationMode {
+    /// Messages are sent to the PTP multicast group
+    #[default]
+    Multicast,
+    /// Messages are exchanged directly with other nodes, after negotiating
+    /// unicast transmission
+    Unicast,
+}
+
+/// A direction of the path between a slave port and its master
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PathDirection {
+    /// The path of Sync messages
+    MasterToSlave,
+    /// The path of DelayReq messages
+    SlaveToMaster,
+}
+
+/// Which types of messages a port is allowed to send.
+///
+/// Disabling a message type is enforced by the port state machines: the
+/// message is never constructed and the timer driving it isn't restarted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TransmitEnable {
+    /// Announce messages, sent while master
+    pub announce: bool,
+    /// Sync and FollowUp messages, sent while master
+    pub sync: bool,
+    /// DelayReq messages, sent while slave. Without these the slave can't
+    /// measure the path delay to its master.
+    pub delay_req: bool,
+    /// DelayResp messages, sent while master in reply to DelayReq messages,
+    /// and PdelayResp messages, sent in reply to PdelayReq messages
+    pub delay_resp: bool,
+    /// Replies to management messages. Without these, management messages
+    /// are ignored rather than acted on without an acknowledgement.
+    pub management: bool,
+    /// Signaling messages negotiating unicast transmission: the requests of a
+    /// unicast client, and the grants and cancellations of a unicast master.
+    /// Without these, unicast negotiation messages are ignored.
+    pub signaling: bool,
+}
+
+impl TransmitEnable {
+    /// Send all messages as usual
+    pub const ALL: Self = Self {
+        announce: true,
+        sync: true,
+        delay_req: true,
+        delay_resp: true,
+        management: true,
+        signaling: true,
+    };
+
+    /// Never send anything, making the port listen-only
+    pub const NONE: Self = Self {
+        announce: false,
+        sync: false,
+        delay_req: false,
+        delay_resp: false,
+        management: false,
+        signaling: false,
+    };
+}
+
+impl Default for TransmitEnable {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// Values of the logMessageInterval header field of sent messages, per
+/// message type, for profiles that deviate from IEEE1588-2019.
+///
+/// A type left at `None` follows table 42 of the standard: multicast Sync
+/// and FollowUp messages carry the sync interval, multicast DelayResp
+/// messages the minimum delay request interval, Announce messages the
+/// announce interval, and everything else 0x7F.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct LogMessageIntervals {
+    /// For Sync messages, instead of the sync interval when multicast
+    pub sync: Option<i8>,
+    /// For DelayReq messages, instead of 0x7F
+    pub delay_req: Option<i8>,
+    /// For PdelayReq messages, instead of 0x7F
+    pub pdelay_req: Option<i8>,
+    /// For PdelayResp messages, instead of 0x7F
+    pub pdelay_resp: Option<i8>,
+    /// For FollowUp messages, instead of the sync interval when multicast
+    pub follow_up: Option<i8>,
+    /// For DelayResp messages, instead of the minimum delay request interval
+    /// when multicast
+    pub delay_resp: Option<i8>,
+    /// For PdelayRespFollowUp messages, instead of 0x7F
+    pub pdelay_resp_follow_up: Option<i8>,
+    /// For Announce messages, instead of the announce interval
+    pub announce: Option<i8>,
+    /// For Signaling messages, instead of 0x7F
+    pub signaling: Option<i8>,
+    /// For Management messages, instead of 0x7F
+    pub management: Option<i8>,
+}
+
+impl LogMessageIntervals {
+    /// Follow IEEE1588-2019 for every message type
+    pub const STANDARD: Self = Self {
+        sync: None,
+        delay_req: None,
+        pdelay_req: None,
+        pdelay_resp: None,
+        follow_up: None,
+        delay_resp: None,
+        pdelay_resp_follow_up: None,
+        announce: None,
+        signaling: None,
+        management: None,
+    };
+
+    /// The value set for messages of type `message_type`, if any
+    pub fn get(&self, message_type: MessageType) -> Option<i8> {
+        match message_type {
+            MessageType::Sync => self.sync,
+            MessageType::DelayReq => self.delay_req,
+            MessageType::PDelayReq => self.pdelay_req,
+            MessageType::PDelayResp => self.pdelay_resp,
+            MessageType::FollowUp => self.follow_up,
+            MessageType::DelayResp => self.delay_resp,
+            MessageType::PDelayRespFollowUp => self.pdelay_resp_follow_up,
+            MessageType::Announce => self.announce,
+            MessageType::Signaling => self.signaling,
+            MessageType::Management => self.management,
+        }
+    }
 }
 
 /// Configuration items of the PTP PortDS dataset. Dynamical fields are kept
@@ -25,17 +207,144 @@ pub struct PortConfig {
     // announce message expires.
     pub announce_receipt_timeout: u8,
     pub sync_interval: Interval,
+    /// Never take time from this port, see IEEE1588-2019 section 9.2.2.1.
+    ///
+    /// Announce messages received on a master-only port are not considered
+    /// by the BMCA, so the port never becomes slave or passive, and other
+    /// masters on its network don't delay its announce receipt timeout. The
+    /// port becomes master once that timeout expires, or at the next BMCA run
+    /// when another port of the instance has found a grandmaster.
     pub master_only: bool,
+    /// How much longer the path from the master to this port takes than the
+    /// mean path delay, see IEEE1588-2019 section 16.8. Positive when
+    /// messages from the master take longer than messages to it, negative
+    /// otherwise.
+    ///
+    /// The asymmetry is half the difference between the delays of both
+    /// directions. Use [`PortConfig::delay_asymmetry_from`] to compute it
+    /// from that difference.
     pub delay_asymmetry: Duration,
+    /// How much later than the packet passed the network interface the
+    /// runtime takes its receive timestamps, for example the latency of the
+    /// PHY. Subtracted from every receive timestamp the port is given, once,
+    /// before it is used. [`Measurement`](crate::Measurement) reports the
+    /// receive timestamp of each sync both before and after this correction.
+    pub ingress_latency: Duration,
+    /// How much earlier than the packet passed the network interface the
+    /// runtime takes its send timestamps, for example the latency of the PHY.
+    /// Added to every send timestamp the port is given, once, before it is
+    /// used.
+    pub egress_latency: Duration,
+    /// Who may change the instance and this port with management messages.
+    /// The default, [`ManagementPolicy::ReadOnly`], only answers requests for
+    /// the datasets.
+    pub management: ManagementPolicy,
+    pub communication_mode: CommunicationMode,
+    pub transmit: TransmitEnable,
+    /// The unicast master table of a port with
+    /// [`CommunicationMode::Unicast`], whose masters it requests unicast
+    /// messages from as a client. Disabled with `None`.
+    pub unicast_client: Option<UnicastClientConfig>,
+    /// Overrides of the logMessageInterval field of the messages the port
+    /// sends, see [`LogMessageIntervals`]
+    pub log_message_intervals: LogMessageIntervals,
     // Notes:
     // Fields specific for delay mechanism are kept as part of [DelayMechanism].
     // Version is always 2.1, so not stored (versionNumber, minorVersionNumber)
 }
 
 impl PortConfig {
+    /// The [`delay_asymmetry`](Self::delay_asymmetry) of a path on which
+    /// messages in `slower` direction take `excess` longer than messages in
+    /// the other direction.
+    pub fn delay_asymmetry_from(slower: PathDirection, excess: Duration) -> Duration {
+        match slower {
+            PathDirection::MasterToSlave => excess / 2,
+            PathDirection::SlaveToMaster => -(excess / 2),
+        }
+    }
+
+    /// Check that the configuration is allowed by the standard, and
+    /// consistent with the instance the port belongs to. Returns the first
+    /// problem found, see [`PortConfig::errors`] for all of them.
+    pub fn validate(&self, instance: &InstanceConfig) -> Result<(), PortConfigError> {
+        self.validate_with_bounds(instance, &IntervalBounds::default())
+    }
+
+    /// Check that the configuration is allowed by the standard and consistent
+    /// with the instance, and that the message intervals lie within the given
+    /// bounds. This can be used to enforce the restrictions of a profile, such
+    /// as [`IntervalBounds::DEFAULT_PROFILE`]. Returns the first problem
+    /// found, see [`PortConfig::errors`] for all of them.
+    pub fn validate_with_bounds(
+        &self,
+        instance: &InstanceConfig,
+        bounds: &IntervalBounds,
+    ) -> Result<(), PortConfigError> {
+        match self.errors(instance, bounds).next() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Every problem [`PortConfig::validate_with_bounds`] finds with the
+    /// configuration, so they can all be reported at once.
+    pub fn errors(
+        &self,
+        instance: &InstanceConfig,
+        bounds: &IntervalBounds,
+    ) -> impl Iterator<Item = PortConfigError> {
+        let mut errors = ArrayVec::<PortConfigError, MAX_PORT_CONFIG_ERRORS>::new();
+
+        if self.master_only && instance.slave_only {
+            errors.push(PortConfigError::MasterOnlyOnSlaveOnlyInstance);
+        }
+
+        if self.announce_receipt_timeout < 2 {
+            errors.push(PortConfigError::AnnounceReceiptTimeoutTooSmall(
+                self.announce_receipt_timeout,
+            ));
+        }
+
+        let mut check = |field, interval: Interval, (min, max): (i8, i8)| {
+            let log_interval = interval.as_log_2();
+            // 0x7F is reserved to signal that a message isn't sent at all
+            if log_interval == 0x7f || !(min..=max).contains(&log_interval) {
+                errors.push(PortConfigError::IntervalOutOfBounds {
+                    field,
+                    log_interval,
+                    min,
+                    max,
+                });
+            }
+        };
+
+        check(
+            IntervalField::Announce,
+            self.announce_interval,
+            bounds.announce,
+        );
+        check(IntervalField::Sync, self.sync_interval, bounds.sync);
+        if let DelayMechanism::E2E { interval } | DelayMechanism::P2P { interval, .. } =
+            self.delay_mechanism
+        {
+            check(IntervalField::MinDelayReq, interval, bounds.min_delay_req);
+        }
+
+        if let Some(Err(error)) = self.unicast_client.map(|config| config.validate()) {
+            errors.push(PortConfigError::Unicast(error));
+        }
+
+        errors.into_iter()
+    }
+
     pub fn min_delay_req_interval(&self) -> Interval {
         match self.delay_mechanism {
             DelayMechanism::E2E { interval } => interval,
+            // 0x7F signals that no delay requests should be sent
+            DelayMechanism::P2P { .. }
+            | DelayMechanism::OneWay { .. }
+            | DelayMechanism::CommonP2P => Interval::from_log_2(0x7f),
         }
     }
 
@@ -48,3 +357,310 @@ impl PortConfig {
         duration.mul_f64(factor * self.announce_receipt_timeout as u32 as f64)
     }
 }
+
+// Each check of PortConfig::errors finds at most one problem
+const MAX_PORT_CONFIG_ERRORS: usize = 6;
+
+/// Inclusive bounds on the log 2 of the message intervals a port may be
+/// configured with
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct IntervalBounds {
+    /// The lowest and highest [`PortConfig::announce_interval`]
+    pub announce: (i8, i8),
+    /// The lowest and highest [`PortConfig::sync_interval`]
+    pub sync: (i8, i8),
+    /// The lowest and highest interval of [`DelayMechanism::E2E`] and
+    /// [`DelayMechanism::P2P`]
+    pub min_delay_req: (i8, i8),
+}
+
+impl IntervalBounds {
+    /// The ranges of the default delay request-response profile, see
+    /// IEEE1588-2019 section I.3.2
+    pub const DEFAULT_PROFILE: Self = Self {
+        announce: (0, 4),
+        sync: (-1, 1),
+        min_delay_req: (0, 5),
+    };
+
+    /// The ranges of the broadcast media profile of SMPTE ST 2059-2
+    pub const SMPTE_2059: Self = Self {
+        announce: (-3, 1),
+        sync: (-7, -1),
+        min_delay_req: (-3, 5),
+    };
+
+    /// The message rates of the ITU-T G.8275.1 telecom profile, which fixes
+    /// them at 8 announce, 16 sync and 16 delay request messages per second
+    pub const G8275_1: Self = Self {
+        announce: (-3, -3),
+        sync: (-4, -4),
+        min_delay_req: (-4, -4),
+    };
+}
+
+impl Default for IntervalBounds {
+    fn default() -> Self {
+        Self {
+            announce: (i8::MIN, 0x7e),
+            sync: (i8::MIN, 0x7e),
+            min_delay_req: (i8::MIN, 0x7e),
+        }
+    }
+}
+
+/// A message interval of [`PortConfig`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum IntervalField {
+    /// [`PortConfig::announce_interval`]
+    Announce,
+    /// [`PortConfig::sync_interval`]
+    Sync,
+    /// The interval of [`DelayMechanism::E2E`] or [`DelayMechanism::P2P`]
+    MinDelayReq,
+}
+
+impl core::fmt::Display for IntervalField {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            IntervalField::Announce => "announce_interval",
+            IntervalField::Sync => "sync_interval",
+            IntervalField::MinDelayReq => "delay_mechanism.interval",
+        })
+    }
+}
+
+/// Reasons a [`PortConfig`] can be rejected
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PortConfigError {
+    /// The port is master-only, but the instance is slave-only
+    MasterOnlyOnSlaveOnlyInstance,
+    /// The announce receipt timeout is below the minimum of 2
+    AnnounceReceiptTimeoutTooSmall(u8),
+    /// A message interval lies outside the allowed bounds
+    IntervalOutOfBounds {
+        /// The interval that is out of bounds
+        field: IntervalField,
+        /// The log 2 of the interval
+        log_interval: i8,
+        /// The lowest log 2 allowed
+        min: i8,
+        /// The highest log 2 allowed
+        max: i8,
+    },
+    /// The unicast master table is invalid
+    Unicast(UnicastConfigError),
+}
+
+impl core::fmt::Display for PortConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PortConfigError::MasterOnlyOnSlaveOnlyInstance => write!(
+                f,
+                "master_only: a port of a slave-only instance can't be master-only"
+            ),
+            PortConfigError::AnnounceReceiptTimeoutTooSmall(value) => write!(
+                f,
+                "announce_receipt_timeout: must be at least 2, but is {value}"
+            ),
+            PortConfigError::IntervalOutOfBounds {
+                field,
+                log_interval,
+                min,
+                max,
+            } => write!(
+                f,
+                "{field}: log interval {log_interval} is outside the allowed range {min} to {max}"
+            ),
+            PortConfigError::Unicast(error) => write!(f, "unicast_client: {error}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PortConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClockIdentity, SdoId};
+
+    fn instance(slave_only: bool) -> InstanceConfig {
+        InstanceConfig {
+            clock_identity: ClockIdentity::default(),
+            priority_1: 255,
+            priority_2: 255,
+            domain_number: 0,
+            slave_only,
+            sdo_id: SdoId::default(),
+        }
+    }
+
+    fn config() -> PortConfig {
+        PortConfig {
+            delay_mechanism: DelayMechanism::E2E {
+                interval: Interval::TWO_SECONDS,
+            },
+            announce_interval: Interval::TWO_SECONDS,
+            announce_receipt_timeout: 3,
+            sync_interval: Interval::ONE_SECOND,
+            master_only: false,
+            delay_asymmetry: Duration::ZERO,
+            ingress_latency: Duration::ZERO,
+            egress_latency: Duration::ZERO,
+            management: Default::default(),
+            communication_mode: CommunicationMode::Multicast,
+            transmit: TransmitEnable::ALL,
+            unicast_client: None,
+            log_message_intervals: LogMessageIntervals::STANDARD,
+        }
+    }
+
+    #[test]
+    fn delay_asymmetry_direction() {
+        let excess = Duration::from_nanos(300);
+        assert_eq!(
+            PortConfig::delay_asymmetry_from(PathDirection::MasterToSlave, excess),
+            Duration::from_nanos(150)
+        );
+        assert_eq!(
+            PortConfig::delay_asymmetry_from(PathDirection::SlaveToMaster, excess),
+            Duration::from_nanos(-150)
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(config().validate(&instance(false)), Ok(()));
+        assert_eq!(config().validate(&instance(true)), Ok(()));
+
+        let master_only = PortConfig {
+            master_only: true,
+            ..config()
+        };
+        assert_eq!(master_only.validate(&instance(false)), Ok(()));
+        assert_eq!(
+            master_only.validate(&instance(true)),
+            Err(PortConfigError::MasterOnlyOnSlaveOnlyInstance)
+        );
+
+        let short_timeout = PortConfig {
+            announce_receipt_timeout: 1,
+            ..config()
+        };
+        assert_eq!(
+            short_timeout.validate(&instance(false)),
+            Err(PortConfigError::AnnounceReceiptTimeoutTooSmall(1))
+        );
+
+        let reserved_sync = PortConfig {
+            sync_interval: Interval::from_log_2(0x7f),
+            ..config()
+        };
+        assert_eq!(
+            reserved_sync.validate(&instance(false)),
+            Err(PortConfigError::IntervalOutOfBounds {
+                field: IntervalField::Sync,
+                log_interval: 0x7f,
+                min: i8::MIN,
+                max: 0x7e,
+            })
+        );
+
+        let no_masters = PortConfig {
+            unicast_client: Some(UnicastClientConfig {
+                masters: 0,
+                lease_duration: 60,
+            }),
+            ..config()
+        };
+        assert_eq!(
+            no_masters.validate(&instance(false)),
+            Err(PortConfigError::Unicast(
+                UnicastConfigError::InvalidMasterCount(0)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_validate_with_bounds() {
+        let bounds = IntervalBounds::DEFAULT_PROFILE;
+        assert_eq!(
+            config().validate_with_bounds(&instance(false), &bounds),
+            Ok(())
+        );
+
+        let fast_sync = PortConfig {
+            sync_interval: Interval::from_log_2(-3),
+            ..config()
+        };
+        assert_eq!(
+            fast_sync.validate_with_bounds(&instance(false), &bounds),
+            Err(PortConfigError::IntervalOutOfBounds {
+                field: IntervalField::Sync,
+                log_interval: -3,
+                min: -1,
+                max: 1,
+            })
+        );
+
+        let slow_delay_req = PortConfig {
+            delay_mechanism: DelayMechanism::E2E {
+                interval: Interval::from_log_2(6),
+            },
+            ..config()
+        };
+        assert_eq!(
+            slow_delay_req.validate_with_bounds(&instance(false), &bounds),
+            Err(PortConfigError::IntervalOutOfBounds {
+                field: IntervalField::MinDelayReq,
+                log_interval: 6,
+                min: 0,
+                max: 5,
+            })
+        );
+
+        // One-way ports don't send delay requests, so have no interval to check
+        let one_way = PortConfig {
+            delay_mechanism: DelayMechanism::OneWay {
+                path_delay: Duration::ZERO,
+            },
+            ..config()
+        };
+        assert_eq!(
+            one_way.validate_with_bounds(&instance(false), &bounds),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let bounds = IntervalBounds::DEFAULT_PROFILE;
+        assert_eq!(config().errors(&instance(true), &bounds).next(), None);
+
+        let everything_wrong = PortConfig {
+            master_only: true,
+            announce_receipt_timeout: 1,
+            sync_interval: Interval::from_log_2(0x7f),
+            ..config()
+        };
+        let errors: std::vec::Vec<_> = everything_wrong.errors(&instance(true), &bounds).collect();
+        assert_eq!(
+            errors,
+            [
+                PortConfigError::MasterOnlyOnSlaveOnlyInstance,
+                PortConfigError::AnnounceReceiptTimeoutTooSmall(1),
+                PortConfigError::IntervalOutOfBounds {
+                    field: IntervalField::Sync,
+                    log_interval: 0x7f,
+                    min: -1,
+                    max: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            everything_wrong.validate_with_bounds(&instance(true), &bounds),
+            Err(errors[0])
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use crate::datastructures::{common::ClockIdentity, messages::SdoId};
+
+/// Maximum number of rules a port can hold, see
+/// [`Port::set_quirks`](crate::Port::set_quirks)
+pub const MAX_QUIRK_RULES: usize = 8;
+
+/// Workarounds for masters that don't quite follow the standard.
+///
+/// Each workaround is enabled separately. How often a workaround was needed
+/// is counted in
+/// [`PortStatistics::quirks`](crate::PortStatistics::quirks).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Quirks {
+    /// Accept a DelayResp for our port while a DelayReq is outstanding, even
+    /// if its sequence id doesn't match the one of the DelayReq. Some masters
+    /// answer with the sequence id of their last Sync instead.
+    pub delay_resp_sequence_id: bool,
+    /// Accept a FollowUp that arrives before its Sync, and complete the
+    /// measurement when the Sync comes in. This is needed for masters that
+    /// send the FollowUp first, and for networks that reorder them. Without
+    /// it, a FollowUp that doesn't belong to the last Sync is ignored.
+    pub follow_up_before_sync: bool,
+}
+
+impl Quirks {
+    /// No workarounds at all, only accept messages that follow the standard
+    pub const NONE: Self = Self {
+        delay_resp_sequence_id: false,
+        follow_up_before_sync: false,
+    };
+
+    /// The workarounds that are enabled when no rule matches. Statime has
+    /// always accepted a FollowUp before its Sync, so that one is enabled.
+    pub const DEFAULT: Self = Self {
+        delay_resp_sequence_id: false,
+        follow_up_before_sync: true,
+    };
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Which masters a [`QuirkRule`] applies to
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum QuirkMatch {
+    /// Every master
+    Any,
+    /// Masters whose grandmaster has a clock identity starting with this
+    /// organizationally unique identifier, which identifies the vendor
+    Oui([u8; 3]),
+    /// Masters in a domain with this SDO id, which identifies the profile
+    SdoId(SdoId),
+}
+
+impl QuirkMatch {
+    fn matches(&self, grandmaster_identity: ClockIdentity, sdo_id: SdoId) -> bool {
+        match self {
+            QuirkMatch::Any => true,
+            QuirkMatch::Oui(oui) => grandmaster_identity.0[..3] == oui[..],
+            QuirkMatch::SdoId(id) => *id == sdo_id,
+        }
+    }
+}
+
+/// The workarounds to use for the masters matching a rule
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct QuirkRule {
+    pub matches: QuirkMatch,
+    pub quirks: Quirks,
+}
+
+/// Find the workarounds for a master: those of the first matching rule, or
+/// [`Quirks::DEFAULT`] when no rule matches.
+pub(crate) fn resolve_quirks(
+    rules: &[QuirkRule],
+    grandmaster_identity: ClockIdentity,
+    sdo_id: SdoId,
+) -> Quirks {
+    rules
+        .iter()
+        .find(|rule| rule.matches.matches(grandmaster_identity, sdo_id))
+        .map(|rule| rule.quirks)
+        .unwrap_or_default()
+}
+
+/// Reasons a set of quirk rules can be rejected
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum QuirkConfigError {
+    /// More than [`MAX_QUIRK_RULES`] rules were given
+    TooManyRules(usize),
+}
+
+impl core::fmt::Display for QuirkConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            QuirkConfigError::TooManyRules(count) => write!(
+                f,
+                "at most {MAX_QUIRK_RULES} quirk rules are supported, but {count} were given"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for QuirkConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let vendor = ClockIdentity([0x00, 0x1b, 0x19, 1, 2, 3, 4, 5]);
+        let other = ClockIdentity([0x00, 0x1b, 0x1a, 1, 2, 3, 4, 5]);
+        let profile = SdoId::new(0x100).unwrap();
+        let tolerant = Quirks {
+            delay_resp_sequence_id: true,
+            ..Quirks::DEFAULT
+        };
+        let rules = [
+            QuirkRule {
+                matches: QuirkMatch::Oui([0x00, 0x1b, 0x19]),
+                quirks: tolerant,
+            },
+            QuirkRule {
+                matches: QuirkMatch::SdoId(profile),
+                quirks: Quirks::NONE,
+            },
+        ];
+
+        assert_eq!(resolve_quirks(&rules, vendor, profile), tolerant);
+        assert_eq!(resolve_quirks(&rules, other, profile), Quirks::NONE);
+        assert_eq!(
+            resolve_quirks(&rules, other, SdoId::default()),
+            Quirks::DEFAULT
+        );
+        assert_eq!(resolve_quirks(&[], vendor, profile), Quirks::DEFAULT);
+    }
+}
@@ -0,0 +1,236 @@
+use super::{IntervalBounds, StartupBurst};
+use crate::{
+    bmc::{ComparisonDataset, DatasetOrdering, MasterSelection, TelecomMasterSelection},
+    datastructures::common::ProfileIdentifier,
+    time::{Duration, Interval},
+};
+
+/// A set of defaults for the message intervals and the domain, see
+/// [`Role::preset`]
+///
+/// A profile also orders candidate masters as it specifies, so it can be
+/// passed to
+/// [`PtpInstance::with_master_selection`](crate::PtpInstance::with_master_selection).
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Profile {
+    /// The default delay request-response profile, see IEEE1588-2019 section
+    /// I.3
+    #[default]
+    Default,
+    /// The broadcast media profile of SMPTE ST 2059-2, with faster messages
+    /// in domain 127
+    Smpte2059,
+    /// The telecom profile of ITU-T G.8275.1 for full timing support from
+    /// the network, with fixed message rates in domain 24 and the alternate
+    /// BMCA of [`TelecomMasterSelection`]. Its clocks are told apart by their
+    /// clock class and localPriority rather than priority 1, see
+    /// [`PtpInstance::set_local_priority`](crate::PtpInstance::set_local_priority)
+    /// and [`Port::set_local_priority`](crate::Port::set_local_priority).
+    G8275_1,
+}
+
+impl Profile {
+    /// The message intervals the profile allows, to check a configuration
+    /// with [`PortConfig::validate_with_bounds`](crate::PortConfig::validate_with_bounds)
+    pub fn interval_bounds(self) -> IntervalBounds {
+        match self {
+            Profile::Default => IntervalBounds::DEFAULT_PROFILE,
+            Profile::Smpte2059 => IntervalBounds::SMPTE_2059,
+            Profile::G8275_1 => IntervalBounds::G8275_1,
+        }
+    }
+
+    /// The identifier of the profile, to advertise with
+    /// [`PtpInstance::set_profile_identifier`](crate::PtpInstance::set_profile_identifier)
+    pub fn identifier(self) -> ProfileIdentifier {
+        match self {
+            Profile::Default => ProfileIdentifier::DEFAULT_DELAY_REQUEST_RESPONSE,
+            Profile::Smpte2059 => ProfileIdentifier::SMPTE_2059_2,
+            Profile::G8275_1 => ProfileIdentifier::ITU_T_G8275_1,
+        }
+    }
+}
+
+impl MasterSelection for Profile {
+    fn compare(&self, a: &ComparisonDataset, b: &ComparisonDataset) -> DatasetOrdering {
+        match self {
+            Profile::Default | Profile::Smpte2059 => a.compare(b),
+            Profile::G8275_1 => TelecomMasterSelection.compare(a, b),
+        }
+    }
+}
+
+/// What an instance is for, as a shorthand for a consistent set of
+/// configuration values.
+///
+/// A role only picks defaults: every value of its [`RolePreset`] can still be
+/// changed before building the [`InstanceConfig`](crate::InstanceConfig) and
+/// [`PortConfig`](crate::PortConfig) from it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Role {
+    /// Provide the time to the network. Uses a low priority 1, so it wins the
+    /// BMCA against clocks left at the default priorities.
+    Grandmaster,
+    /// Take the time from one port and provide it on the others, with the
+    /// default priorities
+    Boundary,
+    /// Only ever take the time, never become master
+    OrdinarySlave,
+    /// Like [`Role::OrdinarySlave`], but measures the path delay more often
+    /// for a while after selecting a master, so devices that need a locked
+    /// clock before they can play out media get there sooner
+    MediaFollower,
+}
+
+/// The configuration values a [`Role`] expands to
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct RolePreset {
+    pub priority_1: u8,
+    pub priority_2: u8,
+    pub domain_number: u8,
+    pub slave_only: bool,
+    pub announce_interval: Interval,
+    pub announce_receipt_timeout: u8,
+    pub sync_interval: Interval,
+    /// The interval of [`DelayMechanism::E2E`](crate::DelayMechanism::E2E)
+    pub min_delay_req_interval: Interval,
+    /// See [`Port::set_startup_burst`](crate::Port::set_startup_burst)
+    pub startup_burst: Option<StartupBurst>,
+    /// See [`PortConfig::master_only`](crate::PortConfig::master_only)
+    pub master_only: bool,
+}
+
+impl Role {
+    /// The configuration values of this role within `profile`. The intervals
+    /// are the defaults of the profile, so they lie within
+    /// [`Profile::interval_bounds`].
+    pub fn preset(self, profile: Profile) -> RolePreset {
+        let (domain_number, announce, sync, min_delay_req) = match profile {
+            Profile::Default => (0, 1, 0, 0),
+            Profile::Smpte2059 => (127, -2, -3, -3),
+            Profile::G8275_1 => (24, -3, -4, -4),
+        };
+
+        let (priority_1, priority_2, slave_only) = match (self, profile) {
+            // The telecom profile fixes priority 1, its grandmasters win by
+            // their clock class
+            (Role::Grandmaster, Profile::G8275_1) => (128, 128, false),
+            (Role::Grandmaster, _) => (64, 128, false),
+            (Role::Boundary, _) => (128, 128, false),
+            (Role::OrdinarySlave | Role::MediaFollower, _) => (255, 255, true),
+        };
+
+        // The ports of a telecom grandmaster never take the time from others
+        let master_only = matches!((self, profile), (Role::Grandmaster, Profile::G8275_1));
+
+        let startup_burst = match self {
+            Role::MediaFollower => Some(StartupBurst {
+                duration: Duration::from_secs(30),
+                delay_req_interval: Interval::from_log_2(min_delay_req.min(-4)),
+            }),
+            _ => None,
+        };
+
+        RolePreset {
+            priority_1,
+            priority_2,
+            domain_number,
+            slave_only,
+            announce_interval: Interval::from_log_2(announce),
+            announce_receipt_timeout: 3,
+            sync_interval: Interval::from_log_2(sync),
+            min_delay_req_interval: Interval::from_log_2(min_delay_req),
+            startup_burst,
+            master_only,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{
+            CommunicationMode, DelayMechanism, LogMessageIntervals, ManagementPolicy,
+            TransmitEnable,
+        },
+        ClockIdentity, InstanceConfig, PortConfig, SdoId,
+    };
+
+    #[test]
+    fn presets_are_valid() {
+        for profile in [Profile::Default, Profile::Smpte2059, Profile::G8275_1] {
+            for role in [
+                Role::Grandmaster,
+                Role::Boundary,
+                Role::OrdinarySlave,
+                Role::MediaFollower,
+            ] {
+                let preset = role.preset(profile);
+                let instance = InstanceConfig {
+                    clock_identity: ClockIdentity::default(),
+                    priority_1: preset.priority_1,
+                    priority_2: preset.priority_2,
+                    domain_number: preset.domain_number,
+                    slave_only: preset.slave_only,
+                    sdo_id: SdoId::default(),
+                };
+                let port = PortConfig {
+                    delay_mechanism: DelayMechanism::E2E {
+                        interval: preset.min_delay_req_interval,
+                    },
+                    announce_interval: preset.announce_interval,
+                    announce_receipt_timeout: preset.announce_receipt_timeout,
+                    sync_interval: preset.sync_interval,
+                    master_only: preset.master_only,
+                    delay_asymmetry: Duration::ZERO,
+                    ingress_latency: Duration::ZERO,
+                    egress_latency: Duration::ZERO,
+                    management: ManagementPolicy::default(),
+                    communication_mode: CommunicationMode::Multicast,
+                    transmit: TransmitEnable::ALL,
+                    unicast_client: None,
+                    log_message_intervals: LogMessageIntervals::STANDARD,
+                };
+
+                assert_eq!(instance.validate(), Ok(()), "{role:?} in {profile:?}");
+                assert_eq!(
+                    port.validate_with_bounds(&instance, &profile.interval_bounds()),
+                    Ok(()),
+                    "{role:?} in {profile:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn media_follower_bursts() {
+        let follower = Role::MediaFollower.preset(Profile::Smpte2059);
+        let slave = Role::OrdinarySlave.preset(Profile::Smpte2059);
+
+        assert_eq!(
+            follower,
+            RolePreset {
+                startup_burst: follower.startup_burst,
+                ..slave
+            }
+        );
+        assert_eq!(
+            follower.startup_burst.map(|burst| burst.delay_req_interval),
+            Some(Interval::from_log_2(-4))
+        );
+        assert_eq!(slave.startup_burst, None);
+    }
+
+    #[test]
+    fn telecom_grandmaster() {
+        let grandmaster = Role::Grandmaster.preset(Profile::G8275_1);
+        assert_eq!(grandmaster.priority_1, 128);
+        assert_eq!(grandmaster.domain_number, 24);
+        assert!(grandmaster.master_only);
+
+        // Elsewhere a grandmaster may still follow a better one
+        assert!(!Role::Grandmaster.preset(Profile::Default).master_only);
+        assert!(!Role::Boundary.preset(Profile::G8275_1).master_only);
+    }
+}
@@ -0,0 +1,35 @@
+use crate::time::Duration;
+
+/// A network path that exists only in the timestamps of a port, for testing
+/// on loopback or virtual networks where the real path delay is effectively
+/// zero. **Never use this in production**: the port then reports a delay and
+/// offset that don't exist, and steers the clock away from its master.
+///
+/// As slave, the port pretends messages from its master arrive
+/// `delay + asymmetry` later than they did, and that its own delay requests
+/// left `delay - asymmetry` earlier than they did. So the measured mean path
+/// delay grows by `delay`, and an `asymmetry` not corrected for with
+/// [`PortConfig::delay_asymmetry`](crate::PortConfig::delay_asymmetry) shows
+/// up as an offset of that size, just like on a real path. Set with
+/// [`Port::set_simulated_path`](crate::Port::set_simulated_path).
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct SimulatedPath {
+    /// The mean path delay added to the path
+    pub delay: Duration,
+    /// How much longer the added path takes from the master than to it, in
+    /// the sense of
+    /// [`PortConfig::delay_asymmetry`](crate::PortConfig::delay_asymmetry)
+    pub asymmetry: Duration,
+}
+
+impl SimulatedPath {
+    /// The time added to messages from the master
+    pub(crate) fn master_to_slave(&self) -> Duration {
+        self.delay + self.asymmetry
+    }
+
+    /// The time added to messages to the master
+    pub(crate) fn slave_to_master(&self) -> Duration {
+        self.delay - self.asymmetry
+    }
+}
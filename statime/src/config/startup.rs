@@ -0,0 +1,19 @@
+use crate::time::{Duration, Interval};
+
+/// A phase right after a port starts following a master, in which it
+/// measures more often to converge faster.
+///
+/// While the burst lasts, the port sends delay requests at
+/// `delay_req_interval` instead of the configured interval, but never faster
+/// than the master allows in its delay responses. The filter is told about
+/// the burst through
+/// [`Filter::set_startup_burst`](crate::Filter::set_startup_burst), so it can
+/// react to new measurements sooner. Set with
+/// [`Port::set_startup_burst`](crate::Port::set_startup_burst).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct StartupBurst {
+    /// How long the burst lasts, counted from the first Sync of the master
+    pub duration: Duration,
+    /// The interval between delay requests during the burst
+    pub delay_req_interval: Interval,
+}
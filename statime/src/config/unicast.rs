@@ -0,0 +1,108 @@
+use crate::time::Interval;
+
+/// Maximum number of masters a port requests unicast transmission from
+pub const MAX_UNICAST_MASTERS: usize = 8;
+
+/// Limits on the unicast messages a port grants as master, in response to
+/// REQUEST_UNICAST_TRANSMISSION TLVs of its clients (16.1).
+///
+/// A grant lasts for the duration the client requested, at most
+/// `max_lease_duration`. Clients renew their grant by requesting it again
+/// before it expires. When a grant expires or is cancelled, the port stops
+/// sending those messages to that client. Set with
+/// [`Port::set_unicast_master`](crate::Port::set_unicast_master).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct UnicastMasterConfig {
+    /// The number of clients granted each type of message at the same time.
+    /// Requests of more clients are denied, as are requests of new clients
+    /// when the grant table of the port is full.
+    pub max_clients: usize,
+    /// The longest grant given, in seconds. Longer requests are granted this
+    /// duration instead.
+    pub max_lease_duration: u32,
+    /// The shortest interval between announce messages to a client. Requests
+    /// for a shorter interval are denied.
+    pub min_announce_interval: Interval,
+    /// The shortest interval between sync messages to a client. Requests for
+    /// a shorter interval are denied.
+    pub min_sync_interval: Interval,
+    /// The shortest interval between the delay requests of a client that the
+    /// port answers with delay responses. Requests for a shorter interval are
+    /// denied.
+    pub min_delay_resp_interval: Interval,
+}
+
+/// How a port requests unicast messages from masters as a client, by sending
+/// them REQUEST_UNICAST_TRANSMISSION TLVs (16.1).
+///
+/// The masters are the entries of the unicast master table of the runtime,
+/// referred to by their index, which the port contacts directly instead of
+/// discovering them from multicast announce messages. It requests announce
+/// messages from every master, so they are all foreign master candidates for
+/// the BMCA. Sync messages, and delay responses when it uses the end to end
+/// delay mechanism, are only requested from the master the BMCA selected as
+/// parent. All are requested at the configured intervals of the port.
+///
+/// The port renews its grants halfway through their duration. When a master
+/// denies a request, cancels a grant or doesn't answer, the port waits a
+/// while before asking it again. Set with [`PortConfig::unicast_client`], or
+/// [`Port::set_unicast_client`](crate::Port::set_unicast_client) while
+/// running.
+///
+/// [`PortConfig::unicast_client`]: crate::PortConfig::unicast_client
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct UnicastClientConfig {
+    /// The number of entries in the unicast master table, at most
+    /// [`MAX_UNICAST_MASTERS`]
+    pub masters: usize,
+    /// The duration of the grants requested, in seconds
+    pub lease_duration: u32,
+}
+
+impl UnicastClientConfig {
+    pub(crate) fn validate(&self) -> Result<(), UnicastConfigError> {
+        if self.masters == 0 || self.masters > MAX_UNICAST_MASTERS {
+            return Err(UnicastConfigError::InvalidMasterCount(self.masters));
+        }
+        if self.lease_duration == 0 {
+            return Err(UnicastConfigError::ZeroLeaseDuration);
+        }
+        Ok(())
+    }
+}
+
+/// Reasons a [`UnicastMasterConfig`] or [`UnicastClientConfig`] can be
+/// rejected
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UnicastConfigError {
+    /// More clients were configured than the grant table has slots for
+    TooManyClients { max_clients: usize, slots: usize },
+    /// No masters, or more than [`MAX_UNICAST_MASTERS`] masters, were
+    /// configured
+    InvalidMasterCount(usize),
+    /// A lease duration of zero seconds was configured, which masters deny
+    ZeroLeaseDuration,
+}
+
+impl core::fmt::Display for UnicastConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            UnicastConfigError::TooManyClients { max_clients, slots } => write!(
+                f,
+                "{max_clients} unicast clients were configured, but the grant table only has \
+                 {slots} slots"
+            ),
+            UnicastConfigError::InvalidMasterCount(count) => write!(
+                f,
+                "between 1 and {MAX_UNICAST_MASTERS} unicast masters are supported, but {count} \
+                 were configured"
+            ),
+            UnicastConfigError::ZeroLeaseDuration => {
+                write!(f, "the unicast lease duration must be at least one second")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UnicastConfigError {}
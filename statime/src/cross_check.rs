@@ -0,0 +1,213 @@
+//! Checking the time of one domain against that of another.
+//!
+//! Deployments that run redundant timing domains steer the clock from one of
+//! them, and want to know when the other stops agreeing with it. For this, a
+//! second [`PtpInstance`](crate::PtpInstance) in the verifying domain runs on
+//! the same clock, slave only and in
+//! [free run](crate::PtpInstance::set_free_run) so it never adjusts the
+//! clock. The ports of both instances measure their offset to the same clock,
+//! so the difference between those offsets is how far the domains are apart.
+//! A [`DomainCrossCheck`] is given the measurements of both, and raises an
+//! alarm when the domains diverge beyond a bound.
+
+use crate::{port::Measurement, time::Duration};
+
+/// Which of the two domains of a [`DomainCrossCheck`] a measurement was made
+/// in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CrossCheckDomain {
+    /// The domain the clock is steered from
+    Steering,
+    /// The domain the clock is only measured against
+    Verifying,
+}
+
+/// A change of the alarm of a [`DomainCrossCheck`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrossCheckEvent {
+    /// The domains were further apart than the bound for long enough
+    Diverged {
+        /// The divergence that raised the alarm
+        divergence: Duration,
+    },
+    /// The domains were back within the bound for long enough
+    Converged {
+        /// The divergence that cleared the alarm
+        divergence: Duration,
+    },
+}
+
+/// Compares the offsets measured in two domains on the same clock, see the
+/// [module documentation](self).
+///
+/// The divergence is the time of the verifying domain minus that of the
+/// steering domain. Every measurement of the verifying domain is compared
+/// with the last one of the steering domain, and is ignored until the
+/// steering domain measured anything. A single measurement doesn't change
+/// the alarm: it takes `persistence` measurements in a row on the other side
+/// of the bound.
+#[derive(Debug, Clone)]
+pub struct DomainCrossCheck {
+    bound: Duration,
+    persistence: u8,
+    steering_offset: Option<Duration>,
+    // Measurements in a row that disagree with the alarm
+    streak: u8,
+    alarm: bool,
+    divergence: Option<Duration>,
+    max_divergence: Duration,
+    alarms: u32,
+}
+
+impl DomainCrossCheck {
+    /// Check that the domains stay within `bound` of each other, changing
+    /// the alarm after `persistence` measurements in a row (at least 1)
+    pub fn new(bound: Duration, persistence: u8) -> Self {
+        Self {
+            bound: bound.abs(),
+            persistence: persistence.max(1),
+            steering_offset: None,
+            streak: 0,
+            alarm: false,
+            divergence: None,
+            max_divergence: Duration::ZERO,
+            alarms: 0,
+        }
+    }
+
+    /// Take a measurement made in `domain`, returning a change of the alarm
+    /// it caused, if any
+    pub fn record(
+        &mut self,
+        domain: CrossCheckDomain,
+        measurement: &Measurement,
+    ) -> Option<CrossCheckEvent> {
+        let verifying_offset = match domain {
+            CrossCheckDomain::Steering => {
+                self.steering_offset = Some(measurement.master_offset);
+                return None;
+            }
+            CrossCheckDomain::Verifying => measurement.master_offset,
+        };
+        // Offsets are the clock minus the master, so the clock drops out
+        let divergence = self.steering_offset? - verifying_offset;
+        self.divergence = Some(divergence);
+        self.max_divergence = self.max_divergence.max(divergence.abs());
+
+        let outside = divergence.abs() > self.bound;
+        if outside == self.alarm {
+            self.streak = 0;
+            return None;
+        }
+        self.streak += 1;
+        if self.streak < self.persistence {
+            return None;
+        }
+
+        self.streak = 0;
+        self.alarm = outside;
+        if outside {
+            self.alarms = self.alarms.saturating_add(1);
+            Some(CrossCheckEvent::Diverged { divergence })
+        } else {
+            Some(CrossCheckEvent::Converged { divergence })
+        }
+    }
+
+    /// Whether the domains are currently considered diverged
+    pub fn alarm(&self) -> bool {
+        self.alarm
+    }
+
+    /// The last divergence measured, if any
+    pub fn divergence(&self) -> Option<Duration> {
+        self.divergence
+    }
+
+    /// The largest divergence measured, in either direction
+    pub fn max_divergence(&self) -> Duration {
+        self.max_divergence
+    }
+
+    /// Number of times the alarm was raised
+    pub fn alarms(&self) -> u32 {
+        self.alarms
+    }
+
+    /// The bound the domains are to stay within
+    pub fn bound(&self) -> Duration {
+        self.bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{port::CorrectionBreakdown, Time, TimestampSource};
+
+    fn measurement(offset_ns: i64) -> Measurement {
+        Measurement {
+            event_time: Time::from_secs(1),
+            master_offset: Duration::from_nanos(offset_ns),
+            raw_receive_time: Time::from_secs(1),
+            receive_time: Time::from_secs(1),
+            timestamp_source: TimestampSource::Hardware,
+            correction: CorrectionBreakdown::default(),
+            follow_up_information: None,
+            neighbor_rate_ratio: None,
+        }
+    }
+
+    #[test]
+    fn needs_steering_measurement() {
+        let mut check = DomainCrossCheck::new(Duration::from_nanos(100), 1);
+        let verifying = measurement(1000);
+        assert_eq!(check.record(CrossCheckDomain::Verifying, &verifying), None);
+        assert_eq!(check.divergence(), None);
+
+        check.record(CrossCheckDomain::Steering, &measurement(50));
+        assert_eq!(
+            check.record(CrossCheckDomain::Verifying, &measurement(-30)),
+            None
+        );
+        assert_eq!(check.divergence(), Some(Duration::from_nanos(80)));
+    }
+
+    #[test]
+    fn alarm_persistence() {
+        let mut check = DomainCrossCheck::new(Duration::from_nanos(100), 3);
+        check.record(CrossCheckDomain::Steering, &measurement(0));
+
+        let mut record =
+            |offset_ns| check.record(CrossCheckDomain::Verifying, &measurement(offset_ns));
+
+        // A single outlier is not enough
+        assert_eq!(record(500), None);
+        assert_eq!(record(0), None);
+        assert_eq!(record(500), None);
+        assert_eq!(record(-500), None);
+        assert_eq!(
+            record(500),
+            Some(CrossCheckEvent::Diverged {
+                divergence: Duration::from_nanos(-500)
+            })
+        );
+
+        // Neither is a single measurement within the bound
+        assert_eq!(record(500), None);
+        assert_eq!(record(10), None);
+        assert_eq!(record(800), None);
+        assert_eq!(record(10), None);
+        assert_eq!(record(-10), None);
+        assert_eq!(
+            record(20),
+            Some(CrossCheckEvent::Converged {
+                divergence: Duration::from_nanos(-20)
+            })
+        );
+
+        assert!(!check.alarm());
+        assert_eq!(check.alarms(), 1);
+        assert_eq!(check.max_divergence(), Duration::from_nanos(800));
+    }
+}
@@ -1,11 +1,14 @@
 use core::cmp::Ordering;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 /// How accurate the underlying clock device is expected to be when not
 /// synchronized.
+///
+/// Accuracies are ordered by their value, so the more accurate of two
+/// compares as less, like in the BMCA.
 pub enum ClockAccuracy {
-    /// Reserved
-    Reserved,
+    /// A value the standard reserves, holding it
+    Reserved(u8),
     /// Accurate within 1 ps
     PS1,
     /// Accurate within 2.5 ps
@@ -69,7 +72,7 @@ pub enum ClockAccuracy {
 impl ClockAccuracy {
     pub(crate) fn to_primitive(self) -> u8 {
         match self {
-            Self::Reserved => 0x00,
+            Self::Reserved(value) => value,
             Self::PS1 => 0x17,
             Self::PS2_5 => 0x18,
             Self::PS10 => 0x19,
@@ -104,7 +107,7 @@ impl ClockAccuracy {
 
     pub(crate) fn from_primitive(value: u8) -> Self {
         match value {
-            0x00..=0x16 | 0x32..=0x7f | 0xff => Self::Reserved,
+            0x00..=0x16 | 0x32..=0x7f | 0xff => Self::Reserved(value),
             0x17 => Self::PS1,
             0x18 => Self::PS2_5,
             0x19 => Self::PS10,
@@ -136,9 +139,28 @@ impl ClockAccuracy {
             0xfe => ClockAccuracy::Unknown,
         }
     }
+}
+
+impl From<u8> for ClockAccuracy {
+    fn from(value: u8) -> Self {
+        Self::from_primitive(value)
+    }
+}
+
+impl From<ClockAccuracy> for u8 {
+    fn from(value: ClockAccuracy) -> Self {
+        value.to_primitive()
+    }
+}
+
+impl PartialOrd for ClockAccuracy {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
-    /// high accuracy to low accuracy
-    pub(crate) fn cmp_numeric(&self, other: &Self) -> Ordering {
+impl Ord for ClockAccuracy {
+    fn cmp(&self, other: &Self) -> Ordering {
         self.to_primitive().cmp(&other.to_primitive())
     }
 }
@@ -155,11 +177,9 @@ mod tests {
 
     #[test]
     fn network_protocol_values() {
-        for i in 0..u8::MAX {
+        for i in 0..=u8::MAX {
             let protocol = ClockAccuracy::from_primitive(i);
-            if !matches!(protocol, ClockAccuracy::Reserved) {
-                assert_eq!(protocol.to_primitive(), i);
-            }
+            assert_eq!(protocol.to_primitive(), i);
         }
 
         assert_eq!(ClockAccuracy::ProfileSpecific(5).to_primitive(), 0x85);
@@ -171,6 +191,7 @@ mod tests {
         let a = ClockAccuracy::PS1;
         let b = ClockAccuracy::PS10;
 
-        assert_eq!(a.cmp_numeric(&b), Ordering::Less);
+        assert_eq!(a.cmp(&b), Ordering::Less);
+        assert!(ClockAccuracy::SGT10 < ClockAccuracy::Unknown);
     }
 }
@@ -0,0 +1,147 @@
+use core::cmp::Ordering;
+
+/// The class of a clock, describing how it is synchronized and what it does
+/// when it loses synchronization, see IEEE1588-2019 section 7.6.2.5.
+///
+/// Classes are ordered by their value, so that the better class of two
+/// compares as less, like in the BMCA. Values without a meaning in the
+/// standard are kept as [`ClockClass::Reserved`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ClockClass {
+    /// Synchronized to a primary reference time source, with the PTP
+    /// timescale (6). Such a clock can't be a slave.
+    PrimaryReference,
+    /// Lost synchronization to its primary reference time source, but still
+    /// in holdover within its specifications (7). Such a clock can't be a
+    /// slave.
+    PrimaryReferenceHoldover,
+    /// Synchronized to an application-specific time source, with an
+    /// arbitrary timescale (13). Such a clock can't be a slave.
+    ApplicationSpecific,
+    /// Lost synchronization to its application-specific time source, but
+    /// still in holdover within its specifications (14). Such a clock can't be
+    /// a slave.
+    ApplicationSpecificHoldover,
+    /// A [`PrimaryReferenceHoldover`](Self::PrimaryReferenceHoldover) clock
+    /// outside of its holdover specifications, following degradation
+    /// alternative A (52)
+    PrimaryReferenceDegradedA,
+    /// An [`ApplicationSpecificHoldover`](Self::ApplicationSpecificHoldover)
+    /// clock outside of its holdover specifications, following degradation
+    /// alternative A (58)
+    ApplicationSpecificDegradedA,
+    /// A [`PrimaryReferenceHoldover`](Self::PrimaryReferenceHoldover) clock
+    /// outside of its holdover specifications, following degradation
+    /// alternative B (187)
+    PrimaryReferenceDegradedB,
+    /// An [`ApplicationSpecificHoldover`](Self::ApplicationSpecificHoldover)
+    /// clock outside of its holdover specifications, following degradation
+    /// alternative B (193)
+    ApplicationSpecificDegradedB,
+    /// A class defined by an alternate PTP profile, holding its value (68 to
+    /// 122, 133 to 170 and 216 to 232)
+    AlternateProfile(u8),
+    /// The class of clocks that are not in any of the other classes (248),
+    /// which is a good option for most use cases
+    #[default]
+    Default,
+    /// A slave-only clock (255)
+    SlaveOnly,
+    /// A value the standard reserves, holding it
+    Reserved(u8),
+}
+
+impl ClockClass {
+    pub(crate) fn to_primitive(self) -> u8 {
+        match self {
+            Self::PrimaryReference => 6,
+            Self::PrimaryReferenceHoldover => 7,
+            Self::ApplicationSpecific => 13,
+            Self::ApplicationSpecificHoldover => 14,
+            Self::PrimaryReferenceDegradedA => 52,
+            Self::ApplicationSpecificDegradedA => 58,
+            Self::PrimaryReferenceDegradedB => 187,
+            Self::ApplicationSpecificDegradedB => 193,
+            Self::Default => 248,
+            Self::SlaveOnly => 255,
+            Self::AlternateProfile(value) | Self::Reserved(value) => value,
+        }
+    }
+
+    pub(crate) fn from_primitive(value: u8) -> Self {
+        match value {
+            6 => Self::PrimaryReference,
+            7 => Self::PrimaryReferenceHoldover,
+            13 => Self::ApplicationSpecific,
+            14 => Self::ApplicationSpecificHoldover,
+            52 => Self::PrimaryReferenceDegradedA,
+            58 => Self::ApplicationSpecificDegradedA,
+            187 => Self::PrimaryReferenceDegradedB,
+            193 => Self::ApplicationSpecificDegradedB,
+            248 => Self::Default,
+            255 => Self::SlaveOnly,
+            68..=122 | 133..=170 | 216..=232 => Self::AlternateProfile(value),
+            _ => Self::Reserved(value),
+        }
+    }
+
+    /// Whether a clock of this class never becomes a slave, which is the case
+    /// for classes 1 through 127 (IEEE1588-2019 section 9.3.3)
+    pub fn is_master_only(self) -> bool {
+        (1..=127).contains(&self.to_primitive())
+    }
+}
+
+impl From<u8> for ClockClass {
+    fn from(value: u8) -> Self {
+        Self::from_primitive(value)
+    }
+}
+
+impl From<ClockClass> for u8 {
+    fn from(value: ClockClass) -> Self {
+        value.to_primitive()
+    }
+}
+
+impl PartialOrd for ClockClass {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ClockClass {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.to_primitive().cmp(&other.to_primitive())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_protocol_values() {
+        for i in 0..=u8::MAX {
+            assert_eq!(ClockClass::from_primitive(i).to_primitive(), i);
+        }
+
+        assert_eq!(ClockClass::from_primitive(6), ClockClass::PrimaryReference);
+        assert_eq!(
+            ClockClass::from_primitive(100),
+            ClockClass::AlternateProfile(100)
+        );
+        assert_eq!(ClockClass::from_primitive(128), ClockClass::Reserved(128));
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(ClockClass::PrimaryReference < ClockClass::PrimaryReferenceHoldover);
+        assert!(ClockClass::AlternateProfile(100) < ClockClass::Default);
+        assert!(ClockClass::Default < ClockClass::SlaveOnly);
+
+        assert!(ClockClass::PrimaryReference.is_master_only());
+        assert!(!ClockClass::Reserved(0).is_master_only());
+        assert!(!ClockClass::Default.is_master_only());
+    }
+}
@@ -7,6 +7,20 @@ use crate::datastructures::{WireFormat, WireFormatError};
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, PartialOrd, Ord, Hash)]
 pub struct ClockIdentity(pub [u8; 8]);
 
+/// Formats the identity as colon separated hex bytes, like
+/// `00:1b:19:ff:fe:00:00:01`
+impl core::fmt::Display for ClockIdentity {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (index, byte) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
 impl WireFormat for ClockIdentity {
     fn wire_size(&self) -> usize {
         8
@@ -46,4 +60,10 @@ mod tests {
             assert_eq!(deserialized_data, object_representation);
         }
     }
+
+    #[test]
+    fn display() {
+        let identity = ClockIdentity([0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x01]);
+        assert_eq!(std::format!("{identity}"), "00:1b:19:ff:fe:00:00:01");
+    }
 }
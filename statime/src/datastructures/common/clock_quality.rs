@@ -1,4 +1,4 @@
-use super::clock_accuracy::ClockAccuracy;
+use super::{clock_accuracy::ClockAccuracy, clock_class::ClockClass};
 use crate::datastructures::{WireFormat, WireFormatError};
 
 /// A description of the accuracy and type of a clock.
@@ -6,13 +6,12 @@ use crate::datastructures::{WireFormat, WireFormatError};
 pub struct ClockQuality {
     /// The PTP clock class.
     ///
-    /// Per the standard, 248 is the default, and a good option for most use
-    /// cases. For grandmaster clocks, this should be below 128 to ensure the
-    /// clock never takes time from another source. A value of 6 is a good
-    /// option for a node with an external time source.
-    ///
-    /// For other potential values, see IEEE1588-2019 section 7.6.2.5
-    pub clock_class: u8,
+    /// Per the standard, [`ClockClass::Default`] is the default, and a good
+    /// option for most use cases. For grandmaster clocks, this should be
+    /// [master only](ClockClass::is_master_only) to ensure the clock never
+    /// takes time from another source. [`ClockClass::PrimaryReference`] is a
+    /// good option for a node with an external time source.
+    pub clock_class: ClockClass,
 
     /// The accuracy of the clock
     pub clock_accuracy: ClockAccuracy,
@@ -28,7 +27,7 @@ impl WireFormat for ClockQuality {
     }
 
     fn serialize(&self, buffer: &mut [u8]) -> Result<(), WireFormatError> {
-        buffer[0] = self.clock_class;
+        buffer[0] = self.clock_class.to_primitive();
         buffer[1] = self.clock_accuracy.to_primitive();
         buffer[2..4].copy_from_slice(&self.offset_scaled_log_variance.to_be_bytes());
         Ok(())
@@ -36,7 +35,7 @@ impl WireFormat for ClockQuality {
 
     fn deserialize(buffer: &[u8]) -> Result<Self, WireFormatError> {
         Ok(Self {
-            clock_class: buffer[0],
+            clock_class: ClockClass::from_primitive(buffer[0]),
             clock_accuracy: ClockAccuracy::from_primitive(buffer[1]),
             offset_scaled_log_variance: u16::from_be_bytes(buffer[2..4].try_into().unwrap()),
         })
@@ -52,7 +51,7 @@ mod tests {
         let representations = [(
             [0x7a, 0x2a, 0x12, 0x34u8],
             ClockQuality {
-                clock_class: 122,
+                clock_class: ClockClass::AlternateProfile(122),
                 clock_accuracy: ClockAccuracy::MS2_5,
                 offset_scaled_log_variance: 0x1234,
             },
@@ -0,0 +1,170 @@
+use fixed::types::I96F32;
+
+use super::TlvType;
+use crate::{datastructures::WireFormatError, time::Duration};
+
+/// The organization id and sub type of the FOLLOW_UP information TLV
+const ORGANIZATION: [u8; 6] = [0x00, 0x80, 0xc2, 0x00, 0x00, 0x01];
+
+/// Scale of the rate and frequency fields, which are fractions times 2^41
+const RATE_SCALE: f64 = (1u64 << 41) as f64;
+
+/// The FOLLOW_UP information TLV that IEEE 802.1AS-2020 section 11.4.4.3
+/// adds to follow up messages, describing how the grandmaster time reached
+/// the sender.
+///
+/// It is sent as an ORGANIZATION_EXTENSION TLV with organization id 00-80-C2
+/// and sub type 1. Statime doesn't compute these values itself: a runtime
+/// implementing the relaying of 802.1AS can set what the master sends with
+/// [`Port::set_follow_up_information`](crate::Port::set_follow_up_information),
+/// and read what it received from
+/// [`Measurement::follow_up_information`](crate::Measurement::follow_up_information).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FollowUpInformation {
+    /// The ratio of the frequency of the grandmaster to that of the sender,
+    /// minus one, times 2^41
+    pub cumulative_scaled_rate_offset: i32,
+    /// Changes whenever the time base of the grandmaster does, like after a
+    /// change of grandmaster or a phase or frequency step
+    pub gm_time_base_indicator: u16,
+    /// The time of the current grandmaster minus that of the previous one,
+    /// at the last change
+    pub last_gm_phase_change: Duration,
+    /// The fractional frequency offset of the current grandmaster relative
+    /// to the previous one, times 2^41
+    pub scaled_last_gm_freq_change: i32,
+}
+
+impl FollowUpInformation {
+    /// The size of the TLV on the wire
+    pub(crate) const TLV_SIZE: usize = 32;
+
+    /// The ratio of the frequency of the grandmaster to that of the sender
+    pub fn rate_ratio(&self) -> f64 {
+        1.0 + self.cumulative_scaled_rate_offset as f64 / RATE_SCALE
+    }
+
+    /// The fractional frequency offset of the last change of grandmaster
+    pub fn last_gm_freq_change(&self) -> f64 {
+        self.scaled_last_gm_freq_change as f64 / RATE_SCALE
+    }
+
+    /// The information a bridge passes on after receiving this over a link
+    /// with the given neighbor rate ratio, see
+    /// [`Measurement::neighbor_rate_ratio`](crate::Measurement::neighbor_rate_ratio).
+    /// The rate ratio to the grandmaster picks up that of the link, the rest
+    /// stays as it is.
+    pub fn with_neighbor_rate_ratio(self, neighbor_rate_ratio: f64) -> Self {
+        let offset = (self.rate_ratio() * neighbor_rate_ratio - 1.0) * RATE_SCALE;
+        Self {
+            // Saturating, like all float to integer casts
+            cumulative_scaled_rate_offset: libm::round(offset) as i32,
+            ..self
+        }
+    }
+
+    pub(crate) fn serialize_tlv(&self, buffer: &mut [u8]) -> Result<(), WireFormatError> {
+        let buffer = buffer
+            .get_mut(..Self::TLV_SIZE)
+            .ok_or(WireFormatError::BufferTooShort)?;
+
+        // lastGmPhaseChange is a 96 bit ScaledNs, nanoseconds times 2^16
+        let limit = 1i128 << 95;
+        let phase_change =
+            (self.last_gm_phase_change.nanos().to_bits() >> 16).clamp(-limit, limit - 1);
+
+        buffer[0..2].copy_from_slice(&TlvType::OrganizationExtension.to_primitive().to_be_bytes());
+        buffer[2..4].copy_from_slice(&((Self::TLV_SIZE - 4) as u16).to_be_bytes());
+        buffer[4..10].copy_from_slice(&ORGANIZATION);
+        buffer[10..14].copy_from_slice(&self.cumulative_scaled_rate_offset.to_be_bytes());
+        buffer[14..16].copy_from_slice(&self.gm_time_base_indicator.to_be_bytes());
+        buffer[16..28].copy_from_slice(&phase_change.to_be_bytes()[4..]);
+        buffer[28..32].copy_from_slice(&self.scaled_last_gm_freq_change.to_be_bytes());
+
+        Ok(())
+    }
+
+    /// Read the value of a TLV, if it is a FOLLOW_UP information TLV
+    pub(crate) fn deserialize_value(tlv_type: TlvType, value: &[u8]) -> Option<Self> {
+        if tlv_type != TlvType::OrganizationExtension || value.get(..6)? != ORGANIZATION {
+            return None;
+        }
+        let value: &[u8; Self::TLV_SIZE - 4] = value.try_into().ok()?;
+
+        // Sign extend the 96 bits of the phase change
+        let sign = if value[12] & 0x80 != 0 { 0xff } else { 0 };
+        let mut phase_change = [sign; 16];
+        phase_change[4..].copy_from_slice(&value[12..24]);
+        let phase_change = i128::from_be_bytes(phase_change) << 16;
+
+        Some(Self {
+            cumulative_scaled_rate_offset: i32::from_be_bytes(value[6..10].try_into().unwrap()),
+            gm_time_base_indicator: u16::from_be_bytes([value[10], value[11]]),
+            last_gm_phase_change: Duration::from_fixed_nanos(I96F32::from_bits(phase_change)),
+            scaled_last_gm_freq_change: i32::from_be_bytes(value[24..28].try_into().unwrap()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follow_up_information_wireformat() {
+        let information = FollowUpInformation {
+            cumulative_scaled_rate_offset: -2199,
+            gm_time_base_indicator: 0x0102,
+            last_gm_phase_change: Duration::from_fixed_nanos(-1.5),
+            scaled_last_gm_freq_change: 1 << 20,
+        };
+
+        let mut buffer = [0; FollowUpInformation::TLV_SIZE];
+        information.serialize_tlv(&mut buffer).unwrap();
+        assert_eq!(
+            buffer,
+            [
+                0x00, 0x03, 0x00, 0x1c, // type, length
+                0x00, 0x80, 0xc2, 0x00, 0x00, 0x01, // organization id and sub type
+                0xff, 0xff, 0xf7, 0x69, // cumulativeScaledRateOffset
+                0x01, 0x02, // gmTimeBaseIndicator
+                0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0x80,
+                0x00, // lastGmPhaseChange
+                0x00, 0x10, 0x00, 0x00, // scaledLastGmFreqChange
+            ]
+        );
+
+        assert_eq!(
+            FollowUpInformation::deserialize_value(TlvType::OrganizationExtension, &buffer[4..]),
+            Some(information)
+        );
+        assert!((information.rate_ratio() - (1.0 - 1e-9)).abs() < 1e-12);
+
+        // A bridge adds the rate ratio of its link
+        let relayed = information.with_neighbor_rate_ratio(1.0 + 2e-9);
+        assert_eq!(relayed.cumulative_scaled_rate_offset, 2199);
+        assert_eq!(
+            relayed.last_gm_phase_change,
+            information.last_gm_phase_change
+        );
+
+        // Other organization extensions are not mistaken for it
+        let mut other = buffer;
+        other[9] = 0x02;
+        assert_eq!(
+            FollowUpInformation::deserialize_value(TlvType::OrganizationExtension, &other[4..]),
+            None
+        );
+        assert_eq!(
+            FollowUpInformation::deserialize_value(
+                TlvType::OrganizationExtensionPropagate,
+                &buffer[4..]
+            ),
+            None
+        );
+        assert_eq!(
+            FollowUpInformation::deserialize_value(TlvType::OrganizationExtension, &buffer[4..20]),
+            None
+        );
+    }
+}
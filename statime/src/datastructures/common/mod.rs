@@ -1,20 +1,28 @@
 //! Common data structures that are used throughout the protocol
 
 mod clock_accuracy;
+mod clock_class;
 mod clock_identity;
 mod clock_quality;
+mod follow_up_information;
 mod leap_indicator;
+mod path_trace;
 mod port_identity;
+mod profile_identifier;
 mod time_interval;
 mod time_source;
 mod timestamp;
 mod tlv;
 
 pub use clock_accuracy::*;
+pub use clock_class::*;
 pub use clock_identity::*;
 pub use clock_quality::*;
+pub use follow_up_information::*;
 pub use leap_indicator::*;
+pub(crate) use path_trace::*;
 pub(crate) use port_identity::*;
+pub use profile_identifier::*;
 pub(crate) use time_interval::*;
 pub use time_source::*;
 pub use timestamp::*;
@@ -0,0 +1,142 @@
+use super::{ClockIdentity, TlvType};
+use crate::datastructures::{WireFormat, WireFormatError};
+
+/// Most clock identities kept of a path trace
+pub(crate) const MAX_PATH_TRACE: usize = 8;
+
+/// The clocks an announce message passed on its way from the grandmaster, as
+/// carried in its PATH_TRACE TLV, see IEEE1588-2019 section 16.2
+///
+/// Only the first [`MAX_PATH_TRACE`] identities are kept. A longer path is
+/// marked truncated, and is not sent on: like a message that would grow too
+/// large, the TLV is left out of the announce messages instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct PathTrace {
+    identities: [ClockIdentity; MAX_PATH_TRACE],
+    len: u8,
+    truncated: bool,
+}
+
+impl PathTrace {
+    /// The path of a grandmaster, which only contains itself
+    pub(crate) fn new(clock_identity: ClockIdentity) -> Self {
+        let mut path_trace = Self::default();
+        path_trace.push(clock_identity);
+        path_trace
+    }
+
+    pub(crate) fn identities(&self) -> &[ClockIdentity] {
+        &self.identities[..self.len as usize]
+    }
+
+    pub(crate) fn contains(&self, clock_identity: ClockIdentity) -> bool {
+        self.identities().contains(&clock_identity)
+    }
+
+    /// Add a clock at the end of the path, or mark the path truncated when it
+    /// is full
+    pub(crate) fn push(&mut self, clock_identity: ClockIdentity) {
+        match self.identities.get_mut(self.len as usize) {
+            Some(slot) => {
+                *slot = clock_identity;
+                self.len += 1;
+            }
+            None => self.truncated = true,
+        }
+    }
+
+    /// The size of the TLV on the wire, zero when it is left out
+    pub(crate) fn tlv_size(&self) -> usize {
+        if self.len == 0 || self.truncated {
+            0
+        } else {
+            4 + 8 * self.len as usize
+        }
+    }
+
+    /// Write the PATH_TRACE TLV, if there is one to send, see
+    /// [`tlv_size`](Self::tlv_size)
+    pub(crate) fn serialize_tlv(&self, buffer: &mut [u8]) -> Result<(), WireFormatError> {
+        let size = self.tlv_size();
+        if size == 0 {
+            return Ok(());
+        }
+        if buffer.len() < size {
+            return Err(WireFormatError::BufferTooShort);
+        }
+
+        buffer[0..2].copy_from_slice(&TlvType::PathTrace.to_primitive().to_be_bytes());
+        buffer[2..4].copy_from_slice(&((size - 4) as u16).to_be_bytes());
+        for (identity, chunk) in self.identities().iter().zip(buffer[4..size].chunks_mut(8)) {
+            identity.serialize(chunk)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the value of a PATH_TRACE TLV. A trailing partial identity is
+    /// ignored.
+    pub(crate) fn deserialize_value(value: &[u8]) -> Result<Self, WireFormatError> {
+        let mut path_trace = Self::default();
+        for chunk in value.chunks_exact(8) {
+            path_trace.push(ClockIdentity::deserialize(chunk)?);
+            if path_trace.truncated {
+                break;
+            }
+        }
+
+        Ok(path_trace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_trace_wireformat() {
+        let first = ClockIdentity([0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x01]);
+        let second = ClockIdentity([0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x02]);
+
+        let mut path_trace = PathTrace::new(first);
+        path_trace.push(second);
+
+        let mut buffer = [0; 20];
+        assert_eq!(path_trace.tlv_size(), 20);
+        path_trace.serialize_tlv(&mut buffer).unwrap();
+        assert_eq!(
+            buffer,
+            [
+                0x00, 0x08, 0x00, 0x10, // type, length
+                0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x01, // first
+                0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x02, // second
+            ]
+        );
+
+        assert_eq!(
+            PathTrace::deserialize_value(&buffer[4..]).unwrap(),
+            path_trace
+        );
+        assert!(path_trace.contains(second));
+        assert!(!path_trace.contains(ClockIdentity::default()));
+    }
+
+    #[test]
+    fn path_trace_truncated() {
+        let mut path_trace = PathTrace::default();
+        assert_eq!(path_trace.tlv_size(), 0);
+
+        for index in 0..MAX_PATH_TRACE as u8 {
+            path_trace.push(ClockIdentity([index; 8]));
+        }
+        assert_eq!(path_trace.tlv_size(), 4 + 8 * MAX_PATH_TRACE);
+
+        path_trace.push(ClockIdentity([0xff; 8]));
+        assert_eq!(path_trace.identities().len(), MAX_PATH_TRACE);
+        assert_eq!(path_trace.tlv_size(), 0);
+
+        let mut buffer = [0; 4];
+        path_trace.serialize_tlv(&mut buffer).unwrap();
+        assert_eq!(buffer, [0; 4]);
+    }
+}
@@ -10,6 +10,24 @@ pub(crate) struct PortIdentity {
     pub(crate) port_number: u16,
 }
 
+impl PortIdentity {
+    /// Stands for all ports, as target of a signaling or management message
+    /// (13.12.1, 15.4.1.2)
+    pub(crate) const ALL: Self = Self {
+        clock_identity: ClockIdentity([0xff; 8]),
+        port_number: 0xffff,
+    };
+
+    /// Whether a message with the given target is meant for this port
+    pub(crate) fn is_targeted_by(&self, target: PortIdentity) -> bool {
+        let all_clocks = target.clock_identity == Self::ALL.clock_identity;
+        let all_ports = target.port_number == Self::ALL.port_number;
+
+        (all_clocks || target.clock_identity == self.clock_identity)
+            && (all_ports || target.port_number == self.port_number)
+    }
+}
+
 impl WireFormat for PortIdentity {
     fn wire_size(&self) -> usize {
         10
@@ -0,0 +1,64 @@
+use super::TlvType;
+use crate::datastructures::messages::message_tlvs;
+
+/// The identifier of a PTP profile, see IEEE1588-2019 section 20.3.3. The
+/// first three bytes are the OUI of the organization that defined the
+/// profile, followed by the profile number and version.
+///
+/// IEEE1588 has no way to advertise the profile a node runs, so statime puts
+/// it in an ORGANIZATION_EXTENSION_DO_NOT_PROPAGATE TLV of its announce
+/// messages, see
+/// [`PtpInstance::set_profile_identifier`](crate::PtpInstance::set_profile_identifier).
+/// The organization id and sub type of that TLV together form the
+/// identifier, and it carries no further data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProfileIdentifier(pub [u8; 6]);
+
+/// Formats the identifier as dash separated hex bytes, like
+/// `00-1B-19-00-01-00`
+impl core::fmt::Display for ProfileIdentifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (index, byte) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str("-")?;
+            }
+            write!(f, "{byte:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+impl ProfileIdentifier {
+    /// The default delay request-response profile, see IEEE1588-2019 section
+    /// I.3.2
+    pub const DEFAULT_DELAY_REQUEST_RESPONSE: Self = Self([0x00, 0x1b, 0x19, 0x00, 0x01, 0x00]);
+    /// The broadcast media profile of SMPTE ST 2059-2
+    pub const SMPTE_2059_2: Self = Self([0x68, 0x97, 0xe8, 0x00, 0x01, 0x00]);
+    /// The telecom profile of ITU-T G.8275.1, version 2
+    pub const ITU_T_G8275_1: Self = Self([0x00, 0x19, 0xa7, 0x01, 0x02, 0x03]);
+
+    /// The identifier advertised in a message, if any
+    pub(crate) fn find_in(data: &[u8]) -> Option<Self> {
+        message_tlvs(data).find_map(|(tlv_type, value)| {
+            match (tlv_type, <[u8; 6]>::try_from(value)) {
+                (TlvType::OrganizationExtensionDoNotPropagate, Ok(identifier)) => {
+                    Some(Self(identifier))
+                }
+                _ => None,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            std::format!("{}", ProfileIdentifier::SMPTE_2059_2),
+            "68-97-E8-00-01-00"
+        );
+    }
+}
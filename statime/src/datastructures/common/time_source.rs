@@ -1,24 +1,33 @@
 /// What the time values for a system are derived from
 ///
-/// This enum encodes the root source of a system's time values. For most use
-/// cases, the default `InternalOscillator` will suffice.
-#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+/// This enum encodes the root source of a system's time values, see
+/// IEEE1588-2019 section 7.6.2.8. For most use cases, the default
+/// `InternalOscillator` will suffice.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TimeSource {
+    /// An atomic clock, or a clock synchronized to one
     AtomicClock,
+    /// A satellite navigation system, like GPS
     Gnss,
+    /// A terrestrial radio time signal
     TerrestrialRadio,
+    /// A serial time code, like IRIG-B
     SerialTimeCode,
+    /// Another PTP domain
     Ptp,
+    /// NTP
     Ntp,
+    /// Set by hand
     HandSet,
+    /// Any other source
     Other,
+    /// A free running oscillator
     #[default]
     InternalOscillator,
+    /// Specific to a profile
     ProfileSpecific(u8),
-    Reserved,
-    /// Time source is unknown. This is not an official variant from the spec,
-    /// but we just need it in practise
-    Unknown(u8),
+    /// A value the standard reserves, holding it
+    Reserved(u8),
 }
 
 impl TimeSource {
@@ -34,8 +43,7 @@ impl TimeSource {
             Self::Other => 0x90,
             Self::InternalOscillator => 0xa0,
             Self::ProfileSpecific(p) => 0xf0 + p,
-            Self::Reserved => 0xff,
-            Self::Unknown(v) => v,
+            Self::Reserved(v) => v,
         }
     }
 
@@ -51,19 +59,30 @@ impl TimeSource {
             0x90 => Self::Other,
             0xa0 => Self::InternalOscillator,
             0xf0..=0xfe => Self::ProfileSpecific(value - 0xf0),
-            0xff => TimeSource::Reserved,
-            v => TimeSource::Unknown(v),
+            v => TimeSource::Reserved(v),
         }
     }
 }
 
+impl From<u8> for TimeSource {
+    fn from(value: u8) -> Self {
+        Self::from_primitive(value)
+    }
+}
+
+impl From<TimeSource> for u8 {
+    fn from(value: TimeSource) -> Self {
+        value.to_primitive()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn network_protocol_values() {
-        for i in 0..u8::MAX {
+        for i in 0..=u8::MAX {
             let protocol = TimeSource::from_primitive(i);
             assert_eq!(protocol.to_primitive(), i);
         }
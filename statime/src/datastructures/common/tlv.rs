@@ -2,6 +2,13 @@ use arrayvec::ArrayVec;
 
 use crate::datastructures::{WireFormat, WireFormatError};
 
+// Counts the TLVs examined on the current thread, to check that the work of
+// parsing a message is bounded
+#[cfg(test)]
+std::thread_local! {
+    pub(crate) static TLVS_PEEKED: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Tlv {
     pub tlv_type: TlvType,
@@ -9,8 +16,31 @@ pub(crate) struct Tlv {
 }
 
 impl Tlv {
-    // TODO: Determine the best max value
-    const CAPACITY: usize = 4;
+    // Fits the 8 bytes of a GRANT_UNICAST_TRANSMISSION TLV, and the 12 bytes
+    // of organization extensions like the message interval request TLV of
+    // IEEE 802.1AS
+    pub(crate) const CAPACITY: usize = 16;
+
+    /// The type and wire size of the TLV at the start of `buffer`, without
+    /// parsing its value. This allows skipping TLVs with values too large to
+    /// hold.
+    pub(crate) fn peek(buffer: &[u8]) -> Result<(TlvType, usize), WireFormatError> {
+        #[cfg(test)]
+        TLVS_PEEKED.with(|peeked| peeked.set(peeked.get() + 1));
+
+        if buffer.len() < 4 {
+            return Err(WireFormatError::BufferTooShort);
+        }
+
+        let tlv_type = TlvType::from_primitive(u16::from_be_bytes([buffer[0], buffer[1]]));
+        let size = 4 + u16::from_be_bytes([buffer[2], buffer[3]]) as usize;
+
+        if buffer.len() < size {
+            return Err(WireFormatError::BufferTooShort);
+        }
+
+        Ok((tlv_type, size))
+    }
 }
 
 impl WireFormat for Tlv {
@@ -19,6 +49,10 @@ impl WireFormat for Tlv {
     }
 
     fn serialize(&self, buffer: &mut [u8]) -> Result<(), WireFormatError> {
+        if buffer.len() < self.wire_size() {
+            return Err(WireFormatError::BufferTooShort);
+        }
+
         buffer[0..][..2].copy_from_slice(&self.tlv_type.to_primitive().to_be_bytes());
         buffer[2..][..2].copy_from_slice(&(self.value.len() as u16).to_be_bytes());
         buffer[4..][..self.value.len()].copy_from_slice(&self.value);
@@ -23,6 +23,8 @@ pub(crate) struct DefaultDS {
     pub(crate) domain_number: u8,
     pub(crate) slave_only: bool,
     pub(crate) sdo_id: SdoId,
+    // Only used by the alternate BMCA of ITU-T G.8275.1
+    pub(crate) local_priority: u8,
 }
 
 impl DefaultDS {
@@ -36,6 +38,7 @@ impl DefaultDS {
             domain_number: config.domain_number,
             slave_only: config.slave_only,
             sdo_id: config.sdo_id,
+            local_priority: 128,
         }
     }
 }
@@ -1,7 +1,7 @@
 pub(crate) use current::CurrentDS;
 pub(crate) use default::DefaultDS;
 pub(crate) use parent::ParentDS;
-pub use time_properties::TimePropertiesDS;
+pub use time_properties::{LeapSecond, TimePropertiesDS, TimePropertiesError};
 
 mod current;
 mod default;
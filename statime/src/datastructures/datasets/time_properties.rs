@@ -1,4 +1,10 @@
-use crate::datastructures::common::{LeapIndicator, TimeSource};
+use crate::{
+    datastructures::common::{LeapIndicator, TimeSource},
+    time::{Duration, Time},
+};
+
+/// How long before a leap second it is announced, in seconds
+const LEAP_SECOND_WARNING_SECS: i64 = 12 * 60 * 60;
 
 /// A concrete implementation of the PTP Time Properties dataset (IEEE1588-2019
 /// section 8.2.4
@@ -71,4 +77,261 @@ impl TimePropertiesDS {
     pub fn leap_indicator(&self) -> LeapIndicator {
         self.leap_indicator
     }
+
+    /// The offset between TAI and UTC in seconds, if it is known
+    pub fn current_utc_offset(&self) -> Option<i16> {
+        self.current_utc_offset
+    }
+
+    pub fn time_traceable(&self) -> bool {
+        self.time_traceable
+    }
+
+    pub fn frequency_traceable(&self) -> bool {
+        self.frequency_traceable
+    }
+
+    pub fn time_source(&self) -> TimeSource {
+        self.time_source
+    }
+
+    /// Change the offset between TAI and UTC, or mark it as unknown by
+    /// passing `None`. Only the PTP timescale has a UTC offset, and it must
+    /// stay known while a leap second is indicated.
+    pub fn set_current_utc_offset(
+        &mut self,
+        current_utc_offset: Option<i16>,
+    ) -> Result<(), TimePropertiesError> {
+        TimePropertiesDS {
+            current_utc_offset,
+            ..*self
+        }
+        .validate()?;
+        self.current_utc_offset = current_utc_offset;
+        Ok(())
+    }
+
+    /// Indicate a leap second at the end of the current UTC day, or clear the
+    /// indication. This needs the PTP timescale with a known UTC offset.
+    ///
+    /// To let statime take care of the leap indicator and of the UTC offset
+    /// once the leap second has passed, schedule it with
+    /// [`PtpInstance::schedule_leap_second`](crate::PtpInstance::schedule_leap_second)
+    /// instead.
+    pub fn set_leap_indicator(
+        &mut self,
+        leap_indicator: LeapIndicator,
+    ) -> Result<(), TimePropertiesError> {
+        TimePropertiesDS {
+            leap_indicator,
+            ..*self
+        }
+        .validate()?;
+        self.leap_indicator = leap_indicator;
+        Ok(())
+    }
+
+    /// Change whether the time and frequency are traceable to a primary
+    /// reference
+    pub fn set_traceability(&mut self, time_traceable: bool, frequency_traceable: bool) {
+        self.time_traceable = time_traceable;
+        self.frequency_traceable = frequency_traceable;
+    }
+
+    pub fn set_time_source(&mut self, time_source: TimeSource) {
+        self.time_source = time_source;
+    }
+
+    /// Check that the properties are consistent with each other (8.2.4)
+    pub(crate) fn validate(&self) -> Result<(), TimePropertiesError> {
+        if !self.ptp_timescale && self.current_utc_offset.is_some() {
+            return Err(TimePropertiesError::UtcOffsetOnArbitraryTimescale);
+        }
+
+        if self.leap_indicator != LeapIndicator::NoLeap && self.current_utc_offset.is_none() {
+            return Err(TimePropertiesError::LeapWithoutUtcOffset);
+        }
+
+        Ok(())
+    }
+
+    /// The time properties to announce at `now`, given an upcoming leap second
+    pub(crate) fn with_leap_second(&self, leap_second: &LeapSecond, now: Time) -> Self {
+        if now >= leap_second.at {
+            let mut passed = *self;
+            passed.pass_leap_second(leap_second);
+            passed
+        } else if leap_second.at - now <= Duration::from_secs(LEAP_SECOND_WARNING_SECS) {
+            TimePropertiesDS {
+                leap_indicator: leap_second.leap_indicator,
+                ..*self
+            }
+        } else {
+            *self
+        }
+    }
+
+    /// Take over a leap second that has happened, adjusting the UTC offset
+    /// and clearing the leap indicator
+    pub(crate) fn pass_leap_second(&mut self, leap_second: &LeapSecond) {
+        let change = match leap_second.leap_indicator {
+            LeapIndicator::NoLeap => 0,
+            LeapIndicator::Leap61 => 1,
+            LeapIndicator::Leap59 => -1,
+        };
+
+        if let Some(offset) = &mut self.current_utc_offset {
+            *offset += change;
+        }
+        self.leap_indicator = LeapIndicator::NoLeap;
+    }
+}
+
+/// Reasons a [`TimePropertiesDS`] can be rejected
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TimePropertiesError {
+    /// A UTC offset was given for an arbitrary timescale, which isn't related
+    /// to UTC
+    UtcOffsetOnArbitraryTimescale,
+    /// A leap second was indicated without a known UTC offset
+    LeapWithoutUtcOffset,
+}
+
+impl core::fmt::Display for TimePropertiesError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TimePropertiesError::UtcOffsetOnArbitraryTimescale => {
+                write!(f, "an arbitrary timescale has no UTC offset")
+            }
+            TimePropertiesError::LeapWithoutUtcOffset => {
+                write!(
+                    f,
+                    "a leap second can only be indicated with a known UTC offset"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TimePropertiesError {}
+
+/// An upcoming leap second, as reported by the time source of a grandmaster
+///
+/// The leap second is announced through the leap indicator during the 12
+/// hours before it happens. Once it has happened, the current UTC offset is
+/// adjusted by one second in the appropriate direction.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LeapSecond {
+    /// The PTP time at which the leap second ends, which is midnight UTC at
+    /// the end of the day containing the leap second.
+    pub at: Time,
+    /// Whether a second is inserted ([`LeapIndicator::Leap61`]) or removed
+    /// ([`LeapIndicator::Leap59`]).
+    pub leap_indicator: LeapIndicator,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leap_second_transition() {
+        let time_properties = TimePropertiesDS::new_ptp_time(
+            Some(37),
+            LeapIndicator::NoLeap,
+            false,
+            false,
+            TimeSource::Gnss,
+        );
+        let leap_second = LeapSecond {
+            at: Time::from_secs(100_000),
+            leap_indicator: LeapIndicator::Leap61,
+        };
+
+        let before_window = time_properties.with_leap_second(&leap_second, Time::from_secs(56_799));
+        assert_eq!(before_window, time_properties);
+
+        let window_start = time_properties.with_leap_second(&leap_second, Time::from_secs(56_800));
+        assert_eq!(window_start.leap_indicator, LeapIndicator::Leap61);
+        assert_eq!(window_start.current_utc_offset, Some(37));
+
+        let window_end =
+            time_properties.with_leap_second(&leap_second, Time::from_millis(99_999_999));
+        assert_eq!(window_end.leap_indicator, LeapIndicator::Leap61);
+        assert_eq!(window_end.current_utc_offset, Some(37));
+
+        let after = time_properties.with_leap_second(&leap_second, Time::from_secs(100_000));
+        assert_eq!(after.leap_indicator, LeapIndicator::NoLeap);
+        assert_eq!(after.current_utc_offset, Some(38));
+
+        let removed = LeapSecond {
+            leap_indicator: LeapIndicator::Leap59,
+            ..leap_second
+        };
+        let after = time_properties.with_leap_second(&removed, Time::from_secs(100_000));
+        assert_eq!(after.current_utc_offset, Some(36));
+    }
+
+    #[test]
+    fn test_time_properties_setters() {
+        let mut time_properties = TimePropertiesDS::new_ptp_time(
+            None,
+            LeapIndicator::NoLeap,
+            false,
+            false,
+            TimeSource::InternalOscillator,
+        );
+        assert_eq!(
+            time_properties.set_leap_indicator(LeapIndicator::Leap61),
+            Err(TimePropertiesError::LeapWithoutUtcOffset)
+        );
+        assert_eq!(time_properties.set_current_utc_offset(Some(37)), Ok(()));
+        assert_eq!(
+            time_properties.set_leap_indicator(LeapIndicator::Leap61),
+            Ok(())
+        );
+        assert_eq!(
+            time_properties.set_current_utc_offset(None),
+            Err(TimePropertiesError::LeapWithoutUtcOffset)
+        );
+        time_properties.set_traceability(true, true);
+        time_properties.set_time_source(TimeSource::Gnss);
+        assert_eq!(
+            time_properties,
+            TimePropertiesDS::new_ptp_time(
+                Some(37),
+                LeapIndicator::Leap61,
+                true,
+                true,
+                TimeSource::Gnss
+            )
+        );
+
+        let mut arbitrary = TimePropertiesDS::new_arbitrary_time(false, false, TimeSource::Other);
+        assert_eq!(
+            arbitrary.set_current_utc_offset(Some(37)),
+            Err(TimePropertiesError::UtcOffsetOnArbitraryTimescale)
+        );
+        assert_eq!(
+            arbitrary.set_leap_indicator(LeapIndicator::Leap59),
+            Err(TimePropertiesError::LeapWithoutUtcOffset)
+        );
+        assert_eq!(arbitrary.current_utc_offset(), None);
+        assert_eq!(arbitrary.leap_indicator(), LeapIndicator::NoLeap);
+
+        // A dataset constructed with an inconsistent leap indicator is caught
+        // when it is used
+        let unknown_offset = TimePropertiesDS::new_ptp_time(
+            None,
+            LeapIndicator::Leap61,
+            false,
+            false,
+            TimeSource::Gnss,
+        );
+        assert_eq!(
+            unknown_offset.validate(),
+            Err(TimePropertiesError::LeapWithoutUtcOffset)
+        );
+    }
 }
@@ -1,6 +1,9 @@
-use super::Header;
+use super::{Header, MAX_MESSAGE_TLVS};
 use crate::datastructures::{
-    common::{ClockIdentity, ClockQuality, LeapIndicator, TimeSource, WireTimestamp},
+    common::{
+        ClockIdentity, ClockQuality, LeapIndicator, PathTrace, TimeSource, Tlv, TlvType,
+        WireTimestamp,
+    },
     datasets::TimePropertiesDS,
     WireFormat, WireFormatError,
 };
@@ -16,11 +19,13 @@ pub(crate) struct AnnounceMessage {
     pub(crate) grandmaster_identity: ClockIdentity,
     pub(crate) steps_removed: u16,
     pub(crate) time_source: TimeSource,
+    /// The PATH_TRACE TLV, empty when the message doesn't carry one
+    pub(crate) path_trace: PathTrace,
 }
 
 impl AnnounceMessage {
     pub(crate) fn content_size(&self) -> usize {
-        30
+        30 + self.path_trace.tlv_size()
     }
 
     pub(crate) fn serialize_content(&self, buffer: &mut [u8]) -> Result<(), WireFormatError> {
@@ -37,6 +42,7 @@ impl AnnounceMessage {
         self.grandmaster_identity.serialize(&mut buffer[19..27])?;
         buffer[27..29].copy_from_slice(&self.steps_removed.to_be_bytes());
         buffer[29] = self.time_source.to_primitive();
+        self.path_trace.serialize_tlv(&mut buffer[30..])?;
 
         Ok(())
     }
@@ -49,6 +55,22 @@ impl AnnounceMessage {
             return Err(WireFormatError::BufferTooShort);
         }
 
+        // Of the TLVs, only the path trace is of interest. The TLVs are
+        // optional, so a malformed TLV ends the search instead of rejecting
+        // the message.
+        let mut path_trace = PathTrace::default();
+        let mut tlvs = &buffer[30..];
+        for _ in 0..MAX_MESSAGE_TLVS {
+            let Ok((tlv_type, size)) = Tlv::peek(tlvs) else {
+                break;
+            };
+            if tlv_type == TlvType::PathTrace {
+                path_trace = PathTrace::deserialize_value(&tlvs[4..size])?;
+                break;
+            }
+            tlvs = &tlvs[size..];
+        }
+
         Ok(Self {
             header,
             origin_timestamp: WireTimestamp::deserialize(&buffer[0..10])?,
@@ -59,6 +81,7 @@ impl AnnounceMessage {
             grandmaster_identity: ClockIdentity::deserialize(&buffer[19..27])?,
             steps_removed: u16::from_be_bytes(buffer[27..29].try_into().unwrap()),
             time_source: TimeSource::from_primitive(buffer[29]),
+            path_trace,
         })
     }
 
@@ -90,7 +113,7 @@ impl AnnounceMessage {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::datastructures::common::ClockAccuracy;
+    use crate::datastructures::common::{ClockAccuracy, ClockClass};
 
     #[test]
     fn announce_wireformat() {
@@ -109,8 +132,8 @@ mod tests {
                 current_utc_offset: 0,
                 grandmaster_priority_1: 96,
                 grandmaster_clock_quality: ClockQuality {
-                    clock_class: 0,
-                    clock_accuracy: ClockAccuracy::Reserved,
+                    clock_class: ClockClass::Reserved(0),
+                    clock_accuracy: ClockAccuracy::Reserved(0),
                     offset_scaled_log_variance: 128,
                 },
                 grandmaster_priority_2: 99,
@@ -118,7 +141,8 @@ mod tests {
                     0xff, 0xff, 0x00, 0x09, 0xba, 0xf8, 0x21, 0x00,
                 ]),
                 steps_removed: 128,
-                time_source: TimeSource::Unknown(0x80),
+                time_source: TimeSource::Reserved(0x80),
+                path_trace: PathTrace::default(),
             },
         )];
 
@@ -137,4 +161,46 @@ mod tests {
             assert_eq!(deserialized_data, object_representation);
         }
     }
+
+    #[test]
+    fn announce_path_trace() {
+        let mut announce = AnnounceMessage {
+            header: Header::default(),
+            origin_timestamp: WireTimestamp::default(),
+            current_utc_offset: 37,
+            grandmaster_priority_1: 128,
+            grandmaster_clock_quality: ClockQuality::default(),
+            grandmaster_priority_2: 128,
+            grandmaster_identity: ClockIdentity([1; 8]),
+            steps_removed: 1,
+            time_source: TimeSource::InternalOscillator,
+            path_trace: PathTrace::new(ClockIdentity([1; 8])),
+        };
+        announce.path_trace.push(ClockIdentity([2; 8]));
+
+        let mut buffer = [0; 64];
+        assert_eq!(announce.content_size(), 50);
+        announce.serialize_content(&mut buffer).unwrap();
+        assert_eq!(buffer[30..34], [0x00, 0x08, 0x00, 0x10]);
+        assert_eq!(
+            AnnounceMessage::deserialize_content(Header::default(), &buffer[..50]).unwrap(),
+            announce
+        );
+
+        // The path trace is found after other TLVs
+        let mut padded = [0; 70];
+        padded[..30].copy_from_slice(&buffer[..30]);
+        padded[30..36].copy_from_slice(&[0x80, 0x08, 0x00, 0x02, 0x00, 0x00]);
+        padded[36..56].copy_from_slice(&buffer[30..50]);
+        assert_eq!(
+            AnnounceMessage::deserialize_content(Header::default(), &padded[..56]).unwrap(),
+            announce
+        );
+
+        // A TLV that doesn't fit the message is ignored, with the ones after it
+        padded[30..34].copy_from_slice(&[0x80, 0x08, 0x00, 0x40]);
+        let truncated =
+            AnnounceMessage::deserialize_content(Header::default(), &padded[..56]).unwrap();
+        assert_eq!(truncated.path_trace, PathTrace::default());
+    }
 }
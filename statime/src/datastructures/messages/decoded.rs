@@ -0,0 +1,297 @@
+//! A read-only view of PTP messages, for tools that inspect traffic with the
+//! same parser the protocol implementation uses.
+
+use arrayvec::ArrayVec;
+
+use super::{Header, Message, MessageType, SignalingMessage, UnicastNegotiation, MAX_MESSAGE_TLVS};
+use crate::{
+    datastructures::{
+        common::{
+            ClockIdentity, ClockQuality, FollowUpInformation, ProfileIdentifier, TimeSource, Tlv,
+            TlvType,
+        },
+        WireFormatError,
+    },
+    time::{Duration, Time},
+};
+
+/// The contents of a PTP message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedMessage {
+    pub message_type: MessageType,
+    pub domain_number: u8,
+    pub sdo_id: u16,
+    pub sequence_id: u16,
+    pub source_clock_identity: ClockIdentity,
+    pub source_port_number: u16,
+    pub correction: Duration,
+    pub log_message_interval: i8,
+    pub two_step: bool,
+    pub unicast: bool,
+    /// The timestamp carried in the body of the message, if it has one: the
+    /// origin timestamp of sync, delay request, peer delay request and
+    /// announce messages, the precise origin timestamp of follow up messages,
+    /// the receive timestamp of delay and peer delay responses, and the
+    /// response origin timestamp of peer delay response follow up messages.
+    pub timestamp: Option<Time>,
+    /// The port whose request is answered by a (peer) delay response or peer
+    /// delay response follow up message, as clock identity and port number
+    pub requesting_port: Option<(ClockIdentity, u16)>,
+    /// The body of an announce message
+    pub announce: Option<DecodedAnnounce>,
+    /// The body of a signaling message
+    pub signaling: Option<DecodedSignaling>,
+    /// The FOLLOW_UP information TLV of IEEE 802.1AS, if a follow up message
+    /// carries one
+    pub follow_up_information: Option<FollowUpInformation>,
+}
+
+/// The body of an announce message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedAnnounce {
+    pub current_utc_offset: i16,
+    pub grandmaster_priority_1: u8,
+    pub grandmaster_clock_quality: ClockQuality,
+    pub grandmaster_priority_2: u8,
+    pub grandmaster_identity: ClockIdentity,
+    pub steps_removed: u16,
+    pub time_source: TimeSource,
+    /// The profile the sender advertises it runs, see [`ProfileIdentifier`]
+    pub profile_identifier: Option<ProfileIdentifier>,
+}
+
+/// The body of a signaling message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedSignaling {
+    /// The port the message is meant for, as clock identity and port number.
+    /// All ones addresses every port.
+    pub target_port: (ClockIdentity, u16),
+    unicast_negotiations: ArrayVec<UnicastNegotiation, { SignalingMessage::CAPACITY }>,
+}
+
+impl DecodedSignaling {
+    /// The unicast negotiation TLVs of the message, in the order they were
+    /// sent
+    pub fn unicast_negotiations(&self) -> &[UnicastNegotiation] {
+        &self.unicast_negotiations
+    }
+}
+
+/// A message that couldn't be decoded
+#[derive(Debug, Clone)]
+pub struct DecodeError(pub(super) WireFormatError);
+
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "invalid PTP message: {}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// Decode a PTP message as received from the network.
+pub fn decode_message(data: &[u8]) -> Result<DecodedMessage, DecodeError> {
+    let message = Message::deserialize(data).map_err(DecodeError)?;
+    let header = message.header();
+
+    let (timestamp, requesting_port) = match &message {
+        Message::Sync(m) => (Some(m.origin_timestamp), None),
+        Message::DelayReq(m) => (Some(m.origin_timestamp), None),
+        Message::PDelayReq(m) => (Some(m.origin_timestamp), None),
+        Message::PDelayResp(m) => (
+            Some(m.request_receive_timestamp),
+            Some(m.requesting_port_identity),
+        ),
+        Message::FollowUp(m) => (Some(m.precise_origin_timestamp), None),
+        Message::DelayResp(m) => (Some(m.receive_timestamp), Some(m.requesting_port_identity)),
+        Message::PDelayRespFollowUp(m) => (
+            Some(m.response_origin_timestamp),
+            Some(m.requesting_port_identity),
+        ),
+        Message::Announce(m) => (Some(m.origin_timestamp), None),
+        Message::Signaling(_) | Message::Management(_) => (None, None),
+    };
+
+    let announce = match &message {
+        Message::Announce(m) => Some(DecodedAnnounce {
+            current_utc_offset: m.current_utc_offset,
+            grandmaster_priority_1: m.grandmaster_priority_1,
+            grandmaster_clock_quality: m.grandmaster_clock_quality,
+            grandmaster_priority_2: m.grandmaster_priority_2,
+            grandmaster_identity: m.grandmaster_identity,
+            steps_removed: m.steps_removed,
+            time_source: m.time_source,
+            profile_identifier: ProfileIdentifier::find_in(data),
+        }),
+        _ => None,
+    };
+
+    let signaling = match &message {
+        Message::Signaling(m) => {
+            let target = m.target_port_identity();
+            Some(DecodedSignaling {
+                target_port: (target.clock_identity, target.port_number),
+                unicast_negotiations: m.unicast_negotiations().collect(),
+            })
+        }
+        _ => None,
+    };
+
+    let follow_up_information = match &message {
+        Message::FollowUp(m) => m.information,
+        _ => None,
+    };
+
+    Ok(DecodedMessage {
+        message_type: message.content_type(),
+        domain_number: header.domain_number,
+        sdo_id: header.sdo_id.into(),
+        sequence_id: header.sequence_id,
+        source_clock_identity: header.source_port_identity.clock_identity,
+        source_port_number: header.source_port_identity.port_number,
+        correction: header.correction_field.into(),
+        log_message_interval: header.log_message_interval,
+        two_step: header.two_step_flag,
+        unicast: header.unicast_flag,
+        timestamp: timestamp.map(Time::from),
+        requesting_port: requesting_port
+            .map(|identity| (identity.clock_identity, identity.port_number)),
+        announce,
+        signaling,
+        follow_up_information,
+    })
+}
+
+/// The TLVs after the body of a message, as their type and value. Stops at the
+/// first malformed TLV and after [`MAX_MESSAGE_TLVS`].
+pub(crate) fn message_tlvs(data: &[u8]) -> impl Iterator<Item = (TlvType, &[u8])> {
+    let mut tlvs = Header::deserialize_header(data)
+        .ok()
+        .and_then(|header_data| {
+            // The header and the fixed part of the body
+            let body_end = 34
+                + match header_data.message_type {
+                    MessageType::Sync | MessageType::DelayReq | MessageType::FollowUp => 10,
+                    MessageType::Signaling => 10,
+                    MessageType::Management => 14,
+                    MessageType::PDelayReq
+                    | MessageType::PDelayResp
+                    | MessageType::DelayResp
+                    | MessageType::PDelayRespFollowUp => 20,
+                    MessageType::Announce => 30,
+                };
+            data.get(..header_data.message_length as usize)?
+                .get(body_end..)
+        })
+        .unwrap_or_default();
+
+    core::iter::from_fn(move || {
+        let (tlv_type, size) = Tlv::peek(tlvs).ok()?;
+        let value = &tlvs[4..size];
+        tlvs = &tlvs[size..];
+        Some((tlv_type, value))
+    })
+    .take(MAX_MESSAGE_TLVS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{InstanceConfig, LogMessageIntervals},
+        datastructures::{common::PortIdentity, datasets::DefaultDS, messages::SdoId},
+        Interval,
+    };
+
+    #[test]
+    fn decode_follow_up() {
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity([1, 2, 3, 4, 5, 6, 7, 8]),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 4,
+            slave_only: false,
+            sdo_id: SdoId::new(0x100).unwrap(),
+        });
+        let port_identity = PortIdentity {
+            clock_identity: default_ds.clock_identity,
+            port_number: 2,
+        };
+        let message = Message::follow_up(
+            &default_ds,
+            port_identity,
+            17,
+            Time::from_fixed_nanos(1_500_000_000.5f64),
+            Interval::ONE_SECOND,
+            false,
+            &LogMessageIntervals::STANDARD,
+        );
+
+        let mut buffer = [0; 128];
+        let len = message.serialize(&mut buffer).unwrap();
+        let decoded = decode_message(&buffer[..len]).unwrap();
+
+        assert_eq!(decoded.message_type, MessageType::FollowUp);
+        assert_eq!(decoded.domain_number, 4);
+        assert_eq!(decoded.sdo_id, 0x100);
+        assert_eq!(decoded.sequence_id, 17);
+        assert_eq!(decoded.source_clock_identity, default_ds.clock_identity);
+        assert_eq!(decoded.source_port_number, 2);
+        // The sub-nanosecond part travels in the correction field
+        assert_eq!(decoded.timestamp, Some(Time::from_nanos(1_500_000_000)));
+        assert_eq!(decoded.correction, Duration::from_fixed_nanos(0.5f64));
+        assert_eq!(decoded.announce, None);
+        assert_eq!(decoded.signaling, None);
+
+        assert!(decode_message(&buffer[..20]).is_err());
+    }
+
+    #[test]
+    fn decode_signaling() {
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity([1, 2, 3, 4, 5, 6, 7, 8]),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let negotiations = [
+            UnicastNegotiation::Request {
+                message_type: MessageType::Sync,
+                log_interval: -4,
+                duration: 300,
+            },
+            UnicastNegotiation::Grant {
+                message_type: MessageType::Announce,
+                log_interval: 1,
+                duration: 0,
+                renewal_invited: false,
+            },
+        ];
+        let target = PortIdentity {
+            clock_identity: ClockIdentity([9; 8]),
+            port_number: 3,
+        };
+        let message = Message::signaling(
+            &default_ds,
+            PortIdentity::default(),
+            5,
+            target,
+            &negotiations,
+            &LogMessageIntervals::STANDARD,
+        );
+
+        let mut buffer = [0; 128];
+        let len = message.serialize(&mut buffer).unwrap();
+        let decoded = decode_message(&buffer[..len]).unwrap();
+
+        assert_eq!(decoded.message_type, MessageType::Signaling);
+        assert!(decoded.unicast);
+        assert_eq!(decoded.timestamp, None);
+        let signaling = decoded.signaling.unwrap();
+        assert_eq!(signaling.target_port, (ClockIdentity([9; 8]), 3));
+        assert_eq!(signaling.unicast_negotiations(), negotiations);
+    }
+}
@@ -1,20 +1,35 @@
-use super::Header;
-use crate::datastructures::{common::WireTimestamp, WireFormat, WireFormatError};
+use super::{Header, MAX_MESSAGE_TLVS};
+use crate::datastructures::{
+    common::{FollowUpInformation, Tlv, WireTimestamp},
+    WireFormat, WireFormatError,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct FollowUpMessage {
     pub(crate) header: Header,
     pub(crate) precise_origin_timestamp: WireTimestamp,
+    // The FOLLOW_UP information TLV of IEEE 802.1AS
+    pub(crate) information: Option<FollowUpInformation>,
 }
 
 impl FollowUpMessage {
     pub(crate) fn content_size(&self) -> usize {
-        10
+        match self.information {
+            Some(_) => 10 + FollowUpInformation::TLV_SIZE,
+            None => 10,
+        }
     }
 
     pub(crate) fn serialize_content(&self, buffer: &mut [u8]) -> Result<(), WireFormatError> {
+        if buffer.len() < self.content_size() {
+            return Err(WireFormatError::BufferTooShort);
+        }
+
         self.precise_origin_timestamp
             .serialize(&mut buffer[0..10])?;
+        if let Some(information) = &self.information {
+            information.serialize_tlv(&mut buffer[10..])?;
+        }
 
         Ok(())
     }
@@ -26,9 +41,23 @@ impl FollowUpMessage {
         let slice = buffer.get(0..10).ok_or(WireFormatError::BufferTooShort)?;
         let precise_origin_timestamp = WireTimestamp::deserialize(slice)?;
 
+        let mut information = None;
+        let mut tlvs = &buffer[10..];
+        for _ in 0..MAX_MESSAGE_TLVS {
+            let Ok((tlv_type, size)) = Tlv::peek(tlvs) else {
+                break;
+            };
+            information = FollowUpInformation::deserialize_value(tlv_type, &tlvs[4..size]);
+            if information.is_some() {
+                break;
+            }
+            tlvs = &tlvs[size..];
+        }
+
         Ok(Self {
             header,
             precise_origin_timestamp,
+            information,
         })
     }
 }
@@ -48,6 +77,7 @@ mod tests {
                         seconds: 1169232218,
                         nanos: 174389936,
                     },
+                    information: None,
                 },
             ),
             (
@@ -58,6 +88,7 @@ mod tests {
                         seconds: 0x0000_0000_0002,
                         nanos: 0x0000_0001,
                     },
+                    information: None,
                 },
             ),
         ];
@@ -77,4 +108,36 @@ mod tests {
             assert_eq!(deserialized_data, object_representation);
         }
     }
+
+    #[test]
+    fn follow_up_information_tlv() {
+        let follow_up = FollowUpMessage {
+            header: Header::default(),
+            precise_origin_timestamp: WireTimestamp::default(),
+            information: Some(FollowUpInformation {
+                cumulative_scaled_rate_offset: 12,
+                gm_time_base_indicator: 3,
+                ..Default::default()
+            }),
+        };
+
+        // The TLV is found after other TLVs, like a PAD TLV
+        let mut buffer = [0; 48];
+        assert_eq!(follow_up.content_size(), 42);
+        follow_up.serialize_content(&mut buffer[6..]).unwrap();
+        buffer.copy_within(6..16, 0);
+        buffer[10..16].copy_from_slice(&[0x80, 0x08, 0x00, 0x02, 0x00, 0x00]);
+        assert_eq!(
+            FollowUpMessage::deserialize_content(Header::default(), &buffer).unwrap(),
+            follow_up
+        );
+
+        // Without it, there is no information
+        assert_eq!(
+            FollowUpMessage::deserialize_content(Header::default(), &buffer[..16])
+                .unwrap()
+                .information,
+            None
+        );
+    }
 }
@@ -153,6 +153,10 @@ impl core::fmt::Display for SdoId {
 }
 
 impl SdoId {
+    /// The sdo id of the messages of the Common Mean Link Delay Service
+    /// (IEEE1588-2019 section 16.6.4): majorSdoId 0x2, minorSdoId 0x00
+    pub const CMLDS: SdoId = SdoId(0x200);
+
     /// Create a new sdo id
     ///
     /// This function only returns an `SdoId` instance if the given identifier
@@ -170,6 +174,12 @@ impl SdoId {
     }
 }
 
+impl From<SdoId> for u16 {
+    fn from(sdo_id: SdoId) -> Self {
+        sdo_id.0
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PtpVersion {
     major: u8,
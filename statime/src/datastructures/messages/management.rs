@@ -1,9 +1,18 @@
+use arrayvec::ArrayVec;
+
 use super::Header;
-use crate::datastructures::{
-    common::{PortIdentity, Tlv},
-    WireFormat, WireFormatError,
+use crate::{
+    config::{DelayMechanism, PortConfig},
+    datastructures::{
+        common::{LeapIndicator, PortIdentity, TimeInterval, Tlv, TlvType},
+        datasets::{CurrentDS, DefaultDS, ParentDS, TimePropertiesDS},
+        WireFormat, WireFormatError,
+    },
+    time::{Duration, Interval},
+    PortStateKind,
 };
 
+/// See: 15.4.1
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct ManagementMessage {
     pub(super) header: Header,
@@ -11,23 +20,42 @@ pub(crate) struct ManagementMessage {
     pub(super) starting_boundary_hops: u8,
     pub(super) boundary_hops: u8,
     pub(super) action: ManagementAction,
-    pub(super) management_tlv: Tlv,
+    pub(super) tlv: ManagementTlv,
 }
 
 impl ManagementMessage {
+    pub(crate) fn target_port_identity(&self) -> PortIdentity {
+        self.target_port_identity
+    }
+
+    pub(crate) fn action(&self) -> ManagementAction {
+        self.action
+    }
+
+    pub(crate) fn tlv(&self) -> &ManagementTlv {
+        &self.tlv
+    }
+
+    /// Whether a boundary clock forwarded the message on its way (15.3.3)
+    pub(crate) fn was_forwarded(&self) -> bool {
+        self.boundary_hops != self.starting_boundary_hops
+    }
+
     pub(crate) fn content_size(&self) -> usize {
-        10
+        14 + self.tlv.wire_size()
     }
 
-    pub(crate) fn serialize_content(
-        &self,
-        buffer: &mut [u8],
-    ) -> Result<(), crate::datastructures::WireFormatError> {
+    pub(crate) fn serialize_content(&self, buffer: &mut [u8]) -> Result<(), WireFormatError> {
+        if buffer.len() < self.content_size() {
+            return Err(WireFormatError::BufferTooShort);
+        }
+
         self.target_port_identity.serialize(&mut buffer[0..10])?;
-        buffer[11] = self.starting_boundary_hops;
-        buffer[12] = self.boundary_hops;
-        buffer[13] = self.action.to_primitive();
-        Tlv::serialize(&self.management_tlv, &mut buffer[14..])?;
+        buffer[10] = self.starting_boundary_hops;
+        buffer[11] = self.boundary_hops;
+        buffer[12] = self.action.to_primitive();
+        buffer[13] = 0;
+        self.tlv.serialize(&mut buffer[14..])?;
 
         Ok(())
     }
@@ -35,17 +63,21 @@ impl ManagementMessage {
     pub(crate) fn deserialize_content(
         header: Header,
         buffer: &[u8],
-    ) -> Result<Self, crate::datastructures::WireFormatError> {
+    ) -> Result<Self, WireFormatError> {
         if buffer.len() < 14 {
             return Err(WireFormatError::BufferTooShort);
         }
+
+        // Only the first TLV is the management TLV, anything after it is
+        // ignored like the PAD TLVs after other messages
         Ok(Self {
             header,
             target_port_identity: PortIdentity::deserialize(&buffer[0..10])?,
-            starting_boundary_hops: buffer[11],
-            boundary_hops: buffer[12],
-            action: ManagementAction::from_primitive(buffer[13]),
-            management_tlv: Tlv::deserialize(&buffer[13..])?,
+            starting_boundary_hops: buffer[10],
+            boundary_hops: buffer[11],
+            // The upper nibble is reserved
+            action: ManagementAction::from_primitive(buffer[12] & 0x0f),
+            tlv: ManagementTlv::deserialize(&buffer[14..])?,
         })
     }
 }
@@ -84,4 +116,539 @@ impl ManagementAction {
             0x5..=u8::MAX => Self::Reserved,
         }
     }
+
+    /// The action of the reply to a message with this action, if it needs one
+    pub fn reply(self) -> Option<Self> {
+        match self {
+            Self::GET | Self::SET => Some(Self::RESPONSE),
+            Self::COMMAND => Some(Self::ACKNOWLEDGE),
+            Self::RESPONSE | Self::ACKNOWLEDGE | Self::Reserved => None,
+        }
+    }
+}
+
+/// The TLV that a management message carries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ManagementTlv {
+    /// A MANAGEMENT TLV, see 15.5.2
+    Management {
+        id: ManagementId,
+        data: ArrayVec<u8, { ManagementTlv::DATA_CAPACITY }>,
+    },
+    /// A MANAGEMENT_ERROR_STATUS TLV, see 15.5.4. The display data is left
+    /// empty when sending, and ignored when receiving.
+    ErrorStatus {
+        error: ManagementErrorId,
+        id: ManagementId,
+    },
+}
+
+impl ManagementTlv {
+    /// Large enough for the data of any dataset a port reports, and of any
+    /// management id statime handles. Messages with more data are rejected.
+    pub(crate) const DATA_CAPACITY: usize = 64;
+
+    // The management error id, management id, four reserved bytes and an
+    // empty display text, padded to an even length
+    const ERROR_STATUS_SIZE: usize = 10;
+
+    pub(crate) fn id(&self) -> ManagementId {
+        match self {
+            ManagementTlv::Management { id, .. } | ManagementTlv::ErrorStatus { id, .. } => *id,
+        }
+    }
+
+    fn wire_size(&self) -> usize {
+        match self {
+            // The data field is padded to an even length (15.5.2.2)
+            ManagementTlv::Management { data, .. } => 6 + data.len() + data.len() % 2,
+            ManagementTlv::ErrorStatus { .. } => 4 + Self::ERROR_STATUS_SIZE,
+        }
+    }
+
+    fn serialize(&self, buffer: &mut [u8]) -> Result<(), WireFormatError> {
+        let size = self.wire_size();
+        let buffer = buffer
+            .get_mut(..size)
+            .ok_or(WireFormatError::BufferTooShort)?;
+        buffer.fill(0);
+
+        let tlv_type = match self {
+            ManagementTlv::Management { .. } => TlvType::Management,
+            ManagementTlv::ErrorStatus { .. } => TlvType::ManagementErrorStatus,
+        };
+        buffer[0..2].copy_from_slice(&tlv_type.to_primitive().to_be_bytes());
+        buffer[2..4].copy_from_slice(&((size - 4) as u16).to_be_bytes());
+
+        match self {
+            ManagementTlv::Management { id, data } => {
+                buffer[4..6].copy_from_slice(&id.to_primitive().to_be_bytes());
+                buffer[6..][..data.len()].copy_from_slice(data);
+            }
+            ManagementTlv::ErrorStatus { error, id } => {
+                buffer[4..6].copy_from_slice(&error.to_primitive().to_be_bytes());
+                buffer[6..8].copy_from_slice(&id.to_primitive().to_be_bytes());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn deserialize(buffer: &[u8]) -> Result<Self, WireFormatError> {
+        let (tlv_type, size) = Tlv::peek(buffer)?;
+        let value = &buffer[4..size];
+
+        match tlv_type {
+            TlvType::Management => {
+                let id = value.get(0..2).ok_or(WireFormatError::BufferTooShort)?;
+                let id = ManagementId::from_primitive(u16::from_be_bytes([id[0], id[1]]));
+                // Keeping only part of the data could not be sent on unchanged
+                let mut data = ArrayVec::new();
+                data.try_extend_from_slice(&value[2..])?;
+
+                Ok(ManagementTlv::Management { id, data })
+            }
+            TlvType::ManagementErrorStatus => {
+                let ids = value.get(0..4).ok_or(WireFormatError::BufferTooShort)?;
+
+                Ok(ManagementTlv::ErrorStatus {
+                    error: ManagementErrorId::from_primitive(u16::from_be_bytes([ids[0], ids[1]])),
+                    id: ManagementId::from_primitive(u16::from_be_bytes([ids[2], ids[3]])),
+                })
+            }
+            _ => Err(WireFormatError::EnumConversionError),
+        }
+    }
+}
+
+/// The data of management responses, see 15.5.3
+impl ManagementTlv {
+    fn with_data(id: ManagementId, data: &[u8]) -> Result<Self, WireFormatError> {
+        let mut tlv_data = ArrayVec::new();
+        tlv_data.try_extend_from_slice(data)?;
+        Ok(ManagementTlv::Management { id, data: tlv_data })
+    }
+
+    /// The data of a single dataset member like PRIORITY1 or DOMAIN, which is
+    /// the value followed by a reserved byte, see 15.5.3.3.3 and onward
+    pub(crate) fn value(id: ManagementId, value: u8) -> Self {
+        ManagementTlv::Management {
+            id,
+            data: [value, 0].into_iter().collect(),
+        }
+    }
+
+    /// See: 15.5.3.3.1
+    pub(crate) fn default_data_set(default_ds: &DefaultDS) -> Result<Self, WireFormatError> {
+        let mut data = [0; 20];
+        // Statime always sends follow up messages, so it is a two-step clock
+        data[0] = 0b01 | (default_ds.slave_only as u8) << 1;
+        data[2..4].copy_from_slice(&default_ds.number_ports.to_be_bytes());
+        data[4] = default_ds.priority_1;
+        default_ds.clock_quality.serialize(&mut data[5..9])?;
+        data[9] = default_ds.priority_2;
+        data[10..18].copy_from_slice(&default_ds.clock_identity.0);
+        data[18] = default_ds.domain_number;
+
+        Self::with_data(ManagementId::DefaultDataSet, &data)
+    }
+
+    /// See: 15.5.3.4.1
+    pub(crate) fn current_data_set(current_ds: &CurrentDS) -> Result<Self, WireFormatError> {
+        let mut data = [0; 18];
+        data[0..2].copy_from_slice(&current_ds.steps_removed.to_be_bytes());
+        TimeInterval::from(current_ds.offset_from_master).serialize(&mut data[2..10])?;
+        TimeInterval::from(current_ds.mean_delay).serialize(&mut data[10..18])?;
+
+        Self::with_data(ManagementId::CurrentDataSet, &data)
+    }
+
+    /// See: 15.5.3.5.1
+    pub(crate) fn parent_data_set(parent_ds: &ParentDS) -> Result<Self, WireFormatError> {
+        let mut data = [0; 32];
+        parent_ds.parent_port_identity.serialize(&mut data[0..10])?;
+        data[10] = parent_ds.parent_stats as u8;
+        data[12..14].copy_from_slice(
+            &parent_ds
+                .observed_parent_offset_scaled_log_variance
+                .to_be_bytes(),
+        );
+        data[14..18].copy_from_slice(
+            &parent_ds
+                .observed_parent_clock_phase_change_rate
+                .to_be_bytes(),
+        );
+        data[18] = parent_ds.grandmaster_priority_1;
+        parent_ds
+            .grandmaster_clock_quality
+            .serialize(&mut data[19..23])?;
+        data[23] = parent_ds.grandmaster_priority_2;
+        data[24..32].copy_from_slice(&parent_ds.grandmaster_identity.0);
+
+        Self::with_data(ManagementId::ParentDataSet, &data)
+    }
+
+    /// See: 15.5.3.6.1
+    pub(crate) fn time_properties_data_set(
+        time_properties_ds: &TimePropertiesDS,
+    ) -> Result<Self, WireFormatError> {
+        let mut data = [0; 4];
+        data[0..2].copy_from_slice(
+            &time_properties_ds
+                .current_utc_offset
+                .unwrap_or(0)
+                .to_be_bytes(),
+        );
+        data[2] = (time_properties_ds.leap_indicator == LeapIndicator::Leap61) as u8
+            | ((time_properties_ds.leap_indicator == LeapIndicator::Leap59) as u8) << 1
+            | (time_properties_ds.current_utc_offset.is_some() as u8) << 2
+            | (time_properties_ds.ptp_timescale as u8) << 3
+            | (time_properties_ds.time_traceable as u8) << 4
+            | (time_properties_ds.frequency_traceable as u8) << 5;
+        data[3] = time_properties_ds.time_source.to_primitive();
+
+        Self::with_data(ManagementId::TimePropertiesDataSet, &data)
+    }
+
+    /// See: 15.5.3.7.1
+    pub(crate) fn port_data_set(
+        port_identity: PortIdentity,
+        port_state: PortStateKind,
+        config: &PortConfig,
+        peer_mean_path_delay: Duration,
+    ) -> Result<Self, WireFormatError> {
+        let (delay_mechanism, min_pdelay_req_interval) = match config.delay_mechanism {
+            DelayMechanism::E2E { .. } => (0x01, Interval::from_log_2(0x7f)),
+            DelayMechanism::P2P { interval, .. } => (0x02, interval),
+            DelayMechanism::CommonP2P => (0x03, Interval::from_log_2(0x7f)),
+            DelayMechanism::OneWay { .. } => (0xfe, Interval::from_log_2(0x7f)),
+        };
+
+        let mut data = [0; 26];
+        port_identity.serialize(&mut data[0..10])?;
+        // See: 8.2.15.3.1 / Table 20
+        data[10] = match port_state {
+            PortStateKind::Faulty => 2,
+            PortStateKind::Disabled => 3,
+            PortStateKind::Listening => 4,
+            PortStateKind::Master => 6,
+            PortStateKind::Passive => 7,
+            PortStateKind::Slave => 9,
+        };
+        data[11] = config.min_delay_req_interval().as_log_2() as u8;
+        TimeInterval::from(peer_mean_path_delay).serialize(&mut data[12..20])?;
+        data[20] = config.announce_interval.as_log_2() as u8;
+        data[21] = config.announce_receipt_timeout;
+        data[22] = config.sync_interval.as_log_2() as u8;
+        data[23] = delay_mechanism;
+        data[24] = min_pdelay_req_interval.as_log_2() as u8;
+        // The minor version in the upper nibble, as in the header
+        data[25] = 0x12;
+
+        Self::with_data(ManagementId::PortDataSet, &data)
+    }
+}
+
+/// The management ids of ordinary and boundary clocks, see 15.5.2.3 / Table 59
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ManagementId {
+    NullPtpManagement,
+    ClockDescription,
+    UserDescription,
+    SaveInNonVolatileStorage,
+    ResetNonVolatileStorage,
+    Initialize,
+    FaultLog,
+    FaultLogReset,
+    DefaultDataSet,
+    CurrentDataSet,
+    ParentDataSet,
+    TimePropertiesDataSet,
+    PortDataSet,
+    Priority1,
+    Priority2,
+    Domain,
+    SlaveOnly,
+    LogAnnounceInterval,
+    AnnounceReceiptTimeout,
+    LogSyncInterval,
+    VersionNumber,
+    EnablePort,
+    DisablePort,
+    Time,
+    ClockAccuracy,
+    UtcProperties,
+    TraceabilityProperties,
+    TimescaleProperties,
+    MasterOnly,
+    DelayMechanism,
+    LogMinPdelayReqInterval,
+    /// Any other id, which may be reserved or specific to an implementation
+    Other(u16),
+}
+
+impl ManagementId {
+    pub fn to_primitive(self) -> u16 {
+        match self {
+            Self::NullPtpManagement => 0x0000,
+            Self::ClockDescription => 0x0001,
+            Self::UserDescription => 0x0002,
+            Self::SaveInNonVolatileStorage => 0x0003,
+            Self::ResetNonVolatileStorage => 0x0004,
+            Self::Initialize => 0x0005,
+            Self::FaultLog => 0x0006,
+            Self::FaultLogReset => 0x0007,
+            Self::DefaultDataSet => 0x2000,
+            Self::CurrentDataSet => 0x2001,
+            Self::ParentDataSet => 0x2002,
+            Self::TimePropertiesDataSet => 0x2003,
+            Self::PortDataSet => 0x2004,
+            Self::Priority1 => 0x2005,
+            Self::Priority2 => 0x2006,
+            Self::Domain => 0x2007,
+            Self::SlaveOnly => 0x2008,
+            Self::LogAnnounceInterval => 0x2009,
+            Self::AnnounceReceiptTimeout => 0x200a,
+            Self::LogSyncInterval => 0x200b,
+            Self::VersionNumber => 0x200c,
+            Self::EnablePort => 0x200d,
+            Self::DisablePort => 0x200e,
+            Self::Time => 0x200f,
+            Self::ClockAccuracy => 0x2010,
+            Self::UtcProperties => 0x2011,
+            Self::TraceabilityProperties => 0x2012,
+            Self::TimescaleProperties => 0x2013,
+            Self::MasterOnly => 0x3001,
+            Self::DelayMechanism => 0x6000,
+            Self::LogMinPdelayReqInterval => 0x6001,
+            Self::Other(value) => value,
+        }
+    }
+
+    pub fn from_primitive(value: u16) -> Self {
+        match value {
+            0x0000 => Self::NullPtpManagement,
+            0x0001 => Self::ClockDescription,
+            0x0002 => Self::UserDescription,
+            0x0003 => Self::SaveInNonVolatileStorage,
+            0x0004 => Self::ResetNonVolatileStorage,
+            0x0005 => Self::Initialize,
+            0x0006 => Self::FaultLog,
+            0x0007 => Self::FaultLogReset,
+            0x2000 => Self::DefaultDataSet,
+            0x2001 => Self::CurrentDataSet,
+            0x2002 => Self::ParentDataSet,
+            0x2003 => Self::TimePropertiesDataSet,
+            0x2004 => Self::PortDataSet,
+            0x2005 => Self::Priority1,
+            0x2006 => Self::Priority2,
+            0x2007 => Self::Domain,
+            0x2008 => Self::SlaveOnly,
+            0x2009 => Self::LogAnnounceInterval,
+            0x200a => Self::AnnounceReceiptTimeout,
+            0x200b => Self::LogSyncInterval,
+            0x200c => Self::VersionNumber,
+            0x200d => Self::EnablePort,
+            0x200e => Self::DisablePort,
+            0x200f => Self::Time,
+            0x2010 => Self::ClockAccuracy,
+            0x2011 => Self::UtcProperties,
+            0x2012 => Self::TraceabilityProperties,
+            0x2013 => Self::TimescaleProperties,
+            0x3001 => Self::MasterOnly,
+            0x6000 => Self::DelayMechanism,
+            0x6001 => Self::LogMinPdelayReqInterval,
+            _ => Self::Other(value),
+        }
+    }
+}
+
+/// See: 15.5.4.1.4 / Table 109
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ManagementErrorId {
+    ResponseTooBig,
+    NoSuchId,
+    WrongLength,
+    WrongValue,
+    NotSetable,
+    NotSupported,
+    GeneralError,
+    Reserved(u16),
+}
+
+impl ManagementErrorId {
+    pub fn to_primitive(self) -> u16 {
+        match self {
+            Self::ResponseTooBig => 0x0001,
+            Self::NoSuchId => 0x0002,
+            Self::WrongLength => 0x0003,
+            Self::WrongValue => 0x0004,
+            Self::NotSetable => 0x0005,
+            Self::NotSupported => 0x0006,
+            Self::GeneralError => 0xfffe,
+            Self::Reserved(value) => value,
+        }
+    }
+
+    pub fn from_primitive(value: u16) -> Self {
+        match value {
+            0x0001 => Self::ResponseTooBig,
+            0x0002 => Self::NoSuchId,
+            0x0003 => Self::WrongLength,
+            0x0004 => Self::WrongValue,
+            0x0005 => Self::NotSetable,
+            0x0006 => Self::NotSupported,
+            0xfffe => Self::GeneralError,
+            _ => Self::Reserved(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::{
+        common::{ClockIdentity, TimeSource},
+        messages::Message,
+    };
+
+    // A GET of the DEFAULT_DATA_SET from `pmc`, to all ports of all clocks
+    const GET_DEFAULT_DATA_SET: [u8; 54] = [
+        0x0d, 0x02, 0x00, 0x36, 0x00, 0x00, 0x00, 0x00, // type, version, length, domain
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // correction
+        0x00, 0x00, 0x00, 0x00, // reserved
+        0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x01, 0x00, 0x03, // source port
+        0x00, 0x05, 0x04, 0x7f, // sequence id, control, log interval
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // target port
+        0x01, 0x01, 0x00, 0x00, // boundary hops, action, reserved
+        0x00, 0x01, 0x00, 0x02, 0x20, 0x00, // management TLV
+    ];
+
+    #[test]
+    fn management_wireformat() {
+        let Ok(Message::Management(get)) = Message::deserialize(&GET_DEFAULT_DATA_SET) else {
+            panic!("Management message not accepted");
+        };
+        assert_eq!(get.header.sequence_id, 5);
+        assert_eq!(get.target_port_identity, PortIdentity::ALL);
+        assert_eq!(get.starting_boundary_hops, 1);
+        assert_eq!(get.boundary_hops, 1);
+        assert_eq!(get.action, ManagementAction::GET);
+        assert_eq!(
+            get.tlv,
+            ManagementTlv::Management {
+                id: ManagementId::DefaultDataSet,
+                data: ArrayVec::new(),
+            }
+        );
+
+        let mut buffer = [0xff; 128];
+        let len = Message::Management(get.clone())
+            .serialize(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer[..len], GET_DEFAULT_DATA_SET);
+
+        // Odd data is padded, and comes back with the padding
+        let response = ManagementMessage {
+            header: Header {
+                source_port_identity: PortIdentity {
+                    clock_identity: ClockIdentity([1; 8]),
+                    port_number: 0,
+                },
+                ..get.header
+            },
+            target_port_identity: get.header.source_port_identity,
+            starting_boundary_hops: 0,
+            boundary_hops: 0,
+            action: ManagementAction::RESPONSE,
+            tlv: ManagementTlv::Management {
+                id: ManagementId::Priority1,
+                data: [64].into_iter().collect(),
+            },
+        };
+        let len = Message::Management(response.clone())
+            .serialize(&mut buffer)
+            .unwrap();
+        assert_eq!(len, 56);
+        assert_eq!(buffer[48..56], [0x00, 0x01, 0x00, 0x04, 0x20, 0x05, 64, 0]);
+        let Ok(Message::Management(parsed)) = Message::deserialize(&buffer[..len]) else {
+            panic!("Management response not accepted");
+        };
+        let ManagementTlv::Management { data, .. } = parsed.tlv else {
+            panic!("Not a management TLV");
+        };
+        assert_eq!(data.as_slice(), [64, 0]);
+
+        let error = ManagementMessage {
+            tlv: ManagementTlv::ErrorStatus {
+                error: ManagementErrorId::NotSupported,
+                id: ManagementId::Time,
+            },
+            ..response
+        };
+        let len = Message::Management(error.clone())
+            .serialize(&mut buffer)
+            .unwrap();
+        assert_eq!(len, 62);
+        assert_eq!(buffer[48..52], [0x00, 0x02, 0x00, 0x0a]);
+        assert_eq!(
+            Message::deserialize(&buffer[..len]).ok(),
+            Some(Message::Management(error))
+        );
+
+        // The management TLV can't be cut short
+        assert!(Message::deserialize(&GET_DEFAULT_DATA_SET[..52]).is_err());
+
+        // Nor longer than any management id statime handles
+        let mut set_user_description = [0; 54 + 66];
+        set_user_description[..54].copy_from_slice(&GET_DEFAULT_DATA_SET);
+        set_user_description[2..4].copy_from_slice(&120u16.to_be_bytes());
+        set_user_description[46] = ManagementAction::SET.to_primitive();
+        set_user_description[50..54].copy_from_slice(&[0x00, 0x44, 0x00, 0x02]);
+        set_user_description[54] = 65;
+        assert!(matches!(
+            Message::deserialize(&set_user_description),
+            Err(WireFormatError::CapacityError)
+        ));
+
+        // Up to the capacity it still makes it through unchanged
+        let mut set_capacity = [0; 54 + 64];
+        set_capacity.copy_from_slice(&set_user_description[..54 + 64]);
+        set_capacity[2..4].copy_from_slice(&118u16.to_be_bytes());
+        set_capacity[50..52].copy_from_slice(&66u16.to_be_bytes());
+        let Ok(set) = Message::deserialize(&set_capacity) else {
+            panic!("Management message at capacity not accepted");
+        };
+        let len = set.serialize(&mut buffer).unwrap();
+        assert_eq!(buffer[..len], set_capacity);
+    }
+
+    #[test]
+    fn time_properties_data_set() {
+        let utc = TimePropertiesDS::new_ptp_time(
+            Some(37),
+            LeapIndicator::Leap61,
+            true,
+            false,
+            TimeSource::Gnss,
+        );
+        assert_eq!(
+            ManagementTlv::time_properties_data_set(&utc).unwrap(),
+            ManagementTlv::with_data(
+                ManagementId::TimePropertiesDataSet,
+                &[0x00, 0x25, 0b01_1101, 0x20]
+            )
+            .unwrap()
+        );
+
+        let arbitrary = TimePropertiesDS::new_arbitrary_time(false, true, TimeSource::AtomicClock);
+        assert_eq!(
+            ManagementTlv::time_properties_data_set(&arbitrary).unwrap(),
+            ManagementTlv::with_data(
+                ManagementId::TimePropertiesDataSet,
+                &[0x00, 0x00, 0b10_0000, 0x10]
+            )
+            .unwrap()
+        );
+    }
 }
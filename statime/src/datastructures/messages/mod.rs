@@ -1,24 +1,34 @@
 //! Ptp network messages
 
 pub(crate) use announce::*;
+pub(crate) use decoded::message_tlvs;
+pub use decoded::{decode_message, DecodeError, DecodedAnnounce, DecodedMessage, DecodedSignaling};
 pub(crate) use delay_req::*;
 pub(crate) use delay_resp::*;
 pub(crate) use follow_up::*;
 pub use header::*;
+pub(crate) use management::{
+    ManagementAction, ManagementErrorId, ManagementId, ManagementMessage, ManagementTlv,
+};
+pub(crate) use p_delay_req::*;
+pub(crate) use p_delay_resp::*;
+pub(crate) use p_delay_resp_follow_up::*;
+pub use route::{route_packet, PacketRoute};
+pub(crate) use signalling::SignalingMessage;
+pub use signalling::UnicastNegotiation;
 pub(crate) use sync::*;
 
-use self::{
-    management::ManagementMessage, p_delay_req::PDelayReqMessage, p_delay_resp::PDelayRespMessage,
-    p_delay_resp_follow_up::PDelayRespFollowUpMessage, signalling::SignalingMessage,
-};
 use super::{
     common::{PortIdentity, TimeInterval, WireTimestamp},
-    datasets::DefaultDS,
+    datasets::{DefaultDS, TimePropertiesDS},
+};
+use crate::{
+    config::LogMessageIntervals, ptp_instance::PtpInstanceState, Interval, LeapIndicator, Time,
 };
-use crate::{ptp_instance::PtpInstanceState, Interval, LeapIndicator, Time};
 
 mod announce;
 mod control_field;
+mod decoded;
 mod delay_req;
 mod delay_resp;
 mod follow_up;
@@ -27,11 +37,19 @@ mod management;
 mod p_delay_req;
 mod p_delay_resp;
 mod p_delay_resp_follow_up;
+mod route;
 mod signalling;
 mod sync;
 
 pub const MAX_DATA_LEN: usize = 255;
 
+/// Most TLVs examined in a received message. Any TLVs after these are
+/// ignored, so the work of parsing a message doesn't grow with its length.
+pub const MAX_MESSAGE_TLVS: usize = 16;
+
+/// The logMessageInterval of messages that aren't sent at a fixed interval
+pub(crate) const UNSPECIFIED_LOG_INTERVAL: i8 = 0x7f;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum MessageType {
@@ -104,6 +122,31 @@ pub(crate) enum Message {
     Management(ManagementMessage),
 }
 
+/// The logMessageInterval of a message of type `message_type`, following
+/// IEEE1588-2019 table 42 unless `overrides` sets it for the type.
+/// `interval` is the interval the field reports, for the types that have one.
+fn log_message_interval(
+    message_type: MessageType,
+    unicast: bool,
+    interval: Option<Interval>,
+    overrides: &LogMessageIntervals,
+) -> i8 {
+    if let Some(log_interval) = overrides.get(message_type) {
+        return log_interval;
+    }
+
+    match (message_type, interval) {
+        (MessageType::Announce, Some(interval)) => interval.as_log_2(),
+        // Unicast clients negotiate these intervals in their grants instead
+        (MessageType::Sync | MessageType::FollowUp | MessageType::DelayResp, Some(interval))
+            if !unicast =>
+        {
+            interval.as_log_2()
+        }
+        _ => UNSPECIFIED_LOG_INTERVAL,
+    }
+}
+
 fn base_header(default_ds: &DefaultDS, port_identity: PortIdentity, sequence_id: u16) -> Header {
     Header {
         sdo_id: default_ds.sdo_id,
@@ -120,10 +163,20 @@ impl Message {
         port_identity: PortIdentity,
         sequence_id: u16,
         current_time: Time,
+        sync_interval: Interval,
+        unicast: bool,
+        overrides: &LogMessageIntervals,
     ) -> Self {
         Message::Sync(SyncMessage {
             header: Header {
                 two_step_flag: true,
+                unicast_flag: unicast,
+                log_message_interval: log_message_interval(
+                    MessageType::Sync,
+                    unicast,
+                    Some(sync_interval),
+                    overrides,
+                ),
                 ..base_header(default_ds, port_identity, sequence_id)
             },
             origin_timestamp: current_time.into(),
@@ -135,26 +188,47 @@ impl Message {
         port_identity: PortIdentity,
         sequence_id: u16,
         timestamp: Time,
+        sync_interval: Interval,
+        unicast: bool,
+        overrides: &LogMessageIntervals,
     ) -> Self {
         Message::FollowUp(FollowUpMessage {
             header: Header {
                 correction_field: timestamp.subnano(),
+                unicast_flag: unicast,
+                log_message_interval: log_message_interval(
+                    MessageType::FollowUp,
+                    unicast,
+                    Some(sync_interval),
+                    overrides,
+                ),
                 ..base_header(default_ds, port_identity, sequence_id)
             },
             precise_origin_timestamp: timestamp.into(),
+            information: None,
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn announce<C, F>(
         global: &PtpInstanceState<C, F>,
+        time_properties_ds: &TimePropertiesDS,
         port_identity: PortIdentity,
         sequence_id: u16,
         current_time: Time,
+        announce_interval: Interval,
+        unicast: bool,
+        overrides: &LogMessageIntervals,
     ) -> Self {
-        let time_properties_ds = &global.time_properties_ds;
-
         Message::Announce(AnnounceMessage {
             header: Header {
+                unicast_flag: unicast,
+                log_message_interval: log_message_interval(
+                    MessageType::Announce,
+                    unicast,
+                    Some(announce_interval),
+                    overrides,
+                ),
                 leap59: time_properties_ds.leap_indicator == LeapIndicator::Leap59,
                 leap61: time_properties_ds.leap_indicator == LeapIndicator::Leap61,
                 current_utc_offset_valid: time_properties_ds.current_utc_offset.is_some(),
@@ -171,17 +245,70 @@ impl Message {
             grandmaster_identity: global.parent_ds.grandmaster_identity,
             steps_removed: global.current_ds.steps_removed,
             time_source: time_properties_ds.time_source,
+            path_trace: global.path_trace,
         })
     }
 
+    pub(crate) fn signaling(
+        default_ds: &DefaultDS,
+        port_identity: PortIdentity,
+        sequence_id: u16,
+        target_port_identity: PortIdentity,
+        negotiations: &[UnicastNegotiation],
+        overrides: &LogMessageIntervals,
+    ) -> Self {
+        Message::Signaling(SignalingMessage::new(
+            Header {
+                unicast_flag: true,
+                log_message_interval: log_message_interval(
+                    MessageType::Signaling,
+                    true,
+                    None,
+                    overrides,
+                ),
+                ..base_header(default_ds, port_identity, sequence_id)
+            },
+            target_port_identity,
+            negotiations,
+        ))
+    }
+
     pub(crate) fn delay_req(
         default_ds: &DefaultDS,
         port_identity: PortIdentity,
         sequence_id: u16,
+        unicast: bool,
+        overrides: &LogMessageIntervals,
     ) -> Self {
         Message::DelayReq(DelayReqMessage {
             header: Header {
-                log_message_interval: 0x7f,
+                unicast_flag: unicast,
+                log_message_interval: log_message_interval(
+                    MessageType::DelayReq,
+                    unicast,
+                    None,
+                    overrides,
+                ),
+                ..base_header(default_ds, port_identity, sequence_id)
+            },
+            origin_timestamp: WireTimestamp::default(),
+        })
+    }
+
+    pub(crate) fn pdelay_req(
+        default_ds: &DefaultDS,
+        port_identity: PortIdentity,
+        sequence_id: u16,
+        overrides: &LogMessageIntervals,
+    ) -> Self {
+        Message::PDelayReq(PDelayReqMessage {
+            header: Header {
+                log_message_interval: log_message_interval(
+                    MessageType::PDelayReq,
+                    false,
+                    None,
+                    overrides,
+                ),
                 ..base_header(default_ds, port_identity, sequence_id)
             },
             origin_timestamp: WireTimestamp::default(),
@@ -193,6 +320,7 @@ impl Message {
         port_identity: PortIdentity,
         min_delay_req_interval: Interval,
         timestamp: Time,
+        overrides: &LogMessageIntervals,
     ) -> Self {
         Message::DelayResp(DelayRespMessage {
             header: Header {
@@ -201,13 +329,130 @@ impl Message {
                 correction_field: TimeInterval(
                     request.header.correction_field.0 + timestamp.subnano().0,
                 ),
-                log_message_interval: min_delay_req_interval.as_log_2(),
+                log_message_interval: log_message_interval(
+                    MessageType::DelayResp,
+                    request.header.unicast_flag,
+                    Some(min_delay_req_interval),
+                    overrides,
+                ),
                 ..request.header
             },
             receive_timestamp: timestamp.into(),
             requesting_port_identity: request.header.source_port_identity,
         })
     }
+
+    /// The PdelayResp of a two-step responder, carrying the time `timestamp`
+    /// the request was received. The correction field is left for the
+    /// PdelayRespFollowUp.
+    pub(crate) fn pdelay_resp(
+        request: &PDelayReqMessage,
+        port_identity: PortIdentity,
+        timestamp: Time,
+        overrides: &LogMessageIntervals,
+    ) -> Self {
+        Message::PDelayResp(PDelayRespMessage {
+            header: Header {
+                two_step_flag: true,
+                source_port_identity: port_identity,
+                correction_field: TimeInterval::default(),
+                log_message_interval: log_message_interval(
+                    MessageType::PDelayResp,
+                    request.header.unicast_flag,
+                    None,
+                    overrides,
+                ),
+                ..request.header
+            },
+            request_receive_timestamp: timestamp.into(),
+            requesting_port_identity: request.header.source_port_identity,
+        })
+    }
+
+    /// The PdelayResp of a one-step responder. The hardware sending it adds
+    /// the turnaround time to the correction field (11.4.3).
+    pub(crate) fn pdelay_resp_one_step(
+        request: &PDelayReqMessage,
+        port_identity: PortIdentity,
+        overrides: &LogMessageIntervals,
+    ) -> Self {
+        Message::PDelayResp(PDelayRespMessage {
+            header: Header {
+                two_step_flag: false,
+                source_port_identity: port_identity,
+                log_message_interval: log_message_interval(
+                    MessageType::PDelayResp,
+                    request.header.unicast_flag,
+                    None,
+                    overrides,
+                ),
+                ..request.header
+            },
+            request_receive_timestamp: WireTimestamp::default(),
+            requesting_port_identity: request.header.source_port_identity,
+        })
+    }
+
+    pub(crate) fn pdelay_resp_follow_up(
+        default_ds: &DefaultDS,
+        port_identity: PortIdentity,
+        requesting_port_identity: PortIdentity,
+        sequence_id: u16,
+        correction: TimeInterval,
+        timestamp: Time,
+        overrides: &LogMessageIntervals,
+    ) -> Self {
+        Message::PDelayRespFollowUp(PDelayRespFollowUpMessage {
+            header: Header {
+                correction_field: TimeInterval(correction.0 + timestamp.subnano().0),
+                log_message_interval: log_message_interval(
+                    MessageType::PDelayRespFollowUp,
+                    false,
+                    None,
+                    overrides,
+                ),
+                ..base_header(default_ds, port_identity, sequence_id)
+            },
+            response_origin_timestamp: timestamp.into(),
+            requesting_port_identity,
+        })
+    }
+
+    /// The reply of port `port_identity` to a management message, addressed
+    /// to the port that sent the request (15.3.3)
+    pub(crate) fn management_reply(
+        request: &ManagementMessage,
+        port_identity: PortIdentity,
+        action: ManagementAction,
+        tlv: ManagementTlv,
+        overrides: &LogMessageIntervals,
+    ) -> Self {
+        let boundary_hops = request
+            .starting_boundary_hops
+            .saturating_sub(request.boundary_hops);
+
+        Message::Management(ManagementMessage {
+            header: Header {
+                sdo_id: request.header.sdo_id,
+                domain_number: request.header.domain_number,
+                unicast_flag: request.header.unicast_flag,
+                source_port_identity: port_identity,
+                sequence_id: request.header.sequence_id,
+                log_message_interval: log_message_interval(
+                    MessageType::Management,
+                    request.header.unicast_flag,
+                    None,
+                    overrides,
+                ),
+                ..Default::default()
+            },
+            target_port_identity: request.header.source_port_identity,
+            starting_boundary_hops: boundary_hops,
+            boundary_hops,
+            action,
+            tlv,
+        })
+    }
 }
 
 impl Message {
@@ -246,7 +491,7 @@ impl Message {
         }
     }
 
-    fn content_type(&self) -> MessageType {
+    pub(crate) fn content_type(&self) -> MessageType {
         match self {
             Message::Sync(_) => MessageType::Sync,
             Message::DelayReq(_) => MessageType::DelayReq,
@@ -288,12 +533,23 @@ impl Message {
 
     /// Deserializes a message from the PTP wire format.
     ///
-    /// Returns the message or an error.
+    /// The message ends where the length in its header says it does, any
+    /// bytes after that are ignored. Returns the message or an error.
     pub(crate) fn deserialize(buffer: &[u8]) -> Result<Self, super::WireFormatError> {
         let header_data = Header::deserialize_header(buffer)?;
 
-        // Skip the header bytes and only keep the content
-        let content_buffer = &buffer[34..];
+        // Anything after the message is padding, for example to reach the
+        // minimum size of an ethernet frame, and is ignored
+        let message_length = header_data.message_length as usize;
+        let message_buffer = buffer
+            .get(..message_length)
+            .ok_or(super::WireFormatError::BufferTooShort)?;
+
+        // Skip the header bytes and only keep the content. TLVs after the
+        // content, like PAD TLVs, are ignored by messages that don't expect any
+        let content_buffer = message_buffer
+            .get(34..)
+            .ok_or(super::WireFormatError::BufferTooShort)?;
 
         Ok(match header_data.message_type {
             MessageType::Sync => Message::Sync(SyncMessage::deserialize_content(
@@ -338,3 +594,269 @@ impl Message {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::common::{ClockIdentity, Tlv, TlvType};
+
+    // A two-step Sync with sequence id 0x1234, sent over ethernet. The
+    // message is 44 bytes, the rest pads the frame to the minimum size.
+    const PADDED_SYNC: [u8; 46] = [
+        0x00, 0x02, 0x00, 0x2c, 0x00, 0x00, 0x02, 0x00, // type, version, length, domain
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // correction
+        0x00, 0x00, 0x00, 0x00, // reserved
+        0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x01, 0x00, 0x03, // source port
+        0x12, 0x34, 0x00, 0x00, // sequence id, control, log interval
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // origin timestamp
+        0x00, 0x00, // frame padding
+    ];
+
+    #[test]
+    fn ignore_frame_padding() {
+        let Ok(Message::Sync(sync)) = Message::deserialize(&PADDED_SYNC) else {
+            panic!("Padded sync not accepted");
+        };
+        assert_eq!(sync.header.sequence_id, 0x1234);
+        assert_eq!(
+            sync.header.source_port_identity,
+            PortIdentity {
+                clock_identity: ClockIdentity([0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x01]),
+                port_number: 3,
+            }
+        );
+
+        // Trailing bytes are ignored, but a message can't be cut short
+        assert_eq!(
+            Message::deserialize(&PADDED_SYNC[..44]).ok(),
+            Message::deserialize(&PADDED_SYNC).ok()
+        );
+        assert!(Message::deserialize(&PADDED_SYNC[..43]).is_err());
+    }
+
+    #[test]
+    fn peer_delay_messages() {
+        let requester = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let responder = PortIdentity {
+            clock_identity: ClockIdentity([2; 8]),
+            port_number: 2,
+        };
+        let header = |source_port_identity| Header {
+            domain_number: 3,
+            two_step_flag: true,
+            source_port_identity,
+            sequence_id: 0x4321,
+            log_message_interval: 0x7f,
+            ..Default::default()
+        };
+        let timestamp = WireTimestamp {
+            seconds: 1169232218,
+            nanos: 174389936,
+        };
+
+        let messages = [
+            Message::PDelayReq(PDelayReqMessage {
+                header: header(requester),
+                origin_timestamp: WireTimestamp::default(),
+            }),
+            Message::PDelayResp(PDelayRespMessage {
+                header: header(responder),
+                request_receive_timestamp: timestamp,
+                requesting_port_identity: requester,
+            }),
+            Message::PDelayRespFollowUp(PDelayRespFollowUpMessage {
+                header: header(responder),
+                response_origin_timestamp: timestamp,
+                requesting_port_identity: requester,
+            }),
+        ];
+
+        for (message, message_type) in messages.into_iter().zip([0x2, 0x3, 0xa]) {
+            let mut buffer = [0xff; MAX_DATA_LEN];
+            let len = message.serialize(&mut buffer).unwrap();
+
+            // All three are 54 bytes long, so they take the same time to
+            // transmit (11.4.1)
+            assert_eq!(len, 54);
+            assert_eq!(buffer[0] & 0x0f, message_type);
+            assert_eq!(buffer[2..4], [0, 54]);
+            assert_eq!(Message::deserialize(&buffer[..len]).ok(), Some(message));
+            assert!(Message::deserialize(&buffer[..len - 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn ignore_pad_tlv() {
+        // A DelayResp followed by a PAD TLV of 8 bytes, and frame padding
+        let mut frame = [0u8; 70];
+        frame[..4].copy_from_slice(&[0x09, 0x02, 0x00, 0x42]);
+        frame[20..30]
+            .copy_from_slice(&[0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x01, 0x00, 0x03]);
+        frame[30..34].copy_from_slice(&[0x00, 0x07, 0x03, 0x00]);
+        frame[36..40].copy_from_slice(&[0x65, 0x4a, 0x1b, 0x2c]);
+        frame[44..54]
+            .copy_from_slice(&[0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x02, 0x00, 0x01]);
+        frame[54..58].copy_from_slice(&[0x80, 0x08, 0x00, 0x08]);
+
+        let Ok(Message::DelayResp(delay_resp)) = Message::deserialize(&frame) else {
+            panic!("DelayResp with PAD TLV not accepted");
+        };
+        assert_eq!(delay_resp.header.sequence_id, 7);
+        assert_eq!(delay_resp.receive_timestamp.seconds, 0x654a_1b2c);
+        assert_eq!(delay_resp.requesting_port_identity.port_number, 1);
+
+        // Messages that do hold TLVs leave the PAD TLV out, even if it is
+        // larger than any TLV they can hold
+        let mut frame = [0u8; 60];
+        frame[..4].copy_from_slice(&[0x0c, 0x02, 0x00, 0x3c]);
+        frame[44..48].copy_from_slice(&[0x80, 0x08, 0x00, 0x08]);
+        frame[56..60].copy_from_slice(&[0x00, 0x08, 0x00, 0x00]);
+
+        let Ok(Message::Signaling(signaling)) = Message::deserialize(&frame) else {
+            panic!("Signaling with PAD TLV not accepted");
+        };
+        assert_eq!(signaling.value.len(), 1);
+        assert_eq!(signaling.value[0].tlv_type, TlvType::PathTrace);
+        assert_eq!(Tlv::peek(&frame[44..]).ok(), Some((TlvType::Pad, 12)));
+    }
+
+    // The logMessageInterval field of every message type, for multicast and
+    // unicast messages, see IEEE1588-2019 table 42
+    #[test]
+    fn log_message_interval_per_type() {
+        let default_ds = DefaultDS::new(crate::config::InstanceConfig {
+            clock_identity: ClockIdentity([1; 8]),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let own = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let remote = PortIdentity {
+            clock_identity: ClockIdentity([2; 8]),
+            port_number: 1,
+        };
+        let interval = Interval::from_log_2(-3);
+        let time = Time::from_secs(1);
+
+        let messages = |unicast: bool, overrides: &LogMessageIntervals| {
+            let Message::DelayReq(delay_req) =
+                Message::delay_req(&default_ds, remote, 1, unicast, overrides)
+            else {
+                unreachable!()
+            };
+            let Message::PDelayReq(pdelay_req) =
+                Message::pdelay_req(&default_ds, remote, 1, overrides)
+            else {
+                unreachable!()
+            };
+            let management = ManagementMessage {
+                header: Header {
+                    unicast_flag: unicast,
+                    ..Header::default()
+                },
+                target_port_identity: PortIdentity::ALL,
+                starting_boundary_hops: 1,
+                boundary_hops: 1,
+                action: ManagementAction::GET,
+                tlv: ManagementTlv::ErrorStatus {
+                    error: ManagementErrorId::NoSuchId,
+                    id: ManagementId::NullPtpManagement,
+                },
+            };
+            let action = ManagementAction::RESPONSE;
+            let tlv = management.tlv.clone();
+
+            [
+                Message::sync(&default_ds, own, 1, time, interval, unicast, overrides),
+                Message::delay_req(&default_ds, own, 1, unicast, overrides),
+                Message::pdelay_req(&default_ds, own, 1, overrides),
+                Message::pdelay_resp(&pdelay_req, own, time, overrides),
+                Message::follow_up(&default_ds, own, 1, time, interval, unicast, overrides),
+                Message::delay_resp(&delay_req, own, interval, time, overrides),
+                Message::pdelay_resp_follow_up(
+                    &default_ds,
+                    own,
+                    remote,
+                    1,
+                    TimeInterval::default(),
+                    time,
+                    overrides,
+                ),
+                Message::signaling(&default_ds, own, 1, remote, &[], overrides),
+                Message::management_reply(&management, own, action, tlv, overrides),
+            ]
+        };
+
+        let expected = |message: &Message, unicast: bool| match message.content_type() {
+            MessageType::Sync | MessageType::FollowUp | MessageType::DelayResp if !unicast => -3,
+            _ => 0x7f,
+        };
+
+        let mut buffer = [0; MAX_DATA_LEN];
+        for unicast in [false, true] {
+            for message in messages(unicast, &LogMessageIntervals::STANDARD) {
+                let log_interval = expected(&message, unicast);
+                assert_eq!(
+                    message.header().log_message_interval,
+                    log_interval,
+                    "{:?}, unicast {unicast}",
+                    message.content_type()
+                );
+
+                message.serialize(&mut buffer).unwrap();
+                assert_eq!(buffer[33] as i8, log_interval);
+            }
+        }
+
+        // A profile can set the field for any type
+        let overrides = LogMessageIntervals {
+            sync: Some(-7),
+            delay_req: Some(-6),
+            pdelay_req: Some(-5),
+            pdelay_resp: Some(-4),
+            follow_up: Some(-3),
+            delay_resp: Some(-2),
+            pdelay_resp_follow_up: Some(-1),
+            announce: Some(0),
+            signaling: Some(1),
+            management: Some(2),
+        };
+        for unicast in [false, true] {
+            for message in messages(unicast, &overrides) {
+                assert_eq!(
+                    Some(message.header().log_message_interval),
+                    overrides.get(message.content_type()),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn log_message_interval_announce() {
+        let overrides = LogMessageIntervals {
+            announce: Some(-2),
+            ..LogMessageIntervals::STANDARD
+        };
+        for unicast in [false, true] {
+            let announce = |overrides| {
+                log_message_interval(
+                    MessageType::Announce,
+                    unicast,
+                    Some(Interval::TWO_SECONDS),
+                    overrides,
+                )
+            };
+            // Unicast announce messages keep reporting their interval
+            assert_eq!(announce(&LogMessageIntervals::STANDARD), 1);
+            assert_eq!(announce(&overrides), -2);
+        }
+    }
+}
@@ -3,24 +3,27 @@ use crate::datastructures::{common::WireTimestamp, WireFormat, WireFormatError};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct PDelayReqMessage {
-    pub(super) header: Header,
-    pub(super) origin_timestamp: WireTimestamp,
+    pub(crate) header: Header,
+    pub(crate) origin_timestamp: WireTimestamp,
 }
 
 impl PDelayReqMessage {
+    // The origin timestamp is followed by 10 reserved bytes, which make the
+    // message as long as the response (11.4.2)
     pub(crate) fn content_size(&self) -> usize {
-        10
+        20
     }
 
     pub(crate) fn serialize_content(
         &self,
         buffer: &mut [u8],
     ) -> Result<(), crate::datastructures::WireFormatError> {
-        if buffer.len() < 10 {
+        if buffer.len() < 20 {
             return Err(WireFormatError::BufferTooShort);
         }
 
         self.origin_timestamp.serialize(&mut buffer[0..10])?;
+        buffer[10..20].fill(0);
 
         Ok(())
     }
@@ -29,7 +32,10 @@ impl PDelayReqMessage {
         header: Header,
         buffer: &[u8],
     ) -> Result<Self, crate::datastructures::WireFormatError> {
-        let slice = buffer.get(0..10).ok_or(WireFormatError::BufferTooShort)?;
+        if buffer.len() < 20 {
+            return Err(WireFormatError::BufferTooShort);
+        }
+        let slice = &buffer[0..10];
 
         Ok(Self {
             header,
@@ -45,7 +51,10 @@ mod tests {
     #[test]
     fn timestamp_wireformat() {
         let representations = [(
-            [0x00, 0x00, 0x45, 0xb1, 0x11, 0x5a, 0x0a, 0x64, 0xfa, 0xb0],
+            [
+                0x00, 0x00, 0x45, 0xb1, 0x11, 0x5a, 0x0a, 0x64, 0xfa, 0xb0, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ],
             PDelayReqMessage {
                 header: Header::default(),
                 origin_timestamp: WireTimestamp {
@@ -57,7 +66,7 @@ mod tests {
 
         for (byte_representation, object_representation) in representations {
             // Test the serialization output
-            let mut serialization_buffer = [0; 10];
+            let mut serialization_buffer = [0xff; 20];
             object_representation
                 .serialize_content(&mut serialization_buffer)
                 .unwrap();
@@ -68,6 +77,13 @@ mod tests {
                 PDelayReqMessage::deserialize_content(Header::default(), &byte_representation)
                     .unwrap();
             assert_eq!(deserialized_data, object_representation);
+
+            // The reserved bytes must be there
+            assert!(PDelayReqMessage::deserialize_content(
+                Header::default(),
+                &byte_representation[..10]
+            )
+            .is_err());
         }
     }
 }
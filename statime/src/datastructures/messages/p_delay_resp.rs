@@ -6,9 +6,9 @@ use crate::datastructures::{
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct PDelayRespMessage {
-    pub(super) header: Header,
-    pub(super) request_receive_timestamp: WireTimestamp,
-    pub(super) requesting_port_identity: PortIdentity,
+    pub(crate) header: Header,
+    pub(crate) request_receive_timestamp: WireTimestamp,
+    pub(crate) requesting_port_identity: PortIdentity,
 }
 
 impl PDelayRespMessage {
@@ -6,9 +6,9 @@ use crate::datastructures::{
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct PDelayRespFollowUpMessage {
-    pub(super) header: Header,
-    pub(super) response_origin_timestamp: WireTimestamp,
-    pub(super) requesting_port_identity: PortIdentity,
+    pub(crate) header: Header,
+    pub(crate) response_origin_timestamp: WireTimestamp,
+    pub(crate) requesting_port_identity: PortIdentity,
 }
 
 impl PDelayRespFollowUpMessage {
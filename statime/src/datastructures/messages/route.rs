@@ -0,0 +1,150 @@
+//! The fields that decide which port handles a received packet, for runtimes
+//! that receive the packets of several ports or instances on one socket.
+
+use super::{decoded::DecodeError, Header, MessageType, SdoId};
+use crate::datastructures::{
+    common::{ClockIdentity, PortIdentity},
+    WireFormat, WireFormatError,
+};
+
+/// Where the requesting port identity starts in (peer) delay responses and
+/// peer delay response follow ups, after the header and a timestamp
+const REQUESTING_PORT_OFFSET: usize = 44;
+
+/// The fields of a received packet that decide which port handles it, see
+/// [`route_packet`] and [`Port::match_packet`](crate::Port::match_packet)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketRoute {
+    /// The type of the message
+    pub message_type: MessageType,
+    /// The domain the message belongs to
+    pub domain_number: u8,
+    /// The SDO the message belongs to, together with the domain identifying
+    /// the instance it is meant for
+    pub sdo_id: SdoId,
+    /// Whether the unicast flag of the message is set
+    pub unicast: bool,
+    /// The port that sent the message, as clock identity and port number
+    pub source_port: (ClockIdentity, u16),
+    /// The port whose request is answered by a (peer) delay response or peer
+    /// delay response follow up message, as clock identity and port number
+    pub requesting_port: Option<(ClockIdentity, u16)>,
+}
+
+impl PacketRoute {
+    /// Whether the message is an event message, which a port only handles
+    /// together with its receive timestamp
+    pub fn is_event(&self) -> bool {
+        matches!(
+            self.message_type,
+            MessageType::Sync
+                | MessageType::DelayReq
+                | MessageType::PDelayReq
+                | MessageType::PDelayResp
+        )
+    }
+}
+
+/// Read the fields that decide which port handles a packet, without decoding
+/// the whole message.
+pub fn route_packet(data: &[u8]) -> Result<PacketRoute, DecodeError> {
+    let header_data = Header::deserialize_header(data).map_err(DecodeError)?;
+    let header = header_data.header;
+
+    let requesting_port = match header_data.message_type {
+        MessageType::DelayResp | MessageType::PDelayResp | MessageType::PDelayRespFollowUp => {
+            let identity = data
+                .get(REQUESTING_PORT_OFFSET..REQUESTING_PORT_OFFSET + 10)
+                .ok_or(DecodeError(WireFormatError::BufferTooShort))?;
+            let identity = PortIdentity::deserialize(identity).map_err(DecodeError)?;
+            Some((identity.clock_identity, identity.port_number))
+        }
+        _ => None,
+    };
+
+    Ok(PacketRoute {
+        message_type: header_data.message_type,
+        domain_number: header.domain_number,
+        sdo_id: header.sdo_id,
+        unicast: header.unicast_flag,
+        source_port: (
+            header.source_port_identity.clock_identity,
+            header.source_port_identity.port_number,
+        ),
+        requesting_port,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{InstanceConfig, LogMessageIntervals},
+        datastructures::{datasets::DefaultDS, messages::Message},
+        Interval, Time,
+    };
+
+    #[test]
+    fn route_delay_messages() {
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity([1; 8]),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 4,
+            slave_only: false,
+            sdo_id: SdoId::new(0x100).unwrap(),
+        });
+        let slave = PortIdentity {
+            clock_identity: default_ds.clock_identity,
+            port_number: 2,
+        };
+        let master = PortIdentity {
+            clock_identity: ClockIdentity([2; 8]),
+            port_number: 1,
+        };
+
+        let request = Message::delay_req(
+            &default_ds,
+            slave,
+            17,
+            false,
+            &LogMessageIntervals::STANDARD,
+        );
+        let mut buffer = [0; 128];
+        let len = request.serialize(&mut buffer).unwrap();
+        let route = route_packet(&buffer[..len]).unwrap();
+        assert_eq!(
+            route,
+            PacketRoute {
+                message_type: MessageType::DelayReq,
+                domain_number: 4,
+                sdo_id: SdoId::new(0x100).unwrap(),
+                unicast: false,
+                source_port: (ClockIdentity([1; 8]), 2),
+                requesting_port: None,
+            }
+        );
+        assert!(route.is_event());
+
+        let Message::DelayReq(request) = request else {
+            unreachable!()
+        };
+        let response = Message::delay_resp(
+            &request,
+            master,
+            Interval::ONE_SECOND,
+            Time::from_secs(1),
+            &LogMessageIntervals::STANDARD,
+        );
+        let len = response.serialize(&mut buffer).unwrap();
+        let route = route_packet(&buffer[..len]).unwrap();
+        assert_eq!(route.message_type, MessageType::DelayResp);
+        assert_eq!(route.source_port, (ClockIdentity([2; 8]), 1));
+        assert_eq!(route.requesting_port, Some((ClockIdentity([1; 8]), 2)));
+        assert!(!route.is_event());
+
+        // The requesting port is part of the route, so it must be there
+        assert!(route_packet(&buffer[..40]).is_err());
+        assert!(route_packet(&buffer[..20]).is_err());
+    }
+}
@@ -1,11 +1,13 @@
 use arrayvec::ArrayVec;
 
-use super::Header;
+use super::{Header, MessageType, MAX_MESSAGE_TLVS};
 use crate::datastructures::{
-    common::{PortIdentity, Tlv},
+    common::{PortIdentity, Tlv, TlvType},
     WireFormat, WireFormatError,
 };
 
+/// A signaling message, see 13.12. It carries requests and responses between
+/// ports in its TLVs, like those of the unicast negotiation mechanism.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct SignalingMessage {
     pub(super) header: Header,
@@ -15,11 +17,47 @@ pub(crate) struct SignalingMessage {
 }
 
 impl SignalingMessage {
-    // TODO: determine the best max length value
-    const CAPACITY: usize = 4;
+    // Enough for a request for each of the message types of unicast
+    // negotiation
+    pub(super) const CAPACITY: usize = 4;
+
+    pub(crate) fn new(
+        header: Header,
+        target_port_identity: PortIdentity,
+        negotiations: &[UnicastNegotiation],
+    ) -> Self {
+        Self {
+            header,
+            target_port_identity,
+            value: negotiations
+                .iter()
+                .take(Self::CAPACITY)
+                .map(|negotiation| negotiation.to_tlv())
+                .collect(),
+        }
+    }
+
+    pub(crate) fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub(crate) fn target_port_identity(&self) -> PortIdentity {
+        self.target_port_identity
+    }
+
+    /// The TLVs in this message, without the PAD TLVs and those that were
+    /// too large to hold
+    pub(crate) fn tlvs(&self) -> &[Tlv] {
+        &self.value
+    }
+
+    /// The unicast negotiation TLVs in this message, skipping any other TLVs
+    pub(crate) fn unicast_negotiations(&self) -> impl Iterator<Item = UnicastNegotiation> + '_ {
+        self.value.iter().filter_map(UnicastNegotiation::from_tlv)
+    }
 
     pub(crate) fn content_size(&self) -> usize {
-        10
+        10 + self.value.iter().map(|tlv| tlv.wire_size()).sum::<usize>()
     }
 
     pub(crate) fn serialize_content(&self, buffer: &mut [u8]) -> Result<(), WireFormatError> {
@@ -52,13 +90,21 @@ impl SignalingMessage {
         let mut buffer = &buffer[10..];
 
         let mut tlvs = ArrayVec::<Tlv, { Self::CAPACITY }>::new();
-        while buffer.len() > 4 {
-            let tlv = Tlv::deserialize(buffer)?;
+        for _ in 0..MAX_MESSAGE_TLVS {
+            if buffer.len() < 4 {
+                break;
+            }
+            let (tlv_type, size) = Tlv::peek(buffer)?;
 
-            buffer = &buffer[tlv.wire_size()..];
+            // PAD TLVs only make the message longer, they carry nothing. TLVs
+            // with larger values than any we handle are left out, so they
+            // don't keep us from answering the other TLVs.
+            if tlv_type != TlvType::Pad && size - 4 <= Tlv::CAPACITY {
+                tlvs.try_push(Tlv::deserialize(buffer)?)
+                    .map_err(|_| WireFormatError::CapacityError)?;
+            }
 
-            tlvs.try_push(tlv)
-                .map_err(|_| WireFormatError::CapacityError)?;
+            buffer = &buffer[size..];
         }
 
         Ok(Self {
@@ -68,3 +114,182 @@ impl SignalingMessage {
         })
     }
 }
+
+/// A TLV of the unicast negotiation mechanism, see IEEE1588-2019 section
+/// 16.1.4. Each one is about the messages of a single type, which a client
+/// asks a master to send it with unicast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicastNegotiation {
+    /// REQUEST_UNICAST_TRANSMISSION
+    Request {
+        message_type: MessageType,
+        /// The log2 of the interval between the messages, in seconds
+        log_interval: i8,
+        /// How long the messages should be sent, in seconds
+        duration: u32,
+    },
+    /// GRANT_UNICAST_TRANSMISSION, a duration of 0 denies the request
+    Grant {
+        message_type: MessageType,
+        /// The log2 of the interval between the messages, in seconds
+        log_interval: i8,
+        /// How long the messages will be sent, in seconds
+        duration: u32,
+        /// Whether the master will grant a request to extend the duration
+        renewal_invited: bool,
+    },
+    /// CANCEL_UNICAST_TRANSMISSION
+    Cancel { message_type: MessageType },
+    /// ACKNOWLEDGE_CANCEL_UNICAST_TRANSMISSION
+    AcknowledgeCancel { message_type: MessageType },
+}
+
+impl UnicastNegotiation {
+    fn from_tlv(tlv: &Tlv) -> Option<Self> {
+        let value = tlv.value.as_slice();
+        // The message type is in the upper nibble of the first byte
+        let message_type = MessageType::try_from(value.first()? >> 4).ok()?;
+
+        match tlv.tlv_type {
+            TlvType::RequestUnicastTransmission => Some(Self::Request {
+                message_type,
+                log_interval: *value.get(1)? as i8,
+                duration: u32::from_be_bytes(value.get(2..6)?.try_into().ok()?),
+            }),
+            TlvType::GrantUnicastTransmission => Some(Self::Grant {
+                message_type,
+                log_interval: *value.get(1)? as i8,
+                duration: u32::from_be_bytes(value.get(2..6)?.try_into().ok()?),
+                renewal_invited: value.get(7)? & 1 != 0,
+            }),
+            TlvType::CancelUnicastTransmission => Some(Self::Cancel { message_type }),
+            TlvType::AcknowledgeCancelUnicastTransmission => {
+                Some(Self::AcknowledgeCancel { message_type })
+            }
+            _ => None,
+        }
+    }
+
+    fn to_tlv(self) -> Tlv {
+        let mut value = ArrayVec::new();
+        let tlv_type = match self {
+            Self::Request {
+                message_type,
+                log_interval,
+                duration,
+            } => {
+                value.push((message_type as u8) << 4);
+                value.push(log_interval as u8);
+                value.extend(duration.to_be_bytes());
+                TlvType::RequestUnicastTransmission
+            }
+            Self::Grant {
+                message_type,
+                log_interval,
+                duration,
+                renewal_invited,
+            } => {
+                value.push((message_type as u8) << 4);
+                value.push(log_interval as u8);
+                value.extend(duration.to_be_bytes());
+                value.push(0);
+                value.push(renewal_invited as u8);
+                TlvType::GrantUnicastTransmission
+            }
+            Self::Cancel { message_type } => {
+                value.extend([(message_type as u8) << 4, 0]);
+                TlvType::CancelUnicastTransmission
+            }
+            Self::AcknowledgeCancel { message_type } => {
+                value.extend([(message_type as u8) << 4, 0]);
+                TlvType::AcknowledgeCancelUnicastTransmission
+            }
+        };
+
+        Tlv { tlv_type, value }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicast_negotiation_wireformat() {
+        let negotiations = [
+            UnicastNegotiation::Request {
+                message_type: MessageType::Announce,
+                log_interval: -3,
+                duration: 300,
+            },
+            UnicastNegotiation::Grant {
+                message_type: MessageType::Announce,
+                log_interval: -3,
+                duration: 60,
+                renewal_invited: true,
+            },
+            UnicastNegotiation::Cancel {
+                message_type: MessageType::Sync,
+            },
+            UnicastNegotiation::AcknowledgeCancel {
+                message_type: MessageType::DelayResp,
+            },
+        ];
+
+        let grant = negotiations[1].to_tlv();
+        assert_eq!(grant.tlv_type, TlvType::GrantUnicastTransmission);
+        assert_eq!(grant.value.as_slice(), [0xb0, 0xfd, 0, 0, 0, 60, 0, 1]);
+
+        for negotiation in negotiations {
+            let tlv = negotiation.to_tlv();
+            let mut buffer = [0; 16];
+            tlv.serialize(&mut buffer).unwrap();
+            let parsed = Tlv::deserialize(&buffer).unwrap();
+            assert_eq!(UnicastNegotiation::from_tlv(&parsed), Some(negotiation));
+        }
+    }
+
+    #[test]
+    fn signaling_tlvs() {
+        let mut content = [0xff; 10].to_vec();
+        // An organization extension, with an organization id, a subtype and
+        // 6 bytes of data
+        content.extend([0x00, 0x03, 0x00, 0x0c, 0x00, 0x80, 0xc2, 0x00, 0x00, 0x02]);
+        content.extend([0xfd, 0xfe, 0x01, 0x03, 0x00, 0x00]);
+        // Too large to hold
+        content.extend([0x20, 0x04, 0x00, 0x14]);
+        content.extend([0; 20]);
+        content.extend([0x00, 0x04, 0x00, 0x06, 0xb0, 0xfd, 0x00, 0x00, 0x01, 0x2c]);
+
+        let message = SignalingMessage::deserialize_content(Header::default(), &content).unwrap();
+        assert_eq!(message.target_port_identity(), PortIdentity::ALL);
+        let types: [TlvType; 2] = core::array::from_fn(|i| message.tlvs()[i].tlv_type);
+        assert_eq!(
+            types,
+            [
+                TlvType::OrganizationExtension,
+                TlvType::RequestUnicastTransmission
+            ]
+        );
+        assert_eq!(message.tlvs()[0].value.len(), 12);
+        assert_eq!(message.tlvs().len(), 2);
+        assert!(message
+            .unicast_negotiations()
+            .eq([UnicastNegotiation::Request {
+                message_type: MessageType::Announce,
+                log_interval: -3,
+                duration: 300,
+            }]));
+
+        let mut buffer = [0; 64];
+        let size = message.content_size();
+        assert_eq!(size, 36);
+        message.serialize_content(&mut buffer[..size]).unwrap();
+        let parsed = SignalingMessage::deserialize_content(Header::default(), &buffer[..size]);
+        assert_eq!(parsed.unwrap(), message);
+        assert!(matches!(
+            message.tlvs()[0].serialize(&mut buffer[..15]),
+            Err(WireFormatError::BufferTooShort)
+        ));
+    }
+}
@@ -43,7 +43,7 @@ impl From<EnumConversionError> for WireFormatError {
     }
 }
 
-trait WireFormat: Debug + Clone + Eq {
+pub(crate) trait WireFormat: Debug + Clone + Eq {
     /// The byte size on the wire of this object
     fn wire_size(&self) -> usize;
 
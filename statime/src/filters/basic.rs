@@ -1,9 +1,40 @@
 //! Implementation of [BasicFilter]
 
+use arrayvec::ArrayVec;
 use fixed::traits::LossyInto;
 
 use super::Filter;
-use crate::{port::Measurement, time::Duration};
+use crate::{log, port::Measurement, time::Duration};
+
+/// Maximum number of offsets the [BasicFilter] can take the median over
+const MAX_WINDOW: usize = 16;
+
+/// How precise the timestamps fed to the filter are expected to be.
+///
+/// This selects a set of filter parameters suitable for the given timestamp
+/// source, so users don't have to tune them individually.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TimestampingQuality {
+    /// Timestamps taken by the network hardware, with little noise
+    #[default]
+    Hardware,
+    /// Timestamps taken by the operating system in software. These are much
+    /// noisier, so the filter averages over a longer period and waits longer
+    /// before considering itself locked.
+    Software,
+}
+
+impl core::str::FromStr for TimestampingQuality {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hardware" => Ok(TimestampingQuality::Hardware),
+            "software" => Ok(TimestampingQuality::Software),
+            _ => Err("expected `hardware` or `software`"),
+        }
+    }
+}
 
 #[derive(Debug)]
 struct PrevStepData {
@@ -18,41 +49,102 @@ struct PrevStepData {
 #[derive(Debug)]
 pub struct BasicFilter {
     last_step: Option<PrevStepData>,
+    offsets: ArrayVec<Duration, MAX_WINDOW>,
+    consecutive_in_bounds: u32,
 
     offset_confidence: Duration,
     freq_confidence: f64,
 
     gain: f64,
+    window: usize,
+    step_threshold: Duration,
+    lock_after: u32,
+    startup_burst: bool,
 }
 
 impl BasicFilter {
     pub fn new(gain: f64) -> Self {
         Self {
             last_step: None,
+            offsets: ArrayVec::new(),
+            consecutive_in_bounds: 0,
             offset_confidence: Duration::from_nanos(1_000_000_000),
             freq_confidence: 1e-4,
             gain,
+            window: 1,
+            step_threshold: Duration::from_nanos(1_000_000_000),
+            lock_after: 1,
+            startup_burst: false,
         }
     }
+
+    /// Create a filter with parameters suitable for the given timestamp
+    /// quality.
+    ///
+    /// For software timestamps the offset is taken as the median over the
+    /// last 8 measurements, so a single delayed packet can neither cause a
+    /// step nor a large correction. Because the lower gain makes slewing away
+    /// large offsets slow, the clock is stepped from 100ms onwards instead of
+    /// from 1s.
+    pub fn for_quality(quality: TimestampingQuality) -> Self {
+        match quality {
+            TimestampingQuality::Hardware => Self::new(0.25),
+            TimestampingQuality::Software => Self {
+                window: 8,
+                step_threshold: Duration::from_millis(100),
+                lock_after: 16,
+                ..Self::new(0.1)
+            },
+        }
+    }
+
+    /// Whether the filter considers the clock to be locked to its master.
+    ///
+    /// This is the case once enough consecutive measurements have been within
+    /// the range the filter expects.
+    pub fn is_locked(&self) -> bool {
+        self.consecutive_in_bounds >= self.lock_after
+    }
+
+    fn filtered_offset(&mut self, offset: Duration) -> Duration {
+        // During a startup burst, follow the measurements without delay
+        let window = if self.startup_burst { 1 } else { self.window };
+        while self.offsets.len() >= window.min(MAX_WINDOW) {
+            self.offsets.remove(0);
+        }
+        self.offsets.push(offset);
+
+        let mut sorted = self.offsets.clone();
+        sorted.sort_unstable();
+        sorted[sorted.len() / 2]
+    }
 }
 
 impl Filter for BasicFilter {
     fn absorb(&mut self, measurement: Measurement) -> (Duration, f64) {
+        let mut offset = self.filtered_offset(measurement.master_offset);
+
         // Reset on too-large difference
-        if measurement.master_offset.abs() > Duration::from_nanos(1_000_000_000) {
-            log::debug!("Offset too large, stepping {}", measurement.master_offset);
+        if offset.abs() > self.step_threshold {
+            log::debug!("Offset too large, stepping {}", offset);
+            self.offsets.clear();
+            self.consecutive_in_bounds = 0;
             self.offset_confidence = Duration::from_nanos(1_000_000_000);
             self.freq_confidence = 1e-4;
-            return (-measurement.master_offset, 1.0);
+            return (-offset, 1.0);
         }
 
         // Determine offset
-        let mut offset = measurement.master_offset;
         if offset.abs() > self.offset_confidence {
             offset = offset.clamp(-self.offset_confidence, self.offset_confidence);
             self.offset_confidence *= 2i32;
+            self.consecutive_in_bounds = 0;
         } else {
             self.offset_confidence -= (self.offset_confidence - offset.abs()) * self.gain;
+            self.consecutive_in_bounds = self.consecutive_in_bounds.saturating_add(1);
+            if self.consecutive_in_bounds == self.lock_after {
+                log::info!("Filter locked to master");
+            }
         }
 
         // And decide it's correction
@@ -80,14 +172,14 @@ impl Filter for BasicFilter {
                     (self.freq_confidence - libm::fabs(freq_diff - 1.0)) * self.gain;
             }
 
-            // and decide the correction
-            1.0 + (freq_diff - 1.0) * self.gain * 0.1
+            // and decide the correction, slowing down a clock that runs fast
+            1.0 - (freq_diff - 1.0) * self.gain * 0.1
         } else {
             // No data, so no correction
             1.0
         };
 
-        log::info!(
+        log::debug!(
             "Offset to master: {:e}ns, corrected with phase change {:e}ns and freq change 1 + \
              {:e}x",
             measurement.master_offset.nanos(),
@@ -103,4 +195,112 @@ impl Filter for BasicFilter {
 
         (correction, freq_corr)
     }
+
+    fn set_startup_burst(&mut self, active: bool) {
+        self.startup_burst = active;
+    }
+
+    fn reset(&mut self) {
+        self.last_step = None;
+        self.offsets.clear();
+        self.consecutive_in_bounds = 0;
+        self.offset_confidence = Duration::from_nanos(1_000_000_000);
+        self.freq_confidence = 1e-4;
+    }
+
+    fn is_locked(&self) -> bool {
+        BasicFilter::is_locked(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{port::TimestampSource, time::Time};
+
+    fn measurement(secs: u64, offset: Duration) -> Measurement {
+        Measurement {
+            event_time: Time::from_secs(100 + secs),
+            raw_receive_time: Time::from_secs(100 + secs),
+            receive_time: Time::from_secs(100 + secs),
+            master_offset: offset,
+            timestamp_source: TimestampSource::Hardware,
+            correction: Default::default(),
+            follow_up_information: None,
+            neighbor_rate_ratio: None,
+        }
+    }
+
+    #[test]
+    fn test_software_ignores_single_outlier() {
+        let mut filter = BasicFilter::for_quality(TimestampingQuality::Software);
+
+        for i in 0..4 {
+            filter.absorb(measurement(i, Duration::from_micros(10)));
+        }
+
+        // A single badly delayed packet should not trigger a step
+        let (correction, _) = filter.absorb(measurement(4, Duration::from_millis(500)));
+        assert!(correction.abs() < Duration::from_millis(1));
+
+        // But the hardware settings step right away
+        let mut filter = BasicFilter::for_quality(TimestampingQuality::Hardware);
+        let (correction, freq) = filter.absorb(measurement(0, Duration::from_secs(2)));
+        assert_eq!(correction, -Duration::from_secs(2));
+        assert_eq!(freq, 1.0);
+    }
+
+    #[test]
+    fn test_frequency_correction_direction() {
+        let mut filter = BasicFilter::for_quality(TimestampingQuality::Hardware);
+        filter.absorb(measurement(0, Duration::ZERO));
+
+        // The local clock gained 10us on the master in a second
+        let (_, freq) = filter.absorb(measurement(1, Duration::from_micros(10)));
+        assert!(freq < 1.0);
+
+        let mut filter = BasicFilter::for_quality(TimestampingQuality::Hardware);
+        filter.absorb(measurement(0, Duration::ZERO));
+        let (_, freq) = filter.absorb(measurement(1, Duration::from_micros(-10)));
+        assert!(freq > 1.0);
+    }
+
+    #[test]
+    fn test_startup_burst_skips_median() {
+        let mut filter = BasicFilter::for_quality(TimestampingQuality::Software);
+        filter.set_startup_burst(true);
+
+        for i in 0..4 {
+            filter.absorb(measurement(i, Duration::from_micros(10)));
+        }
+
+        // Without the median, the last offset is used as is
+        let (correction, _) = filter.absorb(measurement(4, Duration::from_micros(50)));
+        assert_eq!(correction, -Duration::from_micros(50) * 0.1);
+
+        // After the burst, a single measurement is averaged out again
+        filter.set_startup_burst(false);
+        for i in 5..8 {
+            filter.absorb(measurement(i, Duration::from_micros(10)));
+        }
+        let (correction, _) = filter.absorb(measurement(8, Duration::from_micros(50)));
+        assert_eq!(correction, -Duration::from_micros(10) * 0.1);
+    }
+
+    #[test]
+    fn test_software_delays_lock() {
+        let mut hardware = BasicFilter::for_quality(TimestampingQuality::Hardware);
+        let mut software = BasicFilter::for_quality(TimestampingQuality::Software);
+
+        for i in 0..16 {
+            assert_eq!(software.is_locked(), i >= 16);
+            hardware.absorb(measurement(i, Duration::from_micros(10)));
+            software.absorb(measurement(i, Duration::from_micros(10)));
+            assert!(hardware.is_locked());
+        }
+        assert!(software.is_locked());
+
+        software.reset();
+        assert!(!software.is_locked());
+    }
 }
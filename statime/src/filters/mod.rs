@@ -22,4 +22,27 @@ pub trait Filter {
     /// the master time. To prevent overshooting, oscillating, etc, the
     /// filter is allowed to apply some algorithms to prevent that.
     fn absorb(&mut self, m: Measurement) -> (Duration, f64);
+
+    /// Forget all state built up from previous measurements.
+    ///
+    /// This is called when the source of the timestamps changes (for example
+    /// on a bonding failover to an interface with a different PHC), after
+    /// which earlier measurements no longer relate to the new measurements.
+    /// The default keeps the state, so filters that keep any should
+    /// implement this.
+    fn reset(&mut self) {}
+
+    /// Tell the filter whether the port it gets measurements from is in its
+    /// [startup burst](crate::StartupBurst). Called before each measurement
+    /// is absorbed.
+    ///
+    /// Measurements come in faster during the burst, so the filter may
+    /// follow them more closely to converge sooner. The default ignores this.
+    fn set_startup_burst(&mut self, _active: bool) {}
+
+    /// Whether the filter considers the clock to be locked to its master, so
+    /// its frequency correction can be relied on. The default never does.
+    fn is_locked(&self) -> bool {
+        false
+    }
 }
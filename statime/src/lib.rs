@@ -20,6 +20,22 @@
 //! implementations of these interfaces. For other platforms the user will need
 //! to implement these themselves.
 //!
+//! # Bounded processing
+//!
+//! Handling a packet takes a bounded amount of work, so ports can be driven
+//! from real-time loops. A message is parsed once, reading no further than the
+//! length in its header and examining at most [`MAX_MESSAGE_TLVS`] TLVs. This
+//! holds for every type of message: of a path trace only the clocks that are
+//! kept are read, and the AUTHENTICATION TLV is found from the end of the
+//! message, though its ICV covers the whole message. The lists a port
+//! consults per packet have a fixed capacity, like its foreign masters, its
+//! unicast grants (at most the size of the table given to
+//! [`Port::set_unicast_master`]), its replay windows (at most the size of the
+//! table given to [`Port::set_authentication`]) and its quirk rules (at most
+//! [`MAX_QUIRK_RULES`]). Nothing allocates or recurses, and metrics whose cost
+//! grows with their configuration, like those of [`TimeErrorStatistics`], are
+//! only computed when asked for.
+//!
 //! # Clock identities
 //!
 //! All ptp clocks in a network need a unique clock identity. One way to achieve
@@ -39,24 +55,74 @@ extern crate std;
 mod bmc;
 mod clock;
 mod config;
+mod cross_check;
 mod datastructures;
 mod filters;
+mod log;
 mod port;
 mod ptp_instance;
+mod scanner;
+#[cfg(feature = "snapshot")]
+mod snapshot;
 mod time;
+#[cfg(feature = "time-transfer")]
+pub mod time_transfer;
 
+pub use bmc::{
+    ComparisonDataset, DatasetOrdering, DefaultMasterSelection, MasterSelection,
+    TelecomMasterSelection,
+};
 pub use clock::Clock;
-pub use config::{DelayMechanism, InstanceConfig, PortConfig};
+pub use config::{
+    CmldsConfig, CommunicationMode, DelayMechanism, FixedMasterConfig, FixedMasterConfigError,
+    GptpConfig, IdentityCollisionResponse, InstanceConfig, InstanceConfigError, IntervalBounds,
+    IntervalField, LogMessageIntervals, ManagementPolicy, PathDirection, PortConfig,
+    PortConfigError, PriorityBounds, Profile, QuirkConfigError, QuirkMatch, QuirkRule, Quirks,
+    Role, RolePreset, SimulatedPath, StartupBurst, TransmitEnable, UnicastClientConfig,
+    UnicastConfigError, UnicastMasterConfig, MAX_QUIRK_RULES, MAX_UNICAST_MASTERS,
+};
+pub use cross_check::{CrossCheckDomain, CrossCheckEvent, DomainCrossCheck};
 #[cfg(feature = "fuzz")]
 pub use datastructures::messages::FuzzMessage;
 pub use datastructures::{
-    common::{ClockAccuracy, ClockIdentity, ClockQuality, LeapIndicator, TimeSource},
-    datasets::TimePropertiesDS,
-    messages::{SdoId, MAX_DATA_LEN},
+    common::{
+        ClockAccuracy, ClockClass, ClockIdentity, ClockQuality, FollowUpInformation, LeapIndicator,
+        ProfileIdentifier, TimeSource, TlvType,
+    },
+    datasets::{LeapSecond, TimePropertiesDS, TimePropertiesError},
+    messages::{
+        decode_message, route_packet, DecodeError, DecodedAnnounce, DecodedMessage,
+        DecodedSignaling, MessageType, PacketRoute, SdoId, UnicastNegotiation, MAX_DATA_LEN,
+        MAX_MESSAGE_TLVS,
+    },
+};
+pub use filters::{
+    basic::{BasicFilter, TimestampingQuality},
+    Filter,
 };
-pub use filters::{basic::BasicFilter, Filter};
+#[cfg(feature = "testing")]
+pub use port::TestPortState;
 pub use port::{
-    InBmca, Measurement, Port, PortAction, PortActionIterator, Running, TimestampContext,
+    AnnounceContent, AuthenticationConfig, AuthenticationConfigError, AuthenticationFailureAction,
+    AuthenticationFailures, Calibration, CalibrationStore, CalibrationStoreError, CmldsLinkPort,
+    CorrectionBreakdown, DelayRespRejections, Destination, Diagnostic, DurationStatistics,
+    FailedSend, FrequencyCorrection, FrequencyStatistics, InBmca, IntegrityAlgorithm, KeyTable,
+    LinkDelay, Measurement, MessageRate, MessageRates, MessageTypeRates, OrganizationExtension,
+    OrganizationTlv, OrganizationTlvError, OrganizationTlvWriter, PacketArrival, PacketMatch, Port,
+    PortAction, PortActionIterator, PortEvent, PortInput, PortStateKind, PortStatistics,
+    QuirkCounts, ReplayWindowSlot, Running, SecurityKey, SecurityProvider, SendError,
+    SourceAddress, StatisticsWindow, StatisticsWindows, TimeErrorConfigError, TimeErrorMetrics,
+    TimeErrorStatistics, Timeline, TimelineEntry, TimelineEvent, TimestampContext, TimestampSource,
+    TimestampSourceCounts, UnicastGrantCounts, UnicastGrantSlot, UnicastRequestCounts,
+    UnicastSyncClient, EVENT_QUEUE_CAPACITY, FREQUENCY_HISTORY_CAPACITY, FREQUENCY_PERIOD_SECONDS,
+    MAX_ICV_LENGTH, MAX_OBSERVATION_INTERVALS, MEASUREMENT_QUEUE_CAPACITY, REPLAY_TIMEOUT_SECONDS,
+    REPLAY_WINDOW, STATISTICS_WINDOW_HISTORY, TIMELINE_CAPACITY,
+};
+pub use ptp_instance::{InstanceBusy, InstanceStatus, PtpInstance};
+pub use scanner::{
+    InferredProfile, NetworkScanner, ScannedDomain, ScannedMaster, Transport, MAX_SCANNED_DOMAINS,
+    MAX_SCANNED_MASTERS,
 };
-pub use ptp_instance::PtpInstance;
+#[cfg(feature = "snapshot")]
+pub use snapshot::{InstanceSnapshot, SnapshotError};
 pub use time::{Duration, Interval, Time};
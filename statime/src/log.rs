@@ -0,0 +1,135 @@
+//! Logging macros used throughout statime.
+//!
+//! Modules import this module as `log`, so they keep using `log::debug!` and
+//! friends. Normally these forward to the macros of the `log` crate. With the
+//! `silent` feature they expand to dead code instead, so neither the messages
+//! nor the formatting code end up in the binary. Problems worth reporting are
+//! then surfaced as [`Diagnostic`](crate::Diagnostic) events.
+//!
+//! Messages logged for every packet, such as received Sync messages and
+//! extracted measurements, use the debug and trace levels. At higher levels
+//! handling a packet logs nothing, so no formatting happens on that path. The
+//! `high-rate` feature compiles out the debug and trace messages entirely,
+//! for targets where even checking the level for each message costs too
+//! much. Warnings that a peer can trigger with every packet, like those about
+//! unexpected messages, name the message type instead of formatting the whole
+//! message with its `Debug` implementation. The `packet` benchmark measures
+//! the cost of that path.
+//!
+//! Messages about a single port start with `port: <port identity>`, as in
+//! `log::warn!(port: self.port_identity, "Unexpected message")`. These are
+//! logged with the target `statime::port<N>`, where `N` is the port number,
+//! and carry the `port_number` and `clock_identity` of the port as key-value
+//! pairs. That way, the logs of a multi-port instance can be filtered per
+//! port. Messages without a port use the module path as target, as usual.
+
+#[cfg(not(feature = "silent"))]
+use core::fmt::Write;
+
+#[cfg(not(feature = "silent"))]
+use arrayvec::ArrayString;
+
+/// The log target of the messages about a port, `statime::port<N>`
+#[cfg(not(feature = "silent"))]
+pub(crate) fn port_target(port_number: u16) -> ArrayString<24> {
+    let mut target = ArrayString::new();
+    // At most 13 + 5 characters, so this always fits
+    let _ = write!(target, "statime::port{port_number}");
+    target
+}
+
+#[cfg(not(feature = "silent"))]
+macro_rules! port_log {
+    ($level:expr, $port:expr, $($arg:tt)+) => {{
+        let level = $level;
+        // Check the level first, so the target is only built when needed
+        if level <= ::log::STATIC_MAX_LEVEL && level <= ::log::max_level() {
+            let port = &$port;
+            let target = $crate::log::port_target(port.port_number);
+            ::log::log!(
+                target: target.as_str(),
+                level,
+                port_number = port.port_number,
+                clock_identity:% = port.clock_identity;
+                $($arg)+
+            );
+        }
+    }};
+}
+
+#[cfg(not(feature = "silent"))]
+macro_rules! log_error {
+    (port: $port:expr, $($arg:tt)+) => {
+        $crate::log::port_log!(::log::Level::Error, $port, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        ::log::error!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "silent"))]
+macro_rules! log_warn {
+    (port: $port:expr, $($arg:tt)+) => {
+        $crate::log::port_log!(::log::Level::Warn, $port, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        ::log::warn!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "silent"))]
+macro_rules! log_info {
+    (port: $port:expr, $($arg:tt)+) => {
+        $crate::log::port_log!(::log::Level::Info, $port, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        ::log::info!($($arg)+)
+    };
+}
+
+#[cfg(not(any(feature = "silent", feature = "high-rate")))]
+macro_rules! log_debug {
+    (port: $port:expr, $($arg:tt)+) => {
+        $crate::log::port_log!(::log::Level::Debug, $port, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        ::log::debug!($($arg)+)
+    };
+}
+
+#[cfg(not(any(feature = "silent", feature = "high-rate")))]
+macro_rules! log_trace {
+    (port: $port:expr, $($arg:tt)+) => {
+        $crate::log::port_log!(::log::Level::Trace, $port, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        ::log::trace!($($arg)+)
+    };
+}
+
+#[cfg(not(any(feature = "silent", feature = "high-rate")))]
+pub(crate) use {log_debug as debug, log_trace as trace};
+#[cfg(not(feature = "silent"))]
+pub(crate) use {log_error as error, log_info as info, log_warn as warn, port_log};
+
+#[cfg(any(feature = "silent", feature = "high-rate"))]
+macro_rules! discard {
+    (port: $port:expr, $($arg:tt)+) => {
+        if false {
+            let _ = &$port;
+            let _ = core::format_args!($($arg)+);
+        }
+    };
+    ($($arg:tt)+) => {
+        // Type check the arguments, so the code compiles the same with or
+        // without the feature, but never evaluate them
+        if false {
+            let _ = core::format_args!($($arg)+);
+        }
+    };
+}
+
+#[cfg(any(feature = "silent", feature = "high-rate"))]
+pub(crate) use {discard as debug, discard as trace};
+#[cfg(feature = "silent")]
+pub(crate) use {discard as error, discard as info, discard as warn};
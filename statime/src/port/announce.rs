@@ -0,0 +1,218 @@
+use crate::{
+    datastructures::{
+        common::{ClockIdentity, ClockQuality, LeapIndicator, TimeSource},
+        messages::AnnounceMessage,
+    },
+    time::Time,
+};
+
+/// The content of an announce message a port sent, see
+/// [`PortStatistics::last_announce`](crate::PortStatistics::last_announce)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnnounceContent {
+    /// The sequence id of the message
+    pub sequence_id: u16,
+    /// The time the message was sent, as carried in the message
+    pub origin_timestamp: Time,
+    /// Whether the message went to a single unicast client
+    pub unicast: bool,
+    /// The leap second announced for the end of the day
+    pub leap_indicator: LeapIndicator,
+    /// The UTC offset, if it was announced as valid
+    pub current_utc_offset: Option<i16>,
+    /// Whether the grandmaster uses the PTP timescale rather than an
+    /// arbitrary one
+    pub ptp_timescale: bool,
+    /// Whether the time of the grandmaster is traceable to a primary
+    /// reference
+    pub time_traceable: bool,
+    /// Whether the frequency of the grandmaster is traceable to a primary
+    /// reference
+    pub frequency_traceable: bool,
+    /// The source of the time of the grandmaster
+    pub time_source: TimeSource,
+    /// The clock identity of the grandmaster
+    pub grandmaster_identity: ClockIdentity,
+    /// The priority 1 of the grandmaster, lower is preferred
+    pub grandmaster_priority_1: u8,
+    /// The clock class, accuracy and variance of the grandmaster
+    pub grandmaster_clock_quality: ClockQuality,
+    /// The priority 2 of the grandmaster, lower is preferred
+    pub grandmaster_priority_2: u8,
+    /// The number of links between the grandmaster and the sender, zero when
+    /// the sender is the grandmaster
+    pub steps_removed: u16,
+}
+
+impl AnnounceContent {
+    pub(crate) fn new(message: &AnnounceMessage) -> Self {
+        let header = &message.header;
+        let leap_indicator = match (header.leap61, header.leap59) {
+            (true, _) => LeapIndicator::Leap61,
+            (false, true) => LeapIndicator::Leap59,
+            (false, false) => LeapIndicator::NoLeap,
+        };
+
+        AnnounceContent {
+            sequence_id: header.sequence_id,
+            origin_timestamp: message.origin_timestamp.into(),
+            unicast: header.unicast_flag,
+            leap_indicator,
+            current_utc_offset: header
+                .current_utc_offset_valid
+                .then_some(message.current_utc_offset),
+            ptp_timescale: header.ptp_timescale,
+            time_traceable: header.time_tracable,
+            frequency_traceable: header.frequency_tracable,
+            time_source: message.time_source,
+            grandmaster_identity: message.grandmaster_identity,
+            grandmaster_priority_1: message.grandmaster_priority_1,
+            grandmaster_clock_quality: message.grandmaster_clock_quality,
+            grandmaster_priority_2: message.grandmaster_priority_2,
+            steps_removed: message.steps_removed,
+        }
+    }
+
+    /// The field of `next` that changed compared to this announce message
+    /// while nothing could have changed it, if any. Both must be sent by the
+    /// same port during a single period as master, by a clock with identity
+    /// `own_identity`.
+    ///
+    /// A change like that means the datasets the announce messages are built
+    /// from were corrupted.
+    pub(crate) fn unexplained_change(
+        &self,
+        next: &AnnounceContent,
+        own_identity: ClockIdentity,
+    ) -> Option<&'static str> {
+        // Every unicast client gets its own sequence of announce messages
+        let unicast = self.unicast || next.unicast;
+        if !unicast && next.sequence_id != self.sequence_id.wrapping_add(1) {
+            return Some("sequence id");
+        }
+
+        if self.grandmaster_identity != next.grandmaster_identity {
+            // A new grandmaster can change everything else
+            return None;
+        }
+
+        // Our own clock quality and priority 2 are fixed, and we are always
+        // 0 steps removed from ourselves. Only priority 1 can be changed.
+        if next.grandmaster_identity == own_identity {
+            if next.grandmaster_clock_quality != self.grandmaster_clock_quality {
+                return Some("grandmaster clock quality");
+            }
+            if next.grandmaster_priority_2 != self.grandmaster_priority_2 {
+                return Some("grandmaster priority 2");
+            }
+            if next.steps_removed != self.steps_removed {
+                return Some("steps removed");
+            }
+        }
+
+        // The UTC offset of a grandmaster changes with a leap second, which
+        // it announces beforehand
+        if let (Some(previous), Some(current)) = (self.current_utc_offset, next.current_utc_offset)
+        {
+            if previous != current && self.leap_indicator == LeapIndicator::NoLeap {
+                return Some("current UTC offset");
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClockAccuracy;
+
+    const OWN: ClockIdentity = ClockIdentity([1; 8]);
+
+    fn content() -> AnnounceContent {
+        AnnounceContent {
+            sequence_id: 7,
+            origin_timestamp: Time::from_secs(100),
+            unicast: false,
+            leap_indicator: LeapIndicator::NoLeap,
+            current_utc_offset: Some(37),
+            ptp_timescale: true,
+            time_traceable: false,
+            frequency_traceable: false,
+            time_source: TimeSource::InternalOscillator,
+            grandmaster_identity: OWN,
+            grandmaster_priority_1: 128,
+            grandmaster_clock_quality: ClockQuality::default(),
+            grandmaster_priority_2: 128,
+            steps_removed: 0,
+        }
+    }
+
+    #[test]
+    fn consecutive_announce_changes() {
+        let previous = content();
+        let next = AnnounceContent {
+            sequence_id: 8,
+            origin_timestamp: Time::from_secs(101),
+            grandmaster_priority_1: 64,
+            ..previous
+        };
+        assert_eq!(previous.unexplained_change(&next, OWN), None);
+
+        let skipped = AnnounceContent {
+            sequence_id: 9,
+            ..next
+        };
+        assert_eq!(
+            previous.unexplained_change(&skipped, OWN),
+            Some("sequence id")
+        );
+        let unicast = AnnounceContent {
+            unicast: true,
+            ..skipped
+        };
+        assert_eq!(previous.unexplained_change(&unicast, OWN), None);
+        assert_eq!(unicast.unexplained_change(&previous, OWN), None);
+
+        let quality = AnnounceContent {
+            grandmaster_clock_quality: ClockQuality {
+                clock_accuracy: ClockAccuracy::NS25,
+                ..ClockQuality::default()
+            },
+            ..next
+        };
+        assert_eq!(
+            previous.unexplained_change(&quality, OWN),
+            Some("grandmaster clock quality")
+        );
+        // Another grandmaster can change its quality at any time
+        assert_eq!(
+            previous.unexplained_change(&quality, ClockIdentity([2; 8])),
+            None
+        );
+
+        let offset = AnnounceContent {
+            current_utc_offset: Some(38),
+            ..next
+        };
+        assert_eq!(
+            previous.unexplained_change(&offset, OWN),
+            Some("current UTC offset")
+        );
+        let leap = AnnounceContent {
+            leap_indicator: LeapIndicator::Leap61,
+            ..previous
+        };
+        assert_eq!(leap.unexplained_change(&offset, OWN), None);
+
+        // A new grandmaster changes everything
+        let new_grandmaster = AnnounceContent {
+            grandmaster_identity: ClockIdentity([2; 8]),
+            steps_removed: 3,
+            current_utc_offset: Some(10),
+            ..next
+        };
+        assert_eq!(previous.unexplained_change(&new_grandmaster, OWN), None);
+    }
+}
@@ -0,0 +1,554 @@
+//! The AUTHENTICATION TLV of IEEE1588-2019 section 16.14 and annex P, see
+//! [`Port::set_authentication`](crate::Port::set_authentication)
+
+use arrayvec::ArrayVec;
+use atomic_refcell::AtomicRefCell;
+
+use super::{statistics::AuthenticationFailures, PortAction, PortActionIterator};
+use crate::{
+    datastructures::{
+        common::{PortIdentity, Tlv, TlvType},
+        messages::MAX_DATA_LEN,
+    },
+    log,
+    ptp_instance::PtpInstanceState,
+    time::{Duration, Time},
+    Clock,
+};
+
+/// The algorithm that computes the integrity check value (ICV) of a message
+/// from a key, for example HMAC-SHA256 truncated to 128 bits (IEEE1588-2019
+/// annex P.2).
+///
+/// Statime doesn't implement any algorithm itself, so one needs to be
+/// provided, for example from a cryptography crate.
+pub trait IntegrityAlgorithm: core::fmt::Debug + Sync {
+    /// The length of the ICV in bytes
+    fn icv_length(&self) -> usize;
+
+    /// Compute the ICV of `data` with `key` into `icv`, which is
+    /// [`icv_length`](Self::icv_length) bytes long
+    fn compute_icv(&self, key: &[u8], data: &[u8], icv: &mut [u8]);
+}
+
+/// A key of a security association, with the local times between which it
+/// is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityKey<'a> {
+    pub key_id: u32,
+    pub material: &'a [u8],
+    /// The key is not used before this time, if set
+    pub valid_from: Option<Time>,
+    /// The key is not used after this time, if set
+    pub valid_until: Option<Time>,
+}
+
+impl SecurityKey<'_> {
+    /// Whether the key may be used at `time`
+    pub fn is_valid_at(&self, time: Time) -> bool {
+        self.valid_from.is_none_or(|from| from <= time)
+            && self.valid_until.is_none_or(|until| time <= until)
+    }
+}
+
+/// Supplies the keys of the AUTHENTICATION TLV (IEEE1588-2019 section 16.14),
+/// as set up by a key management protocol or by hand.
+///
+/// Keys are rolled over without losing messages by letting the lifetimes of
+/// the old and the new key overlap. Received messages are accepted with any
+/// key that is valid, so ports that switch to the new key a little earlier
+/// or later than others are still understood. Lifetimes are compared with the
+/// local clock, so they should be left open while it may be far off, like
+/// before a slave is first synchronized.
+///
+/// It takes `&self` as the provider can be shared between ports, so changing
+/// keys at runtime needs interior mutability. A fixed set of keys can use
+/// [`KeyTable`].
+pub trait SecurityProvider: core::fmt::Debug + Sync {
+    /// The key with the given id, whether or not it is valid now
+    fn key(&self, key_id: u32) -> Option<SecurityKey<'_>>;
+
+    /// The key to send messages with at `now`
+    fn send_key(&self, now: Time) -> Option<SecurityKey<'_>>;
+}
+
+/// A [`SecurityProvider`] with a fixed set of keys, which can be a `static`.
+/// Messages are sent with the valid key that became valid last.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyTable<'a> {
+    keys: &'a [SecurityKey<'a>],
+}
+
+impl<'a> KeyTable<'a> {
+    pub const fn new(keys: &'a [SecurityKey<'a>]) -> Self {
+        Self { keys }
+    }
+}
+
+impl SecurityProvider for KeyTable<'_> {
+    fn key(&self, key_id: u32) -> Option<SecurityKey<'_>> {
+        self.keys.iter().find(|key| key.key_id == key_id).copied()
+    }
+
+    fn send_key(&self, now: Time) -> Option<SecurityKey<'_>> {
+        self.keys
+            .iter()
+            .filter(|key| key.is_valid_at(now))
+            .max_by_key(|key| key.valid_from)
+            .copied()
+    }
+}
+
+/// The longest ICV an [`IntegrityAlgorithm`] may produce
+pub const MAX_ICV_LENGTH: usize = 64;
+
+/// What a port does with received messages that fail authentication
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AuthenticationFailureAction {
+    /// Ignore the message
+    #[default]
+    Drop,
+    /// Handle the message anyway. Failures are still counted, which helps
+    /// to roll out authentication without losing synchronization.
+    Accept,
+}
+
+/// How a port authenticates the messages it sends and receives
+#[derive(Debug, Clone, Copy)]
+pub struct AuthenticationConfig {
+    pub algorithm: &'static dyn IntegrityAlgorithm,
+    pub keys: &'static dyn SecurityProvider,
+    /// The security parameters pointer, which identifies the security
+    /// association in the TLV. Received messages must carry the same one.
+    pub spp: u8,
+    pub on_failure: AuthenticationFailureAction,
+    /// Reject authenticated messages whose sequence id was seen before, or
+    /// that are more than [`REPLAY_WINDOW`] behind the latest of their
+    /// source, so recorded messages can't be sent again.
+    ///
+    /// Only the sequence ids a source picks itself are tracked, that is
+    /// those of sync, follow up, announce, (peer) delay request and
+    /// signaling messages. Responses carry the id of the request, which the
+    /// port already matches with its own requests. A source that restarts its
+    /// sequence ids is accepted again once it was quiet for
+    /// [`REPLAY_TIMEOUT_SECONDS`].
+    ///
+    /// The sequence ids are kept in the table passed to
+    /// [`Port::set_authentication`](crate::Port::set_authentication), see
+    /// [`ReplayWindowSlot`].
+    pub replay_protection: bool,
+}
+
+/// How many sequence ids behind the latest one a message may be, with
+/// [`AuthenticationConfig::replay_protection`]
+pub const REPLAY_WINDOW: u16 = 64;
+
+/// Seconds after which the sequence ids of a source are forgotten when none
+/// of its messages were accepted, measured with
+/// [`Clock::monotonic_now`](crate::Clock::monotonic_now)
+pub const REPLAY_TIMEOUT_SECONDS: i64 = 10;
+
+/// Room for the sequence ids of one message type of one source, see
+/// [`AuthenticationConfig::replay_protection`].
+///
+/// A port keeps the sequence ids it saw in a table of these provided by the
+/// user, so the memory used for them is chosen with the size of that table.
+/// Without the standard library, the table can be a `static`:
+///
+/// ```
+/// # use statime::ReplayWindowSlot;
+/// static mut REPLAY_WINDOWS: [ReplayWindowSlot; 32] = [ReplayWindowSlot::EMPTY; 32];
+/// ```
+#[derive(Debug)]
+pub struct ReplayWindowSlot {
+    window: Option<ReplayWindow>,
+}
+
+impl ReplayWindowSlot {
+    /// A slot without sequence ids, to initialize tables with
+    pub const EMPTY: Self = Self { window: None };
+}
+
+impl Default for ReplayWindowSlot {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+/// Why an [`AuthenticationConfig`] can't be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthenticationConfigError {
+    /// The ICV has an odd length, which TLVs can't have
+    OddIcvLength(usize),
+    /// The ICV is longer than [`MAX_ICV_LENGTH`]
+    IcvTooLong(usize),
+    /// Replay protection is enabled, but the table to keep the sequence ids
+    /// in is empty
+    NoReplayWindows,
+}
+
+impl core::fmt::Display for AuthenticationConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::OddIcvLength(length) => {
+                write!(f, "the ICV has an odd length of {length} bytes")
+            }
+            Self::IcvTooLong(length) => write!(
+                f,
+                "the ICV of {length} bytes is longer than {MAX_ICV_LENGTH} bytes"
+            ),
+            Self::NoReplayWindows => {
+                write!(f, "replay protection needs room for at least one source")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AuthenticationConfigError {}
+
+impl AuthenticationConfig {
+    pub(crate) fn validate(
+        &self,
+        replay_windows: &[ReplayWindowSlot],
+    ) -> Result<(), AuthenticationConfigError> {
+        let icv_length = self.algorithm.icv_length();
+        if !icv_length.is_multiple_of(2) {
+            return Err(AuthenticationConfigError::OddIcvLength(icv_length));
+        }
+        if icv_length > MAX_ICV_LENGTH {
+            return Err(AuthenticationConfigError::IcvTooLong(icv_length));
+        }
+        if self.replay_protection && replay_windows.is_empty() {
+            return Err(AuthenticationConfigError::NoReplayWindows);
+        }
+        Ok(())
+    }
+}
+
+// The type and length of the TLV, followed by the SPP, the secParamIndicator
+// and the keyID
+const TLV_HEADER_LENGTH: usize = 10;
+
+/// The authentication of a port, with the buffer the messages it sends are
+/// sealed in
+#[derive(Debug)]
+pub(crate) struct Authenticator {
+    config: Option<AuthenticationConfig>,
+    buffer: [u8; MAX_DATA_LEN],
+    replay_windows: &'static mut [ReplayWindowSlot],
+}
+
+impl Authenticator {
+    pub(crate) fn new() -> Self {
+        Self {
+            config: None,
+            buffer: [0; MAX_DATA_LEN],
+            replay_windows: &mut [],
+        }
+    }
+
+    pub(crate) fn set_config(
+        &mut self,
+        config: Option<AuthenticationConfig>,
+        replay_windows: &'static mut [ReplayWindowSlot],
+    ) {
+        self.config = config;
+        self.replay_windows = replay_windows;
+        for slot in self.replay_windows.iter_mut() {
+            slot.window = None;
+        }
+    }
+
+    /// Add an AUTHENTICATION TLV to the message the actions send. The sealed
+    /// message is a copy, as the original borrows the buffer of the port.
+    /// Sends that can't be sealed are left out.
+    pub(crate) fn seal<'a>(
+        &'a mut self,
+        actions: PortActionIterator<'a>,
+        local_clock: &AtomicRefCell<impl Clock>,
+        port_identity: PortIdentity,
+        failures: &mut AuthenticationFailures,
+    ) -> PortActionIterator<'a> {
+        let Some(config) = self.config else {
+            return actions;
+        };
+        let key = config.keys.send_key(local_clock.borrow().now());
+
+        let mut buffer = Some(&mut self.buffer);
+        let mut sealed = ArrayVec::new();
+        for mut action in actions {
+            if let Some(data) = action.data_mut() {
+                // Every set of actions sends at most one message, a second
+                // one can't be sealed
+                debug_assert!(buffer.is_some(), "more than one message to seal");
+                let Some(length) = buffer
+                    .as_deref_mut()
+                    .zip(key)
+                    .and_then(|(buffer, key)| seal_message(&config, key, buffer, data))
+                else {
+                    log::error!(
+                        port: port_identity,
+                        "Could not add the authentication TLV, not sending message"
+                    );
+                    failures.unsealed = failures.unsealed.saturating_add(1);
+                    continue;
+                };
+                if let Some(buffer) = buffer.take() {
+                    *data = &buffer[..length];
+                }
+            }
+            sealed.push(action);
+        }
+
+        PortActionIterator::from(sealed)
+    }
+
+    /// Whether the received message in `data` should be handled, counting
+    /// it if it fails authentication
+    pub(crate) fn verify<C: Clock, F>(
+        &mut self,
+        data: &[u8],
+        state: &PtpInstanceState<C, F>,
+        port_identity: PortIdentity,
+        failures: &mut AuthenticationFailures,
+    ) -> bool {
+        let Some(config) = self.config else {
+            return true;
+        };
+
+        let now = state.local_clock.borrow().now();
+        let failure = match check_message(&config, now, data) {
+            Ok(()) if !config.replay_protection => return true,
+            Ok(()) => match self.check_replay(state.monotonic_now(), data, failures) {
+                Ok(()) => return true,
+                Err(failure) => failure,
+            },
+            Err(failure) => failure,
+        };
+        log::debug!(
+            port: port_identity,
+            "Message failed authentication: {:?}",
+            failure
+        );
+        let count = match failure {
+            Failure::Missing => &mut failures.missing,
+            Failure::Unknown => &mut failures.unknown,
+            Failure::Invalid => &mut failures.invalid,
+            Failure::Replayed => &mut failures.replayed,
+            Failure::OutsideWindow => &mut failures.outside_window,
+        };
+        *count = count.saturating_add(1);
+
+        config.on_failure == AuthenticationFailureAction::Accept
+    }
+
+    // Check the sequence id of an authentic message against those seen from
+    // its source before. Times are monotonic, so steps of the local clock
+    // don't expire windows early or keep them around.
+    fn check_replay(
+        &mut self,
+        now: Time,
+        data: &[u8],
+        failures: &mut AuthenticationFailures,
+    ) -> Result<(), Failure> {
+        // check_message made sure the header is there
+        let message_type = data[0] & 0x0f;
+        if !ReplayWindow::TRACKED_TYPES.contains(&message_type) {
+            return Ok(());
+        }
+        let mut source = [0; 10];
+        source.copy_from_slice(&data[20..30]);
+        let sequence_id = u16::from_be_bytes([data[30], data[31]]);
+
+        let timeout = Duration::from_secs(REPLAY_TIMEOUT_SECONDS);
+        for slot in self.replay_windows.iter_mut() {
+            if slot
+                .window
+                .as_ref()
+                .is_some_and(|window| now - window.last_accepted > timeout)
+            {
+                slot.window = None;
+            }
+        }
+
+        if let Some(window) = self
+            .replay_windows
+            .iter_mut()
+            .filter_map(|slot| slot.window.as_mut())
+            .find(|window| window.source == source && window.message_type == message_type)
+        {
+            window.accept(sequence_id)?;
+            window.last_accepted = now;
+            return Ok(());
+        }
+
+        // A new source takes a free slot, or that of the source that was
+        // quiet the longest
+        let Some(slot) = self
+            .replay_windows
+            .iter_mut()
+            .min_by_key(|slot| slot.window.as_ref().map(|window| window.last_accepted))
+        else {
+            // validate made sure there is a table
+            return Ok(());
+        };
+        if slot.window.is_some() {
+            failures.replay_evictions = failures.replay_evictions.saturating_add(1);
+        }
+        slot.window = Some(ReplayWindow {
+            source,
+            message_type,
+            latest: sequence_id,
+            seen: 1,
+            last_accepted: now,
+        });
+
+        Ok(())
+    }
+}
+
+// The sequence ids seen from a source for one message type
+#[derive(Debug)]
+struct ReplayWindow {
+    // Port identity of the source, as sent
+    source: [u8; 10],
+    message_type: u8,
+    latest: u16,
+    // Bit n is set when latest - n was seen
+    seen: u64,
+    last_accepted: Time,
+}
+
+impl ReplayWindow {
+    // Sync, delay request, peer delay request, follow up, announce and
+    // signaling
+    const TRACKED_TYPES: [u8; 6] = [0x0, 0x1, 0x2, 0x8, 0xb, 0xc];
+
+    fn accept(&mut self, sequence_id: u16) -> Result<(), Failure> {
+        let ahead = sequence_id.wrapping_sub(self.latest);
+        if ahead != 0 && ahead < 0x8000 {
+            self.seen = self.seen.checked_shl(ahead as u32).unwrap_or(0) | 1;
+            self.latest = sequence_id;
+            return Ok(());
+        }
+
+        let behind = self.latest.wrapping_sub(sequence_id);
+        if behind >= REPLAY_WINDOW {
+            Err(Failure::OutsideWindow)
+        } else if self.seen & (1 << behind) != 0 {
+            Err(Failure::Replayed)
+        } else {
+            self.seen |= 1 << behind;
+            Ok(())
+        }
+    }
+}
+
+impl<'a> PortAction<'a> {
+    // The message of a send action
+    fn data_mut(&mut self) -> Option<&mut &'a [u8]> {
+        match self {
+            PortAction::SendTimeCritical { data, .. }
+            | PortAction::SendGeneral { data }
+            | PortAction::SendUnicastGeneral { data, .. }
+            | PortAction::SendUnicastTimeCritical { data, .. }
+            | PortAction::SendToUnicastMaster { data, .. } => Some(data),
+            PortAction::ResetAnnounceTimer { .. }
+            | PortAction::ResetSyncTimer { .. }
+            | PortAction::ResetDelayRequestTimer { .. }
+            | PortAction::ResetAnnounceReceiptTimer { .. }
+            | PortAction::ResetUnicastNegotiationTimer { .. }
+            | PortAction::ResetStatisticsTimer { .. } => None,
+        }
+    }
+}
+
+// Copy `message` into `buffer` with an AUTHENTICATION TLV at the end,
+// returning the length of the sealed message
+fn seal_message(
+    config: &AuthenticationConfig,
+    key: SecurityKey<'_>,
+    buffer: &mut [u8],
+    message: &[u8],
+) -> Option<usize> {
+    let icv_length = config.algorithm.icv_length();
+    let length = message.len() + TLV_HEADER_LENGTH + icv_length;
+    let buffer = buffer.get_mut(..length)?;
+
+    let (message_buffer, tlv) = buffer.split_at_mut(message.len());
+    message_buffer.copy_from_slice(message);
+    tlv[0..2].copy_from_slice(&TlvType::Authentication.to_primitive().to_be_bytes());
+    tlv[2..4].copy_from_slice(&((TLV_HEADER_LENGTH - 4 + icv_length) as u16).to_be_bytes());
+    tlv[4] = config.spp;
+    // No optional fields
+    tlv[5] = 0;
+    tlv[6..10].copy_from_slice(&key.key_id.to_be_bytes());
+    // The message length in the header covers the TLV
+    buffer[2..4].copy_from_slice(&(length as u16).to_be_bytes());
+
+    let (covered, icv) = buffer.split_at_mut(length - icv_length);
+    config.algorithm.compute_icv(key.material, covered, icv);
+    Some(length)
+}
+
+#[derive(Debug)]
+enum Failure {
+    Missing,
+    Unknown,
+    Invalid,
+    Replayed,
+    OutsideWindow,
+}
+
+// Check the AUTHENTICATION TLV, which must be the last TLV of the message
+fn check_message(config: &AuthenticationConfig, now: Time, data: &[u8]) -> Result<(), Failure> {
+    let message_length = data
+        .get(2..4)
+        .map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+        .ok_or(Failure::Missing)?;
+    let message = data.get(..message_length).ok_or(Failure::Missing)?;
+
+    // The length of the TLV follows from that of the ICV, so it is found from
+    // the end of the message, however many TLVs come before it. It can't
+    // start before the end of the header.
+    let icv_length = config.algorithm.icv_length();
+    let tlv = message_length
+        .checked_sub(TLV_HEADER_LENGTH + icv_length)
+        .filter(|start| *start >= 34)
+        .map(|start| &message[start..])
+        .ok_or(Failure::Missing)?;
+    match Tlv::peek(tlv) {
+        Ok((TlvType::Authentication, size)) if size == tlv.len() => {}
+        _ => return Err(Failure::Missing),
+    }
+    let value = &tlv[4..];
+
+    let key_id = u32::from_be_bytes([value[2], value[3], value[4], value[5]]);
+    let key = config
+        .keys
+        .key(key_id)
+        .filter(|key| key.is_valid_at(now))
+        .ok_or(Failure::Unknown)?;
+    if value[0] != config.spp || value[1] != 0 {
+        return Err(Failure::Unknown);
+    }
+
+    let (covered, received) = message.split_at(message_length - icv_length);
+    let mut expected = [0; MAX_ICV_LENGTH];
+    let expected = &mut expected[..icv_length];
+    config
+        .algorithm
+        .compute_icv(key.material, covered, expected);
+
+    // Compare all bytes, so the time taken doesn't tell how many matched
+    let difference = expected
+        .iter()
+        .zip(received)
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    if difference != 0 {
+        return Err(Failure::Invalid);
+    }
+
+    Ok(())
+}
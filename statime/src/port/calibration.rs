@@ -0,0 +1,108 @@
+//! Calibration constants of a port that outlive the process, see
+//! [`PtpInstance::set_calibration_store`](crate::PtpInstance::set_calibration_store)
+
+use fixed::types::I48F16;
+
+use crate::{config::PortConfig, datastructures::common::TimeInterval, time::Duration};
+
+/// The constants a port corrects its timestamps with, which are measured for
+/// the hardware and the network path of the port rather than configured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Calibration {
+    /// See [`PortConfig::delay_asymmetry`]
+    pub delay_asymmetry: Duration,
+    /// See [`PortConfig::ingress_latency`]
+    pub ingress_latency: Duration,
+    /// See [`PortConfig::egress_latency`]
+    pub egress_latency: Duration,
+}
+
+impl Calibration {
+    /// The length of the binary format of a calibration
+    pub const SIZE: usize = 24;
+
+    /// The calibration a port with `config` starts with
+    pub fn from_config(config: &PortConfig) -> Self {
+        Self {
+            delay_asymmetry: config.delay_asymmetry,
+            ingress_latency: config.ingress_latency,
+            egress_latency: config.egress_latency,
+        }
+    }
+
+    pub(crate) fn apply_to(&self, config: &mut PortConfig) {
+        config.delay_asymmetry = self.delay_asymmetry;
+        config.ingress_latency = self.ingress_latency;
+        config.egress_latency = self.egress_latency;
+    }
+
+    /// Write the calibration in a compact binary format, for storage that
+    /// holds bytes, like flash memory or a file. Each constant is stored like
+    /// a PTP correction field, in nanoseconds multiplied by 2^16.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        let constants = [
+            self.delay_asymmetry,
+            self.ingress_latency,
+            self.egress_latency,
+        ];
+        for (chunk, constant) in bytes.chunks_exact_mut(8).zip(constants) {
+            chunk.copy_from_slice(&TimeInterval::from(constant).to_bits().to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Read a calibration written with [`to_bytes`](Self::to_bytes)
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let constant = |index: usize| {
+            let chunk = bytes[8 * index..8 * index + 8].try_into().unwrap();
+            Duration::from(TimeInterval(I48F16::from_bits(i64::from_be_bytes(chunk))))
+        };
+        Self {
+            delay_asymmetry: constant(0),
+            ingress_latency: constant(1),
+            egress_latency: constant(2),
+        }
+    }
+}
+
+/// Why a [`CalibrationStore`] could not store a calibration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationStoreError {
+    /// The storage could not be written
+    WriteFailed,
+    /// The storage has no room for the calibration of this port
+    NoSpace,
+}
+
+impl core::fmt::Display for CalibrationStoreError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::WriteFailed => write!(f, "the calibration could not be written"),
+            Self::NoSpace => write!(f, "there is no room to store the calibration"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CalibrationStoreError {}
+
+/// Keeps the [`Calibration`] of each port across restarts, for example in
+/// files, flash memory or an EEPROM.
+///
+/// Ports are identified by their port number, which they get in the order
+/// they are added to the instance, so that order should not change between
+/// restarts. It takes `&self` as the store is shared by the ports of an
+/// instance, so storing needs interior mutability.
+pub trait CalibrationStore: core::fmt::Debug + Sync {
+    /// The calibration stored for the port, or `None` if there is none or it
+    /// can't be read
+    fn load(&self, port_number: u16) -> Option<Calibration>;
+
+    /// Store the calibration of the port, replacing any stored before
+    fn store(
+        &self,
+        port_number: u16,
+        calibration: &Calibration,
+    ) -> Result<(), CalibrationStoreError>;
+}
@@ -0,0 +1,366 @@
+use crate::{
+    config::{CmldsConfig, InstanceConfig, LogMessageIntervals},
+    datastructures::{
+        common::{ClockIdentity, PortIdentity},
+        datasets::DefaultDS,
+        messages::{Message, PacketRoute, SdoId, MAX_DATA_LEN},
+    },
+    log,
+    port::{
+        peer_delay::{self, LinkDelay, PeerDelay},
+        PacketMatch, PortActionIterator, TimestampContext, TimestampContextInner, TimestampSource,
+    },
+    time::Time,
+};
+
+/// A link port of the Common Mean Link Delay Service (IEEE1588-2019 section
+/// 16.6), measuring the delay of a link once for the ports of all PTP
+/// instances on it.
+///
+/// The link port runs its peer delay exchanges with the link port of its
+/// neighbor in domain 0 with sdo id [`SdoId::CMLDS`], whatever the domains of
+/// the instances, and answers the requests of that neighbor. The runtime
+/// drives it like a [`Port`](crate::Port): it hands it the packets that
+/// [`match_packet`](Self::match_packet) claims and the send timestamps of the
+/// messages it sends, runs the delay request timer of
+/// [`PortAction::ResetDelayRequestTimer`](crate::PortAction::ResetDelayRequestTimer)
+/// and calls [`handle_delay_request_timer`](Self::handle_delay_request_timer)
+/// when it expires. Each new measurement, taken with
+/// [`take_link_delay`](Self::take_link_delay), goes to every port on the link
+/// that uses
+/// [`DelayMechanism::CommonP2P`](crate::DelayMechanism::CommonP2P).
+#[derive(Debug)]
+pub struct CmldsLinkPort {
+    config: CmldsConfig,
+    port_identity: PortIdentity,
+    // Gives the messages of the link port their sdo id and domain
+    default_ds: DefaultDS,
+    peer_delay: PeerDelay,
+    // The correction applied to the local clock, see
+    // set_frequency_multiplier
+    frequency_multiplier: f64,
+    link_delay: Option<LinkDelay>,
+    // Whether the last link delay wasn't taken yet
+    new_link_delay: bool,
+    packet_buffer: [u8; MAX_DATA_LEN],
+}
+
+impl CmldsLinkPort {
+    /// The link port with the given number of the node with the given clock
+    /// identity. Its number is that of the ports on the same link.
+    pub fn new(clock_identity: ClockIdentity, port_number: u16, config: CmldsConfig) -> Self {
+        let port_identity = PortIdentity {
+            clock_identity,
+            port_number,
+        };
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity,
+            priority_1: 255,
+            priority_2: 255,
+            domain_number: 0,
+            slave_only: true,
+            sdo_id: SdoId::CMLDS,
+        });
+
+        CmldsLinkPort {
+            config,
+            port_identity,
+            default_ds,
+            peer_delay: PeerDelay::new(port_identity),
+            frequency_multiplier: 1.0,
+            link_delay: None,
+            new_link_delay: false,
+            packet_buffer: [0; MAX_DATA_LEN],
+        }
+    }
+
+    /// The last delay measured, if any
+    pub fn link_delay(&self) -> Option<LinkDelay> {
+        self.link_delay
+    }
+
+    /// The delay measured since this was last called, if any, to hand to the
+    /// ports on the link with
+    /// [`Port::handle_common_link_delay`](crate::Port::handle_common_link_delay)
+    pub fn take_link_delay(&mut self) -> Option<LinkDelay> {
+        core::mem::take(&mut self.new_link_delay)
+            .then_some(self.link_delay)
+            .flatten()
+    }
+
+    /// The frequency correction currently applied to the local clock, as
+    /// reported by
+    /// [`Clock::frequency_multiplier`](crate::Clock::frequency_multiplier).
+    /// The neighbor rate ratio is relative to the unadjusted oscillator, so
+    /// it takes this out of the intervals measured with the clock. Starts
+    /// out at 1.0, an unadjusted clock.
+    pub fn set_frequency_multiplier(&mut self, frequency_multiplier: f64) {
+        self.frequency_multiplier = frequency_multiplier;
+    }
+
+    /// Whether this link port handles a received packet, see
+    /// [`Port::match_packet`](crate::Port::match_packet). The ports of the
+    /// instances don't handle the packets of the service.
+    pub fn match_packet(&self, route: &PacketRoute) -> PacketMatch {
+        if route.sdo_id != SdoId::CMLDS || route.domain_number != 0 {
+            return PacketMatch::OtherInstance;
+        }
+
+        let own = (
+            self.port_identity.clock_identity,
+            self.port_identity.port_number,
+        );
+        if route.source_port == own {
+            return PacketMatch::Own;
+        }
+
+        match route.requesting_port {
+            Some(requesting_port) if requesting_port != own => PacketMatch::OtherPort,
+            _ => PacketMatch::Handle {
+                event: route.is_event(),
+            },
+        }
+    }
+
+    /// Start the next peer delay exchange
+    pub fn handle_delay_request_timer(&mut self) -> PortActionIterator<'_> {
+        let actions = self.peer_delay.send_request(
+            self.config.interval,
+            self.frequency_multiplier,
+            &LogMessageIntervals::STANDARD,
+            self.port_identity,
+            &self.default_ds,
+            &mut self.packet_buffer,
+        );
+        // Logged by the exchange already, and there are no statistics to
+        // count them in
+        self.peer_delay.diagnostic = None;
+        actions
+    }
+
+    /// Handle a message received over the time critical channel
+    pub fn handle_timecritical_receive(
+        &mut self,
+        data: &[u8],
+        timestamp: Time,
+        source: TimestampSource,
+    ) -> PortActionIterator<'_> {
+        let Some(message) = self.deserialize(data) else {
+            return actions![];
+        };
+        let timestamp = timestamp - self.config.ingress_latency;
+
+        match message {
+            Message::PDelayReq(request) => {
+                log::debug!(port: self.port_identity, "Received CMLDS PdelayReq");
+                match peer_delay::respond(
+                    &request,
+                    timestamp,
+                    self.config.one_step_responder,
+                    self.port_identity,
+                    &LogMessageIntervals::STANDARD,
+                    &mut self.packet_buffer,
+                ) {
+                    Ok(actions) => actions,
+                    Err(error) => {
+                        log::error!(
+                            port: self.port_identity,
+                            "Statime bug: Could not serialize peer delay response {:?}",
+                            error
+                        );
+                        actions![]
+                    }
+                }
+            }
+            Message::PDelayResp(response) => {
+                let link_delay = self.peer_delay.handle_resp(response, timestamp, source);
+                self.update(link_delay);
+                actions![]
+            }
+            _ => actions![],
+        }
+    }
+
+    /// Handle a message received over the general channel
+    pub fn handle_general_receive(&mut self, data: &[u8]) {
+        if let Some(Message::PDelayRespFollowUp(follow_up)) = self.deserialize(data) {
+            let link_delay = self.peer_delay.handle_resp_follow_up(follow_up);
+            self.update(link_delay);
+        }
+    }
+
+    /// The send timestamp of a time critical message of this link port
+    /// became available
+    pub fn handle_send_timestamp(
+        &mut self,
+        context: TimestampContext,
+        timestamp: Time,
+        source: TimestampSource,
+    ) -> PortActionIterator<'_> {
+        let timestamp = timestamp + self.config.egress_latency;
+
+        match context.inner {
+            TimestampContextInner::PDelayReq { id } => {
+                let link_delay = self.peer_delay.handle_timestamp(id, timestamp, source);
+                self.update(link_delay);
+                actions![]
+            }
+            TimestampContextInner::PDelayResp {
+                id,
+                requesting_port_identity,
+                correction,
+            } => match peer_delay::follow_up(
+                &self.default_ds,
+                self.port_identity,
+                requesting_port_identity,
+                id,
+                correction,
+                timestamp,
+                &LogMessageIntervals::STANDARD,
+                &mut self.packet_buffer,
+            ) {
+                Ok(actions) => actions,
+                Err(error) => {
+                    log::error!(
+                        port: self.port_identity,
+                        "Statime bug: Could not serialize peer delay response follow up {:?}",
+                        error
+                    );
+                    actions![]
+                }
+            },
+            _ => actions![],
+        }
+    }
+
+    /// The runtime could not send a time critical message of this link port
+    pub fn handle_send_failure(&mut self, context: TimestampContext) {
+        if let TimestampContextInner::PDelayReq { id } = context.inner {
+            self.peer_delay.handle_send_failure(id);
+        }
+    }
+
+    // Only the peer delay messages of the service concern the link port
+    fn deserialize(&self, data: &[u8]) -> Option<Message> {
+        let message = match Message::deserialize(data) {
+            Ok(message) => message,
+            Err(error) => {
+                log::warn!(port: self.port_identity, "Could not parse packet: {:?}", error);
+                return None;
+            }
+        };
+
+        let header = message.header();
+        if header.sdo_id != SdoId::CMLDS
+            || header.domain_number != 0
+            || header.source_port_identity == self.port_identity
+        {
+            return None;
+        }
+
+        Some(message)
+    }
+
+    fn update(&mut self, link_delay: Option<LinkDelay>) {
+        self.peer_delay.diagnostic = None;
+        if let Some(link_delay) = link_delay {
+            log::debug!(
+                port: self.port_identity,
+                "Measured a mean link delay of {}",
+                link_delay.mean_delay
+            );
+            self.link_delay = Some(link_delay);
+            self.new_link_delay = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::{port::PortAction, time::Duration};
+
+    fn send_time_critical(mut actions: PortActionIterator) -> (TimestampContext, Vec<u8>) {
+        let context_and_data = actions.find_map(|action| match action {
+            PortAction::SendTimeCritical { context, data, .. } => Some((context, data.to_vec())),
+            _ => None,
+        });
+        context_and_data.expect("No time critical message sent")
+    }
+
+    fn send_general(mut actions: PortActionIterator) -> Vec<u8> {
+        let data = actions.find_map(|action| match action {
+            PortAction::SendGeneral { data } => Some(data.to_vec()),
+            _ => None,
+        });
+        data.expect("No general message sent")
+    }
+
+    #[test]
+    fn test_cmlds_exchange() {
+        let config = CmldsConfig::default();
+        let mut requester = CmldsLinkPort::new(ClockIdentity([1; 8]), 1, config);
+        let mut responder = CmldsLinkPort::new(ClockIdentity([2; 8]), 3, config);
+        assert_eq!(requester.take_link_delay(), None);
+
+        // The request goes out in domain 0 with the sdo id of the service
+        let (request_context, request) = send_time_critical(requester.handle_delay_request_timer());
+        let header = *Message::deserialize(&request).unwrap().header();
+        assert_eq!(header.sdo_id, SdoId::CMLDS);
+        assert_eq!(header.domain_number, 0);
+
+        // t1 = 10s, t2 = 500s, t3 = t2 + 10us and t4 = t1 + 30us, for a
+        // mean link delay of 10us
+        let t1 = Time::from_secs(10);
+        let t2 = Time::from_secs(500);
+        let (response_context, response) = send_time_critical(
+            responder.handle_timecritical_receive(&request, t2, TimestampSource::Hardware),
+        );
+        let follow_up = send_general(responder.handle_send_timestamp(
+            response_context,
+            t2 + Duration::from_micros(10),
+            TimestampSource::Hardware,
+        ));
+
+        assert!(requester
+            .handle_send_timestamp(request_context, t1, TimestampSource::Hardware)
+            .next()
+            .is_none());
+        assert!(requester
+            .handle_timecritical_receive(
+                &response,
+                t1 + Duration::from_micros(30),
+                TimestampSource::Hardware
+            )
+            .next()
+            .is_none());
+        requester.handle_general_receive(&follow_up);
+
+        let link_delay = requester.take_link_delay().unwrap();
+        assert_eq!(link_delay.mean_delay, Duration::from_micros(10));
+        assert_eq!(link_delay.source, TimestampSource::Hardware);
+        // Handed out once, but still there for ports that come later
+        assert_eq!(requester.take_link_delay(), None);
+        assert_eq!(requester.link_delay(), Some(link_delay));
+    }
+
+    #[test]
+    fn test_cmlds_ignores_instances() {
+        let mut link_port = CmldsLinkPort::new(ClockIdentity([1; 8]), 1, CmldsConfig::default());
+        let mut other = CmldsLinkPort::new(ClockIdentity([2; 8]), 1, CmldsConfig::default());
+        let (_, mut request) = send_time_critical(other.handle_delay_request_timer());
+
+        // The peer delay requests of an instance in domain 0 are not for the
+        // service, even though they only differ in majorSdoId
+        request[0] &= 0x0f;
+        assert!(link_port
+            .handle_timecritical_receive(&request, Time::from_secs(1), TimestampSource::Hardware)
+            .next()
+            .is_none());
+
+        let route = crate::route_packet(&request).unwrap();
+        assert_eq!(link_port.match_packet(&route), PacketMatch::OtherInstance);
+    }
+}
@@ -0,0 +1,169 @@
+use arrayvec::ArrayVec;
+
+use crate::{
+    datastructures::{
+        common::{LeapIndicator, ProfileIdentifier},
+        messages::MessageType,
+    },
+    log,
+};
+
+/// Number of events a port keeps queued for the runtime
+pub const EVENT_QUEUE_CAPACITY: usize = 8;
+
+/// Something noteworthy that happened on a port, for the runtime to report
+/// to operators.
+///
+/// Events are retrieved with [`Port::take_event`](crate::Port::take_event).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortEvent {
+    /// The leap indicator in the announce messages sent by this port changed,
+    /// for example because an upcoming leap second is now being announced.
+    AnnouncedLeapIndicator {
+        previous: LeapIndicator,
+        current: LeapIndicator,
+    },
+    /// The current UTC offset in the announce messages sent by this port
+    /// changed, for example because a leap second just happened.
+    AnnouncedUtcOffset {
+        previous: Option<i16>,
+        current: Option<i16>,
+    },
+    /// The port ran into a problem. Only reported when statime is built with
+    /// the `silent` feature, where log messages are compiled out, or when the
+    /// problem [indicates a bug](Diagnostic::indicates_bug).
+    Diagnostic(Diagnostic),
+    /// The port received a message from another clock using the clock identity
+    /// of this instance, claiming to be sent by the port with the given
+    /// number. See
+    /// [`IdentityCollisionResponse`](crate::IdentityCollisionResponse).
+    ClockIdentityCollision {
+        port_number: u16,
+        message_type: MessageType,
+    },
+    /// The master of this slave port advertises another profile than the
+    /// instance, see
+    /// [`PtpInstance::set_profile_identifier`](crate::PtpInstance::set_profile_identifier).
+    /// Reported once for each master and profile it advertises.
+    ParentProfileMismatch {
+        local: ProfileIdentifier,
+        advertised: ProfileIdentifier,
+    },
+    /// The port moved to a different state, for example because the BMCA
+    /// selected a new master.
+    StateChanged {
+        previous: PortStateKind,
+        current: PortStateKind,
+    },
+    /// The mean frequency correction of the local clock over the last period
+    /// moved by at least the threshold set with
+    /// [`Port::set_frequency_change_threshold`](crate::Port::set_frequency_change_threshold),
+    /// as happens when the temperature of the oscillator changes. Rounded to
+    /// whole parts per billion, see
+    /// [`FrequencyStatistics`](crate::FrequencyStatistics) for the exact
+    /// values.
+    FrequencyCorrectionChanged { previous_ppb: i64, current_ppb: i64 },
+    /// The link of this port in gPTP mode became asCapable, or stopped being
+    /// so, see [`Port::set_gptp`](crate::Port::set_gptp)
+    AsCapableChanged { as_capable: bool },
+}
+
+/// The state a port is in, without any of the data belonging to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortStateKind {
+    Listening,
+    Passive,
+    Master,
+    Slave,
+    Faulty,
+    /// Turned off with a DISABLE_PORT management command
+    Disabled,
+}
+
+impl core::fmt::Display for PortStateKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PortStateKind::Listening => write!(f, "Listening"),
+            PortStateKind::Passive => write!(f, "Passive"),
+            PortStateKind::Master => write!(f, "Master"),
+            PortStateKind::Slave => write!(f, "Slave"),
+            PortStateKind::Faulty => write!(f, "Faulty"),
+            PortStateKind::Disabled => write!(f, "Disabled"),
+        }
+    }
+}
+
+/// A problem noticed by a port, identified by a numeric code.
+///
+/// With the `silent` feature all log messages are compiled out to save space
+/// on small targets. Problems that would have been logged as a warning or
+/// error are reported as [`PortEvent::Diagnostic`] instead. Problems that
+/// indicate a bug in statime are always reported, and counted in the
+/// [`PortStatistics`](crate::PortStatistics).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum Diagnostic {
+    /// A received packet could not be parsed
+    InvalidPacket = 1,
+    /// A message was received that the port can't use in its current state
+    UnexpectedMessage = 2,
+    /// A message was received more than once
+    DuplicateMessage = 3,
+    /// The runtime reported a send timestamp the port wasn't waiting for
+    UnexpectedTimestamp = 4,
+    /// A message could not be serialized
+    SerializationFailed = 5,
+    /// The local clock could not be adjusted
+    ClockAdjustFailed = 6,
+    /// Something happened that indicates a bug in statime
+    InternalError = 7,
+    /// The runtime could not send a message
+    SendFailed = 8,
+}
+
+impl Diagnostic {
+    /// The numeric code of this diagnostic
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    /// Whether the problem indicates a bug in statime, rather than in the
+    /// network or the runtime. A message failing to serialize means the
+    /// buffer statime sized for it was too small.
+    pub fn indicates_bug(self) -> bool {
+        matches!(
+            self,
+            Diagnostic::SerializationFailed | Diagnostic::InternalError
+        )
+    }
+}
+
+/// Events waiting to be picked up by the runtime, oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct EventQueue {
+    entries: ArrayVec<PortEvent, EVENT_QUEUE_CAPACITY>,
+}
+
+impl EventQueue {
+    /// Add an event, dropping the oldest one if the queue is full.
+    /// Returns whether an event was dropped.
+    pub(crate) fn push(&mut self, event: PortEvent) -> bool {
+        log::debug!("{:?}", event);
+
+        let overflow = self.entries.is_full();
+        if overflow {
+            self.entries.remove(0);
+        }
+        self.entries.push(event);
+
+        overflow
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<PortEvent> {
+        if self.entries.is_empty() {
+            None
+        } else {
+            Some(self.entries.remove(0))
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{
+    config::GptpConfig,
+    datastructures::common::PortIdentity,
+    log,
+    port::peer_delay::{LinkDelay, PeerDelay},
+};
+
+/// The link of a port in gPTP mode: the peer delay exchanges the port runs
+/// in every state, and whether they show the link to be asCapable (IEEE
+/// 802.1AS-2020 section 11.2.2)
+#[derive(Debug)]
+pub(crate) struct GptpLink {
+    config: GptpConfig,
+    pub(crate) peer_delay: PeerDelay,
+    // Requests in a row that went unanswered
+    lost_responses: u8,
+    as_capable: bool,
+    port_identity: PortIdentity,
+}
+
+impl GptpLink {
+    pub(crate) fn new(config: GptpConfig, port_identity: PortIdentity) -> Self {
+        GptpLink {
+            config,
+            peer_delay: PeerDelay::new(port_identity),
+            lost_responses: 0,
+            as_capable: false,
+            port_identity,
+        }
+    }
+
+    pub(crate) fn as_capable(&self) -> bool {
+        self.as_capable
+    }
+
+    /// Count the last exchange as lost if it never completed, before the
+    /// next one starts
+    pub(crate) fn check_lost_response(&mut self) {
+        if !self.peer_delay.outstanding() {
+            return;
+        }
+
+        self.lost_responses = self.lost_responses.saturating_add(1);
+        if self.as_capable && self.lost_responses > self.config.allowed_lost_responses {
+            log::info!(
+                port: self.port_identity,
+                "Link no longer asCapable, {} peer delay responses in a row were lost",
+                self.lost_responses
+            );
+            self.as_capable = false;
+        }
+    }
+
+    /// Judge the link by the delay a completed exchange measured
+    pub(crate) fn update(&mut self, link_delay: &LinkDelay) {
+        self.lost_responses = 0;
+
+        let as_capable = link_delay.mean_delay <= self.config.neighbor_prop_delay_thresh;
+        if as_capable != self.as_capable {
+            if as_capable {
+                log::info!(
+                    port: self.port_identity,
+                    "Link asCapable, with a mean link delay of {}",
+                    link_delay.mean_delay
+                );
+            } else {
+                log::info!(
+                    port: self.port_identity,
+                    "Link no longer asCapable, its mean link delay of {} exceeds {}",
+                    link_delay.mean_delay,
+                    self.config.neighbor_prop_delay_thresh
+                );
+            }
+        }
+        self.as_capable = as_capable;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{port::TimestampSource, time::Duration};
+
+    fn link_delay(nanos: i64) -> LinkDelay {
+        LinkDelay {
+            mean_delay: Duration::from_nanos(nanos),
+            source: TimestampSource::Hardware,
+            neighbor_rate_ratio: None,
+        }
+    }
+
+    #[test]
+    fn as_capable_delay_threshold() {
+        let mut link = GptpLink::new(GptpConfig::default(), PortIdentity::default());
+        assert!(!link.as_capable());
+
+        link.update(&link_delay(500));
+        assert!(link.as_capable());
+        link.update(&link_delay(800));
+        assert!(link.as_capable());
+        link.update(&link_delay(801));
+        assert!(!link.as_capable());
+        link.update(&link_delay(20));
+        assert!(link.as_capable());
+    }
+}
@@ -0,0 +1,139 @@
+use super::{LinkDelay, TimestampContext, TimestampSource};
+use crate::Time;
+
+/// Something that happened to a port, for the port to handle.
+///
+/// Everything the runtime needs to tell a running port about goes through
+/// [`Port::handle`](crate::Port::handle) as one of these. In return, the port
+/// gives a [`PortActionIterator`](crate::PortActionIterator) with the
+/// packets to send and the timers to reset. This makes the port easy to
+/// drive from a single event loop, or from another language.
+///
+/// Each input corresponds with one of the `handle_*` methods of the port,
+/// which can still be used directly.
+#[derive(Debug)]
+pub enum PortInput<'b> {
+    /// A message arrived over the time critical channel, with the time and
+    /// the source of its receive timestamp
+    TimeCriticalReceive {
+        data: &'b [u8],
+        timestamp: Time,
+        source: TimestampSource,
+        arrival: PacketArrival,
+    },
+    /// A message arrived over the general channel
+    GeneralReceive {
+        data: &'b [u8],
+        arrival: PacketArrival,
+    },
+    /// The send timestamp of a message from a
+    /// [`PortAction::SendTimeCritical`](crate::PortAction::SendTimeCritical)
+    /// became available
+    SendTimestamp {
+        context: TimestampContext,
+        timestamp: Time,
+        source: TimestampSource,
+    },
+    /// The announce timer expired
+    AnnounceTimer,
+    /// The sync timer expired
+    SyncTimer,
+    /// The delay request timer expired
+    DelayRequestTimer,
+    /// The announce receipt timer expired
+    AnnounceReceiptTimer,
+    /// The unicast negotiation timer expired
+    UnicastNegotiationTimer,
+    /// The statistics timer expired
+    StatisticsTimer,
+    /// The message of an earlier action could not be sent
+    SendFailure {
+        send: FailedSend<'b>,
+        error: SendError,
+    },
+    /// The link of the port went down or came back up
+    LinkChange { up: bool },
+    /// The [`CmldsLinkPort`](crate::CmldsLinkPort) of the link of the port
+    /// measured its delay
+    CommonLinkDelay { link_delay: LinkDelay },
+}
+
+/// Where a received packet was sent to, see [`PacketArrival`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    /// An address of the port itself
+    Unicast,
+    /// A multicast group the port joined
+    Multicast,
+}
+
+/// Where a received packet was sent from, see [`PacketArrival`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceAddress {
+    /// An address of the port itself, as for the messages of the port that
+    /// come back through multicast loopback
+    Own,
+    /// The address of another host or network interface
+    Other,
+}
+
+/// How a received packet reached the port, as far as the runtime can tell.
+///
+/// The port skips the checks that need something the runtime leaves at
+/// `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketArrival {
+    /// Where the packet was sent to. Messages whose unicast flag doesn't
+    /// match it are ignored, as some middleboxes reflect unicast traffic into
+    /// multicast groups.
+    pub destination: Option<Destination>,
+    /// Where the packet was sent from. A message with our clock identity
+    /// from another address is sent by another clock using that identity.
+    /// Without it, the port can only tell such messages apart from its own
+    /// by their port number and sequence id.
+    pub source: Option<SourceAddress>,
+}
+
+/// Whether a port handles a received packet, see
+/// [`Port::match_packet`](crate::Port::match_packet)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketMatch {
+    /// The port handles the packet. Event messages go to
+    /// [`Port::handle_timecritical_receive`](crate::Port::handle_timecritical_receive)
+    /// with their receive timestamp, other messages to
+    /// [`Port::handle_general_receive`](crate::Port::handle_general_receive).
+    Handle { event: bool },
+    /// The packet belongs to another domain or SDO, so to another instance
+    OtherInstance,
+    /// The packet answers a request of another port
+    OtherPort,
+    /// The port sent the packet itself, and got it back through multicast
+    /// loopback. Announce messages are always handled instead, as the port
+    /// needs them to notice another clock using its identity, as are packets
+    /// from another [`SourceAddress`].
+    Own,
+}
+
+/// A message the runtime could not send, see
+/// [`Port::handle_send_failure`](crate::Port::handle_send_failure)
+#[derive(Debug)]
+pub enum FailedSend<'b> {
+    /// The message of a
+    /// [`PortAction::SendTimeCritical`](crate::PortAction::SendTimeCritical),
+    /// identified by its context
+    TimeCritical { context: TimestampContext },
+    /// The message of a
+    /// [`PortAction::SendGeneral`](crate::PortAction::SendGeneral)
+    General { data: &'b [u8] },
+}
+
+/// Why the runtime could not send a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// Sending may work when tried again, for example because the socket ran
+    /// out of buffer space (`ENOBUFS`)
+    Transient,
+    /// The port can't send at all, for example because its network interface
+    /// went down. The port becomes faulty.
+    Fatal,
+}
@@ -1,10 +1,237 @@
-use crate::time::{Duration, Time};
+use arrayvec::ArrayVec;
+
+use crate::{
+    datastructures::common::FollowUpInformation,
+    time::{Duration, Time},
+};
+
+/// Number of measurements a port keeps queued for the runtime
+pub const MEASUREMENT_QUEUE_CAPACITY: usize = 16;
+
+/// Where a timestamp handed to a port was taken.
+///
+/// Variants are ordered from least to most precise, so the provenance of a
+/// value computed from several timestamps is the minimum of theirs.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum TimestampSource {
+    /// The runtime didn't say where the timestamp came from. This is what the
+    /// port methods that don't take a source assume.
+    Legacy,
+    /// Taken by the operating system when it handled the packet
+    Software,
+    /// Taken by the network hardware
+    Hardware,
+}
+
+impl TimestampSource {
+    /// The provenance of a value computed from timestamps of both sources
+    pub fn combine(self, other: TimestampSource) -> TimestampSource {
+        self.min(other)
+    }
+}
 
 /// A single measurement as produced by a PTP port.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Measurement {
     /// Time this measurement was made.
     pub event_time: Time,
     /// Offset to the remote PTP node.
     pub master_offset: Duration,
+    /// Receive timestamp of the sync this measurement is based on, as the
+    /// runtime provided it.
+    pub raw_receive_time: Time,
+    /// [`raw_receive_time`](Self::raw_receive_time) corrected for the
+    /// [`ingress_latency`](crate::config::PortConfig::ingress_latency), as it
+    /// was used for this measurement.
+    pub receive_time: Time,
+    /// Where the local timestamps this measurement is based on were taken.
+    /// This is the least precise source of all of them.
+    pub timestamp_source: TimestampSource,
+    /// What the correction field of the sync this measurement is based on
+    /// consists of.
+    pub correction: CorrectionBreakdown,
+    /// The FOLLOW_UP information TLV of IEEE 802.1AS carried by the follow
+    /// up of the sync, if it had one.
+    pub follow_up_information: Option<FollowUpInformation>,
+    /// The frequency of the clock of the peer relative to the unadjusted
+    /// oscillator of the local clock, when measuring the delay [peer to
+    /// peer](crate::config::DelayMechanism::P2P) with a two-step responder.
+    /// A bridge relaying the time of IEEE 802.1AS multiplies the rate ratio
+    /// it passes on with this, see
+    /// [`FollowUpInformation::with_neighbor_rate_ratio`].
+    pub neighbor_rate_ratio: Option<f64>,
+}
+
+/// The correction field of a sync, and its follow up, taken apart.
+///
+/// Transparent clocks add the time a message spent inside them to the
+/// correction field, in whole nanoseconds in practice, while a master puts
+/// the fraction of a nanosecond of its origin timestamp there. Comparing the
+/// residence time with the path delay shows how much of the path between the
+/// master and the port is PTP aware.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct CorrectionBreakdown {
+    /// Whole nanoseconds of the correction field, the time the sync spent in
+    /// transparent clocks. With peer to peer delay measurement this includes
+    /// the delay of the links between them.
+    pub residence_time: Duration,
+    /// The fraction of a nanosecond of the correction field, which is the
+    /// sub-nanosecond part of the origin timestamp of the master.
+    pub sub_nanosecond: Duration,
+    /// The delay asymmetry the measurement was corrected for, see
+    /// [`PortConfig::delay_asymmetry`](crate::config::PortConfig::delay_asymmetry).
+    pub asymmetry: Duration,
+    /// The mean path delay the measurement was corrected for, which is the
+    /// part of the path the correction field doesn't account for.
+    pub path_delay: Duration,
+}
+
+impl CorrectionBreakdown {
+    pub(crate) fn new(correction: Duration, asymmetry: Duration, path_delay: Duration) -> Self {
+        let sub_nanosecond = Duration::from_fixed_nanos(correction.nanos().frac());
+        Self {
+            residence_time: correction - sub_nanosecond,
+            sub_nanosecond,
+            asymmetry,
+            path_delay,
+        }
+    }
+
+    /// The correction field, as received
+    pub fn total(&self) -> Duration {
+        self.residence_time + self.sub_nanosecond
+    }
+
+    /// The share of the time between the master and the port that was spent
+    /// in transparent clocks, from 0 when no PTP aware equipment is on the
+    /// path to 1 when the path consists of nothing else. `None` when both
+    /// are zero.
+    pub fn ptp_aware_fraction(&self) -> Option<f64> {
+        let total = self.residence_time + self.path_delay;
+        if total == Duration::ZERO {
+            None
+        } else {
+            Some(self.residence_time.nanos_lossy() / total.nanos_lossy())
+        }
+    }
+}
+
+/// Measurements waiting to be picked up by the runtime, oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct MeasurementQueue {
+    enabled: bool,
+    entries: ArrayVec<Measurement, MEASUREMENT_QUEUE_CAPACITY>,
+}
+
+impl MeasurementQueue {
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.entries.clear();
+        }
+    }
+
+    /// Add a measurement, dropping the oldest one if the queue is full.
+    /// Returns whether a measurement was dropped.
+    pub(crate) fn push(&mut self, measurement: Measurement) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let overflow = self.entries.is_full();
+        if overflow {
+            self.entries.remove(0);
+        }
+        self.entries.push(measurement);
+
+        overflow
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Measurement> {
+        if self.entries.is_empty() {
+            None
+        } else {
+            Some(self.entries.remove(0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(secs: u64) -> Measurement {
+        Measurement {
+            event_time: Time::from_secs(secs),
+            master_offset: Duration::ZERO,
+            raw_receive_time: Time::from_secs(secs),
+            receive_time: Time::from_secs(secs),
+            timestamp_source: TimestampSource::Legacy,
+            correction: CorrectionBreakdown::default(),
+            follow_up_information: None,
+            neighbor_rate_ratio: None,
+        }
+    }
+
+    #[test]
+    fn test_combine_sources() {
+        use TimestampSource::*;
+
+        assert_eq!(Hardware.combine(Hardware), Hardware);
+        assert_eq!(Hardware.combine(Software), Software);
+        assert_eq!(Software.combine(Hardware), Software);
+        assert_eq!(Legacy.combine(Hardware), Legacy);
+        assert_eq!(Software.combine(Legacy), Legacy);
+    }
+
+    #[test]
+    fn test_correction_breakdown() {
+        let correction = CorrectionBreakdown::new(
+            Duration::from_fixed_nanos(3000.25),
+            Duration::from_nanos(40),
+            Duration::from_micros(1),
+        );
+        assert_eq!(correction.residence_time, Duration::from_micros(3));
+        assert_eq!(correction.sub_nanosecond, Duration::from_fixed_nanos(0.25));
+        assert_eq!(correction.asymmetry, Duration::from_nanos(40));
+        assert_eq!(correction.total(), Duration::from_fixed_nanos(3000.25));
+        assert_eq!(correction.ptp_aware_fraction(), Some(0.75));
+
+        // A negative correction is whole nanoseconds plus a positive fraction
+        let correction = CorrectionBreakdown::new(
+            Duration::from_fixed_nanos(-0.75),
+            Duration::ZERO,
+            Duration::ZERO,
+        );
+        assert_eq!(correction.residence_time, Duration::from_nanos(-1));
+        assert_eq!(correction.sub_nanosecond, Duration::from_fixed_nanos(0.25));
+
+        assert_eq!(CorrectionBreakdown::default().ptp_aware_fraction(), None);
+    }
+
+    #[test]
+    fn test_queue_disabled() {
+        let mut queue = MeasurementQueue::default();
+        assert!(!queue.push(measurement(1)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_queue_overflow() {
+        let mut queue = MeasurementQueue::default();
+        queue.set_enabled(true);
+
+        for i in 0..MEASUREMENT_QUEUE_CAPACITY as u64 {
+            assert!(!queue.push(measurement(i)));
+        }
+        assert!(queue.push(measurement(100)));
+
+        // The oldest measurement made room for the newest
+        assert_eq!(queue.pop(), Some(measurement(1)));
+        for i in 2..MEASUREMENT_QUEUE_CAPACITY as u64 {
+            assert_eq!(queue.pop(), Some(measurement(i)));
+        }
+        assert_eq!(queue.pop(), Some(measurement(100)));
+        assert_eq!(queue.pop(), None);
+    }
 }
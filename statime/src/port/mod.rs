@@ -1,24 +1,77 @@
-use core::ops::Deref;
+use core::{ops::Deref, sync::atomic::Ordering};
 
+pub use announce::AnnounceContent;
 use arrayvec::ArrayVec;
 use atomic_refcell::{AtomicRef, AtomicRefCell};
-pub use measurement::Measurement;
+use authentication::Authenticator;
+pub use authentication::{
+    AuthenticationConfig, AuthenticationConfigError, AuthenticationFailureAction,
+    IntegrityAlgorithm, KeyTable, ReplayWindowSlot, SecurityKey, SecurityProvider, MAX_ICV_LENGTH,
+    REPLAY_TIMEOUT_SECONDS, REPLAY_WINDOW,
+};
+pub use calibration::{Calibration, CalibrationStore, CalibrationStoreError};
+pub use cmlds::CmldsLinkPort;
+use event::EventQueue;
+pub use event::{Diagnostic, PortEvent, PortStateKind, EVENT_QUEUE_CAPACITY};
+use gptp::GptpLink;
+pub use input::{
+    Destination, FailedSend, PacketArrival, PacketMatch, PortInput, SendError, SourceAddress,
+};
+use measurement::MeasurementQueue;
+pub use measurement::{
+    CorrectionBreakdown, Measurement, TimestampSource, MEASUREMENT_QUEUE_CAPACITY,
+};
+use organization::{append_organization_tlvs, consume_organization_tlvs};
+pub use organization::{
+    OrganizationExtension, OrganizationTlv, OrganizationTlvError, OrganizationTlvWriter,
+};
+pub use peer_delay::LinkDelay;
+use peer_delay::PeerDelay;
 use rand::Rng;
 use state::{MasterState, PortState};
+pub use statistics::{
+    AuthenticationFailures, DelayRespRejections, DurationStatistics, FrequencyCorrection,
+    FrequencyStatistics, MessageRate, MessageRates, MessageTypeRates, PortStatistics, QuirkCounts,
+    StatisticsWindow, StatisticsWindows, TimeErrorConfigError, TimeErrorMetrics,
+    TimeErrorStatistics, Timeline, TimelineEntry, TimelineEvent, TimestampSourceCounts,
+    UnicastGrantCounts, UnicastRequestCounts, UnicastSyncClient, FREQUENCY_HISTORY_CAPACITY,
+    FREQUENCY_PERIOD_SECONDS, MAX_OBSERVATION_INTERVALS, STATISTICS_WINDOW_HISTORY,
+    TIMELINE_CAPACITY,
+};
+pub use unicast::UnicastGrantSlot;
+use unicast::{SyncGrant, UnicastGrants};
+use unicast_client::UnicastClient;
 
 use self::state::SlaveState;
 use crate::{
-    bmc::bmca::{BestAnnounceMessage, Bmca, RecommendedState},
+    bmc::{
+        bmca::{BestAnnounceMessage, Bmca, RecommendedState},
+        MasterSelection,
+    },
     clock::Clock,
-    config::PortConfig,
+    config::{
+        resolve_quirks, CommunicationMode, DelayMechanism, FixedMasterConfig,
+        FixedMasterConfigError, GptpConfig, IdentityCollisionResponse, PortConfig,
+        QuirkConfigError, QuirkRule, SimulatedPath, StartupBurst, UnicastClientConfig,
+        UnicastConfigError, UnicastMasterConfig, MAX_QUIRK_RULES,
+    },
     datastructures::{
-        common::{LeapIndicator, PortIdentity, TimeSource, WireTimestamp},
+        common::{
+            ClockIdentity, FollowUpInformation, PathTrace, PortIdentity, ProfileIdentifier,
+            TimeInterval, TlvType, WireTimestamp,
+        },
         datasets::{CurrentDS, DefaultDS, ParentDS, TimePropertiesDS},
-        messages::Message,
+        messages::{
+            Header, ManagementAction, ManagementErrorId, ManagementId, ManagementMessage,
+            ManagementTlv, Message, MessageType, PDelayReqMessage, PacketRoute, SignalingMessage,
+            UnicastNegotiation,
+        },
+        WireFormatError,
     },
     filters::Filter,
+    log,
     ptp_instance::PtpInstanceState,
-    time::Duration,
+    time::{Duration, Interval},
     Time, MAX_DATA_LEN,
 };
 
@@ -44,11 +97,59 @@ macro_rules! actions {
             PortActionIterator::from(list)
         }
     };
+    [$action1:expr, $action2:expr, $action3:expr] => {
+        {
+            let mut list = ::arrayvec::ArrayVec::new();
+            list.push($action1);
+            list.push($action2);
+            list.push($action3);
+            PortActionIterator::from(list)
+        }
+    };
+}
+
+// Finish the actions a port is about to return: seal the message among them
+// for sending, report the problems noticed while producing them, along with
+// `diagnostic`, and count the message towards the sent message rates. A macro
+// rather than a method, as the actions borrow the packet buffer of the port.
+macro_rules! finish_actions {
+    ($port:ident, $actions:expr, $diagnostic:expr) => {{
+        let actions = $port.authentication.seal(
+            $actions,
+            &$port.lifecycle.state.local_clock,
+            $port.port_identity,
+            &mut $port.statistics.authentication,
+        );
+        report_diagnostics(
+            &mut $port.port_state,
+            $diagnostic,
+            &mut $port.events,
+            &mut $port.statistics,
+        );
+        record_sent(
+            &mut $port.statistics,
+            &$port.lifecycle.state.local_clock,
+            &actions,
+        );
+        actions
+    }};
 }
 
+mod announce;
+mod authentication;
+mod calibration;
+mod cmlds;
+mod event;
+mod gptp;
+mod input;
 mod measurement;
+mod organization;
+mod peer_delay;
 mod sequence_id;
 pub(crate) mod state;
+mod statistics;
+mod unicast;
+mod unicast_client;
 
 /// A single port of the PTP instance
 ///
@@ -64,6 +165,49 @@ pub struct Port<L, R> {
     packet_buffer: [u8; MAX_DATA_LEN],
     lifecycle: L,
     rng: R,
+    statistics: PortStatistics,
+    time_error: TimeErrorStatistics,
+    frequency: FrequencyStatistics,
+    timeline: Timeline,
+    windows: StatisticsWindows,
+    measurements: MeasurementQueue,
+    events: EventQueue,
+    quirk_rules: ArrayVec<QuirkRule, MAX_QUIRK_RULES>,
+    startup_burst: Option<StartupBurst>,
+    simulated_path: Option<SimulatedPath>,
+    identity_collision: IdentityCollisionResponse,
+    unicast: UnicastGrants,
+    unicast_client: UnicastClient,
+    organization_extension: Option<&'static dyn OrganizationExtension>,
+    follow_up_information: Option<FollowUpInformation>,
+    gptp: Option<GptpLink>,
+    // The last link delay from the Common Mean Link Delay Service
+    common_link_delay: Option<LinkDelay>,
+    fixed_master: Option<FixedMasterConfig>,
+    // portDS.localPriority, see set_local_priority
+    local_priority: u8,
+    calibration_store: Option<&'static dyn CalibrationStore>,
+    // The last profile advertised by the master of this slave port
+    parent_profile: Option<(PortIdentity, ProfileIdentifier)>,
+    authentication: Authenticator,
+    sync_transmit_lead: Option<Duration>,
+    // Clock generation of the instance our measurements belong to
+    clock_generation: u32,
+}
+
+/// The state a port is put in by
+/// [`PtpInstance::add_port_in_state`](crate::PtpInstance::add_port_in_state)
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestPortState {
+    Listening,
+    Passive,
+    Master,
+    /// Slave of the port with the given clock identity and port number
+    Slave {
+        clock_identity: crate::ClockIdentity,
+        port_number: u16,
+    },
 }
 
 #[derive(Debug)]
@@ -77,6 +221,9 @@ pub struct InBmca<'a, C, F> {
     pending_action: PortActionIterator<'static>,
     local_best: Option<BestAnnounceMessage>,
     state_refcell: &'a AtomicRefCell<PtpInstanceState<C, F>>,
+    // Set by set_fixed_master, so the port becomes slave of its fixed master
+    // when the BMCA ends
+    enter_fixed_master: bool,
 }
 
 // Making this non-copy and non-clone ensures a single handle_send_timestamp
@@ -88,8 +235,44 @@ pub struct TimestampContext {
 
 #[derive(Debug)]
 enum TimestampContextInner {
-    Sync { id: u16 },
-    DelayReq { id: u16 },
+    Sync {
+        id: u16,
+    },
+    UnicastSync {
+        id: u16,
+        sync_grant: SyncGrant,
+    },
+    DelayReq {
+        id: u16,
+    },
+    PDelayReq {
+        id: u16,
+    },
+    // What the PdelayRespFollowUp needs, the correction includes the
+    // correction of the request and the sub-nanosecond part of its receive
+    // timestamp
+    PDelayResp {
+        id: u16,
+        requesting_port_identity: PortIdentity,
+        correction: TimeInterval,
+    },
+    // Needs no follow up, the hardware took care of the turnaround time
+    OneStepPDelayResp,
+}
+
+impl TimestampContext {
+    fn message_type(&self) -> MessageType {
+        match self.inner {
+            TimestampContextInner::Sync { .. } | TimestampContextInner::UnicastSync { .. } => {
+                MessageType::Sync
+            }
+            TimestampContextInner::DelayReq { .. } => MessageType::DelayReq,
+            TimestampContextInner::PDelayReq { .. } => MessageType::PDelayReq,
+            TimestampContextInner::PDelayResp { .. } | TimestampContextInner::OneStepPDelayResp => {
+                MessageType::PDelayResp
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -97,10 +280,47 @@ pub enum PortAction<'a> {
     SendTimeCritical {
         context: TimestampContext,
         data: &'a [u8],
+        /// The local time at which the message should leave the network
+        /// interface, for runtimes that can schedule sends, for example with
+        /// `SO_TXTIME` and the ETF qdisc on Linux. Sending right away is fine
+        /// otherwise. Only set for sync messages of ports with
+        /// [`Port::set_sync_transmit_lead`].
+        transmit_at: Option<Time>,
     },
+    /// Send `data` over the general channel. There is no timestamp to report,
+    /// so runtimes may copy `data` and send the general messages of one or
+    /// more action iterators together, for example with `sendmmsg`. A message
+    /// that could not be sent is reported to [`Port::handle_send_failure`] as
+    /// [`FailedSend::General`] with a copy of its data.
     SendGeneral {
         data: &'a [u8],
     },
+    /// Send `data` over the general channel to the single port with the given
+    /// clock identity and port number, at the address it sent its signaling
+    /// messages from. Only used by ports granting unicast transmission, see
+    /// [`Port::set_unicast_master`], and by the unicast clients they granted
+    /// it to, see [`Port::set_unicast_client`].
+    SendUnicastGeneral {
+        data: &'a [u8],
+        clock_identity: ClockIdentity,
+        port_number: u16,
+    },
+    /// Like [`PortAction::SendUnicastGeneral`], over the time critical
+    /// channel. As with [`PortAction::SendTimeCritical`], the send timestamp
+    /// should be passed to [`Port::handle_send_timestamp`] with `context`.
+    SendUnicastTimeCritical {
+        context: TimestampContext,
+        data: &'a [u8],
+        clock_identity: ClockIdentity,
+        port_number: u16,
+    },
+    /// Send `data` over the general channel to entry `master` of the unicast
+    /// master table of the runtime. Only used by unicast clients, see
+    /// [`Port::set_unicast_client`].
+    SendToUnicastMaster {
+        data: &'a [u8],
+        master: usize,
+    },
     ResetAnnounceTimer {
         duration: core::time::Duration,
     },
@@ -113,22 +333,62 @@ pub enum PortAction<'a> {
     ResetAnnounceReceiptTimer {
         duration: core::time::Duration,
     },
+    /// When it expires, call [`Port::handle_unicast_negotiation_timer`]. Only
+    /// used by unicast clients.
+    ResetUnicastNegotiationTimer {
+        duration: core::time::Duration,
+    },
+    /// When it expires, call [`Port::handle_statistics_timer`]. Only used by
+    /// ports with a statistics window, see [`Port::set_statistics_window`].
+    ResetStatisticsTimer {
+        duration: core::time::Duration,
+    },
 }
 
-const MAX_ACTIONS: usize = 2;
+const MAX_ACTIONS: usize = 3;
+
+// The log 2 of the message intervals that can be set with management
+// messages. Wider than any profile uses, but narrow enough that a remote
+// management node can't overflow the timers.
+const MANAGEMENT_LOG_INTERVALS: core::ops::RangeInclusive<i8> = -8..=8;
 
 /// Guarantees to end user: Any set of actions will only ever contain a single
 /// time critical send
+///
+/// A runtime that can't execute an action right away, for example because its
+/// socket would block, can [`defer`](Self::defer) it and take it again once
+/// it can, instead of copying the message out. The data of the send actions
+/// borrows the buffer of the port, so the port can't handle any other input
+/// until the iterator is dropped.
 #[derive(Debug)]
 pub struct PortActionIterator<'a> {
-    internal: <ArrayVec<PortAction<'a>, MAX_ACTIONS> as IntoIterator>::IntoIter,
+    // In reverse, so the next action is at the end
+    remaining: ArrayVec<PortAction<'a>, MAX_ACTIONS>,
 }
 
 impl<'a> PortActionIterator<'a> {
-    fn from(list: ArrayVec<PortAction<'a>, MAX_ACTIONS>) -> Self {
-        Self {
-            internal: list.into_iter(),
-        }
+    fn from(mut list: ArrayVec<PortAction<'a>, MAX_ACTIONS>) -> Self {
+        list.reverse();
+        Self { remaining: list }
+    }
+
+    /// The action the next call to [`next`](Iterator::next) returns, without
+    /// taking it
+    pub fn peek(&self) -> Option<&PortAction<'a>> {
+        self.remaining.last()
+    }
+
+    /// Put back an action taken from this iterator, so the next call to
+    /// [`next`](Iterator::next) returns it again.
+    ///
+    /// Actions are put back in front of the remaining ones, so putting back
+    /// several actions in the reverse order they were taken in restores the
+    /// original order. Returns the action when there is no room for it, which
+    /// only happens when putting back more actions than were taken.
+    pub fn defer(&mut self, action: PortAction<'a>) -> Result<(), PortAction<'a>> {
+        self.remaining
+            .try_push(action)
+            .map_err(|error| error.element())
     }
 }
 
@@ -136,74 +396,373 @@ impl<'a> Iterator for PortActionIterator<'a> {
     type Item = PortAction<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.internal.next()
+        self.remaining.pop()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining.len(), Some(self.remaining.len()))
     }
 }
 
+impl ExactSizeIterator for PortActionIterator<'_> {}
+
 impl<'a, C: Clock, F: Filter, R: Rng> Port<Running<'a, C, F>, R> {
+    /// Handle something that happened to the port, see [`PortInput`]
+    pub fn handle(&mut self, input: PortInput<'_>) -> PortActionIterator<'_> {
+        match input {
+            PortInput::TimeCriticalReceive {
+                data,
+                timestamp,
+                source,
+                arrival,
+            } => self.handle_timecritical_receive_via(data, timestamp, source, arrival),
+            PortInput::GeneralReceive { data, arrival } => {
+                self.handle_general_receive_via(data, arrival)
+            }
+            PortInput::SendTimestamp {
+                context,
+                timestamp,
+                source,
+            } => self.handle_send_timestamp_from(context, timestamp, source),
+            PortInput::AnnounceTimer => self.handle_announce_timer(),
+            PortInput::SyncTimer => self.handle_sync_timer(),
+            PortInput::DelayRequestTimer => self.handle_delay_request_timer(),
+            PortInput::AnnounceReceiptTimer => self.handle_announce_receipt_timer(),
+            PortInput::UnicastNegotiationTimer => self.handle_unicast_negotiation_timer(),
+            PortInput::StatisticsTimer => self.handle_statistics_timer(),
+            PortInput::SendFailure { send, error } => self.handle_send_failure(send, error),
+            PortInput::LinkChange { up } => self.handle_link_change(up),
+            PortInput::CommonLinkDelay { link_delay } => {
+                self.handle_common_link_delay(link_delay);
+                actions![]
+            }
+        }
+    }
+
+    /// The link of the port went down or came back up, as noticed by the
+    /// runtime.
+    ///
+    /// This is only recorded in the [`Timeline`], to explain the offset
+    /// spikes that follow. The port carries on as before, and notices a lost
+    /// master through the announce receipt timeout.
+    pub fn handle_link_change(&mut self, up: bool) -> PortActionIterator<'_> {
+        log::info!(port: self.port_identity, "Link {}", if up { "up" } else { "down" });
+        let now = self.lifecycle.state.local_clock.borrow().now();
+        self.timeline.record(now, TimelineEvent::LinkChanged { up });
+        actions![]
+    }
+
+    /// The [`CmldsLinkPort`] of the link of this port measured its delay.
+    ///
+    /// Only ports using [`DelayMechanism::CommonP2P`] take it over, and keep
+    /// it for whenever they become slave. In gPTP mode it also decides
+    /// whether the link is asCapable, see [`Port::set_gptp`].
+    pub fn handle_common_link_delay(&mut self, link_delay: LinkDelay) {
+        if self.config.delay_mechanism != DelayMechanism::CommonP2P {
+            return;
+        }
+        self.common_link_delay = Some(link_delay);
+
+        if let PortState::Slave(slave) = &mut self.port_state {
+            slave.set_link_delay(link_delay);
+        }
+        if let Some(link) = &mut self.gptp {
+            let was_as_capable = link.as_capable();
+            link.update(&link_delay);
+            if link.as_capable() != was_as_capable {
+                self.handle_as_capable_change(!was_as_capable);
+            }
+        }
+    }
+
+    /// The runtime could not send the message of an earlier
+    /// [`PortAction::SendTimeCritical`] or [`PortAction::SendGeneral`].
+    ///
+    /// After a [`SendError::Fatal`] the port becomes faulty. It stops sending
+    /// and ignores the BMCA until the announce receipt timer expires, after
+    /// which it starts over from listening. After a [`SendError::Transient`]
+    /// the port retries a failed announce shortly, and stops waiting for the
+    /// delay response to a failed delay request. Other messages are not
+    /// retried, as a newer one is sent soon anyway.
+    pub fn handle_send_failure(
+        &mut self,
+        send: FailedSend<'_>,
+        error: SendError,
+    ) -> PortActionIterator<'_> {
+        self.statistics.send_failures = self.statistics.send_failures.saturating_add(1);
+
+        let message_type = match &send {
+            FailedSend::TimeCritical { context } => Some(context.message_type()),
+            FailedSend::General { data } => data
+                .first()
+                .and_then(|byte| MessageType::try_from(byte & 0x0f).ok()),
+        };
+        log::warn!(
+            port: self.port_identity,
+            "Could not send {:?} message: {:?}",
+            message_type,
+            error
+        );
+        report_diagnostics(
+            &mut self.port_state,
+            Some(Diagnostic::SendFailed),
+            &mut self.events,
+            &mut self.statistics,
+        );
+
+        if error == SendError::Fatal {
+            if matches!(self.port_state, PortState::Disabled) {
+                return actions![];
+            }
+            if !matches!(self.port_state, PortState::Faulty) {
+                let now = self.lifecycle.state.local_clock.borrow().now();
+                self.set_forced_port_state(PortState::Faulty, now);
+            }
+            // Try to recover when the announce receipt timer expires
+            let duration = self.config.announce_duration(&mut self.rng);
+            return actions![PortAction::ResetAnnounceReceiptTimer { duration }];
+        }
+
+        match send {
+            FailedSend::TimeCritical { context } => {
+                match (&mut self.gptp, &context.inner) {
+                    (Some(link), TimestampContextInner::PDelayReq { id }) => {
+                        link.peer_delay.handle_send_failure(*id);
+                    }
+                    _ => self.port_state.handle_send_failure(context),
+                }
+                actions![]
+            }
+            FailedSend::General { .. }
+                if message_type == Some(MessageType::Announce)
+                    && matches!(self.port_state, PortState::Master(_)) =>
+            {
+                // Other ports rely on our announces to keep us as their
+                // master, so don't wait a full interval for the next one
+                let duration = self.config.announce_interval.as_core_duration() / 8;
+                actions![PortAction::ResetAnnounceTimer { duration }]
+            }
+            FailedSend::General { .. } => actions![],
+        }
+    }
+
     // Send timestamp for last timecritical message became available
     pub fn handle_send_timestamp(
         &mut self,
         context: TimestampContext,
         timestamp: Time,
     ) -> PortActionIterator<'_> {
-        let actions = self.port_state.handle_timestamp(
-            context,
-            timestamp,
-            self.port_identity,
-            &self.lifecycle.state.default_ds,
-            &mut self.packet_buffer,
-        );
+        self.handle_send_timestamp_from(context, timestamp, TimestampSource::Legacy)
+    }
+
+    /// Like [`handle_send_timestamp`](Self::handle_send_timestamp), for a
+    /// timestamp taken by the given source
+    pub fn handle_send_timestamp_from(
+        &mut self,
+        context: TimestampContext,
+        timestamp: Time,
+        source: TimestampSource,
+    ) -> PortActionIterator<'_> {
+        self.check_clock_generation();
+        self.statistics.timestamp_sources.record(source);
+        let timestamp = timestamp + self.config.egress_latency;
+        // Pretend our requests took the simulated path
+        let timestamp = match (self.simulated_path, &context.inner) {
+            (
+                Some(path),
+                TimestampContextInner::DelayReq { .. } | TimestampContextInner::PDelayReq { .. },
+            ) => timestamp - path.slave_to_master(),
+            _ => timestamp,
+        };
+        if let TimestampContextInner::UnicastSync { id, sync_grant } = context.inner {
+            self.unicast
+                .record_sync_timestamp(sync_grant, id, timestamp);
+        }
+        match context.inner {
+            TimestampContextInner::PDelayResp {
+                id,
+                requesting_port_identity,
+                correction,
+            } => {
+                return self.send_pdelay_resp_follow_up(
+                    id,
+                    requesting_port_identity,
+                    correction,
+                    timestamp,
+                );
+            }
+            TimestampContextInner::OneStepPDelayResp => return actions![],
+            _ => {}
+        }
+
+        let actions = match context.inner {
+            TimestampContextInner::PDelayReq { id } if self.gptp.is_some() => {
+                let diagnostic = self.update_gptp_link(|peer_delay| {
+                    peer_delay.handle_timestamp(id, timestamp, source)
+                });
+                report_diagnostics(
+                    &mut self.port_state,
+                    diagnostic,
+                    &mut self.events,
+                    &mut self.statistics,
+                );
+                actions![]
+            }
+            _ => self.port_state.handle_timestamp(
+                context,
+                timestamp,
+                source,
+                &self.config,
+                self.port_identity,
+                &self.lifecycle.state.default_ds,
+                self.follow_up_information,
+                &mut self.packet_buffer,
+            ),
+        };
 
-        handle_time_measurement(
+        let diagnostic = handle_time_measurement(
             &mut self.port_state,
+            self.port_identity,
+            &mut self.measurements,
+            &mut self.statistics,
+            &mut self.time_error,
+            &mut self.frequency,
+            &mut self.timeline,
+            &mut self.windows,
+            &mut self.events,
             &self.lifecycle.state.filter,
             &self.lifecycle.state.local_clock,
+            &self.lifecycle.state.frequency_multiplier,
+            &self.lifecycle.state.clock_steps,
             &self.lifecycle.state.time_properties_ds,
+            self.lifecycle.state.free_run.load(Ordering::Relaxed),
         );
-
-        actions
+        finish_actions!(self, actions, diagnostic)
     }
 
     // Handle the announce timer going of
     pub fn handle_announce_timer(&mut self) -> PortActionIterator<'_> {
-        self.port_state.send_announce(
+        if !self.is_as_capable() {
+            let duration = self.config.announce_interval.as_core_duration();
+            return actions![PortAction::ResetAnnounceTimer { duration }];
+        }
+
+        if self.is_unicast_master() {
+            return self.send_unicast_announce();
+        }
+
+        let actions = self.port_state.send_announce(
             self.lifecycle.state.deref(),
             &self.config,
             self.port_identity,
+            &mut self.events,
+            &mut self.statistics,
+            self.organization_extension,
             &mut self.packet_buffer,
-        )
+        );
+        finish_actions!(self, actions, None)
     }
 
     // Handle the sync timer going of
     pub fn handle_sync_timer(&mut self) -> PortActionIterator<'_> {
-        self.port_state.send_sync(
+        if !self.is_as_capable() {
+            let duration = self.config.sync_interval.as_core_duration();
+            return actions![PortAction::ResetSyncTimer { duration }];
+        }
+
+        if self.is_unicast_master() {
+            return self.send_unicast_sync();
+        }
+
+        let actions = self.port_state.send_sync(
             &self.lifecycle.state.local_clock,
             &self.config,
             self.port_identity,
             &self.lifecycle.state.default_ds,
+            self.sync_transmit_lead,
             &mut self.packet_buffer,
-        )
+        );
+        finish_actions!(self, actions, None)
     }
 
     // Handle the sync timer going of
     pub fn handle_delay_request_timer(&mut self) -> PortActionIterator<'_> {
-        self.port_state.send_delay_request(
+        if let (Some(_), DelayMechanism::P2P { interval, .. }) =
+            (&self.gptp, self.config.delay_mechanism)
+        {
+            return self.send_gptp_peer_delay_request(interval);
+        }
+
+        if let (Some(fixed_master), PortState::Slave(slave)) = (self.fixed_master, &self.port_state)
+        {
+            if slave.unanswered_delay_requests() >= fixed_master.delay_receipt_timeout as u32 {
+                return self.handle_delay_receipt_timeout(fixed_master);
+            }
+        }
+
+        // A unicast client only measures the delay once its master granted
+        // delay responses, and then sends its requests to that master only.
+        // Peer delay requests go to the neighbor on the link without a grant.
+        let unicast_master = match self.config.delay_mechanism {
+            DelayMechanism::E2E { interval } if self.is_unicast_client() => {
+                let Some(master) = self.unicast_client.delay_resp_master() else {
+                    let duration = interval.as_core_duration();
+                    return actions![PortAction::ResetDelayRequestTimer { duration }];
+                };
+                Some(master)
+            }
+            _ => None,
+        };
+
+        let actions = self.port_state.send_delay_request(
             &mut self.rng,
             &self.config,
             self.port_identity,
             &self.lifecycle.state.default_ds,
+            self.lifecycle.state.current_frequency_multiplier(),
+            unicast_master,
             &mut self.packet_buffer,
-        )
+        );
+        finish_actions!(self, actions, None)
     }
 
     // Handle the announce receipt timer going off
     pub fn handle_announce_receipt_timer(&mut self) -> PortActionIterator<'_> {
+        // A disabled port waits for an ENABLE_PORT management command
+        if matches!(self.port_state, PortState::Disabled) {
+            return actions![];
+        }
+
+        let now = self.lifecycle.state.local_clock.borrow().now();
+
+        // A faulty port starts over, in the hope the fault went away
+        if matches!(self.port_state, PortState::Faulty) {
+            self.set_forced_port_state(PortState::Listening, now);
+
+            let duration = self.config.announce_duration(&mut self.rng);
+            return actions![PortAction::ResetAnnounceReceiptTimer { duration }];
+        }
+
+        // A port that can't transport time over its link doesn't become master
+        // either
+        if !self.is_as_capable() {
+            let duration = self.config.announce_duration(&mut self.rng);
+            return actions![PortAction::ResetAnnounceReceiptTimer { duration }];
+        }
+
+        if let Some(fixed_master) = self.fixed_master {
+            return self.handle_sync_receipt_timer(fixed_master, now);
+        }
+
         // we didn't hear announce messages from other masters, so become master
         // ourselves
         match self.port_state {
             PortState::Master(_) => (),
-            _ => self.set_forced_port_state(PortState::Master(MasterState::new())),
+            _ => {
+                self.timeline
+                    .record(now, TimelineEvent::AnnounceReceiptTimeout);
+                self.set_forced_port_state(PortState::Master(MasterState::new()), now);
+            }
         }
 
         // Immediately start sending syncs and announces
@@ -217,16 +776,189 @@ impl<'a, C: Clock, F: Filter, R: Rng> Port<Running<'a, C, F>, R> {
         ]
     }
 
+    /// End the unicast grants of a client, telling it with
+    /// CANCEL_UNICAST_TRANSMISSION TLVs so it doesn't have to wait for them to
+    /// time out, see [`Port::set_unicast_master`].
+    ///
+    /// Each call handles the next client that has grants, call it until it
+    /// returns no actions, for example before shutting down the port or
+    /// changing its unicast configuration. Clients answer with
+    /// ACKNOWLEDGE_CANCEL_UNICAST_TRANSMISSION TLVs, which are counted in
+    /// [`UnicastGrantCounts::acknowledged`].
+    pub fn cancel_unicast_grants(&mut self) -> PortActionIterator<'_> {
+        if !self.config.transmit.signaling {
+            return actions![];
+        }
+        let Some((client, message_types)) = self
+            .unicast
+            .revoke_next(&mut self.statistics.unicast_grants)
+        else {
+            return actions![];
+        };
+
+        log::debug!(
+            port: self.port_identity,
+            "Ending the {:?} grants of {:?}",
+            message_types,
+            client
+        );
+        let cancels = message_types
+            .into_iter()
+            .map(|message_type| UnicastNegotiation::Cancel { message_type })
+            .collect::<ArrayVec<_, 3>>();
+        let Some(length) = self.serialize_signaling(client, &cancels) else {
+            return actions![];
+        };
+
+        let send = PortAction::SendUnicastGeneral {
+            data: &self.packet_buffer[..length],
+            clock_identity: client.clock_identity,
+            port_number: client.port_number,
+        };
+        finish_actions!(self, actions![send], None)
+    }
+
+    /// Handle the unicast negotiation timer going off, sending the requests
+    /// for unicast transmission that are due to the masters of the unicast
+    /// master table, see [`UnicastClientConfig`]. Call it once after the port
+    /// starts running to send the first requests.
+    pub fn handle_unicast_negotiation_timer(&mut self) -> PortActionIterator<'_> {
+        if !self.is_unicast_client()
+            || !self.config.transmit.signaling
+            || matches!(self.port_state, PortState::Disabled)
+        {
+            return actions![];
+        }
+
+        let now = self.lifecycle.state.local_clock.borrow().now();
+        let (message, duration) = self
+            .unicast_client
+            .next_message(now, &mut self.statistics.unicast_requests);
+
+        let reset = PortAction::ResetUnicastNegotiationTimer { duration };
+        let Some(message) = message else {
+            return actions![reset];
+        };
+        log::debug!(
+            port: self.port_identity,
+            "Sending {:?} to unicast master {}",
+            message.negotiations,
+            message.master
+        );
+        let Some(length) = self.serialize_signaling(message.target, &message.negotiations) else {
+            return actions![reset];
+        };
+
+        let send = PortAction::SendToUnicastMaster {
+            data: &self.packet_buffer[..length],
+            master: message.master,
+        };
+        finish_actions!(self, actions![send, reset], None)
+    }
+
+    /// Handle the statistics timer going off, closing the statistics window
+    /// that ended, see [`Port::set_statistics_window`]. Call it once after
+    /// enabling the windows to start the timer.
+    pub fn handle_statistics_timer(&mut self) -> PortActionIterator<'_> {
+        let now = self.lifecycle.state.local_clock.borrow().now();
+        match self.windows.roll(now) {
+            Some(duration) => actions![PortAction::ResetStatisticsTimer { duration }],
+            None => actions![],
+        }
+    }
+
+    /// Whether this port handles a received packet, for runtimes that receive
+    /// the packets of several ports or instances on a single socket.
+    ///
+    /// Packets that don't match are ignored by the port anyway, so handing
+    /// them to it is harmless, but they don't show up in its statistics.
+    pub fn match_packet(&self, route: &PacketRoute) -> PacketMatch {
+        self.match_packet_via(route, PacketArrival::default())
+    }
+
+    /// Like [`match_packet`](Self::match_packet), for a packet that reached
+    /// the port as described by `arrival`
+    pub fn match_packet_via(&self, route: &PacketRoute, arrival: PacketArrival) -> PacketMatch {
+        let default_ds = &self.lifecycle.state.default_ds;
+        if route.sdo_id != default_ds.sdo_id || route.domain_number != default_ds.domain_number {
+            return PacketMatch::OtherInstance;
+        }
+
+        let own = (
+            self.port_identity.clock_identity,
+            self.port_identity.port_number,
+        );
+        if route.source_port == own {
+            // Another clock with our identity and port number can only be
+            // told apart from our own messages coming back by where they were
+            // sent from, or by the port, see `is_identity_collision`
+            if route.message_type == MessageType::Announce
+                || arrival.source == Some(SourceAddress::Other)
+            {
+                return PacketMatch::Handle {
+                    event: route.is_event(),
+                };
+            }
+            return PacketMatch::Own;
+        }
+
+        match route.requesting_port {
+            Some(requesting_port) if requesting_port != own => PacketMatch::OtherPort,
+            _ => PacketMatch::Handle {
+                event: route.is_event(),
+            },
+        }
+    }
+
     // Handle a message over the timecritical channel
     pub fn handle_timecritical_receive(
         &mut self,
         data: &[u8],
         timestamp: Time,
     ) -> PortActionIterator {
+        self.handle_timecritical_receive_from(data, timestamp, TimestampSource::Legacy)
+    }
+
+    /// Like [`handle_timecritical_receive`](Self::handle_timecritical_receive),
+    /// for a receive timestamp taken by the given source
+    pub fn handle_timecritical_receive_from(
+        &mut self,
+        data: &[u8],
+        timestamp: Time,
+        source: TimestampSource,
+    ) -> PortActionIterator<'_> {
+        self.handle_timecritical_receive_via(data, timestamp, source, PacketArrival::default())
+    }
+
+    /// Like
+    /// [`handle_timecritical_receive_from`](Self::handle_timecritical_receive_from),
+    /// for a packet that reached the port as described by `arrival`
+    pub fn handle_timecritical_receive_via(
+        &mut self,
+        data: &[u8],
+        timestamp: Time,
+        source: TimestampSource,
+        arrival: PacketArrival,
+    ) -> PortActionIterator<'_> {
+        self.check_clock_generation();
+        self.statistics.timestamp_sources.record(source);
+
+        // Management messages, which can enable the port again, are general
+        // messages
+        if matches!(self.port_state, PortState::Disabled) {
+            return actions![];
+        }
+
         let message = match Message::deserialize(data) {
             Ok(message) => message,
             Err(error) => {
-                log::warn!("Could not parse packet: {:?}", error);
+                log::warn!(port: self.port_identity, "Could not parse packet: {:?}", error);
+                report_diagnostics(
+                    &mut self.port_state,
+                    Some(Diagnostic::InvalidPacket),
+                    &mut self.events,
+                    &mut self.statistics,
+                );
                 return actions![];
             }
         };
@@ -238,30 +970,120 @@ impl<'a, C: Clock, F: Filter, R: Rng> Port<Running<'a, C, F>, R> {
             return actions![];
         }
 
-        let actions = self.port_state.handle_event_receive(
-            message,
-            timestamp,
-            self.config.min_delay_req_interval(),
+        if !self.check_unicast_flag(message.header(), arrival) {
+            return actions![];
+        }
+
+        if !self.authentication.verify(
+            data,
+            &self.lifecycle.state,
             self.port_identity,
-            &mut self.packet_buffer,
-        );
+            &mut self.statistics.authentication,
+        ) {
+            return actions![];
+        }
+
+        if self.is_identity_collision(&message, arrival) {
+            return self.handle_identity_collision(&message);
+        }
+
+        self.statistics
+            .message_rates
+            .received
+            .record(message.content_type(), timestamp);
+
+        if let Message::PDelayReq(request) = message {
+            return self.handle_pdelay_req(request, timestamp);
+        }
+
+        // A Sync from a fixed master restarts its sync receipt timeout
+        let sync_receipt_duration = self
+            .fixed_master
+            .filter(|_| self.is_fixed_master_sync(&message))
+            .map(|fixed_master| self.sync_receipt_duration(&fixed_master));
+
+        // Pretend the messages of the master took the simulated path
+        let timestamp = match (self.simulated_path, &self.port_state, &message) {
+            (Some(path), PortState::Slave(_), Message::Sync(_) | Message::PDelayResp(_)) => {
+                timestamp + path.master_to_slave()
+            }
+            _ => timestamp,
+        };
+
+        let mut actions = match message {
+            Message::PDelayResp(response) if self.gptp.is_some() => {
+                let timestamp = timestamp - self.config.ingress_latency;
+                let diagnostic = self.update_gptp_link(|peer_delay| {
+                    peer_delay.handle_resp(response, timestamp, source)
+                });
+                report_diagnostics(
+                    &mut self.port_state,
+                    diagnostic,
+                    &mut self.events,
+                    &mut self.statistics,
+                );
+                actions![]
+            }
+            message => self.port_state.handle_event_receive(
+                message,
+                timestamp,
+                source,
+                &self.config,
+                self.port_identity,
+                &self.lifecycle.state.local_clock,
+                &mut self.statistics,
+                &mut self.packet_buffer,
+            ),
+        };
+        if let Some(duration) = sync_receipt_duration {
+            // A slave takes no other actions on a Sync, so this always fits
+            let _ = actions.defer(PortAction::ResetAnnounceReceiptTimer { duration });
+        }
 
-        handle_time_measurement(
+        let diagnostic = handle_time_measurement(
             &mut self.port_state,
+            self.port_identity,
+            &mut self.measurements,
+            &mut self.statistics,
+            &mut self.time_error,
+            &mut self.frequency,
+            &mut self.timeline,
+            &mut self.windows,
+            &mut self.events,
             &self.lifecycle.state.filter,
             &self.lifecycle.state.local_clock,
+            &self.lifecycle.state.frequency_multiplier,
+            &self.lifecycle.state.clock_steps,
             &self.lifecycle.state.time_properties_ds,
+            self.lifecycle.state.free_run.load(Ordering::Relaxed),
         );
-
-        actions
+        finish_actions!(self, actions, diagnostic)
     }
 
     // Handle a general ptp message
     pub fn handle_general_receive(&mut self, data: &[u8]) -> PortActionIterator {
+        self.handle_general_receive_via(data, PacketArrival::default())
+    }
+
+    /// Like [`handle_general_receive`](Self::handle_general_receive), for a
+    /// packet that reached the port as described by `arrival`
+    pub fn handle_general_receive_via(
+        &mut self,
+        data: &[u8],
+        arrival: PacketArrival,
+    ) -> PortActionIterator<'_> {
+        self.check_clock_generation();
+
         let message = match Message::deserialize(data) {
             Ok(message) => message,
             Err(error) => {
-                log::warn!("Could not parse packet: {:?}", error);
+                log::warn!(port: self.port_identity, "Could not parse packet: {:?}", error);
+                report_diagnostics(
+                    &mut self.port_state,
+                    Some(Diagnostic::InvalidPacket),
+                    &mut self.events,
+                    &mut self.statistics,
+                );
                 return actions![];
             }
         };
@@ -273,297 +1095,6208 @@ impl<'a, C: Clock, F: Filter, R: Rng> Port<Running<'a, C, F>, R> {
             return actions![];
         }
 
-        let action = match message {
-            Message::Announce(announce) => {
-                self.bmca.register_announce_message(
-                    &announce,
-                    self.lifecycle.state.local_clock.borrow().now().into(),
-                );
-                actions![PortAction::ResetAnnounceReceiptTimer {
-                    duration: self.config.announce_duration(&mut self.rng),
-                }]
-            }
-            _ => {
-                self.port_state
-                    .handle_general_receive(message, self.port_identity);
-                actions![]
+        if !self.check_unicast_flag(message.header(), arrival) {
+            return actions![];
+        }
+
+        if !self.authentication.verify(
+            data,
+            &self.lifecycle.state,
+            self.port_identity,
+            &mut self.statistics.authentication,
+        ) {
+            return actions![];
+        }
+
+        if self.is_identity_collision(&message, arrival) {
+            return self.handle_identity_collision(&message);
+        }
+
+        let now = self.lifecycle.state.local_clock.borrow().now();
+        self.statistics
+            .message_rates
+            .received
+            .record(message.content_type(), now);
+
+        if let Message::Management(management) = &message {
+            return self.handle_management(management);
+        }
+
+        if matches!(self.port_state, PortState::Disabled) {
+            return actions![];
+        }
+
+        if let (Some(extension), Message::Announce(_) | Message::Signaling(_)) =
+            (self.organization_extension, &message)
+        {
+            consume_organization_tlvs(
+                extension,
+                message.content_type(),
+                message.header().source_port_identity,
+                data,
+            );
+        }
+
+        if let Message::Signaling(signaling) = &message {
+            return self.handle_signaling(signaling);
+        }
+
+        let action = match message {
+            // A port with a fixed master doesn't need announce messages
+            Message::Announce(_) if self.fixed_master.is_some() => actions![],
+            Message::Announce(announce) => {
+                self.check_parent_profile(announce.header.source_port_identity, data);
+                self.bmca.register_announce_message(
+                    &announce,
+                    self.lifecycle.state.local_clock.borrow().now().into(),
+                );
+                // A master-only port never takes time from other masters, so
+                // their presence shouldn't keep it from becoming master
+                if self.config.master_only {
+                    actions![]
+                } else {
+                    actions![PortAction::ResetAnnounceReceiptTimer {
+                        duration: self.config.announce_duration(&mut self.rng),
+                    }]
+                }
+            }
+            Message::PDelayRespFollowUp(follow_up) if self.gptp.is_some() => {
+                let diagnostic =
+                    self.update_gptp_link(|peer_delay| peer_delay.handle_resp_follow_up(follow_up));
+                report_diagnostics(
+                    &mut self.port_state,
+                    diagnostic,
+                    &mut self.events,
+                    &mut self.statistics,
+                );
+                actions![]
+            }
+            _ => {
+                self.port_state
+                    .handle_general_receive(message, self.port_identity);
+                actions![]
             }
         };
 
-        handle_time_measurement(
+        let diagnostic = handle_time_measurement(
             &mut self.port_state,
+            self.port_identity,
+            &mut self.measurements,
+            &mut self.statistics,
+            &mut self.time_error,
+            &mut self.frequency,
+            &mut self.timeline,
+            &mut self.windows,
+            &mut self.events,
             &self.lifecycle.state.filter,
             &self.lifecycle.state.local_clock,
+            &self.lifecycle.state.frequency_multiplier,
+            &self.lifecycle.state.clock_steps,
             &self.lifecycle.state.time_properties_ds,
+            self.lifecycle.state.free_run.load(Ordering::Relaxed),
         );
+        finish_actions!(self, action, diagnostic)
+    }
+
+    // Warn when the master of this slave port advertises another profile than
+    // the instance
+    fn check_parent_profile(&mut self, source: PortIdentity, data: &[u8]) {
+        let Some(local) = self.lifecycle.state.profile_identifier else {
+            return;
+        };
+        match &self.port_state {
+            PortState::Slave(slave) if slave.remote_master() == source => {}
+            _ => return,
+        }
+        let Some(advertised) = ProfileIdentifier::find_in(data) else {
+            return;
+        };
+
+        let changed = self.parent_profile != Some((source, advertised));
+        self.parent_profile = Some((source, advertised));
+        if advertised == local {
+            return;
+        }
 
-        action
+        self.statistics.profile_mismatches = self.statistics.profile_mismatches.saturating_add(1);
+        if changed {
+            log::warn!(
+                port: self.port_identity,
+                "Master {:?} advertises profile {}, not {}",
+                source,
+                advertised,
+                local
+            );
+            let event = PortEvent::ParentProfileMismatch { local, advertised };
+            if self.events.push(event) {
+                self.statistics.events_dropped = self.statistics.events_dropped.wrapping_add(1);
+            }
+        }
     }
 
-    // Start a BMCA cycle and ensure this happens instantly from the perspective of
-    // the port
-    pub fn start_bmca(self) -> Port<InBmca<'a, C, F>, R> {
-        Port {
-            port_state: self.port_state,
-            config: self.config,
-            port_identity: self.port_identity,
-            bmca: self.bmca,
-            rng: self.rng,
-            packet_buffer: [0; MAX_DATA_LEN],
-            lifecycle: InBmca {
-                pending_action: actions![],
-                local_best: None,
-                state_refcell: self.lifecycle.state_refcell,
-            },
+    // Messages whose unicast flag doesn't match where they were sent to are
+    // reflected or misrouted, so ignore them
+    fn check_unicast_flag(&mut self, header: &Header, arrival: PacketArrival) -> bool {
+        match (arrival.destination, header.unicast_flag) {
+            (Some(Destination::Multicast), true) => {
+                log::debug!(port: self.port_identity, "Ignoring unicast message sent to multicast");
+                self.statistics.unexpected_unicast_messages =
+                    self.statistics.unexpected_unicast_messages.wrapping_add(1);
+                false
+            }
+            (Some(Destination::Unicast), false) => {
+                log::debug!(port: self.port_identity, "Ignoring multicast message sent to unicast");
+                self.statistics.unexpected_multicast_messages = self
+                    .statistics
+                    .unexpected_multicast_messages
+                    .wrapping_add(1);
+                false
+            }
+            _ => true,
         }
     }
-}
 
-impl<'a, C, F, R> Port<InBmca<'a, C, F>, R> {
-    // End a BMCA cycle and make the port available again
-    pub fn end_bmca(self) -> (Port<Running<'a, C, F>, R>, PortActionIterator<'static>) {
-        (
-            Port {
-                port_state: self.port_state,
-                config: self.config,
-                port_identity: self.port_identity,
-                bmca: self.bmca,
-                rng: self.rng,
-                packet_buffer: [0; MAX_DATA_LEN],
-                lifecycle: Running {
-                    state_refcell: self.lifecycle.state_refcell,
-                    state: self.lifecycle.state_refcell.borrow(),
-                },
-            },
-            self.lifecycle.pending_action,
-        )
+    // Whether the message was sent by another clock with our clock identity,
+    // rather than by one of the ports of this instance
+    fn is_identity_collision(&self, message: &Message, arrival: PacketArrival) -> bool {
+        let source = message.header().source_port_identity;
+        let default_ds = &self.lifecycle.state.default_ds;
+        if source.clock_identity != default_ds.clock_identity {
+            return false;
+        }
+
+        if source.port_number == self.port_identity.port_number {
+            return match (arrival.source, message) {
+                // This port sends from its own address, so a message with its
+                // port number from any other address is sent by another clock
+                (Some(source), _) => source == SourceAddress::Other,
+                // Otherwise, only the last announce message we sent can loop
+                // back to us
+                (None, Message::Announce(announce)) => {
+                    let last_sent = self.statistics.last_announce.map(|last| last.sequence_id);
+                    last_sent != Some(announce.header.sequence_id)
+                }
+                (None, _) => false,
+            };
+        }
+
+        // The ports of this instance are numbered from 0. Their messages can
+        // come from the addresses of other interfaces.
+        source.port_number >= default_ds.number_ports
     }
-}
 
-impl<L, R> Port<L, R> {
-    fn set_forced_port_state(&mut self, state: PortState) {
-        log::info!(
-            "new state for port {}: {} -> {}",
-            self.port_identity.port_number,
-            self.port_state,
-            state
+    fn handle_identity_collision(&mut self, message: &Message) -> PortActionIterator<'_> {
+        let port_number = message.header().source_port_identity.port_number;
+        let message_type = message.content_type();
+
+        self.statistics.identity_collisions = self.statistics.identity_collisions.saturating_add(1);
+        log::error!(
+            port: self.port_identity,
+            "Received {:?} message from another clock with our clock identity, as port {}",
+            message_type,
+            port_number
         );
-        self.port_state = state;
+        let event = PortEvent::ClockIdentityCollision {
+            port_number,
+            message_type,
+        };
+        if self.events.push(event) {
+            self.statistics.events_dropped = self.statistics.events_dropped.wrapping_add(1);
+        }
+
+        match self.identity_collision {
+            IdentityCollisionResponse::Report => actions![],
+            IdentityCollisionResponse::Faulty => {
+                if !matches!(self.port_state, PortState::Faulty | PortState::Disabled) {
+                    let now = self.lifecycle.state.local_clock.borrow().now();
+                    self.set_forced_port_state(PortState::Faulty, now);
+                }
+                // Try again when the announce receipt timer expires
+                let duration = self.config.announce_duration(&mut self.rng);
+                actions![PortAction::ResetAnnounceReceiptTimer { duration }]
+            }
+        }
     }
 
-    pub(crate) fn state(&self) -> &PortState {
-        &self.port_state
+    fn is_unicast_master(&self) -> bool {
+        self.config.communication_mode == CommunicationMode::Unicast && self.unicast.is_enabled()
     }
 
-    pub(crate) fn number(&self) -> u16 {
-        self.port_identity.port_number
+    // Answer a peer delay request as a one- or two-step responder (11.4.3).
+    // Unlike delay requests, these are answered in every state but faulty and
+    // disabled.
+    fn handle_pdelay_req(
+        &mut self,
+        request: PDelayReqMessage,
+        timestamp: Time,
+    ) -> PortActionIterator<'_> {
+        let DelayMechanism::P2P {
+            one_step_responder, ..
+        } = self.config.delay_mechanism
+        else {
+            return actions![];
+        };
+        if !self.config.transmit.delay_resp
+            || matches!(self.port_state, PortState::Faulty | PortState::Disabled)
+        {
+            return actions![];
+        }
+
+        // Our own requests can loop back to us
+        if request.header.source_port_identity == self.port_identity {
+            return actions![];
+        }
+
+        log::debug!(port: self.port_identity, "Received PdelayReq");
+        let timestamp = timestamp - self.config.ingress_latency;
+
+        let actions = match peer_delay::respond(
+            &request,
+            timestamp,
+            one_step_responder,
+            self.port_identity,
+            &self.config.log_message_intervals,
+            &mut self.packet_buffer,
+        ) {
+            Ok(actions) => actions,
+            Err(error) => {
+                log::error!(
+                    port: self.port_identity,
+                    "Statime bug: Could not serialize peer delay response {:?}",
+                    error
+                );
+                report_diagnostics(
+                    &mut self.port_state,
+                    Some(Diagnostic::SerializationFailed),
+                    &mut self.events,
+                    &mut self.statistics,
+                );
+                return actions![];
+            }
+        };
+        finish_actions!(self, actions, None)
     }
-}
 
-impl<'a, C, F, R: Rng> Port<InBmca<'a, C, F>, R> {
-    pub(crate) fn calculate_best_local_announce_message(&mut self, current_time: WireTimestamp) {
-        self.lifecycle.local_best = self.bmca.take_best_port_announce_message(current_time)
+    // The PdelayRespFollowUp with the time the PdelayResp was sent
+    fn send_pdelay_resp_follow_up(
+        &mut self,
+        id: u16,
+        requesting_port_identity: PortIdentity,
+        correction: TimeInterval,
+        timestamp: Time,
+    ) -> PortActionIterator<'_> {
+        let actions = match peer_delay::follow_up(
+            &self.lifecycle.state.default_ds,
+            self.port_identity,
+            requesting_port_identity,
+            id,
+            correction,
+            timestamp,
+            &self.config.log_message_intervals,
+            &mut self.packet_buffer,
+        ) {
+            Ok(actions) => actions,
+            Err(error) => {
+                log::error!(
+                    port: self.port_identity,
+                    "Statime bug: Could not serialize peer delay response follow up {:?}",
+                    error
+                );
+                report_diagnostics(
+                    &mut self.port_state,
+                    Some(Diagnostic::SerializationFailed),
+                    &mut self.events,
+                    &mut self.statistics,
+                );
+                return actions![];
+            }
+        };
+        finish_actions!(self, actions, None)
     }
 
-    pub(crate) fn best_local_announce_message(&self) -> Option<BestAnnounceMessage> {
-        // Announce messages received on a masterOnly PTP Port shall not be considered
-        // in the operation of the best master clock algorithm or in the update
-        // of data sets.
-        if self.config.master_only {
-            None
-        } else {
-            self.lifecycle.local_best
+    // In gPTP mode the port measures the link to its neighbor itself, in
+    // every state but faulty and disabled, as the link has to be asCapable
+    // before the port takes part in the BMCA
+    fn send_gptp_peer_delay_request(&mut self, interval: Interval) -> PortActionIterator<'_> {
+        let Some(link) = &mut self.gptp else {
+            return actions![];
+        };
+        if !self.config.transmit.delay_req
+            || matches!(self.port_state, PortState::Faulty | PortState::Disabled)
+        {
+            let duration = interval.as_core_duration();
+            return actions![PortAction::ResetDelayRequestTimer { duration }];
+        }
+
+        let was_as_capable = link.as_capable();
+        link.check_lost_response();
+        if link.as_capable() != was_as_capable {
+            self.handle_as_capable_change(false);
         }
+
+        let Some(link) = &mut self.gptp else {
+            return actions![];
+        };
+        let actions = link.peer_delay.send_request(
+            interval,
+            self.lifecycle.state.current_frequency_multiplier(),
+            &self.config.log_message_intervals,
+            self.port_identity,
+            &self.lifecycle.state.default_ds,
+            &mut self.packet_buffer,
+        );
+        let diagnostic = link.peer_delay.diagnostic.take();
+        finish_actions!(self, actions, diagnostic)
     }
 
-    pub(crate) fn set_recommended_state(
+    // Pass an event to the peer delay exchange of the port in gPTP mode, and
+    // judge the link by what it measured. Returns the problem it noticed, if
+    // any.
+    fn update_gptp_link(
         &mut self,
-        recommended_state: RecommendedState,
-        time_properties_ds: &mut TimePropertiesDS,
-        current_ds: &mut CurrentDS,
-        parent_ds: &mut ParentDS,
-        default_ds: &DefaultDS,
-    ) {
-        self.set_recommended_port_state(&recommended_state, default_ds);
+        exchange: impl FnOnce(&mut PeerDelay) -> Option<LinkDelay>,
+    ) -> Option<Diagnostic> {
+        let link = self.gptp.as_mut()?;
+        let link_delay = exchange(&mut link.peer_delay);
+        let diagnostic = link.peer_delay.diagnostic.take();
+        let Some(link_delay) = link_delay else {
+            return diagnostic;
+        };
 
-        match recommended_state {
-            RecommendedState::M1(defaultds) | RecommendedState::M2(defaultds) => {
-                // a slave-only PTP port should never end up in the master state
-                debug_assert!(!default_ds.slave_only);
+        let was_as_capable = link.as_capable();
+        link.update(&link_delay);
+        let as_capable = link.as_capable();
 
-                current_ds.steps_removed = 0;
-                current_ds.offset_from_master = Duration::ZERO;
-                current_ds.mean_delay = Duration::ZERO;
+        // The slave measures its offset with the delay of the link
+        if let PortState::Slave(slave) = &mut self.port_state {
+            slave.set_link_delay(link_delay);
+        }
+        if as_capable != was_as_capable {
+            self.handle_as_capable_change(as_capable);
+        }
+        diagnostic
+    }
 
-                parent_ds.parent_port_identity.clock_identity = defaultds.clock_identity;
-                parent_ds.parent_port_identity.port_number = 0;
-                parent_ds.grandmaster_identity = defaultds.clock_identity;
-                parent_ds.grandmaster_clock_quality = defaultds.clock_quality;
-                parent_ds.grandmaster_priority_1 = defaultds.priority_1;
-                parent_ds.grandmaster_priority_2 = defaultds.priority_2;
+    // The sync receipt timeout of a port with a fixed master, which runs on
+    // the announce receipt timer. After the port gave up on the master, its
+    // expiry makes the port try again.
+    fn handle_sync_receipt_timer(
+        &mut self,
+        fixed_master: FixedMasterConfig,
+        now: Time,
+    ) -> PortActionIterator<'_> {
+        let duration = self.sync_receipt_duration(&fixed_master);
 
-                time_properties_ds.leap_indicator = LeapIndicator::NoLeap;
-                time_properties_ds.current_utc_offset = None;
-                time_properties_ds.ptp_timescale = true;
-                time_properties_ds.time_traceable = false;
-                time_properties_ds.frequency_traceable = false;
-                time_properties_ds.time_source = TimeSource::InternalOscillator;
-            }
-            RecommendedState::M3(_) | RecommendedState::P1(_) | RecommendedState::P2(_) => {}
-            RecommendedState::S1(announce_message) => {
-                // a master-only PTP port should never end up in the slave state
-                debug_assert!(!self.config.master_only);
+        if matches!(self.port_state, PortState::Slave(_)) {
+            log::warn!(
+                port: self.port_identity,
+                "No sync messages from fixed master within {:?}",
+                duration
+            );
+            self.timeline.record(now, TimelineEvent::SyncReceiptTimeout);
+            self.set_forced_port_state(PortState::Listening, now);
+            return actions![PortAction::ResetAnnounceReceiptTimer { duration }];
+        }
 
-                current_ds.steps_removed = announce_message.steps_removed + 1;
+        let default_ds = self.lifecycle.state.default_ds;
+        self.enter_fixed_master(fixed_master, &default_ds, now)
+    }
 
-                parent_ds.parent_port_identity = announce_message.header.source_port_identity;
-                parent_ds.grandmaster_identity = announce_message.grandmaster_identity;
-                parent_ds.grandmaster_clock_quality = announce_message.grandmaster_clock_quality;
-                parent_ds.grandmaster_priority_1 = announce_message.grandmaster_priority_1;
-                parent_ds.grandmaster_priority_2 = announce_message.grandmaster_priority_2;
+    // The fixed master left the last delay requests unanswered, so the port
+    // gives up on it like on one that stopped sending Sync messages
+    fn handle_delay_receipt_timeout(
+        &mut self,
+        fixed_master: FixedMasterConfig,
+    ) -> PortActionIterator<'_> {
+        let now = self.lifecycle.state.local_clock.borrow().now();
+        log::warn!(
+            port: self.port_identity,
+            "No delay responses from fixed master to the last {} requests",
+            fixed_master.delay_receipt_timeout
+        );
+        self.timeline
+            .record(now, TimelineEvent::DelayReceiptTimeout);
+        self.set_forced_port_state(PortState::Listening, now);
 
-                *time_properties_ds = announce_message.time_properties();
-            }
+        let duration = self.sync_receipt_duration(&fixed_master);
+        actions![PortAction::ResetAnnounceReceiptTimer { duration }]
+    }
+
+    fn handle_as_capable_change(&mut self, as_capable: bool) {
+        if self.events.push(PortEvent::AsCapableChanged { as_capable }) {
+            self.statistics.events_dropped = self.statistics.events_dropped.wrapping_add(1);
         }
 
-        // TODO: Discuss if we should change the clock's own time properties, or keep
-        // the master's time properties separately
-        if let RecommendedState::S1(announce_message) = &recommended_state {
-            // Update time properties
-            *time_properties_ds = announce_message.time_properties();
+        // Without a link to transport time over, the port neither takes time
+        // from a master nor gives it to slaves
+        if !as_capable
+            && matches!(
+                self.port_state,
+                PortState::Master(_) | PortState::Slave(_) | PortState::Passive
+            )
+        {
+            let now = self.lifecycle.state.local_clock.borrow().now();
+            self.set_forced_port_state(PortState::Listening, now);
         }
     }
 
-    fn set_recommended_port_state(
-        &mut self,
-        recommended_state: &RecommendedState,
-        default_ds: &DefaultDS,
-    ) {
-        match recommended_state {
-            // TODO set things like steps_removed once they are added
-            // TODO make sure states are complete
-            RecommendedState::S1(announce_message) => {
-                // a master-only PTP port should never end up in the slave state
-                debug_assert!(!self.config.master_only);
+    // Answer a management message meant for this port (15.3.3). These are
+    // not forwarded to the other ports of a boundary clock.
+    fn handle_management(&mut self, request: &ManagementMessage) -> PortActionIterator<'_> {
+        if !self.config.transmit.management
+            || !self
+                .port_identity
+                .is_targeted_by(request.target_port_identity())
+        {
+            return actions![];
+        }
 
-                let remote_master = announce_message.header.source_port_identity;
-                let state = PortState::Slave(SlaveState::new(remote_master));
+        // Responses and acknowledgements are meant for management nodes
+        let Some(action) = request.action().reply() else {
+            return actions![];
+        };
+        let id = request.tlv().id();
+        log::debug!(
+            port: self.port_identity,
+            "Received management {:?} of {:?}",
+            request.action(),
+            id
+        );
 
-                let update_state = match &self.port_state {
-                    PortState::Listening | PortState::Master(_) | PortState::Passive => true,
-                    PortState::Slave(old_state) => old_state.remote_master() != remote_master,
-                };
+        let (tlv, timer) = match request.tlv() {
+            ManagementTlv::Management { data, .. } => self.management_action(request, data),
+            // Only replies carry an error status
+            ManagementTlv::ErrorStatus { .. } => {
+                let error = ManagementErrorId::GeneralError;
+                (ManagementTlv::ErrorStatus { error, id }, None)
+            }
+        };
 
-                if update_state {
-                    self.set_forced_port_state(state);
+        let intervals = self.config.log_message_intervals;
+        let reply = Message::management_reply(request, self.port_identity, action, tlv, &intervals);
+        let length = match reply.serialize(&mut self.packet_buffer) {
+            Ok(length) => length,
+            Err(error) => {
+                log::error!(
+                    port: self.port_identity,
+                    "Statime bug: Could not serialize management reply {:?}",
+                    error
+                );
+                report_diagnostics(
+                    &mut self.port_state,
+                    Some(Diagnostic::SerializationFailed),
+                    &mut self.events,
+                    &mut self.statistics,
+                );
 
-                    let duration = self.config.announce_duration(&mut self.rng);
-                    let reset_announce = PortAction::ResetAnnounceReceiptTimer { duration };
-                    let reset_delay = PortAction::ResetDelayRequestTimer { duration };
-                    self.lifecycle.pending_action = actions![reset_announce, reset_delay];
+                // Still tell the management node, an error status always fits
+                let error = ManagementErrorId::ResponseTooBig;
+                let tlv = ManagementTlv::ErrorStatus { error, id };
+                let reply =
+                    Message::management_reply(request, self.port_identity, action, tlv, &intervals);
+                match reply.serialize(&mut self.packet_buffer) {
+                    Ok(length) => length,
+                    Err(_) => return actions![],
                 }
             }
-            RecommendedState::M1(_) | RecommendedState::M2(_) | RecommendedState::M3(_) => {
-                if default_ds.slave_only {
-                    match self.port_state {
-                        PortState::Listening => { /* do nothing */ }
-                        PortState::Slave(_) | PortState::Passive => {
-                            self.set_forced_port_state(PortState::Listening);
+        };
 
-                            // consistent with Port<InBmca>::new()
-                            let duration = self.config.announce_duration(&mut self.rng);
-                            let reset_announce = PortAction::ResetAnnounceReceiptTimer { duration };
-                            self.lifecycle.pending_action = actions![reset_announce];
-                        }
-                        PortState::Master(_) => {
-                            let msg = "slave-only PTP port should not be in master state";
-                            debug_assert!(!default_ds.slave_only, "{msg}");
-                            log::error!("{msg}");
-                        }
-                    }
-                } else {
-                    match self.port_state {
-                        PortState::Listening | PortState::Slave(_) | PortState::Passive => {
-                            self.set_forced_port_state(PortState::Master(MasterState::new()));
+        let reply = PortAction::SendGeneral {
+            data: &self.packet_buffer[..length],
+        };
+        let actions = match timer {
+            Some(timer) => actions![reply, timer],
+            None => actions![reply],
+        };
+        finish_actions!(self, actions, None)
+    }
 
-                            // Immediately start sending announces and syncs
-                            let duration = core::time::Duration::from_secs(0);
-                            self.lifecycle.pending_action = actions![
-                                PortAction::ResetAnnounceTimer { duration },
-                                PortAction::ResetSyncTimer { duration }
-                            ];
-                        }
-                        PortState::Master(_) => { /* do nothing */ }
-                    }
+    // The TLV of the reply to `request` with the management `data` it carries,
+    // and the timer to reset for it
+    fn management_action(
+        &mut self,
+        request: &ManagementMessage,
+        data: &[u8],
+    ) -> (ManagementTlv, Option<PortAction<'static>>) {
+        let id = request.tlv().id();
+        if !self.config.management.allows(request) {
+            log::info!(
+                port: self.port_identity,
+                "Refusing management {:?} of {:?}, as the management policy is {:?}",
+                request.action(),
+                id,
+                self.config.management
+            );
+            let error = match request.action() {
+                ManagementAction::SET => ManagementErrorId::NotSetable,
+                _ => ManagementErrorId::NotSupported,
+            };
+            return (ManagementTlv::ErrorStatus { error, id }, None);
+        }
+
+        match request.action() {
+            ManagementAction::GET => (self.management_get(id), None),
+            ManagementAction::SET => (self.management_set(id, data), None),
+            _ => self.management_command(id),
+        }
+    }
+
+    // The TLV of the reply to a GET of `id`
+    fn management_get(&self, id: ManagementId) -> ManagementTlv {
+        let state = &self.lifecycle.state;
+        let tlv = match id {
+            // Carries nothing, but shows the port is there (15.5.3.1.1)
+            ManagementId::NullPtpManagement => {
+                return ManagementTlv::Management {
+                    id,
+                    data: ArrayVec::new(),
                 }
             }
-            RecommendedState::P1(_) | RecommendedState::P2(_) => match self.port_state {
-                PortState::Listening | PortState::Slave(_) | PortState::Master(_) => {
-                    self.set_forced_port_state(PortState::Passive)
+            ManagementId::DefaultDataSet => ManagementTlv::default_data_set(&state.default_ds),
+            ManagementId::CurrentDataSet => ManagementTlv::current_data_set(&state.current_ds),
+            ManagementId::ParentDataSet => ManagementTlv::parent_data_set(&state.parent_ds),
+            ManagementId::TimePropertiesDataSet => {
+                ManagementTlv::time_properties_data_set(&state.time_properties_ds)
+            }
+            ManagementId::PortDataSet => {
+                // Only measured by the peer delay mechanism (8.2.15.3.3)
+                let peer_mean_path_delay = match (&self.port_state, self.config.delay_mechanism) {
+                    (
+                        PortState::Slave(slave),
+                        DelayMechanism::P2P { .. } | DelayMechanism::CommonP2P,
+                    ) => slave.mean_delay().unwrap_or(Duration::ZERO),
+                    _ => Duration::ZERO,
+                };
+                ManagementTlv::port_data_set(
+                    self.port_identity,
+                    self.port_state.kind(),
+                    &self.config,
+                    peer_mean_path_delay,
+                )
+            }
+            // Report a change that waits for the next BMCA run as made
+            ManagementId::Priority1 => Ok(ManagementTlv::value(
+                id,
+                (state.pending_priority_1.get()).unwrap_or(state.default_ds.priority_1),
+            )),
+            ManagementId::Priority2 => Ok(ManagementTlv::value(
+                id,
+                (state.pending_priority_2.get()).unwrap_or(state.default_ds.priority_2),
+            )),
+            ManagementId::Domain => Ok(ManagementTlv::value(
+                id,
+                (state.pending_domain_number.get()).unwrap_or(state.default_ds.domain_number),
+            )),
+            ManagementId::LogAnnounceInterval => Ok(ManagementTlv::value(
+                id,
+                self.config.announce_interval.as_log_2() as u8,
+            )),
+            ManagementId::LogSyncInterval => Ok(ManagementTlv::value(
+                id,
+                self.config.sync_interval.as_log_2() as u8,
+            )),
+            ManagementId::LogMinPdelayReqInterval => match self.config.delay_mechanism {
+                DelayMechanism::P2P { interval, .. } => {
+                    Ok(ManagementTlv::value(id, interval.as_log_2() as u8))
+                }
+                _ => {
+                    return ManagementTlv::ErrorStatus {
+                        error: ManagementErrorId::NotSupported,
+                        id,
+                    }
                 }
-                PortState::Passive => {}
             },
+            ManagementId::Other(_) => {
+                return ManagementTlv::ErrorStatus {
+                    error: ManagementErrorId::NoSuchId,
+                    id,
+                }
+            }
+            _ => {
+                return ManagementTlv::ErrorStatus {
+                    error: ManagementErrorId::NotSupported,
+                    id,
+                }
+            }
+        };
+
+        tlv.unwrap_or_else(|error| {
+            log::error!(
+                port: self.port_identity,
+                "Statime bug: Could not serialize management data {:?}",
+                error
+            );
+            let error = match error {
+                WireFormatError::CapacityError => ManagementErrorId::ResponseTooBig,
+                _ => ManagementErrorId::GeneralError,
+            };
+            ManagementTlv::ErrorStatus { error, id }
+        })
+    }
+
+    // The TLV of the reply to a SET of `id` to `data`. Changes to the instance
+    // take effect at the next BMCA run, those to the port right away.
+    fn management_set(&mut self, id: ManagementId, data: &[u8]) -> ManagementTlv {
+        let error = |error| ManagementTlv::ErrorStatus { error, id };
+
+        let settable = matches!(
+            id,
+            ManagementId::Priority1
+                | ManagementId::Priority2
+                | ManagementId::Domain
+                | ManagementId::LogAnnounceInterval
+                | ManagementId::LogSyncInterval
+                | ManagementId::LogMinPdelayReqInterval
+        );
+        if !settable {
+            return match id {
+                ManagementId::Other(_) => error(ManagementErrorId::NoSuchId),
+                _ => error(ManagementErrorId::NotSetable),
+            };
+        }
+
+        // Each of these is a single byte followed by a reserved one
+        let [value, _] = *data else {
+            return error(ManagementErrorId::WrongLength);
+        };
+
+        let state = &self.lifecycle.state;
+        match id {
+            ManagementId::Priority1 => {
+                // A slave-only clock must never be preferred (9.2.2.1)
+                if state.default_ds.slave_only && value != 255 {
+                    return error(ManagementErrorId::WrongValue);
+                }
+                state.pending_priority_1.set(value);
+                state.request_bmca();
+            }
+            ManagementId::Priority2 => {
+                state.pending_priority_2.set(value);
+                state.request_bmca();
+            }
+            ManagementId::Domain => {
+                state.pending_domain_number.set(value);
+                state.request_bmca();
+            }
+            _ => {
+                let log_interval = value as i8;
+                if !MANAGEMENT_LOG_INTERVALS.contains(&log_interval) {
+                    return error(ManagementErrorId::WrongValue);
+                }
+
+                let interval = Interval::from_log_2(log_interval);
+                match (id, &mut self.config.delay_mechanism) {
+                    (ManagementId::LogAnnounceInterval, _) => {
+                        self.config.announce_interval = interval
+                    }
+                    (ManagementId::LogSyncInterval, _) => self.config.sync_interval = interval,
+                    (
+                        _,
+                        DelayMechanism::P2P {
+                            interval: pdelay, ..
+                        },
+                    ) => *pdelay = interval,
+                    _ => return error(ManagementErrorId::NotSupported),
+                }
+            }
         }
+
+        log::info!(
+            port: self.port_identity,
+            "Set {:?} to {} with a management message",
+            id,
+            value
+        );
+        ManagementTlv::value(id, value)
     }
-}
 
-impl<'a, C, F, R: Rng> Port<InBmca<'a, C, F>, R> {
-    /// Create a new port from a port dataset on a given interface.
-    pub(crate) fn new(
-        state_refcell: &'a AtomicRefCell<PtpInstanceState<C, F>>,
-        config: PortConfig,
-        port_identity: PortIdentity,
-        mut rng: R,
-    ) -> Self {
-        let bmca = Bmca::new(config.announce_interval.as_duration().into(), port_identity);
+    // The TLV of the reply to a COMMAND of `id`, and the timer to reset for it
+    fn management_command(
+        &mut self,
+        id: ManagementId,
+    ) -> (ManagementTlv, Option<PortAction<'static>>) {
+        let mut timer = None;
+        match id {
+            ManagementId::NullPtpManagement => {}
+            ManagementId::EnablePort => {
+                if matches!(self.port_state, PortState::Disabled) {
+                    log::info!(port: self.port_identity, "Enabled with a management message");
+                    let now = self.lifecycle.state.local_clock.borrow().now();
+                    self.set_forced_port_state(PortState::Listening, now);
+                    timer = Some(PortAction::ResetAnnounceReceiptTimer {
+                        duration: self.config.announce_duration(&mut self.rng),
+                    });
+                }
+            }
+            ManagementId::DisablePort => {
+                if !matches!(self.port_state, PortState::Disabled) {
+                    log::info!(port: self.port_identity, "Disabled with a management message");
+                    let now = self.lifecycle.state.local_clock.borrow().now();
+                    self.set_forced_port_state(PortState::Disabled, now);
+                    // The instance may have followed a master on this port
+                    self.lifecycle.state.request_bmca();
+                }
+            }
+            ManagementId::Other(_) => {
+                let error = ManagementErrorId::NoSuchId;
+                return (ManagementTlv::ErrorStatus { error, id }, None);
+            }
+            _ => {
+                let error = ManagementErrorId::NotSupported;
+                return (ManagementTlv::ErrorStatus { error, id }, None);
+            }
+        }
 
-        let duration = config.announce_duration(&mut rng);
+        // Commands are acknowledged without data (15.4.1.6)
+        let tlv = ManagementTlv::Management {
+            id,
+            data: ArrayVec::new(),
+        };
+        (tlv, timer)
+    }
+
+    // Act on the TLVs of a signaling message, see 13.12. Signaling messages
+    // don't involve the port state, so they never reach it.
+    fn handle_signaling(&mut self, message: &SignalingMessage) -> PortActionIterator<'_> {
+        if !self.config.transmit.signaling
+            || !self
+                .port_identity
+                .is_targeted_by(message.target_port_identity())
+        {
+            return actions![];
+        }
+
+        let source = message.header().source_port_identity;
+        // A port acts as unicast client unless it is master to its own clients
+        let is_unicast_client = self.is_unicast_client()
+            && !(self.is_unicast_master() && matches!(self.port_state, PortState::Master(_)));
+        let is_unicast_master = self.is_unicast_master() && !is_unicast_client;
+        for tlv in message.tlvs() {
+            let handled = match tlv.tlv_type {
+                TlvType::RequestUnicastTransmission
+                | TlvType::AcknowledgeCancelUnicastTransmission => is_unicast_master,
+                TlvType::GrantUnicastTransmission => is_unicast_client,
+                TlvType::CancelUnicastTransmission => is_unicast_master || is_unicast_client,
+                _ => false,
+            };
+            if !handled {
+                log::debug!(
+                    port: self.port_identity,
+                    "Ignoring {:?} TLV in signaling message from {:?}",
+                    tlv.tlv_type,
+                    source
+                );
+            }
+        }
+
+        if is_unicast_master {
+            self.handle_unicast_negotiation(source, message)
+        } else if is_unicast_client {
+            self.handle_unicast_grants(source, message)
+        } else {
+            actions![]
+        }
+    }
+
+    // Act on the answers of a master to the requests of this client, and on
+    // the end of its grants, see 16.1
+    fn handle_unicast_grants(
+        &mut self,
+        master: PortIdentity,
+        message: &SignalingMessage,
+    ) -> PortActionIterator<'_> {
+        let now = self.lifecycle.state.local_clock.borrow().now();
+        let counts = &mut self.statistics.unicast_requests;
+
+        let mut acknowledgements = ArrayVec::<UnicastNegotiation, 3>::new();
+        for negotiation in message.unicast_negotiations() {
+            match negotiation {
+                UnicastNegotiation::Grant {
+                    message_type,
+                    duration,
+                    ..
+                } => {
+                    let answered = self.unicast_client.handle_grant(
+                        master,
+                        message_type,
+                        duration,
+                        now,
+                        counts,
+                    );
+                    if answered && duration == 0 {
+                        log::info!(
+                            port: self.port_identity,
+                            "Unicast master {:?} denied {:?} messages",
+                            master,
+                            message_type
+                        );
+                    }
+                }
+                UnicastNegotiation::Cancel { message_type } => {
+                    self.unicast_client
+                        .handle_cancel(master, message_type, now, counts);
+                    if acknowledgements
+                        .try_push(UnicastNegotiation::AcknowledgeCancel { message_type })
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                // Only sent to masters
+                UnicastNegotiation::Request { .. }
+                | UnicastNegotiation::AcknowledgeCancel { .. } => {}
+            }
+        }
+
+        if acknowledgements.is_empty() {
+            return actions![];
+        }
+        let Some(length) = self.serialize_signaling(master, &acknowledgements) else {
+            return actions![];
+        };
+
+        let send = PortAction::SendUnicastGeneral {
+            data: &self.packet_buffer[..length],
+            clock_identity: master.clock_identity,
+            port_number: master.port_number,
+        };
+        finish_actions!(self, actions![send], None)
+    }
+
+    // Answer the requests for unicast transmission of a client, see 16.1
+    fn handle_unicast_negotiation(
+        &mut self,
+        client: PortIdentity,
+        message: &SignalingMessage,
+    ) -> PortActionIterator<'_> {
+        let now = self.lifecycle.state.local_clock.borrow().now();
+        let is_master = matches!(self.port_state, PortState::Master(_));
+        let counts = &mut self.statistics.unicast_grants;
+
+        let mut responses = ArrayVec::<UnicastNegotiation, 4>::new();
+        let mut granted_announce = false;
+        let mut granted_sync = false;
+        for negotiation in message.unicast_negotiations() {
+            let response = match negotiation {
+                UnicastNegotiation::Request {
+                    message_type,
+                    log_interval,
+                    duration,
+                } => {
+                    let duration = if is_master {
+                        self.unicast.request(
+                            client,
+                            message_type,
+                            log_interval,
+                            duration,
+                            now,
+                            counts,
+                        )
+                    } else {
+                        counts.denied = counts.denied.saturating_add(1);
+                        0
+                    };
+                    granted_announce |= duration > 0 && message_type == MessageType::Announce;
+                    granted_sync |= duration > 0 && message_type == MessageType::Sync;
+
+                    UnicastNegotiation::Grant {
+                        message_type,
+                        log_interval,
+                        duration,
+                        renewal_invited: duration > 0,
+                    }
+                }
+                UnicastNegotiation::Cancel { message_type } => {
+                    self.unicast.cancel(client, message_type, counts);
+                    UnicastNegotiation::AcknowledgeCancel { message_type }
+                }
+                UnicastNegotiation::AcknowledgeCancel { message_type } => {
+                    log::debug!(
+                        port: self.port_identity,
+                        "{:?} acknowledged the end of its {:?} grant",
+                        client,
+                        message_type
+                    );
+                    counts.acknowledged = counts.acknowledged.saturating_add(1);
+                    continue;
+                }
+                // Only sent to clients
+                UnicastNegotiation::Grant { .. } => continue,
+            };
+
+            log::debug!(port: self.port_identity, "Answering {:?} with {:?}", client, response);
+            if responses.try_push(response).is_err() {
+                break;
+            }
+        }
+
+        if responses.is_empty() {
+            return actions![];
+        }
+        let Some(length) = self.serialize_signaling(client, &responses) else {
+            return actions![];
+        };
+
+        let send = PortAction::SendUnicastGeneral {
+            data: &self.packet_buffer[..length],
+            clock_identity: client.clock_identity,
+            port_number: client.port_number,
+        };
+        // Start sending to a new client right away
+        let duration = core::time::Duration::ZERO;
+        let actions = match (granted_announce, granted_sync) {
+            (true, true) => actions![
+                send,
+                PortAction::ResetAnnounceTimer { duration },
+                PortAction::ResetSyncTimer { duration }
+            ],
+            (true, false) => actions![send, PortAction::ResetAnnounceTimer { duration }],
+            (false, true) => actions![send, PortAction::ResetSyncTimer { duration }],
+            (false, false) => actions![send],
+        };
+        finish_actions!(self, actions, None)
+    }
+
+    // Write a signaling message with the given unicast negotiation TLVs to
+    // `target` in the packet buffer, returning its length
+    fn serialize_signaling(
+        &mut self,
+        target: PortIdentity,
+        negotiations: &[UnicastNegotiation],
+    ) -> Option<usize> {
+        let message = Message::signaling(
+            &self.lifecycle.state.default_ds,
+            self.port_identity,
+            self.unicast.signaling_seq_ids.generate(target),
+            target,
+            negotiations,
+            &self.config.log_message_intervals,
+        );
+        match message.serialize(&mut self.packet_buffer) {
+            Ok(length) => Some(append_organization_tlvs(
+                self.organization_extension,
+                None,
+                MessageType::Signaling,
+                &mut self.packet_buffer,
+                length,
+            )),
+            Err(error) => {
+                log::error!(
+                    port: self.port_identity,
+                    "Statime bug: Could not serialize signaling message {:?}",
+                    error
+                );
+                report_diagnostics(
+                    &mut self.port_state,
+                    Some(Diagnostic::SerializationFailed),
+                    &mut self.events,
+                    &mut self.statistics,
+                );
+                None
+            }
+        }
+    }
+
+    // Send an announce message to the client that is due for one, if any,
+    // and wait for the next one
+    fn send_unicast_announce(&mut self) -> PortActionIterator<'_> {
+        let now = self.lifecycle.state.local_clock.borrow().now();
+        let (client, next_announce) = self
+            .unicast
+            .next_announce(now, &mut self.statistics.unicast_grants);
 
+        let (client, seq_id, next_announce) = match (client, next_announce) {
+            (Some((client, seq_id)), Some(next_announce)) => (client, seq_id, next_announce),
+            (None, Some(duration)) => return actions![PortAction::ResetAnnounceTimer { duration }],
+            // No grants left, wait for the next request
+            (_, None) => return actions![],
+        };
+
+        let actions = self.port_state.send_unicast_announce(
+            self.lifecycle.state.deref(),
+            &self.config,
+            self.port_identity,
+            client,
+            seq_id,
+            next_announce,
+            &mut self.events,
+            &mut self.statistics,
+            self.organization_extension,
+            &mut self.packet_buffer,
+        );
+        finish_actions!(self, actions, None)
+    }
+
+    // Send a sync message to the client that is due for one, if any, and wait
+    // for the next one
+    fn send_unicast_sync(&mut self) -> PortActionIterator<'_> {
+        let now = self.lifecycle.state.local_clock.borrow().now();
+        let (due, next_sync) = self
+            .unicast
+            .next_sync(now, &mut self.statistics.unicast_grants);
+
+        let (sync_grant, seq_id, next_sync) = match (due, next_sync) {
+            (Some((sync_grant, seq_id)), Some(next_sync)) => (sync_grant, seq_id, next_sync),
+            (None, Some(duration)) => return actions![PortAction::ResetSyncTimer { duration }],
+            // No grants left, wait for the next request
+            (_, None) => return actions![],
+        };
+
+        let actions = self.port_state.send_unicast_sync(
+            &self.config,
+            self.port_identity,
+            &self.lifecycle.state.default_ds,
+            sync_grant,
+            seq_id,
+            next_sync,
+            &mut self.packet_buffer,
+        );
+        finish_actions!(self, actions, None)
+    }
+
+    // Discard measurement state when the instance reports a new clock source
+    fn check_clock_generation(&mut self) {
+        let state = &self.lifecycle.state;
+        let generation = state.clock_generation.load(Ordering::Relaxed);
+        if generation == self.clock_generation {
+            return;
+        }
+
+        log::info!(port: self.port_identity, "Restarting measurements after clock source change");
+
+        self.clock_generation = generation;
+        self.port_state.reset_measurements();
+        self.time_error.clear();
+        self.frequency.clear();
+        self.statistics.clock_source_changes += 1;
+        let now = state.local_clock.borrow().now();
+        self.statistics.last_clock_source_change = Some(now);
+        self.timeline.record(now, TimelineEvent::ClockSourceChanged);
+
+        // The filter is shared between ports, only the first port to notice the
+        // change should reset it
+        if state.filter_generation.load(Ordering::Relaxed) != generation {
+            match state.filter.try_borrow_mut() {
+                Ok(mut filter) => {
+                    if state.filter_generation.swap(generation, Ordering::Relaxed) != generation {
+                        filter.reset();
+                        // Nothing is known about the correction of the new clock
+                        if let Ok(mut multiplier) = state.frequency_multiplier.try_borrow_mut() {
+                            *multiplier = 1.0;
+                        }
+                    }
+                }
+                Err(_) => {
+                    log::error!(port: self.port_identity, "Statime bug: filter busy");
+                    report_diagnostics(
+                        &mut self.port_state,
+                        Some(Diagnostic::InternalError),
+                        &mut self.events,
+                        &mut self.statistics,
+                    );
+                }
+            }
+        }
+    }
+
+    // Start a BMCA cycle and ensure this happens instantly from the perspective of
+    // the port
+    pub fn start_bmca(self) -> Port<InBmca<'a, C, F>, R> {
         Port {
-            config,
-            port_identity,
-            port_state: PortState::Listening,
-            bmca,
-            rng,
+            port_state: self.port_state,
+            config: self.config,
+            port_identity: self.port_identity,
+            bmca: self.bmca,
+            rng: self.rng,
+            statistics: self.statistics,
+            time_error: self.time_error,
+            frequency: self.frequency,
+            timeline: self.timeline,
+            windows: self.windows,
+            measurements: self.measurements,
+            events: self.events,
+            quirk_rules: self.quirk_rules,
+            startup_burst: self.startup_burst,
+            simulated_path: self.simulated_path,
+            identity_collision: self.identity_collision,
+            unicast: self.unicast,
+            unicast_client: self.unicast_client,
+            organization_extension: self.organization_extension,
+            follow_up_information: self.follow_up_information,
+            gptp: self.gptp,
+            common_link_delay: self.common_link_delay,
+            fixed_master: self.fixed_master,
+            local_priority: self.local_priority,
+            calibration_store: self.calibration_store,
+            parent_profile: self.parent_profile,
+            authentication: self.authentication,
+            sync_transmit_lead: self.sync_transmit_lead,
+            clock_generation: self.clock_generation,
             packet_buffer: [0; MAX_DATA_LEN],
             lifecycle: InBmca {
-                pending_action: actions![PortAction::ResetAnnounceReceiptTimer { duration }],
+                pending_action: actions![],
                 local_best: None,
-                state_refcell,
+                state_refcell: self.lifecycle.state_refcell,
+                enter_fixed_master: false,
             },
         }
     }
 }
 
-// Separate from the object to deal with lifetime issues.
-fn handle_time_measurement<C: Clock, F: Filter>(
-    port_state: &mut PortState,
-    filter: &AtomicRefCell<F>,
-    clock: &AtomicRefCell<C>,
-    time_properties_ds: &TimePropertiesDS,
-) {
-    if let Some(measurement) = port_state.extract_measurement() {
-        // If the received message allowed the (slave) state to calculate its offset
-        // from the master, update the local clock
-        let mut filter = match filter.try_borrow_mut() {
-            Ok(filter) => filter,
-            Err(_) => {
-                log::error!("Statime bug: filter busy");
-                return;
+impl<'a, C: Clock, F, R> Port<InBmca<'a, C, F>, R> {
+    // End a BMCA cycle and make the port available again
+    pub fn end_bmca(self) -> (Port<Running<'a, C, F>, R>, PortActionIterator<'static>) {
+        let mut port = Port {
+            port_state: self.port_state,
+            config: self.config,
+            port_identity: self.port_identity,
+            bmca: self.bmca,
+            rng: self.rng,
+            statistics: self.statistics,
+            time_error: self.time_error,
+            frequency: self.frequency,
+            timeline: self.timeline,
+            windows: self.windows,
+            measurements: self.measurements,
+            events: self.events,
+            quirk_rules: self.quirk_rules,
+            startup_burst: self.startup_burst,
+            simulated_path: self.simulated_path,
+            identity_collision: self.identity_collision,
+            unicast: self.unicast,
+            unicast_client: self.unicast_client,
+            organization_extension: self.organization_extension,
+            follow_up_information: self.follow_up_information,
+            gptp: self.gptp,
+            common_link_delay: self.common_link_delay,
+            fixed_master: self.fixed_master,
+            local_priority: self.local_priority,
+            calibration_store: self.calibration_store,
+            parent_profile: self.parent_profile,
+            authentication: self.authentication,
+            sync_transmit_lead: self.sync_transmit_lead,
+            clock_generation: self.clock_generation,
+            packet_buffer: [0; MAX_DATA_LEN],
+            lifecycle: Running {
+                state_refcell: self.lifecycle.state_refcell,
+                state: self.lifecycle.state_refcell.borrow(),
+            },
+        };
+
+        match port
+            .fixed_master
+            .filter(|_| self.lifecycle.enter_fixed_master)
+        {
+            Some(fixed_master) => {
+                let now = port.lifecycle.state.local_clock.borrow().now();
+                let default_ds = port.lifecycle.state.default_ds;
+                let actions = port.enter_fixed_master(fixed_master, &default_ds, now);
+                (port, actions)
             }
+            None => (port, self.lifecycle.pending_action),
+        }
+    }
+}
+
+impl<L, R> Port<L, R> {
+    // Outside gPTP mode every link is fit for time transport
+    fn is_as_capable(&self) -> bool {
+        match &self.gptp {
+            Some(link) => link.as_capable(),
+            None => true,
+        }
+    }
+
+    fn new_slave_state(
+        &self,
+        remote_master: PortIdentity,
+        grandmaster_identity: ClockIdentity,
+        default_ds: &DefaultDS,
+    ) -> SlaveState {
+        let quirks = resolve_quirks(&self.quirk_rules, grandmaster_identity, default_ds.sdo_id);
+        SlaveState::with_delay_mechanism(remote_master, self.config.delay_mechanism)
+            .with_delay_asymmetry(self.config.delay_asymmetry)
+            .with_ingress_latency(self.config.ingress_latency)
+            .with_quirks(quirks)
+            .with_port_identity(self.port_identity)
+            .with_startup_burst(self.startup_burst)
+            .with_link_delay(self.common_link_delay)
+    }
+
+    // Become slave of the fixed master, and start measuring the delay to it
+    fn enter_fixed_master(
+        &mut self,
+        fixed_master: FixedMasterConfig,
+        default_ds: &DefaultDS,
+        now: Time,
+    ) -> PortActionIterator<'static> {
+        let duration = self.sync_receipt_duration(&fixed_master);
+        let slave = self.new_slave_state(
+            fixed_master.master(),
+            fixed_master.clock_identity,
+            default_ds,
+        );
+        self.set_forced_port_state(PortState::Slave(slave), now);
+
+        // A startup burst starts measuring the delay right away
+        let delay_duration = match self.startup_burst {
+            Some(burst) => burst.delay_req_interval.as_core_duration(),
+            None => duration,
         };
-        let mut clock = match clock.try_borrow_mut() {
-            Ok(clock) => clock,
-            Err(_) => {
-                log::error!("Statime bug: clock busy");
-                return;
+        actions![
+            PortAction::ResetAnnounceReceiptTimer { duration },
+            PortAction::ResetDelayRequestTimer {
+                duration: delay_duration
+            }
+        ]
+    }
+
+    fn sync_receipt_duration(&self, fixed_master: &FixedMasterConfig) -> core::time::Duration {
+        self.config.sync_interval.as_core_duration() * fixed_master.sync_receipt_timeout as u32
+    }
+
+    // Whether the port is slave of its fixed master, which sent this Sync
+    fn is_fixed_master_sync(&self, message: &Message) -> bool {
+        match (self.fixed_master, message, &self.port_state) {
+            (Some(fixed_master), Message::Sync(sync), PortState::Slave(_)) => {
+                sync.header.source_port_identity == fixed_master.master()
             }
+            _ => false,
+        }
+    }
+
+    fn is_unicast_client(&self) -> bool {
+        self.config.communication_mode == CommunicationMode::Unicast
+            && self.unicast_client.is_enabled()
+    }
+
+    fn set_forced_port_state(&mut self, state: PortState, now: Time) {
+        log::debug!(port: self.port_identity, "New state: {} -> {}", self.port_state, state);
+
+        let event = PortEvent::StateChanged {
+            previous: self.port_state.kind(),
+            current: state.kind(),
         };
+        if self.events.push(event) {
+            self.statistics.events_dropped = self.statistics.events_dropped.wrapping_add(1);
+        }
 
-        let (offset, freq_corr) = filter.absorb(measurement);
+        if self.port_state.kind() != state.kind() {
+            let event = TimelineEvent::StateChanged {
+                previous: self.port_state.kind(),
+                current: state.kind(),
+            };
+            self.timeline.record(now, event);
+        }
+        let (previous, current) = (self.port_state.remote_master(), state.remote_master());
+        if previous != current {
+            let event = TimelineEvent::ParentChanged {
+                previous: previous.map(|parent| parent.clock_identity),
+                current: current.map(|parent| parent.clock_identity),
+            };
+            self.timeline.record(now, event);
+        }
 
-        if let Err(error) = clock.adjust(offset, freq_corr, time_properties_ds) {
-            log::error!("failed to adjust clock: {:?}", error);
+        // Only a master sends announce messages to its clients, and a client
+        // only wants sync messages from its master
+        if !matches!(state, PortState::Master(_)) {
+            self.unicast.clear();
+        }
+        self.unicast_client
+            .set_parent(state.remote_master(), &mut self.statistics.unicast_requests);
+
+        self.port_state = state;
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn force_state(&mut self, state: TestPortState, sdo_id: crate::SdoId) {
+        self.port_state = match state {
+            TestPortState::Listening => PortState::Listening,
+            TestPortState::Passive => PortState::Passive,
+            TestPortState::Master => PortState::Master(MasterState::new()),
+            TestPortState::Slave {
+                clock_identity,
+                port_number,
+            } => PortState::Slave(
+                SlaveState::with_delay_mechanism(
+                    PortIdentity {
+                        clock_identity,
+                        port_number,
+                    },
+                    self.config.delay_mechanism,
+                )
+                .with_delay_asymmetry(self.config.delay_asymmetry)
+                .with_ingress_latency(self.config.ingress_latency)
+                .with_quirks(resolve_quirks(&self.quirk_rules, clock_identity, sdo_id))
+                .with_port_identity(self.port_identity)
+                .with_startup_burst(self.startup_burst),
+            ),
+        };
+        self.unicast_client.set_parent(
+            self.port_state.remote_master(),
+            &mut self.statistics.unicast_requests,
+        );
+    }
+
+    pub(crate) fn state(&self) -> &PortState {
+        &self.port_state
+    }
+
+    /// Counters and annotations about the operation of this port
+    pub fn statistics(&self) -> &PortStatistics {
+        &self.statistics
+    }
+
+    /// The clients this port currently sends unicast sync messages to, with
+    /// how late those messages went out, see [`Port::set_unicast_master`]
+    pub fn unicast_sync_clients(&self) -> impl Iterator<Item = UnicastSyncClient> + '_ {
+        self.unicast.sync_clients()
+    }
+
+    /// The frequency of the clock of the neighbor this slave port measures
+    /// the peer delay to, relative to the local clock, see
+    /// [`Measurement::neighbor_rate_ratio`]
+    pub fn neighbor_rate_ratio(&self) -> Option<f64> {
+        match &self.port_state {
+            PortState::Slave(slave) => slave.neighbor_rate_ratio(),
+            _ => None,
         }
     }
+
+    /// MTIE and TDEV over the offsets from the master measured by this port
+    pub fn time_error(&self) -> &TimeErrorStatistics {
+        &self.time_error
+    }
+
+    /// The frequency corrections applied to the local clock while this port
+    /// was its source of time
+    pub fn frequency(&self) -> &FrequencyStatistics {
+        &self.frequency
+    }
+
+    /// The recent offset spikes of this port, with the events around them
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    /// Record offsets from the master and corrections of the clock of at
+    /// least `threshold` in the [`Timeline`]. `None`, the default, records
+    /// neither.
+    pub fn set_offset_spike_threshold(&mut self, threshold: Option<Duration>) {
+        self.timeline.set_spike_threshold(threshold);
+    }
+
+    /// The offsets from the master over recent windows of fixed length
+    pub fn statistics_windows(&self) -> &StatisticsWindows {
+        &self.windows
+    }
+
+    /// Keep statistics of the offsets from the master over windows of
+    /// `seconds`, starting at whole multiples of it in local time. `None`,
+    /// the default, keeps none. Changing it drops the windows so far.
+    ///
+    /// Windows end when a measurement after their end comes in, or at the
+    /// latest when [`Port::handle_statistics_timer`] is called after
+    /// [`PortAction::ResetStatisticsTimer`].
+    pub fn set_statistics_window(&mut self, seconds: Option<u64>) {
+        self.windows.set_length(seconds);
+    }
+
+    /// Report a [`PortEvent::FrequencyCorrectionChanged`] when the mean
+    /// frequency correction over a period of [`FREQUENCY_PERIOD_SECONDS`]
+    /// differs by at least this many parts per billion from that of the
+    /// period before. `None`, the default, reports no such events.
+    pub fn set_frequency_change_threshold(&mut self, ppb: Option<f64>) {
+        self.frequency.set_change_threshold(ppb);
+    }
+
+    /// Compute time error metrics over these observation intervals, given as
+    /// a number of measurements. An empty list stops recording offsets.
+    ///
+    /// The offsets of the last measurements are kept in `samples`, which
+    /// needs at least three times the longest interval of them, see
+    /// [`TimeErrorStatistics`]. Pass an empty buffer, like `&mut []`, when
+    /// stopping. Changing the intervals discards the offsets recorded so far.
+    /// They are also discarded when the source of the timestamps changes.
+    pub fn set_time_error_intervals(
+        &mut self,
+        intervals: &[usize],
+        samples: &'static mut [f64],
+    ) -> Result<(), TimeErrorConfigError> {
+        self.time_error.set_intervals(intervals, samples)
+    }
+
+    /// Use workarounds for masters that don't follow the standard. The
+    /// workarounds of the first rule matching a master are used, or
+    /// [`Quirks::DEFAULT`](crate::Quirks::DEFAULT) if none matches.
+    ///
+    /// The rules are applied when the port selects a master, so they should
+    /// be set before the first BMCA run.
+    pub fn set_quirks(&mut self, rules: &[QuirkRule]) -> Result<(), QuirkConfigError> {
+        self.quirk_rules = rules
+            .try_into()
+            .map_err(|_| QuirkConfigError::TooManyRules(rules.len()))?;
+        Ok(())
+    }
+
+    /// Measure more often for a while after selecting a master, see
+    /// [`StartupBurst`]. Disabled with `None`, which is the default.
+    ///
+    /// The burst starts when the port selects a master, so it should be set
+    /// before the first BMCA run.
+    pub fn set_startup_burst(&mut self, startup_burst: Option<StartupBurst>) {
+        self.startup_burst = startup_burst;
+    }
+
+    /// Add an artificial network path to the timestamps of this port as
+    /// slave, see [`SimulatedPath`]. Disabled with `None`, which is the
+    /// default.
+    ///
+    /// Only for testing the filter and the servo on loopback or virtual
+    /// networks, never in production. The simulated path is part of every
+    /// timestamp the port works with, including
+    /// [`Measurement::raw_receive_time`].
+    pub fn set_simulated_path(&mut self, path: Option<SimulatedPath>) {
+        if let Some(path) = path {
+            log::warn!(
+                port: self.port_identity,
+                "Simulating a path delay of {} and asymmetry of {}, the measurements are not real",
+                path.delay,
+                path.asymmetry
+            );
+        }
+        self.simulated_path = path;
+    }
+
+    /// How to respond to another clock using the clock identity of this
+    /// instance, see [`IdentityCollisionResponse`]. Only reporting it is the
+    /// default.
+    pub fn set_identity_collision_response(&mut self, response: IdentityCollisionResponse) {
+        self.identity_collision = response;
+    }
+
+    /// Grant unicast announce and sync messages, and delay responses, to
+    /// clients that request them, within the limits of `config`. Disabled
+    /// with `None`, which is the default.
+    ///
+    /// Only used by ports with [`CommunicationMode::Unicast`]. While master,
+    /// such a port sends its messages to each client separately, with
+    /// [`PortAction::SendUnicastGeneral`] and
+    /// [`PortAction::SendUnicastTimeCritical`]. Sync messages to different
+    /// clients are spread over the interval rather than sent in a burst.
+    /// Changing the configuration ends all grants, without telling the
+    /// clients. Use [`Port::cancel_unicast_grants`] before to let them know.
+    ///
+    /// The grants are kept in `table`, which needs a [`UnicastGrantSlot`] for
+    /// every type of message granted to every client: at least `max_clients`
+    /// of them, and three times as many to grant everything to all clients.
+    /// Its size bounds both the memory used and the work done per message.
+    /// Pass an empty table, like `&mut []`, when disabling.
+    pub fn set_unicast_master(
+        &mut self,
+        config: Option<UnicastMasterConfig>,
+        table: &'static mut [UnicastGrantSlot],
+    ) -> Result<(), UnicastConfigError> {
+        if let Some(config) = config {
+            if config.max_clients > table.len() {
+                return Err(UnicastConfigError::TooManyClients {
+                    max_clients: config.max_clients,
+                    slots: table.len(),
+                });
+            }
+        }
+
+        self.unicast.set_config(config, table);
+        Ok(())
+    }
+
+    /// Request unicast announce and sync messages, and delay responses, from
+    /// the masters of the unicast master table of the runtime, see
+    /// [`UnicastClientConfig`]. Replaces [`PortConfig::unicast_client`].
+    ///
+    /// Only used by ports with [`CommunicationMode::Unicast`]. The requests
+    /// are sent with [`PortAction::SendToUnicastMaster`], starting with the
+    /// first call to [`Port::handle_unicast_negotiation_timer`]. As slave,
+    /// the port only sends delay requests once its master granted delay
+    /// responses, and sends them to that master with
+    /// [`PortAction::SendUnicastTimeCritical`]. Changing the configuration
+    /// starts over, without cancelling the current grants.
+    ///
+    /// A port with both a master and a client configuration acts as master
+    /// towards its clients while in the master state, and as client
+    /// otherwise.
+    pub fn set_unicast_client(
+        &mut self,
+        config: Option<UnicastClientConfig>,
+    ) -> Result<(), UnicastConfigError> {
+        if let Some(config) = config {
+            config.validate()?;
+        }
+
+        self.config.unicast_client = config;
+        self.unicast_client = UnicastClient::new(&self.config);
+        self.unicast_client.set_parent(
+            self.port_state.remote_master(),
+            &mut self.statistics.unicast_requests,
+        );
+        Ok(())
+    }
+
+    /// Attach vendor specific TLVs to the announce and signaling messages
+    /// this port sends, and pass on those of the messages it receives, see
+    /// [`OrganizationExtension`]. Disabled with `None`, which is the default.
+    ///
+    /// The extension is shared rather than owned, so it can be a `static` or,
+    /// with the standard library, a leaked `Box`.
+    pub fn set_organization_extension(
+        &mut self,
+        extension: Option<&'static dyn OrganizationExtension>,
+    ) {
+        self.organization_extension = extension;
+    }
+
+    /// Add the FOLLOW_UP information TLV of IEEE 802.1AS to the follow up
+    /// messages this port sends as master, carrying the given values. Not
+    /// sent with `None`, which is the default.
+    ///
+    /// Statime doesn't relay the rate ratio and grandmaster changes of
+    /// 802.1AS itself, so it is up to the runtime to keep this current. Slave
+    /// ports report the TLV they receive in
+    /// [`Measurement::follow_up_information`] either way.
+    pub fn set_follow_up_information(&mut self, information: Option<FollowUpInformation>) {
+        self.follow_up_information = information;
+    }
+
+    /// Run this port in the gPTP mode of IEEE 802.1AS, with the asCapable
+    /// determination of `config`. Disabled with `None`, which is the default.
+    ///
+    /// In gPTP mode the port sends peer delay requests in every state, and
+    /// only sends Sync and Announce messages and takes part in the BMCA while
+    /// its link is asCapable, see [`GptpConfig`]. Changing the configuration
+    /// starts over with a link that is not asCapable, so it should be set
+    /// before the port starts running. Call
+    /// [`Port::handle_delay_request_timer`] once after to send the first
+    /// request. A port using [`DelayMechanism::CommonP2P`] sends no requests
+    /// itself, but judges its link by the delays the Common Mean Link Delay
+    /// Service measures.
+    pub fn set_gptp(&mut self, config: Option<GptpConfig>) {
+        self.gptp = config.map(|config| GptpLink::new(config, self.port_identity));
+    }
+
+    /// Set the localPriority of this port (portDS.localPriority), 1 to 255
+    /// with the lowest the most preferred. The default is 128.
+    ///
+    /// Only an alternate BMCA such as that of
+    /// [`Profile::G8275_1`](crate::Profile::G8275_1) uses it, to prefer the
+    /// masters heard on one port over those heard on another, before it looks
+    /// at their identity or distance.
+    pub fn set_local_priority(&mut self, local_priority: u8) {
+        self.local_priority = local_priority;
+    }
+
+    /// Whether the link of this port is asCapable, or `None` when the port
+    /// is not in gPTP mode, see [`Port::set_gptp`]
+    pub fn as_capable(&self) -> Option<bool> {
+        self.gptp.as_ref().map(GptpLink::as_capable)
+    }
+
+    /// The calibration this port corrects its timestamps with, from its
+    /// configuration or restored from the store of the instance, see
+    /// [`PtpInstance::set_calibration_store`](crate::PtpInstance::set_calibration_store)
+    pub fn calibration(&self) -> Calibration {
+        Calibration::from_config(&self.config)
+    }
+
+    /// Correct timestamps with a new calibration, for example after measuring
+    /// the asymmetry of the path to the master. It is stored in the store of
+    /// the instance, if any, so the port gets it back after a restart.
+    ///
+    /// A slave port uses it from its next measurement on. It is used even
+    /// when storing it fails.
+    pub fn set_calibration(
+        &mut self,
+        calibration: Calibration,
+    ) -> Result<(), CalibrationStoreError> {
+        calibration.apply_to(&mut self.config);
+        if let PortState::Slave(slave) = &mut self.port_state {
+            slave.set_calibration(calibration.delay_asymmetry, calibration.ingress_latency);
+        }
+
+        match self.calibration_store {
+            Some(store) => store.store(self.port_identity.port_number, &calibration),
+            None => Ok(()),
+        }
+    }
+
+    /// Schedule the transmission of sync messages `lead` after the sync timer
+    /// expires, see [`PortAction::SendTimeCritical`]. Disabled with `None`,
+    /// which is the default.
+    ///
+    /// Scheduled syncs are spaced exactly one sync interval apart, unless the
+    /// timer expires too late for that, so the jitter of the timer and of
+    /// the software path to the network interface doesn't show in their
+    /// transmission times. The lead should cover that path, as messages
+    /// scheduled in the past are dropped by the Linux ETF qdisc.
+    pub fn set_sync_transmit_lead(&mut self, lead: Option<Duration>) {
+        self.sync_transmit_lead = lead;
+    }
+
+    /// Add an AUTHENTICATION TLV (IEEE1588-2019 section 16.14) to every
+    /// message this port sends, and check that of the messages it receives,
+    /// with the algorithm and keys in `config`. Disabled with `None`, which
+    /// is the default.
+    ///
+    /// Received messages without a valid TLV are counted in
+    /// [`PortStatistics::authentication`], and dropped or handled according
+    /// to [`AuthenticationConfig::on_failure`]. The ICV covers the entire
+    /// message, so one-step masters and transparent clocks that change a
+    /// message in flight need to recompute it, or their messages fail.
+    ///
+    /// With [`AuthenticationConfig::replay_protection`], the sequence ids of
+    /// the sources are kept in `replay_windows`, which needs a
+    /// [`ReplayWindowSlot`] for every message type of every source. When it
+    /// is full, the source that was quiet the longest is forgotten, which is
+    /// counted in [`AuthenticationFailures::replay_evictions`]. Pass an empty
+    /// table, like `&mut []`, without replay protection.
+    pub fn set_authentication(
+        &mut self,
+        config: Option<AuthenticationConfig>,
+        replay_windows: &'static mut [ReplayWindowSlot],
+    ) -> Result<(), AuthenticationConfigError> {
+        if let Some(config) = &config {
+            config.validate(replay_windows)?;
+        }
+
+        self.authentication.set_config(config, replay_windows);
+        Ok(())
+    }
+
+    /// Keep a copy of every measurement this port produces, so the runtime
+    /// can retrieve them with [`Port::take_measurement`].
+    ///
+    /// At most [`MEASUREMENT_QUEUE_CAPACITY`] measurements are kept. When the
+    /// queue is full the oldest measurement is dropped, which is counted in
+    /// [`PortStatistics::measurements_dropped`].
+    pub fn set_measurement_queue(&mut self, enabled: bool) {
+        self.measurements.set_enabled(enabled);
+    }
+
+    /// Take the oldest measurement from the measurement queue, if any.
+    pub fn take_measurement(&mut self) -> Option<Measurement> {
+        self.measurements.pop()
+    }
+
+    /// Take the oldest event from the event queue, if any.
+    ///
+    /// At most [`EVENT_QUEUE_CAPACITY`] events are kept. When the queue is
+    /// full the oldest event is dropped, which is counted in
+    /// [`PortStatistics::events_dropped`].
+    pub fn take_event(&mut self) -> Option<PortEvent> {
+        self.events.pop()
+    }
+
+    pub(crate) fn number(&self) -> u16 {
+        self.port_identity.port_number
+    }
+
+    pub(crate) fn is_master(&self) -> bool {
+        matches!(self.port_state, PortState::Master(_))
+    }
+
+    pub(crate) fn announce_interval(&self) -> Interval {
+        self.config.announce_interval
+    }
+}
+
+impl<'a, C, F, R: Rng> Port<InBmca<'a, C, F>, R> {
+    /// Take time from a fixed master, without announce messages or the BMCA,
+    /// see [`FixedMasterConfig`]. Disabled with `None`, which is the default.
+    ///
+    /// The port becomes slave of the master right away, when the BMCA ends,
+    /// and [`Port::end_bmca`] starts its sync receipt and delay request
+    /// timers. A configuration with a zero timeout is rejected, leaving the
+    /// current one in place.
+    pub fn set_fixed_master(
+        &mut self,
+        config: Option<FixedMasterConfig>,
+    ) -> Result<(), FixedMasterConfigError> {
+        if let Some(config) = config {
+            config.validate()?;
+        }
+
+        self.fixed_master = config;
+        self.lifecycle.enter_fixed_master = config.is_some();
+        Ok(())
+    }
+
+    pub(crate) fn calculate_best_local_announce_message(
+        &mut self,
+        current_time: WireTimestamp,
+        selection: &impl MasterSelection,
+    ) {
+        self.lifecycle.local_best =
+            self.bmca
+                .take_best_port_announce_message(current_time, selection, self.local_priority)
+    }
+
+    // Forget the masters of the previous domain, and listen for those of the
+    // new one
+    pub(crate) fn restart_in_new_domain(&mut self, now: Time) {
+        self.bmca = Bmca::new(
+            self.config.announce_interval.as_duration().into(),
+            self.port_identity,
+        );
+        self.lifecycle.local_best = None;
+
+        if !matches!(self.port_state, PortState::Faulty | PortState::Disabled) {
+            self.set_forced_port_state(PortState::Listening, now);
+            let duration = self.config.announce_duration(&mut self.rng);
+            self.lifecycle.pending_action =
+                actions![PortAction::ResetAnnounceReceiptTimer { duration }];
+        }
+    }
+
+    // Send an announce message at the end of the BMCA if we are master. This
+    // is overridden by the actions of a state change.
+    pub(crate) fn announce_now(&mut self) {
+        if let PortState::Master(state) = &mut self.port_state {
+            // Only called for changes made on purpose
+            state.expect_dataset_change();
+            self.lifecycle.pending_action = actions![PortAction::ResetAnnounceTimer {
+                duration: core::time::Duration::from_secs(0),
+            }];
+        }
+    }
+
+    pub(crate) fn best_local_announce_message(&self) -> Option<BestAnnounceMessage> {
+        // Announce messages received on a masterOnly PTP Port shall not be considered
+        // in the operation of the best master clock algorithm or in the update
+        // of data sets.
+        // Neither shall those received on a faulty or disabled port, or in
+        // gPTP mode on a link that is not asCapable.
+        if self.config.master_only
+            || matches!(self.port_state, PortState::Faulty | PortState::Disabled)
+            || !self.is_as_capable()
+        {
+            None
+        } else {
+            self.lifecycle.local_best
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn set_recommended_state(
+        &mut self,
+        recommended_state: RecommendedState,
+        time_properties_ds: &mut TimePropertiesDS,
+        current_ds: &mut CurrentDS,
+        parent_ds: &mut ParentDS,
+        path_trace: &mut PathTrace,
+        default_ds: &DefaultDS,
+        local_time_properties_ds: &TimePropertiesDS,
+        now: Time,
+    ) {
+        // A faulty port stays out of the BMCA until it recovered, and a
+        // disabled one until it is enabled
+        if matches!(self.port_state, PortState::Faulty | PortState::Disabled) {
+            return;
+        }
+
+        // A port with a fixed master stays out of the BMCA, but the instance
+        // follows it all the same
+        if let Some(fixed_master) = self.fixed_master {
+            if matches!(self.port_state, PortState::Slave(_)) {
+                current_ds.steps_removed = 1;
+                parent_ds.parent_port_identity = fixed_master.master();
+                parent_ds.grandmaster_identity = fixed_master.clock_identity;
+            }
+            return;
+        }
+
+        // In gPTP mode a port without an asCapable link listens until it has
+        // one
+        if !self.is_as_capable() {
+            if !matches!(self.port_state, PortState::Listening) {
+                self.set_forced_port_state(PortState::Listening, now);
+            }
+            return;
+        }
+
+        self.set_recommended_port_state(&recommended_state, default_ds, now);
+
+        match recommended_state {
+            RecommendedState::M1(defaultds) | RecommendedState::M2(defaultds) => {
+                // a slave-only PTP port should never end up in the master state
+                debug_assert!(!default_ds.slave_only);
+
+                current_ds.steps_removed = 0;
+                current_ds.offset_from_master = Duration::ZERO;
+                current_ds.mean_delay = Duration::ZERO;
+
+                parent_ds.parent_port_identity.clock_identity = defaultds.clock_identity;
+                parent_ds.parent_port_identity.port_number = 0;
+                parent_ds.grandmaster_identity = defaultds.clock_identity;
+                parent_ds.grandmaster_clock_quality = defaultds.clock_quality;
+                parent_ds.grandmaster_priority_1 = defaultds.priority_1;
+                parent_ds.grandmaster_priority_2 = defaultds.priority_2;
+
+                *path_trace = PathTrace::new(defaultds.clock_identity);
+
+                *time_properties_ds = *local_time_properties_ds;
+            }
+            RecommendedState::M3(_) | RecommendedState::P1(_) | RecommendedState::P2(_) => {}
+            RecommendedState::S1(announce_message) => {
+                // a master-only PTP port should never end up in the slave state
+                debug_assert!(!self.config.master_only);
+
+                current_ds.steps_removed = announce_message.steps_removed + 1;
+
+                parent_ds.parent_port_identity = announce_message.header.source_port_identity;
+                parent_ds.grandmaster_identity = announce_message.grandmaster_identity;
+                parent_ds.grandmaster_clock_quality = announce_message.grandmaster_clock_quality;
+                parent_ds.grandmaster_priority_1 = announce_message.grandmaster_priority_1;
+                parent_ds.grandmaster_priority_2 = announce_message.grandmaster_priority_2;
+
+                // The path of our parent, which starts empty if the parent
+                // doesn't send one, with us added
+                *path_trace = announce_message.path_trace;
+                path_trace.push(default_ds.clock_identity);
+
+                *time_properties_ds = announce_message.time_properties();
+            }
+        }
+
+        // TODO: Discuss if we should change the clock's own time properties, or keep
+        // the master's time properties separately
+        if let RecommendedState::S1(announce_message) = &recommended_state {
+            // Update time properties
+            *time_properties_ds = announce_message.time_properties();
+        }
+    }
+
+    fn set_recommended_port_state(
+        &mut self,
+        recommended_state: &RecommendedState,
+        default_ds: &DefaultDS,
+        now: Time,
+    ) {
+        match recommended_state {
+            // TODO set things like steps_removed once they are added
+            // TODO make sure states are complete
+            RecommendedState::S1(announce_message) => {
+                // a master-only PTP port should never end up in the slave state
+                debug_assert!(!self.config.master_only);
+
+                let remote_master = announce_message.header.source_port_identity;
+                let state = PortState::Slave(self.new_slave_state(
+                    remote_master,
+                    announce_message.grandmaster_identity,
+                    default_ds,
+                ));
+
+                let update_state = match &self.port_state {
+                    PortState::Listening | PortState::Master(_) | PortState::Passive => true,
+                    PortState::Slave(old_state) => old_state.remote_master() != remote_master,
+                    // Never recommended, see set_recommended_state
+                    PortState::Faulty | PortState::Disabled => false,
+                };
+
+                if update_state {
+                    self.set_forced_port_state(state, now);
+
+                    let duration = self.config.announce_duration(&mut self.rng);
+                    let reset_announce = PortAction::ResetAnnounceReceiptTimer { duration };
+                    // A startup burst starts measuring the delay right away
+                    let delay_duration = match self.startup_burst {
+                        Some(burst) => burst.delay_req_interval.as_core_duration(),
+                        None => duration,
+                    };
+                    let reset_delay = PortAction::ResetDelayRequestTimer {
+                        duration: delay_duration,
+                    };
+                    self.lifecycle.pending_action = if self.is_unicast_client() {
+                        // Request sync messages from the new master right away
+                        let duration = core::time::Duration::ZERO;
+                        let reset_unicast = PortAction::ResetUnicastNegotiationTimer { duration };
+                        actions![reset_announce, reset_delay, reset_unicast]
+                    } else {
+                        actions![reset_announce, reset_delay]
+                    };
+                }
+            }
+            RecommendedState::M1(_) | RecommendedState::M2(_) | RecommendedState::M3(_) => {
+                if default_ds.slave_only {
+                    match self.port_state {
+                        PortState::Listening | PortState::Faulty | PortState::Disabled => {
+                            // do nothing
+                        }
+                        PortState::Slave(_) | PortState::Passive => {
+                            self.set_forced_port_state(PortState::Listening, now);
+
+                            // consistent with Port<InBmca>::new()
+                            let duration = self.config.announce_duration(&mut self.rng);
+                            let reset_announce = PortAction::ResetAnnounceReceiptTimer { duration };
+                            self.lifecycle.pending_action = actions![reset_announce];
+                        }
+                        PortState::Master(_) => {
+                            let msg = "slave-only PTP port should not be in master state";
+                            debug_assert!(!default_ds.slave_only, "{msg}");
+                            log::error!(port: self.port_identity, "{msg}");
+                            report_diagnostics(
+                                &mut self.port_state,
+                                Some(Diagnostic::InternalError),
+                                &mut self.events,
+                                &mut self.statistics,
+                            );
+                        }
+                    }
+                } else {
+                    match self.port_state {
+                        PortState::Listening | PortState::Slave(_) | PortState::Passive => {
+                            self.set_forced_port_state(PortState::Master(MasterState::new()), now);
+
+                            // Immediately start sending announces and syncs
+                            let duration = core::time::Duration::from_secs(0);
+                            self.lifecycle.pending_action = actions![
+                                PortAction::ResetAnnounceTimer { duration },
+                                PortAction::ResetSyncTimer { duration }
+                            ];
+                        }
+                        PortState::Master(_) | PortState::Faulty | PortState::Disabled => {
+                            // do nothing
+                        }
+                    }
+                }
+            }
+            RecommendedState::P1(_) | RecommendedState::P2(_) => match self.port_state {
+                PortState::Listening | PortState::Slave(_) | PortState::Master(_) => {
+                    self.set_forced_port_state(PortState::Passive, now)
+                }
+                PortState::Passive | PortState::Faulty | PortState::Disabled => {}
+            },
+        }
+    }
+}
+
+impl<'a, C, F, R: Rng> Port<InBmca<'a, C, F>, R> {
+    /// Create a new port from a port dataset on a given interface.
+    pub(crate) fn new(
+        state_refcell: &'a AtomicRefCell<PtpInstanceState<C, F>>,
+        config: PortConfig,
+        port_identity: PortIdentity,
+        clock_generation: u32,
+        calibration_store: Option<&'static dyn CalibrationStore>,
+        mut rng: R,
+    ) -> Self {
+        let bmca = Bmca::new(config.announce_interval.as_duration().into(), port_identity);
+
+        let duration = config.announce_duration(&mut rng);
+
+        Port {
+            config,
+            port_identity,
+            port_state: PortState::Listening,
+            bmca,
+            rng,
+            statistics: PortStatistics::default(),
+            time_error: TimeErrorStatistics::default(),
+            frequency: FrequencyStatistics::default(),
+            timeline: Timeline::default(),
+            windows: StatisticsWindows::default(),
+            measurements: MeasurementQueue::default(),
+            events: EventQueue::default(),
+            quirk_rules: ArrayVec::new(),
+            startup_burst: None,
+            simulated_path: None,
+            identity_collision: IdentityCollisionResponse::default(),
+            unicast: UnicastGrants::default(),
+            unicast_client: UnicastClient::new(&config),
+            organization_extension: None,
+            follow_up_information: None,
+            gptp: None,
+            common_link_delay: None,
+            fixed_master: None,
+            local_priority: 128,
+            calibration_store,
+            parent_profile: None,
+            authentication: Authenticator::new(),
+            sync_transmit_lead: None,
+            clock_generation,
+            packet_buffer: [0; MAX_DATA_LEN],
+            lifecycle: InBmca {
+                pending_action: actions![PortAction::ResetAnnounceReceiptTimer { duration }],
+                local_best: None,
+                state_refcell,
+                enter_fixed_master: false,
+            },
+        }
+    }
+}
+
+// Separate from the object to deal with lifetime issues.
+#[allow(clippy::too_many_arguments)]
+fn handle_time_measurement<C: Clock, F: Filter>(
+    port_state: &mut PortState,
+    port_identity: PortIdentity,
+    measurements: &mut MeasurementQueue,
+    statistics: &mut PortStatistics,
+    time_error: &mut TimeErrorStatistics,
+    frequency: &mut FrequencyStatistics,
+    timeline: &mut Timeline,
+    windows: &mut StatisticsWindows,
+    events: &mut EventQueue,
+    filter: &AtomicRefCell<F>,
+    clock: &AtomicRefCell<C>,
+    frequency_multiplier: &AtomicRefCell<f64>,
+    clock_steps: &AtomicRefCell<Duration>,
+    time_properties_ds: &TimePropertiesDS,
+    free_run: bool,
+) -> Option<Diagnostic> {
+    if let Some(measurement) = port_state.extract_measurement() {
+        if measurements.push(measurement) {
+            statistics.measurements_dropped = statistics.measurements_dropped.wrapping_add(1);
+        }
+        statistics
+            .measurement_sources
+            .record(measurement.timestamp_source);
+        time_error.record(measurement.master_offset);
+        timeline.record_offset(measurement.event_time, measurement.master_offset);
+        windows.record(measurement.event_time, measurement.master_offset);
+
+        if free_run {
+            log::trace!(port: port_identity, "Free-run, not adjusting the clock");
+            return None;
+        }
+
+        // If the received message allowed the (slave) state to calculate its offset
+        // from the master, update the local clock
+        let mut filter = match filter.try_borrow_mut() {
+            Ok(filter) => filter,
+            Err(_) => {
+                log::error!(port: port_identity, "Statime bug: filter busy");
+                return Some(Diagnostic::InternalError);
+            }
+        };
+        let mut clock = match clock.try_borrow_mut() {
+            Ok(clock) => clock,
+            Err(_) => {
+                log::error!(port: port_identity, "Statime bug: clock busy");
+                return Some(Diagnostic::InternalError);
+            }
+        };
+
+        filter.set_startup_burst(port_state.in_startup_burst());
+        let (offset, freq_corr) = filter.absorb(measurement);
+
+        if let Err(error) = clock.adjust(offset, freq_corr, time_properties_ds) {
+            log::error!(port: port_identity, "failed to adjust clock: {:?}", error);
+            return Some(Diagnostic::ClockAdjustFailed);
+        }
+        timeline.record_correction(measurement.event_time, offset);
+
+        let Ok(mut clock_steps) = clock_steps.try_borrow_mut() else {
+            log::error!(port: port_identity, "Statime bug: clock steps busy");
+            return Some(Diagnostic::InternalError);
+        };
+        *clock_steps += offset;
+
+        // Follow the correction ourselves for clocks that don't report it
+        let Ok(mut multiplier) = frequency_multiplier.try_borrow_mut() else {
+            log::error!(port: port_identity, "Statime bug: frequency multiplier busy");
+            return Some(Diagnostic::InternalError);
+        };
+        *multiplier = clock
+            .frequency_multiplier()
+            .unwrap_or(*multiplier * freq_corr);
+
+        let ppb = (*multiplier - 1.0) * 1e9;
+        if let Some((previous, current)) = frequency.record(measurement.event_time, ppb) {
+            let event = PortEvent::FrequencyCorrectionChanged {
+                previous_ppb: libm::round(previous) as i64,
+                current_ppb: libm::round(current) as i64,
+            };
+            if events.push(event) {
+                statistics.events_dropped = statistics.events_dropped.wrapping_add(1);
+            }
+        }
+    }
+
+    None
+}
+
+// Count the messages the port is about to send towards their rates
+fn record_sent<C: Clock>(
+    statistics: &mut PortStatistics,
+    clock: &AtomicRefCell<C>,
+    actions: &PortActionIterator<'_>,
+) {
+    // Only a bug keeps the clock busy, which the send itself reports
+    let Ok(clock) = clock.try_borrow() else {
+        return;
+    };
+    let now = clock.now();
+
+    for action in &actions.remaining {
+        let data = match action {
+            PortAction::SendTimeCritical { data, .. }
+            | PortAction::SendGeneral { data }
+            | PortAction::SendUnicastGeneral { data, .. }
+            | PortAction::SendUnicastTimeCritical { data, .. }
+            | PortAction::SendToUnicastMaster { data, .. } => data,
+            _ => continue,
+        };
+        let message_type = data
+            .first()
+            .and_then(|byte| MessageType::try_from(byte & 0x0f).ok());
+        if let Some(message_type) = message_type {
+            statistics.message_rates.sent.record(message_type, now);
+        }
+    }
+}
+
+// Report the problems noticed by the port state and by the port itself as
+// events. This is only done with the `silent` feature, as they are logged
+// otherwise, except for bugs in statime, which are rare and should be noticed
+// even without access to the logs. Those are counted as well, together with
+// what the port state counted.
+fn report_diagnostics(
+    port_state: &mut PortState,
+    diagnostic: Option<Diagnostic>,
+    events: &mut EventQueue,
+    statistics: &mut PortStatistics,
+) {
+    for diagnostic in [port_state.take_diagnostic(), diagnostic]
+        .into_iter()
+        .flatten()
+    {
+        match diagnostic {
+            Diagnostic::SerializationFailed => {
+                statistics.serialization_failures =
+                    statistics.serialization_failures.saturating_add(1);
+            }
+            Diagnostic::InternalError => {
+                statistics.internal_errors = statistics.internal_errors.saturating_add(1);
+            }
+            _ => {}
+        }
+
+        if (cfg!(feature = "silent") || diagnostic.indicates_bug())
+            && events.push(PortEvent::Diagnostic(diagnostic))
+        {
+            statistics.events_dropped = statistics.events_dropped.wrapping_add(1);
+        }
+    }
+
+    port_state.take_counts(statistics);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{
+            InstanceConfig, LogMessageIntervals, ManagementPolicy, Profile, TransmitEnable,
+            MAX_UNICAST_MASTERS,
+        },
+        datastructures::{
+            messages::{
+                message_tlvs, ManagementAction, SdoId, MAX_MESSAGE_TLVS, UNSPECIFIED_LOG_INTERVAL,
+            },
+            WireFormat,
+        },
+        BasicFilter, ClockIdentity, Interval, PtpInstance,
+    };
+
+    struct TestClock;
+
+    impl Clock for TestClock {
+        type Error = std::convert::Infallible;
+
+        fn now(&self) -> Time {
+            Time::from_secs(10)
+        }
+
+        fn adjust(
+            &mut self,
+            _time_offset: Duration,
+            _frequency_multiplier: f64,
+            _time_properties_ds: &TimePropertiesDS,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    fn test_instance() -> PtpInstance<TestClock, BasicFilter> {
+        PtpInstance::new(
+            InstanceConfig {
+                clock_identity: ClockIdentity::default(),
+                priority_1: 128,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            },
+            TimePropertiesDS::default(),
+            TestClock,
+            BasicFilter::new(0.25),
+        )
+    }
+
+    fn test_config() -> PortConfig {
+        PortConfig {
+            delay_mechanism: DelayMechanism::E2E {
+                interval: Interval::ONE_SECOND,
+            },
+            announce_interval: Interval::ONE_SECOND,
+            announce_receipt_timeout: 3,
+            sync_interval: Interval::ONE_SECOND,
+            master_only: false,
+            delay_asymmetry: Duration::ZERO,
+            ingress_latency: Duration::ZERO,
+            egress_latency: Duration::ZERO,
+            management: Default::default(),
+            communication_mode: CommunicationMode::Multicast,
+            transmit: TransmitEnable::ALL,
+            unicast_client: None,
+            log_message_intervals: LogMessageIntervals::STANDARD,
+        }
+    }
+
+    fn grant_table(slots: usize) -> &'static mut [UnicastGrantSlot] {
+        std::vec::Vec::from_iter((0..slots).map(|_| UnicastGrantSlot::EMPTY)).leak()
+    }
+
+    #[test]
+    fn test_action_iterator_peek_and_defer() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        drop(port.handle_announce_receipt_timer());
+
+        let mut actions = port.handle_sync_timer();
+        assert_eq!(actions.len(), 2);
+        assert!(matches!(
+            actions.peek(),
+            Some(PortAction::ResetSyncTimer { .. })
+        ));
+        let reset = actions.next().unwrap();
+        assert!(matches!(
+            actions.peek(),
+            Some(PortAction::SendTimeCritical { .. })
+        ));
+
+        // The socket would block, so the sync is sent later
+        let sync = actions.next().unwrap();
+        let PortAction::SendTimeCritical { data: sent, .. } = sync else {
+            panic!("Unexpected action {sync:?}");
+        };
+        assert!(actions.next().is_none());
+        actions.defer(sync).unwrap();
+        assert_eq!(actions.len(), 1);
+        let Some(PortAction::SendTimeCritical { data, .. }) = actions.next() else {
+            panic!("Deferred action not returned");
+        };
+        assert_eq!(data, sent);
+
+        // Putting back in reverse restores the original order, until nothing
+        // more fits
+        actions.defer(PortAction::SendGeneral { data }).unwrap();
+        actions.defer(reset).unwrap();
+        actions
+            .defer(PortAction::ResetAnnounceTimer {
+                duration: core::time::Duration::from_secs(1),
+            })
+            .unwrap();
+        let overflow = actions.defer(PortAction::ResetDelayRequestTimer {
+            duration: core::time::Duration::from_secs(1),
+        });
+        assert!(matches!(
+            overflow,
+            Err(PortAction::ResetDelayRequestTimer { .. })
+        ));
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceTimer { .. })
+        ));
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetSyncTimer { .. })
+        ));
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::SendGeneral { .. })
+        ));
+        assert!(actions.next().is_none());
+    }
+
+    #[test]
+    fn test_clock_source_change() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+
+        assert!(port.handle_general_receive(&[]).next().is_none());
+        assert_eq!(port.statistics().clock_source_changes, 0);
+
+        instance.notify_clock_source_change();
+
+        assert!(port.handle_general_receive(&[]).next().is_none());
+        assert_eq!(port.statistics().clock_source_changes, 1);
+        assert_eq!(
+            port.statistics().last_clock_source_change,
+            Some(Time::from_secs(10))
+        );
+
+        // The change is only accounted for once
+        assert!(port.handle_general_receive(&[]).next().is_none());
+        assert_eq!(port.statistics().clock_source_changes, 1);
+    }
+
+    #[test]
+    fn test_state_changed_event() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        while port.take_event().is_some() {}
+
+        // Nobody else announces, so the port becomes master
+        port.handle_announce_receipt_timer();
+        assert_eq!(
+            port.take_event(),
+            Some(PortEvent::StateChanged {
+                previous: PortStateKind::Listening,
+                current: PortStateKind::Master,
+            })
+        );
+        assert_eq!(port.take_event(), None);
+
+        // Staying master is not a change
+        port.handle_announce_receipt_timer();
+        assert_eq!(port.take_event(), None);
+    }
+
+    #[test]
+    fn test_timeline() {
+        let instance = test_instance();
+        let remote = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let config = PortConfig {
+            delay_mechanism: DelayMechanism::OneWay {
+                path_delay: Duration::ZERO,
+            },
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let slave = TestPortState::Slave {
+            clock_identity: remote.clock_identity,
+            port_number: remote.port_number,
+        };
+        let (mut port, _) = instance.add_port_in_state(config, rng, slave).end_bmca();
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: remote.clock_identity,
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut buffer = [0; MAX_DATA_LEN];
+        let mut sync = |port: &mut Port<Running<_, _>, _>, sequence_id, sent, received| {
+            let mut message = Message::sync(
+                &default_ds,
+                remote,
+                sequence_id,
+                sent,
+                Interval::ONE_SECOND,
+                false,
+                &LogMessageIntervals::STANDARD,
+            );
+            if let Message::Sync(sync) = &mut message {
+                sync.header.two_step_flag = false;
+            }
+            let len = message.serialize(&mut buffer).unwrap();
+            assert!(port
+                .handle_timecritical_receive(&buffer[..len], received)
+                .next()
+                .is_none());
+        };
+
+        // Offsets are only recorded once a threshold is set, the test clock
+        // stands at 10 seconds
+        sync(&mut port, 1, Time::from_secs(1), Time::from_secs(2));
+        assert!(port.timeline().entries().is_empty());
+
+        port.set_offset_spike_threshold(Some(Duration::from_millis(1)));
+        port.handle_link_change(false);
+        sync(&mut port, 2, Time::from_secs(9), Time::from_secs(10));
+        port.handle_announce_receipt_timer();
+
+        let events: std::vec::Vec<_> = port
+            .timeline()
+            .around(Time::from_secs(10), Duration::from_secs(1))
+            .map(|entry| entry.event)
+            .collect();
+        assert_eq!(
+            events,
+            [
+                TimelineEvent::LinkChanged { up: false },
+                TimelineEvent::OffsetSpike {
+                    offset: Duration::from_secs(1)
+                },
+                TimelineEvent::ClockStepped {
+                    correction: Duration::from_millis(-250)
+                },
+                TimelineEvent::AnnounceReceiptTimeout,
+                TimelineEvent::StateChanged {
+                    previous: PortStateKind::Slave,
+                    current: PortStateKind::Master,
+                },
+                TimelineEvent::ParentChanged {
+                    previous: Some(remote.clock_identity),
+                    current: None,
+                },
+            ]
+        );
+        assert_eq!(
+            port.timeline()
+                .around(Time::from_secs(2), Duration::from_secs(1))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_events() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        while port.take_event().is_some() {}
+
+        assert!(port.handle_general_receive(&[]).next().is_none());
+
+        if cfg!(feature = "silent") {
+            assert_eq!(
+                port.take_event(),
+                Some(PortEvent::Diagnostic(Diagnostic::InvalidPacket))
+            );
+        }
+        assert_eq!(port.take_event(), None);
+    }
+
+    #[test]
+    fn test_pdelay_responder() {
+        let instance = test_instance();
+
+        let config = PortConfig {
+            delay_mechanism: DelayMechanism::P2P {
+                interval: Interval::ONE_SECOND,
+                one_step_responder: false,
+            },
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(config, rng).end_bmca();
+
+        let requester = PortIdentity {
+            clock_identity: ClockIdentity([2; 8]),
+            port_number: 1,
+        };
+        let requester_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: requester.clock_identity,
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut request =
+            Message::pdelay_req(&requester_ds, requester, 7, &LogMessageIntervals::STANDARD);
+        if let Message::PDelayReq(request) = &mut request {
+            // Residence time added by a transparent clock on the way
+            request.header.correction_field = TimeInterval(2000.into());
+        }
+        let mut buffer = [0; MAX_DATA_LEN];
+        let length = request.serialize(&mut buffer).unwrap();
+        let request = &buffer[..length];
+
+        // Respond with t2 and t3, and check the turnaround time a requester
+        // derives from the response and follow up
+        let respond = |port: &mut Port<_, _>, t2: Time, t3: Time| {
+            let mut actions = port.handle_timecritical_receive(request, t2);
+            let Some(PortAction::SendTimeCritical { context, data, .. }) = actions.next() else {
+                panic!("Unexpected action");
+            };
+            assert!(actions.next().is_none());
+            let Message::PDelayResp(response) = Message::deserialize(data).unwrap() else {
+                panic!("Incorrect message type");
+            };
+            drop(actions);
+            assert!(response.header.two_step_flag);
+            assert_eq!(response.header.sequence_id, 7);
+            assert_eq!(response.requesting_port_identity, requester);
+
+            let mut actions = port.handle_send_timestamp(context, t3);
+            let Some(PortAction::SendGeneral { data }) = actions.next() else {
+                panic!("Unexpected action");
+            };
+            assert!(actions.next().is_none());
+            let Message::PDelayRespFollowUp(follow_up) = Message::deserialize(data).unwrap() else {
+                panic!("Incorrect message type");
+            };
+            assert_eq!(follow_up.header.sequence_id, 7);
+            assert_eq!(follow_up.requesting_port_identity, requester);
+
+            (Time::from(follow_up.response_origin_timestamp)
+                + Duration::from(follow_up.header.correction_field))
+                - (Time::from(response.request_receive_timestamp)
+                    - Duration::from(response.header.correction_field))
+        };
+
+        // Answered while listening, exact to below a nanosecond
+        let t2 = Time::from_fixed_nanos(1_000_000.25);
+        let t3 = Time::from_fixed_nanos(1_010_000.5);
+        assert_eq!(
+            respond(&mut port, t2, t3),
+            t3 - t2 + Duration::from_micros(2)
+        );
+
+        // And while master
+        port.handle_announce_receipt_timer();
+        assert!(matches!(port.port_state, PortState::Master(_)));
+        let t2 = Time::from_secs(20);
+        let t3 = t2 + Duration::from_micros(10);
+        assert_eq!(respond(&mut port, t2, t3), Duration::from_micros(12));
+
+        // But not when faulty
+        drop(port.handle_send_failure(FailedSend::General { data: &[] }, SendError::Fatal));
+        assert!(port
+            .handle_timecritical_receive(request, Time::from_secs(30))
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn test_one_step_pdelay_responder() {
+        let instance = test_instance();
+
+        let config = PortConfig {
+            delay_mechanism: DelayMechanism::P2P {
+                interval: Interval::ONE_SECOND,
+                one_step_responder: true,
+            },
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(config, rng).end_bmca();
+
+        let requester = PortIdentity {
+            clock_identity: ClockIdentity([2; 8]),
+            port_number: 1,
+        };
+        let requester_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: requester.clock_identity,
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut request =
+            Message::pdelay_req(&requester_ds, requester, 7, &LogMessageIntervals::STANDARD);
+        if let Message::PDelayReq(request) = &mut request {
+            request.header.correction_field = TimeInterval(2000.into());
+        }
+        let mut buffer = [0; MAX_DATA_LEN];
+        let length = request.serialize(&mut buffer).unwrap();
+
+        let mut actions = port.handle_timecritical_receive(&buffer[..length], Time::from_secs(20));
+        let Some(PortAction::SendTimeCritical { context, data, .. }) = actions.next() else {
+            panic!("Unexpected action");
+        };
+        assert!(actions.next().is_none());
+        let Message::PDelayResp(response) = Message::deserialize(data).unwrap() else {
+            panic!("Incorrect message type");
+        };
+        drop(actions);
+
+        // The hardware adds the turnaround time to the correction of the
+        // request, and no follow up is needed
+        assert!(!response.header.two_step_flag);
+        assert_eq!(response.header.sequence_id, 7);
+        assert_eq!(response.header.correction_field, TimeInterval(2000.into()));
+        assert_eq!(response.request_receive_timestamp, WireTimestamp::default());
+        assert_eq!(response.requesting_port_identity, requester);
+        assert!(port
+            .handle_send_timestamp(context, Time::from_secs(21))
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn test_gptp_as_capable() {
+        let instance = test_instance();
+
+        let config = PortConfig {
+            delay_mechanism: DelayMechanism::P2P {
+                interval: Interval::ONE_SECOND,
+                one_step_responder: false,
+            },
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut port = instance.add_port(config, rng);
+        assert_eq!(port.as_capable(), None);
+        port.set_gptp(Some(GptpConfig {
+            allowed_lost_responses: 2,
+            ..Default::default()
+        }));
+        let (mut port, _) = port.end_bmca();
+        assert_eq!(port.as_capable(), Some(false));
+
+        // Without an asCapable link the port doesn't become master
+        let mut actions = port.handle_announce_receipt_timer();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceReceiptTimer { .. })
+        ));
+        assert!(actions.next().is_none());
+        drop(actions);
+        assert!(matches!(port.state(), PortState::Listening));
+
+        let peer = PortIdentity {
+            clock_identity: ClockIdentity([2; 8]),
+            port_number: 1,
+        };
+        let peer_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: peer.clock_identity,
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+
+        // Request and respond with a mean link delay of `delay`
+        let exchange = |port: &mut Port<_, _>, delay: Duration| {
+            let t1 = Time::from_secs(10);
+            let t2 = Time::from_secs(500);
+            let t3 = t2 + Duration::from_micros(10);
+            let t4 = t1 + Duration::from_micros(10) + delay * 2;
+
+            let mut actions = port.handle_delay_request_timer();
+            assert!(matches!(
+                actions.next(),
+                Some(PortAction::ResetDelayRequestTimer { .. })
+            ));
+            let Some(PortAction::SendTimeCritical { context, data, .. }) = actions.next() else {
+                panic!("Unexpected action");
+            };
+            let Message::PDelayReq(request) = Message::deserialize(data).unwrap() else {
+                panic!("Incorrect message type");
+            };
+            drop(actions);
+            assert!(port.handle_send_timestamp(context, t1).next().is_none());
+
+            let mut buffer = [0; MAX_DATA_LEN];
+            let response = Message::pdelay_resp(&request, peer, t2, &LogMessageIntervals::STANDARD);
+            let length = response.serialize(&mut buffer).unwrap();
+            assert!(port
+                .handle_timecritical_receive(&buffer[..length], t4)
+                .next()
+                .is_none());
+
+            let follow_up = Message::pdelay_resp_follow_up(
+                &peer_ds,
+                peer,
+                request.header.source_port_identity,
+                request.header.sequence_id,
+                TimeInterval::default(),
+                t3,
+                &LogMessageIntervals::STANDARD,
+            );
+            let length = follow_up.serialize(&mut buffer).unwrap();
+            assert!(port
+                .handle_general_receive(&buffer[..length])
+                .next()
+                .is_none());
+        };
+
+        // A short enough link is asCapable
+        exchange(&mut port, Duration::from_nanos(500));
+        assert_eq!(port.as_capable(), Some(true));
+        assert_eq!(
+            port.take_event(),
+            Some(PortEvent::AsCapableChanged { as_capable: true })
+        );
+        port.handle_announce_receipt_timer();
+        assert!(matches!(port.state(), PortState::Master(_)));
+
+        // Until more responses than allowed are lost in a row
+        for _ in 0..3 {
+            drop(port.handle_delay_request_timer());
+            assert_eq!(port.as_capable(), Some(true));
+        }
+        drop(port.handle_delay_request_timer());
+        assert_eq!(port.as_capable(), Some(false));
+        assert!(matches!(port.state(), PortState::Listening));
+
+        // Which keeps it from sending sync messages, and from the BMCA
+        let mut actions = port.handle_sync_timer();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetSyncTimer { .. })
+        ));
+        assert!(actions.next().is_none());
+        drop(actions);
+        for announce in better_master_announces() {
+            drop(port.handle_general_receive(&announce));
+        }
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        assert!(matches!(port.state(), PortState::Listening));
+        let (mut port, _) = port.end_bmca();
+
+        // A link that is too long is not asCapable either
+        exchange(&mut port, Duration::from_nanos(900));
+        assert_eq!(port.as_capable(), Some(false));
+        exchange(&mut port, Duration::from_nanos(700));
+        assert_eq!(port.as_capable(), Some(true));
+    }
+
+    #[test]
+    fn test_common_link_delay() {
+        let instance = test_instance();
+
+        let config = PortConfig {
+            delay_mechanism: DelayMechanism::CommonP2P,
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut port = instance.add_port(config, rng);
+        port.set_gptp(Some(GptpConfig::default()));
+        let (mut port, _) = port.end_bmca();
+
+        // The link is measured by the service, not the port
+        assert!(port.handle_delay_request_timer().next().is_none());
+
+        let link_delay = LinkDelay {
+            mean_delay: Duration::from_nanos(500),
+            source: TimestampSource::Hardware,
+            neighbor_rate_ratio: Some(1.00001),
+        };
+        assert!(port
+            .handle(PortInput::CommonLinkDelay { link_delay })
+            .next()
+            .is_none());
+        assert_eq!(port.as_capable(), Some(true));
+        assert_eq!(
+            port.take_event(),
+            Some(PortEvent::AsCapableChanged { as_capable: true })
+        );
+
+        // A port that becomes slave starts out with the last delay measured
+        for announce in better_master_announces() {
+            drop(port.handle_general_receive(&announce));
+        }
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        let (port, _) = port.end_bmca();
+        let PortState::Slave(slave) = port.state() else {
+            panic!("Port did not become slave");
+        };
+        assert_eq!(slave.mean_delay(), Some(Duration::from_nanos(500)));
+        assert_eq!(slave.neighbor_rate_ratio(), Some(1.00001));
+
+        // Ports that measure their own link ignore the service
+        drop(port);
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        port.handle_common_link_delay(link_delay);
+        assert_eq!(port.common_link_delay, None);
+    }
+
+    #[test]
+    fn test_fixed_master() {
+        let instance = test_instance();
+        let master = PortIdentity {
+            clock_identity: ClockIdentity([2; 8]),
+            port_number: 3,
+        };
+        let fixed_master = FixedMasterConfig {
+            clock_identity: master.clock_identity,
+            port_number: master.port_number,
+            sync_receipt_timeout: 3,
+            delay_receipt_timeout: 2,
+        };
+        let timeout = core::time::Duration::from_secs(3);
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut port = instance.add_port(test_config(), rng);
+        for invalid in [
+            FixedMasterConfig {
+                sync_receipt_timeout: 0,
+                ..fixed_master
+            },
+            FixedMasterConfig {
+                delay_receipt_timeout: 0,
+                ..fixed_master
+            },
+        ] {
+            assert!(port.set_fixed_master(Some(invalid)).is_err());
+        }
+        port.set_fixed_master(Some(fixed_master)).unwrap();
+
+        // The port becomes slave right away, without a single announce message
+        let (mut port, mut actions) = port.end_bmca();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceReceiptTimer { duration }) if duration == timeout
+        ));
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetDelayRequestTimer { .. })
+        ));
+        assert!(actions.next().is_none());
+        drop(actions);
+        assert_eq!(port.state().remote_master(), Some(master));
+
+        // Announce messages of a better master change nothing
+        for announce in better_master_announces() {
+            assert!(port.handle_general_receive(&announce).next().is_none());
+        }
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        let (mut port, _) = port.end_bmca();
+        assert_eq!(port.state().remote_master(), Some(master));
+        assert_eq!(
+            instance.status().grandmaster_identity,
+            master.clock_identity
+        );
+        assert_eq!(instance.status().steps_removed, 1);
+
+        // A Sync from the master restarts the timeout
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: master.clock_identity,
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let sync = Message::sync(
+            &default_ds,
+            master,
+            1,
+            Time::from_secs(1),
+            Interval::ONE_SECOND,
+            false,
+            &LogMessageIntervals::STANDARD,
+        );
+        let mut buffer = [0; MAX_DATA_LEN];
+        let len = sync.serialize(&mut buffer).unwrap();
+        let mut actions = port.handle_timecritical_receive(&buffer[..len], Time::from_secs(2));
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceReceiptTimer { duration }) if duration == timeout
+        ));
+        assert!(actions.next().is_none());
+        drop(actions);
+
+        // Without one the port gives up on the master, and tries again later
+        let mut actions = port.handle_announce_receipt_timer();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceReceiptTimer { duration }) if duration == timeout
+        ));
+        assert!(actions.next().is_none());
+        drop(actions);
+        assert!(matches!(port.state(), PortState::Listening));
+        assert!(port
+            .timeline()
+            .entries()
+            .iter()
+            .any(|entry| entry.event == TimelineEvent::SyncReceiptTimeout));
+
+        drop(port.handle_announce_receipt_timer());
+        assert_eq!(port.state().remote_master(), Some(master));
+
+        // As it does when its delay requests go unanswered
+        for _ in 0..2 {
+            let mut actions = port.handle_delay_request_timer();
+            assert!(matches!(
+                actions.next(),
+                Some(PortAction::ResetDelayRequestTimer { .. })
+            ));
+            assert!(matches!(
+                actions.next(),
+                Some(PortAction::SendTimeCritical { .. })
+            ));
+        }
+        let mut actions = port.handle_delay_request_timer();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceReceiptTimer { duration }) if duration == timeout
+        ));
+        assert!(actions.next().is_none());
+        drop(actions);
+        assert!(matches!(port.state(), PortState::Listening));
+        assert!(port
+            .timeline()
+            .entries()
+            .iter()
+            .any(|entry| entry.event == TimelineEvent::DelayReceiptTimeout));
+    }
+
+    #[test]
+    fn test_internal_error_counted() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        port.handle_announce_receipt_timer();
+        while port.take_event().is_some() {}
+
+        // Only a bug in statime keeps the clock busy while sending a sync
+        let state = AtomicRef::clone(&port.lifecycle.state);
+        let clock = state.local_clock.borrow_mut();
+        drop(port.handle_sync_timer());
+        drop(clock);
+
+        assert_eq!(port.statistics().internal_errors, 1);
+        assert_eq!(port.statistics().serialization_failures, 0);
+        // Bugs are reported even when they are logged
+        assert_eq!(
+            port.take_event(),
+            Some(PortEvent::Diagnostic(Diagnostic::InternalError))
+        );
+        assert_eq!(port.take_event(), None);
+    }
+
+    struct CountingClock(std::rc::Rc<core::cell::Cell<u32>>);
+
+    impl Clock for CountingClock {
+        type Error = std::convert::Infallible;
+
+        fn now(&self) -> Time {
+            Time::from_secs(10)
+        }
+
+        fn adjust(
+            &mut self,
+            _time_offset: Duration,
+            _frequency_multiplier: f64,
+            _time_properties_ds: &TimePropertiesDS,
+        ) -> Result<(), Self::Error> {
+            self.0.set(self.0.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "snapshot")]
+    #[test]
+    fn test_snapshot_restore() {
+        use crate::{InstanceSnapshot, LeapIndicator, LeapSecond, SnapshotError};
+
+        let instance = test_instance();
+        instance.set_priority_1(100).unwrap();
+        instance.set_free_run(true).unwrap();
+        instance
+            .schedule_leap_second(Some(LeapSecond {
+                at: Time::from_secs(100_000),
+                leap_indicator: LeapIndicator::Leap61,
+            }))
+            .unwrap();
+
+        let mut buffer = [0; InstanceSnapshot::SIZE];
+        instance.snapshot().serialize(&mut buffer).unwrap();
+        let snapshot = InstanceSnapshot::deserialize(&buffer).unwrap();
+
+        let restarted = test_instance();
+        restarted.restore(&snapshot).unwrap();
+        assert_eq!(restarted.snapshot(), instance.snapshot());
+        assert_eq!(restarted.priority_1(), 100);
+        assert!(restarted.free_run());
+
+        let other = PtpInstance::<_, BasicFilter>::new(
+            InstanceConfig {
+                clock_identity: ClockIdentity([1; 8]),
+                priority_1: 128,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            },
+            TimePropertiesDS::default(),
+            TestClock,
+            BasicFilter::new(0.25),
+        );
+        assert_eq!(
+            other.restore(&snapshot),
+            Err(SnapshotError::ClockIdentityMismatch)
+        );
+        assert_eq!(other.priority_1(), 128);
+    }
+
+    #[test]
+    fn test_free_run() {
+        let adjustments = std::rc::Rc::new(core::cell::Cell::new(0));
+        let instance = PtpInstance::new(
+            InstanceConfig {
+                clock_identity: ClockIdentity::default(),
+                priority_1: 128,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            },
+            TimePropertiesDS::default(),
+            CountingClock(adjustments.clone()),
+            BasicFilter::new(0.25),
+        );
+        assert!(!instance.free_run());
+
+        let remote = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let config = PortConfig {
+            delay_mechanism: DelayMechanism::OneWay {
+                path_delay: Duration::ZERO,
+            },
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance
+            .add_port_in_state(
+                config,
+                rng,
+                TestPortState::Slave {
+                    clock_identity: remote.clock_identity,
+                    port_number: remote.port_number,
+                },
+            )
+            .end_bmca();
+        port.set_measurement_queue(true);
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: remote.clock_identity,
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut message = Message::sync(
+            &default_ds,
+            remote,
+            1,
+            Time::from_secs(1),
+            Interval::ONE_SECOND,
+            false,
+            &LogMessageIntervals::STANDARD,
+        );
+        if let Message::Sync(sync) = &mut message {
+            sync.header.two_step_flag = false;
+        }
+        let mut buffer = [0; MAX_DATA_LEN];
+        let len = message.serialize(&mut buffer).unwrap();
+
+        // Measurements are still produced, but only applied outside free-run
+        for (free_run, expected_adjustments) in [(true, 0), (false, 1)] {
+            instance.set_free_run(free_run).unwrap();
+            assert_eq!(instance.free_run(), free_run);
+
+            assert!(port
+                .handle_timecritical_receive(&buffer[..len], Time::from_secs(2))
+                .next()
+                .is_none());
+            assert!(port.take_measurement().is_some());
+            assert_eq!(adjustments.get(), expected_adjustments);
+            assert_eq!(port.frequency().current().is_some(), !free_run);
+        }
+
+        // The clock doesn't report its correction, so it is followed from none
+        let applied = port.frequency().current().unwrap();
+        assert_eq!(applied.time, Time::from_secs(2));
+        assert_eq!(applied.ppb, 0.0);
+    }
+
+    #[test]
+    fn test_quirks() {
+        let instance = test_instance();
+        let remote = PortIdentity {
+            clock_identity: ClockIdentity([0x00, 0x1b, 0x19, 1, 2, 3, 4, 5]),
+            port_number: 1,
+        };
+        let config = PortConfig {
+            delay_mechanism: DelayMechanism::OneWay {
+                path_delay: Duration::ZERO,
+            },
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let slave = TestPortState::Slave {
+            clock_identity: remote.clock_identity,
+            port_number: remote.port_number,
+        };
+        let (mut port, _) = instance.add_port_in_state(config, rng, slave).end_bmca();
+        port.set_measurement_queue(true);
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: remote.clock_identity,
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut sync_buffer = [0; MAX_DATA_LEN];
+        let mut follow_up_buffer = [0; MAX_DATA_LEN];
+        let mut exchange = |port: &mut Port<Running<_, _>, _>, sequence_id| {
+            let sync = Message::sync(
+                &default_ds,
+                remote,
+                sequence_id,
+                Time::from_secs(1),
+                Interval::ONE_SECOND,
+                false,
+                &LogMessageIntervals::STANDARD,
+            );
+            let sync_len = sync.serialize(&mut sync_buffer).unwrap();
+            let follow_up = Message::follow_up(
+                &default_ds,
+                remote,
+                sequence_id,
+                Time::from_secs(1),
+                Interval::ONE_SECOND,
+                false,
+                &LogMessageIntervals::STANDARD,
+            );
+            let follow_up_len = follow_up.serialize(&mut follow_up_buffer).unwrap();
+
+            // The FollowUp overtakes its Sync
+            assert!(port
+                .handle_general_receive(&follow_up_buffer[..follow_up_len])
+                .next()
+                .is_none());
+            assert!(port
+                .handle_timecritical_receive(&sync_buffer[..sync_len], Time::from_secs(2))
+                .next()
+                .is_none());
+        };
+
+        // Accepted by default, and counted
+        exchange(&mut port, 1);
+        assert!(port.take_measurement().is_some());
+        assert_eq!(port.statistics().quirks.follow_up_before_sync, 1);
+
+        port.set_quirks(&[QuirkRule {
+            matches: crate::QuirkMatch::Oui([0x00, 0x1b, 0x19]),
+            quirks: crate::Quirks::NONE,
+        }])
+        .unwrap();
+        port.force_state(slave, SdoId::default());
+
+        exchange(&mut port, 2);
+        assert!(port.take_measurement().is_none());
+        assert_eq!(port.statistics().quirks.follow_up_before_sync, 1);
+
+        let rules = [QuirkRule {
+            matches: crate::QuirkMatch::Any,
+            quirks: crate::Quirks::DEFAULT,
+        }; MAX_QUIRK_RULES + 1];
+        assert_eq!(
+            port.set_quirks(&rules),
+            Err(QuirkConfigError::TooManyRules(MAX_QUIRK_RULES + 1))
+        );
+    }
+
+    #[test]
+    fn test_simulated_path() {
+        let instance = test_instance();
+        let remote = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let slave = TestPortState::Slave {
+            clock_identity: remote.clock_identity,
+            port_number: remote.port_number,
+        };
+        let (mut port, _) = instance
+            .add_port_in_state(test_config(), rng, slave)
+            .end_bmca();
+        port.set_measurement_queue(true);
+        port.set_simulated_path(Some(SimulatedPath {
+            delay: Duration::from_micros(100),
+            asymmetry: Duration::from_micros(20),
+        }));
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: remote.clock_identity,
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut buffer = [0; MAX_DATA_LEN];
+
+        // A loopback path, on which messages arrive the moment they are sent
+        let sync = Message::sync(
+            &default_ds,
+            remote,
+            1,
+            Time::from_secs(1),
+            Interval::ONE_SECOND,
+            false,
+            &LogMessageIntervals::STANDARD,
+        );
+        let len = sync.serialize(&mut buffer).unwrap();
+        drop(port.handle_timecritical_receive(&buffer[..len], Time::from_secs(1)));
+        let follow_up = Message::follow_up(
+            &default_ds,
+            remote,
+            1,
+            Time::from_secs(1),
+            Interval::ONE_SECOND,
+            false,
+            &LogMessageIntervals::STANDARD,
+        );
+        let len = follow_up.serialize(&mut buffer).unwrap();
+        drop(port.handle_general_receive(&buffer[..len]));
+
+        let mut actions = port.handle_delay_request_timer();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetDelayRequestTimer { .. })
+        ));
+        let Some(PortAction::SendTimeCritical { context, data, .. }) = actions.next() else {
+            panic!("Unexpected action");
+        };
+        let Message::DelayReq(request) = Message::deserialize(data).unwrap() else {
+            panic!("Unexpected message type");
+        };
+        drop(actions);
+        drop(port.handle_send_timestamp(context, Time::from_secs(2)));
+        let response = Message::delay_resp(
+            &request,
+            remote,
+            Interval::ONE_SECOND,
+            Time::from_secs(2),
+            &LogMessageIntervals::STANDARD,
+        );
+        let len = response.serialize(&mut buffer).unwrap();
+        drop(port.handle_general_receive(&buffer[..len]));
+
+        let PortState::Slave(slave) = &port.port_state else {
+            panic!("Not slave");
+        };
+        assert_eq!(slave.mean_delay(), Some(Duration::from_micros(100)));
+
+        // The asymmetry isn't corrected for, so it shows up as offset
+        let measurement = std::iter::from_fn(|| port.take_measurement())
+            .last()
+            .unwrap();
+        assert_eq!(measurement.master_offset, Duration::from_micros(20));
+    }
+
+    #[test]
+    fn test_ignore_sync_from_other_master() {
+        let instance = test_instance();
+        let parent = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let other = PortIdentity {
+            clock_identity: ClockIdentity([2; 8]),
+            port_number: 1,
+        };
+        let config = PortConfig {
+            delay_mechanism: DelayMechanism::OneWay {
+                path_delay: Duration::ZERO,
+            },
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance
+            .add_port_in_state(
+                config,
+                rng,
+                TestPortState::Slave {
+                    clock_identity: parent.clock_identity,
+                    port_number: parent.port_number,
+                },
+            )
+            .end_bmca();
+        port.set_measurement_queue(true);
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: other.clock_identity,
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut buffer = [0; MAX_DATA_LEN];
+
+        let mut sync = Message::sync(
+            &default_ds,
+            other,
+            1,
+            Time::from_secs(1),
+            Interval::ONE_SECOND,
+            false,
+            &LogMessageIntervals::STANDARD,
+        );
+        if let Message::Sync(sync) = &mut sync {
+            sync.header.two_step_flag = false;
+        }
+        let len = sync.serialize(&mut buffer).unwrap();
+        assert!(port
+            .handle_timecritical_receive(&buffer[..len], Time::from_secs(2))
+            .next()
+            .is_none());
+
+        let follow_up = Message::follow_up(
+            &default_ds,
+            other,
+            2,
+            Time::from_secs(1),
+            Interval::ONE_SECOND,
+            false,
+            &LogMessageIntervals::STANDARD,
+        );
+        let len = follow_up.serialize(&mut buffer).unwrap();
+        assert!(port.handle_general_receive(&buffer[..len]).next().is_none());
+
+        assert!(port.take_measurement().is_none());
+        assert_eq!(port.statistics().non_parent_sync_messages, 2);
+    }
+
+    #[test]
+    fn test_reevaluate_bmca_now() {
+        let instance = test_instance();
+        assert!(!instance.bmca_requested());
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (port, _) = instance.add_port(test_config(), rng).end_bmca();
+
+        // Can be requested while the ports are running
+        instance.reevaluate_bmca_now();
+        assert!(instance.bmca_requested());
+
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        assert!(!instance.bmca_requested());
+    }
+
+    #[test]
+    fn test_set_priority_1() {
+        let instance = test_instance();
+        assert_eq!(instance.priority_1(), 128);
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut port = instance.add_port_in_state(test_config(), rng, TestPortState::Master);
+        instance.bmca(&mut [&mut port]);
+        let (port, _) = port.end_bmca();
+
+        instance.set_priority_1(10).unwrap();
+        assert_eq!(instance.priority_1(), 10);
+        assert!(instance.bmca_requested());
+
+        // The master port announces the new priority right away
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        let (mut port, mut actions) = port.end_bmca();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceTimer { duration }) if duration.is_zero()
+        ));
+        assert!(actions.next().is_none());
+
+        let mut actions = port.handle_announce_timer();
+        actions.next();
+        let Some(PortAction::SendGeneral { data }) = actions.next() else {
+            panic!("Unexpected action");
+        };
+        let Message::Announce(announce) = Message::deserialize(data).unwrap() else {
+            panic!("Unexpected message type");
+        };
+        assert_eq!(announce.grandmaster_priority_1, 10);
+        drop(actions);
+
+        // Without a change there is nothing to announce early
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        let (_, mut actions) = port.end_bmca();
+        assert!(actions.next().is_none());
+    }
+
+    // A clock the test moves forward by hand
+    struct ManualClock(std::rc::Rc<core::cell::Cell<Time>>);
+
+    impl Clock for ManualClock {
+        type Error = std::convert::Infallible;
+
+        fn now(&self) -> Time {
+            self.0.get()
+        }
+
+        fn adjust(
+            &mut self,
+            _time_offset: Duration,
+            _frequency_multiplier: f64,
+            _time_properties_ds: &TimePropertiesDS,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_demote() {
+        let now = std::rc::Rc::new(core::cell::Cell::new(Time::from_secs(10)));
+        let instance = PtpInstance::new(
+            InstanceConfig {
+                clock_identity: ClockIdentity::default(),
+                priority_1: 5,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            },
+            TimePropertiesDS::default(),
+            ManualClock(now.clone()),
+            BasicFilter::new(0.25),
+        );
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut port = instance.add_port_in_state(test_config(), rng, TestPortState::Master);
+        instance.bmca(&mut [&mut port]);
+        let (mut port, _) = port.end_bmca();
+
+        // The better master takes over once it sees the demoted priority
+        for announce in better_master_announces() {
+            drop(port.handle_general_receive(&announce));
+        }
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        assert!(port.is_master());
+
+        instance.demote(200, 2).unwrap();
+        assert_eq!(instance.priority_1(), 200);
+        assert!(instance.demotion_pending());
+
+        // Sync continues while the slaves follow the new master
+        for secs in [10, 11] {
+            now.set(Time::from_secs(secs));
+            instance.bmca(&mut [&mut port]);
+            assert!(port.is_master());
+            assert_eq!(instance.status().priority_1, 200);
+            let (mut running, _) = port.end_bmca();
+            assert!(running
+                .handle_sync_timer()
+                .any(|action| matches!(action, PortAction::SendTimeCritical { .. })));
+            port = running.start_bmca();
+        }
+        assert!(instance.demotion_pending());
+
+        now.set(Time::from_secs(12));
+        instance.bmca(&mut [&mut port]);
+        assert!(!port.is_master());
+        assert!(!instance.demotion_pending());
+        assert_eq!(
+            instance.status().grandmaster_identity,
+            ClockIdentity([1; 8])
+        );
+
+        // Setting the priority cancels a demotion
+        instance.demote(100, 2).unwrap();
+        instance.set_priority_1(100).unwrap();
+        assert!(!instance.demotion_pending());
+    }
+
+    #[test]
+    fn test_message_rates() {
+        let instance = test_instance();
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance
+            .add_port_in_state(test_config(), rng, TestPortState::Master)
+            .end_bmca();
+
+        drop(port.handle_sync_timer());
+        drop(port.handle_announce_timer());
+        let sent = port.statistics().message_rates.sent;
+        assert_eq!(sent.sync.last, Some(Time::from_secs(10)));
+        assert_eq!(sent.announce.last, Some(Time::from_secs(10)));
+        assert_eq!(sent.delay_req.last, None);
+
+        let remote = PortIdentity {
+            clock_identity: ClockIdentity([0x00, 0x1b, 0x19, 1, 2, 3, 4, 5]),
+            port_number: 1,
+        };
+        let mut remote_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: remote.clock_identity,
+            priority_1: 255,
+            priority_2: 255,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut buffer = [0; MAX_DATA_LEN];
+        for millis in [2000, 2500, 3000] {
+            let message = Message::sync(
+                &remote_ds,
+                remote,
+                1,
+                Time::from_secs(1),
+                Interval::ONE_SECOND,
+                false,
+                &LogMessageIntervals::STANDARD,
+            );
+            let len = message.serialize(&mut buffer).unwrap();
+            drop(port.handle_timecritical_receive(&buffer[..len], Time::from_millis(millis)));
+        }
+
+        // Messages of other domains don't count
+        remote_ds.domain_number = 1;
+        let message = Message::sync(
+            &remote_ds,
+            remote,
+            1,
+            Time::from_secs(1),
+            Interval::ONE_SECOND,
+            false,
+            &LogMessageIntervals::STANDARD,
+        );
+        let len = message.serialize(&mut buffer).unwrap();
+        drop(port.handle_timecritical_receive(&buffer[..len], Time::from_secs(4)));
+
+        let received = port.statistics().message_rates.received;
+        assert_eq!(received.sync.per_second(), Some(2.0));
+        assert_eq!(received.sync.last, Some(Time::from_secs(3)));
+        assert_eq!(received.announce.last, None);
+    }
+
+    #[test]
+    fn test_set_time_properties() {
+        use crate::{LeapIndicator, TimePropertiesError, TimeSource};
+
+        let instance = test_instance();
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut port = instance.add_port_in_state(test_config(), rng, TestPortState::Master);
+        instance.bmca(&mut [&mut port]);
+        let (port, _) = port.end_bmca();
+
+        let invalid = TimePropertiesDS::new_ptp_time(
+            None,
+            LeapIndicator::Leap61,
+            false,
+            false,
+            TimeSource::Gnss,
+        );
+        assert_eq!(
+            instance.set_time_properties(invalid),
+            Err(TimePropertiesError::LeapWithoutUtcOffset)
+        );
+        assert!(!instance.bmca_requested());
+
+        let mut time_properties = instance.time_properties();
+        assert_eq!(time_properties, TimePropertiesDS::default());
+        time_properties.set_time_source(TimeSource::Gnss);
+        time_properties.set_traceability(true, true);
+        instance.set_time_properties(time_properties).unwrap();
+        assert_eq!(instance.time_properties(), time_properties);
+        assert!(instance.bmca_requested());
+
+        // The master port announces the new time properties right away
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        let (mut port, mut actions) = port.end_bmca();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceTimer { duration }) if duration.is_zero()
+        ));
+        assert!(actions.next().is_none());
+        assert_eq!(instance.status().time_properties_ds, time_properties);
+
+        let mut actions = port.handle_announce_timer();
+        actions.next();
+        let Some(PortAction::SendGeneral { data }) = actions.next() else {
+            panic!("Unexpected action");
+        };
+        let Message::Announce(announce) = Message::deserialize(data).unwrap() else {
+            panic!("Unexpected message type");
+        };
+        assert_eq!(announce.time_source, TimeSource::Gnss);
+        assert!(announce.header.time_tracable);
+        assert!(announce.header.frequency_tracable);
+        assert_eq!(announce.time_properties(), time_properties);
+    }
+
+    // Announce messages of a master port of a remote instance with a better
+    // grandmaster
+    fn better_master_announces() -> std::vec::Vec<std::vec::Vec<u8>> {
+        let remote = PtpInstance::new(
+            InstanceConfig {
+                clock_identity: ClockIdentity([1; 8]),
+                priority_1: 10,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            },
+            TimePropertiesDS::default(),
+            TestClock,
+            BasicFilter::new(0.25),
+        );
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut port = remote.add_port_in_state(test_config(), rng, TestPortState::Master);
+        remote.bmca(&mut [&mut port]);
+        let (mut port, _) = port.end_bmca();
+
+        // Enough to qualify the remote as a foreign master
+        (0..3)
+            .map(|_| {
+                port.handle_announce_timer()
+                    .find_map(|action| match action {
+                        PortAction::SendGeneral { data } => Some(data.to_vec()),
+                        _ => None,
+                    })
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_master_only() {
+        let instance = test_instance();
+        let master_only = PortConfig {
+            master_only: true,
+            ..test_config()
+        };
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(master_only, rng).end_bmca();
+
+        // Other masters don't delay the announce receipt timeout
+        for announce in better_master_announces() {
+            assert!(port.handle_general_receive(&announce).next().is_none());
+        }
+
+        // and are ignored by the BMCA
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        assert!(matches!(port.state(), PortState::Listening));
+        assert_eq!(
+            instance.status().grandmaster_identity,
+            ClockIdentity::default()
+        );
+
+        let (mut port, _) = port.end_bmca();
+        port.handle_announce_receipt_timer();
+        assert!(matches!(port.state(), PortState::Master(_)));
+
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        assert!(matches!(port.state(), PortState::Master(_)));
+    }
+
+    #[test]
+    fn test_master_only_boundary_clock() {
+        let instance = test_instance();
+        let master_only = PortConfig {
+            master_only: true,
+            ..test_config()
+        };
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let master_port = instance.add_port(master_only, rng);
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let port = instance.add_port(test_config(), rng);
+        let (mut master_port, _) = master_port.end_bmca();
+        let (mut port, _) = port.end_bmca();
+
+        // The better master is visible on both ports
+        for announce in better_master_announces() {
+            assert!(master_port
+                .handle_general_receive(&announce)
+                .next()
+                .is_none());
+            assert!(matches!(
+                port.handle_general_receive(&announce).next(),
+                Some(PortAction::ResetAnnounceReceiptTimer { .. })
+            ));
+        }
+
+        // Only the normal port follows it, the master-only port passes its
+        // time on
+        let mut master_port = master_port.start_bmca();
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut master_port, &mut port]);
+        assert!(matches!(master_port.state(), PortState::Master(_)));
+        assert!(matches!(port.state(), PortState::Slave(_)));
+        assert_eq!(
+            instance.status().grandmaster_identity,
+            ClockIdentity([1; 8])
+        );
+    }
+
+    #[test]
+    fn test_path_trace() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let master_port = instance.add_port(
+            PortConfig {
+                master_only: true,
+                ..test_config()
+            },
+            rng,
+        );
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let port = instance.add_port(test_config(), rng);
+        let (master_port, _) = master_port.end_bmca();
+        let (mut port, _) = port.end_bmca();
+
+        for announce in better_master_announces() {
+            let Message::Announce(message) = Message::deserialize(&announce).unwrap() else {
+                panic!("Unexpected message type");
+            };
+            assert_eq!(message.path_trace.identities(), [ClockIdentity([1; 8])]);
+            drop(port.handle_general_receive(&announce));
+        }
+
+        let mut master_port = master_port.start_bmca();
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut master_port, &mut port]);
+        assert!(matches!(port.state(), PortState::Slave(_)));
+
+        // The time is passed on with us added to the path
+        let (mut master_port, _) = master_port.end_bmca();
+        let data = master_port
+            .handle_announce_timer()
+            .find_map(|action| match action {
+                PortAction::SendGeneral { data } => Some(data.to_vec()),
+                _ => None,
+            })
+            .unwrap();
+        let Message::Announce(announce) = Message::deserialize(&data).unwrap() else {
+            panic!("Unexpected message type");
+        };
+        assert_eq!(
+            announce.path_trace.identities(),
+            [ClockIdentity([1; 8]), ClockIdentity::default()]
+        );
+    }
+
+    #[test]
+    fn test_path_trace_loop() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+
+        // The better master got its time from us, through a loop
+        for announce in better_master_announces() {
+            let Message::Announce(mut message) = Message::deserialize(&announce).unwrap() else {
+                panic!("Unexpected message type");
+            };
+            message.path_trace.push(ClockIdentity::default());
+            let mut buffer = [0; MAX_DATA_LEN];
+            let len = Message::Announce(message).serialize(&mut buffer).unwrap();
+            drop(port.handle_general_receive(&buffer[..len]));
+        }
+
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        assert!(matches!(port.state(), PortState::Listening));
+        assert_eq!(
+            instance.status().grandmaster_identity,
+            ClockIdentity::default()
+        );
+    }
+
+    #[derive(Debug, Default)]
+    struct TestCalibrationStore(std::sync::Mutex<std::vec::Vec<(u16, Calibration)>>);
+
+    impl CalibrationStore for TestCalibrationStore {
+        fn load(&self, port_number: u16) -> Option<Calibration> {
+            let stored = self.0.lock().unwrap();
+            stored
+                .iter()
+                .find(|(number, _)| *number == port_number)
+                .map(|(_, calibration)| *calibration)
+        }
+
+        fn store(
+            &self,
+            port_number: u16,
+            calibration: &Calibration,
+        ) -> Result<(), CalibrationStoreError> {
+            let mut stored = self.0.lock().unwrap();
+            stored.retain(|(number, _)| *number != port_number);
+            stored.push((port_number, *calibration));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_calibration_store() {
+        let store: &'static TestCalibrationStore =
+            std::boxed::Box::leak(std::boxed::Box::default());
+        let calibration = Calibration {
+            delay_asymmetry: Duration::from_nanos(-150),
+            ingress_latency: Duration::from_fixed_nanos(210.5),
+            egress_latency: Duration::from_nanos(180),
+        };
+        assert_eq!(
+            Calibration::from_bytes(&calibration.to_bytes()),
+            calibration
+        );
+        store.store(1, &calibration).unwrap();
+
+        let instance = test_instance();
+        instance.set_calibration_store(Some(store));
+
+        // Port 0 has nothing stored and keeps its configuration
+        let config = PortConfig {
+            ingress_latency: Duration::from_nanos(50),
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let port_0 = instance.add_port(config, rng);
+        assert_eq!(port_0.calibration(), Calibration::from_config(&config));
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let port_1 = instance.add_port_in_state(
+            config,
+            rng,
+            TestPortState::Slave {
+                clock_identity: ClockIdentity([1; 8]),
+                port_number: 1,
+            },
+        );
+        assert_eq!(port_1.calibration(), calibration);
+
+        let (mut port_1, _) = port_1.end_bmca();
+        let recalibrated = Calibration {
+            delay_asymmetry: Duration::from_nanos(75),
+            ..calibration
+        };
+        port_1.set_calibration(recalibrated).unwrap();
+        assert_eq!(port_1.calibration(), recalibrated);
+        assert_eq!(store.load(1), Some(recalibrated));
+        assert_eq!(store.load(0), None);
+    }
+
+    #[derive(Debug)]
+    struct TestExtension {
+        consumed: core::sync::atomic::AtomicU32,
+    }
+
+    impl OrganizationExtension for TestExtension {
+        fn produce(&self, message_type: MessageType, tlvs: &mut OrganizationTlvWriter<'_>) {
+            assert_eq!(message_type, MessageType::Announce);
+            let tlv = OrganizationTlv {
+                tlv_type: TlvType::OrganizationExtension,
+                organization_id: [0x00, 0x1b, 0x19],
+                organization_sub_type: [0, 0, 1],
+                data: &[1, 2],
+            };
+            assert_eq!(
+                tlvs.push(OrganizationTlv { data: &[1], ..tlv }),
+                Err(OrganizationTlvError::OddLength)
+            );
+            assert_eq!(
+                tlvs.push(OrganizationTlv {
+                    tlv_type: TlvType::Pad,
+                    ..tlv
+                }),
+                Err(OrganizationTlvError::InvalidType(TlvType::Pad))
+            );
+            tlvs.push(tlv).unwrap();
+        }
+
+        fn consume(
+            &self,
+            message_type: MessageType,
+            source: (ClockIdentity, u16),
+            tlv: OrganizationTlv<'_>,
+        ) {
+            assert_eq!(message_type, MessageType::Announce);
+            assert_eq!(source.0, ClockIdentity([1; 8]));
+            assert_eq!(tlv.organization_id, [0x00, 0x1b, 0x19]);
+            assert_eq!(tlv.data, [1, 2]);
+            self.consumed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    static TEST_EXTENSION: TestExtension = TestExtension {
+        consumed: core::sync::atomic::AtomicU32::new(0),
+    };
+
+    #[test]
+    fn test_organization_extension() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut master_port = instance.add_port_in_state(test_config(), rng, TestPortState::Master);
+        instance.bmca(&mut [&mut master_port]);
+        let (mut master_port, _) = master_port.end_bmca();
+        master_port.set_organization_extension(Some(&TEST_EXTENSION));
+
+        let data = master_port
+            .handle_announce_timer()
+            .find_map(|action| match action {
+                PortAction::SendGeneral { data } => Some(data.to_vec()),
+                _ => None,
+            })
+            .unwrap();
+        assert!(Message::deserialize(&data).is_ok());
+        assert_eq!(
+            message_tlvs(&data).last(),
+            Some((
+                TlvType::OrganizationExtension,
+                &[0x00, 0x1b, 0x19, 0, 0, 1, 1, 2][..]
+            ))
+        );
+        drop(master_port);
+
+        // Only ports with the extension pass on received TLVs
+        let announce = &better_master_announces()[0];
+        let mut buffer = [0; MAX_DATA_LEN];
+        buffer[..announce.len()].copy_from_slice(announce);
+        let len = append_organization_tlvs(
+            Some(&TEST_EXTENSION),
+            None,
+            MessageType::Announce,
+            &mut buffer,
+            announce.len(),
+        );
+        assert_eq!(len, announce.len() + 12);
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        drop(port.handle_general_receive(&buffer[..len]));
+        assert_eq!(TEST_EXTENSION.consumed.load(Ordering::Relaxed), 0);
+
+        port.set_organization_extension(Some(&TEST_EXTENSION));
+        drop(port.handle_general_receive(&buffer[..len]));
+        assert_eq!(TEST_EXTENSION.consumed.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_profile_identifier() {
+        let remote = PtpInstance::new(
+            InstanceConfig {
+                clock_identity: ClockIdentity([1; 8]),
+                priority_1: 10,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            },
+            TimePropertiesDS::default(),
+            TestClock,
+            BasicFilter::new(0.25),
+        );
+        remote.set_profile_identifier(Some(Profile::Smpte2059.identifier()));
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut remote_port = remote.add_port_in_state(test_config(), rng, TestPortState::Master);
+        remote.bmca(&mut [&mut remote_port]);
+        let (mut remote_port, _) = remote_port.end_bmca();
+        let announce = remote_port
+            .handle_announce_timer()
+            .find_map(|action| match action {
+                PortAction::SendGeneral { data } => Some(data.to_vec()),
+                _ => None,
+            })
+            .unwrap();
+        let decoded = crate::decode_message(&announce).unwrap();
+        assert_eq!(
+            decoded.announce.unwrap().profile_identifier,
+            Some(ProfileIdentifier::SMPTE_2059_2)
+        );
+
+        let instance = test_instance();
+        instance.set_profile_identifier(Some(Profile::Default.identifier()));
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let listening = instance.add_port(test_config(), rng);
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let slave = instance.add_port_in_state(
+            test_config(),
+            rng,
+            TestPortState::Slave {
+                clock_identity: ClockIdentity([1; 8]),
+                port_number: 0,
+            },
+        );
+        let (mut listening, _) = listening.end_bmca();
+        let (mut slave, _) = slave.end_bmca();
+
+        // Only the master of a slave port is checked
+        drop(listening.handle_general_receive(&announce));
+        assert_eq!(listening.statistics().profile_mismatches, 0);
+
+        drop(slave.handle_general_receive(&announce));
+        drop(slave.handle_general_receive(&announce));
+        assert_eq!(slave.statistics().profile_mismatches, 2);
+        assert_eq!(
+            slave.take_event(),
+            Some(PortEvent::ParentProfileMismatch {
+                local: ProfileIdentifier::DEFAULT_DELAY_REQUEST_RESPONSE,
+                advertised: ProfileIdentifier::SMPTE_2059_2,
+            })
+        );
+        assert_eq!(slave.take_event(), None);
+    }
+
+    // An ICV that is a sum of the key and the data
+    #[derive(Debug)]
+    struct TestAlgorithm {
+        icv_length: usize,
+    }
+
+    impl IntegrityAlgorithm for TestAlgorithm {
+        fn icv_length(&self) -> usize {
+            self.icv_length
+        }
+
+        fn compute_icv(&self, key: &[u8], data: &[u8], icv: &mut [u8]) {
+            let sum = key
+                .iter()
+                .chain(data)
+                .fold(0u32, |sum, byte| sum.wrapping_mul(31) + *byte as u32);
+            icv.copy_from_slice(&sum.to_be_bytes());
+        }
+    }
+
+    // Rolling over from key 1 to key 2 at 10 seconds, the time of the test
+    // clock, with key 3 to follow
+    static TEST_KEYS: KeyTable = KeyTable::new(&[
+        SecurityKey {
+            key_id: 1,
+            material: b"old",
+            valid_from: None,
+            valid_until: Some(Time::from_secs(20)),
+        },
+        SecurityKey {
+            key_id: 2,
+            material: b"new",
+            valid_from: Some(Time::from_secs(5)),
+            valid_until: None,
+        },
+        SecurityKey {
+            key_id: 3,
+            material: b"next",
+            valid_from: Some(Time::from_secs(30)),
+            valid_until: None,
+        },
+    ]);
+
+    // The test keys, but sending with a fixed one
+    #[derive(Debug)]
+    struct SendWithKey(u32);
+
+    impl SecurityProvider for SendWithKey {
+        fn key(&self, key_id: u32) -> Option<SecurityKey<'_>> {
+            TEST_KEYS.key(key_id)
+        }
+
+        fn send_key(&self, _now: Time) -> Option<SecurityKey<'_>> {
+            TEST_KEYS.key(self.0)
+        }
+    }
+
+    fn seal_with_key(
+        config: AuthenticationConfig,
+        key_id: u32,
+        message: &[u8],
+    ) -> std::vec::Vec<u8> {
+        let mut authenticator = Authenticator::new();
+        authenticator.set_config(
+            Some(AuthenticationConfig {
+                keys: std::boxed::Box::leak(std::boxed::Box::new(SendWithKey(key_id))),
+                ..config
+            }),
+            &mut [],
+        );
+        let sealed = authenticator
+            .seal(
+                actions![PortAction::SendGeneral { data: message }],
+                &AtomicRefCell::new(TestClock),
+                PortIdentity::default(),
+                &mut AuthenticationFailures::default(),
+            )
+            .find_map(|action| match action {
+                PortAction::SendGeneral { data } => Some(data.to_vec()),
+                _ => None,
+            });
+        sealed.unwrap()
+    }
+
+    #[test]
+    fn test_authentication() {
+        let config = AuthenticationConfig {
+            algorithm: &TestAlgorithm { icv_length: 4 },
+            keys: &TEST_KEYS,
+            spp: 7,
+            on_failure: AuthenticationFailureAction::Drop,
+            // The same announce is sent with different keys
+            replay_protection: false,
+        };
+
+        let instance = test_instance();
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut master_port = instance.add_port_in_state(test_config(), rng, TestPortState::Master);
+        instance.bmca(&mut [&mut master_port]);
+        let (mut master_port, _) = master_port.end_bmca();
+        assert_eq!(
+            master_port.set_authentication(
+                Some(AuthenticationConfig {
+                    algorithm: &TestAlgorithm { icv_length: 3 },
+                    ..config
+                }),
+                &mut []
+            ),
+            Err(AuthenticationConfigError::OddIcvLength(3))
+        );
+        assert_eq!(
+            master_port.set_authentication(
+                Some(AuthenticationConfig {
+                    replay_protection: true,
+                    ..config
+                }),
+                &mut []
+            ),
+            Err(AuthenticationConfigError::NoReplayWindows)
+        );
+        master_port
+            .set_authentication(Some(config), &mut [])
+            .unwrap();
+
+        // Sent with the newest valid key
+        let data = master_port
+            .handle_announce_timer()
+            .find_map(|action| match action {
+                PortAction::SendGeneral { data } => Some(data.to_vec()),
+                _ => None,
+            })
+            .unwrap();
+        assert!(Message::deserialize(&data).is_ok());
+        let (tlv_type, value) = message_tlvs(&data).last().unwrap();
+        assert_eq!(tlv_type, TlvType::Authentication);
+        assert_eq!(value[..6], [7, 0, 0, 0, 0, 2]);
+        assert_eq!(value.len(), 10);
+        drop(master_port);
+
+        let announce = &better_master_announces()[0];
+        let sealed = seal_with_key(config, 1, announce);
+        assert_eq!(sealed.len(), announce.len() + 14);
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        port.set_authentication(Some(config), &mut []).unwrap();
+
+        // Ports that didn't roll over yet are still understood
+        assert!(port.handle_general_receive(&sealed).next().is_some());
+        assert!(port
+            .handle_general_receive(&seal_with_key(config, 2, announce))
+            .next()
+            .is_some());
+        assert_eq!(
+            port.statistics().authentication,
+            AuthenticationFailures::default()
+        );
+
+        assert!(port.handle_general_receive(announce).next().is_none());
+        let mut tampered = sealed.clone();
+        tampered[50] ^= 1;
+        assert!(port.handle_general_receive(&tampered).next().is_none());
+        // Not valid yet
+        assert!(port
+            .handle_general_receive(&seal_with_key(config, 3, announce))
+            .next()
+            .is_none());
+        port.set_authentication(Some(AuthenticationConfig { spp: 8, ..config }), &mut [])
+            .unwrap();
+        assert!(port.handle_general_receive(&sealed).next().is_none());
+        assert_eq!(
+            port.statistics().authentication,
+            AuthenticationFailures {
+                missing: 1,
+                unknown: 2,
+                invalid: 1,
+                ..Default::default()
+            }
+        );
+
+        // Still counted when accepted
+        port.set_authentication(
+            Some(AuthenticationConfig {
+                on_failure: AuthenticationFailureAction::Accept,
+                ..config
+            }),
+            &mut [],
+        )
+        .unwrap();
+        assert!(port.handle_general_receive(announce).next().is_some());
+        assert_eq!(port.statistics().authentication.missing, 2);
+    }
+
+    #[test]
+    fn test_authentication_replay() {
+        let config = AuthenticationConfig {
+            algorithm: &TestAlgorithm { icv_length: 4 },
+            keys: &TEST_KEYS,
+            spp: 7,
+            on_failure: AuthenticationFailureAction::Drop,
+            replay_protection: true,
+        };
+        let announce = |sequence_id: u16| {
+            let mut announce = better_master_announces().swap_remove(0);
+            announce[30..32].copy_from_slice(&sequence_id.to_be_bytes());
+            seal_with_key(config, 2, &announce)
+        };
+
+        let instance = test_instance();
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        port.set_authentication(Some(config), replay_table(1))
+            .unwrap();
+
+        let mut accepted = |sequence_id| {
+            port.handle_general_receive(&announce(sequence_id))
+                .next()
+                .is_some()
+        };
+        assert!(accepted(100));
+        assert!(!accepted(100));
+        assert!(accepted(101));
+        // Late, but not seen before
+        assert!(accepted(99));
+        assert!(!accepted(99));
+        assert!(!accepted(101 - REPLAY_WINDOW));
+        assert!(accepted(30000));
+        assert!(accepted(60000));
+        assert!(accepted(u16::MAX));
+        // Wrapping around
+        assert!(accepted(3));
+        assert!(!accepted(u16::MAX));
+
+        assert_eq!(
+            port.statistics().authentication,
+            AuthenticationFailures {
+                replayed: 3,
+                outside_window: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    fn replay_table(slots: usize) -> &'static mut [ReplayWindowSlot] {
+        std::vec::Vec::from_iter((0..slots).map(|_| ReplayWindowSlot::EMPTY)).leak()
+    }
+
+    // A clock the test steps by hand, with a monotonic time that the steps
+    // don't change
+    struct SteppedClock {
+        now: std::rc::Rc<core::cell::Cell<Time>>,
+        monotonic: std::rc::Rc<core::cell::Cell<Time>>,
+    }
+
+    impl Clock for SteppedClock {
+        type Error = std::convert::Infallible;
+
+        fn now(&self) -> Time {
+            self.now.get()
+        }
+
+        fn adjust(
+            &mut self,
+            _time_offset: Duration,
+            _frequency_multiplier: f64,
+            _time_properties_ds: &TimePropertiesDS,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn monotonic_now(&self) -> Option<Time> {
+            Some(self.monotonic.get())
+        }
+    }
+
+    #[test]
+    fn test_authentication_replay_table() {
+        let config = AuthenticationConfig {
+            algorithm: &TestAlgorithm { icv_length: 4 },
+            keys: &TEST_KEYS,
+            spp: 7,
+            on_failure: AuthenticationFailureAction::Drop,
+            replay_protection: true,
+        };
+        let announce = |clock_identity: u8, sequence_id: u16| {
+            let mut announce = better_master_announces().swap_remove(0);
+            announce[20..28].copy_from_slice(&[clock_identity; 8]);
+            announce[30..32].copy_from_slice(&sequence_id.to_be_bytes());
+            seal_with_key(config, 2, &announce)
+        };
+
+        let now = std::rc::Rc::new(core::cell::Cell::new(Time::from_secs(10)));
+        let monotonic = std::rc::Rc::new(core::cell::Cell::new(Time::from_secs(1000)));
+        let instance = PtpInstance::new(
+            InstanceConfig {
+                clock_identity: ClockIdentity::default(),
+                priority_1: 128,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            },
+            TimePropertiesDS::default(),
+            SteppedClock {
+                now: now.clone(),
+                monotonic: monotonic.clone(),
+            },
+            BasicFilter::new(0.25),
+        );
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        port.set_authentication(Some(config), replay_table(2))
+            .unwrap();
+
+        let mut accepted = |clock_identity, sequence_id| {
+            port.handle_general_receive(&announce(clock_identity, sequence_id))
+                .next()
+                .is_some()
+        };
+        assert!(accepted(1, 100));
+        // A step of the local clock doesn't expire the window
+        now.set(Time::from_secs(3600));
+        monotonic.set(Time::from_secs(1001));
+        assert!(!accepted(1, 100));
+        // But being quiet does
+        monotonic.set(Time::from_secs(1001 + REPLAY_TIMEOUT_SECONDS as u64 + 1));
+        assert!(accepted(1, 100));
+
+        // The third source takes the place of the one quiet the longest
+        monotonic.set(monotonic.get() + Duration::from_secs(1));
+        assert!(accepted(2, 200));
+        monotonic.set(monotonic.get() + Duration::from_secs(1));
+        assert!(accepted(3, 300));
+        assert!(!accepted(2, 200));
+        assert!(accepted(1, 100));
+
+        assert_eq!(
+            port.statistics().authentication,
+            AuthenticationFailures {
+                replayed: 2,
+                replay_evictions: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_authentication_after_many_tlvs() {
+        let config = AuthenticationConfig {
+            algorithm: &TestAlgorithm { icv_length: 4 },
+            keys: &TEST_KEYS,
+            spp: 7,
+            on_failure: AuthenticationFailureAction::Drop,
+            replay_protection: false,
+        };
+
+        // More TLVs than are parsed come before the AUTHENTICATION TLV
+        let mut announce = better_master_announces().swap_remove(0);
+        for _ in 0..MAX_MESSAGE_TLVS + 4 {
+            announce.extend_from_slice(&TlvType::Pad.to_primitive().to_be_bytes());
+            announce.extend_from_slice(&[0, 0]);
+        }
+        let length = announce.len() as u16;
+        announce[2..4].copy_from_slice(&length.to_be_bytes());
+        let sealed = seal_with_key(config, 2, &announce);
+
+        let instance = test_instance();
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        port.set_authentication(Some(config), &mut []).unwrap();
+        assert!(port.handle_general_receive(&sealed).next().is_some());
+        assert_eq!(
+            port.statistics().authentication,
+            AuthenticationFailures::default()
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "more than one message to seal")]
+    fn test_seal_single_send() {
+        let mut authenticator = Authenticator::new();
+        authenticator.set_config(
+            Some(AuthenticationConfig {
+                algorithm: &TestAlgorithm { icv_length: 4 },
+                keys: &TEST_KEYS,
+                spp: 7,
+                on_failure: AuthenticationFailureAction::Drop,
+                replay_protection: false,
+            }),
+            &mut [],
+        );
+        let message = &better_master_announces()[0];
+        let sealed = authenticator.seal(
+            actions![
+                PortAction::SendGeneral { data: message },
+                PortAction::SendGeneral { data: message }
+            ],
+            &AtomicRefCell::new(TestClock),
+            PortIdentity::default(),
+            &mut AuthenticationFailures::default(),
+        );
+        drop(sealed);
+    }
+
+    #[test]
+    fn test_unicast_flag_mismatch() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity([1; 8]),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut message = Message::sync(
+            &default_ds,
+            PortIdentity::default(),
+            1,
+            Time::from_secs(1),
+            Interval::ONE_SECOND,
+            false,
+            &LogMessageIntervals::STANDARD,
+        );
+        let mut buffer = [0; MAX_DATA_LEN];
+        let multicast = PacketArrival {
+            destination: Some(Destination::Multicast),
+            ..Default::default()
+        };
+        let unicast = PacketArrival {
+            destination: Some(Destination::Unicast),
+            ..Default::default()
+        };
+
+        let len = message.serialize(&mut buffer).unwrap();
+        assert!(port
+            .handle_timecritical_receive_via(
+                &buffer[..len],
+                Time::from_secs(2),
+                TimestampSource::Legacy,
+                multicast
+            )
+            .next()
+            .is_none());
+        assert_eq!(port.statistics().unexpected_unicast_messages, 0);
+
+        if let Message::Sync(sync) = &mut message {
+            sync.header.unicast_flag = true;
+        }
+        let len = message.serialize(&mut buffer).unwrap();
+        assert!(port
+            .handle_timecritical_receive_via(
+                &buffer[..len],
+                Time::from_secs(2),
+                TimestampSource::Legacy,
+                multicast
+            )
+            .next()
+            .is_none());
+        assert_eq!(port.statistics().unexpected_unicast_messages, 1);
+        assert_eq!(port.statistics().unexpected_multicast_messages, 0);
+
+        // The same message sent to the port itself is fine, whether or not
+        // the port negotiates unicast transmission
+        assert!(port
+            .handle_timecritical_receive_via(
+                &buffer[..len],
+                Time::from_secs(2),
+                TimestampSource::Legacy,
+                unicast
+            )
+            .next()
+            .is_none());
+        assert_eq!(port.statistics().unexpected_unicast_messages, 1);
+
+        // Runtimes that can't tell where packets were sent to skip the check
+        assert!(port
+            .handle_timecritical_receive(&buffer[..len], Time::from_secs(2))
+            .next()
+            .is_none());
+        assert_eq!(port.statistics().unexpected_unicast_messages, 1);
+
+        if let Message::Sync(sync) = &mut message {
+            sync.header.unicast_flag = false;
+        }
+        let len = message.serialize(&mut buffer).unwrap();
+        let input = PortInput::GeneralReceive {
+            data: &buffer[..len],
+            arrival: unicast,
+        };
+        assert!(port.handle(input).next().is_none());
+        assert_eq!(port.statistics().unexpected_multicast_messages, 1);
+        assert_eq!(port.statistics().unexpected_unicast_messages, 1);
+    }
+
+    #[test]
+    fn test_match_packet() {
+        let instance = test_instance();
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (port, _) = instance.add_port(test_config(), rng).end_bmca();
+        let own = port.port_identity;
+
+        let instance_config = InstanceConfig {
+            clock_identity: ClockIdentity([1; 8]),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        };
+        let remote = PortIdentity {
+            clock_identity: instance_config.clock_identity,
+            port_number: 1,
+        };
+        let mut buffer = [0; MAX_DATA_LEN];
+        let mut match_message = |message: Message| {
+            let len = message.serialize(&mut buffer).unwrap();
+            port.match_packet(&crate::route_packet(&buffer[..len]).unwrap())
+        };
+
+        let default_ds = DefaultDS::new(instance_config);
+        assert_eq!(
+            match_message(Message::sync(
+                &default_ds,
+                remote,
+                1,
+                Time::from_secs(1),
+                Interval::ONE_SECOND,
+                false,
+                &LogMessageIntervals::STANDARD
+            )),
+            PacketMatch::Handle { event: true }
+        );
+        assert_eq!(
+            match_message(Message::follow_up(
+                &default_ds,
+                remote,
+                1,
+                Time::from_secs(1),
+                Interval::ONE_SECOND,
+                false,
+                &LogMessageIntervals::STANDARD
+            )),
+            PacketMatch::Handle { event: false }
+        );
+
+        let other_domain = DefaultDS::new(InstanceConfig {
+            domain_number: 1,
+            ..instance_config
+        });
+        assert_eq!(
+            match_message(Message::sync(
+                &other_domain,
+                remote,
+                1,
+                Time::from_secs(1),
+                Interval::ONE_SECOND,
+                false,
+                &LogMessageIntervals::STANDARD
+            )),
+            PacketMatch::OtherInstance
+        );
+
+        // Our own delay request, looped back
+        let own_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: own.clock_identity,
+            ..instance_config
+        });
+        let Message::DelayReq(request) =
+            Message::delay_req(&own_ds, own, 3, false, &LogMessageIntervals::STANDARD)
+        else {
+            unreachable!()
+        };
+        assert_eq!(match_message(Message::DelayReq(request)), PacketMatch::Own);
+
+        // The response is for us, a response to another port isn't
+        let response = Message::delay_resp(
+            &request,
+            remote,
+            Interval::ONE_SECOND,
+            Time::from_secs(1),
+            &LogMessageIntervals::STANDARD,
+        );
+        assert_eq!(
+            match_message(response),
+            PacketMatch::Handle { event: false }
+        );
+        let Message::DelayReq(other_request) = Message::delay_req(
+            &default_ds,
+            remote,
+            3,
+            false,
+            &LogMessageIntervals::STANDARD,
+        ) else {
+            unreachable!()
+        };
+        let master = PortIdentity {
+            clock_identity: ClockIdentity([3; 8]),
+            port_number: 1,
+        };
+        let response = Message::delay_resp(
+            &other_request,
+            master,
+            Interval::ONE_SECOND,
+            Time::from_secs(1),
+            &LogMessageIntervals::STANDARD,
+        );
+        assert_eq!(match_message(response), PacketMatch::OtherPort);
+    }
+
+    #[test]
+    fn test_forced_master_state() {
+        let instance = test_instance();
+        instance.set_grandmaster(ClockIdentity([1; 8]), Default::default(), 15, 16);
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let port = instance.add_port_in_state(test_config(), rng, TestPortState::Master);
+        let (mut port, _) = port.end_bmca();
+
+        let mut actions = port.handle_announce_timer();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceTimer { .. })
+        ));
+        let Some(PortAction::SendGeneral { data }) = actions.next() else {
+            panic!("Unexpected action");
+        };
+
+        let Message::Announce(announce) = Message::deserialize(data).unwrap() else {
+            panic!("Unexpected message type");
+        };
+        assert_eq!(announce.grandmaster_identity, ClockIdentity([1; 8]));
+        assert_eq!(announce.grandmaster_priority_1, 15);
+        assert_eq!(announce.grandmaster_priority_2, 16);
+    }
+
+    #[test]
+    fn test_handle_input() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+
+        let mut actions = port.handle(PortInput::AnnounceReceiptTimer);
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceTimer { .. })
+        ));
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetSyncTimer { .. })
+        ));
+        assert!(actions.next().is_none());
+        drop(actions);
+
+        let mut actions = port.handle(PortInput::SyncTimer);
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetSyncTimer { .. })
+        ));
+        let Some(PortAction::SendTimeCritical { context, .. }) = actions.next() else {
+            panic!("Unexpected action");
+        };
+        drop(actions);
+
+        // The send timestamp of the sync leads to its follow up
+        let mut actions = port.handle(PortInput::SendTimestamp {
+            context,
+            timestamp: Time::from_secs(10),
+            source: TimestampSource::Hardware,
+        });
+        let Some(PortAction::SendGeneral { data }) = actions.next() else {
+            panic!("Unexpected action");
+        };
+        assert!(matches!(
+            Message::deserialize(data),
+            Ok(Message::FollowUp(_))
+        ));
+        drop(actions);
+        assert_eq!(port.statistics().timestamp_sources.hardware, 1);
+
+        assert!(port
+            .handle(PortInput::GeneralReceive {
+                data: &[],
+                arrival: PacketArrival::default(),
+            })
+            .next()
+            .is_none());
+
+        port.set_statistics_window(Some(10));
+        let mut actions = port.handle(PortInput::StatisticsTimer);
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetStatisticsTimer { .. })
+        ));
+        assert!(actions.next().is_none());
+    }
+
+    #[test]
+    fn test_send_failure() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        drop(port.handle_announce_receipt_timer());
+
+        let announce = port
+            .handle_announce_timer()
+            .find_map(|action| match action {
+                PortAction::SendGeneral { data } => Some(data.to_vec()),
+                _ => None,
+            })
+            .unwrap();
+
+        // A failed announce is retried before the next one is due
+        let mut actions = port.handle_send_failure(
+            FailedSend::General { data: &announce },
+            SendError::Transient,
+        );
+        let Some(PortAction::ResetAnnounceTimer { duration }) = actions.next() else {
+            panic!("Unexpected action");
+        };
+        assert!(duration < test_config().announce_interval.as_core_duration());
+        assert!(actions.next().is_none());
+        drop(actions);
+        assert_eq!(port.state().kind(), PortStateKind::Master);
+
+        // A port that can't send at all becomes faulty
+        let mut actions =
+            port.handle_send_failure(FailedSend::General { data: &announce }, SendError::Fatal);
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceReceiptTimer { .. })
+        ));
+        assert!(actions.next().is_none());
+        drop(actions);
+        assert_eq!(port.state().kind(), PortStateKind::Faulty);
+        assert!(port.handle_announce_timer().next().is_none());
+        assert!(port.handle_sync_timer().next().is_none());
+
+        // and starts over when the announce receipt timer expires
+        let mut actions = port.handle_announce_receipt_timer();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceReceiptTimer { .. })
+        ));
+        drop(actions);
+        assert_eq!(port.state().kind(), PortStateKind::Listening);
+
+        assert_eq!(port.statistics().send_failures, 2);
+    }
+
+    #[test]
+    fn test_identity_collision() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        drop(port.handle_announce_receipt_timer());
+        while port.take_event().is_some() {}
+
+        let mut own_announce = port
+            .handle_announce_timer()
+            .find_map(|action| match action {
+                PortAction::SendGeneral { data } => Some(data.to_vec()),
+                _ => None,
+            })
+            .unwrap();
+
+        // Our own announce message looping back is fine
+        drop(port.handle_general_receive(&own_announce));
+        assert_eq!(port.statistics().identity_collisions, 0);
+        assert_eq!(port.take_event(), None);
+
+        // but another one with our port identity, or with our clock identity
+        // and a port number we don't have, is not
+        own_announce[31] = own_announce[31].wrapping_add(5);
+        let mut foreign_sync = [0; MAX_DATA_LEN];
+        let length = Message::sync(
+            &DefaultDS::new(InstanceConfig {
+                clock_identity: ClockIdentity::default(),
+                priority_1: 128,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            }),
+            PortIdentity {
+                clock_identity: ClockIdentity::default(),
+                port_number: 7,
+            },
+            0,
+            Time::from_secs(1),
+            Interval::ONE_SECOND,
+            false,
+            &LogMessageIntervals::STANDARD,
+        )
+        .serialize(&mut foreign_sync)
+        .unwrap();
+
+        assert!(port.handle_general_receive(&own_announce).next().is_none());
+        assert!(port
+            .handle_timecritical_receive(&foreign_sync[..length], Time::from_secs(1))
+            .next()
+            .is_none());
+        assert_eq!(port.statistics().identity_collisions, 2);
+        assert_eq!(
+            port.take_event(),
+            Some(PortEvent::ClockIdentityCollision {
+                port_number: 0,
+                message_type: MessageType::Announce,
+            })
+        );
+        assert_eq!(
+            port.take_event(),
+            Some(PortEvent::ClockIdentityCollision {
+                port_number: 7,
+                message_type: MessageType::Sync,
+            })
+        );
+        assert_eq!(port.state().kind(), PortStateKind::Master);
+
+        // A defensive port steps out of the domain
+        port.set_identity_collision_response(IdentityCollisionResponse::Faulty);
+        let mut actions = port.handle_general_receive(&own_announce);
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceReceiptTimer { .. })
+        ));
+        drop(actions);
+        assert_eq!(port.state().kind(), PortStateKind::Faulty);
+        assert_eq!(port.statistics().identity_collisions, 3);
+    }
+
+    #[test]
+    fn test_identity_collision_by_source() {
+        let instance = test_instance();
+
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+        drop(port.handle_announce_receipt_timer());
+        while port.take_event().is_some() {}
+
+        // A sync with our port identity, which the port takes for its own
+        // when it doesn't know where it came from
+        let mut sync = [0; MAX_DATA_LEN];
+        let length = Message::sync(
+            &DefaultDS::new(InstanceConfig {
+                clock_identity: ClockIdentity::default(),
+                priority_1: 128,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            }),
+            port.port_identity,
+            3,
+            Time::from_secs(1),
+            Interval::ONE_SECOND,
+            false,
+            &LogMessageIntervals::STANDARD,
+        )
+        .serialize(&mut sync)
+        .unwrap();
+        let sync = &sync[..length];
+        let route = crate::route_packet(sync).unwrap();
+        let arrival = |source| PacketArrival {
+            source: Some(source),
+            ..Default::default()
+        };
+
+        drop(port.handle_timecritical_receive(sync, Time::from_secs(1)));
+        assert_eq!(port.statistics().identity_collisions, 0);
+
+        // or when it comes back from our own address
+        assert_eq!(
+            port.match_packet_via(&route, arrival(SourceAddress::Own)),
+            PacketMatch::Own
+        );
+        drop(port.handle_timecritical_receive_via(
+            sync,
+            Time::from_secs(1),
+            TimestampSource::Hardware,
+            arrival(SourceAddress::Own),
+        ));
+        assert_eq!(port.statistics().identity_collisions, 0);
+
+        // But from another address, another clock uses our identity
+        assert_eq!(
+            port.match_packet_via(&route, arrival(SourceAddress::Other)),
+            PacketMatch::Handle { event: true }
+        );
+        assert!(port
+            .handle_timecritical_receive_via(
+                sync,
+                Time::from_secs(1),
+                TimestampSource::Hardware,
+                arrival(SourceAddress::Other),
+            )
+            .next()
+            .is_none());
+        assert_eq!(port.statistics().identity_collisions, 1);
+        assert_eq!(
+            port.take_event(),
+            Some(PortEvent::ClockIdentityCollision {
+                port_number: port.port_identity.port_number,
+                message_type: MessageType::Sync,
+            })
+        );
+    }
+
+    #[test]
+    fn test_management() {
+        let instance = test_instance();
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+
+        let node = PortIdentity {
+            clock_identity: ClockIdentity([0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x01]),
+            port_number: 3,
+        };
+        // A management message of a management node, like `pmc` sends
+        let request = |target: PortIdentity, action: ManagementAction, id: u16| {
+            let mut buffer = [0; 54];
+            buffer[..4].copy_from_slice(&[0x0d, 0x02, 0x00, 0x36]);
+            node.serialize(&mut buffer[20..30]).unwrap();
+            buffer[30..34].copy_from_slice(&[0x00, 0x05, 0x04, 0x7f]);
+            target.serialize(&mut buffer[34..44]).unwrap();
+            buffer[44..47].copy_from_slice(&[2, 1, action.to_primitive()]);
+            buffer[48..52].copy_from_slice(&[0x00, 0x01, 0x00, 0x02]);
+            buffer[52..54].copy_from_slice(&id.to_be_bytes());
+            buffer
+        };
+        let mut reply = |request: [u8; 54]| {
+            let mut actions = port.handle_general_receive(&request);
+            let Some(PortAction::SendGeneral { data }) = actions.next() else {
+                panic!("Unexpected action");
+            };
+            let message = Message::deserialize(data).unwrap();
+            let Message::Management(reply) = &message else {
+                panic!("Expected a management message");
+            };
+            assert!(actions.next().is_none());
+            (*message.header(), reply.clone())
+        };
+
+        let (header, ping) = reply(request(PortIdentity::ALL, ManagementAction::GET, 0x0000));
+        assert_eq!(ping.action(), ManagementAction::RESPONSE);
+        assert_eq!(ping.target_port_identity(), node);
+        assert_eq!(header.sequence_id, 5);
+        assert_eq!(
+            header.source_port_identity.clock_identity,
+            ClockIdentity::default()
+        );
+        assert_eq!(
+            *ping.tlv(),
+            ManagementTlv::Management {
+                id: ManagementId::NullPtpManagement,
+                data: ArrayVec::new(),
+            }
+        );
+
+        let (_, command) = reply(request(
+            PortIdentity::ALL,
+            ManagementAction::COMMAND,
+            0x0000,
+        ));
+        assert_eq!(command.action(), ManagementAction::ACKNOWLEDGE);
+
+        let (_, dataset) = reply(request(PortIdentity::ALL, ManagementAction::GET, 0x200f));
+        assert_eq!(
+            *dataset.tlv(),
+            ManagementTlv::ErrorStatus {
+                error: ManagementErrorId::NotSupported,
+                id: ManagementId::Time,
+            }
+        );
+        let (_, unknown) = reply(request(PortIdentity::ALL, ManagementAction::GET, 0xc000));
+        assert_eq!(
+            *unknown.tlv(),
+            ManagementTlv::ErrorStatus {
+                error: ManagementErrorId::NoSuchId,
+                id: ManagementId::Other(0xc000),
+            }
+        );
+
+        // The datasets of the instance and the port
+        let data_set = |tlv: &ManagementTlv| {
+            let ManagementTlv::Management { data, .. } = tlv else {
+                panic!("Expected a management TLV, got {tlv:?}");
+            };
+            data.clone()
+        };
+        let (_, default_ds) = reply(request(PortIdentity::ALL, ManagementAction::GET, 0x2000));
+        assert_eq!(
+            data_set(default_ds.tlv()).as_slice(),
+            [0x01, 0, 0, 1, 128, 248, 0xfe, 0, 0, 128, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        let (_, current_ds) = reply(request(PortIdentity::ALL, ManagementAction::GET, 0x2001));
+        assert_eq!(data_set(current_ds.tlv()).as_slice(), [0; 18]);
+        let (_, parent_ds) = reply(request(PortIdentity::ALL, ManagementAction::GET, 0x2002));
+        assert_eq!(
+            data_set(parent_ds.tlv()).as_slice(),
+            [
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff, 128, 248,
+                0xfe, 0, 0, 128, 0, 0, 0, 0, 0, 0, 0, 0
+            ]
+        );
+        let (_, time_properties_ds) =
+            reply(request(PortIdentity::ALL, ManagementAction::GET, 0x2003));
+        assert_eq!(
+            data_set(time_properties_ds.tlv()).as_slice(),
+            [0, 0, 0, 0xa0]
+        );
+        let (_, port_ds) = reply(request(PortIdentity::ALL, ManagementAction::GET, 0x2004));
+        assert_eq!(
+            data_set(port_ds.tlv()).as_slice(),
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 1, 0x7f, 0x12]
+        );
+
+        // They can't be changed
+        let (_, set) = reply(request(PortIdentity::ALL, ManagementAction::SET, 0x2000));
+        assert_eq!(
+            *set.tlv(),
+            ManagementTlv::ErrorStatus {
+                error: ManagementErrorId::NotSetable,
+                id: ManagementId::DefaultDataSet,
+            }
+        );
+
+        // Only messages for this port that need a reply are answered
+        let this_port = PortIdentity {
+            clock_identity: ClockIdentity::default(),
+            port_number: 0xffff,
+        };
+        reply(request(this_port, ManagementAction::GET, 0x0000));
+        let other_clock = PortIdentity {
+            clock_identity: ClockIdentity([3; 8]),
+            port_number: 0xffff,
+        };
+        let other_port = PortIdentity {
+            clock_identity: ClockIdentity::default(),
+            port_number: 1,
+        };
+        for ignored in [
+            request(other_clock, ManagementAction::GET, 0x0000),
+            request(other_port, ManagementAction::GET, 0x0000),
+            request(PortIdentity::ALL, ManagementAction::RESPONSE, 0x0000),
+        ] {
+            assert!(port.handle_general_receive(&ignored).next().is_none());
+        }
+    }
+
+    #[test]
+    fn test_management_set_on_master() {
+        let instance = test_instance();
+        let config = PortConfig {
+            management: ManagementPolicy::Full,
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(config, rng).end_bmca();
+        drop(port.handle_announce_receipt_timer());
+        drop(port.handle_announce_timer());
+
+        // A SET of PRIORITY2, from a management node on the same network
+        let mut set_priority_2 = [0; 56];
+        set_priority_2[..4].copy_from_slice(&[0x0d, 0x02, 0x00, 0x38]);
+        set_priority_2[20..28].copy_from_slice(&[0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x01]);
+        set_priority_2[30..34].copy_from_slice(&[0x00, 0x05, 0x04, 0x7f]);
+        PortIdentity::ALL
+            .serialize(&mut set_priority_2[34..44])
+            .unwrap();
+        set_priority_2[44..47].copy_from_slice(&[1, 1, ManagementAction::SET.to_primitive()]);
+        set_priority_2[48..56].copy_from_slice(&[0x00, 0x01, 0x00, 0x04, 0x20, 0x06, 100, 0]);
+        drop(port.handle_general_receive(&set_priority_2));
+
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        let (mut port, mut actions) = port.end_bmca();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceTimer { duration }) if duration.is_zero()
+        ));
+        drop(actions);
+
+        // The new priority is announced right away, and isn't taken for a bug
+        for _ in 0..2 {
+            drop(port.handle_announce_timer());
+            let last_announce = port.statistics().last_announce.unwrap();
+            assert_eq!(last_announce.grandmaster_priority_2, 100);
+            assert_eq!(port.statistics().internal_errors, 0);
+        }
+    }
+
+    #[test]
+    fn test_management_set() {
+        let instance = test_instance();
+        let config = PortConfig {
+            management: ManagementPolicy::Full,
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(config, rng).end_bmca();
+
+        // A management message with `data`, of a management node on the same
+        // network unless it was `forwarded` by a boundary clock
+        let request = |action: ManagementAction, id: u16, data: &[u8], forwarded: bool| {
+            let length = 54 + data.len();
+            let mut buffer = ArrayVec::<u8, 128>::from([0; 128]);
+            buffer[..2].copy_from_slice(&[0x0d, 0x02]);
+            buffer[2..4].copy_from_slice(&(length as u16).to_be_bytes());
+            buffer[20..28].copy_from_slice(&[0x00, 0x1b, 0x19, 0xff, 0xfe, 0x00, 0x00, 0x01]);
+            buffer[30..34].copy_from_slice(&[0x00, 0x05, 0x04, 0x7f]);
+            PortIdentity::ALL.serialize(&mut buffer[34..44]).unwrap();
+            let boundary_hops = if forwarded { 0 } else { 1 };
+            buffer[44..47].copy_from_slice(&[1, boundary_hops, action.to_primitive()]);
+            buffer[48..50].copy_from_slice(&[0x00, 0x01]);
+            buffer[50..52].copy_from_slice(&(2 + data.len() as u16).to_be_bytes());
+            buffer[52..54].copy_from_slice(&id.to_be_bytes());
+            buffer[54..length].copy_from_slice(data);
+            buffer.truncate(length);
+            buffer
+        };
+        fn reply_tlv(mut actions: PortActionIterator) -> ManagementTlv {
+            let Some(PortAction::SendGeneral { data }) = actions.next() else {
+                panic!("Unexpected action");
+            };
+            let Message::Management(reply) = Message::deserialize(data).unwrap() else {
+                panic!("Expected a management message");
+            };
+            assert!(actions.next().is_none());
+            reply.tlv().clone()
+        }
+        let error = |error, id| ManagementTlv::ErrorStatus { error, id };
+
+        // Instance members change at the next BMCA run
+        let set_priority_1 = request(ManagementAction::SET, 0x2005, &[100, 0], false);
+        assert_eq!(
+            reply_tlv(port.handle_general_receive(&set_priority_1)),
+            ManagementTlv::value(ManagementId::Priority1, 100)
+        );
+        assert!(instance.bmca_requested());
+        assert_eq!(instance.priority_1(), 100);
+        let get_priority_1 = request(ManagementAction::GET, 0x2005, &[], false);
+        assert_eq!(
+            reply_tlv(port.handle_general_receive(&get_priority_1)),
+            ManagementTlv::value(ManagementId::Priority1, 100)
+        );
+        for data in [&[][..], &[7, 0, 0, 0], &[7; 64]] {
+            let wrong_length = request(ManagementAction::SET, 0x2006, data, false);
+            assert_eq!(
+                reply_tlv(port.handle_general_receive(&wrong_length)),
+                error(ManagementErrorId::WrongLength, ManagementId::Priority2)
+            );
+        }
+        // More data than any management id takes is not a management message
+        let too_long = request(ManagementAction::SET, 0x2006, &[7; 66], false);
+        assert!(port.handle_general_receive(&too_long).next().is_none());
+        assert_eq!(port.lifecycle.state.pending_priority_2.get(), None);
+
+        // Only replies carry an error status
+        let mut error_status = request(ManagementAction::GET, 0x0006, &[0x20, 0x00], false);
+        error_status[48..50].copy_from_slice(&[0x00, 0x02]);
+        assert_eq!(
+            reply_tlv(port.handle_general_receive(&error_status)),
+            error(
+                ManagementErrorId::GeneralError,
+                ManagementId::DefaultDataSet
+            )
+        );
+
+        // Port members right away, within reason
+        let set_sync_interval = request(ManagementAction::SET, 0x200b, &[0xfd, 0], false);
+        assert_eq!(
+            reply_tlv(port.handle_general_receive(&set_sync_interval)),
+            ManagementTlv::value(ManagementId::LogSyncInterval, 0xfd)
+        );
+        assert_eq!(port.config.sync_interval, Interval::from_log_2(-3));
+        let too_long = request(ManagementAction::SET, 0x2009, &[0x7f, 0], false);
+        assert_eq!(
+            reply_tlv(port.handle_general_receive(&too_long)),
+            error(
+                ManagementErrorId::WrongValue,
+                ManagementId::LogAnnounceInterval
+            )
+        );
+        let pdelay_interval = request(ManagementAction::SET, 0x6001, &[2, 0], false);
+        assert_eq!(
+            reply_tlv(port.handle_general_receive(&pdelay_interval)),
+            error(
+                ManagementErrorId::NotSupported,
+                ManagementId::LogMinPdelayReqInterval
+            )
+        );
+
+        // A disabled port ignores everything but management messages
+        let disable = request(ManagementAction::COMMAND, 0x200e, &[], false);
+        assert_eq!(
+            reply_tlv(port.handle_general_receive(&disable)),
+            ManagementTlv::Management {
+                id: ManagementId::DisablePort,
+                data: ArrayVec::new(),
+            }
+        );
+        assert_eq!(port.state().kind(), PortStateKind::Disabled);
+        assert!(port.handle_announce_receipt_timer().next().is_none());
+        assert_eq!(port.state().kind(), PortStateKind::Disabled);
+
+        let enable = request(ManagementAction::COMMAND, 0x200d, &[], false);
+        let mut actions = port.handle_general_receive(&enable);
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::SendGeneral { .. })
+        ));
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceReceiptTimer { .. })
+        ));
+        drop(actions);
+        assert_eq!(port.state().kind(), PortStateKind::Listening);
+
+        // Depending on the policy
+        port.config.management = ManagementPolicy::LocalOnly;
+        let forwarded = request(ManagementAction::SET, 0x2006, &[7, 0], true);
+        assert_eq!(
+            reply_tlv(port.handle_general_receive(&forwarded)),
+            error(ManagementErrorId::NotSetable, ManagementId::Priority2)
+        );
+        port.config.management = ManagementPolicy::ReadOnly;
+        assert_eq!(
+            reply_tlv(port.handle_general_receive(&disable)),
+            error(ManagementErrorId::NotSupported, ManagementId::DisablePort)
+        );
+        assert_eq!(
+            reply_tlv(port.handle_general_receive(&get_priority_1)),
+            ManagementTlv::value(ManagementId::Priority1, 100)
+        );
+        port.config.management = ManagementPolicy::Full;
+
+        // Ports that can't reply ignore management messages altogether
+        port.config.transmit = TransmitEnable::NONE;
+        assert!(port.handle_general_receive(&disable).next().is_none());
+        assert_eq!(port.state().kind(), PortStateKind::Listening);
+        port.config.transmit = TransmitEnable::ALL;
+
+        // A port in a new domain starts over
+        drop(port.handle_announce_receipt_timer());
+        assert_eq!(port.state().kind(), PortStateKind::Master);
+        let set_domain = request(ManagementAction::SET, 0x2007, &[5, 0], false);
+        assert_eq!(
+            reply_tlv(port.handle_general_receive(&set_domain)),
+            ManagementTlv::value(ManagementId::Domain, 5)
+        );
+
+        let mut port = port.start_bmca();
+        instance.bmca(&mut [&mut port]);
+        assert!(!instance.bmca_requested());
+        let (port, _) = port.end_bmca();
+        assert_eq!(port.state().kind(), PortStateKind::Listening);
+        let default_ds = &port.lifecycle.state.default_ds;
+        assert_eq!(default_ds.priority_1, 100);
+        assert_eq!(default_ds.domain_number, 5);
+    }
+
+    #[test]
+    fn test_signaling_without_unicast() {
+        let instance = test_instance();
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let port = instance.add_port(test_config(), rng);
+        let (mut port, _) = port.end_bmca();
+        drop(port.handle_announce_receipt_timer());
+        assert_eq!(port.state().kind(), PortStateKind::Master);
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity([1; 8]),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let client = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let request = UnicastNegotiation::Request {
+            message_type: MessageType::Announce,
+            log_interval: 0,
+            duration: 300,
+        };
+        let mut buffer = [0; MAX_DATA_LEN];
+        let message = Message::signaling(
+            &default_ds,
+            client,
+            1,
+            PortIdentity::ALL,
+            &[request],
+            &LogMessageIntervals::STANDARD,
+        );
+        let len = message.serialize(&mut buffer).unwrap();
+        // Multicast ports ignore messages with the unicast flag set
+        buffer[6] &= !0x04;
+
+        // Only a unicast master answers, and a master doesn't mistake the
+        // message for one of another master
+        assert_eq!(port.handle_general_receive(&buffer[..len]).count(), 0);
+        assert_eq!(port.state().kind(), PortStateKind::Master);
+    }
+
+    #[test]
+    fn test_worst_case_packet() {
+        use crate::datastructures::{common::TLVS_PEEKED, messages::MAX_MESSAGE_TLVS};
+
+        let instance = test_instance();
+        let config = PortConfig {
+            communication_mode: CommunicationMode::Unicast,
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut port = instance.add_port(config, rng);
+        port.set_unicast_master(
+            Some(UnicastMasterConfig {
+                max_clients: 1,
+                max_lease_duration: 60,
+                min_announce_interval: Interval::from_log_2(-3),
+                min_sync_interval: Interval::from_log_2(-3),
+                min_delay_resp_interval: Interval::from_log_2(-3),
+            }),
+            grant_table(6),
+        )
+        .unwrap();
+        let (mut port, _) = port.end_bmca();
+        drop(port.handle_announce_receipt_timer());
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity([1; 8]),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let client = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let requests = [MessageType::Announce, MessageType::Sync].map(|message_type| {
+            UnicastNegotiation::Request {
+                message_type,
+                log_interval: 0,
+                duration: 300,
+            }
+        });
+
+        // The longest message a header can describe, with as many TLVs as fit
+        // after the requests
+        let mut packet = std::vec![0; u16::MAX as usize];
+        let message = Message::signaling(
+            &default_ds,
+            client,
+            1,
+            PortIdentity::ALL,
+            &requests,
+            &LogMessageIntervals::STANDARD,
+        );
+        let len = message.serialize(&mut packet).unwrap();
+        packet[2..4].copy_from_slice(&u16::MAX.to_be_bytes());
+        for pad in packet[len..].chunks_exact_mut(4) {
+            pad.copy_from_slice(&[0x80, 0x08, 0x00, 0x00]);
+        }
+
+        TLVS_PEEKED.with(|peeked| peeked.set(0));
+        let mut actions = port.handle_general_receive(&packet);
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::SendUnicastGeneral { .. })
+        ));
+        drop(actions);
+        assert_eq!(TLVS_PEEKED.with(|peeked| peeked.get()), MAX_MESSAGE_TLVS);
+        assert_eq!(port.unicast_sync_clients().count(), 1);
+    }
+
+    // `message` followed by `pads` empty PAD TLVs
+    fn padded(message: &[u8], pads: usize) -> std::vec::Vec<u8> {
+        let mut packet = message.to_vec();
+        for _ in 0..pads {
+            packet.extend_from_slice(&[0x80, 0x08, 0x00, 0x00]);
+        }
+        let length = packet.len() as u16;
+        packet[2..4].copy_from_slice(&length.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_worst_case_other_packets() {
+        use crate::datastructures::{common::TLVS_PEEKED, messages::MAX_MESSAGE_TLVS};
+
+        // The most PAD TLVs that fit after `message` and `reserved` more bytes
+        let max_pads =
+            |message: &[u8], reserved: usize| (u16::MAX as usize - message.len() - reserved) / 4;
+        // Whether the port handled the general message, and the TLVs it
+        // examined doing so
+        let handle = |port: &mut Port<Running<'_, TestClock, BasicFilter>, _>, packet: &[u8]| {
+            TLVS_PEEKED.with(|peeked| peeked.set(0));
+            let handled = port.handle_general_receive(packet).next().is_some();
+            (handled, TLVS_PEEKED.with(|peeked| peeked.get()))
+        };
+
+        let instance = test_instance();
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let (mut port, _) = instance.add_port(test_config(), rng).end_bmca();
+
+        // Announce messages with more TLVs than are examined take as much
+        // work as the longest one
+        let announce = &better_master_announces()[0];
+        let (handled, peeked) = handle(&mut port, &padded(announce, MAX_MESSAGE_TLVS + 1));
+        assert!(handled);
+        let longest = padded(announce, max_pads(announce, 0));
+        assert_eq!(handle(&mut port, &longest), (true, peeked));
+
+        // Only the clocks of a path trace that are kept are read
+        let mut path_trace = announce[..64].to_vec();
+        let identities = (u16::MAX as usize - path_trace.len() - 4) / 8;
+        path_trace.extend_from_slice(&TlvType::PathTrace.to_primitive().to_be_bytes());
+        path_trace.extend_from_slice(&(identities as u16 * 8).to_be_bytes());
+        for index in 0..identities {
+            path_trace.extend_from_slice(&(index as u64 + 2).to_be_bytes());
+        }
+        let length = path_trace.len() as u16;
+        path_trace[2..4].copy_from_slice(&length.to_be_bytes());
+        assert!(handle(&mut port, &path_trace).0);
+
+        // Management messages
+        let mut request = [0; 54];
+        request[..4].copy_from_slice(&[0x0d, 0x02, 0x00, 0x36]);
+        request[30..34].copy_from_slice(&[0x00, 0x05, 0x04, 0x7f]);
+        PortIdentity::ALL.serialize(&mut request[34..44]).unwrap();
+        request[44..47].copy_from_slice(&[2, 1, ManagementAction::GET.to_primitive()]);
+        request[48..52].copy_from_slice(&[0x00, 0x01, 0x00, 0x02]);
+        let (handled, peeked) = handle(&mut port, &padded(&request, MAX_MESSAGE_TLVS + 1));
+        assert!(handled);
+        let longest = padded(&request, max_pads(&request, 0));
+        assert_eq!(handle(&mut port, &longest), (true, peeked));
+
+        // And authenticated messages, whose TLV is found from the end
+        let config = AuthenticationConfig {
+            algorithm: &TestAlgorithm { icv_length: 4 },
+            keys: &TEST_KEYS,
+            spp: 7,
+            on_failure: AuthenticationFailureAction::Drop,
+            replay_protection: false,
+        };
+        port.set_authentication(Some(config), &mut []).unwrap();
+        let seal = |message: std::vec::Vec<u8>| {
+            let mut packet = message;
+            packet.extend_from_slice(&[0x80, 0x09, 0x00, 0x0a, 7, 0, 0, 0, 0, 2]);
+            let length = packet.len() as u16 + 4;
+            packet[2..4].copy_from_slice(&length.to_be_bytes());
+            let mut icv = [0; 4];
+            config.algorithm.compute_icv(b"new", &packet, &mut icv);
+            packet.extend_from_slice(&icv);
+            packet
+        };
+        let (handled, peeked) = handle(&mut port, &seal(padded(announce, MAX_MESSAGE_TLVS + 1)));
+        assert!(handled);
+        let longest = seal(padded(announce, max_pads(announce, 14)));
+        assert_eq!(handle(&mut port, &longest), (true, peeked));
+        assert_eq!(
+            port.statistics().authentication,
+            AuthenticationFailures::default()
+        );
+    }
+
+    #[test]
+    fn test_unicast_master() {
+        let instance = test_instance();
+
+        let config = PortConfig {
+            communication_mode: CommunicationMode::Unicast,
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut port = instance.add_port(config, rng);
+        let master_config = UnicastMasterConfig {
+            max_clients: 1,
+            max_lease_duration: 60,
+            min_announce_interval: Interval::from_log_2(-3),
+            min_sync_interval: Interval::from_log_2(-3),
+            min_delay_resp_interval: Interval::from_log_2(-3),
+        };
+        assert_eq!(
+            port.set_unicast_master(Some(master_config), &mut []),
+            Err(UnicastConfigError::TooManyClients {
+                max_clients: 1,
+                slots: 0
+            })
+        );
+        port.set_unicast_master(Some(master_config), grant_table(6))
+            .unwrap();
+        let (mut port, _) = port.end_bmca();
+        drop(port.handle_announce_receipt_timer());
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity([1; 8]),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let client = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let signaling = |negotiation| {
+            let mut buffer = [0; MAX_DATA_LEN];
+            let message = Message::signaling(
+                &default_ds,
+                client,
+                1,
+                PortIdentity::ALL,
+                &[negotiation],
+                &LogMessageIntervals::STANDARD,
+            );
+            let len = message.serialize(&mut buffer).unwrap();
+            buffer[..len].to_vec()
+        };
+        let negotiations = |data: &[u8]| {
+            let Ok(Message::Signaling(message)) = Message::deserialize(data) else {
+                panic!("Expected a signaling message");
+            };
+            message.unicast_negotiations().collect::<std::vec::Vec<_>>()
+        };
+
+        let request = signaling(UnicastNegotiation::Request {
+            message_type: MessageType::Announce,
+            log_interval: 0,
+            duration: 300,
+        });
+        let mut actions = port.handle_general_receive(&request);
+        let Some(PortAction::SendUnicastGeneral {
+            data,
+            clock_identity,
+            port_number,
+        }) = actions.next()
+        else {
+            panic!("Unexpected action");
+        };
+        assert_eq!((clock_identity, port_number), (ClockIdentity([1; 8]), 1));
+        assert_eq!(
+            negotiations(data),
+            [UnicastNegotiation::Grant {
+                message_type: MessageType::Announce,
+                log_interval: 0,
+                duration: 60,
+                renewal_invited: true,
+            }]
+        );
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceTimer { .. })
+        ));
+        drop(actions);
+
+        // Announce messages go to the client only
+        let mut actions = port.handle_announce_timer();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetAnnounceTimer { .. })
+        ));
+        let Some(PortAction::SendUnicastGeneral { data, .. }) = actions.next() else {
+            panic!("Unexpected action");
+        };
+        let Ok(Message::Announce(announce)) = Message::deserialize(data) else {
+            panic!("Expected an announce message");
+        };
+        assert!(announce.header.unicast_flag);
+        drop(actions);
+
+        let cancel = signaling(UnicastNegotiation::Cancel {
+            message_type: MessageType::Announce,
+        });
+        let mut actions = port.handle_general_receive(&cancel);
+        let Some(PortAction::SendUnicastGeneral { data, .. }) = actions.next() else {
+            panic!("Unexpected action");
+        };
+        assert_eq!(
+            negotiations(data),
+            [UnicastNegotiation::AcknowledgeCancel {
+                message_type: MessageType::Announce,
+            }]
+        );
+        drop(actions);
+
+        assert!(port.handle_announce_timer().next().is_none());
+
+        // Ending the grant of the client on our side
+        drop(port.handle_general_receive(&request));
+        let mut actions = port.cancel_unicast_grants();
+        let Some(PortAction::SendUnicastGeneral {
+            data,
+            clock_identity,
+            port_number,
+        }) = actions.next()
+        else {
+            panic!("Unexpected action");
+        };
+        assert_eq!((clock_identity, port_number), (ClockIdentity([1; 8]), 1));
+        assert_eq!(
+            negotiations(data),
+            [UnicastNegotiation::Cancel {
+                message_type: MessageType::Announce,
+            }]
+        );
+        assert!(actions.next().is_none());
+        drop(actions);
+        assert!(port.cancel_unicast_grants().next().is_none());
+        assert!(port.handle_announce_timer().next().is_none());
+
+        let acknowledge = signaling(UnicastNegotiation::AcknowledgeCancel {
+            message_type: MessageType::Announce,
+        });
+        assert!(port.handle_general_receive(&acknowledge).next().is_none());
+        assert_eq!(
+            port.statistics().unicast_grants,
+            UnicastGrantCounts {
+                granted: 2,
+                cancelled: 1,
+                revoked: 1,
+                acknowledged: 1,
+                ..Default::default()
+            }
+        );
+
+        // Ports that can't answer ignore requests, and leave the grants they
+        // gave to time out
+        drop(port.handle_general_receive(&request));
+        port.config.transmit = TransmitEnable::NONE;
+        assert!(port.handle_general_receive(&request).next().is_none());
+        assert!(port.handle_general_receive(&cancel).next().is_none());
+        assert!(port.cancel_unicast_grants().next().is_none());
+        assert_eq!(port.statistics().unicast_grants.granted, 3);
+        assert_eq!(port.statistics().unicast_grants.cancelled, 1);
+    }
+
+    #[test]
+    fn test_unicast_sync() {
+        let instance = test_instance();
+
+        let config = PortConfig {
+            communication_mode: CommunicationMode::Unicast,
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut port = instance.add_port(config, rng);
+        port.set_unicast_master(
+            Some(UnicastMasterConfig {
+                max_clients: 2,
+                max_lease_duration: 60,
+                min_announce_interval: Interval::from_log_2(-3),
+                min_sync_interval: Interval::from_log_2(-3),
+                min_delay_resp_interval: Interval::from_log_2(-3),
+            }),
+            grant_table(6),
+        )
+        .unwrap();
+        let (mut port, _) = port.end_bmca();
+        drop(port.handle_announce_receipt_timer());
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity([1; 8]),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let request = |number| {
+            let client = PortIdentity {
+                clock_identity: ClockIdentity([number; 8]),
+                port_number: 1,
+            };
+            let negotiations = [
+                UnicastNegotiation::Request {
+                    message_type: MessageType::Announce,
+                    log_interval: 0,
+                    duration: 60,
+                },
+                UnicastNegotiation::Request {
+                    message_type: MessageType::Sync,
+                    log_interval: -1,
+                    duration: 60,
+                },
+            ];
+            let mut buffer = [0; MAX_DATA_LEN];
+            let message = Message::signaling(
+                &default_ds,
+                client,
+                1,
+                PortIdentity::ALL,
+                &negotiations,
+                &LogMessageIntervals::STANDARD,
+            );
+            let len = message.serialize(&mut buffer).unwrap();
+            buffer[..len].to_vec()
+        };
+
+        for number in [1, 2] {
+            let mut actions = port.handle_general_receive(&request(number));
+            assert!(matches!(
+                actions.next(),
+                Some(PortAction::SendUnicastGeneral { .. })
+            ));
+            assert!(matches!(
+                actions.next(),
+                Some(PortAction::ResetAnnounceTimer { .. })
+            ));
+            assert!(matches!(
+                actions.next(),
+                Some(PortAction::ResetSyncTimer { duration }) if duration.is_zero()
+            ));
+            drop(actions);
+        }
+
+        // Both clients are due, but the second one has to wait half an
+        // interval. After that, the first one is due again a full interval
+        // after it was sent.
+        let mut sent = std::vec::Vec::new();
+        for next_sync in [250, 500] {
+            let mut actions = port.handle_sync_timer();
+            assert!(matches!(
+                actions.next(),
+                Some(PortAction::ResetSyncTimer { duration })
+                    if duration == core::time::Duration::from_millis(next_sync)
+            ));
+            let Some(PortAction::SendUnicastTimeCritical {
+                context,
+                data,
+                clock_identity,
+                port_number,
+            }) = actions.next()
+            else {
+                panic!("Unexpected action");
+            };
+            let Ok(Message::Sync(sync)) = Message::deserialize(data) else {
+                panic!("Expected a sync message");
+            };
+            assert!(sync.header.unicast_flag);
+            assert_eq!(sync.header.sequence_id, 0);
+            sent.push((clock_identity, port_number));
+            drop(actions);
+
+            let mut actions = port.handle_send_timestamp(context, Time::from_micros(10));
+            let Some(PortAction::SendUnicastGeneral {
+                data,
+                clock_identity,
+                port_number,
+            }) = actions.next()
+            else {
+                panic!("Unexpected action");
+            };
+            assert_eq!(sent.last(), Some(&(clock_identity, port_number)));
+            let Ok(Message::FollowUp(follow_up)) = Message::deserialize(data) else {
+                panic!("Expected a follow up message");
+            };
+            assert!(follow_up.header.unicast_flag);
+            assert_eq!(follow_up.header.sequence_id, 0);
+        }
+        assert_ne!(sent[0], sent[1]);
+
+        let clients = port.unicast_sync_clients().collect::<std::vec::Vec<_>>();
+        assert_eq!(clients.len(), 2);
+        for client in clients {
+            assert_eq!(client.interval, Interval::from_log_2(-1));
+            assert_eq!(client.send_latency.count, 1);
+        }
+
+        // Every client gets announce messages with its own sequence ids
+        let mut sent = std::vec::Vec::new();
+        for _ in 0..2 {
+            let mut actions = port.handle_announce_timer();
+            let Some(PortAction::SendUnicastGeneral {
+                data,
+                clock_identity,
+                ..
+            }) = actions.find(|action| matches!(action, PortAction::SendUnicastGeneral { .. }))
+            else {
+                panic!("No announce message sent");
+            };
+            let Ok(Message::Announce(announce)) = Message::deserialize(data) else {
+                panic!("Expected an announce message");
+            };
+            sent.push((clock_identity, announce.header.sequence_id));
+        }
+        sent.sort();
+        assert_eq!(
+            sent,
+            [(ClockIdentity([1; 8]), 0), (ClockIdentity([2; 8]), 0)]
+        );
+        assert_eq!(port.statistics().internal_errors, 0);
+    }
+
+    #[test]
+    fn test_unicast_client_peer_delay() {
+        let instance = test_instance();
+        let config = PortConfig {
+            delay_mechanism: DelayMechanism::P2P {
+                interval: Interval::ONE_SECOND,
+                one_step_responder: false,
+            },
+            communication_mode: CommunicationMode::Unicast,
+            unicast_client: Some(UnicastClientConfig {
+                masters: 1,
+                lease_duration: 60,
+            }),
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let slave = TestPortState::Slave {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let (mut port, _) = instance.add_port_in_state(config, rng, slave).end_bmca();
+
+        // Delay responses are never requested for peer delay, and the link
+        // is measured without them
+        let mut actions = port.handle_delay_request_timer();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetDelayRequestTimer { duration })
+                if duration == core::time::Duration::from_secs(1)
+        ));
+        let Some(PortAction::SendTimeCritical { data, .. }) = actions.next() else {
+            panic!("No peer delay request sent");
+        };
+        assert!(matches!(
+            Message::deserialize(data),
+            Ok(Message::PDelayReq(_))
+        ));
+    }
+
+    #[test]
+    fn test_unicast_client() {
+        let instance = test_instance();
+        let master = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let client_config = |masters, lease_duration| {
+            Some(UnicastClientConfig {
+                masters,
+                lease_duration,
+            })
+        };
+
+        let config = PortConfig {
+            communication_mode: CommunicationMode::Unicast,
+            unicast_client: client_config(2, 60),
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let slave = TestPortState::Slave {
+            clock_identity: master.clock_identity,
+            port_number: master.port_number,
+        };
+        let (mut port, _) = instance.add_port_in_state(config, rng, slave).end_bmca();
+
+        // Invalid tables leave the configured one in place
+        assert_eq!(
+            port.set_unicast_client(client_config(0, 60)),
+            Err(UnicastConfigError::InvalidMasterCount(0))
+        );
+        assert_eq!(
+            port.set_unicast_client(client_config(MAX_UNICAST_MASTERS + 1, 60)),
+            Err(UnicastConfigError::InvalidMasterCount(
+                MAX_UNICAST_MASTERS + 1
+            ))
+        );
+        assert_eq!(
+            port.set_unicast_client(client_config(2, 0)),
+            Err(UnicastConfigError::ZeroLeaseDuration)
+        );
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: master.clock_identity,
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let signaling = |negotiations: &[UnicastNegotiation]| {
+            let mut buffer = [0; MAX_DATA_LEN];
+            let message = Message::signaling(
+                &default_ds,
+                master,
+                1,
+                PortIdentity::ALL,
+                negotiations,
+                &LogMessageIntervals::STANDARD,
+            );
+            let len = message.serialize(&mut buffer).unwrap();
+            buffer[..len].to_vec()
+        };
+        let sent_to_master = |actions: &mut PortActionIterator| {
+            let Some(PortAction::SendToUnicastMaster { data, master }) = actions.next() else {
+                panic!("Unexpected action");
+            };
+            let Ok(Message::Signaling(message)) = Message::deserialize(data) else {
+                panic!("Expected a signaling message");
+            };
+            let negotiations = message.unicast_negotiations().collect::<std::vec::Vec<_>>();
+            (master, message.target_port_identity(), negotiations)
+        };
+        let request = |message_type| UnicastNegotiation::Request {
+            message_type,
+            log_interval: 0,
+            duration: 60,
+        };
+        let grant = |message_type| UnicastNegotiation::Grant {
+            message_type,
+            log_interval: 0,
+            duration: 60,
+            renewal_invited: true,
+        };
+
+        // No delay requests without a grant for the responses
+        let mut actions = port.handle_delay_request_timer();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetDelayRequestTimer { .. })
+        ));
+        assert!(actions.next().is_none());
+        drop(actions);
+
+        // Announce messages are requested first, from every master
+        let mut actions = port.handle_unicast_negotiation_timer();
+        let (index, target, negotiations) = sent_to_master(&mut actions);
+        assert_eq!((index, target), (0, PortIdentity::ALL));
+        assert_eq!(negotiations, [request(MessageType::Announce)]);
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetUnicastNegotiationTimer { duration })
+                if duration == core::time::Duration::from_secs(1)
+        ));
+        drop(actions);
+        assert!(port
+            .handle_general_receive(&signaling(&[grant(MessageType::Announce)]))
+            .next()
+            .is_none());
+
+        // The master answering is the parent, so it also gets asked for the
+        // messages to synchronize to
+        let mut actions = port.handle(PortInput::UnicastNegotiationTimer);
+        let (index, target, negotiations) = sent_to_master(&mut actions);
+        assert_eq!((index, target), (0, master));
+        assert_eq!(
+            negotiations,
+            [MessageType::Sync, MessageType::DelayResp].map(request)
+        );
+        // The next master is due right away
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetUnicastNegotiationTimer { duration })
+                if duration == core::time::Duration::ZERO
+        ));
+        drop(actions);
+        let grants = signaling(&[MessageType::Sync, MessageType::DelayResp].map(grant));
+        assert!(port.handle_general_receive(&grants).next().is_none());
+
+        // Delay requests now go to the master that granted the responses
+        let mut actions = port.handle_delay_request_timer();
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::ResetDelayRequestTimer { .. })
+        ));
+        let Some(PortAction::SendUnicastTimeCritical {
+            data,
+            clock_identity,
+            port_number,
+            ..
+        }) = actions.next()
+        else {
+            panic!("Unexpected action");
+        };
+        assert_eq!((clock_identity, port_number), (ClockIdentity([1; 8]), 1));
+        let Ok(Message::DelayReq(delay_req)) = Message::deserialize(data) else {
+            panic!("Expected a delay request");
+        };
+        assert!(delay_req.header.unicast_flag);
+        drop(actions);
+
+        // A cancelled grant is acknowledged, and only requested again after
+        // the back-off
+        let cancel = signaling(&[UnicastNegotiation::Cancel {
+            message_type: MessageType::Sync,
+        }]);
+        let mut actions = port.handle_general_receive(&cancel);
+        assert!(matches!(
+            actions.next(),
+            Some(PortAction::SendUnicastGeneral { .. })
+        ));
+        assert!(actions.next().is_none());
+        drop(actions);
+
+        let mut actions = port.handle_unicast_negotiation_timer();
+        let (index, target, negotiations) = sent_to_master(&mut actions);
+        assert_eq!((index, target), (1, PortIdentity::ALL));
+        assert_eq!(negotiations, [request(MessageType::Announce)]);
+        drop(actions);
+
+        assert_eq!(
+            port.statistics().unicast_requests,
+            UnicastRequestCounts {
+                requested: 4,
+                granted: 3,
+                cancelled: 1,
+                master_changes: 1,
+                ..Default::default()
+            }
+        );
+
+        // Without signaling there are no requests, nor a timer to send them
+        port.config.transmit = TransmitEnable::NONE;
+        assert!(port.handle_unicast_negotiation_timer().next().is_none());
+    }
+
+    #[test]
+    fn test_unicast_end_to_end() {
+        let master_instance = PtpInstance::new(
+            InstanceConfig {
+                clock_identity: ClockIdentity([1; 8]),
+                priority_1: 128,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            },
+            TimePropertiesDS::default(),
+            TestClock,
+            BasicFilter::new(0.25),
+        );
+        let config = PortConfig {
+            communication_mode: CommunicationMode::Unicast,
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut master = master_instance.add_port(config, rng);
+        master
+            .set_unicast_master(
+                Some(UnicastMasterConfig {
+                    max_clients: 1,
+                    max_lease_duration: 60,
+                    min_announce_interval: Interval::from_log_2(-3),
+                    min_sync_interval: Interval::from_log_2(-3),
+                    min_delay_resp_interval: Interval::from_log_2(-3),
+                }),
+                grant_table(3),
+            )
+            .unwrap();
+        let (mut master, _) = master.end_bmca();
+        drop(master.handle_announce_receipt_timer());
+
+        let client_instance = test_instance();
+        let config = PortConfig {
+            communication_mode: CommunicationMode::Unicast,
+            unicast_client: Some(UnicastClientConfig {
+                masters: 1,
+                lease_duration: 60,
+            }),
+            ..test_config()
+        };
+        let rng = rand::rngs::mock::StepRng::new(2, 1);
+        // The first port of an instance is number 0
+        let slave = TestPortState::Slave {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 0,
+        };
+        let (mut client, _) = client_instance
+            .add_port_in_state(config, rng, slave)
+            .end_bmca();
+
+        // Hand the grants of the master to the client, for the announce
+        // messages first and then the sync and delay responses
+        for _ in 0..2 {
+            let mut actions = client.handle_unicast_negotiation_timer();
+            let Some(PortAction::SendToUnicastMaster { data, .. }) = actions.next() else {
+                panic!("Unexpected action");
+            };
+            let request = data.to_vec();
+            drop(actions);
+            let mut actions = master.handle_general_receive(&request);
+            let Some(PortAction::SendUnicastGeneral { data, .. }) = actions.next() else {
+                panic!("Unexpected action");
+            };
+            for action in client.handle_general_receive(data) {
+                assert!(!matches!(action, PortAction::SendUnicastGeneral { .. }));
+            }
+        }
+
+        for round in 0..2 {
+            let mut actions = client.handle_delay_request_timer();
+            assert!(matches!(
+                actions.next(),
+                Some(PortAction::ResetDelayRequestTimer { .. })
+            ));
+            let Some(PortAction::SendUnicastTimeCritical { context, data, .. }) = actions.next()
+            else {
+                panic!("No delay request sent in round {round}");
+            };
+            let delay_req = data.to_vec();
+            drop(actions);
+            let send_time = Time::from_secs(10 + round);
+            let mut actions = master.handle_timecritical_receive(&delay_req, send_time);
+            let Some(PortAction::SendUnicastGeneral { data, .. }) = actions.next() else {
+                panic!("No delay response sent in round {round}");
+            };
+            let Ok(Message::DelayResp(delay_resp)) = Message::deserialize(data) else {
+                panic!("Expected a delay response");
+            };
+            // The interval was negotiated, the client keeps the configured one
+            assert_eq!(
+                delay_resp.header.log_message_interval,
+                UNSPECIFIED_LOG_INTERVAL
+            );
+            drop(client.handle_send_timestamp(context, send_time));
+            drop(client.handle_general_receive(data));
+        }
+        assert_eq!(
+            client.statistics().delay_resp_rejections,
+            DelayRespRejections::default()
+        );
+    }
 }
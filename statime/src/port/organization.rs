@@ -0,0 +1,181 @@
+//! Vendor specific data in ORGANIZATION_EXTENSION TLVs, see
+//! [`Port::set_organization_extension`](crate::Port::set_organization_extension)
+
+use crate::datastructures::{
+    common::{ClockIdentity, PortIdentity, ProfileIdentifier, TlvType},
+    messages::{message_tlvs, MessageType},
+};
+
+/// Produces and consumes the ORGANIZATION_EXTENSION TLVs of the announce and
+/// signaling messages of a port, see IEEE1588-2019 section 14.3.
+///
+/// Both methods are called while the port handles a message, so they should
+/// return quickly. They take `&self` as the extension can be shared between
+/// ports, any state needs interior mutability.
+pub trait OrganizationExtension: core::fmt::Debug + Sync {
+    /// Add TLVs to an announce or signaling message the port is about to
+    /// send. Adding none leaves the message as it is.
+    fn produce(&self, message_type: MessageType, tlvs: &mut OrganizationTlvWriter<'_>);
+
+    /// Take in a TLV of a received announce or signaling message, sent by the
+    /// port with the given clock identity and port number. Only messages of
+    /// the domain of the port are passed on.
+    fn consume(
+        &self,
+        message_type: MessageType,
+        source: (ClockIdentity, u16),
+        tlv: OrganizationTlv<'_>,
+    );
+}
+
+/// An organization extension TLV
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrganizationTlv<'a> {
+    /// [`TlvType::OrganizationExtension`],
+    /// [`TlvType::OrganizationExtensionPropagate`] or
+    /// [`TlvType::OrganizationExtensionDoNotPropagate`]
+    pub tlv_type: TlvType,
+    /// The OUI or CID of the organization that defined the TLV
+    pub organization_id: [u8; 3],
+    pub organization_sub_type: [u8; 3],
+    /// The data after the organization sub type
+    pub data: &'a [u8],
+}
+
+impl OrganizationTlv<'_> {
+    fn is_organization_extension(tlv_type: TlvType) -> bool {
+        matches!(
+            tlv_type,
+            TlvType::OrganizationExtension
+                | TlvType::OrganizationExtensionPropagate
+                | TlvType::OrganizationExtensionDoNotPropagate
+        )
+    }
+}
+
+/// Why an [`OrganizationTlv`] could not be added to a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrganizationTlvError {
+    /// The type is not one of the organization extension TLV types
+    InvalidType(TlvType),
+    /// The data has an odd length, which TLVs can't have
+    OddLength,
+    /// The TLV doesn't fit in the message
+    NoSpace,
+}
+
+impl core::fmt::Display for OrganizationTlvError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidType(tlv_type) => {
+                write!(f, "{tlv_type:?} is not an organization extension TLV")
+            }
+            Self::OddLength => write!(f, "the TLV data has an odd length"),
+            Self::NoSpace => write!(f, "the TLV does not fit in the message"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for OrganizationTlvError {}
+
+/// Adds TLVs to the end of a message, see
+/// [`OrganizationExtension::produce`]
+#[derive(Debug)]
+pub struct OrganizationTlvWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl OrganizationTlvWriter<'_> {
+    /// Add a TLV after those added before it
+    pub fn push(&mut self, tlv: OrganizationTlv<'_>) -> Result<(), OrganizationTlvError> {
+        if !OrganizationTlv::is_organization_extension(tlv.tlv_type) {
+            return Err(OrganizationTlvError::InvalidType(tlv.tlv_type));
+        }
+        if !tlv.data.len().is_multiple_of(2) {
+            return Err(OrganizationTlvError::OddLength);
+        }
+
+        let size = 10 + tlv.data.len();
+        let buffer = self
+            .buffer
+            .get_mut(self.len..self.len + size)
+            .ok_or(OrganizationTlvError::NoSpace)?;
+        buffer[0..2].copy_from_slice(&tlv.tlv_type.to_primitive().to_be_bytes());
+        buffer[2..4].copy_from_slice(&((size - 4) as u16).to_be_bytes());
+        buffer[4..7].copy_from_slice(&tlv.organization_id);
+        buffer[7..10].copy_from_slice(&tlv.organization_sub_type);
+        buffer[10..].copy_from_slice(tlv.data);
+        self.len += size;
+
+        Ok(())
+    }
+
+    /// Bytes left in the message, each TLV takes 10 bytes besides its data
+    pub fn remaining(&self) -> usize {
+        self.buffer.len() - self.len
+    }
+}
+
+/// Add the TLV advertising `profile` and those of `extension` to the message
+/// of `length` bytes at the start of `buffer`, returning the length of the
+/// message with them
+pub(crate) fn append_organization_tlvs(
+    extension: Option<&dyn OrganizationExtension>,
+    profile: Option<ProfileIdentifier>,
+    message_type: MessageType,
+    buffer: &mut [u8],
+    length: usize,
+) -> usize {
+    if extension.is_none() && profile.is_none() {
+        return length;
+    }
+
+    let (message, tail) = buffer.split_at_mut(length);
+    let mut writer = OrganizationTlvWriter {
+        buffer: tail,
+        len: 0,
+    };
+    if let Some(ProfileIdentifier([a, b, c, d, e, f])) = profile {
+        let tlv = OrganizationTlv {
+            tlv_type: TlvType::OrganizationExtensionDoNotPropagate,
+            organization_id: [a, b, c],
+            organization_sub_type: [d, e, f],
+            data: &[],
+        };
+        // Announce messages always have room for it
+        let _ = writer.push(tlv);
+    }
+    if let Some(extension) = extension {
+        extension.produce(message_type, &mut writer);
+    }
+
+    let length = length + writer.len;
+    // The message length in the header
+    message[2..4].copy_from_slice(&(length as u16).to_be_bytes());
+    length
+}
+
+/// Pass the organization extension TLVs of a received message to `extension`
+pub(crate) fn consume_organization_tlvs(
+    extension: &dyn OrganizationExtension,
+    message_type: MessageType,
+    source: PortIdentity,
+    data: &[u8],
+) {
+    for (tlv_type, value) in message_tlvs(data) {
+        if OrganizationTlv::is_organization_extension(tlv_type) && value.len() >= 6 {
+            extension.consume(
+                message_type,
+                (source.clock_identity, source.port_number),
+                OrganizationTlv {
+                    tlv_type,
+                    organization_id: [value[0], value[1], value[2]],
+                    organization_sub_type: [value[3], value[4], value[5]],
+                    data: &value[6..],
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,584 @@
+use arrayvec::ArrayVec;
+
+use crate::{
+    config::LogMessageIntervals,
+    datastructures::{
+        common::{PortIdentity, TimeInterval},
+        datasets::DefaultDS,
+        messages::{Message, PDelayReqMessage, PDelayRespFollowUpMessage, PDelayRespMessage},
+        WireFormatError,
+    },
+    log,
+    port::{
+        sequence_id::SequenceIdGenerator, Diagnostic, PortAction, PortActionIterator,
+        TimestampContext, TimestampContextInner, TimestampSource,
+    },
+    time::{Duration, Interval, Time},
+};
+
+/// Number of peer delay exchanges the neighbor rate ratio is measured over
+const NEIGHBOR_RATE_RATIO_WINDOW: usize = 8;
+
+/// What a completed peer delay exchange measured about the link, as handed
+/// from a [`CmldsLinkPort`](crate::CmldsLinkPort) to the ports on its link
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkDelay {
+    /// The mean link delay of 11.4.2, the asymmetry is accounted for when
+    /// calculating the offset
+    pub mean_delay: Duration,
+    /// Where the timestamps it was computed from were taken
+    pub source: TimestampSource,
+    /// The frequency of the clock of the peer relative to our unadjusted
+    /// oscillator, once two exchanges with a two-step responder completed
+    pub neighbor_rate_ratio: Option<f64>,
+}
+
+/// The requesting side of the peer delay mechanism (IEEE1588-2019 section
+/// 11.4), measuring the link to the neighbor of a port one exchange at a
+/// time
+#[derive(Debug)]
+pub(crate) struct PeerDelay {
+    state: PeerDelayState,
+    ids: SequenceIdGenerator,
+    // Where the send timestamp of the current request was taken
+    send_source: TimestampSource,
+    // Where the receive timestamp of the current response was taken
+    recv_source: TimestampSource,
+    // The frequency correction of the local clock when the current request
+    // was sent
+    frequency_multiplier: f64,
+    // When the responses of recent exchanges left the peer, when they
+    // arrived here and the frequency correction of the local clock from then
+    // on, oldest first
+    exchanges: ArrayVec<(Time, Time, f64), NEIGHBOR_RATE_RATIO_WINDOW>,
+    neighbor_rate_ratio: Option<f64>,
+    // The port this belongs to, to attach to log messages and to match
+    // responses with
+    port_identity: PortIdentity,
+
+    // Problem noticed while handling the last event, for the owner to report
+    pub(in crate::port) diagnostic: Option<Diagnostic>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum PeerDelayState {
+    Empty,
+    Measuring {
+        id: u16,
+        // When our PdelayReq left (t1)
+        send_time: Option<Time>,
+        // When the PdelayResp arrived (t4)
+        recv_time: Option<Time>,
+        // When the peer received our request (t2), minus the correction of
+        // the response, until the PdelayRespFollowUp comes in
+        request_receipt_time: Option<Time>,
+        // When the response left the peer (t3), which one-step responders
+        // don't tell
+        response_origin_time: Option<Time>,
+        // How long the peer took to respond, including the corrections
+        turnaround: Option<Duration>,
+    },
+}
+
+impl PeerDelay {
+    pub(crate) fn new(port_identity: PortIdentity) -> Self {
+        PeerDelay {
+            state: PeerDelayState::Empty,
+            ids: SequenceIdGenerator::new(),
+            send_source: TimestampSource::Legacy,
+            recv_source: TimestampSource::Legacy,
+            frequency_multiplier: 1.0,
+            exchanges: ArrayVec::new(),
+            neighbor_rate_ratio: None,
+            port_identity,
+            diagnostic: None,
+        }
+    }
+
+    /// Whether the last request sent is still waiting for its response
+    pub(crate) fn outstanding(&self) -> bool {
+        matches!(self.state, PeerDelayState::Measuring { .. })
+    }
+
+    /// Start a new exchange, giving up on the previous one if it didn't
+    /// complete. The `frequency_multiplier` is the correction currently
+    /// applied to the local clock, see
+    /// [`Clock::frequency_multiplier`](crate::Clock::frequency_multiplier).
+    pub(crate) fn send_request<'a>(
+        &mut self,
+        log_min_pdelay_req_interval: Interval,
+        frequency_multiplier: f64,
+        overrides: &LogMessageIntervals,
+        port_identity: PortIdentity,
+        default_ds: &DefaultDS,
+        buffer: &'a mut [u8],
+    ) -> PortActionIterator<'a> {
+        log::debug!(port: self.port_identity, "Starting new peer delay measurement");
+
+        let pdelay_id = self.ids.generate();
+        let pdelay_req = Message::pdelay_req(default_ds, port_identity, pdelay_id, overrides);
+
+        let message_length = match pdelay_req.serialize(buffer) {
+            Ok(length) => length,
+            Err(error) => {
+                self.diagnostic = Some(Diagnostic::SerializationFailed);
+                log::error!(
+                    port: self.port_identity,
+                    "Could not serialize peer delay request: {:?}",
+                    error
+                );
+                return actions![];
+            }
+        };
+
+        self.state = PeerDelayState::Measuring {
+            id: pdelay_id,
+            send_time: None,
+            recv_time: None,
+            request_receipt_time: None,
+            response_origin_time: None,
+            turnaround: None,
+        };
+        self.frequency_multiplier = frequency_multiplier;
+
+        // Unlike delay requests, peer delay requests are sent at a fixed
+        // interval (9.5.13.2)
+        actions![
+            PortAction::ResetDelayRequestTimer {
+                duration: log_min_pdelay_req_interval.as_core_duration(),
+            },
+            PortAction::SendTimeCritical {
+                context: TimestampContext {
+                    inner: TimestampContextInner::PDelayReq { id: pdelay_id },
+                },
+                data: &buffer[..message_length],
+                transmit_at: None,
+            }
+        ]
+    }
+
+    /// The request with this id was never sent, so its response will never
+    /// come
+    pub(crate) fn handle_send_failure(&mut self, failed_id: u16) {
+        match self.state {
+            PeerDelayState::Measuring { id, .. } if id == failed_id => {
+                self.state = PeerDelayState::Empty;
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn handle_timestamp(
+        &mut self,
+        timestamp_id: u16,
+        timestamp: Time,
+        source: TimestampSource,
+    ) -> Option<LinkDelay> {
+        match self.state {
+            PeerDelayState::Measuring {
+                id,
+                send_time: Some(_),
+                ..
+            } if id == timestamp_id => {
+                self.diagnostic = Some(Diagnostic::UnexpectedTimestamp);
+                log::error!(
+                    port: self.port_identity,
+                    "Double send timestamp for peer delay request"
+                );
+            }
+            PeerDelayState::Measuring {
+                id,
+                ref mut send_time,
+                ..
+            } if id == timestamp_id => {
+                *send_time = Some(timestamp);
+                self.send_source = source;
+            }
+            _ => {
+                self.diagnostic = Some(Diagnostic::UnexpectedTimestamp);
+                log::warn!(
+                    port: self.port_identity,
+                    "Late timestamp for peer delay request ignored"
+                );
+            }
+        }
+
+        self.try_finish()
+    }
+
+    /// Handle a response, with its receive timestamp already corrected for
+    /// the ingress latency
+    pub(crate) fn handle_resp(
+        &mut self,
+        message: PDelayRespMessage,
+        timestamp: Time,
+        source: TimestampSource,
+    ) -> Option<LinkDelay> {
+        log::debug!(port: self.port_identity, "Received PdelayResp");
+
+        // Responses to other ports are normal on a multicast network
+        if message.requesting_port_identity != self.port_identity {
+            return None;
+        }
+
+        match self.state {
+            PeerDelayState::Measuring {
+                id,
+                recv_time: Some(_),
+                ..
+            } if id == message.header.sequence_id => {
+                self.diagnostic = Some(Diagnostic::DuplicateMessage);
+                log::warn!(port: self.port_identity, "Duplicate PdelayResp message");
+            }
+            PeerDelayState::Measuring {
+                id,
+                ref mut recv_time,
+                ref mut request_receipt_time,
+                ref mut turnaround,
+                ..
+            } if id == message.header.sequence_id => {
+                let correction = Duration::from(message.header.correction_field);
+                if message.header.two_step_flag {
+                    // The time the response left follows in a
+                    // PdelayRespFollowUp
+                    let Some(receipt_time) =
+                        Time::from(message.request_receive_timestamp).checked_sub(correction)
+                    else {
+                        self.diagnostic = Some(Diagnostic::InvalidPacket);
+                        log::warn!(
+                            port: self.port_identity,
+                            "PdelayResp correction out of range, ignored"
+                        );
+                        return None;
+                    };
+                    *request_receipt_time = Some(receipt_time);
+                } else {
+                    // A one-step responder adds its turnaround time to the
+                    // correction field
+                    *turnaround = Some(correction);
+                }
+
+                *recv_time = Some(timestamp);
+                self.recv_source = source;
+            }
+            _ => {
+                self.diagnostic = Some(Diagnostic::UnexpectedMessage);
+                log::warn!(port: self.port_identity, "Unexpected PdelayResp message");
+            }
+        }
+
+        self.try_finish()
+    }
+
+    pub(crate) fn handle_resp_follow_up(
+        &mut self,
+        message: PDelayRespFollowUpMessage,
+    ) -> Option<LinkDelay> {
+        log::debug!(port: self.port_identity, "Received PdelayRespFollowUp");
+
+        if message.requesting_port_identity != self.port_identity {
+            return None;
+        }
+
+        match self.state {
+            PeerDelayState::Measuring {
+                id,
+                turnaround: Some(_),
+                ..
+            } if id == message.header.sequence_id => {
+                self.diagnostic = Some(Diagnostic::DuplicateMessage);
+                log::warn!(
+                    port: self.port_identity,
+                    "Duplicate PdelayRespFollowUp message"
+                );
+            }
+            PeerDelayState::Measuring {
+                id,
+                request_receipt_time: Some(request_receipt_time),
+                ref mut response_origin_time,
+                ref mut turnaround,
+                ..
+            } if id == message.header.sequence_id => {
+                let Some(origin_time) = Time::from(message.response_origin_timestamp)
+                    .checked_add(Duration::from(message.header.correction_field))
+                else {
+                    self.diagnostic = Some(Diagnostic::InvalidPacket);
+                    log::warn!(
+                        port: self.port_identity,
+                        "PdelayRespFollowUp correction out of range, ignored"
+                    );
+                    return None;
+                };
+                *response_origin_time = Some(origin_time);
+                *turnaround = Some(origin_time - request_receipt_time);
+            }
+            _ => {
+                self.diagnostic = Some(Diagnostic::UnexpectedMessage);
+                log::warn!(
+                    port: self.port_identity,
+                    "Unexpected PdelayRespFollowUp message"
+                );
+            }
+        }
+
+        self.try_finish()
+    }
+
+    fn try_finish(&mut self) -> Option<LinkDelay> {
+        let PeerDelayState::Measuring {
+            send_time: Some(send_time),
+            recv_time: Some(recv_time),
+            response_origin_time,
+            turnaround: Some(turnaround),
+            ..
+        } = self.state
+        else {
+            return None;
+        };
+
+        self.state = PeerDelayState::Empty;
+        if let Some(response_origin_time) = response_origin_time {
+            self.update_neighbor_rate_ratio(
+                response_origin_time,
+                recv_time,
+                self.frequency_multiplier,
+            );
+        }
+
+        Some(LinkDelay {
+            mean_delay: (recv_time - send_time - turnaround) / 2,
+            source: self.send_source.combine(self.recv_source),
+            neighbor_rate_ratio: self.neighbor_rate_ratio,
+        })
+    }
+
+    // The neighbor rate ratio of IEEE 802.1AS-2020 section 11.2.19.3.3: how
+    // much time passed at the peer between the responses of the oldest and
+    // the latest exchange, relative to how much passed on the unadjusted
+    // oscillator here. Our timestamps come from the steered clock, so the time
+    // between exchanges is divided by the frequency correction of the clock
+    // over it.
+    fn update_neighbor_rate_ratio(
+        &mut self,
+        response_origin_time: Time,
+        recv_time: Time,
+        frequency_multiplier: f64,
+    ) {
+        if self
+            .exchanges
+            .last()
+            .is_some_and(|&(origin, recv, _)| response_origin_time <= origin || recv_time <= recv)
+        {
+            // One of the clocks stepped back, the old exchanges say nothing
+            // about the rate anymore
+            self.exchanges.clear();
+            self.neighbor_rate_ratio = None;
+        }
+        if self.exchanges.is_full() {
+            self.exchanges.remove(0);
+        }
+        self.exchanges
+            .push((response_origin_time, recv_time, frequency_multiplier));
+
+        if let [(first_origin, ..), .., (last_origin, ..)] = self.exchanges[..] {
+            let peer_interval = (last_origin - first_origin).nanos_lossy();
+            let local_interval: f64 = self
+                .exchanges
+                .windows(2)
+                .map(|pair| (pair[1].1 - pair[0].1).nanos_lossy() / pair[0].2)
+                .sum();
+            self.neighbor_rate_ratio = Some(peer_interval / local_interval);
+        }
+    }
+}
+
+/// Answer a peer delay request as a one- or two-step responder (11.4.3),
+/// given its receive timestamp corrected for the ingress latency. A two-step
+/// responder sends the [`follow_up`] once the response was timestamped.
+pub(crate) fn respond<'a>(
+    request: &PDelayReqMessage,
+    timestamp: Time,
+    one_step_responder: bool,
+    port_identity: PortIdentity,
+    overrides: &LogMessageIntervals,
+    buffer: &'a mut [u8],
+) -> Result<PortActionIterator<'a>, WireFormatError> {
+    let response = if one_step_responder {
+        Message::pdelay_resp_one_step(request, port_identity, overrides)
+    } else {
+        Message::pdelay_resp(request, port_identity, timestamp, overrides)
+    };
+    let length = response.serialize(buffer)?;
+
+    let inner = if one_step_responder {
+        TimestampContextInner::OneStepPDelayResp
+    } else {
+        TimestampContextInner::PDelayResp {
+            id: request.header.sequence_id,
+            requesting_port_identity: request.header.source_port_identity,
+            correction: TimeInterval(request.header.correction_field.0 - timestamp.subnano().0),
+        }
+    };
+    Ok(actions![PortAction::SendTimeCritical {
+        context: TimestampContext { inner },
+        data: &buffer[..length],
+        transmit_at: None,
+    }])
+}
+
+/// The PdelayRespFollowUp with the time the PdelayResp to a two-step request
+/// was sent
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn follow_up<'a>(
+    default_ds: &DefaultDS,
+    port_identity: PortIdentity,
+    requesting_port_identity: PortIdentity,
+    id: u16,
+    correction: TimeInterval,
+    timestamp: Time,
+    overrides: &LogMessageIntervals,
+    buffer: &'a mut [u8],
+) -> Result<PortActionIterator<'a>, WireFormatError> {
+    let follow_up = Message::pdelay_resp_follow_up(
+        default_ds,
+        port_identity,
+        requesting_port_identity,
+        id,
+        correction,
+        timestamp,
+        overrides,
+    );
+    let length = follow_up.serialize(buffer)?;
+
+    Ok(actions![PortAction::SendGeneral {
+        data: &buffer[..length],
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::messages::Header;
+
+    #[test]
+    fn test_correction_out_of_range() {
+        let port_identity = PortIdentity::default();
+        let mut peer_delay = PeerDelay::new(port_identity);
+        let default_ds = DefaultDS::new(crate::config::InstanceConfig {
+            clock_identity: Default::default(),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: Default::default(),
+        });
+        let mut buffer = [0; 64];
+        drop(peer_delay.send_request(
+            Interval::ONE_SECOND,
+            1.0,
+            &LogMessageIntervals::STANDARD,
+            port_identity,
+            &default_ds,
+            &mut buffer,
+        ));
+        let PeerDelayState::Measuring { id, .. } = peer_delay.state else {
+            panic!("Not measuring");
+        };
+        let header = Header {
+            sequence_id: id,
+            two_step_flag: true,
+            ..Default::default()
+        };
+
+        // A correction that would put the request before the origin of the
+        // timescale drops the response
+        let response = PDelayRespMessage {
+            header: Header {
+                correction_field: Duration::from_secs(20).into(),
+                ..header
+            },
+            request_receive_timestamp: Time::from_secs(10).into(),
+            requesting_port_identity: port_identity,
+        };
+        assert_eq!(
+            peer_delay.handle_resp(response, Time::from_secs(12), TimestampSource::Hardware),
+            None
+        );
+        assert_eq!(
+            peer_delay.diagnostic.take(),
+            Some(Diagnostic::InvalidPacket)
+        );
+        assert!(matches!(
+            peer_delay.state,
+            PeerDelayState::Measuring {
+                recv_time: None,
+                request_receipt_time: None,
+                ..
+            }
+        ));
+
+        // Likewise for the follow up
+        let response = PDelayRespMessage { header, ..response };
+        assert_eq!(
+            peer_delay.handle_resp(response, Time::from_secs(12), TimestampSource::Hardware),
+            None
+        );
+        let follow_up = PDelayRespFollowUpMessage {
+            header: Header {
+                correction_field: Duration::from_secs(-20).into(),
+                ..header
+            },
+            response_origin_timestamp: Time::from_secs(10).into(),
+            requesting_port_identity: port_identity,
+        };
+        assert_eq!(peer_delay.handle_resp_follow_up(follow_up), None);
+        assert_eq!(
+            peer_delay.diagnostic.take(),
+            Some(Diagnostic::InvalidPacket)
+        );
+    }
+
+    #[test]
+    fn test_neighbor_rate_ratio() {
+        let mut peer_delay = PeerDelay::new(Default::default());
+
+        // The peer runs 10ppm fast, responding every second of our time
+        let exchange = |peer_delay: &mut PeerDelay, second: i64| {
+            let recv_time = Time::from_secs(100) + Duration::from_secs(second);
+            let origin_time = Time::from_secs(500) + Duration::from_micros(second * 1_000_010);
+            peer_delay.update_neighbor_rate_ratio(origin_time, recv_time, 1.0);
+            peer_delay.neighbor_rate_ratio
+        };
+        assert_eq!(exchange(&mut peer_delay, 0), None);
+        for second in 1..20 {
+            let ratio = exchange(&mut peer_delay, second).unwrap();
+            assert!((ratio - 1.00001).abs() < 1e-12, "{ratio}");
+        }
+        assert_eq!(peer_delay.exchanges.len(), NEIGHBOR_RATE_RATIO_WINDOW);
+
+        // Exchanges from before a clock stepped back are dropped
+        assert_eq!(exchange(&mut peer_delay, 5), None);
+        assert!(exchange(&mut peer_delay, 6).is_some());
+    }
+
+    #[test]
+    fn test_neighbor_rate_ratio_steered_clock() {
+        let mut peer_delay = PeerDelay::new(Default::default());
+
+        // The peer runs 10ppm fast, responding every second of our
+        // oscillator, while our clock is corrected to run 20ppm fast after
+        // five seconds
+        let mut recv_time = Time::from_secs(100);
+        for second in 0..20 {
+            let correction_ppm = if second < 5 { 0 } else { 20 };
+            let origin_time = Time::from_secs(500) + Duration::from_micros(second * 1_000_010);
+            let multiplier = 1.0 + correction_ppm as f64 * 1e-6;
+            peer_delay.update_neighbor_rate_ratio(origin_time, recv_time, multiplier);
+            if second > 0 {
+                let ratio = peer_delay.neighbor_rate_ratio.unwrap();
+                assert!((ratio - 1.00001).abs() < 1e-12, "{ratio}");
+            }
+            recv_time += Duration::from_micros(1_000_000 + correction_ppm);
+        }
+    }
+}
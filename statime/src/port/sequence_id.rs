@@ -1,3 +1,5 @@
+use arrayvec::ArrayVec;
+
 #[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub(crate) struct SequenceIdGenerator {
     current: u16,
@@ -14,3 +16,52 @@ impl SequenceIdGenerator {
         id
     }
 }
+
+/// Sequence ids kept separately per destination, like the port identity a
+/// unicast message is sent to, for at most `N` destinations.
+///
+/// When a new destination doesn't fit, the one used least recently is
+/// forgotten. Both start over from 0 the next time they are used.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct KeyedSequenceIds<K, const N: usize> {
+    // Ordered from least to most recently used
+    generators: ArrayVec<(K, SequenceIdGenerator), N>,
+}
+
+impl<K: PartialEq, const N: usize> KeyedSequenceIds<K, N> {
+    pub(crate) fn generate(&mut self, key: K) -> u16 {
+        let position = self.generators.iter().position(|(known, _)| *known == key);
+        let mut entry = match position {
+            Some(position) => self.generators.remove(position),
+            None => (key, SequenceIdGenerator::new()),
+        };
+        if self.generators.is_full() {
+            self.generators.remove(0);
+        }
+
+        let id = entry.1.generate();
+        self.generators.push(entry);
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyed_sequence_ids() {
+        let mut ids = KeyedSequenceIds::<u8, 2>::default();
+        assert_eq!(ids.generate(1), 0);
+        assert_eq!(ids.generate(1), 1);
+        assert_eq!(ids.generate(2), 0);
+        assert_eq!(ids.generate(1), 2);
+
+        // 2 was used least recently, so it makes room for 3
+        assert_eq!(ids.generate(3), 0);
+        assert_eq!(ids.generate(1), 3);
+        assert_eq!(ids.generate(2), 0);
+        assert_eq!(ids.generate(1), 4);
+        assert_eq!(ids.generate(3), 0);
+    }
+}
@@ -1,27 +1,110 @@
 use core::fmt::Debug;
 
+use arrayvec::ArrayVec;
 use atomic_refcell::AtomicRefCell;
 
 use crate::{
     clock::Clock,
     datastructures::{
-        common::PortIdentity,
+        common::{FollowUpInformation, PortIdentity, ProfileIdentifier, WireTimestamp},
         datasets::DefaultDS,
-        messages::{DelayReqMessage, Message},
+        messages::{AnnounceMessage, DelayReqMessage, Message, MessageType, MAX_DATA_LEN},
+        WireFormat,
     },
+    log,
     port::{
-        sequence_id::SequenceIdGenerator, PortAction, PortActionIterator, TimestampContext,
+        announce::AnnounceContent,
+        event::EventQueue,
+        organization::{append_organization_tlvs, OrganizationExtension},
+        sequence_id::SequenceIdGenerator,
+        unicast::SyncGrant,
+        Diagnostic, PortAction, PortActionIterator, PortEvent, PortStatistics, TimestampContext,
         TimestampContextInner,
     },
     ptp_instance::PtpInstanceState,
-    time::{Interval, Time},
-    PortConfig,
+    time::{Duration, Time},
+    DelayMechanism, PortConfig,
 };
 
-#[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+// Sync messages have no TLVs, so they fit with room to spare
+const SYNC_TEMPLATE_CAPACITY: usize = 64;
+// Offset of the sequence id in the header of a serialized message
+const SEQUENCE_ID_OFFSET: usize = 30;
+// Offset of the origin timestamp, right after the header
+const ORIGIN_TIMESTAMP_OFFSET: usize = 34;
+
+// Serialized announce message for unicast clients, which only needs a new
+// sequence id and origin timestamp as long as the rest of the message stays
+// the same
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct AnnounceTemplate {
+    // The message it was serialized from, with a zero sequence id and origin
+    // timestamp
+    message: AnnounceMessage,
+    profile_identifier: Option<ProfileIdentifier>,
+    data: ArrayVec<u8, MAX_DATA_LEN>,
+}
+
+impl AnnounceTemplate {
+    fn new(
+        message: &AnnounceMessage,
+        profile_identifier: Option<ProfileIdentifier>,
+        data: &[u8],
+    ) -> Option<Self> {
+        Some(Self {
+            message: Self::normalize(message),
+            profile_identifier,
+            data: data.try_into().ok()?,
+        })
+    }
+
+    fn normalize(message: &AnnounceMessage) -> AnnounceMessage {
+        let mut message = *message;
+        message.header.sequence_id = 0;
+        message.origin_timestamp = WireTimestamp::default();
+        message
+    }
+
+    fn matches(
+        &self,
+        message: &AnnounceMessage,
+        profile_identifier: Option<ProfileIdentifier>,
+    ) -> bool {
+        self.message == Self::normalize(message) && self.profile_identifier == profile_identifier
+    }
+
+    // Write the message into the buffer, returning its length
+    fn fill(&self, message: &AnnounceMessage, buffer: &mut [u8]) -> Option<usize> {
+        let length = self.data.len();
+        buffer.get_mut(..length)?.copy_from_slice(&self.data);
+        buffer[SEQUENCE_ID_OFFSET..SEQUENCE_ID_OFFSET + 2]
+            .copy_from_slice(&message.header.sequence_id.to_be_bytes());
+        message
+            .origin_timestamp
+            .serialize(&mut buffer[ORIGIN_TIMESTAMP_OFFSET..ORIGIN_TIMESTAMP_OFFSET + 10])
+            .ok()?;
+        Some(length)
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub(crate) struct MasterState {
     pub(in crate::port) announce_seq_ids: SequenceIdGenerator,
     pub(in crate::port) sync_seq_ids: SequenceIdGenerator,
+    // The last announce message we sent
+    last_announce: Option<AnnounceContent>,
+    // Whether the datasets were changed on purpose since then, so the next
+    // announce message may differ from it in any way
+    datasets_changed: bool,
+    // Serialized sync message for unicast clients, of which only the
+    // sequence id differs between clients
+    unicast_sync_template: ArrayVec<u8, SYNC_TEMPLATE_CAPACITY>,
+    // Saves serializing the same announce message for every unicast client
+    unicast_announce_template: Option<AnnounceTemplate>,
+    // When the last scheduled sync message was to be sent
+    last_sync_transmit: Option<Time>,
+    // Problem noticed while handling the last event, for the port to report
+    pub(in crate::port) diagnostic: Option<Diagnostic>,
 }
 
 impl MasterState {
@@ -29,51 +112,110 @@ impl MasterState {
         MasterState {
             announce_seq_ids: SequenceIdGenerator::new(),
             sync_seq_ids: SequenceIdGenerator::new(),
+            last_announce: None,
+            datasets_changed: false,
+            unicast_sync_template: ArrayVec::new(),
+            unicast_announce_template: None,
+            last_sync_transmit: None,
+            diagnostic: None,
         }
     }
 
+    /// Accept any change in the next announce message, as the datasets it is
+    /// built from were changed on purpose
+    pub(crate) fn expect_dataset_change(&mut self) {
+        self.datasets_changed = true;
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn handle_timestamp<'a>(
         &mut self,
         context: TimestampContext,
         timestamp: Time,
+        config: &PortConfig,
         port_identity: PortIdentity,
         default_ds: &DefaultDS,
+        follow_up_information: Option<FollowUpInformation>,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
         match context.inner {
-            TimestampContextInner::Sync { id } => {
-                self.handle_sync_timestamp(id, timestamp, port_identity, default_ds, buffer)
-            }
+            TimestampContextInner::Sync { id } => self.handle_sync_timestamp(
+                id,
+                None,
+                timestamp,
+                config,
+                port_identity,
+                default_ds,
+                follow_up_information,
+                buffer,
+            ),
+            TimestampContextInner::UnicastSync { id, sync_grant } => self.handle_sync_timestamp(
+                id,
+                Some(sync_grant.client),
+                timestamp,
+                config,
+                port_identity,
+                default_ds,
+                follow_up_information,
+                buffer,
+            ),
             _ => {
-                log::error!("Unexpected send timestamp");
+                self.diagnostic = Some(Diagnostic::UnexpectedTimestamp);
+                log::error!(port: port_identity, "Unexpected send timestamp");
                 actions![]
             }
         }
     }
 
+    // Send the follow up of a sync message, to `client` only if it was sent to
+    // a single unicast client
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn handle_sync_timestamp<'a>(
         &mut self,
         id: u16,
+        client: Option<PortIdentity>,
         timestamp: Time,
+        config: &PortConfig,
         port_identity: PortIdentity,
         default_ds: &DefaultDS,
+        information: Option<FollowUpInformation>,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
-        let packet_length =
-            match Message::follow_up(default_ds, port_identity, id, timestamp).serialize(buffer) {
-                Ok(length) => length,
-                Err(error) => {
-                    log::error!(
-                        "Statime bug: Could not serialize sync follow up {:?}",
-                        error
-                    );
-                    return actions![];
-                }
-            };
+        let mut message = Message::follow_up(
+            default_ds,
+            port_identity,
+            id,
+            timestamp,
+            config.sync_interval,
+            client.is_some(),
+            &config.log_message_intervals,
+        );
+        if let Message::FollowUp(follow_up) = &mut message {
+            follow_up.information = information;
+        }
+
+        let packet_length = match message.serialize(buffer) {
+            Ok(length) => length,
+            Err(error) => {
+                self.diagnostic = Some(Diagnostic::SerializationFailed);
+                log::error!(
+                    port: port_identity,
+                    "Statime bug: Could not serialize sync follow up {:?}",
+                    error
+                );
+                return actions![];
+            }
+        };
 
-        actions![PortAction::SendGeneral {
-            data: &buffer[..packet_length],
-        }]
+        let data = &buffer[..packet_length];
+        match client {
+            Some(client) => actions![PortAction::SendUnicastGeneral {
+                data,
+                clock_identity: client.clock_identity,
+                port_number: client.port_number,
+            }],
+            None => actions![PortAction::SendGeneral { data }],
+        }
     }
 
     pub(crate) fn send_sync<'a>(
@@ -82,25 +224,56 @@ impl MasterState {
         config: &PortConfig,
         port_identity: PortIdentity,
         default_ds: &DefaultDS,
+        transmit_lead: Option<Duration>,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
-        log::trace!("sending sync message");
+        if !config.transmit.sync {
+            return actions![];
+        }
+
+        log::trace!(port: port_identity, "sending sync message");
 
         let current_time = match local_clock.try_borrow().map(|borrow| borrow.now()) {
             Ok(time) => time,
             Err(error) => {
-                log::error!("Statime bug: Clock busy {:?}", error);
+                self.diagnostic = Some(Diagnostic::InternalError);
+                log::error!(port: port_identity, "Statime bug: Clock busy {:?}", error);
                 return actions![];
             }
         };
 
+        // Keep scheduled syncs one interval apart, unless the timer expired
+        // too late to make that
+        let transmit_at = transmit_lead.map(|lead| {
+            let earliest = current_time + lead;
+            let transmit_at = match self.last_sync_transmit {
+                Some(last) => (last + config.sync_interval.as_duration()).max(earliest),
+                None => earliest,
+            };
+            self.last_sync_transmit = Some(transmit_at);
+            transmit_at
+        });
+
         let seq_id = self.sync_seq_ids.generate();
-        let packet_length = match Message::sync(default_ds, port_identity, seq_id, current_time)
-            .serialize(buffer)
-        {
+        let origin_timestamp = transmit_at.unwrap_or(current_time);
+        let message = Message::sync(
+            default_ds,
+            port_identity,
+            seq_id,
+            origin_timestamp,
+            config.sync_interval,
+            false,
+            &config.log_message_intervals,
+        );
+        let packet_length = match message.serialize(buffer) {
             Ok(message) => message,
             Err(error) => {
-                log::error!("Statime bug: Could not serialize sync: {:?}", error);
+                self.diagnostic = Some(Diagnostic::SerializationFailed);
+                log::error!(
+                    port: port_identity,
+                    "Statime bug: Could not serialize sync: {:?}",
+                    error
+                );
                 return actions![];
             }
         };
@@ -114,43 +287,41 @@ impl MasterState {
                     inner: TimestampContextInner::Sync { id: seq_id },
                 },
                 data: &buffer[..packet_length],
+                transmit_at,
             }
         ]
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn send_announce<'a, C: Clock, F>(
         &mut self,
         global: &PtpInstanceState<C, F>,
         config: &PortConfig,
         port_identity: PortIdentity,
+        events: &mut EventQueue,
+        statistics: &mut PortStatistics,
+        extension: Option<&dyn OrganizationExtension>,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
-        log::trace!("sending announce message");
+        if !config.transmit.announce {
+            return actions![];
+        }
 
-        let current_time = match global.local_clock.try_borrow().map(|borrow| borrow.now()) {
-            Ok(time) => time,
-            Err(error) => {
-                log::error!("Statime bug: clock busy {:?}", error);
-                return actions![];
-            }
-        };
+        log::trace!(port: port_identity, "sending announce message");
 
-        let packet_length = match Message::announce(
+        let seq_id = self.announce_seq_ids.generate();
+        let Some(packet_length) = self.serialize_announce(
             global,
+            config,
             port_identity,
-            self.announce_seq_ids.generate(),
-            current_time,
-        )
-        .serialize(buffer)
-        {
-            Ok(length) => length,
-            Err(error) => {
-                log::error!(
-                    "Statime bug: Could not serialize announce message {:?}",
-                    error
-                );
-                return actions![];
-            }
+            seq_id,
+            false,
+            events,
+            statistics,
+            extension,
+            buffer,
+        ) else {
+            return actions![];
         };
 
         actions![
@@ -163,12 +334,268 @@ impl MasterState {
         ]
     }
 
+    /// Send a sync message to a single client that was granted them, with the
+    /// time until the next sync message to any client
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn send_unicast_sync<'a>(
+        &mut self,
+        config: &PortConfig,
+        port_identity: PortIdentity,
+        default_ds: &DefaultDS,
+        sync_grant: SyncGrant,
+        seq_id: u16,
+        next_sync: core::time::Duration,
+        buffer: &'a mut [u8],
+    ) -> PortActionIterator<'a> {
+        if !config.transmit.sync {
+            return actions![];
+        }
+
+        let client = sync_grant.client;
+        log::trace!(port: port_identity, "sending sync message to {:?}", client);
+
+        if self.unicast_sync_template.is_empty() {
+            // This is a two-step sync, so the precise origin timestamp follows
+            // in the follow up, and a zero origin timestamp is allowed here
+            let message = Message::sync(
+                default_ds,
+                port_identity,
+                0,
+                Time::from_nanos(0),
+                config.sync_interval,
+                true,
+                &config.log_message_intervals,
+            );
+
+            let mut template = [0; SYNC_TEMPLATE_CAPACITY];
+            match message.serialize(&mut template) {
+                Ok(length) => self
+                    .unicast_sync_template
+                    .extend(template[..length].iter().copied()),
+                Err(error) => {
+                    self.diagnostic = Some(Diagnostic::SerializationFailed);
+                    log::error!(
+                        port: port_identity,
+                        "Statime bug: Could not serialize sync: {:?}",
+                        error
+                    );
+                    return actions![];
+                }
+            }
+        }
+
+        let packet_length = self.unicast_sync_template.len();
+        buffer[..packet_length].copy_from_slice(&self.unicast_sync_template);
+        buffer[SEQUENCE_ID_OFFSET..SEQUENCE_ID_OFFSET + 2].copy_from_slice(&seq_id.to_be_bytes());
+
+        actions![
+            PortAction::ResetSyncTimer {
+                duration: next_sync,
+            },
+            PortAction::SendUnicastTimeCritical {
+                context: TimestampContext {
+                    inner: TimestampContextInner::UnicastSync {
+                        id: seq_id,
+                        sync_grant
+                    },
+                },
+                data: &buffer[..packet_length],
+                clock_identity: client.clock_identity,
+                port_number: client.port_number,
+            }
+        ]
+    }
+
+    /// Send an announce message to a single client that was granted them,
+    /// with the time until the next announce message to any client
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn send_unicast_announce<'a, C: Clock, F>(
+        &mut self,
+        global: &PtpInstanceState<C, F>,
+        config: &PortConfig,
+        port_identity: PortIdentity,
+        client: PortIdentity,
+        seq_id: u16,
+        next_announce: core::time::Duration,
+        events: &mut EventQueue,
+        statistics: &mut PortStatistics,
+        extension: Option<&dyn OrganizationExtension>,
+        buffer: &'a mut [u8],
+    ) -> PortActionIterator<'a> {
+        if !config.transmit.announce {
+            return actions![];
+        }
+
+        log::trace!(port: port_identity, "sending announce message to {:?}", client);
+
+        let Some(packet_length) = self.serialize_announce(
+            global,
+            config,
+            port_identity,
+            seq_id,
+            true,
+            events,
+            statistics,
+            extension,
+            buffer,
+        ) else {
+            return actions![];
+        };
+
+        actions![
+            PortAction::ResetAnnounceTimer {
+                duration: next_announce,
+            },
+            PortAction::SendUnicastGeneral {
+                data: &buffer[..packet_length],
+                clock_identity: client.clock_identity,
+                port_number: client.port_number,
+            }
+        ]
+    }
+
+    // Serialize an announce message with sequence id `seq_id` into the buffer,
+    // returning its length
+    #[allow(clippy::too_many_arguments)]
+    fn serialize_announce<C: Clock, F>(
+        &mut self,
+        global: &PtpInstanceState<C, F>,
+        config: &PortConfig,
+        port_identity: PortIdentity,
+        seq_id: u16,
+        unicast: bool,
+        events: &mut EventQueue,
+        statistics: &mut PortStatistics,
+        extension: Option<&dyn OrganizationExtension>,
+        buffer: &mut [u8],
+    ) -> Option<usize> {
+        let current_time = match global.local_clock.try_borrow().map(|borrow| borrow.now()) {
+            Ok(time) => time,
+            Err(error) => {
+                self.diagnostic = Some(Diagnostic::InternalError);
+                log::error!(port: port_identity, "Statime bug: clock busy {:?}", error);
+                return None;
+            }
+        };
+
+        let time_properties_ds = match global.announced_time_properties(current_time) {
+            Ok(time_properties_ds) => time_properties_ds,
+            Err(error) => {
+                self.diagnostic = Some(Diagnostic::InternalError);
+                log::error!(port: port_identity, "Statime bug: leap second busy {:?}", error);
+                global.time_properties_ds
+            }
+        };
+        let message = Message::announce(
+            global,
+            &time_properties_ds,
+            port_identity,
+            seq_id,
+            current_time,
+            config.announce_interval,
+            unicast,
+            &config.log_message_intervals,
+        );
+        let Message::Announce(announce) = message else {
+            unreachable!("Message::announce builds an announce message")
+        };
+        let content = AnnounceContent::new(&announce);
+
+        // Extensions may add different TLVs to every message, so those are
+        // always serialized in full
+        let use_template = unicast && extension.is_none();
+        let from_template = match &self.unicast_announce_template {
+            Some(template) if use_template => template
+                .matches(&announce, global.profile_identifier)
+                .then(|| template.fill(&announce, buffer))
+                .flatten(),
+            _ => None,
+        };
+
+        let length = match from_template {
+            Some(length) => length,
+            None => match message.serialize(buffer) {
+                Ok(length) => {
+                    let length = append_organization_tlvs(
+                        extension,
+                        global.profile_identifier,
+                        MessageType::Announce,
+                        buffer,
+                        length,
+                    );
+                    if use_template {
+                        self.unicast_announce_template = AnnounceTemplate::new(
+                            &announce,
+                            global.profile_identifier,
+                            &buffer[..length],
+                        );
+                    }
+                    length
+                }
+                Err(error) => {
+                    self.diagnostic = Some(Diagnostic::SerializationFailed);
+                    log::error!(
+                        port: port_identity,
+                        "Statime bug: Could not serialize announce message {:?}",
+                        error
+                    );
+                    return None;
+                }
+            },
+        };
+
+        if let Some(previous) = self.last_announce {
+            let mut push = |event| {
+                if events.push(event) {
+                    statistics.events_dropped = statistics.events_dropped.wrapping_add(1);
+                }
+            };
+
+            if previous.leap_indicator != content.leap_indicator {
+                push(PortEvent::AnnouncedLeapIndicator {
+                    previous: previous.leap_indicator,
+                    current: content.leap_indicator,
+                });
+            }
+            if previous.current_utc_offset != content.current_utc_offset {
+                push(PortEvent::AnnouncedUtcOffset {
+                    previous: previous.current_utc_offset,
+                    current: content.current_utc_offset,
+                });
+            }
+
+            let own_identity = global.default_ds.clock_identity;
+            let unexplained_change = match self.datasets_changed {
+                true => None,
+                false => previous.unexplained_change(&content, own_identity),
+            };
+            if let Some(field) = unexplained_change {
+                self.diagnostic = Some(Diagnostic::InternalError);
+                log::error!(
+                    port: port_identity,
+                    "Statime bug: announced {} changed unexpectedly from {:?} to {:?}",
+                    field,
+                    previous,
+                    content
+                );
+            }
+        }
+        self.last_announce = Some(content);
+        self.datasets_changed = false;
+        statistics.last_announce = Some(content);
+
+        Some(length)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn handle_event_receive<'a>(
         &mut self,
         message: Message,
         timestamp: Time,
-        min_delay_req_interval: Interval,
+        config: &PortConfig,
         port_identity: PortIdentity,
+        local_clock: &AtomicRefCell<impl Clock>,
+        statistics: &mut PortStatistics,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
         if message.header().source_port_identity == port_identity {
@@ -178,54 +605,106 @@ impl MasterState {
         match message {
             Message::DelayReq(message) => self.handle_delay_req(
                 message,
-                timestamp,
-                min_delay_req_interval,
+                timestamp - config.ingress_latency,
+                config,
                 port_identity,
+                local_clock,
+                statistics,
                 buffer,
             ),
             _ => {
-                log::warn!("Unexpected message {:?}", message);
+                self.diagnostic = Some(Diagnostic::UnexpectedMessage);
+                log::warn!(
+                    port: port_identity,
+                    "Unexpected {:?} message",
+                    message.content_type()
+                );
                 actions![]
             }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn handle_delay_req<'a>(
         &mut self,
         message: DelayReqMessage,
         timestamp: Time,
-        min_delay_req_interval: Interval,
+        config: &PortConfig,
         port_identity: PortIdentity,
+        local_clock: &AtomicRefCell<impl Clock>,
+        statistics: &mut PortStatistics,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
-        log::debug!("Received DelayReq");
-        let delay_resp_message =
-            Message::delay_resp(&message, port_identity, min_delay_req_interval, timestamp);
+        log::debug!(port: port_identity, "Received DelayReq");
+        if !config.transmit.delay_resp {
+            return actions![];
+        }
+
+        match config.delay_mechanism {
+            DelayMechanism::E2E { .. } => {}
+            DelayMechanism::P2P { .. } | DelayMechanism::CommonP2P => {
+                log::debug!(port: port_identity, "Ignoring DelayReq on a peer to peer port");
+                return actions![];
+            }
+            DelayMechanism::OneWay { .. } => {
+                log::debug!(port: port_identity, "Ignoring DelayReq in one-way operation");
+                return actions![];
+            }
+        }
+
+        let delay_resp_message = Message::delay_resp(
+            &message,
+            port_identity,
+            config.min_delay_req_interval(),
+            timestamp,
+            &config.log_message_intervals,
+        );
 
         let packet_length = match delay_resp_message.serialize(buffer) {
             Ok(length) => length,
             Err(error) => {
-                log::error!("Could not serialize delay response: {:?}", error);
+                self.diagnostic = Some(Diagnostic::SerializationFailed);
+                log::error!(port: port_identity, "Could not serialize delay response: {:?}", error);
                 return actions![];
             }
         };
 
-        actions![PortAction::SendGeneral {
-            data: &buffer[..packet_length],
-        }]
+        // The response is sent as a general message, so we don't get a send
+        // timestamp. The moment it is handed to the runtime is the best we can do.
+        match local_clock.try_borrow().map(|borrow| borrow.now()) {
+            Ok(time) => statistics.delay_resp_turnaround.record(time - timestamp),
+            Err(error) => {
+                self.diagnostic = Some(Diagnostic::InternalError);
+                log::error!(port: port_identity, "Statime bug: Clock busy {:?}", error);
+            }
+        }
+
+        // The response copies the unicast flag of the request, and a unicast
+        // request is answered at the address of the client
+        let data = &buffer[..packet_length];
+        if message.header.unicast_flag {
+            let client = message.header.source_port_identity;
+            actions![PortAction::SendUnicastGeneral {
+                data,
+                clock_identity: client.clock_identity,
+                port_number: client.port_number,
+            }]
+        } else {
+            actions![PortAction::SendGeneral { data }]
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use fixed::types::{I48F16, U96F32};
+    use fixed::types::{I48F16, I96F32, U96F32};
 
     use super::*;
     use crate::{
         config::InstanceConfig,
         datastructures::{
-            common::{ClockIdentity, TimeInterval},
-            datasets::{CurrentDS, ParentDS},
+            common::{ClockIdentity, LeapIndicator, TimeInterval, TimeSource},
+            datasets::LeapSecond,
             messages::{Header, SdoId},
         },
         time::Interval,
@@ -253,9 +732,33 @@ mod tests {
         }
     }
 
+    fn delay_config(log_delay_req_interval: i8) -> PortConfig {
+        PortConfig {
+            delay_mechanism: crate::DelayMechanism::E2E {
+                interval: Interval::from_log_2(log_delay_req_interval),
+            },
+            announce_interval: Interval::TWO_SECONDS,
+            announce_receipt_timeout: 2,
+            sync_interval: Interval::ONE_SECOND,
+            master_only: false,
+            delay_asymmetry: Duration::ZERO,
+            ingress_latency: Duration::ZERO,
+            egress_latency: Duration::ZERO,
+            management: Default::default(),
+            communication_mode: Default::default(),
+            transmit: Default::default(),
+            unicast_client: None,
+            log_message_intervals: Default::default(),
+        }
+    }
+
     #[test]
     fn test_delay_response() {
         let mut state = MasterState::new();
+        let clock = AtomicRefCell::new(TestClock {
+            current_time: Time::from_micros(230),
+        });
+        let mut statistics = PortStatistics::default();
 
         let mut buffer = [0u8; MAX_DATA_LEN];
 
@@ -273,8 +776,10 @@ mod tests {
                 origin_timestamp: Time::from_micros(0).into(),
             }),
             Time::from_fixed_nanos(U96F32::from_bits((200000 << 32) + (500 << 16))),
-            Interval::from_log_2(2),
+            &delay_config(2),
             PortIdentity::default(),
+            &clock,
+            &mut statistics,
             &mut buffer,
         );
 
@@ -318,8 +823,10 @@ mod tests {
                 origin_timestamp: Time::from_micros(0).into(),
             }),
             Time::from_fixed_nanos(U96F32::from_bits((220000 << 32) + (300 << 16))),
-            Interval::from_log_2(5),
+            &delay_config(5),
             PortIdentity::default(),
+            &clock,
+            &mut statistics,
             &mut buffer,
         );
 
@@ -347,6 +854,19 @@ mod tests {
             msg.header.correction_field,
             TimeInterval(I48F16::from_bits(500))
         );
+        drop(action);
+
+        let turnaround = statistics.delay_resp_turnaround;
+        assert_eq!(turnaround.count, 2);
+        assert_eq!(
+            turnaround.last,
+            Duration::from_fixed_nanos(I96F32::from_bits((10000 << 32) - (300 << 16)))
+        );
+        assert_eq!(turnaround.min, turnaround.last);
+        assert_eq!(
+            turnaround.max,
+            Duration::from_fixed_nanos(I96F32::from_bits((30000 << 32) - (500 << 16)))
+        );
     }
 
     #[test]
@@ -361,20 +881,17 @@ mod tests {
             slave_only: false,
             sdo_id: SdoId::default(),
         });
-        let mut parent_ds = ParentDS::new(default_ds);
-        parent_ds.grandmaster_priority_1 = 15;
-        let current_ds = CurrentDS::default();
-        let time_properties_ds = TimePropertiesDS::default();
-        let global = PtpInstanceState {
+        let mut global = PtpInstanceState::new(
             default_ds,
-            current_ds,
-            parent_ds,
-            time_properties_ds,
-            local_clock: AtomicRefCell::new(TestClock {
+            TimePropertiesDS::default(),
+            TestClock {
                 current_time: Time::from_micros(600),
-            }),
-            filter: AtomicRefCell::new(()),
-        };
+            },
+            (),
+        );
+        global.parent_ds.grandmaster_priority_1 = 15;
+        let mut events = EventQueue::default();
+        let mut statistics = PortStatistics::default();
 
         let config = PortConfig {
             delay_mechanism: crate::DelayMechanism::E2E {
@@ -385,11 +902,25 @@ mod tests {
             sync_interval: Interval::ONE_SECOND,
             master_only: false,
             delay_asymmetry: Duration::ZERO,
+            ingress_latency: Duration::ZERO,
+            egress_latency: Duration::ZERO,
+            management: Default::default(),
+            communication_mode: Default::default(),
+            transmit: Default::default(),
+            unicast_client: None,
+            log_message_intervals: Default::default(),
         };
         let mut state = MasterState::new();
 
-        let mut actions =
-            state.send_announce(&global, &config, PortIdentity::default(), &mut buffer);
+        let mut actions = state.send_announce(
+            &global,
+            &config,
+            PortIdentity::default(),
+            &mut events,
+            &mut statistics,
+            None,
+            &mut buffer,
+        );
 
         assert!(matches!(
             actions.next(),
@@ -407,9 +938,17 @@ mod tests {
         };
 
         assert_eq!(msg.grandmaster_priority_1, 15);
+        assert_eq!(msg.header.log_message_interval, 1);
 
-        let mut actions =
-            state.send_announce(&global, &config, PortIdentity::default(), &mut buffer);
+        let mut actions = state.send_announce(
+            &global,
+            &config,
+            PortIdentity::default(),
+            &mut events,
+            &mut statistics,
+            None,
+            &mut buffer,
+        );
 
         assert!(matches!(
             actions.next(),
@@ -427,6 +966,361 @@ mod tests {
 
         assert_eq!(msg2.grandmaster_priority_1, 15);
         assert_ne!(msg2.header.sequence_id, msg.header.sequence_id);
+
+        let last_announce = statistics.last_announce.unwrap();
+        assert_eq!(last_announce.sequence_id, msg2.header.sequence_id);
+        assert_eq!(last_announce.grandmaster_priority_1, 15);
+        assert_eq!(state.diagnostic, None);
+
+        // Our own priority 2 can't change, so this must be a bug
+        global.parent_ds.grandmaster_priority_2 = 1;
+        drop(state.send_announce(
+            &global,
+            &config,
+            PortIdentity::default(),
+            &mut events,
+            &mut statistics,
+            None,
+            &mut [0; MAX_DATA_LEN],
+        ));
+        assert_eq!(state.diagnostic, Some(Diagnostic::InternalError));
+        assert_eq!(statistics.last_announce.unwrap().grandmaster_priority_2, 1);
+    }
+
+    #[test]
+    fn test_unicast_announce_template() {
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity::default(),
+            priority_1: 15,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut global = PtpInstanceState::new(
+            default_ds,
+            TimePropertiesDS::default(),
+            TestClock {
+                current_time: Time::from_micros(600),
+            },
+            (),
+        );
+        let mut events = EventQueue::default();
+        let mut statistics = PortStatistics::default();
+        let config = delay_config(0);
+        let client = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let mut state = MasterState::new();
+
+        let mut send = |global: &PtpInstanceState<TestClock, ()>, seq_id| {
+            let mut buffer = [0u8; MAX_DATA_LEN];
+            let mut actions = state.send_unicast_announce(
+                global,
+                &config,
+                PortIdentity::default(),
+                client,
+                seq_id,
+                core::time::Duration::from_secs(1),
+                &mut events,
+                &mut statistics,
+                None,
+                &mut buffer,
+            );
+            assert!(matches!(
+                actions.next(),
+                Some(PortAction::ResetAnnounceTimer { .. })
+            ));
+            let Some(PortAction::SendUnicastGeneral { data, .. }) = actions.next() else {
+                panic!("Unexpected action");
+            };
+            match Message::deserialize(data).unwrap() {
+                Message::Announce(msg) => msg,
+                _ => panic!("Unexpected message type"),
+            }
+        };
+
+        let first = send(&global, 7);
+        assert!(first.header.unicast_flag);
+        assert_eq!(first.header.sequence_id, 7);
+
+        // The second message comes from the template, with its own sequence
+        // id and timestamp
+        global.local_clock.borrow_mut().current_time = Time::from_secs(2);
+        let second = send(&global, 8);
+        assert_eq!(second.header.sequence_id, 8);
+        assert_eq!(second.origin_timestamp, Time::from_secs(2).into());
+        assert_eq!(
+            AnnounceTemplate::normalize(&second),
+            AnnounceTemplate::normalize(&first)
+        );
+
+        // Other changes need a new template
+        global.parent_ds.grandmaster_priority_1 = 10;
+        let third = send(&global, 9);
+        assert_eq!(third.grandmaster_priority_1, 10);
+        assert_eq!(third.header.sequence_id, 9);
+    }
+
+    #[test]
+    fn test_announce_leap_second() {
+        let mut buffer = [0u8; MAX_DATA_LEN];
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity::default(),
+            priority_1: 15,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut global = PtpInstanceState::new(
+            default_ds,
+            TimePropertiesDS::new_ptp_time(
+                Some(37),
+                LeapIndicator::NoLeap,
+                true,
+                true,
+                TimeSource::Gnss,
+            ),
+            TestClock {
+                current_time: Time::from_secs(10_000),
+            },
+            (),
+        );
+        *global.leap_second.get_mut() = Some(LeapSecond {
+            at: Time::from_secs(100_000),
+            leap_indicator: LeapIndicator::Leap61,
+        });
+        let config = delay_config(2);
+        let mut events = EventQueue::default();
+        let mut statistics = PortStatistics::default();
+        let mut state = MasterState::new();
+
+        let mut announce =
+            |global: &mut PtpInstanceState<TestClock, ()>, events: &mut EventQueue, secs| {
+                global.local_clock.get_mut().current_time = Time::from_secs(secs);
+                let mut actions = state.send_announce(
+                    global,
+                    &config,
+                    PortIdentity::default(),
+                    events,
+                    &mut statistics,
+                    None,
+                    &mut buffer,
+                );
+                actions.next();
+                let Some(PortAction::SendGeneral { data }) = actions.next() else {
+                    panic!("Unexpected action");
+                };
+                match Message::deserialize(data).unwrap() {
+                    Message::Announce(msg) => msg,
+                    _ => panic!("Unexpected message type"),
+                }
+            };
+
+        // More than 12 hours before the leap second nothing is announced yet
+        let msg = announce(&mut global, &mut events, 10_000);
+        assert!(!msg.header.leap61);
+        assert_eq!(msg.current_utc_offset, 37);
+
+        let msg = announce(&mut global, &mut events, 60_000);
+        assert!(msg.header.leap61);
+        assert_eq!(msg.current_utc_offset, 37);
+
+        let msg = announce(&mut global, &mut events, 100_001);
+        assert!(!msg.header.leap61);
+        assert!(msg.header.current_utc_offset_valid);
+        assert_eq!(msg.current_utc_offset, 38);
+
+        assert_eq!(
+            events.pop(),
+            Some(PortEvent::AnnouncedLeapIndicator {
+                previous: LeapIndicator::NoLeap,
+                current: LeapIndicator::Leap61,
+            })
+        );
+        assert_eq!(
+            events.pop(),
+            Some(PortEvent::AnnouncedLeapIndicator {
+                previous: LeapIndicator::Leap61,
+                current: LeapIndicator::NoLeap,
+            })
+        );
+        assert_eq!(
+            events.pop(),
+            Some(PortEvent::AnnouncedUtcOffset {
+                previous: Some(37),
+                current: Some(38),
+            })
+        );
+        assert_eq!(events.pop(), None);
+
+        // Only the grandmaster announces its own leap seconds
+        global.parent_ds.grandmaster_identity = ClockIdentity([1; 8]);
+        let msg = announce(&mut global, &mut events, 60_000);
+        assert!(!msg.header.leap61);
+        assert_eq!(msg.current_utc_offset, 37);
+    }
+
+    #[test]
+    fn test_transmit_disabled() {
+        let mut buffer = [0u8; MAX_DATA_LEN];
+        let config = PortConfig {
+            transmit: crate::TransmitEnable::NONE,
+            ..delay_config(2)
+        };
+
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity::default(),
+            priority_1: 15,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let global = PtpInstanceState::new(
+            default_ds,
+            TimePropertiesDS::default(),
+            TestClock {
+                current_time: Time::from_micros(600),
+            },
+            (),
+        );
+        let mut statistics = PortStatistics::default();
+        let mut state = MasterState::new();
+
+        assert!(state
+            .send_sync(
+                &global.local_clock,
+                &config,
+                PortIdentity::default(),
+                &default_ds,
+                None,
+                &mut buffer
+            )
+            .next()
+            .is_none());
+
+        assert!(state
+            .send_announce(
+                &global,
+                &config,
+                PortIdentity::default(),
+                &mut EventQueue::default(),
+                &mut statistics,
+                None,
+                &mut buffer
+            )
+            .next()
+            .is_none());
+
+        assert!(state
+            .handle_event_receive(
+                Message::DelayReq(DelayReqMessage {
+                    header: Header {
+                        source_port_identity: PortIdentity {
+                            port_number: 83,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                    origin_timestamp: Time::from_micros(0).into(),
+                }),
+                Time::from_micros(500),
+                &config,
+                PortIdentity::default(),
+                &global.local_clock,
+                &mut statistics,
+                &mut buffer,
+            )
+            .next()
+            .is_none());
+        assert_eq!(statistics.delay_resp_turnaround.count, 0);
+    }
+
+    #[test]
+    fn test_one_way_ignores_delay_req() {
+        let mut buffer = [0u8; MAX_DATA_LEN];
+        let config = PortConfig {
+            delay_mechanism: DelayMechanism::OneWay {
+                path_delay: Duration::ZERO,
+            },
+            ..delay_config(2)
+        };
+        let clock = AtomicRefCell::new(TestClock {
+            current_time: Time::from_micros(600),
+        });
+        let mut statistics = PortStatistics::default();
+        let mut state = MasterState::new();
+
+        let mut actions = state.handle_event_receive(
+            Message::DelayReq(DelayReqMessage {
+                header: Header {
+                    source_port_identity: PortIdentity {
+                        port_number: 83,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                origin_timestamp: Time::from_micros(0).into(),
+            }),
+            Time::from_micros(500),
+            &config,
+            PortIdentity::default(),
+            &clock,
+            &mut statistics,
+            &mut buffer,
+        );
+        assert!(actions.next().is_none());
+    }
+
+    #[test]
+    fn test_unicast_delay_response() {
+        let mut buffer = [0u8; MAX_DATA_LEN];
+        let clock = AtomicRefCell::new(TestClock {
+            current_time: Time::from_micros(600),
+        });
+        let mut statistics = PortStatistics::default();
+        let mut state = MasterState::new();
+        let client = PortIdentity {
+            clock_identity: ClockIdentity([7; 8]),
+            port_number: 83,
+        };
+
+        let mut actions = state.handle_event_receive(
+            Message::DelayReq(DelayReqMessage {
+                header: Header {
+                    source_port_identity: client,
+                    unicast_flag: true,
+                    ..Default::default()
+                },
+                origin_timestamp: Time::from_micros(0).into(),
+            }),
+            Time::from_micros(500),
+            &delay_config(2),
+            PortIdentity::default(),
+            &clock,
+            &mut statistics,
+            &mut buffer,
+        );
+
+        let Some(PortAction::SendUnicastGeneral {
+            data,
+            clock_identity,
+            port_number,
+        }) = actions.next()
+        else {
+            panic!("Unexpected resulting action");
+        };
+        assert_eq!(clock_identity, client.clock_identity);
+        assert_eq!(port_number, client.port_number);
+        let Message::DelayResp(response) = Message::deserialize(data).unwrap() else {
+            panic!("Unexpected message type");
+        };
+        assert!(response.header.unicast_flag);
+        assert_eq!(response.requesting_port_identity, client);
     }
 
     #[test]
@@ -441,6 +1335,13 @@ mod tests {
             sync_interval: Interval::ONE_SECOND,
             master_only: false,
             delay_asymmetry: crate::Duration::ZERO,
+            ingress_latency: crate::Duration::ZERO,
+            egress_latency: crate::Duration::ZERO,
+            management: Default::default(),
+            communication_mode: Default::default(),
+            transmit: Default::default(),
+            unicast_client: None,
+            log_message_intervals: Default::default(),
         };
 
         let clock = AtomicRefCell::new(TestClock {
@@ -462,6 +1363,7 @@ mod tests {
             &config,
             PortIdentity::default(),
             &defaultds,
+            None,
             &mut buffer,
         );
 
@@ -469,7 +1371,7 @@ mod tests {
             actions.next(),
             Some(PortAction::ResetSyncTimer { .. })
         ));
-        let Some(PortAction::SendTimeCritical { context, data }) = actions.next() else {
+        let Some(PortAction::SendTimeCritical { context, data, .. }) = actions.next() else {
             panic!("Unexpected action");
         };
         assert!(actions.next().is_none());
@@ -483,8 +1385,10 @@ mod tests {
         let mut actions = state.handle_timestamp(
             context,
             Time::from_fixed_nanos(U96F32::from_bits((601300 << 32) + (230 << 16))),
+            &config,
             PortIdentity::default(),
             &defaultds,
+            None,
             &mut buffer,
         );
 
@@ -521,6 +1425,7 @@ mod tests {
             &config,
             PortIdentity::default(),
             &defaultds,
+            None,
             &mut buffer,
         );
 
@@ -528,7 +1433,7 @@ mod tests {
             actions.next(),
             Some(PortAction::ResetSyncTimer { .. })
         ));
-        let Some(PortAction::SendTimeCritical { context, data }) = actions.next() else {
+        let Some(PortAction::SendTimeCritical { context, data, .. }) = actions.next() else {
             panic!("Unexpected action");
         };
         assert!(actions.next().is_none());
@@ -539,11 +1444,19 @@ mod tests {
             _ => panic!("Unexpected message type"),
         };
 
+        // A runtime relaying 802.1AS time adds the FOLLOW_UP information TLV
+        let information = FollowUpInformation {
+            cumulative_scaled_rate_offset: 1000,
+            gm_time_base_indicator: 2,
+            ..Default::default()
+        };
         let mut actions = state.handle_timestamp(
             context,
             Time::from_fixed_nanos(U96F32::from_bits((1000601300 << 32) + (543 << 16))),
+            &config,
             PortIdentity::default(),
             &defaultds,
+            Some(information),
             &mut buffer,
         );
 
@@ -572,5 +1485,61 @@ mod tests {
             follow2.header.correction_field,
             TimeInterval(I48F16::from_bits(543))
         );
+        assert_eq!(follow.information, None);
+        assert_eq!(follow2.information, Some(information));
+    }
+
+    #[test]
+    fn test_sync_transmit_lead() {
+        let mut buffer = [0u8; MAX_DATA_LEN];
+        let config = delay_config(0);
+        let clock = AtomicRefCell::new(TestClock {
+            current_time: Time::from_secs(10),
+        });
+        let defaultds = DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity::default(),
+            priority_1: 15,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        });
+        let mut state = MasterState::new();
+
+        let mut send_sync = |now: Time| {
+            clock.borrow_mut().current_time = now;
+            let actions = state.send_sync(
+                &clock,
+                &config,
+                PortIdentity::default(),
+                &defaultds,
+                Some(Duration::from_millis(2)),
+                &mut buffer,
+            );
+            actions
+                .filter_map(|action| match action {
+                    PortAction::SendTimeCritical {
+                        data, transmit_at, ..
+                    } => Some((Message::deserialize(data).unwrap(), transmit_at)),
+                    _ => None,
+                })
+                .next()
+                .unwrap()
+        };
+
+        let (sync, transmit_at) = send_sync(Time::from_secs(10));
+        assert_eq!(transmit_at, Some(Time::from_millis(10_002)));
+        let Message::Sync(sync) = sync else {
+            panic!("Unexpected message type");
+        };
+        assert_eq!(sync.origin_timestamp, Time::from_millis(10_002).into());
+
+        // A timer that expires early doesn't move the sync
+        let (_, transmit_at) = send_sync(Time::from_micros(10_999_500));
+        assert_eq!(transmit_at, Some(Time::from_millis(11_002)));
+
+        // One that expires too late does
+        let (_, transmit_at) = send_sync(Time::from_millis(12_500));
+        assert_eq!(transmit_at, Some(Time::from_millis(12_502)));
     }
 }
@@ -3,12 +3,22 @@ use core::fmt::{Display, Formatter};
 use atomic_refcell::AtomicRefCell;
 use rand::Rng;
 
-use super::{Measurement, PortActionIterator, TimestampContext};
+use super::{
+    event::{EventQueue, PortStateKind},
+    organization::OrganizationExtension,
+    unicast::SyncGrant,
+    Diagnostic, Measurement, PortActionIterator, PortStatistics, TimestampContext, TimestampSource,
+};
 use crate::{
     clock::Clock,
-    datastructures::{common::PortIdentity, datasets::DefaultDS, messages::Message},
+    datastructures::{
+        common::{FollowUpInformation, PortIdentity},
+        datasets::DefaultDS,
+        messages::Message,
+    },
+    log,
     ptp_instance::PtpInstanceState,
-    time::{Interval, Time},
+    time::{Duration, Time},
     PortConfig,
 };
 
@@ -25,56 +35,85 @@ pub(crate) enum PortState {
     Master(MasterState),
     Passive,
     Slave(SlaveState),
+    Faulty,
+    // Turned off with a DISABLE_PORT management command
+    Disabled,
 }
 
 impl PortState {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn handle_timestamp<'a>(
         &mut self,
         context: TimestampContext,
         timestamp: Time,
+        source: TimestampSource,
+        config: &PortConfig,
         port_identity: PortIdentity,
         default_ds: &DefaultDS,
+        follow_up_information: Option<FollowUpInformation>,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
         match self {
-            PortState::Slave(slave) => slave.handle_timestamp(context, timestamp),
-            PortState::Master(master) => {
-                master.handle_timestamp(context, timestamp, port_identity, default_ds, buffer)
+            PortState::Slave(slave) => slave.handle_timestamp(context, timestamp, source),
+            PortState::Master(master) => master.handle_timestamp(
+                context,
+                timestamp,
+                config,
+                port_identity,
+                default_ds,
+                follow_up_information,
+                buffer,
+            ),
+            PortState::Listening | PortState::Passive | PortState::Faulty | PortState::Disabled => {
+                actions![]
             }
-            PortState::Listening | PortState::Passive => actions![],
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn handle_event_receive<'a>(
         &mut self,
         message: Message,
         timestamp: Time,
-        min_delay_req_interval: Interval,
+        source: TimestampSource,
+        config: &PortConfig,
         port_identity: PortIdentity,
+        local_clock: &AtomicRefCell<impl Clock>,
+        statistics: &mut PortStatistics,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
         match self {
             PortState::Master(master) => master.handle_event_receive(
                 message,
                 timestamp,
-                min_delay_req_interval,
+                config,
                 port_identity,
+                local_clock,
+                statistics,
                 buffer,
             ),
-            PortState::Slave(slave) => slave.handle_event_receive(message, timestamp),
-            PortState::Listening | PortState::Passive => actions![],
+            PortState::Slave(slave) => slave.handle_event_receive(message, timestamp, source),
+            PortState::Listening | PortState::Passive | PortState::Faulty | PortState::Disabled => {
+                actions![]
+            }
         }
     }
 
     pub(crate) fn handle_general_receive(&mut self, message: Message, port_identity: PortIdentity) {
         match self {
-            PortState::Master(_) => {
+            PortState::Master(master) => {
                 if message.header().source_port_identity != port_identity {
-                    log::warn!("Unexpected message {:?}", message);
+                    master.diagnostic = Some(Diagnostic::UnexpectedMessage);
+                    log::warn!(
+                        port: port_identity,
+                        "Unexpected {:?} message",
+                        message.content_type()
+                    );
                 }
             }
             PortState::Slave(slave) => slave.handle_general_receive(message, port_identity),
-            PortState::Listening | PortState::Passive => {}
+            PortState::Listening | PortState::Passive | PortState::Faulty | PortState::Disabled => {
+            }
         }
     }
 
@@ -84,66 +123,258 @@ impl PortState {
         config: &PortConfig,
         port_identity: PortIdentity,
         default_ds: &DefaultDS,
+        transmit_lead: Option<Duration>,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
         match self {
-            PortState::Master(master) => {
-                master.send_sync(local_clock, config, port_identity, default_ds, buffer)
+            PortState::Master(master) => master.send_sync(
+                local_clock,
+                config,
+                port_identity,
+                default_ds,
+                transmit_lead,
+                buffer,
+            ),
+            PortState::Slave(_)
+            | PortState::Listening
+            | PortState::Passive
+            | PortState::Faulty
+            | PortState::Disabled => {
+                actions![]
             }
-            PortState::Slave(_) | PortState::Listening | PortState::Passive => {
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn send_unicast_sync<'a>(
+        &mut self,
+        config: &PortConfig,
+        port_identity: PortIdentity,
+        default_ds: &DefaultDS,
+        sync_grant: SyncGrant,
+        seq_id: u16,
+        next_sync: core::time::Duration,
+        buffer: &'a mut [u8],
+    ) -> PortActionIterator<'a> {
+        match self {
+            PortState::Master(master) => master.send_unicast_sync(
+                config,
+                port_identity,
+                default_ds,
+                sync_grant,
+                seq_id,
+                next_sync,
+                buffer,
+            ),
+            PortState::Slave(_)
+            | PortState::Listening
+            | PortState::Passive
+            | PortState::Faulty
+            | PortState::Disabled => {
                 actions![]
             }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn send_delay_request<'a>(
         &mut self,
         rng: &mut impl Rng,
         port_config: &PortConfig,
         port_identity: PortIdentity,
         default_ds: &DefaultDS,
+        frequency_multiplier: f64,
+        unicast_master: Option<PortIdentity>,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
         match self {
-            PortState::Slave(slave) => {
-                slave.send_delay_request(rng, port_config, port_identity, default_ds, buffer)
-            }
-            PortState::Master(_) | PortState::Listening | PortState::Passive => {
+            PortState::Slave(slave) => slave.send_delay_request(
+                rng,
+                port_config,
+                port_identity,
+                default_ds,
+                frequency_multiplier,
+                unicast_master,
+                buffer,
+            ),
+            PortState::Master(_)
+            | PortState::Listening
+            | PortState::Passive
+            | PortState::Faulty
+            | PortState::Disabled => {
                 actions![]
             }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn send_announce<'a, C: Clock, F>(
         &mut self,
         global: &PtpInstanceState<C, F>,
         config: &PortConfig,
         port_identity: PortIdentity,
+        events: &mut EventQueue,
+        statistics: &mut PortStatistics,
+        extension: Option<&dyn OrganizationExtension>,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
         match self {
-            PortState::Master(master) => {
-                master.send_announce(global, config, port_identity, buffer)
+            PortState::Master(master) => master.send_announce(
+                global,
+                config,
+                port_identity,
+                events,
+                statistics,
+                extension,
+                buffer,
+            ),
+            PortState::Slave(_)
+            | PortState::Listening
+            | PortState::Passive
+            | PortState::Faulty
+            | PortState::Disabled => {
+                actions![]
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn send_unicast_announce<'a, C: Clock, F>(
+        &mut self,
+        global: &PtpInstanceState<C, F>,
+        config: &PortConfig,
+        port_identity: PortIdentity,
+        client: PortIdentity,
+        seq_id: u16,
+        next_announce: core::time::Duration,
+        events: &mut EventQueue,
+        statistics: &mut PortStatistics,
+        extension: Option<&dyn OrganizationExtension>,
+        buffer: &'a mut [u8],
+    ) -> PortActionIterator<'a> {
+        match self {
+            PortState::Master(master) => master.send_unicast_announce(
+                global,
+                config,
+                port_identity,
+                client,
+                seq_id,
+                next_announce,
+                events,
+                statistics,
+                extension,
+                buffer,
+            ),
+            PortState::Slave(_)
+            | PortState::Listening
+            | PortState::Passive
+            | PortState::Faulty
+            | PortState::Disabled => {
+                actions![]
+            }
+        }
+    }
+
+    /// Take the problem noticed while handling the last event, if any
+    pub(crate) fn take_diagnostic(&mut self) -> Option<Diagnostic> {
+        match self {
+            PortState::Master(master) => master.diagnostic.take(),
+            PortState::Slave(slave) => slave.diagnostic.take(),
+            PortState::Listening | PortState::Passive | PortState::Faulty | PortState::Disabled => {
+                None
             }
-            PortState::Slave(_) | PortState::Listening | PortState::Passive => actions![],
+        }
+    }
+
+    /// Add what the state counted since the last call to the statistics
+    pub(crate) fn take_counts(&mut self, statistics: &mut PortStatistics) {
+        if let PortState::Slave(slave) = self {
+            statistics
+                .quirks
+                .add(&core::mem::take(&mut slave.quirks_triggered));
+            statistics
+                .delay_resp_rejections
+                .add(&core::mem::take(&mut slave.delay_resp_rejections));
+            statistics.non_parent_sync_messages = statistics
+                .non_parent_sync_messages
+                .saturating_add(core::mem::take(&mut slave.non_parent_sync_messages));
+        }
+    }
+
+    pub(crate) fn reset_measurements(&mut self) {
+        match self {
+            PortState::Slave(slave) => *slave = slave.restarted(),
+            PortState::Master(_)
+            | PortState::Listening
+            | PortState::Passive
+            | PortState::Faulty
+            | PortState::Disabled => {}
+        }
+    }
+
+    /// The master a slave takes its time from
+    pub(crate) fn remote_master(&self) -> Option<PortIdentity> {
+        match self {
+            PortState::Slave(slave) => Some(slave.remote_master()),
+            PortState::Master(_)
+            | PortState::Listening
+            | PortState::Passive
+            | PortState::Faulty
+            | PortState::Disabled => None,
+        }
+    }
+
+    pub(crate) fn in_startup_burst(&self) -> bool {
+        match self {
+            PortState::Slave(slave) => slave.in_startup_burst(),
+            PortState::Master(_)
+            | PortState::Listening
+            | PortState::Passive
+            | PortState::Faulty
+            | PortState::Disabled => false,
+        }
+    }
+
+    /// Give up on the measurement the time critical message that could not be
+    /// sent was part of
+    pub(crate) fn handle_send_failure(&mut self, context: TimestampContext) {
+        match self {
+            PortState::Slave(slave) => slave.handle_send_failure(context),
+            PortState::Master(_)
+            | PortState::Listening
+            | PortState::Passive
+            | PortState::Faulty
+            | PortState::Disabled => {}
         }
     }
 
     pub(crate) fn extract_measurement(&mut self) -> Option<Measurement> {
         match self {
             PortState::Slave(slave) => slave.extract_measurement(),
-            PortState::Master(_) | PortState::Listening | PortState::Passive => None,
+            PortState::Master(_)
+            | PortState::Listening
+            | PortState::Passive
+            | PortState::Faulty
+            | PortState::Disabled => None,
         }
     }
 }
 
-impl Display for PortState {
-    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+impl PortState {
+    pub(crate) fn kind(&self) -> PortStateKind {
         match self {
-            PortState::Listening => write!(f, "Listening"),
-            PortState::Master(_) => write!(f, "Master"),
-            PortState::Passive => write!(f, "Passive"),
-            PortState::Slave(_) => write!(f, "Slave"),
+            PortState::Listening => PortStateKind::Listening,
+            PortState::Master(_) => PortStateKind::Master,
+            PortState::Passive => PortStateKind::Passive,
+            PortState::Slave(_) => PortStateKind::Slave,
+            PortState::Faulty => PortStateKind::Faulty,
+            PortState::Disabled => PortStateKind::Disabled,
         }
     }
 }
+
+impl Display for PortState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.kind().fmt(f)
+    }
+}
@@ -2,16 +2,22 @@ use rand::Rng;
 
 use crate::{
     datastructures::{
-        common::PortIdentity,
+        common::{FollowUpInformation, PortIdentity},
         datasets::DefaultDS,
-        messages::{DelayRespMessage, FollowUpMessage, Message, SyncMessage},
+        messages::{
+            DelayRespMessage, FollowUpMessage, Message, SyncMessage, UNSPECIFIED_LOG_INTERVAL,
+        },
     },
+    log,
     port::{
-        sequence_id::SequenceIdGenerator, Measurement, PortAction, PortActionIterator,
-        TimestampContext, TimestampContextInner,
+        peer_delay::{LinkDelay, PeerDelay},
+        sequence_id::SequenceIdGenerator,
+        statistics::{DelayRespRejections, QuirkCounts},
+        CorrectionBreakdown, Diagnostic, Measurement, PortAction, PortActionIterator,
+        TimestampContext, TimestampContextInner, TimestampSource,
     },
-    time::{Duration, Time},
-    DelayMechanism, PortConfig,
+    time::{Duration, Interval, Time},
+    DelayMechanism, PortConfig, Quirks, StartupBurst,
 };
 
 #[derive(Debug)]
@@ -20,19 +26,81 @@ pub(crate) struct SlaveState {
 
     sync_state: SyncState,
     delay_state: DelayState,
+    peer_delay: PeerDelay,
 
     mean_delay: Option<Duration>,
+    // Assumed mean path delay when not measuring the delay
+    fixed_mean_delay: Option<Duration>,
+    // Sync receive minus send time, corrected for the delay asymmetry
     last_raw_offset: Option<Duration>,
+    // See PortConfig::delay_asymmetry
+    delay_asymmetry: Duration,
+    // See PortConfig::ingress_latency
+    ingress_latency: Duration,
+
+    // Where the receive timestamp of the current sync was taken
+    sync_recv_source: TimestampSource,
+    // The receive timestamp of the current sync as given, and corrected for
+    // the ingress latency
+    sync_raw_recv_time: Time,
+    sync_recv_time: Time,
+    // Where the send timestamp of the current delay request was taken
+    delay_send_source: TimestampSource,
+    // Where the timestamps the mean delay was computed from were taken, if it
+    // was measured at all
+    mean_delay_source: Option<TimestampSource>,
+    // Of the link to the peer, as of the last peer delay exchange
+    neighbor_rate_ratio: Option<f64>,
 
     delay_req_ids: SequenceIdGenerator,
+    // Delay requests sent since the last response to one
+    unanswered_delay_requests: u32,
 
     next_delay_measurement: Option<Time>,
+
+    // Workarounds for the current master
+    quirks: Quirks,
+    // The port this state belongs to, to attach to log messages and to match
+    // peer delay responses with
+    port_identity: PortIdentity,
+
+    // Measure more often right after the master was selected. The end of the
+    // burst is set by the first Sync.
+    startup_burst: Option<StartupBurst>,
+    startup_burst_end: Option<Time>,
+    // The minimum delay request interval the master asked for
+    master_delay_req_interval: Option<Interval>,
+
+    // Problem noticed while handling the last event, for the port to report
+    pub(in crate::port) diagnostic: Option<Diagnostic>,
+    // Workarounds needed since the port last looked, for it to count
+    pub(in crate::port) quirks_triggered: QuirkCounts,
+    // DelayResp messages rejected since the port last looked
+    pub(in crate::port) delay_resp_rejections: DelayRespRejections,
+    // Sync and FollowUp messages from other masters since the port last looked
+    pub(in crate::port) non_parent_sync_messages: u32,
 }
 
 impl SlaveState {
     pub(crate) fn remote_master(&self) -> PortIdentity {
         self.remote_master
     }
+
+    /// The mean path delay to the master, once it is known
+    pub(crate) fn mean_delay(&self) -> Option<Duration> {
+        self.mean_delay
+    }
+
+    /// The frequency of the clock of the peer relative to ours, once two peer
+    /// delay exchanges with a two-step responder completed
+    pub(crate) fn neighbor_rate_ratio(&self) -> Option<f64> {
+        self.neighbor_rate_ratio
+    }
+
+    /// How many delay requests were sent since the master last answered one
+    pub(crate) fn unanswered_delay_requests(&self) -> u32 {
+        self.unanswered_delay_requests
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -42,6 +110,9 @@ enum SyncState {
         id: u16,
         send_time: Option<Time>,
         recv_time: Option<Time>,
+        // The correction fields of the sync and follow up received so far
+        correction: Duration,
+        follow_up_information: Option<FollowUpInformation>,
     },
 }
 
@@ -61,10 +132,119 @@ impl SlaveState {
             remote_master,
             sync_state: SyncState::Empty,
             delay_state: DelayState::Empty,
+            peer_delay: PeerDelay::new(PortIdentity::default()),
             mean_delay: None,
+            fixed_mean_delay: None,
             last_raw_offset: None,
+            delay_asymmetry: Duration::ZERO,
+            ingress_latency: Duration::ZERO,
+            sync_recv_source: TimestampSource::Legacy,
+            sync_raw_recv_time: Time::default(),
+            sync_recv_time: Time::default(),
+            delay_send_source: TimestampSource::Legacy,
+            mean_delay_source: None,
+            neighbor_rate_ratio: None,
             delay_req_ids: SequenceIdGenerator::new(),
+            unanswered_delay_requests: 0,
             next_delay_measurement: None,
+            quirks: Quirks::DEFAULT,
+            port_identity: PortIdentity::default(),
+            startup_burst: None,
+            startup_burst_end: None,
+            master_delay_req_interval: None,
+            diagnostic: None,
+            quirks_triggered: QuirkCounts::default(),
+            delay_resp_rejections: DelayRespRejections::default(),
+            non_parent_sync_messages: 0,
+        }
+    }
+
+    pub(crate) fn with_delay_mechanism(
+        remote_master: PortIdentity,
+        delay_mechanism: DelayMechanism,
+    ) -> Self {
+        let fixed_mean_delay = match delay_mechanism {
+            DelayMechanism::E2E { .. } | DelayMechanism::P2P { .. } | DelayMechanism::CommonP2P => {
+                None
+            }
+            DelayMechanism::OneWay { path_delay } => Some(path_delay),
+        };
+
+        SlaveState {
+            mean_delay: fixed_mean_delay,
+            fixed_mean_delay,
+            ..Self::new(remote_master)
+        }
+    }
+
+    /// Use these workarounds for the master
+    pub(crate) fn with_quirks(self, quirks: Quirks) -> Self {
+        SlaveState { quirks, ..self }
+    }
+
+    /// Log messages as being about this port
+    pub(crate) fn with_port_identity(self, port_identity: PortIdentity) -> Self {
+        SlaveState {
+            port_identity,
+            peer_delay: PeerDelay::new(port_identity),
+            ..self
+        }
+    }
+
+    /// Correct the measurements for this delay asymmetry
+    pub(crate) fn with_delay_asymmetry(self, delay_asymmetry: Duration) -> Self {
+        SlaveState {
+            delay_asymmetry,
+            ..self
+        }
+    }
+
+    /// Correct receive timestamps for this ingress latency
+    pub(crate) fn with_ingress_latency(self, ingress_latency: Duration) -> Self {
+        SlaveState {
+            ingress_latency,
+            ..self
+        }
+    }
+
+    /// Use a new delay asymmetry and ingress latency from the next
+    /// measurement on
+    pub(crate) fn set_calibration(&mut self, delay_asymmetry: Duration, ingress_latency: Duration) {
+        self.delay_asymmetry = delay_asymmetry;
+        self.ingress_latency = ingress_latency;
+    }
+
+    /// Start with a burst of measurements, if given
+    pub(crate) fn with_startup_burst(self, startup_burst: Option<StartupBurst>) -> Self {
+        SlaveState {
+            startup_burst,
+            ..self
+        }
+    }
+
+    /// Start out with the delay of the link to the master measured by the
+    /// Common Mean Link Delay Service, if it measured one yet
+    pub(crate) fn with_link_delay(mut self, link_delay: Option<LinkDelay>) -> Self {
+        if let Some(link_delay) = link_delay {
+            self.set_link_delay(link_delay);
+        }
+        self
+    }
+
+    /// A fresh state for the same master, without any measurement data
+    pub(crate) fn restarted(&self) -> Self {
+        SlaveState {
+            mean_delay: self.fixed_mean_delay,
+            fixed_mean_delay: self.fixed_mean_delay,
+            delay_asymmetry: self.delay_asymmetry,
+            ingress_latency: self.ingress_latency,
+            quirks: self.quirks,
+            port_identity: self.port_identity,
+            peer_delay: PeerDelay::new(self.port_identity),
+            startup_burst: self.startup_burst,
+            startup_burst_end: self.startup_burst_end,
+            master_delay_req_interval: self.master_delay_req_interval,
+            ..Self::new(self.remote_master)
         }
     }
 
@@ -72,23 +252,58 @@ impl SlaveState {
         &mut self,
         context: TimestampContext,
         timestamp: Time,
+        source: TimestampSource,
     ) -> PortActionIterator<'a> {
         match context.inner {
             crate::port::TimestampContextInner::DelayReq { id } => {
                 // handle our send timestamp on a delay request message
-                self.handle_delay_timestamp(id, timestamp)
+                self.handle_delay_timestamp(id, timestamp, source)
+            }
+            crate::port::TimestampContextInner::PDelayReq { id } => {
+                let link_delay = self.peer_delay.handle_timestamp(id, timestamp, source);
+                self.update_from_peer_delay(link_delay);
+                actions![]
             }
             _ => {
-                log::error!("Unexpected timestamp");
+                self.diagnostic = Some(Diagnostic::UnexpectedTimestamp);
+                log::error!(port: self.port_identity, "Unexpected timestamp");
                 actions![]
             }
         }
     }
 
+    pub(crate) fn handle_send_failure(&mut self, context: TimestampContext) {
+        // The delay response will never come, so don't wait for it, nor blame
+        // the master for it
+        match context.inner {
+            crate::port::TimestampContextInner::DelayReq { id: failed_id } => {
+                match self.delay_state {
+                    DelayState::Measuring { id, .. } if id == failed_id => {
+                        self.delay_state = DelayState::Empty;
+                        self.unanswered_delay_requests =
+                            self.unanswered_delay_requests.saturating_sub(1);
+                    }
+                    _ => {}
+                }
+            }
+            crate::port::TimestampContextInner::PDelayReq { id }
+                if self.peer_delay.outstanding() =>
+            {
+                self.peer_delay.handle_send_failure(id);
+                if !self.peer_delay.outstanding() {
+                    self.unanswered_delay_requests =
+                        self.unanswered_delay_requests.saturating_sub(1);
+                }
+            }
+            _ => {}
+        }
+    }
+
     fn handle_delay_timestamp<'a>(
         &mut self,
         timestamp_id: u16,
         timestamp: Time,
+        source: TimestampSource,
     ) -> PortActionIterator<'a> {
         match self.delay_state {
             DelayState::Measuring {
@@ -96,15 +311,20 @@ impl SlaveState {
                 send_time: Some(_),
                 ..
             } if id == timestamp_id => {
-                log::error!("Double send timestamp for delay request");
+                self.diagnostic = Some(Diagnostic::UnexpectedTimestamp);
+                log::error!(port: self.port_identity, "Double send timestamp for delay request");
             }
             DelayState::Measuring {
                 id,
                 ref mut send_time,
                 ..
-            } if id == timestamp_id => *send_time = Some(timestamp),
+            } if id == timestamp_id => {
+                *send_time = Some(timestamp);
+                self.delay_send_source = source;
+            }
             _ => {
-                log::warn!("Late timestamp for delay request ignored");
+                self.diagnostic = Some(Diagnostic::UnexpectedTimestamp);
+                log::warn!(port: self.port_identity, "Late timestamp for delay request ignored");
             }
         }
 
@@ -115,31 +335,85 @@ impl SlaveState {
         &mut self,
         message: Message,
         timestamp: Time,
+        source: TimestampSource,
     ) -> PortActionIterator<'a> {
+        // The only place receive timestamps are corrected for the ingress
+        // latency. The raw one is kept for the measurement.
+        let raw_timestamp = timestamp;
+        let timestamp = raw_timestamp - self.ingress_latency;
+
+        // Peer delay responses come from our neighbour on the link, which
+        // need not be the master
+        if let Message::PDelayResp(message) = message {
+            let link_delay = self.peer_delay.handle_resp(message, timestamp, source);
+            self.update_from_peer_delay(link_delay);
+            return actions![];
+        }
+
         // Ignore everything not from master
         if message.header().source_port_identity != self.remote_master {
+            if let Message::Sync(_) = message {
+                self.non_parent_sync_messages += 1;
+                log::debug!(
+                    port: self.port_identity,
+                    "Ignoring Sync from {:?}, which is not our master",
+                    message.header().source_port_identity
+                );
+            }
             return actions![];
         }
 
         match message {
-            Message::Sync(message) => self.handle_sync(message, timestamp),
+            Message::Sync(message) => self.handle_sync(message, timestamp, raw_timestamp, source),
             _ => {
-                log::warn!("Unexpected message {:?}", message);
+                self.diagnostic = Some(Diagnostic::UnexpectedMessage);
+                log::warn!(
+                    port: self.port_identity,
+                    "Unexpected {:?} message",
+                    message.content_type()
+                );
                 actions![]
             }
         }
     }
 
     pub(crate) fn handle_general_receive(&mut self, message: Message, port_identity: PortIdentity) {
+        // DelayResp messages are validated separately, to count what is wrong
+        // with them
+        if let Message::DelayResp(message) = message {
+            self.handle_delay_resp(message, port_identity);
+            return;
+        }
+
+        if let Message::PDelayRespFollowUp(message) = message {
+            let link_delay = self.peer_delay.handle_resp_follow_up(message);
+            self.update_from_peer_delay(link_delay);
+            return;
+        }
+
         // Ignore everything not from master
         if message.header().source_port_identity != self.remote_master {
+            if let Message::FollowUp(_) = message {
+                self.non_parent_sync_messages += 1;
+                log::debug!(
+                    port: self.port_identity,
+                    "Ignoring FollowUp from {:?}, which is not our master",
+                    message.header().source_port_identity
+                );
+            }
             return;
         }
 
         match message {
             Message::FollowUp(message) => self.handle_follow_up(message),
-            Message::DelayResp(message) => self.handle_delay_resp(message, port_identity),
-            _ => log::warn!("Unexpected message {:?}", message),
+            _ => {
+                self.diagnostic = Some(Diagnostic::UnexpectedMessage);
+                log::warn!(
+                    port: self.port_identity,
+                    "Unexpected {:?} message",
+                    message.content_type()
+                );
+            }
         }
     }
 
@@ -150,17 +424,29 @@ impl SlaveState {
             ..
         } = self.sync_state
         {
-            self.last_raw_offset = Some(recv_time - send_time);
+            // The master to slave delay is the mean delay plus the asymmetry
+            // (16.8), take it out to leave the offset plus the mean delay
+            self.last_raw_offset = Some(recv_time - send_time - self.delay_asymmetry);
             self.try_finish_delay_measurement();
         }
     }
 
-    fn handle_sync<'a>(&mut self, message: SyncMessage, recv_time: Time) -> PortActionIterator<'a> {
-        log::debug!("Received sync {:?}", message.header.sequence_id);
+    fn handle_sync<'a>(
+        &mut self,
+        message: SyncMessage,
+        recv_time: Time,
+        raw_recv_time: Time,
+        source: TimestampSource,
+    ) -> PortActionIterator<'a> {
+        log::debug!(port: self.port_identity, "Received sync {:?}", message.header.sequence_id);
+
+        self.update_startup_burst(recv_time);
+        let recv_times = (raw_recv_time, recv_time);
 
         // substracting correction from recv time is equivalent to adding it to send
         // time
-        let corrected_recv_time = recv_time - Duration::from(message.header.correction_field);
+        let sync_correction = Duration::from(message.header.correction_field);
+        let corrected_recv_time = recv_time - sync_correction;
 
         if message.header.two_step_flag {
             match self.sync_state {
@@ -169,26 +455,38 @@ impl SlaveState {
                     recv_time: Some(_),
                     ..
                 } if id == message.header.sequence_id => {
-                    log::warn!("Duplicate sync message");
+                    self.diagnostic = Some(Diagnostic::DuplicateMessage);
+                    log::warn!(port: self.port_identity, "Duplicate sync message");
                     // Ignore the sync message
                 }
                 SyncState::Measuring {
                     id,
                     ref mut recv_time,
+                    ref mut correction,
                     ..
-                } if id == message.header.sequence_id => *recv_time = Some(corrected_recv_time),
+                } if id == message.header.sequence_id => {
+                    *recv_time = Some(corrected_recv_time);
+                    *correction += sync_correction;
+                    self.sync_recv_source = source;
+                    (self.sync_raw_recv_time, self.sync_recv_time) = recv_times;
+                }
                 _ => {
                     self.sync_state = SyncState::Measuring {
                         id: message.header.sequence_id,
                         send_time: None,
                         recv_time: Some(corrected_recv_time),
-                    }
+                        correction: sync_correction,
+                        follow_up_information: None,
+                    };
+                    self.sync_recv_source = source;
+                    (self.sync_raw_recv_time, self.sync_recv_time) = recv_times;
                 }
             }
         } else {
             match self.sync_state {
                 SyncState::Measuring { id, .. } if id == message.header.sequence_id => {
-                    log::warn!("Duplicate sync message");
+                    self.diagnostic = Some(Diagnostic::DuplicateMessage);
+                    log::warn!(port: self.port_identity, "Duplicate sync message");
                     // Ignore the sync message
                 }
                 _ => {
@@ -196,7 +494,11 @@ impl SlaveState {
                         id: message.header.sequence_id,
                         send_time: Some(Time::from(message.origin_timestamp)),
                         recv_time: Some(corrected_recv_time),
+                        correction: sync_correction,
+                        follow_up_information: None,
                     };
+                    self.sync_recv_source = source;
+                    (self.sync_raw_recv_time, self.sync_recv_time) = recv_times;
                 }
             }
         }
@@ -206,23 +508,95 @@ impl SlaveState {
         actions![]
     }
 
+    fn update_startup_burst(&mut self, now: Time) {
+        let Some(burst) = self.startup_burst else {
+            return;
+        };
+
+        let end = *self.startup_burst_end.get_or_insert(now + burst.duration);
+        if now >= end {
+            log::info!(port: self.port_identity, "Startup burst ended");
+            self.startup_burst = None;
+        }
+    }
+
+    pub(crate) fn in_startup_burst(&self) -> bool {
+        self.startup_burst.is_some()
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn send_delay_request<'a>(
         &mut self,
         rng: &mut impl Rng,
         port_config: &PortConfig,
         port_identity: PortIdentity,
         default_ds: &DefaultDS,
+        frequency_multiplier: f64,
+        unicast_master: Option<PortIdentity>,
         buffer: &'a mut [u8],
     ) -> PortActionIterator<'a> {
-        log::debug!("Starting new delay measurement");
+        let (log_min_delay_req_interval, peer_to_peer) = match port_config.delay_mechanism {
+            // the interval corresponds to the PortDS logMinDelayReqInterval
+            DelayMechanism::E2E { interval } => (interval, false),
+            // the interval corresponds to the PortDS logMinPdelayReqInterval
+            DelayMechanism::P2P { interval, .. } => (interval, true),
+            // Not measured, or measured by the Common Mean Link Delay Service
+            DelayMechanism::OneWay { .. } | DelayMechanism::CommonP2P => return actions![],
+        };
+
+        // Measure faster during the startup burst, but never faster than the
+        // master allows
+        let log_min_delay_req_interval = match self.startup_burst {
+            Some(burst) => {
+                let allowed = match self.master_delay_req_interval {
+                    Some(interval) => burst.delay_req_interval.max(interval),
+                    None => burst.delay_req_interval,
+                };
+                log_min_delay_req_interval.min(allowed)
+            }
+            None => log_min_delay_req_interval,
+        };
+
+        if !port_config.transmit.delay_req {
+            return actions![];
+        }
+
+        if peer_to_peer {
+            let actions = self.peer_delay.send_request(
+                log_min_delay_req_interval,
+                frequency_multiplier,
+                &port_config.log_message_intervals,
+                port_identity,
+                default_ds,
+                buffer,
+            );
+            if self.peer_delay.outstanding() {
+                self.unanswered_delay_requests += 1;
+            }
+            self.update_from_peer_delay(None);
+            return actions;
+        }
+
+        log::debug!(port: self.port_identity, "Starting new delay measurement");
 
         let delay_id = self.delay_req_ids.generate();
-        let delay_req = Message::delay_req(default_ds, port_identity, delay_id);
+        let delay_req = Message::delay_req(
+            default_ds,
+            port_identity,
+            delay_id,
+            unicast_master.is_some(),
+            &port_config.log_message_intervals,
+        );
 
         let message_length = match delay_req.serialize(buffer) {
             Ok(length) => length,
             Err(error) => {
-                log::error!("Could not serialize delay request: {:?}", error);
+                self.diagnostic = Some(Diagnostic::SerializationFailed);
+                log::error!(
+                    port: self.port_identity,
+                    "Could not serialize delay request: {:?}",
+                    error
+                );
                 return actions![];
             }
         };
@@ -232,34 +606,40 @@ impl SlaveState {
             send_time: None,
             recv_time: None,
         };
+        self.unanswered_delay_requests += 1;
 
         let random = rng.sample::<f64, _>(rand::distributions::Open01);
-        let log_min_delay_req_interval = match port_config.delay_mechanism {
-            // the interval corresponds to the PortDS logMinDelayReqInterval
-            DelayMechanism::E2E { interval } => interval,
-        };
         let log_sync_interval = port_config.sync_interval.as_log_2() as i32;
-        let factor = random * 2.0f64.powi(log_sync_interval + 1);
+        let factor = random * libm::pow(2.0, (log_sync_interval + 1) as f64);
         let duration = log_min_delay_req_interval
             .as_core_duration()
             .mul_f64(factor);
 
-        actions![
-            PortAction::ResetDelayRequestTimer { duration },
-            PortAction::SendTimeCritical {
-                context: TimestampContext {
-                    inner: TimestampContextInner::DelayReq { id: delay_id },
-                },
-                data: &buffer[..message_length],
-            }
-        ]
+        let context = TimestampContext {
+            inner: TimestampContextInner::DelayReq { id: delay_id },
+        };
+        let data = &buffer[..message_length];
+        let send = match unicast_master {
+            Some(master) => PortAction::SendUnicastTimeCritical {
+                context,
+                data,
+                clock_identity: master.clock_identity,
+                port_number: master.port_number,
+            },
+            None => PortAction::SendTimeCritical {
+                context,
+                data,
+                transmit_at: None,
+            },
+        };
+        actions![PortAction::ResetDelayRequestTimer { duration }, send]
     }
 
     fn handle_follow_up(&mut self, message: FollowUpMessage) {
-        log::debug!("Received FollowUp {:?}", message.header.sequence_id);
+        log::debug!(port: self.port_identity, "Received FollowUp {:?}", message.header.sequence_id);
 
-        let packet_send_time = Time::from(message.precise_origin_timestamp)
-            + Duration::from(message.header.correction_field);
+        let follow_up_correction = Duration::from(message.header.correction_field);
+        let packet_send_time = Time::from(message.precise_origin_timestamp) + follow_up_correction;
 
         match self.sync_state {
             SyncState::Measuring {
@@ -267,21 +647,37 @@ impl SlaveState {
                 send_time: Some(_),
                 ..
             } if id == message.header.sequence_id => {
-                log::warn!("Duplicate FollowUp message");
+                self.diagnostic = Some(Diagnostic::DuplicateMessage);
+                log::warn!(port: self.port_identity, "Duplicate FollowUp message");
                 // Ignore the followup
             }
             SyncState::Measuring {
                 id,
                 ref mut send_time,
+                ref mut correction,
+                ref mut follow_up_information,
                 ..
-            } if id == message.header.sequence_id => *send_time = Some(packet_send_time),
-            _ => {
+            } if id == message.header.sequence_id => {
+                *send_time = Some(packet_send_time);
+                *correction += follow_up_correction;
+                *follow_up_information = message.information;
+            }
+            _ if self.quirks.follow_up_before_sync => {
+                // Keep the FollowUp until its Sync comes in
+                self.quirks_triggered.follow_up_before_sync += 1;
                 self.sync_state = SyncState::Measuring {
                     id: message.header.sequence_id,
                     send_time: Some(packet_send_time),
                     recv_time: None,
+                    correction: follow_up_correction,
+                    follow_up_information: message.information,
                 }
             }
+            _ => {
+                self.diagnostic = Some(Diagnostic::UnexpectedMessage);
+                log::warn!(port: self.port_identity, "Unexpected FollowUp message");
+                // Ignore the followup
+            }
         }
 
         self.update_last_raw_offset();
@@ -297,14 +693,34 @@ impl SlaveState {
             Some(last_raw_offset),
         ) = (&self.delay_state, self.last_raw_offset)
         {
-            self.mean_delay = Some(((*recv_time - *send_time) + last_raw_offset) / 2);
+            // The slave to master delay is the mean delay minus the asymmetry
+            let slave_to_master = *recv_time - *send_time + self.delay_asymmetry;
+            self.mean_delay = Some((slave_to_master + last_raw_offset) / 2);
+            self.mean_delay_source = Some(self.delay_send_source.combine(self.sync_recv_source));
             self.delay_state = DelayState::Empty;
         }
     }
 
     fn handle_delay_resp(&mut self, message: DelayRespMessage, port_identity: PortIdentity) {
-        log::debug!("Received DelayResp");
+        log::debug!(port: self.port_identity, "Received DelayResp");
+
+        // Responses to other ports are normal with multiple slaves on a
+        // multicast network
         if port_identity != message.requesting_port_identity {
+            self.delay_resp_rejections.requesting_port += 1;
+            return;
+        }
+
+        // Only the parent knows when our DelayReq arrived, anything else would
+        // corrupt the path delay
+        if message.header.source_port_identity != self.remote_master {
+            self.delay_resp_rejections.source += 1;
+            self.diagnostic = Some(Diagnostic::UnexpectedMessage);
+            log::warn!(
+                port: self.port_identity,
+                "Ignoring DelayResp from {:?}, which is not our master",
+                message.header.source_port_identity
+            );
             return;
         }
 
@@ -314,26 +730,47 @@ impl SlaveState {
                 recv_time: Some(_),
                 ..
             } if id == message.header.sequence_id => {
-                log::warn!("Duplicate DelayResp message");
+                self.diagnostic = Some(Diagnostic::DuplicateMessage);
+                log::warn!(port: self.port_identity, "Duplicate DelayResp message");
                 // Ignore the Delay response
             }
             DelayState::Measuring {
                 id,
                 ref mut recv_time,
                 ..
-            } if id == message.header.sequence_id => {
+            } if id == message.header.sequence_id
+                || (self.quirks.delay_resp_sequence_id && recv_time.is_none()) =>
+            {
+                if id != message.header.sequence_id {
+                    log::debug!(
+                        port: self.port_identity,
+                        "Accepting DelayResp with sequence id {} for DelayReq {}",
+                        message.header.sequence_id,
+                        id
+                    );
+                    self.quirks_triggered.delay_resp_sequence_id += 1;
+                }
+
                 *recv_time = Some(
                     Time::from(message.receive_timestamp)
                         - Duration::from(message.header.correction_field),
                 );
-                self.next_delay_measurement = Some(
-                    *recv_time.as_ref().unwrap()
-                        + Duration::from_log_interval(message.header.log_message_interval)
-                        - Duration::from_fixed_nanos(0.1f64),
-                );
+                self.unanswered_delay_requests = 0;
+                // Unicast masters leave the interval unspecified, as it was
+                // negotiated in the grant, so the configured one still holds
+                let log_interval = message.header.log_message_interval;
+                if log_interval != UNSPECIFIED_LOG_INTERVAL {
+                    self.master_delay_req_interval = Some(Interval::from_log_2(log_interval));
+                    self.next_delay_measurement = Some(
+                        *recv_time.as_ref().unwrap() + Duration::from_log_interval(log_interval)
+                            - Duration::from_fixed_nanos(0.1f64),
+                    );
+                }
             }
             _ => {
-                log::warn!("Unexpected DelayResp message");
+                self.delay_resp_rejections.sequence_id += 1;
+                self.diagnostic = Some(Diagnostic::UnexpectedMessage);
+                log::warn!(port: self.port_identity, "Unexpected DelayResp message");
                 // Ignore the Delay response
             }
         }
@@ -341,24 +778,61 @@ impl SlaveState {
         self.try_finish_delay_measurement();
     }
 
+    // Take over what the peer delay exchange measured, and any problem it
+    // noticed
+    fn update_from_peer_delay(&mut self, link_delay: Option<LinkDelay>) {
+        if let Some(diagnostic) = self.peer_delay.diagnostic.take() {
+            self.diagnostic = Some(diagnostic);
+        }
+        if let Some(link_delay) = link_delay {
+            self.unanswered_delay_requests = 0;
+            self.set_link_delay(link_delay);
+        }
+    }
+
+    /// Use the delay of the link to the master measured by a peer delay
+    /// exchange, which the port runs itself in gPTP mode or takes from the
+    /// Common Mean Link Delay Service
+    pub(crate) fn set_link_delay(&mut self, link_delay: LinkDelay) {
+        self.mean_delay = Some(link_delay.mean_delay);
+        self.mean_delay_source = Some(link_delay.source);
+        self.neighbor_rate_ratio = link_delay.neighbor_rate_ratio;
+    }
+
     pub(crate) fn extract_measurement(&mut self) -> Option<Measurement> {
         match (&self.sync_state, self.mean_delay) {
             (
                 SyncState::Measuring {
                     send_time: Some(send_time),
                     recv_time: Some(recv_time),
+                    correction,
+                    follow_up_information,
                     ..
                 },
                 Some(mean_delay),
             ) => {
+                let timestamp_source = match self.mean_delay_source {
+                    Some(source) => source.combine(self.sync_recv_source),
+                    None => self.sync_recv_source,
+                };
                 let result = Measurement {
-                    master_offset: *recv_time - *send_time - mean_delay,
+                    master_offset: *recv_time - *send_time - self.delay_asymmetry - mean_delay,
                     event_time: *recv_time,
+                    raw_receive_time: self.sync_raw_recv_time,
+                    receive_time: self.sync_recv_time,
+                    timestamp_source,
+                    correction: CorrectionBreakdown::new(
+                        *correction,
+                        self.delay_asymmetry,
+                        mean_delay,
+                    ),
+                    follow_up_information: *follow_up_information,
+                    neighbor_rate_ratio: self.neighbor_rate_ratio,
                 };
 
                 self.sync_state = SyncState::Empty;
 
-                log::debug!("Extracted measurement {:?}", result);
+                log::debug!(port: self.port_identity, "Extracted measurement {:?}", result);
 
                 Some(result)
             }
@@ -374,11 +848,202 @@ mod tests {
         config::InstanceConfig,
         datastructures::{
             common::{ClockIdentity, TimeInterval},
-            messages::{Header, SdoId},
+            messages::{Header, PDelayRespFollowUpMessage, PDelayRespMessage, SdoId},
         },
-        Interval, MAX_DATA_LEN,
+        Interval, PathDirection, MAX_DATA_LEN,
     };
 
+    fn test_default_ds() -> DefaultDS {
+        DefaultDS::new(InstanceConfig {
+            clock_identity: ClockIdentity::default(),
+            priority_1: 15,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        })
+    }
+
+    fn test_config() -> PortConfig {
+        PortConfig {
+            delay_mechanism: DelayMechanism::E2E {
+                interval: Interval::ONE_SECOND,
+            },
+            announce_interval: Interval::ONE_SECOND,
+            announce_receipt_timeout: Default::default(),
+            sync_interval: Interval::ONE_SECOND,
+            master_only: Default::default(),
+            delay_asymmetry: Default::default(),
+            ingress_latency: Default::default(),
+            egress_latency: Default::default(),
+            management: Default::default(),
+            communication_mode: Default::default(),
+            transmit: Default::default(),
+            unicast_client: None,
+            log_message_intervals: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_delay_req_disabled() {
+        let mut state = SlaveState::new(Default::default());
+        let mut buffer = [0u8; MAX_DATA_LEN];
+
+        let default_ds = test_default_ds();
+        let port_config = PortConfig {
+            transmit: crate::TransmitEnable {
+                delay_req: false,
+                ..Default::default()
+            },
+            ..test_config()
+        };
+
+        let mut rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut action = state.send_delay_request(
+            &mut rng,
+            &port_config,
+            Default::default(),
+            &default_ds,
+            1.0,
+            None,
+            &mut buffer,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+
+        assert!(matches!(state.delay_state, DelayState::Empty));
+    }
+
+    #[test]
+    fn test_one_way() {
+        let delay_mechanism = DelayMechanism::OneWay {
+            path_delay: Duration::from_micros(100),
+        };
+        let mut state = SlaveState::with_delay_mechanism(Default::default(), delay_mechanism);
+        let mut buffer = [0u8; MAX_DATA_LEN];
+
+        let default_ds = test_default_ds();
+        let port_config = PortConfig {
+            delay_mechanism,
+            ..test_config()
+        };
+
+        // No delay requests are sent, and the delay timer isn't restarted
+        let mut rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut action = state.send_delay_request(
+            &mut rng,
+            &port_config,
+            Default::default(),
+            &default_ds,
+            1.0,
+            None,
+            &mut buffer,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+
+        let sync = Message::Sync(SyncMessage {
+            header: Header {
+                two_step_flag: false,
+                correction_field: TimeInterval(1000.into()),
+                ..Default::default()
+            },
+            origin_timestamp: Time::from_micros(0).into(),
+        });
+
+        // The assumed path delay is used right away
+        let mut action = state.handle_event_receive(
+            sync.clone(),
+            Time::from_micros(50),
+            TimestampSource::Hardware,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+        assert_eq!(
+            state.extract_measurement(),
+            Some(Measurement {
+                event_time: Time::from_micros(49),
+                master_offset: Duration::from_micros(-51),
+                raw_receive_time: Time::from_micros(50),
+                receive_time: Time::from_micros(50),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    residence_time: Duration::from_micros(1),
+                    path_delay: Duration::from_micros(100),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
+            })
+        );
+
+        // And kept when restarting measurements
+        let mut state = state.restarted();
+        let mut action =
+            state.handle_event_receive(sync, Time::from_micros(50), TimestampSource::Hardware);
+        assert!(action.next().is_none());
+        drop(action);
+        assert_eq!(
+            state.extract_measurement(),
+            Some(Measurement {
+                event_time: Time::from_micros(49),
+                master_offset: Duration::from_micros(-51),
+                raw_receive_time: Time::from_micros(50),
+                receive_time: Time::from_micros(50),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    residence_time: Duration::from_micros(1),
+                    path_delay: Duration::from_micros(100),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_ingress_latency() {
+        let mut state =
+            SlaveState::new(Default::default()).with_ingress_latency(Duration::from_micros(2));
+        state.mean_delay = Some(Duration::from_micros(100));
+
+        let mut action = state.handle_event_receive(
+            Message::Sync(SyncMessage {
+                header: Header {
+                    two_step_flag: false,
+                    correction_field: TimeInterval(1000.into()),
+                    ..Default::default()
+                },
+                origin_timestamp: Time::from_micros(0).into(),
+            }),
+            Time::from_micros(50),
+            TimestampSource::Hardware,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+
+        // The latency is taken off the receive timestamp once, before the
+        // correction field
+        assert_eq!(
+            state.extract_measurement(),
+            Some(Measurement {
+                event_time: Time::from_micros(47),
+                master_offset: Duration::from_micros(-53),
+                raw_receive_time: Time::from_micros(50),
+                receive_time: Time::from_micros(48),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    residence_time: Duration::from_micros(1),
+                    path_delay: Duration::from_micros(100),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
+            })
+        );
+    }
+
     #[test]
     fn test_sync_without_delay_msg() {
         let mut state = SlaveState::new(Default::default());
@@ -395,6 +1060,7 @@ mod tests {
                 origin_timestamp: Time::from_micros(0).into(),
             }),
             Time::from_micros(50),
+            TimestampSource::Hardware,
         );
 
         assert!(action.next().is_none());
@@ -403,7 +1069,17 @@ mod tests {
             state.extract_measurement(),
             Some(Measurement {
                 event_time: Time::from_micros(49),
-                master_offset: Duration::from_micros(-51)
+                master_offset: Duration::from_micros(-51),
+                raw_receive_time: Time::from_micros(50),
+                receive_time: Time::from_micros(50),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    residence_time: Duration::from_micros(1),
+                    path_delay: Duration::from_micros(100),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
             })
         );
 
@@ -418,11 +1094,18 @@ mod tests {
                 origin_timestamp: Time::from_micros(0).into(),
             }),
             Time::from_micros(1050),
+            TimestampSource::Hardware,
         );
 
         assert!(action.next().is_none());
         assert_eq!(state.extract_measurement(), None);
 
+        // The measurement carries the FOLLOW_UP information TLV of 802.1AS
+        let information = FollowUpInformation {
+            gm_time_base_indicator: 7,
+            last_gm_phase_change: Duration::from_nanos(-20),
+            ..Default::default()
+        };
         state.handle_general_receive(
             Message::FollowUp(FollowUpMessage {
                 header: Header {
@@ -431,6 +1114,7 @@ mod tests {
                     ..Default::default()
                 },
                 precise_origin_timestamp: Time::from_micros(1000).into(),
+                information: Some(information),
             }),
             PortIdentity::default(),
         );
@@ -439,7 +1123,17 @@ mod tests {
             state.extract_measurement(),
             Some(Measurement {
                 event_time: Time::from_micros(1049),
-                master_offset: Duration::from_micros(-53)
+                master_offset: Duration::from_micros(-53),
+                raw_receive_time: Time::from_micros(1050),
+                receive_time: Time::from_micros(1050),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    residence_time: Duration::from_micros(3),
+                    path_delay: Duration::from_micros(100),
+                    ..Default::default()
+                },
+                follow_up_information: Some(information),
+                neighbor_rate_ratio: None,
             })
         );
     }
@@ -458,39 +1152,26 @@ mod tests {
                 origin_timestamp: Time::from_micros(0).into(),
             }),
             Time::from_micros(50),
+            TimestampSource::Hardware,
         );
 
         assert!(action.next().is_none());
 
         let mut buffer = [0u8; MAX_DATA_LEN];
-        let default_ds = DefaultDS::new(InstanceConfig {
-            clock_identity: ClockIdentity::default(),
-            priority_1: 15,
-            priority_2: 128,
-            domain_number: 0,
-            slave_only: false,
-            sdo_id: SdoId::default(),
-        });
+        let default_ds = test_default_ds();
 
         // mock rng and port config
         let mut rng = rand::rngs::mock::StepRng::new(2, 1);
         let port_identity = Default::default();
-        let port_config = PortConfig {
-            delay_mechanism: DelayMechanism::E2E {
-                interval: Interval::ONE_SECOND,
-            },
-            announce_interval: Interval::ONE_SECOND,
-            announce_receipt_timeout: Default::default(),
-            sync_interval: Interval::ONE_SECOND,
-            master_only: Default::default(),
-            delay_asymmetry: Default::default(),
-        };
+        let port_config = test_config();
 
         let mut action = state.send_delay_request(
             &mut rng,
             &port_config,
             port_identity,
             &default_ds,
+            1.0,
+            None,
             &mut buffer,
         );
 
@@ -498,7 +1179,7 @@ mod tests {
             panic!("Unexpected action");
         };
 
-        let Some(PortAction::SendTimeCritical { context, data }) = action.next() else {
+        let Some(PortAction::SendTimeCritical { context, data, .. }) = action.next() else {
             panic!("Unexpected action");
         };
         assert!(action.next().is_none());
@@ -510,7 +1191,8 @@ mod tests {
             _ => panic!("Incorrect message type"),
         };
 
-        let mut action = state.handle_timestamp(context, Time::from_micros(100));
+        let mut action =
+            state.handle_timestamp(context, Time::from_micros(100), TimestampSource::Hardware);
         assert!(action.next().is_none());
         drop(action);
 
@@ -532,7 +1214,17 @@ mod tests {
             state.extract_measurement(),
             Some(Measurement {
                 event_time: Time::from_micros(49),
-                master_offset: Duration::from_micros(-51)
+                master_offset: Duration::from_micros(-51),
+                raw_receive_time: Time::from_micros(50),
+                receive_time: Time::from_micros(50),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    residence_time: Duration::from_micros(1),
+                    path_delay: Duration::from_micros(100),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
             })
         );
 
@@ -548,6 +1240,7 @@ mod tests {
                 origin_timestamp: Time::from_micros(0).into(),
             }),
             Time::from_micros(1050),
+            TimestampSource::Hardware,
         );
 
         assert!(action.next().is_none());
@@ -557,6 +1250,8 @@ mod tests {
             &port_config,
             port_identity,
             &default_ds,
+            1.0,
+            None,
             &mut buffer,
         );
 
@@ -564,7 +1259,7 @@ mod tests {
             panic!("Unexpected action");
         };
 
-        let Some(PortAction::SendTimeCritical { context, data }) = action.next() else {
+        let Some(PortAction::SendTimeCritical { context, data, .. }) = action.next() else {
             panic!("Unexpected action");
         };
         assert!(action.next().is_none());
@@ -575,7 +1270,8 @@ mod tests {
             _ => panic!("Incorrect message type"),
         };
 
-        let mut action = state.handle_timestamp(context, Time::from_micros(1100));
+        let mut action =
+            state.handle_timestamp(context, Time::from_micros(1100), TimestampSource::Hardware);
         assert!(action.next().is_none());
 
         assert_eq!(state.extract_measurement(), None);
@@ -587,6 +1283,7 @@ mod tests {
                     ..Default::default()
                 },
                 precise_origin_timestamp: Time::from_micros(1000).into(),
+                information: None,
             }),
             PortIdentity::default(),
         );
@@ -609,7 +1306,513 @@ mod tests {
             state.extract_measurement(),
             Some(Measurement {
                 event_time: Time::from_micros(1049),
-                master_offset: Duration::from_micros(-53)
+                master_offset: Duration::from_micros(-53),
+                raw_receive_time: Time::from_micros(1050),
+                receive_time: Time::from_micros(1050),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    residence_time: Duration::from_micros(3),
+                    path_delay: Duration::from_micros(100),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_peer_delay() {
+        let port_identity = PortIdentity {
+            clock_identity: ClockIdentity([1; 8]),
+            port_number: 1,
+        };
+        let peer = PortIdentity {
+            clock_identity: ClockIdentity([2; 8]),
+            port_number: 1,
+        };
+        let default_ds = DefaultDS::new(InstanceConfig {
+            clock_identity: port_identity.clock_identity,
+            priority_1: 255,
+            priority_2: 255,
+            domain_number: 0,
+            slave_only: true,
+            sdo_id: SdoId::default(),
+        });
+        let port_config = PortConfig {
+            delay_mechanism: DelayMechanism::P2P {
+                interval: Interval::ONE_SECOND,
+                one_step_responder: false,
+            },
+            ..test_config()
+        };
+
+        // Send a PdelayReq at t1 = 100us, returning its sequence id
+        let send_request = |state: &mut SlaveState| {
+            let mut rng = rand::rngs::mock::StepRng::new(2, 1);
+            let mut buffer = [0u8; MAX_DATA_LEN];
+            let mut action = state.send_delay_request(
+                &mut rng,
+                &port_config,
+                port_identity,
+                &default_ds,
+                1.0,
+                None,
+                &mut buffer,
+            );
+
+            // Peer delay requests aren't randomized
+            let Some(PortAction::ResetDelayRequestTimer { duration }) = action.next() else {
+                panic!("Unexpected action");
+            };
+            assert_eq!(duration, core::time::Duration::from_secs(1));
+            let Some(PortAction::SendTimeCritical { context, data, .. }) = action.next() else {
+                panic!("Unexpected action");
+            };
+            assert!(action.next().is_none());
+
+            let Message::PDelayReq(request) = Message::deserialize(data).unwrap() else {
+                panic!("Incorrect message type");
+            };
+            assert_eq!(request.header.source_port_identity, port_identity);
+            drop(action);
+
+            let mut action =
+                state.handle_timestamp(context, Time::from_micros(100), TimestampSource::Hardware);
+            assert!(action.next().is_none());
+
+            request.header.sequence_id
+        };
+
+        // The peer received the request at t2 = 1000us and responded at
+        // t3 = 1010us, with 2us of correction on the response. The response
+        // arrived at t4 = 130us, so the mean link delay is
+        // ((130 - 100) - (1010 - 1000) - 2) / 2 = 9us.
+        let mut state = SlaveState::new(Default::default()).with_port_identity(port_identity);
+        let id = send_request(&mut state);
+
+        let response = |requesting_port_identity, sequence_id| {
+            Message::PDelayResp(PDelayRespMessage {
+                header: Header {
+                    two_step_flag: true,
+                    correction_field: TimeInterval(2000.into()),
+                    sequence_id,
+                    source_port_identity: peer,
+                    ..Default::default()
+                },
+                request_receive_timestamp: Time::from_micros(1000).into(),
+                requesting_port_identity,
+            })
+        };
+
+        // Responses to other ports and requests are ignored
+        let other_port = PortIdentity {
+            port_number: 2,
+            ..port_identity
+        };
+        let mut action = state.handle_event_receive(
+            response(other_port, id),
+            Time::from_micros(120),
+            TimestampSource::Hardware,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+        let mut action = state.handle_event_receive(
+            response(port_identity, id.wrapping_add(1)),
+            Time::from_micros(120),
+            TimestampSource::Hardware,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+
+        let mut action = state.handle_event_receive(
+            response(port_identity, id),
+            Time::from_micros(130),
+            TimestampSource::Hardware,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+        assert_eq!(state.mean_delay, None);
+
+        state.handle_general_receive(
+            Message::PDelayRespFollowUp(PDelayRespFollowUpMessage {
+                header: Header {
+                    sequence_id: id,
+                    source_port_identity: peer,
+                    ..Default::default()
+                },
+                response_origin_timestamp: Time::from_micros(1010).into(),
+                requesting_port_identity: port_identity,
+            }),
+            peer,
+        );
+        assert_eq!(state.mean_delay, Some(Duration::from_micros(9)));
+
+        // The link delay applies to the sync messages of the master
+        let mut action = state.handle_event_receive(
+            Message::Sync(SyncMessage {
+                header: Header {
+                    two_step_flag: false,
+                    ..Default::default()
+                },
+                origin_timestamp: Time::from_micros(0).into(),
+            }),
+            Time::from_micros(50),
+            TimestampSource::Hardware,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+        assert_eq!(
+            state.extract_measurement(),
+            Some(Measurement {
+                event_time: Time::from_micros(50),
+                master_offset: Duration::from_micros(41),
+                raw_receive_time: Time::from_micros(50),
+                receive_time: Time::from_micros(50),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    path_delay: Duration::from_micros(9),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
+            })
+        );
+
+        // A one-step peer puts its turnaround time in the correction field
+        let mut state = SlaveState::new(Default::default()).with_port_identity(port_identity);
+        let id = send_request(&mut state);
+        let mut action = state.handle_event_receive(
+            Message::PDelayResp(PDelayRespMessage {
+                header: Header {
+                    two_step_flag: false,
+                    correction_field: TimeInterval(12000.into()),
+                    sequence_id: id,
+                    source_port_identity: peer,
+                    ..Default::default()
+                },
+                request_receive_timestamp: Time::from_micros(0).into(),
+                requesting_port_identity: port_identity,
+            }),
+            Time::from_micros(130),
+            TimestampSource::Software,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+        assert_eq!(state.mean_delay, Some(Duration::from_micros(9)));
+        assert_eq!(state.mean_delay_source, Some(TimestampSource::Software));
+    }
+
+    #[test]
+    fn test_delay_asymmetry() {
+        // Measure over a path with a mean delay of 100us, to a master 10us
+        // behind us, with sync and delay request timestamps t1 to t4
+        let measure = |delay_asymmetry, t2, t4| {
+            let mut state =
+                SlaveState::new(Default::default()).with_delay_asymmetry(delay_asymmetry);
+
+            let mut action = state.handle_event_receive(
+                Message::Sync(SyncMessage {
+                    header: Header {
+                        two_step_flag: false,
+                        ..Default::default()
+                    },
+                    origin_timestamp: Time::from_micros(0).into(),
+                }),
+                Time::from_micros(t2),
+                TimestampSource::Hardware,
+            );
+            assert!(action.next().is_none());
+            drop(action);
+
+            let mut buffer = [0u8; MAX_DATA_LEN];
+            let default_ds = test_default_ds();
+            let port_config = PortConfig {
+                delay_asymmetry,
+                ..test_config()
+            };
+
+            let mut rng = rand::rngs::mock::StepRng::new(2, 1);
+            let mut action = state.send_delay_request(
+                &mut rng,
+                &port_config,
+                Default::default(),
+                &default_ds,
+                1.0,
+                None,
+                &mut buffer,
+            );
+            let Some(PortAction::ResetDelayRequestTimer { .. }) = action.next() else {
+                panic!("Unexpected action");
+            };
+            let Some(PortAction::SendTimeCritical { context, data, .. }) = action.next() else {
+                panic!("Unexpected action");
+            };
+            drop(action);
+            let Message::DelayReq(req) = Message::deserialize(data).unwrap() else {
+                panic!("Incorrect message type");
+            };
+
+            let mut action =
+                state.handle_timestamp(context, Time::from_micros(200), TimestampSource::Hardware);
+            assert!(action.next().is_none());
+            drop(action);
+
+            state.handle_general_receive(
+                Message::DelayResp(DelayRespMessage {
+                    header: Header {
+                        sequence_id: req.header.sequence_id,
+                        ..Default::default()
+                    },
+                    receive_timestamp: Time::from_micros(t4).into(),
+                    requesting_port_identity: req.header.source_port_identity,
+                }),
+                PortIdentity::default(),
+            );
+
+            let measurement = state.extract_measurement().unwrap();
+            (state.mean_delay.unwrap(), measurement.master_offset)
+        };
+        let asymmetry = |slower, excess| {
+            PortConfig::delay_asymmetry_from(slower, Duration::from_micros(excess))
+        };
+        let expected = (Duration::from_micros(100), Duration::from_micros(10));
+
+        // Symmetric path, 100us each way
+        assert_eq!(measure(Duration::ZERO, 110, 290), expected);
+
+        // Master to slave takes 120us, slave to master 80us
+        let master_to_slave = asymmetry(PathDirection::MasterToSlave, 40);
+        assert_eq!(master_to_slave, Duration::from_micros(20));
+        assert_eq!(measure(master_to_slave, 130, 270), expected);
+        // Ignoring the asymmetry puts half of it in the offset
+        assert_eq!(
+            measure(Duration::ZERO, 130, 270),
+            (Duration::from_micros(100), Duration::from_micros(30))
+        );
+
+        // Master to slave takes 80us, slave to master 120us
+        let slave_to_master = asymmetry(PathDirection::SlaveToMaster, 40);
+        assert_eq!(slave_to_master, Duration::from_micros(-20));
+        assert_eq!(measure(slave_to_master, 90, 310), expected);
+        assert_eq!(
+            measure(Duration::ZERO, 90, 310),
+            (Duration::from_micros(100), Duration::from_micros(-10))
+        );
+
+        // Without delay measurement the assumed path delay is the mean delay
+        let mut state = SlaveState::with_delay_mechanism(
+            Default::default(),
+            DelayMechanism::OneWay {
+                path_delay: Duration::from_micros(100),
+            },
+        )
+        .with_delay_asymmetry(master_to_slave);
+        let mut action = state.handle_event_receive(
+            Message::Sync(SyncMessage {
+                header: Header {
+                    two_step_flag: false,
+                    ..Default::default()
+                },
+                origin_timestamp: Time::from_micros(0).into(),
+            }),
+            Time::from_micros(130),
+            TimestampSource::Hardware,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+        assert_eq!(
+            state.extract_measurement().map(|m| m.master_offset),
+            Some(Duration::from_micros(10))
+        );
+    }
+
+    #[test]
+    fn test_measurement_timestamp_source() {
+        let mut state = SlaveState::new(Default::default());
+        let sync = |sequence_id| {
+            Message::Sync(SyncMessage {
+                header: Header {
+                    sequence_id,
+                    ..Default::default()
+                },
+                origin_timestamp: Time::from_micros(0).into(),
+            })
+        };
+
+        let mut action =
+            state.handle_event_receive(sync(1), Time::from_micros(50), TimestampSource::Hardware);
+        assert!(action.next().is_none());
+        drop(action);
+
+        let mut buffer = [0u8; MAX_DATA_LEN];
+        let default_ds = test_default_ds();
+        let port_config = test_config();
+
+        let mut rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut action = state.send_delay_request(
+            &mut rng,
+            &port_config,
+            Default::default(),
+            &default_ds,
+            1.0,
+            None,
+            &mut buffer,
+        );
+        let Some(PortAction::ResetDelayRequestTimer { .. }) = action.next() else {
+            panic!("Unexpected action");
+        };
+        let Some(PortAction::SendTimeCritical { context, data, .. }) = action.next() else {
+            panic!("Unexpected action");
+        };
+        drop(action);
+
+        let req = match Message::deserialize(data).unwrap() {
+            Message::DelayReq(msg) => msg,
+            _ => panic!("Incorrect message type"),
+        };
+
+        // The delay request was timestamped in software only
+        let mut action =
+            state.handle_timestamp(context, Time::from_micros(100), TimestampSource::Software);
+        assert!(action.next().is_none());
+        drop(action);
+
+        state.handle_general_receive(
+            Message::DelayResp(DelayRespMessage {
+                header: Header {
+                    sequence_id: req.header.sequence_id,
+                    ..Default::default()
+                },
+                receive_timestamp: Time::from_micros(250).into(),
+                requesting_port_identity: req.header.source_port_identity,
+            }),
+            PortIdentity::default(),
+        );
+
+        assert_eq!(
+            state.extract_measurement().map(|m| m.timestamp_source),
+            Some(TimestampSource::Software)
+        );
+
+        // The mean delay used by later measurements still is based on it
+        let mut action =
+            state.handle_event_receive(sync(2), Time::from_micros(1050), TimestampSource::Hardware);
+        assert!(action.next().is_none());
+        drop(action);
+        assert_eq!(
+            state.extract_measurement().map(|m| m.timestamp_source),
+            Some(TimestampSource::Software)
+        );
+    }
+
+    #[test]
+    fn test_sub_nanosecond_precision() {
+        let mut state = SlaveState::new(Default::default());
+        let subnanos = |nanos: f64| TimeInterval(fixed::types::I48F16::from_num(nanos));
+
+        // t2 = 50000.75 - 0.25 = 50000.5ns
+        let mut action = state.handle_event_receive(
+            Message::Sync(SyncMessage {
+                header: Header {
+                    two_step_flag: true,
+                    sequence_id: 1,
+                    correction_field: subnanos(0.25),
+                    ..Default::default()
+                },
+                origin_timestamp: Time::from_micros(0).into(),
+            }),
+            Time::from_fixed_nanos(50_000.75f64),
+            TimestampSource::Hardware,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+
+        // t1 = 1000 + 0.125 = 1000.125ns
+        state.handle_general_receive(
+            Message::FollowUp(FollowUpMessage {
+                header: Header {
+                    sequence_id: 1,
+                    correction_field: subnanos(0.125),
+                    ..Default::default()
+                },
+                precise_origin_timestamp: Time::from_nanos(1000).into(),
+                information: None,
+            }),
+            PortIdentity::default(),
+        );
+
+        let mut buffer = [0u8; MAX_DATA_LEN];
+        let default_ds = test_default_ds();
+        let port_config = test_config();
+
+        let mut rng = rand::rngs::mock::StepRng::new(2, 1);
+        let mut action = state.send_delay_request(
+            &mut rng,
+            &port_config,
+            Default::default(),
+            &default_ds,
+            1.0,
+            None,
+            &mut buffer,
+        );
+        let Some(PortAction::ResetDelayRequestTimer { .. }) = action.next() else {
+            panic!("Unexpected action");
+        };
+        let Some(PortAction::SendTimeCritical { context, data, .. }) = action.next() else {
+            panic!("Unexpected action");
+        };
+        drop(action);
+
+        let req = match Message::deserialize(data).unwrap() {
+            Message::DelayReq(msg) => msg,
+            _ => panic!("Incorrect message type"),
+        };
+
+        // t3 = 60000.0625ns
+        let mut action = state.handle_timestamp(
+            context,
+            Time::from_fixed_nanos(60_000.062_5f64),
+            TimestampSource::Hardware,
+        );
+        assert!(action.next().is_none());
+        drop(action);
+
+        // t4 = 110000 - 0.5 = 109999.5ns
+        state.handle_general_receive(
+            Message::DelayResp(DelayRespMessage {
+                header: Header {
+                    sequence_id: req.header.sequence_id,
+                    correction_field: subnanos(0.5),
+                    ..Default::default()
+                },
+                receive_timestamp: Time::from_nanos(110_000).into(),
+                requesting_port_identity: req.header.source_port_identity,
+            }),
+            PortIdentity::default(),
+        );
+
+        // ((t4 - t3) + (t2 - t1)) / 2 = (49999.4375 + 49000.375) / 2
+        assert_eq!(
+            state.mean_delay,
+            Some(Duration::from_fixed_nanos(49_499.906_25f64))
+        );
+        // (t2 - t1) - delay
+        assert_eq!(
+            state.extract_measurement(),
+            Some(Measurement {
+                event_time: Time::from_fixed_nanos(50_000.5f64),
+                master_offset: Duration::from_fixed_nanos(-499.531_25f64),
+                raw_receive_time: Time::from_fixed_nanos(50_000.75f64),
+                receive_time: Time::from_fixed_nanos(50_000.75f64),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    sub_nanosecond: Duration::from_fixed_nanos(0.375),
+                    path_delay: Duration::from_fixed_nanos(49499.90625),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
             })
         );
     }
@@ -628,6 +1831,7 @@ mod tests {
                     ..Default::default()
                 },
                 precise_origin_timestamp: Time::from_micros(10).into(),
+                information: None,
             }),
             PortIdentity::default(),
         );
@@ -645,6 +1849,7 @@ mod tests {
                 origin_timestamp: Time::from_micros(0).into(),
             }),
             Time::from_micros(50),
+            TimestampSource::Hardware,
         );
 
         assert!(action.next().is_none());
@@ -652,7 +1857,17 @@ mod tests {
             state.extract_measurement(),
             Some(Measurement {
                 event_time: Time::from_micros(49),
-                master_offset: Duration::from_micros(-63)
+                master_offset: Duration::from_micros(-63),
+                raw_receive_time: Time::from_micros(50),
+                receive_time: Time::from_micros(50),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    residence_time: Duration::from_micros(3),
+                    path_delay: Duration::from_micros(100),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
             })
         );
     }
@@ -674,6 +1889,7 @@ mod tests {
                 origin_timestamp: Time::from_micros(0).into(),
             }),
             Time::from_micros(50),
+            TimestampSource::Hardware,
         );
 
         assert!(action.next().is_none());
@@ -687,6 +1903,7 @@ mod tests {
                     ..Default::default()
                 },
                 precise_origin_timestamp: Time::from_micros(10).into(),
+                information: None,
             }),
             PortIdentity::default(),
         );
@@ -701,6 +1918,7 @@ mod tests {
                     ..Default::default()
                 },
                 precise_origin_timestamp: Time::from_micros(10).into(),
+                information: None,
             }),
             PortIdentity::default(),
         );
@@ -725,6 +1943,7 @@ mod tests {
                 origin_timestamp: Time::from_micros(0).into(),
             }),
             Time::from_micros(50),
+            TimestampSource::Hardware,
         );
 
         assert!(action.next().is_none());
@@ -742,6 +1961,7 @@ mod tests {
                 origin_timestamp: Time::from_micros(0).into(),
             }),
             Time::from_micros(1050),
+            TimestampSource::Hardware,
         );
 
         assert!(action.next().is_none());
@@ -755,6 +1975,7 @@ mod tests {
                     ..Default::default()
                 },
                 precise_origin_timestamp: Time::from_micros(1000).into(),
+                information: None,
             }),
             PortIdentity::default(),
         );
@@ -763,7 +1984,17 @@ mod tests {
             state.extract_measurement(),
             Some(Measurement {
                 event_time: Time::from_micros(1049),
-                master_offset: Duration::from_micros(-53)
+                master_offset: Duration::from_micros(-53),
+                raw_receive_time: Time::from_micros(1050),
+                receive_time: Time::from_micros(1050),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    residence_time: Duration::from_micros(3),
+                    path_delay: Duration::from_micros(100),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
             })
         );
     }
@@ -782,6 +2013,7 @@ mod tests {
                 origin_timestamp: Time::from_micros(0).into(),
             }),
             Time::from_micros(50),
+            TimestampSource::Hardware,
         );
 
         // DelayReq is sent independently
@@ -789,34 +2021,20 @@ mod tests {
 
         let mut buffer = [0u8; MAX_DATA_LEN];
 
-        let default_ds = DefaultDS::new(InstanceConfig {
-            clock_identity: ClockIdentity::default(),
-            priority_1: 15,
-            priority_2: 128,
-            domain_number: 0,
-            slave_only: false,
-            sdo_id: SdoId::default(),
-        });
+        let default_ds = test_default_ds();
 
         // mock rng and port config
         let mut rng = rand::rngs::mock::StepRng::new(2, 1);
         let port_identity = Default::default();
-        let port_config = PortConfig {
-            delay_mechanism: DelayMechanism::E2E {
-                interval: Interval::ONE_SECOND,
-            },
-            announce_interval: Interval::ONE_SECOND,
-            announce_receipt_timeout: Default::default(),
-            sync_interval: Interval::ONE_SECOND,
-            master_only: Default::default(),
-            delay_asymmetry: Default::default(),
-        };
+        let port_config = test_config();
 
         let mut action = state.send_delay_request(
             &mut rng,
             &port_config,
             port_identity,
             &default_ds,
+            1.0,
+            None,
             &mut buffer,
         );
 
@@ -824,11 +2042,12 @@ mod tests {
             panic!("Unexpected action");
         };
 
-        let Some(PortAction::SendTimeCritical { context, data }) = action.next() else {
+        let Some(PortAction::SendTimeCritical { context, data, .. }) = action.next() else {
             panic!("Unexpected action");
         };
 
-        let mut action = state.handle_timestamp(context, Time::from_micros(100));
+        let mut action =
+            state.handle_timestamp(context, Time::from_micros(100), TimestampSource::Hardware);
 
         assert!(action.next().is_none());
 
@@ -856,6 +2075,28 @@ mod tests {
         );
 
         assert_eq!(state.extract_measurement(), None);
+        assert_eq!(state.delay_resp_rejections.requesting_port, 1);
+
+        // A spoofed response from another master
+        state.handle_general_receive(
+            Message::DelayResp(DelayRespMessage {
+                header: Header {
+                    correction_field: TimeInterval(2000.into()),
+                    sequence_id: req.header.sequence_id,
+                    source_port_identity: PortIdentity {
+                        port_number: 9,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                receive_timestamp: Time::from_micros(353).into(),
+                requesting_port_identity: req.header.source_port_identity,
+            }),
+            PortIdentity::default(),
+        );
+
+        assert_eq!(state.extract_measurement(), None);
+        assert_eq!(state.delay_resp_rejections.source, 1);
 
         state.handle_general_receive(
             Message::DelayResp(DelayRespMessage {
@@ -871,6 +2112,7 @@ mod tests {
         );
 
         assert_eq!(state.extract_measurement(), None);
+        assert_eq!(state.delay_resp_rejections.sequence_id, 1);
 
         state.handle_general_receive(
             Message::DelayResp(DelayRespMessage {
@@ -886,13 +2128,191 @@ mod tests {
         );
 
         assert_eq!(state.mean_delay, Some(Duration::from_micros(100)));
+        assert_eq!(
+            state.delay_resp_rejections,
+            DelayRespRejections {
+                requesting_port: 1,
+                source: 1,
+                sequence_id: 1,
+            }
+        );
 
         assert_eq!(
             state.extract_measurement(),
             Some(Measurement {
                 event_time: Time::from_micros(49),
-                master_offset: Duration::from_micros(-51)
+                master_offset: Duration::from_micros(-51),
+                raw_receive_time: Time::from_micros(50),
+                receive_time: Time::from_micros(50),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    residence_time: Duration::from_micros(1),
+                    path_delay: Duration::from_micros(100),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
             })
         );
     }
+
+    #[test]
+    fn test_delay_resp_sequence_id_quirk() {
+        let mut state = SlaveState::new(Default::default()).with_quirks(Quirks {
+            delay_resp_sequence_id: true,
+            ..Quirks::DEFAULT
+        });
+
+        let mut action = state.handle_event_receive(
+            Message::Sync(SyncMessage {
+                header: Header {
+                    two_step_flag: false,
+                    correction_field: TimeInterval(1000.into()),
+                    ..Default::default()
+                },
+                origin_timestamp: Time::from_micros(0).into(),
+            }),
+            Time::from_micros(50),
+            TimestampSource::Hardware,
+        );
+        assert!(action.next().is_none());
+
+        let mut buffer = [0u8; MAX_DATA_LEN];
+        let default_ds = test_default_ds();
+        let mut rng = rand::rngs::mock::StepRng::new(2, 1);
+        let port_config = test_config();
+
+        let mut action = state.send_delay_request(
+            &mut rng,
+            &port_config,
+            Default::default(),
+            &default_ds,
+            1.0,
+            None,
+            &mut buffer,
+        );
+        let Some(PortAction::ResetDelayRequestTimer { .. }) = action.next() else {
+            panic!("Unexpected action");
+        };
+        let Some(PortAction::SendTimeCritical { context, data, .. }) = action.next() else {
+            panic!("Unexpected action");
+        };
+        let req = match Message::deserialize(data).unwrap() {
+            Message::DelayReq(msg) => msg,
+            _ => panic!("Incorrect message type"),
+        };
+        state.handle_timestamp(context, Time::from_micros(100), TimestampSource::Hardware);
+
+        // The master answers with the wrong sequence id
+        let delay_resp = |receive_timestamp| {
+            Message::DelayResp(DelayRespMessage {
+                header: Header {
+                    correction_field: TimeInterval(2000.into()),
+                    sequence_id: req.header.sequence_id.wrapping_add(7),
+                    ..Default::default()
+                },
+                receive_timestamp: Time::from_micros(receive_timestamp).into(),
+                requesting_port_identity: req.header.source_port_identity,
+            })
+        };
+        state.handle_general_receive(delay_resp(253), PortIdentity::default());
+
+        assert_eq!(state.quirks_triggered.delay_resp_sequence_id, 1);
+        assert_eq!(state.mean_delay, Some(Duration::from_micros(100)));
+        assert_eq!(
+            state.extract_measurement(),
+            Some(Measurement {
+                event_time: Time::from_micros(49),
+                master_offset: Duration::from_micros(-51),
+                raw_receive_time: Time::from_micros(50),
+                receive_time: Time::from_micros(50),
+                timestamp_source: TimestampSource::Hardware,
+                correction: CorrectionBreakdown {
+                    residence_time: Duration::from_micros(1),
+                    path_delay: Duration::from_micros(100),
+                    ..Default::default()
+                },
+                follow_up_information: None,
+                neighbor_rate_ratio: None,
+            })
+        );
+
+        // Only one response per request is accepted
+        state.handle_general_receive(delay_resp(353), PortIdentity::default());
+        assert_eq!(state.quirks_triggered.delay_resp_sequence_id, 1);
+        assert_eq!(state.mean_delay, Some(Duration::from_micros(100)));
+    }
+
+    fn delay_req_duration(state: &mut SlaveState) -> core::time::Duration {
+        let default_ds = test_default_ds();
+        let port_config = test_config();
+
+        // Samples halfway the random range, so the timer is set to the interval
+        let mut rng = rand::rngs::mock::StepRng::new(u64::MAX / 2, 0);
+        let mut buffer = [0u8; MAX_DATA_LEN];
+        let mut actions = state.send_delay_request(
+            &mut rng,
+            &port_config,
+            Default::default(),
+            &default_ds,
+            1.0,
+            None,
+            &mut buffer,
+        );
+        let Some(PortAction::ResetDelayRequestTimer { duration }) = actions.next() else {
+            panic!("Unexpected action");
+        };
+        duration
+    }
+
+    #[test]
+    fn test_startup_burst() {
+        let mut state =
+            SlaveState::new(Default::default()).with_startup_burst(Some(StartupBurst {
+                duration: Duration::from_secs(10),
+                delay_req_interval: Interval::from_log_2(-4),
+            }));
+        let sync = |sequence_id| {
+            Message::Sync(SyncMessage {
+                header: Header {
+                    sequence_id,
+                    ..Default::default()
+                },
+                origin_timestamp: Time::from_secs(0).into(),
+            })
+        };
+
+        assert!(state.in_startup_burst());
+        let duration = delay_req_duration(&mut state);
+        assert!(
+            duration
+                .abs_diff(core::time::Duration::from_micros(62_500))
+                .as_millis()
+                < 1
+        );
+
+        // The master decides how fast we may go
+        state.handle_event_receive(sync(1), Time::from_secs(1), TimestampSource::Hardware);
+        state.master_delay_req_interval = Some(Interval::from_log_2(-2));
+        let duration = delay_req_duration(&mut state);
+        assert!(
+            duration
+                .abs_diff(core::time::Duration::from_millis(250))
+                .as_millis()
+                < 1
+        );
+
+        // Ten seconds after the first sync, the configured interval is used
+        state.handle_event_receive(sync(2), Time::from_secs(10), TimestampSource::Hardware);
+        assert!(state.in_startup_burst());
+        state.handle_event_receive(sync(3), Time::from_secs(11), TimestampSource::Hardware);
+        assert!(!state.in_startup_burst());
+        let duration = delay_req_duration(&mut state);
+        assert!(
+            duration
+                .abs_diff(core::time::Duration::from_secs(1))
+                .as_millis()
+                < 1
+        );
+    }
 }
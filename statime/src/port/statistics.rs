@@ -0,0 +1,1229 @@
+use arrayvec::ArrayVec;
+
+use super::{AnnounceContent, PortStateKind, TimestampSource};
+use crate::{
+    datastructures::messages::MessageType,
+    time::{Duration, Interval, Time},
+    ClockIdentity,
+};
+
+/// Counters and annotations about the operation of a single port.
+///
+/// These are kept up to date by the port itself and can be read at any time
+/// through [`Port::statistics`](crate::Port::statistics).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PortStatistics {
+    /// Number of times the port discarded its measurement state because the
+    /// source of its timestamps changed.
+    pub clock_source_changes: u32,
+    /// Local time at which the port last noticed a change of timestamp source.
+    pub last_clock_source_change: Option<Time>,
+    /// Time between receiving a delay request and handing the matching delay
+    /// response to the runtime for transmission, measured while master.
+    pub delay_resp_turnaround: DurationStatistics,
+    /// Number of measurements dropped from the measurement queue because the
+    /// runtime didn't take them out in time.
+    pub measurements_dropped: u32,
+    /// Number of messages ignored because they had the unicast flag set while
+    /// they were sent to a multicast group, see
+    /// [`PacketArrival`](crate::PacketArrival).
+    pub unexpected_unicast_messages: u32,
+    /// Number of messages ignored because they didn't have the unicast flag
+    /// set while they were sent to the port itself.
+    pub unexpected_multicast_messages: u32,
+    /// Number of events dropped from the event queue because the runtime
+    /// didn't take them out in time.
+    pub events_dropped: u32,
+    /// Number of timestamps the runtime handed to the port, by source.
+    pub timestamp_sources: TimestampSourceCounts,
+    /// Number of measurements the port produced, by the least precise source
+    /// of the timestamps they are based on. Only the `hardware` count going up
+    /// confirms hardware timestamping is in effect end-to-end.
+    pub measurement_sources: TimestampSourceCounts,
+    /// Number of times each interoperability workaround was needed, see
+    /// [`Quirks`](crate::Quirks).
+    pub quirks: QuirkCounts,
+    /// Number of DelayResp messages ignored while slave, by reason
+    pub delay_resp_rejections: DelayRespRejections,
+    /// Number of Sync and FollowUp messages ignored while slave because they
+    /// came from another master than the one selected by the BMCA.
+    pub non_parent_sync_messages: u32,
+    /// Number of messages the runtime reported it could not send, see
+    /// [`Port::handle_send_failure`](crate::Port::handle_send_failure).
+    pub send_failures: u32,
+    /// Number of messages ignored because another clock sent them with the
+    /// clock identity of this instance.
+    pub identity_collisions: u32,
+    /// Number of announce messages of the master of this slave port that
+    /// advertised another profile than the instance, see
+    /// [`PtpInstance::set_profile_identifier`](crate::PtpInstance::set_profile_identifier).
+    pub profile_mismatches: u32,
+    /// Number of messages the port could not serialize, see
+    /// [`Diagnostic::SerializationFailed`](crate::Diagnostic::SerializationFailed).
+    pub serialization_failures: u32,
+    /// Number of times the port ran into something that indicates a bug in
+    /// statime, see
+    /// [`Diagnostic::InternalError`](crate::Diagnostic::InternalError).
+    pub internal_errors: u32,
+    /// What happened to the requests for unicast messages this port received
+    /// as master, see
+    /// [`Port::set_unicast_master`](crate::Port::set_unicast_master).
+    pub unicast_grants: UnicastGrantCounts,
+    /// What happened to the requests for unicast messages this port sent as
+    /// client, see
+    /// [`Port::set_unicast_client`](crate::Port::set_unicast_client).
+    pub unicast_requests: UnicastRequestCounts,
+    /// The last announce message the port sent as master. It is kept when
+    /// the port stops being master.
+    pub last_announce: Option<AnnounceContent>,
+    /// How often the port receives and sends the messages of each type, to
+    /// compare with the configured intervals.
+    pub message_rates: MessageRates,
+    /// Number of messages that failed authentication, see
+    /// [`Port::set_authentication`](crate::Port::set_authentication).
+    pub authentication: AuthenticationFailures,
+}
+
+/// Counts of things tagged with a [`TimestampSource`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampSourceCounts {
+    pub hardware: u32,
+    pub software: u32,
+    pub legacy: u32,
+}
+
+impl TimestampSourceCounts {
+    pub(crate) fn record(&mut self, source: TimestampSource) {
+        let count = match source {
+            TimestampSource::Hardware => &mut self.hardware,
+            TimestampSource::Software => &mut self.software,
+            TimestampSource::Legacy => &mut self.legacy,
+        };
+        *count = count.saturating_add(1);
+    }
+}
+
+/// Number of times each of the [`Quirks`](crate::Quirks) was needed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QuirkCounts {
+    pub delay_resp_sequence_id: u32,
+    pub follow_up_before_sync: u32,
+}
+
+impl QuirkCounts {
+    pub(crate) fn add(&mut self, other: &QuirkCounts) {
+        self.delay_resp_sequence_id = self
+            .delay_resp_sequence_id
+            .saturating_add(other.delay_resp_sequence_id);
+        self.follow_up_before_sync = self
+            .follow_up_before_sync
+            .saturating_add(other.follow_up_before_sync);
+    }
+}
+
+/// Number of DelayResp messages a slave port ignored, by reason. A message
+/// is counted for the first reason that applies, in the order of the fields.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DelayRespRejections {
+    /// The response was to a DelayReq of another port. This is normal on a
+    /// multicast network with several slaves.
+    pub requesting_port: u32,
+    /// The response didn't come from the master of the port
+    pub source: u32,
+    /// The response didn't match the sequence id of the outstanding
+    /// DelayReq, or no DelayReq was outstanding
+    pub sequence_id: u32,
+}
+
+impl DelayRespRejections {
+    pub(crate) fn add(&mut self, other: &DelayRespRejections) {
+        self.requesting_port = self.requesting_port.saturating_add(other.requesting_port);
+        self.source = self.source.saturating_add(other.source);
+        self.sequence_id = self.sequence_id.saturating_add(other.sequence_id);
+    }
+}
+
+/// Number of messages that failed authentication, by reason. Received
+/// messages are counted once they pass the domain and unicast checks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AuthenticationFailures {
+    /// Received messages that didn't end with an AUTHENTICATION TLV with an
+    /// ICV of the configured length
+    pub missing: u32,
+    /// Received messages with another security parameters pointer, optional
+    /// fields, or a key that is unknown or not valid at the time
+    pub unknown: u32,
+    /// Received messages with an ICV that didn't match their contents
+    pub invalid: u32,
+    /// Authentic messages with a sequence id that was seen before, see
+    /// [`AuthenticationConfig::replay_protection`](crate::AuthenticationConfig::replay_protection)
+    pub replayed: u32,
+    /// Authentic messages with a sequence id too far behind the latest of
+    /// their source
+    pub outside_window: u32,
+    /// Messages the port didn't send because the TLV could not be added,
+    /// because no key was valid to send with or the message got too long
+    pub unsealed: u32,
+    /// Sources whose sequence ids were forgotten before
+    /// [`REPLAY_TIMEOUT_SECONDS`](crate::REPLAY_TIMEOUT_SECONDS) to make room
+    /// for another. Replays of their messages go unnoticed, so this should
+    /// stay zero with a large enough table.
+    pub replay_evictions: u32,
+}
+
+/// Number of unicast grants a master port gave, and how they ended
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UnicastGrantCounts {
+    /// Requests of new clients that were granted
+    pub granted: u32,
+    /// Requests of clients with a grant that renewed it
+    pub renewed: u32,
+    /// Requests that were denied, because the port is not master, has no
+    /// room for more clients, or can't send at the requested interval
+    pub denied: u32,
+    /// Grants ended because the client cancelled them
+    pub cancelled: u32,
+    /// Grants ended because the client didn't renew them in time
+    pub expired: u32,
+    /// Grants ended by this port, see
+    /// [`Port::cancel_unicast_grants`](crate::Port::cancel_unicast_grants)
+    pub revoked: u32,
+    /// Ends of grants that the client acknowledged
+    pub acknowledged: u32,
+}
+
+/// Number of unicast requests a client port sent, and how the masters
+/// answered them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UnicastRequestCounts {
+    /// Requests sent, including renewals and retries
+    pub requested: u32,
+    /// Requests the master granted
+    pub granted: u32,
+    /// Requests the master denied
+    pub denied: u32,
+    /// Grants the master cancelled
+    pub cancelled: u32,
+    /// Grants that ended because they could not be renewed in time
+    pub expired: u32,
+    /// Times the port started requesting sync messages from another master of
+    /// its table, because the BMCA selected that master as parent
+    pub master_changes: u32,
+}
+
+/// Rates of the messages a port received and sent, by message type.
+///
+/// Received messages are counted once they pass the domain and unicast
+/// checks, whether or not the port acts on them. Sent messages are counted
+/// when the port hands them to the runtime.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MessageRates {
+    pub received: MessageTypeRates,
+    pub sent: MessageTypeRates,
+}
+
+/// A [`MessageRate`] for each of the message types that are sent at a
+/// configured interval
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MessageTypeRates {
+    pub sync: MessageRate,
+    pub announce: MessageRate,
+    pub delay_req: MessageRate,
+    pub pdelay_req: MessageRate,
+}
+
+impl MessageTypeRates {
+    pub(crate) fn record(&mut self, message_type: MessageType, time: Time) {
+        let rate = match message_type {
+            MessageType::Sync => &mut self.sync,
+            MessageType::Announce => &mut self.announce,
+            MessageType::DelayReq => &mut self.delay_req,
+            MessageType::PDelayReq => &mut self.pdelay_req,
+            _ => return,
+        };
+        rate.record(time);
+    }
+}
+
+/// The rate of a stream of messages, from an exponentially weighted moving
+/// average of the time between them.
+///
+/// Each new interval moves the average by 1/8th of its difference with the
+/// average, so the rate follows a change of interval within a few dozen
+/// messages. The rate is not updated while no messages arrive, which
+/// [`last`](Self::last) shows.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MessageRate {
+    /// Local time of the most recent message
+    pub last: Option<Time>,
+    mean_interval: Option<Duration>,
+}
+
+impl MessageRate {
+    const SMOOTHING: i32 = 8;
+
+    pub(crate) fn record(&mut self, time: Time) {
+        if let Some(last) = self.last {
+            let interval = time - last;
+            // Time going backwards, like after a clock step, says nothing
+            // about the rate
+            if interval > Duration::ZERO {
+                self.mean_interval = Some(match self.mean_interval {
+                    Some(mean) => mean + (interval - mean) / Self::SMOOTHING,
+                    None => interval,
+                });
+            }
+        }
+        self.last = Some(time);
+    }
+
+    /// The average time between messages, once there were two of them
+    pub fn mean_interval(&self) -> Option<Duration> {
+        self.mean_interval
+    }
+
+    /// The average number of messages per second, once there were two of
+    /// them
+    pub fn per_second(&self) -> Option<f64> {
+        self.mean_interval
+            .map(|interval| 1_000_000_000.0 / interval.nanos_lossy())
+    }
+}
+
+/// A client a master port sends unicast sync messages to, see
+/// [`Port::unicast_sync_clients`](crate::Port::unicast_sync_clients)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnicastSyncClient {
+    /// Clock identity of the client
+    pub clock_identity: ClockIdentity,
+    /// Port number of the client
+    pub port_number: u16,
+    /// The granted interval between sync messages
+    pub interval: Interval,
+    /// Time from when each sync message was due until its send timestamp.
+    /// Sync messages to clients that are due at the same time are spread
+    /// over the interval, which shows up here too.
+    pub send_latency: DurationStatistics,
+}
+
+/// Summary of a series of measured durations
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DurationStatistics {
+    /// Number of durations recorded, up to `u32::MAX`
+    pub count: u32,
+    /// The most recently recorded duration
+    pub last: Duration,
+    /// The shortest duration recorded
+    pub min: Duration,
+    /// The longest duration recorded
+    pub max: Duration,
+    /// Sum of the durations counted in `count`
+    pub total: Duration,
+}
+
+impl DurationStatistics {
+    pub(crate) fn record(&mut self, duration: Duration) {
+        if self.count == 0 {
+            self.min = duration;
+            self.max = duration;
+        } else {
+            self.min = self.min.min(duration);
+            self.max = self.max.max(duration);
+        }
+
+        self.last = duration;
+        // Past the maximum count the total stops growing too, so the mean
+        // stays that of the durations counted
+        if let Some(count) = self.count.checked_add(1) {
+            self.count = count;
+            self.total += duration;
+        }
+    }
+
+    /// The average of all recorded durations, if any
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total / self.count)
+    }
+}
+
+/// Maximum number of observation intervals the time error metrics can be
+/// computed for
+pub const MAX_OBSERVATION_INTERVALS: usize = 8;
+
+/// Reasons a set of observation intervals can be rejected
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TimeErrorConfigError {
+    /// More than [`MAX_OBSERVATION_INTERVALS`] intervals were given
+    TooManyIntervals(usize),
+    /// The interval is zero, or too long to compute TDEV from the number of
+    /// samples kept, see [`TimeErrorStatistics::max_interval`]
+    InvalidInterval(usize),
+}
+
+impl core::fmt::Display for TimeErrorConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TimeErrorConfigError::TooManyIntervals(count) => write!(
+                f,
+                "at most {MAX_OBSERVATION_INTERVALS} observation intervals are supported, but \
+                 {count} were given"
+            ),
+            TimeErrorConfigError::InvalidInterval(interval) => write!(
+                f,
+                "observation interval {interval} must be at least 1, and at most a third of the \
+                 number of samples kept"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TimeErrorConfigError {}
+
+/// Time error metrics for a single observation interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeErrorMetrics {
+    /// The observation interval, as a number of measurements
+    pub observation_interval: usize,
+    /// Maximum time interval error, if enough samples were collected
+    pub mtie: Option<Duration>,
+    /// Time deviation, if enough samples were collected
+    pub tdev: Option<Duration>,
+}
+
+/// MTIE and TDEV over the offsets from the master measured by a port.
+///
+/// The offsets of the last measurements are kept, and the metrics are
+/// computed over them on request. Observation intervals are given as a number
+/// of measurements, so with one measurement per sync message an interval of
+/// `n` spans `n` sync intervals.
+///
+/// Nothing is recorded until observation intervals are configured through
+/// [`Port::set_time_error_intervals`](crate::Port::set_time_error_intervals),
+/// with a buffer for the offsets provided by the user. Its length is the
+/// number of offsets kept, so ports that don't compute the metrics use no
+/// memory for them. Without the standard library, the buffer can be a
+/// `static`:
+///
+/// ```
+/// static mut TIME_ERROR_SAMPLES: [f64; 256] = [0.0; 256];
+/// ```
+#[derive(Debug)]
+pub struct TimeErrorStatistics {
+    intervals: ArrayVec<usize, MAX_OBSERVATION_INTERVALS>,
+    // Ring buffer of offsets in nanoseconds, the oldest at `next` once full
+    samples: &'static mut [f64],
+    len: usize,
+    next: usize,
+}
+
+impl Default for TimeErrorStatistics {
+    fn default() -> Self {
+        Self {
+            intervals: ArrayVec::new(),
+            samples: &mut [],
+            len: 0,
+            next: 0,
+        }
+    }
+}
+
+impl TimeErrorStatistics {
+    pub(crate) fn set_intervals(
+        &mut self,
+        intervals: &[usize],
+        samples: &'static mut [f64],
+    ) -> Result<(), TimeErrorConfigError> {
+        if intervals.len() > MAX_OBSERVATION_INTERVALS {
+            return Err(TimeErrorConfigError::TooManyIntervals(intervals.len()));
+        }
+
+        // TDEV over an interval of n needs at least 3n samples
+        let max_interval = samples.len() / 3;
+        if let Some(&interval) = intervals
+            .iter()
+            .find(|&&interval| interval == 0 || interval > max_interval)
+        {
+            return Err(TimeErrorConfigError::InvalidInterval(interval));
+        }
+
+        self.intervals.clear();
+        self.intervals.extend(intervals.iter().copied());
+        self.samples = samples;
+        self.clear();
+        Ok(())
+    }
+
+    /// Longest supported observation interval, as TDEV over an interval of
+    /// `n` needs at least `3n` samples
+    pub fn max_interval(&self) -> usize {
+        self.samples.len() / 3
+    }
+
+    /// The configured observation intervals
+    pub fn intervals(&self) -> &[usize] {
+        &self.intervals
+    }
+
+    /// Number of samples currently kept
+    pub fn samples(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn record(&mut self, offset: Duration) {
+        if self.intervals.is_empty() {
+            return;
+        }
+
+        let capacity = self.samples.len();
+        self.samples[self.next] = offset.nanos_lossy();
+        self.next = (self.next + 1) % capacity;
+        self.len = (self.len + 1).min(capacity);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+
+    fn sample(&self, index: usize) -> f64 {
+        let capacity = self.samples.len();
+        let oldest = (self.next + capacity - self.len) % capacity;
+        self.samples[(oldest + index) % capacity]
+    }
+
+    /// Maximum time interval error over an observation interval of `n`
+    /// measurements: the largest peak-to-peak offset within any `n + 1`
+    /// consecutive samples.
+    pub fn mtie(&self, n: usize) -> Option<Duration> {
+        if n == 0 || self.len < n + 1 {
+            return None;
+        }
+
+        let mut mtie = 0.0f64;
+        for start in 0..self.len - n {
+            let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+            for index in start..=start + n {
+                let sample = self.sample(index);
+                min = min.min(sample);
+                max = max.max(sample);
+            }
+            mtie = mtie.max(max - min);
+        }
+
+        Some(Duration::from_fixed_nanos(mtie))
+    }
+
+    /// Time deviation over an observation interval of `n` measurements,
+    /// estimated from the kept samples as in ITU-T G.810.
+    pub fn tdev(&self, n: usize) -> Option<Duration> {
+        if n == 0 || self.len < 3 * n {
+            return None;
+        }
+
+        let count = self.len - 3 * n + 1;
+        let mut total = 0.0;
+        for start in 0..count {
+            let mut sum = 0.0;
+            for index in start..start + n {
+                sum +=
+                    self.sample(index + 2 * n) - 2.0 * self.sample(index + n) + self.sample(index);
+            }
+            total += sum * sum;
+        }
+
+        let n = n as f64;
+        let variance = total / (6.0 * n * n * count as f64);
+        Some(Duration::from_fixed_nanos(libm::sqrt(variance)))
+    }
+
+    /// The metrics for each of the configured observation intervals
+    pub fn metrics(&self) -> impl Iterator<Item = TimeErrorMetrics> + '_ {
+        self.intervals.iter().map(|&n| TimeErrorMetrics {
+            observation_interval: n,
+            mtie: self.mtie(n),
+            tdev: self.tdev(n),
+        })
+    }
+}
+
+/// Number of periods [`FrequencyStatistics`] keeps the mean frequency
+/// correction of
+pub const FREQUENCY_HISTORY_CAPACITY: usize = 60;
+
+/// Length of the periods [`FrequencyStatistics`] averages the frequency
+/// correction over, in seconds of the local clock
+pub const FREQUENCY_PERIOD_SECONDS: i64 = 60;
+
+/// A frequency correction of the local clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrequencyCorrection {
+    /// Local time at which the correction was applied, or at which the period
+    /// it is the mean of ended
+    pub time: Time,
+    /// How much faster the clock runs than its unadjusted oscillator, in
+    /// parts per billion. Negative when it runs slower.
+    pub ppb: f64,
+}
+
+/// The frequency corrections the servo applied to the local clock while this
+/// port was its source of time.
+///
+/// These follow the oscillator of the clock, so they show it aging over
+/// days and reacting to temperature within minutes. Besides the latest
+/// correction, the mean correction over each of the last
+/// [`FREQUENCY_HISTORY_CAPACITY`] periods of [`FREQUENCY_PERIOD_SECONDS`] is
+/// kept.
+///
+/// The correction is tracked by statime, so it is available for any
+/// [`Clock`](crate::Clock). It starts from the correction the clock reports
+/// through [`Clock::frequency_multiplier`](crate::Clock::frequency_multiplier),
+/// and from none when the clock doesn't report it.
+#[derive(Debug, Clone, Default)]
+pub struct FrequencyStatistics {
+    current: Option<FrequencyCorrection>,
+    history: ArrayVec<FrequencyCorrection, FREQUENCY_HISTORY_CAPACITY>,
+    change_threshold: Option<f64>,
+    // The period in progress
+    period_start: Option<Time>,
+    period_sum: f64,
+    period_count: u32,
+}
+
+impl FrequencyStatistics {
+    /// Record a correction applied at `time`. When this ends a period whose
+    /// mean differs from that of the period before by at least the change
+    /// threshold, returns both means.
+    pub(crate) fn record(&mut self, time: Time, ppb: f64) -> Option<(f64, f64)> {
+        self.current = Some(FrequencyCorrection { time, ppb });
+
+        let mut change = None;
+        match self.period_start {
+            // Time going backwards, like after a clock step, ends the period
+            // without a mean
+            Some(start) if time < start => self.start_period(time),
+            Some(start) if time - start >= Duration::from_secs(FREQUENCY_PERIOD_SECONDS) => {
+                let mean = self.period_sum / self.period_count as f64;
+                let previous = self.history.last().map(|correction| correction.ppb);
+                if self.history.is_full() {
+                    self.history.remove(0);
+                }
+                self.history.push(FrequencyCorrection { time, ppb: mean });
+
+                change = previous
+                    .zip(self.change_threshold)
+                    .and_then(|(previous, threshold)| {
+                        (libm::fabs(mean - previous) >= threshold).then_some((previous, mean))
+                    });
+                self.start_period(time);
+            }
+            Some(_) => {}
+            None => self.start_period(time),
+        }
+
+        self.period_sum += ppb;
+        self.period_count += 1;
+        change
+    }
+
+    fn start_period(&mut self, time: Time) {
+        self.period_start = Some(time);
+        self.period_sum = 0.0;
+        self.period_count = 0;
+    }
+
+    pub(crate) fn set_change_threshold(&mut self, ppb: Option<f64>) {
+        self.change_threshold = ppb;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        let change_threshold = self.change_threshold;
+        *self = Self {
+            change_threshold,
+            ..Default::default()
+        };
+    }
+
+    /// The latest frequency correction
+    pub fn current(&self) -> Option<FrequencyCorrection> {
+        self.current
+    }
+
+    /// The mean frequency correction over each of the recent periods, oldest
+    /// first
+    pub fn history(&self) -> &[FrequencyCorrection] {
+        &self.history
+    }
+}
+
+/// Number of closed windows [`StatisticsWindows`] keeps
+pub const STATISTICS_WINDOW_HISTORY: usize = 16;
+
+/// Number of offsets a window keeps to compute its percentiles from
+const WINDOW_SAMPLE_CAPACITY: usize = 256;
+
+/// The offsets from the master measured during one window of
+/// [`StatisticsWindows`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatisticsWindow {
+    /// Local time at which the window started, a whole multiple of its length
+    pub start: Time,
+    /// Local time at which the window ended, which is where the next one
+    /// starts
+    pub end: Time,
+    /// The offsets from the master measured in the window
+    pub offsets: DurationStatistics,
+    /// Median of the absolute offsets, if any were measured
+    pub median: Option<Duration>,
+    /// 90th percentile of the absolute offsets
+    pub p90: Option<Duration>,
+    /// 99th percentile of the absolute offsets
+    pub p99: Option<Duration>,
+}
+
+/// Statistics of the offsets from the master over consecutive windows of a
+/// fixed length, see
+/// [`Port::set_statistics_window`](crate::Port::set_statistics_window).
+///
+/// Windows start at whole multiples of their length in local time, so the
+/// windows of different ports and integrations line up no matter when they
+/// are read. A measurement past the end of the window in progress closes it
+/// first, and so does
+/// [`Port::handle_statistics_timer`](crate::Port::handle_statistics_timer) when
+/// no measurement came in. The last [`STATISTICS_WINDOW_HISTORY`]
+/// closed windows are kept.
+///
+/// Percentiles come from at most 256 of the offsets of a window. Once a
+/// window has more, every other one is dropped, so they stay spread over the
+/// whole window.
+#[derive(Debug, Clone, Default)]
+pub struct StatisticsWindows {
+    length: Option<u64>,
+    // The window in progress
+    start: Option<Time>,
+    offsets: DurationStatistics,
+    samples: ArrayVec<Duration, WINDOW_SAMPLE_CAPACITY>,
+    // Only every stride-th offset becomes a sample
+    stride: u32,
+    skipped: u32,
+    history: ArrayVec<StatisticsWindow, STATISTICS_WINDOW_HISTORY>,
+}
+
+impl StatisticsWindows {
+    /// Use windows of `seconds`, or none. Drops all windows so far.
+    pub(crate) fn set_length(&mut self, seconds: Option<u64>) {
+        *self = Self {
+            length: seconds.filter(|&seconds| seconds > 0),
+            ..Default::default()
+        };
+    }
+
+    pub(crate) fn record(&mut self, time: Time, offset: Duration) {
+        if self.roll(time).is_none() {
+            return;
+        }
+
+        self.offsets.record(offset);
+        self.skipped += 1;
+        if self.skipped < self.stride {
+            return;
+        }
+        self.skipped = 0;
+
+        if self.samples.is_full() {
+            let mut index = 0;
+            self.samples.retain(|_| {
+                index += 1;
+                index % 2 == 1
+            });
+            self.stride *= 2;
+        }
+        self.samples.push(offset.abs());
+    }
+
+    /// Close the window in progress when `now` is past its end, and start the
+    /// one `now` is in. Returns how long until that one ends, unless windows
+    /// are disabled.
+    pub(crate) fn roll(&mut self, now: Time) -> Option<core::time::Duration> {
+        let length = self.length?;
+        let start = Time::from_secs(now.secs() - now.secs() % length);
+        let end = start + Duration::from_secs(length as i64);
+
+        match self.start {
+            Some(current) if current == start => {}
+            // Time going backwards, like after a clock step, drops the window
+            Some(current) if now < current => self.start_window(start),
+            Some(current) => {
+                let window = self.close(current, current + Duration::from_secs(length as i64));
+                if self.history.is_full() {
+                    self.history.remove(0);
+                }
+                self.history.push(window);
+                self.start_window(start);
+            }
+            None => self.start_window(start),
+        }
+
+        Some((end - now).into())
+    }
+
+    fn start_window(&mut self, start: Time) {
+        self.start = Some(start);
+        self.offsets = DurationStatistics::default();
+        self.samples.clear();
+        self.stride = 1;
+        self.skipped = 0;
+    }
+
+    fn close(&mut self, start: Time, end: Time) -> StatisticsWindow {
+        self.samples.sort_unstable();
+        let percentile = |percent: usize| {
+            let last = self.samples.len().checked_sub(1)?;
+            Some(self.samples[(last * percent).div_ceil(100)])
+        };
+
+        StatisticsWindow {
+            start,
+            end,
+            offsets: self.offsets,
+            median: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        }
+    }
+
+    /// The length of the windows in seconds, if enabled
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
+    /// The closed windows, oldest first
+    pub fn history(&self) -> &[StatisticsWindow] {
+        &self.history
+    }
+
+    /// The most recently closed window
+    pub fn latest(&self) -> Option<&StatisticsWindow> {
+        self.history.last()
+    }
+}
+
+/// Number of entries a [`Timeline`] keeps
+pub const TIMELINE_CAPACITY: usize = 32;
+
+/// Something that happened on a port, as kept in its [`Timeline`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimelineEvent {
+    /// An offset from the master of at least the spike threshold was
+    /// measured
+    OffsetSpike { offset: Duration },
+    /// The filter corrected the clock by at least the spike threshold at
+    /// once, which the [`BasicFilter`](crate::BasicFilter) only does when it
+    /// steps the clock
+    ClockStepped { correction: Duration },
+    /// The port moved to a different state
+    StateChanged {
+        previous: PortStateKind,
+        current: PortStateKind,
+    },
+    /// The port started taking its time from another master, or stopped
+    /// taking it from one. Holds the clock identities of the masters.
+    ParentChanged {
+        previous: Option<ClockIdentity>,
+        current: Option<ClockIdentity>,
+    },
+    /// No announce messages arrived within the announce receipt timeout, so
+    /// the port became master
+    AnnounceReceiptTimeout,
+    /// No Sync messages arrived from the fixed master within the sync receipt
+    /// timeout, so the port stopped taking its time from it, see
+    /// [`FixedMasterConfig`](crate::FixedMasterConfig)
+    SyncReceiptTimeout,
+    /// The fixed master left too many delay requests in a row unanswered, so
+    /// the port stopped taking its time from it, see
+    /// [`FixedMasterConfig`](crate::FixedMasterConfig)
+    DelayReceiptTimeout,
+    /// The source of the timestamps changed, see
+    /// [`PortStatistics::clock_source_changes`]
+    ClockSourceChanged,
+    /// The runtime reported the link of the port went down or came up, see
+    /// [`Port::handle_link_change`](crate::Port::handle_link_change)
+    LinkChanged { up: bool },
+}
+
+/// An entry of a [`Timeline`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimelineEntry {
+    /// Local time at which it happened
+    pub time: Time,
+    pub event: TimelineEvent,
+}
+
+/// The recent offset spikes of a port, among the events that tend to cause
+/// them.
+///
+/// Offsets from the master of at least the threshold set with
+/// [`Port::set_offset_spike_threshold`](crate::Port::set_offset_spike_threshold)
+/// are recorded as [`TimelineEvent::OffsetSpike`], and corrections of the
+/// clock of at least that threshold as [`TimelineEvent::ClockStepped`]. The
+/// other events are recorded whether or not a threshold is set. Only the
+/// last [`TIMELINE_CAPACITY`] entries are kept, so a spike can be explained
+/// from the daemon itself by the entries around it, see
+/// [`Timeline::around`].
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    entries: ArrayVec<TimelineEntry, TIMELINE_CAPACITY>,
+    spike_threshold: Option<Duration>,
+}
+
+impl Timeline {
+    /// Add an entry, dropping the oldest one if the timeline is full
+    pub(crate) fn record(&mut self, time: Time, event: TimelineEvent) {
+        if self.entries.is_full() {
+            self.entries.remove(0);
+        }
+        self.entries.push(TimelineEntry { time, event });
+    }
+
+    /// Record a measured offset from the master if it is a spike
+    pub(crate) fn record_offset(&mut self, time: Time, offset: Duration) {
+        if self.is_spike(offset) {
+            self.record(time, TimelineEvent::OffsetSpike { offset });
+        }
+    }
+
+    /// Record a correction of the clock if it is large enough to be a step
+    pub(crate) fn record_correction(&mut self, time: Time, correction: Duration) {
+        if self.is_spike(correction) {
+            self.record(time, TimelineEvent::ClockStepped { correction });
+        }
+    }
+
+    fn is_spike(&self, offset: Duration) -> bool {
+        self.spike_threshold
+            .is_some_and(|threshold| offset.abs() >= threshold)
+    }
+
+    pub(crate) fn set_spike_threshold(&mut self, threshold: Option<Duration>) {
+        self.spike_threshold = threshold;
+    }
+
+    /// All entries, oldest first
+    pub fn entries(&self) -> &[TimelineEntry] {
+        &self.entries
+    }
+
+    /// The entries less than `window` away from `time`, oldest first, like
+    /// the events that led up to a spike
+    pub fn around(
+        &self,
+        time: Time,
+        window: Duration,
+    ) -> impl Iterator<Item = &TimelineEntry> + '_ {
+        self.entries
+            .iter()
+            .filter(move |entry| (entry.time - time).abs() < window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: usize = 256;
+
+    fn samples() -> &'static mut [f64] {
+        std::vec![0.0; SAMPLES].leak()
+    }
+
+    fn statistics(
+        intervals: &[usize],
+        offsets: impl IntoIterator<Item = i64>,
+    ) -> TimeErrorStatistics {
+        let mut statistics = TimeErrorStatistics::default();
+        statistics.set_intervals(intervals, samples()).unwrap();
+        for offset in offsets {
+            statistics.record(Duration::from_nanos(offset));
+        }
+        statistics
+    }
+
+    #[test]
+    fn frequency_history() {
+        let mut frequency = FrequencyStatistics::default();
+        frequency.set_change_threshold(Some(5.0));
+        let at = |seconds| Time::from_secs(100) + Duration::from_secs(seconds);
+
+        // A period of corrections around 10ppb
+        for (seconds, ppb) in [(0, 8.0), (20, 10.0), (40, 12.0)] {
+            assert_eq!(frequency.record(at(seconds), ppb), None);
+        }
+        assert_eq!(
+            frequency.current(),
+            Some(FrequencyCorrection {
+                time: at(40),
+                ppb: 12.0
+            })
+        );
+        assert!(frequency.history().is_empty());
+
+        // Ending it gives the first mean, which has nothing to compare with
+        assert_eq!(frequency.record(at(60), 13.0), None);
+        assert_eq!(
+            frequency.history(),
+            [FrequencyCorrection {
+                time: at(60),
+                ppb: 10.0
+            }]
+        );
+
+        // The next period warmed up by 5ppb
+        assert_eq!(frequency.record(at(90), 17.0), None);
+        assert_eq!(frequency.record(at(120), 20.0), Some((10.0, 15.0)));
+        assert_eq!(frequency.history().len(), 2);
+
+        // A clock step back ends the period without a mean
+        assert_eq!(frequency.record(at(10), 20.0), None);
+        assert_eq!(frequency.history().len(), 2);
+        assert_eq!(frequency.record(at(70), 20.0), Some((15.0, 20.0)));
+        assert_eq!(frequency.history().len(), 3);
+        assert_eq!(frequency.history()[2].ppb, 20.0);
+
+        // Only the most recent periods are kept
+        for period in 0..FREQUENCY_HISTORY_CAPACITY as i64 {
+            frequency.record(at(130 + 60 * period), 1.0);
+        }
+        assert_eq!(frequency.history().len(), FREQUENCY_HISTORY_CAPACITY);
+        assert_eq!(frequency.history()[0].ppb, 20.0);
+
+        frequency.clear();
+        assert_eq!(frequency.current(), None);
+        assert!(frequency.history().is_empty());
+    }
+
+    #[test]
+    fn duration_statistics_saturate() {
+        let mut statistics = DurationStatistics::default();
+        assert_eq!(statistics.mean(), None);
+        statistics.record(Duration::from_nanos(10));
+        statistics.record(Duration::from_nanos(20));
+        assert_eq!(statistics.mean(), Some(Duration::from_nanos(15)));
+
+        let mut statistics = DurationStatistics {
+            count: u32::MAX - 1,
+            total: Duration::from_nanos(10) * (u32::MAX - 1) as i64,
+            ..statistics
+        };
+        statistics.record(Duration::from_nanos(10));
+        statistics.record(Duration::from_nanos(1000));
+        assert_eq!(statistics.count, u32::MAX);
+        assert_eq!(statistics.mean(), Some(Duration::from_nanos(10)));
+        assert_eq!(statistics.last, Duration::from_nanos(1000));
+        assert_eq!(statistics.max, Duration::from_nanos(1000));
+    }
+
+    #[test]
+    fn message_rate() {
+        let mut rate = MessageRate::default();
+        rate.record(Time::from_secs(10));
+        assert_eq!(rate.per_second(), None);
+
+        // Eight messages per second
+        for i in 1..=4 {
+            rate.record(Time::from_secs(10) + Duration::from_millis(125 * i));
+        }
+        assert_eq!(rate.mean_interval(), Some(Duration::from_millis(125)));
+        assert_eq!(rate.per_second(), Some(8.0));
+
+        // A clock step back is ignored
+        rate.record(Time::from_secs(5));
+        assert_eq!(rate.per_second(), Some(8.0));
+        assert_eq!(rate.last, Some(Time::from_secs(5)));
+
+        // A longer interval moves the average an eighth of the way
+        rate.record(Time::from_secs(5) + Duration::from_millis(1125));
+        assert_eq!(rate.mean_interval(), Some(Duration::from_millis(250)));
+
+        // Other messages than those sent at an interval are not tracked
+        let mut rates = MessageTypeRates::default();
+        rates.record(MessageType::FollowUp, Time::from_secs(1));
+        rates.record(MessageType::PDelayReq, Time::from_secs(1));
+        assert_eq!(
+            rates,
+            MessageTypeRates {
+                pdelay_req: MessageRate {
+                    last: Some(Time::from_secs(1)),
+                    mean_interval: None,
+                },
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn time_error_needs_intervals() {
+        let mut statistics = TimeErrorStatistics::default();
+        statistics.record(Duration::from_nanos(5));
+        assert_eq!(statistics.samples(), 0);
+
+        assert_eq!(
+            statistics.set_intervals(&[0], samples()),
+            Err(TimeErrorConfigError::InvalidInterval(0))
+        );
+        assert_eq!(
+            statistics.set_intervals(&[SAMPLES / 3 + 1], samples()),
+            Err(TimeErrorConfigError::InvalidInterval(SAMPLES / 3 + 1))
+        );
+        assert_eq!(
+            statistics.set_intervals(&[1], &mut []),
+            Err(TimeErrorConfigError::InvalidInterval(1))
+        );
+        assert_eq!(
+            statistics.set_intervals(&[1; MAX_OBSERVATION_INTERVALS + 1], samples()),
+            Err(TimeErrorConfigError::TooManyIntervals(
+                MAX_OBSERVATION_INTERVALS + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn time_error_of_ramp() {
+        // A constant frequency error has an MTIE growing with the interval,
+        // but no time deviation
+        let statistics = statistics(&[1, 4], 0..20);
+
+        let metrics: ArrayVec<_, 2> = statistics.metrics().collect();
+        assert_eq!(
+            metrics[0],
+            TimeErrorMetrics {
+                observation_interval: 1,
+                mtie: Some(Duration::from_nanos(1)),
+                tdev: Some(Duration::ZERO),
+            }
+        );
+        assert_eq!(
+            metrics[1],
+            TimeErrorMetrics {
+                observation_interval: 4,
+                mtie: Some(Duration::from_nanos(4)),
+                tdev: Some(Duration::ZERO),
+            }
+        );
+    }
+
+    #[test]
+    fn time_error_of_alternating_offsets() {
+        let statistics = statistics(&[1], (0..10).map(|i| if i % 2 == 0 { 1 } else { -1 }));
+
+        assert_eq!(statistics.mtie(1), Some(Duration::from_nanos(2)));
+        // Every second difference is 4ns, so TDEV is sqrt(16 / 6)
+        let tdev = statistics.tdev(1).unwrap().nanos_lossy();
+        assert!((tdev - libm::sqrt(16.0 / 6.0)).abs() < 1e-6);
+
+        // MTIE over an interval of 4 needs 5 samples, but TDEV needs 12
+        assert_eq!(statistics.mtie(4), Some(Duration::from_nanos(2)));
+        assert_eq!(statistics.tdev(4), None);
+    }
+
+    #[test]
+    fn time_error_keeps_latest_samples() {
+        // The large early offsets are pushed out of the buffer
+        let statistics = statistics(
+            &[1],
+            (0..SAMPLES + 10).map(|i| if i < 10 { 1000 } else { 3 }),
+        );
+
+        assert_eq!(statistics.samples(), SAMPLES);
+        assert_eq!(statistics.mtie(1), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn timeline_keeps_latest_spikes() {
+        let mut timeline = Timeline::default();
+        timeline.set_spike_threshold(Some(Duration::from_micros(10)));
+
+        for i in 0..TIMELINE_CAPACITY as u64 + 5 {
+            let time = Time::from_secs(i);
+            timeline.record_offset(
+                time,
+                Duration::from_micros(if i % 2 == 0 { 5 } else { -20 }),
+            );
+            timeline.record(time, TimelineEvent::LinkChanged { up: true });
+        }
+
+        // Only offsets beyond the threshold, in either direction, are spikes
+        let entries = timeline.entries();
+        assert_eq!(entries.len(), TIMELINE_CAPACITY);
+        assert!(entries.iter().all(|entry| match entry.event {
+            TimelineEvent::OffsetSpike { offset } => offset == Duration::from_micros(-20),
+            _ => true,
+        }));
+        assert_eq!(entries.last().unwrap().time, Time::from_secs(36));
+
+        let around: ArrayVec<_, 4> = timeline
+            .around(Time::from_secs(35), Duration::from_secs(1))
+            .map(|entry| entry.event)
+            .collect();
+        assert_eq!(
+            around.as_slice(),
+            [
+                TimelineEvent::OffsetSpike {
+                    offset: Duration::from_micros(-20)
+                },
+                TimelineEvent::LinkChanged { up: true },
+            ]
+        );
+    }
+
+    #[test]
+    fn statistics_windows() {
+        let mut windows = StatisticsWindows::default();
+        windows.record(Time::from_secs(5), Duration::from_micros(1));
+        assert_eq!(windows.roll(Time::from_secs(5)), None);
+
+        windows.set_length(Some(10));
+        assert_eq!(
+            windows.roll(Time::from_secs(12)),
+            Some(core::time::Duration::from_secs(8))
+        );
+
+        // Enough offsets to thin out the samples, the largest at the end
+        for i in 0..1000 {
+            let time = Time::from_millis(12_000 + i * 5);
+            windows.record(time, Duration::from_nanos(-(i as i64)));
+        }
+        assert!(windows.history().is_empty());
+
+        // The window ends at its boundary, also when the timer fires late
+        assert_eq!(
+            windows.roll(Time::from_millis(20_100)),
+            Some(core::time::Duration::from_millis(9_900))
+        );
+        let window = *windows.latest().unwrap();
+        assert_eq!(
+            (window.start, window.end),
+            (Time::from_secs(10), Time::from_secs(20))
+        );
+        assert_eq!(window.offsets.count, 1000);
+        assert_eq!(window.offsets.min, Duration::from_nanos(-999));
+        let median = window.median.unwrap().nanos_lossy();
+        let p99 = window.p99.unwrap().nanos_lossy();
+        assert!((480.0..=520.0).contains(&median), "{median}");
+        assert!((970.0..=999.0).contains(&p99), "{p99}");
+
+        // Windows without measurements are closed too
+        windows.roll(Time::from_secs(30));
+        assert_eq!(windows.history().len(), 2);
+        assert_eq!(windows.latest().unwrap().offsets.count, 0);
+        assert_eq!(windows.latest().unwrap().median, None);
+
+        for i in 0..STATISTICS_WINDOW_HISTORY as u64 + 2 {
+            windows.record(Time::from_secs(40 + 10 * i), Duration::ZERO);
+        }
+        assert_eq!(windows.history().len(), STATISTICS_WINDOW_HISTORY);
+        assert_eq!(
+            windows.latest().unwrap().start,
+            Time::from_secs(40 + 10 * STATISTICS_WINDOW_HISTORY as u64)
+        );
+    }
+}
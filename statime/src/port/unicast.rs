@@ -0,0 +1,823 @@
+use arrayvec::ArrayVec;
+
+use super::{
+    sequence_id::{KeyedSequenceIds, SequenceIdGenerator},
+    DurationStatistics, UnicastGrantCounts, UnicastSyncClient,
+};
+use crate::{
+    config::UnicastMasterConfig,
+    datastructures::{common::PortIdentity, messages::MessageType},
+    time::{Duration, Interval, Time},
+};
+
+// The message types sent to clients on a schedule, each with a schedule of
+// its own
+const SCHEDULED: [MessageType; 2] = [MessageType::Announce, MessageType::Sync];
+
+// Destinations of signaling messages that keep their own sequence ids
+const SIGNALING_DESTINATIONS: usize = 16;
+
+fn schedule_of(message_type: MessageType) -> Option<usize> {
+    SCHEDULED
+        .iter()
+        .position(|&scheduled| scheduled == message_type)
+}
+
+// Messages of one type granted to a single client
+#[derive(Debug, Clone)]
+struct Grant {
+    client: PortIdentity,
+    message_type: MessageType,
+    interval: Interval,
+    expires: Time,
+    // Position in the schedule of its message type, if it has one
+    position: usize,
+    // Sequence ids of the messages sent to this client, which are separate
+    // from those of other clients
+    seq_ids: SequenceIdGenerator,
+    // Only used for sync messages
+    sync: SyncSchedule,
+}
+
+// Sync messages to a single client, and how late they went out
+#[derive(Debug, Clone, Default)]
+struct SyncSchedule {
+    // Sequence id of the last sync message, and when it was due
+    pending: Option<(u16, Time)>,
+    send_latency: DurationStatistics,
+}
+
+// When the next message of the grant in `slot` is due
+#[derive(Debug, Clone, Copy)]
+struct ScheduleEntry {
+    next_send: Time,
+    slot: usize,
+}
+
+impl ScheduleEntry {
+    const EMPTY: Self = Self {
+        next_send: Time::from_secs(0),
+        slot: 0,
+    };
+}
+
+/// Room for a single grant of a master port, that is the messages of one
+/// type to one client. See
+/// [`Port::set_unicast_master`](crate::Port::set_unicast_master).
+///
+/// A port keeps its grants in a table of these provided by the user, so the
+/// memory used for them is chosen with the size of that table. Without the
+/// standard library, the table can be a `static`:
+///
+/// ```
+/// # use statime::UnicastGrantSlot;
+/// static mut GRANTS: [UnicastGrantSlot; 32] = [UnicastGrantSlot::EMPTY; 32];
+/// ```
+#[derive(Debug)]
+pub struct UnicastGrantSlot {
+    grant: Option<Grant>,
+    // The entry of each schedule kept in this slot, see UnicastGrants
+    schedule: [ScheduleEntry; SCHEDULED.len()],
+}
+
+impl UnicastGrantSlot {
+    /// A slot without a grant, to initialize tables with
+    pub const EMPTY: Self = Self {
+        grant: None,
+        schedule: [ScheduleEntry::EMPTY; SCHEDULED.len()],
+    };
+}
+
+impl Default for UnicastGrantSlot {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+/// A sync grant to a client, with the slot it is kept in so it is found
+/// again without searching the table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SyncGrant {
+    pub(crate) client: PortIdentity,
+    slot: usize,
+}
+
+/// The unicast messages a master port granted, and when the next one is due
+/// for each client.
+///
+/// The grants of announce and sync messages are each on a schedule, a binary
+/// heap ordered by when their next message is due, so the next client to
+/// send to is found in O(log n) for n clients. The schedules are stored in
+/// the table of the user, with entry `i` of each schedule in slot `i`, so
+/// they take no memory of their own. Requests and cancellations still go
+/// through the whole table, as they are much rarer than the messages sent.
+#[derive(Debug, Default)]
+pub(crate) struct UnicastGrants {
+    config: Option<UnicastMasterConfig>,
+    grants: &'static mut [UnicastGrantSlot],
+    // Number of grants on each schedule
+    scheduled: [usize; SCHEDULED.len()],
+    pub(crate) signaling_seq_ids: KeyedSequenceIds<PortIdentity, SIGNALING_DESTINATIONS>,
+}
+
+impl UnicastGrants {
+    pub(crate) fn set_config(
+        &mut self,
+        config: Option<UnicastMasterConfig>,
+        table: &'static mut [UnicastGrantSlot],
+    ) {
+        self.config = config;
+        self.grants = table;
+        self.clear();
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Grant or renew messages of `message_type` to `client` at the requested
+    /// interval. Returns the granted duration in seconds, which is 0 if the
+    /// request is denied.
+    pub(crate) fn request(
+        &mut self,
+        client: PortIdentity,
+        message_type: MessageType,
+        log_interval: i8,
+        duration: u32,
+        now: Time,
+        counts: &mut UnicastGrantCounts,
+    ) -> u32 {
+        let interval = Interval::from_log_2(log_interval);
+        let min_interval = |config: &UnicastMasterConfig| match message_type {
+            MessageType::Announce => Some(config.min_announce_interval),
+            MessageType::Sync => Some(config.min_sync_interval),
+            MessageType::DelayResp => Some(config.min_delay_resp_interval),
+            _ => None,
+        };
+        let config = match self.config {
+            Some(config)
+                if min_interval(&config).is_some_and(|min| interval >= min) && duration > 0 =>
+            {
+                config
+            }
+            _ => {
+                counts.denied = counts.denied.saturating_add(1);
+                return 0;
+            }
+        };
+
+        // Make room for the new client first
+        let expired = self.remove(|grant| grant.expires <= now);
+        counts.expired = counts.expired.saturating_add(expired);
+
+        let duration = duration.min(config.max_lease_duration);
+        let expires = now + Duration::from_secs(duration as i64);
+
+        let clients = self.grants_of(message_type).count();
+        let has_room = clients < config.max_clients;
+        if let Some(grant) = self.find_mut(client, message_type) {
+            grant.interval = interval;
+            grant.expires = expires;
+            counts.renewed = counts.renewed.saturating_add(1);
+            return duration;
+        }
+
+        // New clients also need a free slot in the table
+        match self.grants.iter().position(|slot| slot.grant.is_none()) {
+            Some(slot) if has_room => {
+                self.grants[slot].grant = Some(Grant {
+                    client,
+                    message_type,
+                    interval,
+                    expires,
+                    position: 0,
+                    seq_ids: SequenceIdGenerator::new(),
+                    sync: SyncSchedule::default(),
+                });
+                if let Some(schedule) = schedule_of(message_type) {
+                    self.push(
+                        schedule,
+                        ScheduleEntry {
+                            next_send: now,
+                            slot,
+                        },
+                    );
+                }
+                counts.granted = counts.granted.saturating_add(1);
+            }
+            _ => {
+                counts.denied = counts.denied.saturating_add(1);
+                return 0;
+            }
+        }
+
+        duration
+    }
+
+    /// End the grant of `message_type` to `client`, returning whether it had
+    /// one
+    pub(crate) fn cancel(
+        &mut self,
+        client: PortIdentity,
+        message_type: MessageType,
+        counts: &mut UnicastGrantCounts,
+    ) -> bool {
+        let cancelled =
+            self.remove(|grant| grant.client == client && grant.message_type == message_type) > 0;
+        if cancelled {
+            counts.cancelled = counts.cancelled.saturating_add(1);
+        }
+        cancelled
+    }
+
+    /// End all grants of the client that was granted messages first,
+    /// returning it with the types of the messages it was granted
+    pub(crate) fn revoke_next(
+        &mut self,
+        counts: &mut UnicastGrantCounts,
+    ) -> Option<(PortIdentity, ArrayVec<MessageType, 3>)> {
+        let client = self.iter().next()?.client;
+        let message_types = self
+            .iter()
+            .filter(|grant| grant.client == client)
+            .map(|grant| grant.message_type)
+            .collect::<ArrayVec<_, 3>>();
+        self.remove(|grant| grant.client == client);
+
+        counts.revoked = counts.revoked.saturating_add(message_types.len() as u32);
+        Some((client, message_types))
+    }
+
+    /// End all grants, for example because the port is no longer master
+    pub(crate) fn clear(&mut self) {
+        for slot in self.grants.iter_mut() {
+            slot.grant = None;
+        }
+        self.scheduled = [0; SCHEDULED.len()];
+    }
+
+    /// Take the client an announce message is due for now, if any, with the
+    /// sequence id to send to it with. Expired grants that were due before
+    /// it are dropped. Also returns how long until the next announce message
+    /// is due after that, unless no grants are left.
+    pub(crate) fn next_announce(
+        &mut self,
+        now: Time,
+        counts: &mut UnicastGrantCounts,
+    ) -> (Option<(PortIdentity, u16)>, Option<core::time::Duration>) {
+        let (due, next) = self.take_due(MessageType::Announce, now, counts);
+        let due = due.and_then(|(slot, _)| {
+            let grant = self.grants[slot].grant.as_mut()?;
+            Some((grant.client, grant.seq_ids.generate()))
+        });
+        (due, next)
+    }
+
+    /// Like [`next_announce`](Self::next_announce), for sync messages.
+    ///
+    /// Sync messages to different clients are spread over the interval of
+    /// the client, so they don't all go out at the same moment. Clients that
+    /// were due at the same time are moved apart, and stay apart afterwards.
+    pub(crate) fn next_sync(
+        &mut self,
+        now: Time,
+        counts: &mut UnicastGrantCounts,
+    ) -> (Option<(SyncGrant, u16)>, Option<core::time::Duration>) {
+        let clients = self.scheduled[1];
+        let (due, next) = self.take_due(MessageType::Sync, now, counts);
+        let Some((slot, due_at)) = due else {
+            return (None, next);
+        };
+        let Some(grant) = self.grants[slot].grant.as_mut() else {
+            return (None, next);
+        };
+
+        let seq_id = grant.seq_ids.generate();
+        grant.sync.pending = Some((seq_id, due_at));
+
+        let min_gap = grant.interval.as_core_duration() / clients.max(1) as u32;
+        let next = next.map(|next| next.max(min_gap));
+        let sync_grant = SyncGrant {
+            client: grant.client,
+            slot,
+        };
+        (Some((sync_grant, seq_id)), next)
+    }
+
+    /// Record the send timestamp of the sync message of `sync_grant` with
+    /// sequence id `seq_id`
+    pub(crate) fn record_sync_timestamp(&mut self, sync_grant: SyncGrant, seq_id: u16, time: Time) {
+        // The grant may have ended, and its slot been taken by another one
+        let grant = self
+            .grants
+            .get_mut(sync_grant.slot)
+            .and_then(|slot| slot.grant.as_mut())
+            .filter(|grant| {
+                grant.client == sync_grant.client && grant.message_type == MessageType::Sync
+            });
+        if let Some(grant) = grant {
+            if let Some((pending_id, due_at)) = grant.sync.pending {
+                if pending_id == seq_id {
+                    grant.sync.pending = None;
+                    grant.sync.send_latency.record(time - due_at);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn sync_clients(&self) -> impl Iterator<Item = UnicastSyncClient> + '_ {
+        self.grants_of(MessageType::Sync)
+            .map(|grant| UnicastSyncClient {
+                clock_identity: grant.client.clock_identity,
+                port_number: grant.client.port_number,
+                interval: grant.interval,
+                send_latency: grant.sync.send_latency,
+            })
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Grant> {
+        self.grants.iter().filter_map(|slot| slot.grant.as_ref())
+    }
+
+    fn grants_of(&self, message_type: MessageType) -> impl Iterator<Item = &Grant> {
+        self.iter()
+            .filter(move |grant| grant.message_type == message_type)
+    }
+
+    fn find_mut(&mut self, client: PortIdentity, message_type: MessageType) -> Option<&mut Grant> {
+        self.grants
+            .iter_mut()
+            .filter_map(|slot| slot.grant.as_mut())
+            .find(|grant| grant.client == client && grant.message_type == message_type)
+    }
+
+    // Free the slots of the grants matching `predicate`, returning how many
+    // there were
+    fn remove(&mut self, predicate: impl Fn(&Grant) -> bool) -> u32 {
+        let mut removed = 0;
+        for slot in 0..self.grants.len() {
+            if self.grants[slot].grant.as_ref().is_some_and(&predicate) {
+                self.free(slot);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    // Free a slot, taking its grant off its schedule
+    fn free(&mut self, slot: usize) {
+        let Some(grant) = self.grants[slot].grant.take() else {
+            return;
+        };
+        let Some(schedule) = schedule_of(grant.message_type) else {
+            return;
+        };
+
+        let last = self.scheduled[schedule] - 1;
+        self.scheduled[schedule] = last;
+        if grant.position < last {
+            let entry = self.entry(schedule, last);
+            self.set_entry(schedule, grant.position, entry);
+            self.sift_down(schedule, grant.position);
+            self.sift_up(schedule, grant.position);
+        }
+    }
+
+    // Take the slot and due time of the grant of `message_type` that is due
+    // the longest, moving it to its next interval. Expired grants are dropped
+    // once they are due, without sending to them. Also returns how long
+    // until the next grant of `message_type` is due.
+    fn take_due(
+        &mut self,
+        message_type: MessageType,
+        now: Time,
+        counts: &mut UnicastGrantCounts,
+    ) -> (Option<(usize, Time)>, Option<core::time::Duration>) {
+        let Some(schedule) = schedule_of(message_type) else {
+            return (None, None);
+        };
+
+        let mut due = None;
+        while self.scheduled[schedule] > 0 {
+            let first = self.entry(schedule, 0);
+            if first.next_send > now {
+                break;
+            }
+            let Some(grant) = &self.grants[first.slot].grant else {
+                break;
+            };
+            if grant.expires <= now {
+                self.free(first.slot);
+                counts.expired = counts.expired.saturating_add(1);
+                continue;
+            }
+
+            let interval = grant.interval.as_duration();
+            let mut next_send = first.next_send + interval;
+            // Don't try to catch up on messages we were late for. Sync
+            // messages keep the phase they were actually sent at, which
+            // keeps clients apart once they were moved apart.
+            if next_send < now || message_type == MessageType::Sync {
+                next_send = now + interval;
+            }
+            self.set_entry(schedule, 0, ScheduleEntry { next_send, ..first });
+            self.sift_down(schedule, 0);
+
+            due = Some((first.slot, first.next_send));
+            break;
+        }
+
+        let next = (self.scheduled[schedule] > 0)
+            .then(|| (self.entry(schedule, 0).next_send - now).into());
+        (due, next)
+    }
+
+    fn entry(&self, schedule: usize, position: usize) -> ScheduleEntry {
+        self.grants[position].schedule[schedule]
+    }
+
+    // Put an entry at a position of a schedule, and let its grant know
+    fn set_entry(&mut self, schedule: usize, position: usize, entry: ScheduleEntry) {
+        self.grants[position].schedule[schedule] = entry;
+        if let Some(grant) = &mut self.grants[entry.slot].grant {
+            grant.position = position;
+        }
+    }
+
+    fn push(&mut self, schedule: usize, entry: ScheduleEntry) {
+        let position = self.scheduled[schedule];
+        self.scheduled[schedule] += 1;
+        self.set_entry(schedule, position, entry);
+        self.sift_up(schedule, position);
+    }
+
+    // Move the entry at `position` towards the start of the schedule while it
+    // is due before its parent
+    fn sift_up(&mut self, schedule: usize, mut position: usize) {
+        while position > 0 {
+            let parent = (position - 1) / 2;
+            let (entry, above) = (self.entry(schedule, position), self.entry(schedule, parent));
+            if above.next_send <= entry.next_send {
+                break;
+            }
+            self.set_entry(schedule, parent, entry);
+            self.set_entry(schedule, position, above);
+            position = parent;
+        }
+    }
+
+    // Move the entry at `position` towards the end of the schedule while one
+    // of its children is due before it
+    fn sift_down(&mut self, schedule: usize, mut position: usize) {
+        let len = self.scheduled[schedule];
+        loop {
+            let mut first = position;
+            for child in [2 * position + 1, 2 * position + 2] {
+                if child < len
+                    && self.entry(schedule, child).next_send < self.entry(schedule, first).next_send
+                {
+                    first = child;
+                }
+            }
+            if first == position {
+                break;
+            }
+
+            let (entry, below) = (self.entry(schedule, position), self.entry(schedule, first));
+            self.set_entry(schedule, position, below);
+            self.set_entry(schedule, first, entry);
+            position = first;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClockIdentity;
+
+    fn client(number: u8) -> PortIdentity {
+        PortIdentity {
+            clock_identity: ClockIdentity([number; 8]),
+            port_number: 1,
+        }
+    }
+
+    fn table(slots: usize) -> &'static mut [UnicastGrantSlot] {
+        std::iter::repeat_with(|| UnicastGrantSlot::EMPTY)
+            .take(slots)
+            .collect::<std::vec::Vec<_>>()
+            .leak()
+    }
+
+    fn grants_in(max_clients: usize, slots: usize) -> UnicastGrants {
+        let mut grants = UnicastGrants::default();
+        grants.set_config(
+            Some(UnicastMasterConfig {
+                max_clients,
+                max_lease_duration: 60,
+                min_announce_interval: Interval::from_log_2(-3),
+                min_sync_interval: Interval::from_log_2(-4),
+                min_delay_resp_interval: Interval::from_log_2(-4),
+            }),
+            table(slots),
+        );
+        grants
+    }
+
+    fn grants(max_clients: usize) -> UnicastGrants {
+        grants_in(max_clients, 3 * max_clients)
+    }
+
+    const ANNOUNCE: MessageType = MessageType::Announce;
+    const SYNC: MessageType = MessageType::Sync;
+
+    // Check that the schedules are heaps holding every grant of their type,
+    // and that the grants know where they are
+    fn assert_scheduled(grants: &UnicastGrants) {
+        for (schedule, &message_type) in SCHEDULED.iter().enumerate() {
+            let len = grants.scheduled[schedule];
+            assert_eq!(len, grants.grants_of(message_type).count());
+            for position in 0..len {
+                let entry = grants.entry(schedule, position);
+                let grant = grants.grants[entry.slot].grant.as_ref().unwrap();
+                assert_eq!(grant.message_type, message_type);
+                assert_eq!(grant.position, position);
+                if position > 0 {
+                    let parent = grants.entry(schedule, (position - 1) / 2);
+                    assert!(parent.next_send <= entry.next_send);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn grant_limits() {
+        let mut grants = grants(1);
+        let mut counts = UnicastGrantCounts::default();
+        let now = Time::from_secs(100);
+
+        // Too fast, and too many clients
+        assert_eq!(
+            grants.request(client(1), ANNOUNCE, -4, 30, now, &mut counts),
+            0
+        );
+        assert_eq!(
+            grants.request(client(1), ANNOUNCE, 0, 300, now, &mut counts),
+            60
+        );
+        assert_eq!(
+            grants.request(client(2), ANNOUNCE, 0, 30, now, &mut counts),
+            0
+        );
+        assert_eq!(
+            grants.request(client(1), ANNOUNCE, 0, 30, now, &mut counts),
+            30
+        );
+
+        // Sync messages and delay responses are limited separately, and other
+        // types never granted
+        assert_eq!(
+            grants.request(client(2), SYNC, -4, 30, now, &mut counts),
+            30
+        );
+        let delay_resp = MessageType::DelayResp;
+        assert_eq!(
+            grants.request(client(2), delay_resp, 0, 30, now, &mut counts),
+            30
+        );
+        let follow_up = MessageType::FollowUp;
+        assert_eq!(
+            grants.request(client(2), follow_up, 0, 30, now, &mut counts),
+            0
+        );
+
+        assert_eq!(
+            counts,
+            UnicastGrantCounts {
+                granted: 3,
+                renewed: 1,
+                denied: 3,
+                ..Default::default()
+            }
+        );
+
+        assert!(grants.cancel(client(1), ANNOUNCE, &mut counts));
+        assert!(!grants.cancel(client(1), ANNOUNCE, &mut counts));
+        assert!(!grants.cancel(client(2), ANNOUNCE, &mut counts));
+        assert_eq!(counts.cancelled, 1);
+        assert_eq!(
+            grants.request(client(2), ANNOUNCE, 0, 30, now, &mut counts),
+            30
+        );
+    }
+
+    #[test]
+    fn full_table() {
+        let mut grants = grants_in(4, 3);
+        let mut counts = UnicastGrantCounts::default();
+        let now = Time::from_secs(100);
+
+        for number in 1..=3 {
+            assert_eq!(
+                grants.request(client(number), SYNC, 0, 30, now, &mut counts),
+                30
+            );
+        }
+        // Allowed by the configuration, but there is no room left
+        assert_eq!(grants.request(client(4), SYNC, 0, 30, now, &mut counts), 0);
+        // Renewals don't need room
+        assert_eq!(grants.request(client(1), SYNC, 0, 30, now, &mut counts), 30);
+
+        // The slot of an ended grant is used again
+        assert!(grants.cancel(client(2), SYNC, &mut counts));
+        assert_eq!(
+            grants.request(client(4), ANNOUNCE, 0, 30, now, &mut counts),
+            30
+        );
+    }
+
+    #[test]
+    fn revoke_grants() {
+        let mut grants = grants(2);
+        let mut counts = UnicastGrantCounts::default();
+        let now = Time::from_secs(0);
+        for (number, message_type) in [
+            (1, MessageType::Announce),
+            (2, MessageType::Sync),
+            (1, MessageType::Sync),
+        ] {
+            grants.request(client(number), message_type, 0, 60, now, &mut counts);
+        }
+
+        let (first, message_types) = grants.revoke_next(&mut counts).unwrap();
+        assert_eq!(first, client(1));
+        assert_eq!(
+            message_types.as_slice(),
+            [MessageType::Announce, MessageType::Sync]
+        );
+        let (second, message_types) = grants.revoke_next(&mut counts).unwrap();
+        assert_eq!(second, client(2));
+        assert_eq!(message_types.as_slice(), [MessageType::Sync]);
+        assert!(grants.revoke_next(&mut counts).is_none());
+        assert_eq!(counts.revoked, 3);
+    }
+
+    #[test]
+    fn announce_schedule() {
+        let mut grants = grants(2);
+        let mut counts = UnicastGrantCounts::default();
+
+        grants.request(
+            client(1),
+            ANNOUNCE,
+            0,
+            10,
+            Time::from_secs(100),
+            &mut counts,
+        );
+        grants.request(
+            client(2),
+            ANNOUNCE,
+            1,
+            30,
+            Time::from_secs(100),
+            &mut counts,
+        );
+
+        // Both are due right away, one after the other
+        let (first, _) = grants.next_announce(Time::from_secs(100), &mut counts);
+        let (second, next) = grants.next_announce(Time::from_secs(100), &mut counts);
+        assert_ne!(first, second);
+        assert_eq!(first.map(|(_, seq_id)| seq_id), Some(0));
+        assert_eq!(second.map(|(_, seq_id)| seq_id), Some(0));
+        assert_eq!(next, Some(core::time::Duration::from_secs(1)));
+
+        let (none, next) = grants.next_announce(Time::from_millis(100_500), &mut counts);
+        assert_eq!(none, None);
+        assert_eq!(next, Some(core::time::Duration::from_millis(500)));
+
+        // The first grant expires without renewal, the second one stays
+        let (due, next) = grants.next_announce(Time::from_secs(115), &mut counts);
+        assert_eq!(due, Some((client(2), 1)));
+        assert_eq!(next, Some(core::time::Duration::from_secs(2)));
+        assert_eq!(counts.expired, 1);
+
+        let (due, next) = grants.next_announce(Time::from_secs(200), &mut counts);
+        assert_eq!((due, next), (None, None));
+        assert_eq!(counts.expired, 2);
+    }
+
+    #[test]
+    fn sync_fan_out() {
+        let mut grants = grants(4);
+        let mut counts = UnicastGrantCounts::default();
+        let start = Time::from_secs(100);
+
+        for number in 1..=4 {
+            grants.request(client(number), SYNC, 0, 60, start, &mut counts);
+        }
+
+        // All clients are due at once, but go out a quarter interval apart
+        let mut now = start;
+        let mut sent = std::vec::Vec::new();
+        for _ in 0..4 {
+            let (due, next) = grants.next_sync(now, &mut counts);
+            let (sync_grant, seq_id) = due.unwrap();
+            assert_eq!(seq_id, 0);
+            grants.record_sync_timestamp(sync_grant, seq_id, now + Duration::from_micros(10));
+            sent.push(sync_grant);
+
+            assert_eq!(next, Some(core::time::Duration::from_millis(250)));
+            now += Duration::from_millis(250);
+        }
+        let mut clients: std::vec::Vec<_> =
+            sent.iter().map(|sync_grant| sync_grant.client).collect();
+        clients.sort_by_key(|client| client.clock_identity);
+        assert_eq!(clients, [client(1), client(2), client(3), client(4)]);
+
+        // After that, they stay spread over the interval, in the same order
+        for &expected in &sent {
+            let (due, next) = grants.next_sync(now, &mut counts);
+            assert_eq!(due, Some((expected, 1)));
+            assert_eq!(next, Some(core::time::Duration::from_millis(250)));
+            now += Duration::from_millis(250);
+        }
+
+        // The deliberate delays show up in the latencies
+        let latencies: std::vec::Vec<_> = grants
+            .sync_clients()
+            .map(|client| client.send_latency)
+            .collect();
+        assert!(latencies.iter().all(|latency| latency.count == 1));
+        let mut latest = latencies
+            .iter()
+            .map(|latency| latency.max)
+            .collect::<std::vec::Vec<_>>();
+        latest.sort();
+        assert_eq!(
+            latest,
+            [
+                Duration::from_micros(10),
+                Duration::from_micros(250_010),
+                Duration::from_micros(500_010),
+                Duration::from_micros(750_010),
+            ]
+        );
+
+        // Timestamps of unknown messages are ignored
+        grants.record_sync_timestamp(sent[0], 7, now);
+        let unknown = SyncGrant {
+            client: client(9),
+            ..sent[0]
+        };
+        grants.record_sync_timestamp(unknown, 1, now);
+        assert!(grants
+            .sync_clients()
+            .all(|client| client.send_latency.count == 1));
+    }
+
+    #[test]
+    fn many_clients() {
+        let mut grants = grants(200);
+        let mut counts = UnicastGrantCounts::default();
+        let start = Time::from_secs(100);
+
+        for number in 0..200 {
+            let joined = start + Duration::from_millis(number as i64);
+            let log_interval = -((number % 5) as i8);
+            grants.request(client(number), SYNC, log_interval, 60, joined, &mut counts);
+            grants.request(client(number), ANNOUNCE, 0, 60, joined, &mut counts);
+        }
+        for number in (0..200).step_by(3) {
+            grants.cancel(client(number), SYNC, &mut counts);
+        }
+        assert_scheduled(&grants);
+
+        // Always send to the client that is due the longest
+        let mut now = start + Duration::from_secs(1);
+        let mut sent = [0; 200];
+        while now < start + Duration::from_secs(10) {
+            let (due, next) = grants.next_sync(now, &mut counts);
+            if let Some((sync_grant, _)) = due {
+                sent[sync_grant.client.clock_identity.0[0] as usize] += 1;
+            }
+            assert_scheduled(&grants);
+            now += Duration::from_nanos(next.unwrap().as_nanos() as i64);
+        }
+        for (number, &sent) in sent.iter().enumerate() {
+            assert_eq!(sent == 0, number % 3 == 0);
+        }
+
+        // Expired grants are dropped as they come up
+        let now = start + Duration::from_secs(61);
+        let (due, next) = grants.next_sync(now, &mut counts);
+        assert_eq!((due, next), (None, None));
+        assert_eq!(counts.expired, 133);
+        assert_eq!(grants.sync_clients().count(), 0);
+        assert_scheduled(&grants);
+        grants.request(client(0), SYNC, 0, 60, now, &mut counts);
+        assert_eq!(counts.expired, 333);
+        assert_scheduled(&grants);
+    }
+}
@@ -0,0 +1,572 @@
+use arrayvec::ArrayVec;
+
+use super::UnicastRequestCounts;
+use crate::{
+    config::{DelayMechanism, PortConfig, UnicastClientConfig, MAX_UNICAST_MASTERS},
+    datastructures::{
+        common::PortIdentity,
+        messages::{MessageType, UnicastNegotiation},
+    },
+    time::{Duration, Time},
+};
+
+// How long a master gets to answer a request before it is sent again
+const REQUEST_TIMEOUT_SECONDS: i64 = 1;
+// Requests a master can leave unanswered before the client backs off
+const MAX_UNANSWERED: u8 = 3;
+// How long the client waits before requesting messages again that a master
+// denied, cancelled or didn't answer requests for
+const BACKOFF_SECONDS: i64 = 16;
+
+// One type of message requested from a master
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    message_type: MessageType,
+    log_interval: i8,
+    // When the grant of the master ends, if it granted the request
+    expires: Option<Time>,
+    // When to request the messages again, to get them granted or to renew
+    // the grant. Right away if not set.
+    next_request: Option<Time>,
+    // Requests sent since the master last answered
+    unanswered: u8,
+}
+
+impl Lease {
+    fn is_due(&self, now: Time) -> bool {
+        self.next_request.is_none_or(|next| next <= now)
+    }
+
+    fn back_off(&mut self, now: Time) {
+        self.expires = None;
+        self.next_request = Some(now + Duration::from_secs(BACKOFF_SECONDS));
+        self.unanswered = 0;
+    }
+}
+
+// An entry of the unicast master table
+#[derive(Debug, Clone)]
+struct Master {
+    // Learned from its first answer
+    identity: Option<PortIdentity>,
+    leases: ArrayVec<Lease, 3>,
+}
+
+impl Master {
+    fn is_waiting(&self) -> bool {
+        self.leases.iter().any(|lease| lease.unanswered > 0)
+    }
+}
+
+/// A signaling message to send to an entry of the unicast master table
+#[derive(Debug)]
+pub(crate) struct MasterMessage {
+    pub(crate) master: usize,
+    pub(crate) target: PortIdentity,
+    pub(crate) negotiations: ArrayVec<UnicastNegotiation, 3>,
+}
+
+/// The unicast messages a client port requested from the masters of its
+/// unicast master table.
+///
+/// Announce messages are requested from every master, so they all take part
+/// in the BMCA. Sync messages and delay responses are only requested from
+/// the master the BMCA selected as parent.
+#[derive(Debug, Default)]
+pub(crate) struct UnicastClient {
+    config: Option<UnicastClientConfig>,
+    masters: ArrayVec<Master, MAX_UNICAST_MASTERS>,
+    parent: Option<PortIdentity>,
+    // The grants of the previous parent, which still have to be cancelled
+    pending_cancel: Option<MasterMessage>,
+}
+
+impl UnicastClient {
+    /// A client for a port with `config`, requesting the messages the port
+    /// needs at its intervals
+    pub(crate) fn new(config: &PortConfig) -> Self {
+        let mut requests = ArrayVec::<_, 3>::new();
+        requests.push((MessageType::Announce, config.announce_interval.as_log_2()));
+        requests.push((MessageType::Sync, config.sync_interval.as_log_2()));
+        if let DelayMechanism::E2E { interval } = config.delay_mechanism {
+            requests.push((MessageType::DelayResp, interval.as_log_2()));
+        }
+
+        let mut client = Self::default();
+        client.set_config(config.unicast_client, &requests);
+        client
+    }
+
+    /// Request the messages of each type at the given log interval
+    pub(crate) fn set_config(
+        &mut self,
+        config: Option<UnicastClientConfig>,
+        requests: &[(MessageType, i8)],
+    ) {
+        let leases = requests
+            .iter()
+            .map(|&(message_type, log_interval)| Lease {
+                message_type,
+                log_interval,
+                expires: None,
+                next_request: None,
+                unanswered: 0,
+            })
+            .collect::<ArrayVec<_, 3>>();
+        let masters = config.map_or(0, |config| config.masters.min(MAX_UNICAST_MASTERS));
+
+        *self = Self {
+            config,
+            masters: (0..masters)
+                .map(|_| Master {
+                    identity: None,
+                    leases: leases.clone(),
+                })
+                .collect(),
+            ..Default::default()
+        };
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Request sync messages and delay responses from `parent` only, once it
+    /// is known to be in the table
+    pub(crate) fn set_parent(
+        &mut self,
+        parent: Option<PortIdentity>,
+        counts: &mut UnicastRequestCounts,
+    ) {
+        if parent == self.parent {
+            return;
+        }
+
+        if let Some(index) = self.parent_index() {
+            let master = &mut self.masters[index];
+            let mut cancels = ArrayVec::new();
+            for lease in &mut master.leases {
+                if lease.message_type == MessageType::Announce {
+                    continue;
+                }
+                if lease.expires.is_some() {
+                    cancels.push(UnicastNegotiation::Cancel {
+                        message_type: lease.message_type,
+                    });
+                }
+                lease.expires = None;
+                lease.next_request = None;
+                lease.unanswered = 0;
+            }
+            if let (Some(target), false) = (master.identity, cancels.is_empty()) {
+                self.pending_cancel = Some(MasterMessage {
+                    master: index,
+                    target,
+                    negotiations: cancels,
+                });
+            }
+        }
+
+        self.parent = parent;
+        if self.parent_index().is_some() {
+            counts.master_changes = counts.master_changes.saturating_add(1);
+        }
+    }
+
+    /// The master that currently grants delay responses
+    pub(crate) fn delay_resp_master(&self) -> Option<PortIdentity> {
+        self.masters[self.parent_index()?]
+            .leases
+            .iter()
+            .find(|lease| lease.message_type == MessageType::DelayResp)
+            .and_then(|lease| lease.expires)
+            .and(self.parent)
+    }
+
+    /// Drop the expired grants, and take the message to send to a master
+    /// now, if any. Also returns how long until the next message is due.
+    ///
+    /// Cancellations of the grants of the previous parent go first, and the
+    /// requests to the masters follow in the order of the table, each due
+    /// right after the previous one. The client can't tell the answers of
+    /// masters that never answered before apart, so those are asked one at a
+    /// time.
+    pub(crate) fn next_message(
+        &mut self,
+        now: Time,
+        counts: &mut UnicastRequestCounts,
+    ) -> (Option<MasterMessage>, core::time::Duration) {
+        if let Some(cancel) = self.pending_cancel.take() {
+            return (Some(cancel), core::time::Duration::ZERO);
+        }
+        let Some(config) = self.config else {
+            return (None, core::time::Duration::MAX);
+        };
+
+        for lease in self
+            .masters
+            .iter_mut()
+            .flat_map(|master| &mut master.leases)
+        {
+            if lease.expires.is_some_and(|expires| expires <= now) {
+                lease.expires = None;
+                counts.expired = counts.expired.saturating_add(1);
+            }
+            if lease.is_due(now) && lease.unanswered >= MAX_UNANSWERED {
+                lease.back_off(now);
+            }
+        }
+
+        let mut message = None;
+        for index in 0..self.masters.len() {
+            let due = self.due_leases(index, now);
+            if due.is_empty() {
+                continue;
+            }
+            if message.is_some() {
+                return (message, core::time::Duration::ZERO);
+            }
+
+            let master = &mut self.masters[index];
+            let mut negotiations = ArrayVec::new();
+            for lease_index in due {
+                let lease = &mut master.leases[lease_index];
+                negotiations.push(UnicastNegotiation::Request {
+                    message_type: lease.message_type,
+                    log_interval: lease.log_interval,
+                    duration: config.lease_duration,
+                });
+                lease.unanswered = lease.unanswered.saturating_add(1);
+                lease.next_request = Some(now + Duration::from_secs(REQUEST_TIMEOUT_SECONDS));
+                counts.requested = counts.requested.saturating_add(1);
+            }
+            message = Some(MasterMessage {
+                master: index,
+                target: master.identity.unwrap_or(PortIdentity::ALL),
+                negotiations,
+            });
+        }
+
+        let this = &*self;
+        let next = (0..this.masters.len())
+            .filter(|&index| !this.is_blocked(index))
+            .flat_map(move |index| {
+                let master = &this.masters[index];
+                master
+                    .leases
+                    .iter()
+                    .filter(move |lease| this.is_wanted(master, lease))
+            })
+            .filter_map(|lease| lease.next_request)
+            .min()
+            .map_or(core::time::Duration::MAX, |next| (next - now).into());
+        (message, next)
+    }
+
+    /// Handle the answer of `source` to a request for `message_type`. Returns
+    /// whether it answered a request of the client.
+    pub(crate) fn handle_grant(
+        &mut self,
+        source: PortIdentity,
+        message_type: MessageType,
+        duration: u32,
+        now: Time,
+        counts: &mut UnicastRequestCounts,
+    ) -> bool {
+        let is_waiting_for =
+            |lease: &Lease| lease.message_type == message_type && lease.unanswered > 0;
+        let index = self
+            .masters
+            .iter()
+            .position(|master| master.identity == Some(source))
+            .or_else(|| {
+                self.masters.iter().position(|master| {
+                    master.identity.is_none() && master.leases.iter().any(is_waiting_for)
+                })
+            });
+        let parent = self.parent;
+        let Some(master) = index.map(|index| &mut self.masters[index]) else {
+            return false;
+        };
+        let Some(lease) = master.leases.iter_mut().find(|lease| is_waiting_for(lease)) else {
+            return false;
+        };
+        // A parent selected before it answered is only now found in the table
+        if master.identity.is_none() && parent == Some(source) {
+            counts.master_changes = counts.master_changes.saturating_add(1);
+        }
+        master.identity = Some(source);
+
+        if duration == 0 {
+            counts.denied = counts.denied.saturating_add(1);
+            lease.back_off(now);
+            return true;
+        }
+
+        counts.granted = counts.granted.saturating_add(1);
+        let duration = Duration::from_secs(duration as i64);
+        lease.expires = Some(now + duration);
+        lease.next_request = Some(now + duration / 2);
+        lease.unanswered = 0;
+        true
+    }
+
+    /// Handle the end of the grant of `message_type` by `source`. Returns
+    /// whether it is a master of the table.
+    pub(crate) fn handle_cancel(
+        &mut self,
+        source: PortIdentity,
+        message_type: MessageType,
+        now: Time,
+        counts: &mut UnicastRequestCounts,
+    ) -> bool {
+        let Some(master) = self
+            .masters
+            .iter_mut()
+            .find(|master| master.identity == Some(source))
+        else {
+            return false;
+        };
+
+        for lease in &mut master.leases {
+            if lease.message_type == message_type {
+                lease.back_off(now);
+            }
+        }
+        counts.cancelled = counts.cancelled.saturating_add(1);
+        true
+    }
+
+    fn parent_index(&self) -> Option<usize> {
+        let parent = self.parent?;
+        self.masters
+            .iter()
+            .position(|master| master.identity == Some(parent))
+    }
+
+    // Sync messages and delay responses are only wanted from the parent
+    fn is_wanted(&self, master: &Master, lease: &Lease) -> bool {
+        lease.message_type == MessageType::Announce
+            || master.identity.is_some() && master.identity == self.parent
+    }
+
+    // Whether requests to a master that never answered have to wait for the
+    // answer of another such master
+    fn is_blocked(&self, index: usize) -> bool {
+        self.masters[index].identity.is_none()
+            && self.masters.iter().enumerate().any(|(other, master)| {
+                other != index && master.identity.is_none() && master.is_waiting()
+            })
+    }
+
+    // The leases of a master to request now, by index
+    fn due_leases(&self, index: usize, now: Time) -> ArrayVec<usize, 3> {
+        if self.is_blocked(index) {
+            return ArrayVec::new();
+        }
+
+        let master = &self.masters[index];
+        master
+            .leases
+            .iter()
+            .enumerate()
+            .filter(|(_, lease)| self.is_wanted(master, lease) && lease.is_due(now))
+            .map(|(lease_index, _)| lease_index)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClockIdentity;
+
+    fn master(number: u8) -> PortIdentity {
+        PortIdentity {
+            clock_identity: ClockIdentity([number; 8]),
+            port_number: 1,
+        }
+    }
+
+    fn client(masters: usize) -> UnicastClient {
+        let mut client = UnicastClient::default();
+        client.set_config(
+            Some(UnicastClientConfig {
+                masters,
+                lease_duration: 60,
+            }),
+            &[(MessageType::Announce, 1), (MessageType::Sync, -3)],
+        );
+        client
+    }
+
+    fn requested(message: &MasterMessage) -> std::vec::Vec<MessageType> {
+        message
+            .negotiations
+            .iter()
+            .map(|negotiation| match negotiation {
+                UnicastNegotiation::Request { message_type, .. }
+                | UnicastNegotiation::Cancel { message_type } => *message_type,
+                _ => panic!("Unexpected negotiation {negotiation:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn announce_from_every_master() {
+        let mut client = client(2);
+        let mut counts = UnicastRequestCounts::default();
+        let start = Time::from_secs(100);
+
+        // Masters that never answered are asked one at a time
+        let (message, next) = client.next_message(start, &mut counts);
+        let message = message.unwrap();
+        assert_eq!((message.master, message.target), (0, PortIdentity::ALL));
+        assert_eq!(requested(&message), [MessageType::Announce]);
+        assert_eq!(next, core::time::Duration::from_secs(1));
+        assert!(client.next_message(start, &mut counts).0.is_none());
+
+        assert!(client.handle_grant(master(1), MessageType::Announce, 60, start, &mut counts));
+        // Only requested messages are taken as answers
+        assert!(!client.handle_grant(master(2), MessageType::Sync, 60, start, &mut counts));
+
+        let (message, _) = client.next_message(start, &mut counts);
+        let message = message.unwrap();
+        assert_eq!((message.master, message.target), (1, PortIdentity::ALL));
+        assert!(client.handle_grant(master(2), MessageType::Announce, 60, start, &mut counts));
+
+        // Renewed halfway, at the masters that granted them
+        let (message, next) = client.next_message(start + Duration::from_secs(1), &mut counts);
+        assert!(message.is_none());
+        assert_eq!(next, core::time::Duration::from_secs(29));
+        let renewal = start + Duration::from_secs(30);
+        let (message, next) = client.next_message(renewal, &mut counts);
+        assert_eq!(message.unwrap().target, master(1));
+        assert_eq!(next, core::time::Duration::ZERO);
+        let (message, _) = client.next_message(renewal, &mut counts);
+        assert_eq!(message.unwrap().target, master(2));
+
+        assert_eq!(
+            counts,
+            UnicastRequestCounts {
+                requested: 4,
+                granted: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn sync_from_parent() {
+        let mut client = client(2);
+        let mut counts = UnicastRequestCounts::default();
+        let now = Time::from_secs(100);
+
+        for (index, identity) in [master(1), master(2)].into_iter().enumerate() {
+            let (message, _) = client.next_message(now, &mut counts);
+            assert_eq!(message.unwrap().master, index);
+            client.handle_grant(identity, MessageType::Announce, 60, now, &mut counts);
+        }
+
+        // A parent outside of the table changes nothing
+        client.set_parent(Some(master(3)), &mut counts);
+        assert!(client.next_message(now, &mut counts).0.is_none());
+
+        client.set_parent(Some(master(2)), &mut counts);
+        let (message, _) = client.next_message(now, &mut counts);
+        let message = message.unwrap();
+        assert_eq!((message.master, message.target), (1, master(2)));
+        assert_eq!(requested(&message), [MessageType::Sync]);
+        client.handle_grant(master(2), MessageType::Sync, 60, now, &mut counts);
+
+        // A new parent first cancels the grants of the previous one
+        client.set_parent(Some(master(1)), &mut counts);
+        let (message, next) = client.next_message(now, &mut counts);
+        let message = message.unwrap();
+        assert_eq!((message.master, message.target), (1, master(2)));
+        assert_eq!(
+            message.negotiations.as_slice(),
+            [UnicastNegotiation::Cancel {
+                message_type: MessageType::Sync
+            }]
+        );
+        assert_eq!(next, core::time::Duration::ZERO);
+        let (message, _) = client.next_message(now, &mut counts);
+        let message = message.unwrap();
+        assert_eq!((message.master, message.target), (0, master(1)));
+        assert_eq!(requested(&message), [MessageType::Sync]);
+
+        assert_eq!(counts.master_changes, 2);
+    }
+
+    #[test]
+    fn back_off() {
+        let mut client = client(2);
+        let mut counts = UnicastRequestCounts::default();
+        let mut now = Time::from_secs(100);
+
+        // A master that doesn't answer is left alone for a while after three
+        // requests, giving the next master its turn
+        for _ in 0..3 {
+            let (message, _) = client.next_message(now, &mut counts);
+            assert_eq!(message.unwrap().master, 0);
+            now += Duration::from_secs(1);
+        }
+        let (message, next) = client.next_message(now, &mut counts);
+        assert_eq!(message.unwrap().master, 1);
+        assert_eq!(next, core::time::Duration::from_secs(1));
+
+        // As is a master that denies a request
+        assert!(client.handle_grant(master(2), MessageType::Announce, 0, now, &mut counts));
+        let (message, next) = client.next_message(now, &mut counts);
+        assert!(message.is_none());
+        assert_eq!(next, core::time::Duration::from_secs(16));
+
+        now += Duration::from_secs(16);
+        let (message, next) = client.next_message(now, &mut counts);
+        assert_eq!(message.unwrap().master, 0);
+        assert_eq!(next, core::time::Duration::ZERO);
+        client.handle_grant(master(1), MessageType::Announce, 60, now, &mut counts);
+        let (message, _) = client.next_message(now, &mut counts);
+        assert_eq!(message.unwrap().target, master(2));
+        client.handle_grant(master(2), MessageType::Announce, 60, now, &mut counts);
+
+        // Or cancels a grant
+        assert!(!client.handle_cancel(master(3), MessageType::Announce, now, &mut counts));
+        assert!(client.handle_cancel(master(1), MessageType::Announce, now, &mut counts));
+        let (message, next) = client.next_message(now, &mut counts);
+        assert!(message.is_none());
+        assert_eq!(next, core::time::Duration::from_secs(16));
+
+        assert_eq!(counts.denied, 1);
+        assert_eq!(counts.cancelled, 1);
+    }
+
+    #[test]
+    fn grants_expire() {
+        let mut client = client(1);
+        let mut counts = UnicastRequestCounts::default();
+        let start = Time::from_secs(100);
+
+        // The parent is only known once it answered
+        client.set_parent(Some(master(1)), &mut counts);
+        client.next_message(start, &mut counts);
+        client.handle_grant(master(1), MessageType::Announce, 4, start, &mut counts);
+        let (message, _) = client.next_message(start, &mut counts);
+        assert_eq!(requested(&message.unwrap()), [MessageType::Sync]);
+        client.handle_grant(master(1), MessageType::Sync, 4, start, &mut counts);
+
+        // The renewals go unanswered until the grants end, after which the
+        // client backs off
+        for seconds in [2, 3, 4] {
+            let (message, _) =
+                client.next_message(start + Duration::from_secs(seconds), &mut counts);
+            assert_eq!(message.unwrap().negotiations.len(), 2);
+        }
+        assert_eq!(counts.expired, 2);
+        assert_eq!(counts.master_changes, 1);
+        let (message, next) = client.next_message(start + Duration::from_secs(5), &mut counts);
+        assert!(message.is_none());
+        assert_eq!(next, core::time::Duration::from_secs(16));
+    }
+}
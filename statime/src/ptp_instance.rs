@@ -1,19 +1,31 @@
-use core::sync::atomic::{AtomicI8, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicI8, AtomicU16, AtomicU32, AtomicU8, Ordering};
 
-use atomic_refcell::AtomicRefCell;
+use atomic_refcell::{AtomicRef, AtomicRefCell, AtomicRefMut, BorrowError};
 use rand::Rng;
 
+#[cfg(any(test, feature = "testing"))]
+use crate::port::TestPortState;
 use crate::{
-    bmc::bmca::Bmca,
+    bmc::{
+        bmca::{Bmca, RecommendedState},
+        DefaultMasterSelection, MasterSelection,
+    },
     clock::Clock,
-    config::InstanceConfig,
+    config::{InstanceConfig, InstanceConfigError},
     datastructures::{
-        common::PortIdentity,
-        datasets::{CurrentDS, DefaultDS, ParentDS, TimePropertiesDS},
+        common::{ClockIdentity, ClockQuality, PathTrace, PortIdentity, ProfileIdentifier},
+        datasets::{
+            CurrentDS, DefaultDS, LeapSecond, ParentDS, TimePropertiesDS, TimePropertiesError,
+        },
     },
-    port::{InBmca, Port},
+    filters::Filter,
+    log,
+    port::{CalibrationStore, InBmca, Port},
+    time::{Duration, Time},
     PortConfig,
 };
+#[cfg(feature = "snapshot")]
+use crate::{InstanceSnapshot, SnapshotError};
 
 /// A PTP node.
 ///
@@ -59,9 +71,56 @@ use crate::{
 ///
 /// instance.run(&TimerImpl).await;
 /// ```
-pub struct PtpInstance<C, F> {
+pub struct PtpInstance<C, F, S = DefaultMasterSelection> {
     state: AtomicRefCell<PtpInstanceState<C, F>>,
     log_bmca_interval: AtomicI8,
+    // Time properties of our own time source to use from the next BMCA run
+    pending_time_properties: AtomicRefCell<Option<TimePropertiesDS>>,
+    selection: S,
+}
+
+/// The state of a [`PtpInstance`] could not be changed, because the BMCA is
+/// running on another thread. Trying again once it finished succeeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstanceBusy;
+
+impl core::fmt::Display for InstanceBusy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "the instance is busy running the BMCA")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InstanceBusy {}
+
+/// A snapshot of the datasets of a [`PtpInstance`], see
+/// [`PtpInstance::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstanceStatus {
+    /// Identity of the local clock
+    pub clock_identity: ClockIdentity,
+    /// Priority 1 currently advertised by the local clock
+    pub priority_1: u8,
+    /// Priority 2 advertised by the local clock
+    pub priority_2: u8,
+    /// Quality advertised by the local clock
+    pub clock_quality: ClockQuality,
+    /// The PTP domain of the instance
+    pub domain_number: u8,
+    /// Number of communication paths between the grandmaster and this instance
+    pub steps_removed: u16,
+    /// Identity of the current grandmaster
+    pub grandmaster_identity: ClockIdentity,
+    /// Priority 1 of the current grandmaster
+    pub grandmaster_priority_1: u8,
+    /// Priority 2 of the current grandmaster
+    pub grandmaster_priority_2: u8,
+    /// Quality of the current grandmaster
+    pub grandmaster_clock_quality: ClockQuality,
+    /// Time properties of the current grandmaster
+    pub time_properties_ds: TimePropertiesDS,
+    /// Whether the instance is in free-run measurement mode
+    pub free_run: bool,
 }
 
 #[derive(Debug)]
@@ -69,20 +128,244 @@ pub(crate) struct PtpInstanceState<C, F> {
     pub(crate) default_ds: DefaultDS,
     pub(crate) current_ds: CurrentDS,
     pub(crate) parent_ds: ParentDS,
+    // The clocks between the grandmaster and us, including us, to send in
+    // announce messages. This is the pathTraceDS of IEEE1588-2019 section
+    // 16.2.2.
+    pub(crate) path_trace: PathTrace,
     pub(crate) time_properties_ds: TimePropertiesDS,
+    // Time properties of our own time source, used while we are grandmaster
+    pub(crate) local_time_properties_ds: TimePropertiesDS,
+    // Upcoming leap second of our own time source
+    pub(crate) leap_second: AtomicRefCell<Option<LeapSecond>>,
     pub(crate) local_clock: AtomicRefCell<C>,
     pub(crate) filter: AtomicRefCell<F>,
+    // Incremented every time the source of timestamps changes
+    pub(crate) clock_generation: AtomicU32,
+    // The clock generation the filter was last reset for
+    pub(crate) filter_generation: AtomicU32,
+    // Measure only, never adjust the local clock
+    pub(crate) free_run: AtomicBool,
+    // The frequency correction applied to the local clock, as the rate of the
+    // clock relative to its unadjusted oscillator
+    pub(crate) frequency_multiplier: AtomicRefCell<f64>,
+    // The total of the offsets the local clock was stepped by, to take out of
+    // its time for clocks that don't have a monotonic time
+    pub(crate) clock_steps: AtomicRefCell<Duration>,
+    // Set by a change that needs a BMCA run to take effect
+    pub(crate) bmca_requested: AtomicBool,
+    // Changes to the default dataset that take effect at the next BMCA run.
+    // They can be made while the ports are running, through the instance or
+    // through management messages.
+    pub(crate) pending_priority_1: PendingValue,
+    pub(crate) pending_priority_2: PendingValue,
+    pub(crate) pending_domain_number: PendingValue,
+    // Announce intervals master ports keep going after a better master
+    // appeared, while demoting. Zero when not demoting.
+    pub(crate) demotion_handover: AtomicU8,
+    // When master ports may leave master state during a demotion, set once a
+    // better master appeared
+    handover_at: Option<Time>,
+    // Where ports restore their calibration from when they are added
+    calibration_store: Option<&'static dyn CalibrationStore>,
+    // The profile we advertise in announce messages and expect our parent to
+    // advertise
+    pub(crate) profile_identifier: Option<ProfileIdentifier>,
+}
+
+/// A value of the default dataset to use from the next BMCA run
+#[derive(Debug)]
+pub(crate) struct PendingValue(AtomicU16);
+
+impl PendingValue {
+    const NONE: u16 = u16::MAX;
+
+    fn new() -> Self {
+        Self(AtomicU16::new(Self::NONE))
+    }
+
+    pub(crate) fn set(&self, value: u8) {
+        self.0.store(value.into(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> Option<u8> {
+        u8::try_from(self.0.load(Ordering::Relaxed)).ok()
+    }
+
+    fn take(&self) -> Option<u8> {
+        u8::try_from(self.0.swap(Self::NONE, Ordering::Relaxed)).ok()
+    }
+}
+
+impl<C, F> PtpInstanceState<C, F> {
+    pub(crate) fn new(
+        default_ds: DefaultDS,
+        time_properties_ds: TimePropertiesDS,
+        local_clock: C,
+        filter: F,
+    ) -> Self {
+        Self {
+            default_ds,
+            current_ds: Default::default(),
+            parent_ds: ParentDS::new(default_ds),
+            path_trace: PathTrace::new(default_ds.clock_identity),
+            time_properties_ds,
+            local_time_properties_ds: time_properties_ds,
+            leap_second: AtomicRefCell::new(None),
+            local_clock: AtomicRefCell::new(local_clock),
+            filter: AtomicRefCell::new(filter),
+            clock_generation: AtomicU32::new(0),
+            filter_generation: AtomicU32::new(0),
+            free_run: AtomicBool::new(false),
+            frequency_multiplier: AtomicRefCell::new(1.0),
+            clock_steps: AtomicRefCell::new(Duration::ZERO),
+            bmca_requested: AtomicBool::new(false),
+            pending_priority_1: PendingValue::new(),
+            pending_priority_2: PendingValue::new(),
+            pending_domain_number: PendingValue::new(),
+            demotion_handover: AtomicU8::new(0),
+            handover_at: None,
+            calibration_store: None,
+            profile_identifier: None,
+        }
+    }
+
+    /// The frequency correction currently applied to the local clock, see
+    /// [`Clock::frequency_multiplier`]. While another port is updating it,
+    /// the clock is taken to be uncorrected.
+    pub(crate) fn current_frequency_multiplier(&self) -> f64 {
+        self.frequency_multiplier
+            .try_borrow()
+            .map_or(1.0, |multiplier| *multiplier)
+    }
+
+    pub(crate) fn request_bmca(&self) {
+        log::debug!("Immediate BMCA requested");
+        self.bmca_requested.store(true, Ordering::Relaxed);
+    }
+
+    /// The time properties to put in announce messages sent at `now`
+    ///
+    /// While we are the grandmaster, these include any scheduled leap second.
+    /// Fails when the scheduled leap second is unexpectedly busy, which is a
+    /// bug in statime.
+    pub(crate) fn announced_time_properties(
+        &self,
+        now: Time,
+    ) -> Result<TimePropertiesDS, BorrowError> {
+        if self.parent_ds.grandmaster_identity != self.default_ds.clock_identity {
+            return Ok(self.time_properties_ds);
+        }
+
+        let leap_second = self.leap_second.try_borrow()?;
+        Ok(match *leap_second {
+            Some(leap_second) => self.time_properties_ds.with_leap_second(&leap_second, now),
+            None => self.time_properties_ds,
+        })
+    }
+
+    // Once a scheduled leap second has happened, it becomes part of our time
+    // properties
+    fn apply_leap_second(&mut self, now: Time) {
+        let leap_second = self.leap_second.get_mut();
+        if let Some(leap) = *leap_second {
+            if now >= leap.at {
+                log::info!("Leap second at {} has passed", leap.at);
+                self.local_time_properties_ds.pass_leap_second(&leap);
+                *leap_second = None;
+            }
+        }
+    }
 }
 
 impl<C: Clock, F> PtpInstanceState<C, F> {
-    fn bmca<R: Rng>(&mut self, ports: &mut [&mut Port<InBmca<'_, C, F>, R>]) {
-        let current_time = self.local_clock.get_mut().now().into();
+    // A time that doesn't jump when the local clock is stepped, see
+    // Clock::monotonic_now
+    pub(crate) fn monotonic_now(&self) -> Time {
+        let clock = self.local_clock.borrow();
+        clock
+            .monotonic_now()
+            .unwrap_or_else(|| clock.now() - *self.clock_steps.borrow())
+    }
+
+    fn bmca<R: Rng>(
+        &mut self,
+        ports: &mut [&mut Port<InBmca<'_, C, F>, R>],
+        selection: &impl MasterSelection,
+        new_time_properties: Option<TimePropertiesDS>,
+    ) {
+        let now = self.local_clock.get_mut().now();
+        self.apply_leap_second(now);
+        let current_time = now.into();
+
+        let mut announce = false;
+        if let Some(priority_1) = self.pending_priority_1.take() {
+            if priority_1 != self.default_ds.priority_1 {
+                log::info!(
+                    "Changing priority 1 from {} to {}",
+                    self.default_ds.priority_1,
+                    priority_1
+                );
+                self.default_ds.priority_1 = priority_1;
+                announce = true;
+            }
+        }
+
+        if let Some(priority_2) = self.pending_priority_2.take() {
+            if priority_2 != self.default_ds.priority_2 {
+                log::info!(
+                    "Changing priority 2 from {} to {}",
+                    self.default_ds.priority_2,
+                    priority_2
+                );
+                self.default_ds.priority_2 = priority_2;
+                announce = true;
+            }
+        }
+
+        if let Some(domain_number) = self.pending_domain_number.take() {
+            if domain_number != self.default_ds.domain_number {
+                log::info!(
+                    "Changing domain from {} to {}",
+                    self.default_ds.domain_number,
+                    domain_number
+                );
+                self.default_ds.domain_number = domain_number;
+                // Nothing learned in the old domain applies to the new one
+                self.current_ds = Default::default();
+                self.parent_ds = ParentDS::new(self.default_ds);
+                self.path_trace = PathTrace::new(self.default_ds.clock_identity);
+                self.time_properties_ds = self.local_time_properties_ds;
+                for port in ports.iter_mut() {
+                    port.restart_in_new_domain(now);
+                }
+            }
+        }
+
+        // Let the network know about the change right away, in case the ports
+        // stay master
+        if announce {
+            for port in ports.iter_mut() {
+                port.announce_now();
+            }
+        }
+
+        if let Some(time_properties_ds) = new_time_properties {
+            if time_properties_ds != self.local_time_properties_ds {
+                log::info!("Changing time properties to {:?}", time_properties_ds);
+                // Ports that stay master take these over below
+                self.local_time_properties_ds = time_properties_ds;
+                for port in ports.iter_mut() {
+                    port.announce_now();
+                }
+            }
+        }
 
         for port in ports.iter_mut() {
-            port.calculate_best_local_announce_message(current_time)
+            port.calculate_best_local_announce_message(current_time, selection)
         }
 
         let ebest = Bmca::find_best_announce_message(
+            selection,
             ports
                 .iter()
                 .filter_map(|port| port.best_local_announce_message()),
@@ -90,6 +373,7 @@ impl<C: Clock, F> PtpInstanceState<C, F> {
 
         for port in ports.iter_mut() {
             let recommended_state = Bmca::calculate_recommended_state(
+                selection,
                 &self.default_ds,
                 ebest,
                 port.best_local_announce_message(), // erbest
@@ -102,15 +386,59 @@ impl<C: Clock, F> PtpInstanceState<C, F> {
             );
 
             if let Some(recommended_state) = recommended_state {
+                if self.defer_handover(port, &recommended_state, now) {
+                    continue;
+                }
+
                 port.set_recommended_state(
                     recommended_state,
                     &mut self.time_properties_ds,
                     &mut self.current_ds,
                     &mut self.parent_ds,
+                    &mut self.path_trace,
                     &self.default_ds,
+                    &self.local_time_properties_ds,
+                    now,
                 );
             }
         }
+
+        self.finish_demotion(ports);
+    }
+
+    // Whether a master port should stay master while demoting, although a
+    // better master appeared, to give the slaves time to select it
+    fn defer_handover<R>(
+        &mut self,
+        port: &Port<InBmca<'_, C, F>, R>,
+        recommended_state: &RecommendedState,
+        now: Time,
+    ) -> bool {
+        let handover = self.demotion_handover.load(Ordering::Relaxed);
+        let stays_master = matches!(
+            recommended_state,
+            RecommendedState::M1(_) | RecommendedState::M2(_) | RecommendedState::M3(_)
+        );
+        if handover == 0 || stays_master || !port.is_master() {
+            return false;
+        }
+
+        let handover_at = *self.handover_at.get_or_insert_with(|| {
+            log::info!("New master observed, handing over in {handover} announce intervals");
+            now + Duration::from_interval(port.announce_interval()) * handover
+        });
+        now < handover_at
+    }
+
+    // A demotion is done once a better master took over all master ports
+    fn finish_demotion<R>(&mut self, ports: &[&mut Port<InBmca<'_, C, F>, R>]) {
+        if self.demotion_handover.load(Ordering::Relaxed) == 0 {
+            self.handover_at = None;
+        } else if self.handover_at.is_some() && !ports.iter().any(|port| port.is_master()) {
+            log::info!("Handed over to the new master");
+            self.demotion_handover.store(0, Ordering::Relaxed);
+            self.handover_at = None;
+        }
     }
 }
 
@@ -121,24 +449,46 @@ impl<C: Clock, F> PtpInstance<C, F> {
         local_clock: C,
         filter: F,
     ) -> Self {
-        let default_ds = DefaultDS::new(config);
+        Self::with_master_selection(
+            config,
+            time_properties_ds,
+            local_clock,
+            filter,
+            DefaultMasterSelection,
+        )
+    }
+}
+
+impl<C: Clock, F, S: MasterSelection> PtpInstance<C, F, S> {
+    /// Create an instance that orders candidate masters using `selection`
+    /// instead of the dataset comparison algorithm of IEEE1588.
+    pub fn with_master_selection(
+        config: InstanceConfig,
+        time_properties_ds: TimePropertiesDS,
+        local_clock: C,
+        filter: F,
+        selection: S,
+    ) -> Self {
         Self {
-            state: AtomicRefCell::new(PtpInstanceState {
-                default_ds,
-                current_ds: Default::default(),
-                parent_ds: ParentDS::new(default_ds),
+            state: AtomicRefCell::new(PtpInstanceState::new(
+                DefaultDS::new(config),
                 time_properties_ds,
-                local_clock: AtomicRefCell::new(local_clock),
-                filter: AtomicRefCell::new(filter),
-            }),
+                local_clock,
+                filter,
+            )),
             log_bmca_interval: AtomicI8::new(i8::MAX),
+            pending_time_properties: AtomicRefCell::new(None),
+            selection,
         }
     }
 
     /// Add and initialize this port
     ///
     /// We start in the BMCA state because that is convenient
-    pub fn add_port<R: Rng>(&self, config: PortConfig, rng: R) -> Port<InBmca<'_, C, F>, R> {
+    ///
+    /// With a [calibration store](Self::set_calibration_store), a calibration
+    /// stored for the port replaces the one in `config`.
+    pub fn add_port<R: Rng>(&self, mut config: PortConfig, rng: R) -> Port<InBmca<'_, C, F>, R> {
         self.log_bmca_interval
             .fetch_min(config.announce_interval.as_log_2(), Ordering::Relaxed);
         let mut state = self.state.borrow_mut();
@@ -147,16 +497,584 @@ impl<C: Clock, F> PtpInstance<C, F> {
             port_number: state.default_ds.number_ports,
         };
         state.default_ds.number_ports += 1;
-        Port::new(&self.state, config, port_identity, rng)
+
+        let calibration_store = state.calibration_store;
+        if let Some(calibration) =
+            calibration_store.and_then(|store| store.load(port_identity.port_number))
+        {
+            log::info!(
+                "Restoring calibration of port {}: {:?}",
+                port_identity.port_number,
+                calibration
+            );
+            calibration.apply_to(&mut config);
+        }
+
+        let clock_generation = state.clock_generation.load(Ordering::Relaxed);
+        Port::new(
+            &self.state,
+            config,
+            port_identity,
+            clock_generation,
+            calibration_store,
+            rng,
+        )
+    }
+
+    /// Keep the calibration of the ports in `store`, see [`CalibrationStore`].
+    /// Ports restore their calibration from it when they are added, and store
+    /// it there when it changes with
+    /// [`Port::set_calibration`](crate::Port::set_calibration). Disabled with
+    /// `None`, which is the default.
+    ///
+    /// This should be called right after creating the instance, before adding
+    /// any ports.
+    pub fn set_calibration_store(&self, store: Option<&'static dyn CalibrationStore>) {
+        self.state.borrow_mut().calibration_store = store;
+    }
+
+    /// Advertise the profile this instance runs in its announce messages,
+    /// for peers and monitoring tools, see [`ProfileIdentifier`]. Disabled
+    /// with `None`, which is the default.
+    /// [`Profile::identifier`](crate::Profile::identifier) gives the
+    /// identifiers of the profiles statime knows about.
+    ///
+    /// A slave port whose master advertises another profile counts its
+    /// announce messages in
+    /// [`PortStatistics::profile_mismatches`](crate::PortStatistics::profile_mismatches),
+    /// and reports a
+    /// [`PortEvent::ParentProfileMismatch`](crate::PortEvent::ParentProfileMismatch).
+    ///
+    ///
+    /// This should be called right after creating the instance, before adding
+    /// any ports.
+    pub fn set_profile_identifier(&self, profile_identifier: Option<ProfileIdentifier>) {
+        self.state.borrow_mut().profile_identifier = profile_identifier;
+    }
+
+    /// Set the localPriority of the clock itself (defaultDS.localPriority),
+    /// 1 to 255 with the lowest the most preferred. The default is 128.
+    ///
+    /// Only an alternate BMCA such as that of
+    /// [`Profile::G8275_1`](crate::Profile::G8275_1) uses it, when comparing
+    /// this clock against the masters it hears. Takes effect at the next run
+    /// of the BMCA.
+    pub fn set_local_priority(&self, local_priority: u8) {
+        self.state.borrow_mut().default_ds.local_priority = local_priority;
     }
 
+    /// Run the best master clock algorithm over the given ports.
+    ///
+    /// With the [`DefaultMasterSelection`], candidate grandmasters are
+    /// compared as in IEEE1588-2019 figure 34: by
+    /// priority 1, then clock class, clock accuracy, offset scaled log
+    /// variance, priority 2 and finally clock identity. For each of these
+    /// the lower value wins, and a later field only matters when all earlier
+    /// fields are equal. This matches the ordering used by ptp4l.
     pub fn bmca<R: Rng>(&self, ports: &mut [&mut Port<InBmca<'_, C, F>, R>]) {
-        self.state.borrow_mut().bmca(ports)
+        let new_time_properties = self.pending_time_properties().take();
+        let mut state = self.state.borrow_mut();
+        state.bmca_requested.store(false, Ordering::Relaxed);
+        state.bmca(ports, &self.selection, new_time_properties)
+    }
+
+    /// Request the best master clock algorithm to be run as soon as possible,
+    /// instead of at the end of the current [`bmca_interval`].
+    ///
+    /// This is useful after changing the priorities or clock quality of this
+    /// instance, so the port states reflect the change right away. Running the
+    /// BMCA requires all ports, which are owned by the runtime, so this only
+    /// records the request. The runtime should check [`bmca_requested`]
+    /// whenever it handles an event and run [`bmca`] if it returns true.
+    ///
+    /// [`bmca_interval`]: Self::bmca_interval
+    /// [`bmca_requested`]: Self::bmca_requested
+    /// [`bmca`]: Self::bmca
+    pub fn reevaluate_bmca_now(&self) {
+        self.shared_state().request_bmca();
+    }
+
+    /// Change the priority 1 this instance advertises.
+    ///
+    /// This allows an external availability manager to coordinate an
+    /// active/standby pair of grandmasters, by demoting the standby to a
+    /// higher (worse) priority 1 and promoting it again on failover. The new
+    /// priority takes effect at the next run of the BMCA, which is requested
+    /// immediately as with [`reevaluate_bmca_now`](Self::reevaluate_bmca_now).
+    /// Ports that are master at that point send an announce message right
+    /// away, so the rest of the network learns about the change without
+    /// waiting for the announce interval.
+    ///
+    /// A slave-only instance must keep priority 1 at 255.
+    pub fn set_priority_1(&self, priority_1: u8) -> Result<(), InstanceConfigError> {
+        if self.shared_state().default_ds.slave_only && priority_1 != 255 {
+            return Err(InstanceConfigError::SlaveOnlyPriority1(priority_1));
+        }
+
+        let state = self.shared_state();
+        state.pending_priority_1.set(priority_1);
+        state.demotion_handover.store(0, Ordering::Relaxed);
+        state.request_bmca();
+        Ok(())
+    }
+
+    /// Step down as grandmaster without interrupting the sync messages the
+    /// slaves rely on, unlike simply shutting down.
+    ///
+    /// The demoted `priority_1`, which should be worse than that of the clock
+    /// taking over, is announced right away as with
+    /// [`set_priority_1`](Self::set_priority_1). The other clocks then start
+    /// to select the new master, but ports that are master keep sending
+    /// announce and sync messages until `handover` announce intervals after
+    /// this instance observed the new master, giving all slaves time to
+    /// follow. Only then do they leave master state. With a `handover` of 0
+    /// this is the same as [`set_priority_1`](Self::set_priority_1).
+    ///
+    /// As long as no better master appears, the ports stay master with the
+    /// demoted priority.
+    pub fn demote(&self, priority_1: u8, handover: u8) -> Result<(), InstanceConfigError> {
+        self.set_priority_1(priority_1)?;
+        log::info!("Demoting to priority 1 {priority_1}");
+        self.shared_state()
+            .demotion_handover
+            .store(handover, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Whether a demotion started with [`demote`](Self::demote) is waiting
+    /// for a new master to take over. Changing the priority with
+    /// [`set_priority_1`](Self::set_priority_1) cancels it.
+    pub fn demotion_pending(&self) -> bool {
+        self.shared_state()
+            .demotion_handover
+            .load(Ordering::Relaxed)
+            != 0
+    }
+
+    /// The priority 1 this instance advertises, including a change made with
+    /// [`set_priority_1`](Self::set_priority_1) that has not taken effect yet.
+    pub fn priority_1(&self) -> u8 {
+        let state = self.shared_state();
+        state
+            .pending_priority_1
+            .get()
+            .unwrap_or(state.default_ds.priority_1)
+    }
+
+    /// Change the time properties of the time source of this instance, for
+    /// example when a GNSS receiver gains or loses its fix or learns the
+    /// current UTC offset.
+    ///
+    /// While this instance is the grandmaster, the new time properties are
+    /// announced from the next run of the BMCA, which is requested
+    /// immediately. Ports that are master at that point send an announce
+    /// message right away, so slaves use the new properties for their next
+    /// clock adjustment. A leap second scheduled with
+    /// [`schedule_leap_second`](Self::schedule_leap_second) is still applied
+    /// on top of these.
+    pub fn set_time_properties(
+        &self,
+        time_properties_ds: TimePropertiesDS,
+    ) -> Result<(), TimePropertiesError> {
+        time_properties_ds.validate()?;
+
+        *self.pending_time_properties() = Some(time_properties_ds);
+        self.reevaluate_bmca_now();
+        Ok(())
+    }
+
+    /// The time properties of the time source of this instance, including a
+    /// change made with [`set_time_properties`](Self::set_time_properties)
+    /// that has not taken effect yet.
+    pub fn time_properties(&self) -> TimePropertiesDS {
+        match *self.pending_time_properties() {
+            Some(time_properties_ds) => time_properties_ds,
+            None => self.shared_state().local_time_properties_ds,
+        }
+    }
+
+    fn pending_time_properties(&self) -> AtomicRefMut<'_, Option<TimePropertiesDS>> {
+        // Only ever borrowed briefly, by the BMCA and the functions above
+        loop {
+            if let Ok(pending) = self.pending_time_properties.try_borrow_mut() {
+                return pending;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Whether [`reevaluate_bmca_now`](Self::reevaluate_bmca_now) was called,
+    /// or a management message changed the instance, since the last run of
+    /// the BMCA.
+    pub fn bmca_requested(&self) -> bool {
+        self.shared_state().bmca_requested.load(Ordering::Relaxed)
+    }
+
+    /// Schedule a leap second reported by the time source of this instance,
+    /// or cancel it by passing `None`.
+    ///
+    /// While this instance is the grandmaster, its announce messages carry the
+    /// leap indicator during the 12 hours before the leap second, and the
+    /// adjusted UTC offset from the moment it has happened. Each port reports
+    /// these changes as [`PortEvent`](crate::PortEvent)s.
+    ///
+    /// Fails while the BMCA is running on another thread, or a port is
+    /// building an announce message.
+    pub fn schedule_leap_second(
+        &self,
+        leap_second: Option<LeapSecond>,
+    ) -> Result<(), InstanceBusy> {
+        let state = self.state.try_borrow().map_err(|_| InstanceBusy)?;
+        *state
+            .leap_second
+            .try_borrow_mut()
+            .map_err(|_| InstanceBusy)? = leap_second;
+        log::info!("Scheduled leap second: {:?}", leap_second);
+        Ok(())
+    }
+
+    /// Notify the instance that the source of its timestamps has changed.
+    ///
+    /// This should be called when the clock that timestamps packets is
+    /// replaced at runtime, for example when a bonded interface fails over to
+    /// a NIC with a different PHC. Timestamps taken before and after such a
+    /// change come from unrelated oscillators, so the filter is reset and
+    /// every port discards its in-flight measurements and delay estimate the
+    /// next time it handles an event. Each port records the change in its
+    /// [`PortStatistics`](crate::PortStatistics).
+    pub fn notify_clock_source_change(&self) {
+        let state = self.shared_state();
+        state.clock_generation.fetch_add(1, Ordering::Relaxed);
+        log::info!("Clock source changed, restarting synchronization");
+    }
+
+    /// Switch free-run measurement mode on or off.
+    ///
+    /// In free-run mode the full protocol keeps running and ports still
+    /// produce measurements and statistics, but the local clock is never
+    /// adjusted. This is meant for monitoring setups that must not touch the
+    /// clock. When switching back to normal operation the filter is reset, so
+    /// it starts from the current state of the clock.
+    ///
+    /// Fails while the BMCA is running on another thread, or a port is
+    /// handling a measurement.
+    pub fn set_free_run(&self, enabled: bool) -> Result<(), InstanceBusy>
+    where
+        F: Filter,
+    {
+        let state = self.state.try_borrow().map_err(|_| InstanceBusy)?;
+        if state.free_run.load(Ordering::Relaxed) == enabled {
+            return Ok(());
+        }
+
+        if !enabled {
+            state
+                .filter
+                .try_borrow_mut()
+                .map_err(|_| InstanceBusy)?
+                .reset();
+        }
+        state.free_run.store(enabled, Ordering::Relaxed);
+
+        log::info!(
+            "Free-run measurement mode {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        Ok(())
+    }
+
+    /// Whether the filter considers the local clock to be locked to its
+    /// master, see [`Filter::is_locked`].
+    ///
+    /// Fails while the BMCA is running on another thread, or a port is
+    /// handling a measurement.
+    pub fn is_locked(&self) -> Result<bool, InstanceBusy>
+    where
+        F: Filter,
+    {
+        let state = self.state.try_borrow().map_err(|_| InstanceBusy)?;
+        let filter = state.filter.try_borrow().map_err(|_| InstanceBusy)?;
+        Ok(filter.is_locked())
+    }
+
+    /// A snapshot of the datasets of this instance, as determined by the last
+    /// run of the BMCA.
+    pub fn status(&self) -> InstanceStatus {
+        let state = self.shared_state();
+        InstanceStatus {
+            clock_identity: state.default_ds.clock_identity,
+            priority_1: state.default_ds.priority_1,
+            priority_2: state.default_ds.priority_2,
+            clock_quality: state.default_ds.clock_quality,
+            domain_number: state.default_ds.domain_number,
+            steps_removed: state.current_ds.steps_removed,
+            grandmaster_identity: state.parent_ds.grandmaster_identity,
+            grandmaster_priority_1: state.parent_ds.grandmaster_priority_1,
+            grandmaster_priority_2: state.parent_ds.grandmaster_priority_2,
+            grandmaster_clock_quality: state.parent_ds.grandmaster_clock_quality,
+            time_properties_ds: state.time_properties_ds,
+            free_run: state.free_run.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether the instance is in free-run measurement mode, see
+    /// [`set_free_run`](Self::set_free_run).
+    pub fn free_run(&self) -> bool {
+        self.shared_state().free_run.load(Ordering::Relaxed)
+    }
+
+    /// Take a snapshot of the state this instance has built up while
+    /// running, to warm-start a new instance with after a restart.
+    #[cfg(feature = "snapshot")]
+    pub fn snapshot(&self) -> InstanceSnapshot {
+        let priority_1 = self.priority_1();
+        let local_time_properties_ds = self.time_properties();
+        let state = self.shared_state();
+        let leap_second = loop {
+            // Ports only hold this borrow briefly while building an announce message
+            if let Ok(leap_second) = state.leap_second.try_borrow() {
+                break *leap_second;
+            }
+            core::hint::spin_loop();
+        };
+
+        InstanceSnapshot {
+            clock_identity: state.default_ds.clock_identity,
+            domain_number: state.default_ds.domain_number,
+            priority_1,
+            free_run: state.free_run.load(Ordering::Relaxed),
+            steps_removed: state.current_ds.steps_removed,
+            parent_ds: state.parent_ds.clone(),
+            time_properties_ds: state.time_properties_ds,
+            local_time_properties_ds,
+            leap_second,
+        }
+    }
+
+    /// Restore a snapshot taken with [`snapshot`](Self::snapshot).
+    ///
+    /// This should be called right after creating the instance, before adding
+    /// any ports. The snapshot must have been taken of an instance with the
+    /// same clock identity and domain. The restored time properties of the
+    /// local time source replace the ones the instance was created with, as
+    /// they include any leap second that has passed since.
+    #[cfg(feature = "snapshot")]
+    pub fn restore(&self, snapshot: &InstanceSnapshot) -> Result<(), SnapshotError> {
+        let mut state = self.state.borrow_mut();
+
+        if snapshot.clock_identity != state.default_ds.clock_identity {
+            return Err(SnapshotError::ClockIdentityMismatch);
+        }
+
+        if snapshot.domain_number != state.default_ds.domain_number {
+            return Err(SnapshotError::DomainMismatch);
+        }
+
+        if state.default_ds.slave_only && snapshot.priority_1 != 255 {
+            return Err(SnapshotError::SlaveOnlyPriority1(snapshot.priority_1));
+        }
+
+        log::info!("Restoring instance snapshot");
+
+        state.default_ds.priority_1 = snapshot.priority_1;
+        state.current_ds.steps_removed = snapshot.steps_removed;
+        state.parent_ds = snapshot.parent_ds.clone();
+        state.time_properties_ds = snapshot.time_properties_ds;
+        state.local_time_properties_ds = snapshot.local_time_properties_ds;
+        *state.leap_second.get_mut() = snapshot.leap_second;
+        state.free_run.store(snapshot.free_run, Ordering::Relaxed);
+        state.pending_priority_1.take();
+        *self.pending_time_properties() = None;
+
+        Ok(())
     }
 
     pub fn bmca_interval(&self) -> core::time::Duration {
-        core::time::Duration::from_secs_f64(
-            2f64.powi(self.log_bmca_interval.load(Ordering::Relaxed) as i32),
+        core::time::Duration::from_secs_f64(libm::pow(
+            2.0,
+            self.log_bmca_interval.load(Ordering::Relaxed) as f64,
+        ))
+    }
+
+    // Borrow the state from outside the ports. The BMCA only holds the state
+    // mutably for a short time, so wait for it instead of panicking when the
+    // runtime calls us from another thread.
+    fn shared_state(&self) -> AtomicRef<'_, PtpInstanceState<C, F>> {
+        loop {
+            if let Ok(state) = self.state.try_borrow() {
+                return state;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Direct access to the instance state for state-machine level tests.
+///
+/// These functions bypass the normal protocol operation, allowing tests to set
+/// up the datasets and ports in a specific situation without first having to
+/// exchange messages to get there. They borrow the instance state mutably, so
+/// they may only be called while none of the ports are running.
+#[cfg(any(test, feature = "testing"))]
+impl<C: Clock, F, S: MasterSelection> PtpInstance<C, F, S> {
+    /// Override the grandmaster information in the parent dataset
+    pub fn set_grandmaster(
+        &self,
+        identity: ClockIdentity,
+        clock_quality: ClockQuality,
+        priority_1: u8,
+        priority_2: u8,
+    ) {
+        let mut state = self.state.borrow_mut();
+        state.parent_ds.grandmaster_identity = identity;
+        state.parent_ds.grandmaster_clock_quality = clock_quality;
+        state.parent_ds.grandmaster_priority_1 = priority_1;
+        state.parent_ds.grandmaster_priority_2 = priority_2;
+    }
+
+    /// Override the steps removed from the grandmaster in the current dataset
+    pub fn set_steps_removed(&self, steps_removed: u16) {
+        self.state.borrow_mut().current_ds.steps_removed = steps_removed;
+    }
+
+    /// Override the time properties dataset
+    pub fn set_time_properties_ds(&self, time_properties_ds: TimePropertiesDS) {
+        self.state.borrow_mut().time_properties_ds = time_properties_ds;
+    }
+
+    /// Add a port that starts out in the given state rather than listening.
+    pub fn add_port_in_state<R: Rng>(
+        &self,
+        config: PortConfig,
+        rng: R,
+        port_state: TestPortState,
+    ) -> Port<InBmca<'_, C, F>, R> {
+        let sdo_id = self.state.borrow().default_ds.sdo_id;
+        let mut port = self.add_port(config, rng);
+        port.force_state(port_state, sdo_id);
+        port
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BasicFilter, Duration, LeapIndicator, SdoId, TimePropertiesDS};
+
+    struct TestClock;
+
+    impl Clock for TestClock {
+        type Error = core::convert::Infallible;
+
+        fn now(&self) -> Time {
+            Time::from_secs(10)
+        }
+
+        fn adjust(
+            &mut self,
+            _time_offset: Duration,
+            _frequency_multiplier: f64,
+            _time_properties_ds: &TimePropertiesDS,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    fn test_instance() -> PtpInstance<TestClock, BasicFilter> {
+        PtpInstance::new(
+            InstanceConfig {
+                clock_identity: ClockIdentity([1; 8]),
+                priority_1: 128,
+                priority_2: 128,
+                domain_number: 0,
+                slave_only: false,
+                sdo_id: SdoId::default(),
+            },
+            TimePropertiesDS::default(),
+            TestClock,
+            BasicFilter::new(0.25),
         )
     }
+
+    #[test]
+    fn schedule_leap_second_while_busy() {
+        let instance = test_instance();
+        let leap_second = Some(LeapSecond {
+            at: Time::from_secs(100_000),
+            leap_indicator: LeapIndicator::Leap61,
+        });
+
+        // The BMCA on another thread
+        let bmca = instance.state.borrow_mut();
+        assert_eq!(
+            instance.schedule_leap_second(leap_second),
+            Err(InstanceBusy)
+        );
+        drop(bmca);
+
+        // A port building an announce message
+        let state = instance.state.borrow();
+        let announcing = state.leap_second.borrow();
+        assert_eq!(
+            instance.schedule_leap_second(leap_second),
+            Err(InstanceBusy)
+        );
+        drop(announcing);
+        drop(state);
+
+        instance.schedule_leap_second(leap_second).unwrap();
+        assert_eq!(*instance.state.borrow().leap_second.borrow(), leap_second);
+    }
+
+    #[test]
+    fn set_free_run_while_busy() {
+        let instance = test_instance();
+        instance.set_free_run(true).unwrap();
+
+        // The BMCA on another thread
+        let bmca = instance.state.borrow_mut();
+        assert_eq!(instance.set_free_run(false), Err(InstanceBusy));
+        drop(bmca);
+
+        // A port handling a measurement, which leaves the mode unchanged
+        let state = instance.state.borrow();
+        let measuring = state.filter.borrow_mut();
+        assert_eq!(instance.set_free_run(false), Err(InstanceBusy));
+        assert!(instance.free_run());
+        drop(measuring);
+        drop(state);
+
+        instance.set_free_run(false).unwrap();
+        assert!(!instance.free_run());
+    }
+    #[test]
+    fn is_locked_while_busy() {
+        let instance = test_instance();
+        assert_eq!(instance.is_locked(), Ok(false));
+
+        // The BMCA on another thread
+        let bmca = instance.state.borrow_mut();
+        assert_eq!(instance.is_locked(), Err(InstanceBusy));
+        drop(bmca);
+
+        // A port handling a measurement
+        let state = instance.state.borrow();
+        let measuring = state.filter.borrow_mut();
+        assert_eq!(instance.is_locked(), Err(InstanceBusy));
+        drop(measuring);
+        drop(state);
+
+        assert_eq!(instance.is_locked(), Ok(false));
+    }
+
+    #[test]
+    fn monotonic_now_without_clock_support() {
+        let instance = test_instance();
+        let state = instance.state.borrow();
+        assert_eq!(state.monotonic_now(), Time::from_secs(10));
+
+        // The steps the ports made are taken out of the time of the clock
+        *state.clock_steps.borrow_mut() += Duration::from_secs(4);
+        assert_eq!(state.monotonic_now(), Time::from_secs(6));
+    }
 }
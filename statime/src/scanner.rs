@@ -0,0 +1,451 @@
+//! Passive discovery of the PTP traffic on a network, to help configure a new
+//! node on a network that isn't documented.
+//!
+//! A [`NetworkScanner`] is given every packet received on the PTP ports and
+//! never sends anything. It keeps track of the domains that carry traffic, the
+//! masters that announce themselves in them, and which profile the message
+//! intervals and TLVs of a domain point to.
+
+use arrayvec::ArrayVec;
+
+use crate::{
+    config::{IntervalBounds, Profile},
+    datastructures::{
+        common::TlvType,
+        messages::{
+            decode_message, message_tlvs, DecodeError, DecodedAnnounce, MessageType,
+            UNSPECIFIED_LOG_INTERVAL,
+        },
+    },
+    port::MessageRate,
+    ClockIdentity, Time,
+};
+
+/// Most domains a [`NetworkScanner`] keeps track of
+pub const MAX_SCANNED_DOMAINS: usize = 8;
+
+/// Most masters a [`NetworkScanner`] keeps track of, over all domains
+pub const MAX_SCANNED_MASTERS: usize = 16;
+
+/// Most distinct TLV types kept per domain
+const MAX_SCANNED_TLV_TYPES: usize = 8;
+
+/// How a packet reached the scanner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    /// UDP over IPv4, IEEE1588-2019 annex C
+    UdpIpv4,
+    /// UDP over IPv6, IEEE1588-2019 annex D
+    UdpIpv6,
+    /// Directly in ethernet frames, IEEE1588-2019 annex E
+    Ethernet,
+}
+
+/// The profile a domain most likely uses, judging by its traffic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InferredProfile {
+    /// The default delay request-response profile, see [`Profile::Default`]
+    Default,
+    /// The broadcast media profile of SMPTE ST 2059-2, see
+    /// [`Profile::Smpte2059`]
+    Smpte2059,
+    /// The generalized PTP of IEEE 802.1AS, which statime doesn't implement
+    Ieee8021As,
+}
+
+impl InferredProfile {
+    /// The profile to configure statime with to join the domain, if statime
+    /// implements it
+    pub fn profile(self) -> Option<Profile> {
+        match self {
+            InferredProfile::Default => Some(Profile::Default),
+            InferredProfile::Smpte2059 => Some(Profile::Smpte2059),
+            InferredProfile::Ieee8021As => None,
+        }
+    }
+}
+
+/// The traffic seen in one domain, see [`NetworkScanner::domains`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedDomain {
+    pub domain_number: u8,
+    pub sdo_id: u16,
+    /// Number of messages received in the domain
+    pub messages: u32,
+    /// Whether any message was sent unicast
+    pub unicast: bool,
+    /// Whether any message was sent multicast
+    pub multicast: bool,
+    /// Whether any sync message announced a follow up
+    pub two_step: bool,
+    /// Whether delay request-response messages were seen
+    pub delay_req: bool,
+    /// Whether peer delay messages were seen
+    pub peer_delay: bool,
+    /// The log 2 of the announce interval, from the most recent announce
+    pub announce_interval: Option<i8>,
+    /// The log 2 of the sync interval, from the most recent sync
+    pub sync_interval: Option<i8>,
+    /// The log 2 of the minimum delay request interval, from the most recent
+    /// delay response
+    pub min_delay_req_interval: Option<i8>,
+    transports: ArrayVec<Transport, 3>,
+    tlv_types: ArrayVec<TlvType, MAX_SCANNED_TLV_TYPES>,
+}
+
+impl ScannedDomain {
+    fn new(domain_number: u8, sdo_id: u16) -> Self {
+        Self {
+            domain_number,
+            sdo_id,
+            messages: 0,
+            unicast: false,
+            multicast: false,
+            two_step: false,
+            delay_req: false,
+            peer_delay: false,
+            announce_interval: None,
+            sync_interval: None,
+            min_delay_req_interval: None,
+            transports: ArrayVec::new(),
+            tlv_types: ArrayVec::new(),
+        }
+    }
+
+    /// The transports messages of the domain arrived over
+    pub fn transports(&self) -> &[Transport] {
+        &self.transports
+    }
+
+    /// The types of the TLVs that messages in the domain carried, up to eight
+    /// of them
+    pub fn tlv_types(&self) -> &[TlvType] {
+        &self.tlv_types
+    }
+
+    /// The profile the domain most likely uses.
+    ///
+    /// An sdo id with major 1 or peer delay messages point to IEEE 802.1AS.
+    /// Otherwise, domain 127 or intervals faster than the default profile
+    /// allows point to SMPTE ST 2059-2, as long as its intervals fit that
+    /// profile. Without announce or sync messages, or with intervals neither
+    /// profile allows, there is no telling.
+    pub fn profile(&self) -> Option<InferredProfile> {
+        if self.sdo_id >> 8 == 1 || self.peer_delay {
+            return Some(InferredProfile::Ieee8021As);
+        }
+
+        if self.announce_interval.is_none() && self.sync_interval.is_none() {
+            return None;
+        }
+
+        if self.domain_number == 127 || !self.fits(IntervalBounds::DEFAULT_PROFILE) {
+            self.fits(IntervalBounds::SMPTE_2059)
+                .then_some(InferredProfile::Smpte2059)
+        } else {
+            Some(InferredProfile::Default)
+        }
+    }
+
+    fn fits(&self, bounds: IntervalBounds) -> bool {
+        let within = |interval: Option<i8>, (min, max): (i8, i8)| {
+            interval.is_none_or(|interval| (min..=max).contains(&interval))
+        };
+
+        within(self.announce_interval, bounds.announce)
+            && within(self.sync_interval, bounds.sync)
+            && within(self.min_delay_req_interval, bounds.min_delay_req)
+    }
+}
+
+/// A master that announced itself, see [`NetworkScanner::masters`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScannedMaster {
+    pub domain_number: u8,
+    pub sdo_id: u16,
+    /// Clock identity of the port sending the announce messages
+    pub clock_identity: ClockIdentity,
+    /// Number of the port sending the announce messages
+    pub port_number: u16,
+    /// The transport its most recent announce message arrived over
+    pub transport: Transport,
+    /// The body of its most recent announce message
+    pub announce: DecodedAnnounce,
+    /// The rate of its announce messages
+    pub announce_rate: MessageRate,
+    /// The rate of its sync messages, counted once it announced itself
+    pub sync_rate: MessageRate,
+}
+
+/// Passive scanner of the PTP traffic on a network, see the
+/// [module documentation](self).
+///
+/// Domains and masters are kept in the order they were first seen, up to
+/// [`MAX_SCANNED_DOMAINS`] and [`MAX_SCANNED_MASTERS`]. Messages of domains
+/// and masters beyond those are only counted in
+/// [`untracked`](Self::untracked).
+#[derive(Debug, Clone, Default)]
+pub struct NetworkScanner {
+    domains: ArrayVec<ScannedDomain, MAX_SCANNED_DOMAINS>,
+    masters: ArrayVec<ScannedMaster, MAX_SCANNED_MASTERS>,
+    untracked: u32,
+}
+
+impl NetworkScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take in a packet received at local time `now` on one of the PTP ports,
+    /// over `transport`
+    pub fn observe(
+        &mut self,
+        data: &[u8],
+        transport: Transport,
+        now: Time,
+    ) -> Result<(), DecodeError> {
+        let message = decode_message(data)?;
+
+        let domain = match self.domains.iter().position(|domain| {
+            domain.domain_number == message.domain_number && domain.sdo_id == message.sdo_id
+        }) {
+            Some(index) => &mut self.domains[index],
+            None => {
+                let domain = ScannedDomain::new(message.domain_number, message.sdo_id);
+                if self.domains.try_push(domain).is_err() {
+                    self.untracked = self.untracked.saturating_add(1);
+                    return Ok(());
+                }
+                self.domains.last_mut().unwrap()
+            }
+        };
+
+        domain.messages = domain.messages.saturating_add(1);
+        if !domain.transports.contains(&transport) {
+            // Holds every transport
+            domain.transports.push(transport);
+        }
+        for (tlv_type, _) in message_tlvs(data) {
+            if !domain.tlv_types.contains(&tlv_type) {
+                // Types beyond the capacity are left out
+                let _ = domain.tlv_types.try_push(tlv_type);
+            }
+        }
+        if message.unicast {
+            domain.unicast = true;
+        } else {
+            domain.multicast = true;
+        }
+
+        let interval = (message.log_message_interval != UNSPECIFIED_LOG_INTERVAL)
+            .then_some(message.log_message_interval);
+        match message.message_type {
+            MessageType::Sync => {
+                domain.two_step |= message.two_step;
+                domain.sync_interval = interval.or(domain.sync_interval);
+            }
+            MessageType::Announce => {
+                domain.announce_interval = interval.or(domain.announce_interval);
+            }
+            MessageType::DelayReq => domain.delay_req = true,
+            MessageType::DelayResp => {
+                domain.delay_req = true;
+                domain.min_delay_req_interval = interval.or(domain.min_delay_req_interval);
+            }
+            MessageType::PDelayReq | MessageType::PDelayResp | MessageType::PDelayRespFollowUp => {
+                domain.peer_delay = true
+            }
+            MessageType::FollowUp | MessageType::Signaling | MessageType::Management => {}
+        }
+
+        let master = self.masters.iter_mut().find(|master| {
+            master.domain_number == message.domain_number
+                && master.sdo_id == message.sdo_id
+                && master.clock_identity == message.source_clock_identity
+                && master.port_number == message.source_port_number
+        });
+        match (message.message_type, message.announce, master) {
+            (MessageType::Announce, Some(announce), Some(master)) => {
+                master.transport = transport;
+                master.announce = announce;
+                master.announce_rate.record(now);
+            }
+            (MessageType::Announce, Some(announce), None) => {
+                let mut announce_rate = MessageRate::default();
+                announce_rate.record(now);
+                let master = ScannedMaster {
+                    domain_number: message.domain_number,
+                    sdo_id: message.sdo_id,
+                    clock_identity: message.source_clock_identity,
+                    port_number: message.source_port_number,
+                    transport,
+                    announce,
+                    announce_rate,
+                    sync_rate: MessageRate::default(),
+                };
+                if self.masters.try_push(master).is_err() {
+                    self.untracked = self.untracked.saturating_add(1);
+                }
+            }
+            (MessageType::Sync, _, Some(master)) => master.sync_rate.record(now),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// The domains that carried traffic
+    pub fn domains(&self) -> impl Iterator<Item = &ScannedDomain> {
+        self.domains.iter()
+    }
+
+    /// The masters that announced themselves
+    pub fn masters(&self) -> impl Iterator<Item = &ScannedMaster> {
+        self.masters.iter()
+    }
+
+    /// Number of messages of domains or masters that didn't fit
+    pub fn untracked(&self) -> u32 {
+        self.untracked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::{
+        common::{ClockQuality, PathTrace, PortIdentity, TimeSource},
+        messages::{AnnounceMessage, Header, Message, SyncMessage, MAX_DATA_LEN},
+    };
+
+    fn packet(
+        message_type: MessageType,
+        domain_number: u8,
+        clock_identity: ClockIdentity,
+        log_message_interval: i8,
+    ) -> ([u8; MAX_DATA_LEN], usize) {
+        let header = Header {
+            domain_number,
+            source_port_identity: PortIdentity {
+                clock_identity,
+                port_number: 1,
+            },
+            log_message_interval,
+            ..Default::default()
+        };
+        let message = match message_type {
+            MessageType::Sync => Message::Sync(SyncMessage {
+                header: Header {
+                    two_step_flag: true,
+                    ..header
+                },
+                origin_timestamp: Default::default(),
+            }),
+            MessageType::Announce => Message::Announce(AnnounceMessage {
+                header,
+                origin_timestamp: Default::default(),
+                current_utc_offset: 37,
+                grandmaster_priority_1: 128,
+                grandmaster_clock_quality: ClockQuality::default(),
+                grandmaster_priority_2: 128,
+                grandmaster_identity: clock_identity,
+                steps_removed: 0,
+                time_source: TimeSource::InternalOscillator,
+                path_trace: PathTrace::new(clock_identity),
+            }),
+            _ => unreachable!(),
+        };
+
+        let mut buffer = [0; MAX_DATA_LEN];
+        let len = message.serialize(&mut buffer).unwrap();
+        (buffer, len)
+    }
+
+    #[test]
+    fn scan_domains() {
+        let mut scanner = NetworkScanner::new();
+        for second in 0..4 {
+            let now = Time::from_secs(second);
+            for (domain_number, clock_identity, announce_interval, sync_interval) in [
+                (0, ClockIdentity([1; 8]), 1, 0),
+                (127, ClockIdentity([2; 8]), -2, -3),
+            ] {
+                for (message_type, interval) in [
+                    (MessageType::Announce, announce_interval),
+                    (MessageType::Sync, sync_interval),
+                ] {
+                    let (buffer, len) =
+                        packet(message_type, domain_number, clock_identity, interval);
+                    scanner
+                        .observe(&buffer[..len], Transport::UdpIpv4, now)
+                        .unwrap();
+                }
+            }
+        }
+
+        let domains: std::vec::Vec<_> = scanner.domains().collect();
+        assert_eq!(domains.len(), 2);
+        assert_eq!(domains[0].domain_number, 0);
+        assert_eq!(domains[0].messages, 8);
+        assert_eq!(domains[0].announce_interval, Some(1));
+        assert_eq!(domains[0].sync_interval, Some(0));
+        assert_eq!(domains[0].transports(), [Transport::UdpIpv4]);
+        assert_eq!(domains[0].tlv_types(), [TlvType::PathTrace]);
+        assert!(domains[0].two_step);
+        assert!(domains[0].multicast && !domains[0].unicast);
+        assert_eq!(domains[0].profile(), Some(InferredProfile::Default));
+        assert_eq!(domains[1].domain_number, 127);
+        assert_eq!(domains[1].profile(), Some(InferredProfile::Smpte2059));
+
+        let masters: std::vec::Vec<_> = scanner.masters().collect();
+        assert_eq!(masters.len(), 2);
+        assert_eq!(masters[1].clock_identity, ClockIdentity([2; 8]));
+        assert_eq!(
+            masters[1].announce.grandmaster_identity,
+            ClockIdentity([2; 8])
+        );
+        assert_eq!(masters[1].announce_rate.per_second(), Some(1.0));
+        assert_eq!(masters[1].sync_rate.per_second(), Some(1.0));
+        assert_eq!(scanner.untracked(), 0);
+
+        assert!(scanner
+            .observe(&[0; 20], Transport::UdpIpv4, Time::from_secs(5))
+            .is_err());
+    }
+
+    #[test]
+    fn scan_profile() {
+        let mut domain = ScannedDomain::new(0, 0);
+        assert_eq!(domain.profile(), None);
+
+        domain.sync_interval = Some(-3);
+        assert_eq!(domain.profile(), Some(InferredProfile::Smpte2059));
+        domain.sync_interval = Some(-8);
+        assert_eq!(domain.profile(), None);
+
+        domain.sync_interval = Some(-1);
+        domain.peer_delay = true;
+        assert_eq!(domain.profile(), Some(InferredProfile::Ieee8021As));
+        assert_eq!(InferredProfile::Ieee8021As.profile(), None);
+    }
+
+    #[test]
+    fn scan_capacity() {
+        let mut scanner = NetworkScanner::new();
+        for domain_number in 0..=MAX_SCANNED_DOMAINS as u8 {
+            let (buffer, len) = packet(
+                MessageType::Announce,
+                domain_number,
+                ClockIdentity([1; 8]),
+                1,
+            );
+            scanner
+                .observe(&buffer[..len], Transport::Ethernet, Time::from_secs(0))
+                .unwrap();
+        }
+
+        assert_eq!(scanner.domains().count(), MAX_SCANNED_DOMAINS);
+        assert_eq!(scanner.masters().count(), MAX_SCANNED_DOMAINS);
+        assert_eq!(scanner.untracked(), 1);
+    }
+}
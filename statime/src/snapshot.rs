@@ -0,0 +1,408 @@
+//! Warm-start snapshots of the state of a [`PtpInstance`](crate::PtpInstance).
+//!
+//! A snapshot holds the state an instance has built up while running that
+//! isn't part of its configuration: the datasets describing the current
+//! parent and grandmaster, the time properties of the local time source
+//! including any leap second that has passed or is scheduled, and priority 1
+//! and free-run mode as changed at runtime. Restoring it when a daemon is
+//! restarted, for example to upgrade it, means the instance starts out
+//! advertising the same state as before, instead of falling back to its
+//! configured defaults until the BMCA and time source have caught up.
+//!
+//! State kept by the ports rather than the instance is not part of a
+//! snapshot:
+//! - The calibration of the ports is persisted as it changes by a
+//!   [`CalibrationStore`](crate::CalibrationStore), which the instance reads
+//!   again when the ports are added.
+//! - The unicast transmission a master port granted expires within the duration
+//!   the clients asked for, and clients request it again before then. A
+//!   restarted port serves the clients again once they renew their grants, so a
+//!   snapshot doesn't hold the grant tables.
+//!
+//! Snapshots are stored in a small versioned binary format, see
+//! [`InstanceSnapshot::serialize`].
+
+use fixed::types::U96F32;
+
+use crate::{
+    datastructures::{
+        common::{ClockIdentity, ClockQuality, LeapIndicator, PortIdentity, TimeSource},
+        datasets::{LeapSecond, ParentDS, TimePropertiesDS},
+        WireFormat,
+    },
+    time::Time,
+};
+
+/// Version of the serialized format, stored in its first byte. Version 1
+/// was written before the state left out of a snapshot was settled, and is
+/// not read.
+const SNAPSHOT_VERSION: u8 = 2;
+
+/// The state of a [`PtpInstance`](crate::PtpInstance) that can be carried
+/// over to a restarted instance, see
+/// [`PtpInstance::snapshot`](crate::PtpInstance::snapshot) and
+/// [`PtpInstance::restore`](crate::PtpInstance::restore).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceSnapshot {
+    pub(crate) clock_identity: ClockIdentity,
+    pub(crate) domain_number: u8,
+    pub(crate) priority_1: u8,
+    pub(crate) free_run: bool,
+    pub(crate) steps_removed: u16,
+    pub(crate) parent_ds: ParentDS,
+    pub(crate) time_properties_ds: TimePropertiesDS,
+    pub(crate) local_time_properties_ds: TimePropertiesDS,
+    pub(crate) leap_second: Option<LeapSecond>,
+}
+
+/// Reasons a snapshot can't be read or restored
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SnapshotError {
+    /// The buffer is too short to hold a snapshot
+    BufferTooShort,
+    /// The snapshot was written in a format this version doesn't understand
+    UnsupportedVersion(u8),
+    /// The snapshot contains a value that can't be decoded
+    InvalidValue,
+    /// The snapshot was taken of an instance with a different clock identity
+    ClockIdentityMismatch,
+    /// The snapshot was taken of an instance in a different domain
+    DomainMismatch,
+    /// The instance is slave-only, but the snapshot has a priority 1 other
+    /// than 255
+    SlaveOnlyPriority1(u8),
+}
+
+impl core::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SnapshotError::BufferTooShort => f.write_str("buffer too short for a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {version}")
+            }
+            SnapshotError::InvalidValue => f.write_str("snapshot contains an invalid value"),
+            SnapshotError::ClockIdentityMismatch => {
+                f.write_str("snapshot was taken of an instance with another clock identity")
+            }
+            SnapshotError::DomainMismatch => {
+                f.write_str("snapshot was taken of an instance in another domain")
+            }
+            SnapshotError::SlaveOnlyPriority1(value) => write!(
+                f,
+                "priority 1 must be 255 for a slave-only instance, but the snapshot has {value}"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SnapshotError {}
+
+impl InstanceSnapshot {
+    /// Size of a serialized snapshot in bytes
+    pub const SIZE: usize = 75;
+
+    /// Identity of the clock the snapshot was taken of
+    pub fn clock_identity(&self) -> ClockIdentity {
+        self.clock_identity
+    }
+
+    /// Write the snapshot to the start of `buffer`, returning the number of
+    /// bytes written, which is always [`SIZE`](Self::SIZE).
+    ///
+    /// All values are stored big endian. The first byte is a version number,
+    /// so later versions can keep reading snapshots written by this one.
+    pub fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SnapshotError> {
+        let buffer = buffer
+            .get_mut(..Self::SIZE)
+            .ok_or(SnapshotError::BufferTooShort)?;
+
+        buffer[0] = SNAPSHOT_VERSION;
+        self.clock_identity
+            .serialize(&mut buffer[1..9])
+            .map_err(|_| SnapshotError::BufferTooShort)?;
+        buffer[9] = self.domain_number;
+        buffer[10] = self.priority_1;
+        buffer[11] = self.free_run as u8;
+        buffer[12..14].copy_from_slice(&self.steps_removed.to_be_bytes());
+        serialize_parent_ds(&self.parent_ds, &mut buffer[14..45])?;
+        serialize_time_properties(&self.time_properties_ds, &mut buffer[45..51]);
+        serialize_time_properties(&self.local_time_properties_ds, &mut buffer[51..57]);
+        match self.leap_second {
+            Some(leap_second) => {
+                buffer[57] = 1;
+                buffer[58..74].copy_from_slice(&leap_second.at.nanos().to_bits().to_be_bytes());
+                buffer[74] = leap_indicator_to_primitive(leap_second.leap_indicator);
+            }
+            None => buffer[57..75].fill(0),
+        }
+
+        Ok(Self::SIZE)
+    }
+
+    /// Read a snapshot written by [`serialize`](Self::serialize).
+    pub fn deserialize(buffer: &[u8]) -> Result<Self, SnapshotError> {
+        match buffer.first() {
+            None => return Err(SnapshotError::BufferTooShort),
+            Some(&SNAPSHOT_VERSION) => {}
+            Some(&version) => return Err(SnapshotError::UnsupportedVersion(version)),
+        }
+
+        let buffer = buffer
+            .get(..Self::SIZE)
+            .ok_or(SnapshotError::BufferTooShort)?;
+
+        let leap_second = match buffer[57] {
+            0 => None,
+            1 => Some(LeapSecond {
+                at: Time::from_fixed_nanos(U96F32::from_bits(u128::from_be_bytes(
+                    buffer[58..74].try_into().unwrap(),
+                ))),
+                leap_indicator: leap_indicator_from_primitive(buffer[74])?,
+            }),
+            _ => return Err(SnapshotError::InvalidValue),
+        };
+
+        Ok(Self {
+            clock_identity: ClockIdentity::deserialize(&buffer[1..9])
+                .map_err(|_| SnapshotError::BufferTooShort)?,
+            domain_number: buffer[9],
+            priority_1: buffer[10],
+            free_run: bool_from_primitive(buffer[11])?,
+            steps_removed: u16::from_be_bytes(buffer[12..14].try_into().unwrap()),
+            parent_ds: deserialize_parent_ds(&buffer[14..45])?,
+            time_properties_ds: deserialize_time_properties(&buffer[45..51])?,
+            local_time_properties_ds: deserialize_time_properties(&buffer[51..57])?,
+            leap_second,
+        })
+    }
+}
+
+fn serialize_parent_ds(parent_ds: &ParentDS, buffer: &mut [u8]) -> Result<(), SnapshotError> {
+    parent_ds
+        .parent_port_identity
+        .serialize(&mut buffer[0..10])
+        .map_err(|_| SnapshotError::BufferTooShort)?;
+    buffer[10] = parent_ds.parent_stats as u8;
+    buffer[11..13].copy_from_slice(
+        &parent_ds
+            .observed_parent_offset_scaled_log_variance
+            .to_be_bytes(),
+    );
+    buffer[13..17].copy_from_slice(
+        &parent_ds
+            .observed_parent_clock_phase_change_rate
+            .to_be_bytes(),
+    );
+    parent_ds
+        .grandmaster_identity
+        .serialize(&mut buffer[17..25])
+        .map_err(|_| SnapshotError::BufferTooShort)?;
+    parent_ds
+        .grandmaster_clock_quality
+        .serialize(&mut buffer[25..29])
+        .map_err(|_| SnapshotError::BufferTooShort)?;
+    buffer[29] = parent_ds.grandmaster_priority_1;
+    buffer[30] = parent_ds.grandmaster_priority_2;
+    Ok(())
+}
+
+fn deserialize_parent_ds(buffer: &[u8]) -> Result<ParentDS, SnapshotError> {
+    Ok(ParentDS {
+        parent_port_identity: PortIdentity::deserialize(&buffer[0..10])
+            .map_err(|_| SnapshotError::BufferTooShort)?,
+        parent_stats: bool_from_primitive(buffer[10])?,
+        observed_parent_offset_scaled_log_variance: u16::from_be_bytes(
+            buffer[11..13].try_into().unwrap(),
+        ),
+        observed_parent_clock_phase_change_rate: u32::from_be_bytes(
+            buffer[13..17].try_into().unwrap(),
+        ),
+        grandmaster_identity: ClockIdentity::deserialize(&buffer[17..25])
+            .map_err(|_| SnapshotError::BufferTooShort)?,
+        grandmaster_clock_quality: ClockQuality::deserialize(&buffer[25..29])
+            .map_err(|_| SnapshotError::BufferTooShort)?,
+        grandmaster_priority_1: buffer[29],
+        grandmaster_priority_2: buffer[30],
+    })
+}
+
+fn serialize_time_properties(time_properties: &TimePropertiesDS, buffer: &mut [u8]) {
+    match time_properties.current_utc_offset {
+        Some(offset) => {
+            buffer[0] = 1;
+            buffer[1..3].copy_from_slice(&offset.to_be_bytes());
+        }
+        None => buffer[0..3].fill(0),
+    }
+    buffer[3] = leap_indicator_to_primitive(time_properties.leap_indicator);
+    buffer[4] = (time_properties.time_traceable as u8)
+        | (time_properties.frequency_traceable as u8) << 1
+        | (time_properties.ptp_timescale as u8) << 2;
+    buffer[5] = time_properties.time_source.to_primitive();
+}
+
+fn deserialize_time_properties(buffer: &[u8]) -> Result<TimePropertiesDS, SnapshotError> {
+    let current_utc_offset = match buffer[0] {
+        0 => None,
+        1 => Some(i16::from_be_bytes(buffer[1..3].try_into().unwrap())),
+        _ => return Err(SnapshotError::InvalidValue),
+    };
+
+    if buffer[4] & !0b111 != 0 {
+        return Err(SnapshotError::InvalidValue);
+    }
+
+    Ok(TimePropertiesDS {
+        current_utc_offset,
+        leap_indicator: leap_indicator_from_primitive(buffer[3])?,
+        time_traceable: buffer[4] & 0b001 != 0,
+        frequency_traceable: buffer[4] & 0b010 != 0,
+        ptp_timescale: buffer[4] & 0b100 != 0,
+        time_source: TimeSource::from_primitive(buffer[5]),
+    })
+}
+
+fn leap_indicator_to_primitive(leap_indicator: LeapIndicator) -> u8 {
+    match leap_indicator {
+        LeapIndicator::NoLeap => 0,
+        LeapIndicator::Leap61 => 1,
+        LeapIndicator::Leap59 => 2,
+    }
+}
+
+fn leap_indicator_from_primitive(value: u8) -> Result<LeapIndicator, SnapshotError> {
+    match value {
+        0 => Ok(LeapIndicator::NoLeap),
+        1 => Ok(LeapIndicator::Leap61),
+        2 => Ok(LeapIndicator::Leap59),
+        _ => Err(SnapshotError::InvalidValue),
+    }
+}
+
+fn bool_from_primitive(value: u8) -> Result<bool, SnapshotError> {
+    match value {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(SnapshotError::InvalidValue),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::common::{ClockAccuracy, ClockClass};
+
+    fn snapshot() -> InstanceSnapshot {
+        let grandmaster_identity = ClockIdentity([1, 2, 3, 4, 5, 6, 7, 8]);
+        InstanceSnapshot {
+            clock_identity: ClockIdentity([8, 7, 6, 5, 4, 3, 2, 1]),
+            domain_number: 24,
+            priority_1: 100,
+            free_run: true,
+            steps_removed: 2,
+            parent_ds: ParentDS {
+                parent_port_identity: PortIdentity {
+                    clock_identity: grandmaster_identity,
+                    port_number: 3,
+                },
+                parent_stats: false,
+                observed_parent_offset_scaled_log_variance: 0xffff,
+                observed_parent_clock_phase_change_rate: 0x7fffffff,
+                grandmaster_identity,
+                grandmaster_clock_quality: ClockQuality {
+                    clock_class: ClockClass::PrimaryReference,
+                    clock_accuracy: ClockAccuracy::NS100,
+                    offset_scaled_log_variance: 0x4e5d,
+                },
+                grandmaster_priority_1: 64,
+                grandmaster_priority_2: 128,
+            },
+            time_properties_ds: TimePropertiesDS::new_ptp_time(
+                Some(37),
+                LeapIndicator::Leap61,
+                true,
+                true,
+                TimeSource::Gnss,
+            ),
+            local_time_properties_ds: TimePropertiesDS::new_arbitrary_time(
+                false,
+                true,
+                TimeSource::InternalOscillator,
+            ),
+            leap_second: Some(LeapSecond {
+                at: Time::from_secs(1_000_000),
+                leap_indicator: LeapIndicator::Leap59,
+            }),
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut buffer = [0xaa; InstanceSnapshot::SIZE + 4];
+
+        let snapshot = snapshot();
+        assert_eq!(snapshot.serialize(&mut buffer), Ok(InstanceSnapshot::SIZE));
+        assert_eq!(InstanceSnapshot::deserialize(&buffer), Ok(snapshot.clone()));
+
+        let snapshot = InstanceSnapshot {
+            leap_second: None,
+            ..snapshot
+        };
+        assert_eq!(snapshot.serialize(&mut buffer), Ok(InstanceSnapshot::SIZE));
+        assert_eq!(InstanceSnapshot::deserialize(&buffer), Ok(snapshot));
+    }
+
+    #[test]
+    fn snapshot_old_version() {
+        let mut buffer = [0; InstanceSnapshot::SIZE];
+        snapshot().serialize(&mut buffer).unwrap();
+        assert_eq!(buffer[0], 2);
+
+        buffer[0] = 1;
+        assert_eq!(
+            InstanceSnapshot::deserialize(&buffer),
+            Err(SnapshotError::UnsupportedVersion(1))
+        );
+    }
+
+    #[test]
+    fn snapshot_invalid() {
+        let mut buffer = [0; InstanceSnapshot::SIZE];
+        assert_eq!(
+            snapshot().serialize(&mut buffer[..InstanceSnapshot::SIZE - 1]),
+            Err(SnapshotError::BufferTooShort)
+        );
+
+        snapshot().serialize(&mut buffer).unwrap();
+        assert_eq!(
+            InstanceSnapshot::deserialize(&buffer[..InstanceSnapshot::SIZE - 1]),
+            Err(SnapshotError::BufferTooShort)
+        );
+        assert_eq!(
+            InstanceSnapshot::deserialize(&[]),
+            Err(SnapshotError::BufferTooShort)
+        );
+
+        let mut invalid = buffer;
+        invalid[0] = 3;
+        assert_eq!(
+            InstanceSnapshot::deserialize(&invalid),
+            Err(SnapshotError::UnsupportedVersion(3))
+        );
+
+        let mut invalid = buffer;
+        invalid[11] = 2;
+        assert_eq!(
+            InstanceSnapshot::deserialize(&invalid),
+            Err(SnapshotError::InvalidValue)
+        );
+
+        let mut invalid = buffer;
+        invalid[74] = 3;
+        assert_eq!(
+            InstanceSnapshot::deserialize(&invalid),
+            Err(SnapshotError::InvalidValue)
+        );
+    }
+}
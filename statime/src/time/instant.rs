@@ -12,6 +12,8 @@ use fixed::{
 
 use super::duration::Duration;
 use crate::datastructures::common::WireTimestamp;
+#[cfg(feature = "std")]
+use crate::datastructures::{common::LeapIndicator, datasets::TimePropertiesDS};
 
 /// Time represents a specific moment in time.
 ///
@@ -24,9 +26,11 @@ pub struct Time {
 }
 
 impl Time {
-    /// Create an instance with the given amount of seconds from the origin
-    pub fn from_secs(secs: u64) -> Self {
-        let inner = secs.to_fixed::<U96F32>() * 1_000_000_000.to_fixed::<U96F32>();
+    /// Create an instance with the given amount of seconds from the origin.
+    /// This can be used in constants, like the lifetimes in a static
+    /// [`KeyTable`](crate::KeyTable).
+    pub const fn from_secs(secs: u64) -> Self {
+        let inner = U96F32::from_bits((secs as u128 * 1_000_000_000) << 32);
         Self { inner }
     }
     /// Create an instance with the given amount of milliseconds from the origin
@@ -59,6 +63,31 @@ impl Time {
         Self { inner }
     }
 
+    /// Add `duration`, or `None` when the result would be before the origin
+    /// or too large to represent. Useful with durations received from the
+    /// network.
+    pub fn checked_add(self, duration: Duration) -> Option<Self> {
+        let nanos = duration.nanos();
+        let inner = if nanos.is_negative() {
+            self.inner.checked_sub(nanos.unsigned_abs())
+        } else {
+            self.inner.checked_add(nanos.unsigned_abs())
+        }?;
+        Some(Self { inner })
+    }
+
+    /// Subtract `duration`, or `None` when the result would be before the
+    /// origin or too large to represent
+    pub fn checked_sub(self, duration: Duration) -> Option<Self> {
+        let nanos = duration.nanos();
+        let inner = if nanos.is_negative() {
+            self.inner.checked_add(nanos.unsigned_abs())
+        } else {
+            self.inner.checked_sub(nanos.unsigned_abs())
+        }?;
+        Some(Self { inner })
+    }
+
     /// Get the total amount of nanoseconds since the origin
     pub fn nanos(&self) -> U96F32 {
         self.inner
@@ -71,12 +100,95 @@ impl Time {
     pub fn secs(&self) -> u64 {
         (self.inner / 1_000_000_000.to_fixed::<U96F32>()).to_num()
     }
-    // Get the subnanosecond amount
+    // Get the subnanosecond amount, rounded to the nearest value the correction
+    // field can carry. Added to the nanoseconds of the wire timestamp, this
+    // gives the time back to within 2^-17 ns.
     pub(crate) fn subnano(&self) -> crate::datastructures::common::TimeInterval {
-        let inter: U112F16 = self.inner.frac().lossy_into();
-        // unwrap is ok since always less than 1.
+        let half_step = U96F32::from_bits(1 << 15);
+        let inter: U112F16 = (self.inner.frac() + half_step).lossy_into();
+        // unwrap is ok since always at most 1.
         crate::datastructures::common::TimeInterval(inter.lossless_try_into().unwrap())
     }
+
+    /// Format this time on the PTP timescale as an RFC3339 timestamp, like
+    /// `2024-06-01T12:00:00.000000000Z`, for people to check what time it is.
+    ///
+    /// The time is converted to UTC with the offset in `time_properties`, and
+    /// shown in the timezone `local_offset` seconds east of UTC. A leap
+    /// second indicated by `time_properties` shows up as second 60, and the
+    /// second removed by a negative leap second is skipped. Returns `None` on
+    /// an arbitrary timescale or when the UTC offset is unknown, as the time
+    /// is unrelated to UTC then.
+    #[cfg(feature = "std")]
+    pub fn to_rfc3339(
+        &self,
+        time_properties: &TimePropertiesDS,
+        local_offset: i32,
+    ) -> Option<std::string::String> {
+        const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+        if !time_properties.is_ptp() {
+            return None;
+        }
+        let mut utc =
+            i64::try_from(self.secs()).ok()? - i64::from(time_properties.current_utc_offset()?);
+
+        // The offset only changes once the leap second is over
+        let mut leap_second = false;
+        match time_properties.leap_indicator() {
+            LeapIndicator::Leap61 if utc.rem_euclid(SECS_PER_DAY) == 0 => {
+                leap_second = true;
+                utc -= 1;
+            }
+            LeapIndicator::Leap59 if utc.rem_euclid(SECS_PER_DAY) == SECS_PER_DAY - 1 => {
+                utc += 1;
+            }
+            _ => {}
+        }
+
+        let local = utc + i64::from(local_offset);
+        let (year, month, day) = civil_from_days(local.div_euclid(SECS_PER_DAY));
+        let secs_of_day = local.rem_euclid(SECS_PER_DAY);
+
+        let timezone = if local_offset == 0 {
+            std::string::String::from("Z")
+        } else {
+            let sign = if local_offset < 0 { '-' } else { '+' };
+            let minutes = local_offset.unsigned_abs() / 60;
+            std::format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+        };
+
+        Some(std::format!(
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:09}{timezone}",
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60 + i64::from(leap_second),
+            self.subsec_nanos(),
+        ))
+    }
+}
+
+/// The year, month and day of the given number of days since 1970-01-01, in
+/// the proleptic Gregorian calendar
+#[cfg(feature = "std")]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Count from 0000-03-01, so leap days fall at the end of a year, in eras
+    // of 400 years that each have the same number of days
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+
+    (era * 400 + year_of_era + i64::from(month <= 2), month, day)
 }
 
 impl From<WireTimestamp> for Time {
@@ -162,4 +274,103 @@ mod tests {
         assert_eq!(Time::from_millis(10).secs(), 0);
         assert_eq!(Time::from_millis(1001).secs(), 1);
     }
+
+    #[test]
+    fn checked() {
+        let time = Time::from_secs(10);
+        assert_eq!(
+            time.checked_add(Duration::from_secs(-4)),
+            Some(Time::from_secs(6))
+        );
+        assert_eq!(
+            time.checked_sub(Duration::from_secs(-4)),
+            Some(Time::from_secs(14))
+        );
+        assert_eq!(time.checked_add(Duration::from_secs(-11)), None);
+        assert_eq!(time.checked_sub(Duration::from_secs(11)), None);
+    }
+
+    // What a receiver reconstructs from the wire timestamp and correction field
+    fn wire_round_trip(time: Time) -> Time {
+        Time::from(WireTimestamp::from(time)) + Duration::from(time.subnano())
+    }
+
+    #[test]
+    fn subnano_wire_round_trip() {
+        // Representable in the correction field, so exact
+        for subnanos in [0.0, 0.5, 0.25, 0.125, 0.999_984_741_210_937_5] {
+            let time = Time::from_fixed_nanos(1_000_000_123.0f64 + subnanos);
+            assert_eq!(wire_round_trip(time), time);
+        }
+
+        // Otherwise to within half a step of the correction field
+        for subnanos in [1, 0x7fff, 0x8000, 0x1234_5678, 0xffff_7fff, 0xffff_ffff] {
+            let time = Time::from_nanos_subnanos(1_000_000_123, subnanos);
+            let error = (wire_round_trip(time) - time).nanos().abs();
+            assert!(error <= 2f64.powi(-17), "error {error} for {subnanos:#x}");
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn rfc3339() {
+        use crate::datastructures::common::TimeSource;
+
+        let properties = |leap_indicator| {
+            TimePropertiesDS::new_ptp_time(Some(37), leap_indicator, false, false, TimeSource::Gnss)
+        };
+        let time = Time::from_secs(1_700_000_037) + Duration::from_millis(250);
+
+        assert_eq!(
+            time.to_rfc3339(&properties(LeapIndicator::NoLeap), 0)
+                .as_deref(),
+            Some("2023-11-14T22:13:20.250000000Z")
+        );
+        assert_eq!(
+            time.to_rfc3339(&properties(LeapIndicator::NoLeap), -(5 * 3600 + 30 * 60))
+                .as_deref(),
+            Some("2023-11-14T16:43:20.250000000-05:30")
+        );
+        assert_eq!(
+            Time::from_secs(37)
+                .to_rfc3339(&properties(LeapIndicator::NoLeap), 3600)
+                .as_deref(),
+            Some("1970-01-01T01:00:00.000000000+01:00")
+        );
+        assert_eq!(
+            Time::from_secs(951_782_437)
+                .to_rfc3339(&properties(LeapIndicator::NoLeap), 0)
+                .as_deref(),
+            Some("2000-02-29T00:00:00.000000000Z")
+        );
+
+        // The offset was 36 seconds until the leap second at the end of 2016
+        let midnight = Time::from_secs(1_483_228_800 + 36);
+        let properties = |leap_indicator| {
+            TimePropertiesDS::new_ptp_time(Some(36), leap_indicator, false, false, TimeSource::Gnss)
+        };
+        assert_eq!(
+            midnight
+                .to_rfc3339(&properties(LeapIndicator::Leap61), 0)
+                .as_deref(),
+            Some("2016-12-31T23:59:60.000000000Z")
+        );
+        assert_eq!(
+            (midnight - Duration::from_secs(1))
+                .to_rfc3339(&properties(LeapIndicator::Leap59), 3600)
+                .as_deref(),
+            Some("2017-01-01T01:00:00.000000000+01:00")
+        );
+
+        let unknown_offset = TimePropertiesDS::new_ptp_time(
+            None,
+            LeapIndicator::NoLeap,
+            false,
+            false,
+            TimeSource::Gnss,
+        );
+        assert_eq!(time.to_rfc3339(&unknown_offset, 0), None);
+        let arbitrary = TimePropertiesDS::new_arbitrary_time(false, false, TimeSource::Gnss);
+        assert_eq!(time.to_rfc3339(&arbitrary, 0), None);
+    }
 }
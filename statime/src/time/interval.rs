@@ -30,14 +30,8 @@ impl Interval {
         core::time::Duration::from_secs_f64(self.seconds())
     }
 
-    #[cfg(no_std)]
     pub fn as_f64(self) -> f64 {
-        libm::pow(2.0f64, log_interval as f64)
-    }
-
-    #[cfg(not(no_std))]
-    pub fn as_f64(self) -> f64 {
-        2.0f64.powi(self.0 as i32)
+        libm::pow(2.0, self.0 as f64)
     }
 
     pub fn as_log_2(self) -> i8 {
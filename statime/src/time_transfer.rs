@@ -0,0 +1,549 @@
+//! Passing the time of an instance to other clocks on the same machine.
+//!
+//! A host synchronized over the network, like a hypervisor, publishes its time
+//! in a [`SharedTime`] placed in memory its guests can read, like a shared
+//! memory device of its virtual machines. Each guest reads it through a
+//! [`TransferredClock`], which can serve as the local clock of a guest
+//! instance. This way the guests get the time of the host without each
+//! running PTP on the network.
+//!
+//! Host and guests need a common reference: a counter that they all read with
+//! the same value at the same moment, like the raw monotonic clock for
+//! containers, or a time stamp counter that the hypervisor passes through.
+//! The host publishes the time of its clock at a reading of the reference,
+//! and the rate of its clock relative to the reference. Guests extrapolate
+//! from that to their own reading of the reference, see
+//! [`TransferredTime::time_at`].
+//!
+//! The host can publish on every adjustment of its clock by wrapping it in a
+//! [`PublishingClock`], or publish at its own pace with
+//! [`TransferredTime::measure`] and [`SharedTime::publish`].
+
+use core::sync::atomic::{fence, AtomicU32, Ordering};
+
+use fixed::types::U96F32;
+
+use crate::{
+    datastructures::{
+        common::{LeapIndicator, TimeSource},
+        datasets::TimePropertiesDS,
+    },
+    time::{Duration, Time},
+    Clock,
+};
+
+/// Version of the layout of [`SharedTime`], stored next to the time
+const LAYOUT_VERSION: u32 = 1;
+
+/// Number of words holding a [`TransferredTime`]
+const DATA_WORDS: usize = 11;
+
+/// Attempts at reading a consistent [`TransferredTime`] before giving up
+const MAX_READ_ATTEMPTS: usize = 16;
+
+/// A counter read by both the host and its guests, see the [module
+/// documentation](self)
+pub trait ReferenceClock {
+    /// The current value of the reference. Only differences between values
+    /// matter, so the epoch is arbitrary.
+    fn now(&self) -> Time;
+}
+
+/// Reasons the time of the host can't be used
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TimeTransferError {
+    /// The host hasn't published its time yet
+    NotPublished,
+    /// The host kept publishing while reading, so no consistent time was read
+    Busy,
+    /// The host publishes in a layout this version doesn't understand
+    UnsupportedVersion(u32),
+    /// The published time contains a value that can't be decoded
+    InvalidValue,
+    /// A [`TransferredClock`] follows the host, so it can't be adjusted
+    ReadOnly,
+}
+
+impl core::fmt::Display for TimeTransferError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TimeTransferError::NotPublished => f.write_str("no time published yet"),
+            TimeTransferError::Busy => f.write_str("published time kept changing while reading"),
+            TimeTransferError::UnsupportedVersion(version) => {
+                write!(f, "unsupported shared time layout version {version}")
+            }
+            TimeTransferError::InvalidValue => f.write_str("published time is invalid"),
+            TimeTransferError::ReadOnly => f.write_str("transferred clock can't be adjusted"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TimeTransferError {}
+
+/// The time of the host clock at a reading of the reference
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TransferredTime {
+    /// The reading of the [`ReferenceClock`]
+    pub reference: Time,
+    /// The time of the host clock at that reading
+    pub time: Time,
+    /// The rate of the host clock relative to the reference
+    pub rate: f64,
+    /// The time properties of the host clock, like the UTC offset
+    pub time_properties: TimePropertiesDS,
+}
+
+impl TransferredTime {
+    /// Read `clock` between two readings of `reference`, with the rate of
+    /// the clock taken from its [`Clock::frequency_multiplier`]. That rate is
+    /// only right when the reference runs off the unadjusted oscillator of the
+    /// clock, otherwise the time should be measured often.
+    pub fn measure<C: Clock, R: ReferenceClock>(
+        clock: &C,
+        reference: &R,
+        time_properties: TimePropertiesDS,
+    ) -> Self {
+        let before = reference.now();
+        let time = clock.now();
+        let after = reference.now();
+
+        Self {
+            reference: before + (after - before) / 2,
+            time,
+            rate: clock.frequency_multiplier().unwrap_or(1.0),
+            time_properties,
+        }
+    }
+
+    /// The time of the host clock at a later (or earlier) `reference` reading
+    pub fn time_at(&self, reference: Time) -> Time {
+        self.time + (reference - self.reference) * self.rate
+    }
+
+    fn encode(&self) -> [u32; DATA_WORDS] {
+        let mut words = [0; DATA_WORDS];
+        words[0..4].copy_from_slice(&split_u128(self.reference.nanos().to_bits()));
+        words[4..8].copy_from_slice(&split_u128(self.time.nanos().to_bits()));
+        let rate = self.rate.to_bits();
+        words[8] = (rate >> 32) as u32;
+        words[9] = rate as u32;
+        words[10] = encode_time_properties(&self.time_properties);
+        words
+    }
+
+    fn decode(words: &[u32; DATA_WORDS]) -> Result<Self, TimeTransferError> {
+        let reference = join_u128(words[0..4].try_into().unwrap());
+        let time = join_u128(words[4..8].try_into().unwrap());
+        let rate = f64::from_bits((words[8] as u64) << 32 | words[9] as u64);
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(TimeTransferError::InvalidValue);
+        }
+
+        Ok(Self {
+            reference: Time::from_fixed_nanos(U96F32::from_bits(reference)),
+            time: Time::from_fixed_nanos(U96F32::from_bits(time)),
+            rate,
+            time_properties: decode_time_properties(words[10])?,
+        })
+    }
+}
+
+/// The published time of the host, laid out to be placed in memory shared
+/// with guests.
+///
+/// The layout is a sequence of 32 bit words in native byte order: a sequence
+/// number that is odd while the host is publishing, the layout version, and
+/// the [`TransferredTime`]. Only a single host may publish to it, but any
+/// number of guests can read it at the same time.
+#[derive(Debug)]
+#[repr(C)]
+pub struct SharedTime {
+    sequence: AtomicU32,
+    version: AtomicU32,
+    data: [AtomicU32; DATA_WORDS],
+}
+
+impl SharedTime {
+    /// Size of the shared time in bytes
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    /// Shared time to which nothing has been published yet. Memory that is
+    /// all zeroes holds the same.
+    pub const fn new() -> Self {
+        Self {
+            sequence: AtomicU32::new(0),
+            version: AtomicU32::new(0),
+            data: [const { AtomicU32::new(0) }; DATA_WORDS],
+        }
+    }
+
+    /// Replace the published time
+    pub fn publish(&self, transferred: &TransferredTime) {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        self.sequence
+            .store(sequence.wrapping_add(1) | 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        self.version.store(LAYOUT_VERSION, Ordering::Relaxed);
+        for (word, value) in self.data.iter().zip(transferred.encode()) {
+            word.store(value, Ordering::Relaxed);
+        }
+
+        // Skip zero on wrapping around, as that means nothing was published
+        let next = sequence.wrapping_add(2) & !1;
+        self.sequence
+            .store(if next == 0 { 2 } else { next }, Ordering::Release);
+    }
+
+    /// The time last published by the host
+    pub fn read(&self) -> Result<TransferredTime, TimeTransferError> {
+        for _ in 0..MAX_READ_ATTEMPTS {
+            let sequence = self.sequence.load(Ordering::Acquire);
+            if sequence == 0 {
+                return Err(TimeTransferError::NotPublished);
+            }
+            if sequence & 1 != 0 {
+                core::hint::spin_loop();
+                continue;
+            }
+
+            let version = self.version.load(Ordering::Relaxed);
+            let mut words = [0; DATA_WORDS];
+            for (value, word) in words.iter_mut().zip(&self.data) {
+                *value = word.load(Ordering::Relaxed);
+            }
+
+            fence(Ordering::Acquire);
+            if self.sequence.load(Ordering::Relaxed) != sequence {
+                continue;
+            }
+
+            if version != LAYOUT_VERSION {
+                return Err(TimeTransferError::UnsupportedVersion(version));
+            }
+            return TransferredTime::decode(&words);
+        }
+
+        Err(TimeTransferError::Busy)
+    }
+}
+
+impl Default for SharedTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A clock that publishes its time to a [`SharedTime`] after every
+/// adjustment, for the host side of a time transfer
+#[derive(Debug)]
+pub struct PublishingClock<'a, C, R> {
+    clock: C,
+    reference: R,
+    shared: &'a SharedTime,
+}
+
+impl<'a, C: Clock, R: ReferenceClock> PublishingClock<'a, C, R> {
+    /// Wrap `clock`. Nothing is published until the first adjustment or call
+    /// to [`publish`](Self::publish).
+    pub fn new(clock: C, reference: R, shared: &'a SharedTime) -> Self {
+        Self {
+            clock,
+            reference,
+            shared,
+        }
+    }
+
+    /// Publish the current time of the clock, for example when its time
+    /// properties change without an adjustment
+    pub fn publish(&self, time_properties: &TimePropertiesDS) {
+        self.shared.publish(&TransferredTime::measure(
+            &self.clock,
+            &self.reference,
+            *time_properties,
+        ));
+    }
+
+    /// The wrapped clock
+    pub fn inner(&self) -> &C {
+        &self.clock
+    }
+}
+
+impl<C: Clock, R: ReferenceClock> Clock for PublishingClock<'_, C, R> {
+    type Error = C::Error;
+
+    fn now(&self) -> Time {
+        self.clock.now()
+    }
+
+    fn adjust(
+        &mut self,
+        time_offset: Duration,
+        frequency_multiplier: f64,
+        time_properties_ds: &TimePropertiesDS,
+    ) -> Result<(), Self::Error> {
+        self.clock
+            .adjust(time_offset, frequency_multiplier, time_properties_ds)?;
+        self.publish(time_properties_ds);
+        Ok(())
+    }
+
+    fn frequency_multiplier(&self) -> Option<f64> {
+        self.clock.frequency_multiplier()
+    }
+}
+
+/// The clock of the host as read from a [`SharedTime`], for the guest side of
+/// a time transfer.
+///
+/// The clock follows the host, so it can't be adjusted: a guest instance using
+/// it should only pass the time on, with ports that are master only.
+#[derive(Debug)]
+pub struct TransferredClock<'a, R> {
+    shared: &'a SharedTime,
+    reference: R,
+    // The last time read, used while the host is publishing
+    last: core::cell::Cell<Option<TransferredTime>>,
+}
+
+impl<'a, R: ReferenceClock> TransferredClock<'a, R> {
+    /// Follow the time published to `shared`
+    pub fn new(shared: &'a SharedTime, reference: R) -> Self {
+        Self {
+            shared,
+            reference,
+            last: core::cell::Cell::new(None),
+        }
+    }
+
+    /// The time last published by the host
+    pub fn transferred(&self) -> Result<TransferredTime, TimeTransferError> {
+        match self.shared.read() {
+            Ok(transferred) => {
+                self.last.set(Some(transferred));
+                Ok(transferred)
+            }
+            Err(TimeTransferError::Busy) => self.last.get().ok_or(TimeTransferError::Busy),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The time properties of the host, to pass on with
+    /// [`PtpInstance::set_time_properties`](crate::PtpInstance::set_time_properties)
+    pub fn time_properties(&self) -> Result<TimePropertiesDS, TimeTransferError> {
+        self.transferred()
+            .map(|transferred| transferred.time_properties)
+    }
+}
+
+impl<R: ReferenceClock> Clock for TransferredClock<'_, R> {
+    type Error = TimeTransferError;
+
+    /// The time of the host, or the time of the reference when the host
+    /// hasn't published any
+    fn now(&self) -> Time {
+        let reference = self.reference.now();
+        match self.transferred() {
+            Ok(transferred) => transferred.time_at(reference),
+            Err(_) => reference,
+        }
+    }
+
+    fn adjust(
+        &mut self,
+        _time_offset: Duration,
+        _frequency_multiplier: f64,
+        _time_properties_ds: &TimePropertiesDS,
+    ) -> Result<(), Self::Error> {
+        Err(TimeTransferError::ReadOnly)
+    }
+}
+
+fn split_u128(value: u128) -> [u32; 4] {
+    [
+        (value >> 96) as u32,
+        (value >> 64) as u32,
+        (value >> 32) as u32,
+        value as u32,
+    ]
+}
+
+fn join_u128(words: [u32; 4]) -> u128 {
+    words
+        .iter()
+        .fold(0, |value, &word| value << 32 | word as u128)
+}
+
+// Bits 0-15 hold the UTC offset, bit 16 whether it is valid, bits 17-18 the
+// leap indicator, bits 19-21 the traceability and timescale flags and bits
+// 22-29 the time source
+fn encode_time_properties(time_properties: &TimePropertiesDS) -> u32 {
+    let (offset, valid) = match time_properties.current_utc_offset {
+        Some(offset) => (offset as u16 as u32, 1),
+        None => (0, 0),
+    };
+    let leap = match time_properties.leap_indicator {
+        LeapIndicator::NoLeap => 0,
+        LeapIndicator::Leap61 => 1,
+        LeapIndicator::Leap59 => 2,
+    };
+
+    offset
+        | valid << 16
+        | leap << 17
+        | (time_properties.time_traceable as u32) << 19
+        | (time_properties.frequency_traceable as u32) << 20
+        | (time_properties.ptp_timescale as u32) << 21
+        | (time_properties.time_source.to_primitive() as u32) << 22
+}
+
+fn decode_time_properties(word: u32) -> Result<TimePropertiesDS, TimeTransferError> {
+    let leap_indicator = match (word >> 17) & 0b11 {
+        0 => LeapIndicator::NoLeap,
+        1 => LeapIndicator::Leap61,
+        2 => LeapIndicator::Leap59,
+        _ => return Err(TimeTransferError::InvalidValue),
+    };
+
+    Ok(TimePropertiesDS {
+        current_utc_offset: (word & 1 << 16 != 0).then_some(word as u16 as i16),
+        leap_indicator,
+        time_traceable: word & 1 << 19 != 0,
+        frequency_traceable: word & 1 << 20 != 0,
+        ptp_timescale: word & 1 << 21 != 0,
+        time_source: TimeSource::from_primitive((word >> 22) as u8),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+
+    struct TestClock {
+        time: Time,
+        rate: f64,
+    }
+
+    impl Clock for TestClock {
+        type Error = ();
+
+        fn now(&self) -> Time {
+            self.time
+        }
+
+        fn adjust(
+            &mut self,
+            time_offset: Duration,
+            frequency_multiplier: f64,
+            _time_properties_ds: &TimePropertiesDS,
+        ) -> Result<(), Self::Error> {
+            self.time += time_offset;
+            self.rate *= frequency_multiplier;
+            Ok(())
+        }
+
+        fn frequency_multiplier(&self) -> Option<f64> {
+            Some(self.rate)
+        }
+    }
+
+    // A reference that advances a microsecond every time it is read
+    struct TestReference(Cell<Time>);
+
+    impl ReferenceClock for &TestReference {
+        fn now(&self) -> Time {
+            let now = self.0.get();
+            self.0.set(now + Duration::from_micros(1));
+            now
+        }
+    }
+
+    #[test]
+    fn transfer_time() {
+        let shared = SharedTime::new();
+        let reference = TestReference(Cell::new(Time::from_secs(100)));
+        let time_properties = TimePropertiesDS::new_ptp_time(
+            Some(37),
+            LeapIndicator::Leap61,
+            true,
+            false,
+            TimeSource::Gnss,
+        );
+
+        let guest = TransferredClock::new(&shared, &reference);
+        assert_eq!(guest.transferred(), Err(TimeTransferError::NotPublished));
+        assert_eq!(guest.now(), Time::from_secs(100));
+
+        let mut host = PublishingClock::new(
+            TestClock {
+                time: Time::from_secs(1000),
+                rate: 1.0,
+            },
+            &reference,
+            &shared,
+        );
+        host.adjust(Duration::from_secs(1), 2.0, &time_properties)
+            .unwrap();
+
+        // The host read its clock between reference readings 100.000001 and
+        // 100.000002
+        let transferred = shared.read().unwrap();
+        assert_eq!(
+            transferred.reference,
+            Time::from_fixed_nanos(100_000_001_500u64)
+        );
+        assert_eq!(transferred.time, Time::from_secs(1001));
+        assert_eq!(transferred.rate, 2.0);
+        assert_eq!(guest.time_properties(), Ok(time_properties));
+
+        // The guest reads the reference at 100.000003, 1.5us later
+        assert_eq!(guest.now(), Time::from_nanos(1_001_000_003_000));
+
+        let mut guest = guest;
+        assert_eq!(
+            guest.adjust(Duration::ZERO, 1.0, &time_properties),
+            Err(TimeTransferError::ReadOnly)
+        );
+    }
+
+    #[test]
+    fn shared_time_layout() {
+        let shared = SharedTime::default();
+        assert_eq!(SharedTime::SIZE, 52);
+
+        let transferred = TransferredTime {
+            reference: Time::from_nanos(5),
+            time: Time::from_fixed_nanos(U96F32::from_bits(u128::MAX)),
+            rate: 1.0 + 1e-6,
+            time_properties: TimePropertiesDS::new_arbitrary_time(
+                false,
+                true,
+                TimeSource::InternalOscillator,
+            ),
+        };
+        shared.publish(&transferred);
+        assert_eq!(shared.read(), Ok(transferred));
+        assert_eq!(shared.sequence.load(Ordering::Relaxed), 2);
+
+        // Zero is skipped when the sequence wraps around
+        shared.sequence.store(u32::MAX - 1, Ordering::Relaxed);
+        shared.publish(&transferred);
+        assert_eq!(shared.sequence.load(Ordering::Relaxed), 2);
+
+        // Reading gives up while the host keeps publishing
+        shared.sequence.store(3, Ordering::Relaxed);
+        assert_eq!(shared.read(), Err(TimeTransferError::Busy));
+
+        shared.sequence.store(4, Ordering::Relaxed);
+        shared.version.store(2, Ordering::Relaxed);
+        assert_eq!(shared.read(), Err(TimeTransferError::UnsupportedVersion(2)));
+
+        shared.version.store(LAYOUT_VERSION, Ordering::Relaxed);
+        shared.data[10].store(3 << 17, Ordering::Relaxed);
+        assert_eq!(shared.read(), Err(TimeTransferError::InvalidValue));
+    }
+}
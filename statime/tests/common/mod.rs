@@ -0,0 +1,114 @@
+//! Setup shared by the integration tests and the benchmarks: a port that is
+//! slave of a remote master, and the packets that master sends it.
+
+use rand::rngs::mock::StepRng;
+use statime::{
+    BasicFilter, Clock, ClockIdentity, CommunicationMode, DelayMechanism, Duration, InstanceConfig,
+    Interval, LogMessageIntervals, Port, PortConfig, PtpInstance, Running, SdoId, TestPortState,
+    Time, TimePropertiesDS, TransmitEnable,
+};
+
+pub struct TestClock;
+
+impl Clock for TestClock {
+    type Error = core::convert::Infallible;
+
+    fn now(&self) -> Time {
+        Time::from_secs(10)
+    }
+
+    fn adjust(
+        &mut self,
+        _time_offset: Duration,
+        _frequency_multiplier: f64,
+        _time_properties_ds: &TimePropertiesDS,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+pub type SlavePort<'a> = Port<Running<'a, TestClock, BasicFilter>, StepRng>;
+
+/// The clock identity of the master, whose port number is 1
+pub const MASTER: ClockIdentity = ClockIdentity([1; 8]);
+
+pub fn instance() -> PtpInstance<TestClock, BasicFilter> {
+    PtpInstance::new(
+        InstanceConfig {
+            clock_identity: ClockIdentity::default(),
+            priority_1: 128,
+            priority_2: 128,
+            domain_number: 0,
+            slave_only: false,
+            sdo_id: SdoId::default(),
+        },
+        TimePropertiesDS::default(),
+        TestClock,
+        BasicFilter::new(0.25),
+    )
+}
+
+/// A port of `instance` that is slave of [`MASTER`]. The path delay is
+/// configured, so each Sync and FollowUp gives a measurement without any
+/// delay requests.
+pub fn slave_port(instance: &PtpInstance<TestClock, BasicFilter>) -> SlavePort<'_> {
+    let config = PortConfig {
+        delay_mechanism: DelayMechanism::OneWay {
+            path_delay: Duration::ZERO,
+        },
+        announce_interval: Interval::ONE_SECOND,
+        announce_receipt_timeout: 3,
+        sync_interval: Interval::ONE_SECOND,
+        master_only: false,
+        delay_asymmetry: Duration::ZERO,
+        ingress_latency: Duration::ZERO,
+        egress_latency: Duration::ZERO,
+        management: Default::default(),
+        communication_mode: CommunicationMode::Multicast,
+        transmit: TransmitEnable::ALL,
+        unicast_client: None,
+        log_message_intervals: LogMessageIntervals::STANDARD,
+    };
+    let slave = TestPortState::Slave {
+        clock_identity: MASTER,
+        port_number: 1,
+    };
+    let (port, _) = instance
+        .add_port_in_state(config, StepRng::new(2, 1), slave)
+        .end_bmca();
+    port
+}
+
+const MESSAGE_LEN: usize = 44;
+
+/// The two-step Sync of the master with `sequence_id`, and its FollowUp
+pub fn sync_packets(sequence_id: u16) -> ([u8; MESSAGE_LEN], [u8; MESSAGE_LEN]) {
+    // messageType, twoStepFlag and controlField of each
+    (
+        message(0x0, 0x02, 0, sequence_id),
+        message(0x8, 0x00, 2, sequence_id),
+    )
+}
+
+// A message with a header and an origin timestamp of 1 s, see IEEE1588-2019
+// section 13.3
+fn message(message_type: u8, flags: u8, control_field: u8, sequence_id: u16) -> [u8; MESSAGE_LEN] {
+    let mut data = [0; MESSAGE_LEN];
+    data[0] = message_type;
+    data[1] = 2;
+    data[2..4].copy_from_slice(&(MESSAGE_LEN as u16).to_be_bytes());
+    data[6] = flags;
+    data[20..28].copy_from_slice(&MASTER.0);
+    data[28..30].copy_from_slice(&1u16.to_be_bytes());
+    data[30..32].copy_from_slice(&sequence_id.to_be_bytes());
+    data[32] = control_field;
+    data[39] = 1;
+    data
+}
+
+/// Hand the Sync with `sequence_id` and its FollowUp to the port
+pub fn exchange(port: &mut SlavePort<'_>, sequence_id: u16) {
+    let (sync, follow_up) = sync_packets(sequence_id);
+    drop(port.handle_timecritical_receive(&sync, Time::from_secs(2)));
+    drop(port.handle_general_receive(&follow_up));
+}
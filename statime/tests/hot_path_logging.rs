@@ -0,0 +1,67 @@
+//! Handling a packet formats no log messages unless debug logging is enabled.
+//!
+//! This installs a global logger and changes the maximum log level, so it
+//! runs as its own test binary, apart from the unit tests.
+
+mod common;
+
+use std::sync::Mutex;
+
+static LOG_RECORDS: Mutex<Vec<log::Level>> = Mutex::new(Vec::new());
+
+// Records the level of every message logged
+struct RecordingLogger;
+
+impl log::Log for RecordingLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        LOG_RECORDS.lock().unwrap().push(record.level());
+    }
+
+    fn flush(&self) {}
+}
+
+fn take_records() -> Vec<log::Level> {
+    std::mem::take(&mut LOG_RECORDS.lock().unwrap())
+}
+
+#[test]
+fn test_hot_path_logging() {
+    static LOGGER: RecordingLogger = RecordingLogger;
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+
+    let instance = common::instance();
+    let mut port = common::slave_port(&instance);
+
+    // Let the filter settle, it logs when it locks
+    port.set_measurement_queue(true);
+    for sequence_id in 0..10 {
+        common::exchange(&mut port, sequence_id);
+    }
+    assert!(port.take_measurement().is_some());
+    port.set_measurement_queue(false);
+    take_records();
+
+    // Then handling packets logs nothing above debug, so nothing is formatted
+    for sequence_id in 10..1000 {
+        common::exchange(&mut port, sequence_id);
+    }
+    let records = take_records();
+    assert!(records.is_empty(), "{records:?}");
+
+    // While the packets are still visible when debugging
+    log::set_max_level(log::LevelFilter::Trace);
+    common::exchange(&mut port, 1000);
+    log::set_max_level(log::LevelFilter::Info);
+    let records = take_records();
+    if cfg!(any(feature = "silent", feature = "high-rate")) {
+        assert!(records.is_empty(), "{records:?}");
+    } else {
+        assert!(!records.is_empty());
+        assert!(records.iter().all(|level| *level >= log::Level::Debug));
+    }
+}